@@ -17,6 +17,7 @@ use prometheus::{Encoder, TextEncoder};
 
 use garage_model::garage::Garage;
 use garage_rpc::system::ClusterHealthStatus;
+use garage_util::background::BackgroundRunner;
 use garage_util::error::Error as GarageError;
 
 use crate::generic_server::*;
@@ -26,10 +27,12 @@ use crate::admin::cluster::*;
 use crate::admin::error::*;
 use crate::admin::key::*;
 use crate::admin::router::{Authorization, Endpoint};
+use crate::admin::worker::*;
 use crate::helpers::host_to_bucket;
 
 pub struct AdminApiServer {
 	garage: Arc<Garage>,
+	background: Arc<BackgroundRunner>,
 	#[cfg(feature = "metrics")]
 	exporter: PrometheusExporter,
 	metrics_token: Option<String>,
@@ -39,6 +42,7 @@ pub struct AdminApiServer {
 impl AdminApiServer {
 	pub fn new(
 		garage: Arc<Garage>,
+		background: Arc<BackgroundRunner>,
 		#[cfg(feature = "metrics")] exporter: PrometheusExporter,
 	) -> Self {
 		let cfg = &garage.config.admin;
@@ -52,6 +56,7 @@ impl AdminApiServer {
 			.map(|tok| format!("Bearer {}", tok));
 		Self {
 			garage,
+			background,
 			#[cfg(feature = "metrics")]
 			exporter,
 			metrics_token,
@@ -237,23 +242,32 @@ impl ApiHandler for AdminApiServer {
 		req: Request<Body>,
 		endpoint: Endpoint,
 	) -> Result<Response<Body>, Error> {
-		let expected_auth_header =
+		// The metrics endpoint accepts both the metrics token and the admin token,
+		// and is only left open if neither of them is configured
+		let accepted_tokens =
 			match endpoint.authorization_type() {
-				Authorization::None => None,
-				Authorization::MetricsToken => self.metrics_token.as_ref(),
+				Authorization::None => vec![],
+				Authorization::MetricsToken => self
+					.metrics_token
+					.iter()
+					.chain(self.admin_token.iter())
+					.collect(),
 				Authorization::AdminToken => match &self.admin_token {
 					None => return Err(Error::forbidden(
 						"Admin token isn't configured, admin API access is disabled for security.",
 					)),
-					Some(t) => Some(t),
+					Some(t) => vec![t],
 				},
 			};
 
-		if let Some(h) = expected_auth_header {
+		if !accepted_tokens.is_empty() {
 			match req.headers().get("Authorization") {
 				None => return Err(Error::forbidden("Authorization token must be provided")),
 				Some(v) => {
-					let authorized = v.to_str().map(|hv| hv.trim() == h).unwrap_or(false);
+					let authorized = v
+						.to_str()
+						.map(|hv| accepted_tokens.iter().any(|t| hv.trim() == t.as_str()))
+						.unwrap_or(false);
 					if !authorized {
 						return Err(Error::forbidden("Invalid authorization token provided"));
 					}
@@ -268,7 +282,12 @@ impl ApiHandler for AdminApiServer {
 			Endpoint::Metrics => self.handle_metrics(),
 			Endpoint::GetClusterStatus => handle_get_cluster_status(&self.garage).await,
 			Endpoint::GetClusterHealth => handle_get_cluster_health(&self.garage).await,
+			Endpoint::GetClusterStatistics => handle_get_cluster_statistics(&self.garage).await,
 			Endpoint::ConnectClusterNodes => handle_connect_cluster_nodes(&self.garage, req).await,
+			Endpoint::GetScrubStatus => handle_get_scrub_status(&self.garage).await,
+			Endpoint::ListBackgroundWorkers => {
+				handle_list_background_workers(&self.background).await
+			}
 			// Layout
 			Endpoint::GetClusterLayout => handle_get_cluster_layout(&self.garage).await,
 			Endpoint::UpdateClusterLayout => handle_update_cluster_layout(&self.garage, req).await,
@@ -291,6 +310,14 @@ impl ApiHandler for AdminApiServer {
 			Endpoint::CreateBucket => handle_create_bucket(&self.garage, req).await,
 			Endpoint::DeleteBucket { id } => handle_delete_bucket(&self.garage, id).await,
 			Endpoint::UpdateBucket { id } => handle_update_bucket(&self.garage, id, req).await,
+			// Bucket CORS rules
+			Endpoint::GetBucketCors { id } => handle_get_bucket_cors(&self.garage, id).await,
+			Endpoint::PutBucketCors { id } => handle_put_bucket_cors(&self.garage, id, req).await,
+			Endpoint::DeleteBucketCors { id } => handle_delete_bucket_cors(&self.garage, id).await,
+			// Bucket replication
+			Endpoint::GetBucketReplication { id } => {
+				handle_get_bucket_replication(&self.garage, id).await
+			}
 			// Bucket-key permissions
 			Endpoint::BucketAllowKey => {
 				handle_bucket_change_key_perm(&self.garage, req, true).await
@@ -298,6 +325,12 @@ impl ApiHandler for AdminApiServer {
 			Endpoint::BucketDenyKey => {
 				handle_bucket_change_key_perm(&self.garage, req, false).await
 			}
+			Endpoint::KeyAllowBucket { id } => {
+				handle_key_change_bucket_perm(&self.garage, id, req, true).await
+			}
+			Endpoint::KeyDenyBucket { id } => {
+				handle_key_change_bucket_perm(&self.garage, id, req, false).await
+			}
 			// Bucket aliasing
 			Endpoint::GlobalAliasBucket { id, alias } => {
 				handle_global_alias_bucket(&self.garage, id, alias).await
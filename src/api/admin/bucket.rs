@@ -20,6 +20,7 @@ use crate::admin::error::*;
 use crate::admin::key::ApiBucketKeyPerm;
 use crate::common_error::CommonError;
 use crate::helpers::{json_ok_response, parse_json_body};
+use crate::s3::cors::check_cors_rule;
 
 pub async fn handle_list_buckets(garage: &Arc<Garage>) -> Result<Response<Body>, Error> {
 	let buckets = garage
@@ -192,11 +193,7 @@ async fn bucket_info_results(
 						permissions: p
 							.authorized_buckets
 							.get(&bucket.id)
-							.map(|p| ApiBucketKeyPerm {
-								read: p.allow_read,
-								write: p.allow_write,
-								owner: p.allow_owner,
-							})
+							.map(ApiBucketKeyPerm::from_perm)
 							.unwrap_or_default(),
 						bucket_local_aliases: p
 							.local_aliases
@@ -309,19 +306,15 @@ pub async fn handle_create_bucket(
 			.set_local_bucket_alias(bucket.id, &la.access_key_id, &la.alias)
 			.await?;
 
-		if la.allow.read || la.allow.write || la.allow.owner {
+		let mut perm = BucketKeyPerm {
+			timestamp: now_msec(),
+			..BucketKeyPerm::NO_PERMISSIONS
+		};
+		la.allow.apply(&mut perm, true)?;
+		if perm.is_any() {
 			garage
 				.bucket_helper()
-				.set_bucket_key_permissions(
-					bucket.id,
-					&la.access_key_id,
-					BucketKeyPerm {
-						timestamp: now_msec(),
-						allow_read: la.allow.read,
-						allow_write: la.allow.write,
-						allow_owner: la.allow.owner,
-					},
-				)
+				.set_bucket_key_permissions(bucket.id, &la.access_key_id, perm)
 				.await?;
 		}
 	}
@@ -416,6 +409,8 @@ pub async fn handle_update_bucket(
 					"Please specify indexDocument when enabling website access.",
 				)?,
 				error_document: wa.error_document,
+				redirect_all: None,
+				routing_rules: vec![],
 			}));
 		} else {
 			if wa.index_document.is_some() || wa.error_document.is_some() {
@@ -454,6 +449,209 @@ struct UpdateBucketWebsiteAccess {
 	error_document: Option<String>,
 }
 
+// ---- BUCKET CORS RULES ----
+
+pub async fn handle_get_bucket_cors(
+	garage: &Arc<Garage>,
+	id: String,
+) -> Result<Response<Body>, Error> {
+	let bucket_id = parse_bucket_id(&id)?;
+	let bucket = garage
+		.bucket_helper()
+		.get_existing_bucket(bucket_id)
+		.await?;
+
+	let cors_rules = bucket
+		.params()
+		.unwrap()
+		.cors_config
+		.get()
+		.iter()
+		.flatten()
+		.map(ApiCorsRule::from)
+		.collect::<Vec<_>>();
+
+	Ok(json_ok_response(&BucketCorsRules { cors_rules })?)
+}
+
+pub async fn handle_put_bucket_cors(
+	garage: &Arc<Garage>,
+	id: String,
+	req: Request<Body>,
+) -> Result<Response<Body>, Error> {
+	let req = parse_json_body::<BucketCorsRules>(req).await?;
+	let bucket_id = parse_bucket_id(&id)?;
+
+	if req.cors_rules.is_empty() {
+		return Err(Error::bad_request(
+			"Please specify at least one CORS rule, or delete the CORS rules of the bucket.",
+		));
+	}
+	let cors_rules = req
+		.cors_rules
+		.into_iter()
+		.map(CorsRule::from)
+		.collect::<Vec<_>>();
+	for rule in cors_rules.iter() {
+		check_cors_rule(rule)?;
+	}
+
+	let mut bucket = garage
+		.bucket_helper()
+		.get_existing_bucket(bucket_id)
+		.await?;
+	bucket
+		.params_mut()
+		.unwrap()
+		.cors_config
+		.update(Some(cors_rules));
+	garage.bucket_table.insert(&bucket).await?;
+
+	handle_get_bucket_cors(garage, id).await
+}
+
+pub async fn handle_delete_bucket_cors(
+	garage: &Arc<Garage>,
+	id: String,
+) -> Result<Response<Body>, Error> {
+	let bucket_id = parse_bucket_id(&id)?;
+
+	let mut bucket = garage
+		.bucket_helper()
+		.get_existing_bucket(bucket_id)
+		.await?;
+	bucket.params_mut().unwrap().cors_config.update(None);
+	garage.bucket_table.insert(&bucket).await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::NO_CONTENT)
+		.body(Body::empty())?)
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BucketCorsRules {
+	cors_rules: Vec<ApiCorsRule>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiCorsRule {
+	#[serde(default)]
+	id: Option<String>,
+	#[serde(default)]
+	max_age_seconds: Option<u64>,
+	allowed_origins: Vec<String>,
+	allowed_methods: Vec<String>,
+	#[serde(default)]
+	allowed_headers: Vec<String>,
+	#[serde(default)]
+	expose_headers: Vec<String>,
+}
+
+impl From<&CorsRule> for ApiCorsRule {
+	fn from(rule: &CorsRule) -> Self {
+		Self {
+			id: rule.id.clone(),
+			max_age_seconds: rule.max_age_seconds,
+			allowed_origins: rule.allow_origins.clone(),
+			allowed_methods: rule.allow_methods.clone(),
+			allowed_headers: rule.allow_headers.clone(),
+			expose_headers: rule.expose_headers.clone(),
+		}
+	}
+}
+
+impl From<ApiCorsRule> for CorsRule {
+	fn from(rule: ApiCorsRule) -> Self {
+		Self {
+			id: rule.id,
+			max_age_seconds: rule.max_age_seconds,
+			allow_origins: rule.allowed_origins,
+			allow_methods: rule.allowed_methods,
+			allow_headers: rule.allowed_headers,
+			expose_headers: rule.expose_headers,
+		}
+	}
+}
+
+// ---- BUCKET REPLICATION ----
+
+pub async fn handle_get_bucket_replication(
+	garage: &Arc<Garage>,
+	id: String,
+) -> Result<Response<Body>, Error> {
+	let bucket_id = parse_bucket_id(&id)?;
+	let bucket = garage
+		.bucket_helper()
+		.get_existing_bucket(bucket_id)
+		.await?;
+
+	let rules = bucket
+		.params()
+		.unwrap()
+		.replication_config
+		.get()
+		.clone()
+		.unwrap_or_default();
+	let status = garage
+		.bucket_helper()
+		.get_replication_status(&bucket_id, &rules)
+		.await?;
+
+	let replication_rules = rules
+		.into_iter()
+		.zip(status)
+		.map(|(rule, status)| ApiReplicationRule {
+			id: rule.id,
+			enabled: rule.enabled,
+			prefixes: rule.prefixes,
+			replicate_deletes: rule.replicate_deletes,
+			destination: ApiReplicationDestination {
+				endpoint: rule.destination.endpoint,
+				region: rule.destination.region,
+				bucket: rule.destination.bucket,
+				access_key_id: rule.destination.access_key_id,
+			},
+			replicated_objects: status.replicated_objects,
+			pending_objects: status.pending_objects,
+			last_replicated: status.last_replicated.map(msec_to_rfc3339),
+		})
+		.collect::<Vec<_>>();
+
+	Ok(json_ok_response(&BucketReplicationRules {
+		replication_rules,
+	})?)
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BucketReplicationRules {
+	replication_rules: Vec<ApiReplicationRule>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiReplicationRule {
+	id: String,
+	enabled: bool,
+	prefixes: Vec<String>,
+	replicate_deletes: bool,
+	destination: ApiReplicationDestination,
+	replicated_objects: u64,
+	pending_objects: u64,
+	last_replicated: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiReplicationDestination {
+	endpoint: String,
+	region: String,
+	bucket: String,
+	access_key_id: String,
+}
+
 // ---- BUCKET/KEY PERMISSIONS ----
 
 pub async fn handle_bucket_change_key_perm(
@@ -463,7 +661,43 @@ pub async fn handle_bucket_change_key_perm(
 ) -> Result<Response<Body>, Error> {
 	let req = parse_json_body::<BucketKeyPermChangeRequest>(req).await?;
 
-	let bucket_id = parse_bucket_id(&req.bucket_id)?;
+	change_key_perm(
+		garage,
+		&req.bucket_id,
+		&req.access_key_id,
+		&req.permissions,
+		new_perm_flag,
+	)
+	.await
+}
+
+/// Same as `handle_bucket_change_key_perm`, but the access key is given in the path
+pub async fn handle_key_change_bucket_perm(
+	garage: &Arc<Garage>,
+	access_key_id: String,
+	req: Request<Body>,
+	new_perm_flag: bool,
+) -> Result<Response<Body>, Error> {
+	let req = parse_json_body::<KeyBucketPermChangeRequest>(req).await?;
+
+	change_key_perm(
+		garage,
+		&req.bucket_id,
+		&access_key_id,
+		&req.permissions,
+		new_perm_flag,
+	)
+	.await
+}
+
+async fn change_key_perm(
+	garage: &Arc<Garage>,
+	bucket_id: &str,
+	access_key_id: &str,
+	permissions: &ApiBucketKeyPerm,
+	new_perm_flag: bool,
+) -> Result<Response<Body>, Error> {
+	let bucket_id = parse_bucket_id(bucket_id)?;
 
 	let bucket = garage
 		.bucket_helper()
@@ -473,7 +707,7 @@ pub async fn handle_bucket_change_key_perm(
 
 	let key = garage
 		.key_helper()
-		.get_existing_key(&req.access_key_id)
+		.get_existing_key(&access_key_id.to_string())
 		.await?;
 
 	let mut perm = state
@@ -482,15 +716,7 @@ pub async fn handle_bucket_change_key_perm(
 		.cloned()
 		.unwrap_or(BucketKeyPerm::NO_PERMISSIONS);
 
-	if req.permissions.read {
-		perm.allow_read = new_perm_flag;
-	}
-	if req.permissions.write {
-		perm.allow_write = new_perm_flag;
-	}
-	if req.permissions.owner {
-		perm.allow_owner = new_perm_flag;
-	}
+	permissions.apply(&mut perm, new_perm_flag)?;
 
 	garage
 		.bucket_helper()
@@ -508,6 +734,13 @@ struct BucketKeyPermChangeRequest {
 	permissions: ApiBucketKeyPerm,
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct KeyBucketPermChangeRequest {
+	bucket_id: String,
+	permissions: ApiBucketKeyPerm,
+}
+
 // ---- BUCKET ALIASES ----
 
 pub async fn handle_global_alias_bucket(
@@ -10,7 +10,10 @@ use garage_util::data::*;
 
 use garage_rpc::layout::*;
 
+use garage_block::repair::ScrubState;
+
 use garage_model::garage::Garage;
+use garage_model::stats::gather_cluster_stats;
 
 use crate::admin::error::*;
 use crate::helpers::{json_ok_response, parse_json_body};
@@ -49,6 +52,11 @@ pub async fn handle_get_cluster_health(garage: &Arc<Garage>) -> Result<Response<
 	Ok(json_ok_response(&health)?)
 }
 
+pub async fn handle_get_cluster_statistics(garage: &Arc<Garage>) -> Result<Response<Body>, Error> {
+	let stats = gather_cluster_stats(garage).await?;
+	Ok(json_ok_response(&stats)?)
+}
+
 pub async fn handle_connect_cluster_nodes(
 	garage: &Arc<Garage>,
 	req: Request<Body>,
@@ -73,6 +81,21 @@ pub async fn handle_connect_cluster_nodes(
 	Ok(json_ok_response(&res)?)
 }
 
+pub async fn handle_get_scrub_status(garage: &Arc<Garage>) -> Result<Response<Body>, Error> {
+	let stats = garage.block_manager.scrub_stats();
+	let res = GetScrubStatusResponse {
+		state: stats.state,
+		blocks_checked: stats.blocks_checked,
+		bytes_scanned: stats.bytes_scanned,
+		bytes_total: stats.bytes_total,
+		errors_found: stats.errors_found,
+		last_error: stats.last_error,
+		eta_secs: stats.eta.map(|eta| eta.as_secs()),
+	};
+
+	Ok(json_ok_response(&res)?)
+}
+
 pub async fn handle_get_cluster_layout(garage: &Arc<Garage>) -> Result<Response<Body>, Error> {
 	let res = get_cluster_layout(garage);
 
@@ -119,6 +142,18 @@ struct ConnectClusterNodesResponse {
 	error: Option<String>,
 }
 
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GetScrubStatusResponse {
+	state: ScrubState,
+	blocks_checked: u64,
+	bytes_scanned: u64,
+	bytes_total: u64,
+	errors_found: u64,
+	last_error: Option<String>,
+	eta_secs: Option<u64>,
+}
+
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 struct GetClusterLayoutResponse {
@@ -146,10 +181,18 @@ pub async fn handle_update_cluster_layout(
 	let mut roles = layout.roles.clone();
 	roles.merge(&layout.staging);
 
+	let known_nodes = garage.system.get_known_nodes();
+
 	for (node, role) in updates {
 		let node = hex::decode(node).ok_or_bad_request("Invalid node identifier")?;
 		let node = Uuid::try_from(&node).ok_or_bad_request("Invalid node identifier")?;
 
+		if let (Some(role), Some(adv)) = (&role, known_nodes.iter().find(|adv| adv.id == node)) {
+			adv.status
+				.check_layout_role(role)
+				.map_err(Error::bad_request)?;
+		}
+
 		layout
 			.staging
 			.merge(&roles.update_mutator(node, NodeRoleV(role)));
@@ -8,6 +8,7 @@ use garage_table::*;
 
 use garage_model::garage::Garage;
 use garage_model::key_table::*;
+use garage_model::permission::{BucketKeyPerm, S3Op, S3OpSet};
 
 use crate::admin::error::*;
 use crate::helpers::{json_ok_response, parse_json_body};
@@ -178,7 +179,7 @@ async fn key_info_results(garage: &Arc<Garage>, key: Key) -> Result<Response<Bod
 	let res = GetKeyInfoResult {
 		name: key_state.name.get().clone(),
 		access_key_id: key.key_id.clone(),
-		secret_access_key: key_state.secret_key.clone(),
+		secret_access_key: key_state.secret_key.get().clone(),
 		permissions: KeyPerm {
 			create_bucket: *key_state.allow_create_bucket.get(),
 		},
@@ -205,11 +206,7 @@ async fn key_info_results(garage: &Arc<Garage>, key: Key) -> Result<Response<Bod
 					permissions: key_state
 						.authorized_buckets
 						.get(&bucket.id)
-						.map(|p| ApiBucketKeyPerm {
-							read: p.allow_read,
-							write: p.allow_write,
-							owner: p.allow_owner,
-						})
+						.map(ApiBucketKeyPerm::from_perm)
 						.unwrap_or_default(),
 				}
 			})
@@ -253,4 +250,49 @@ pub(crate) struct ApiBucketKeyPerm {
 	pub(crate) write: bool,
 	#[serde(default)]
 	pub(crate) owner: bool,
+	#[serde(default, rename = "bypassGovernance")]
+	pub(crate) bypass_governance: bool,
+	/// S3 operations the key can do (in responses), or to allow
+	/// or deny individually (in requests)
+	#[serde(default)]
+	pub(crate) operations: Vec<String>,
+}
+
+impl ApiBucketKeyPerm {
+	pub(crate) fn from_perm(p: &BucketKeyPerm) -> Self {
+		Self {
+			read: p.allow_read,
+			write: p.allow_write,
+			owner: p.allow_owner,
+			bypass_governance: p.allow_bypass_governance,
+			operations: p.allowed_ops().iter().map(|op| op.to_string()).collect(),
+		}
+	}
+
+	pub(crate) fn parse_operations(&self) -> Result<S3OpSet, Error> {
+		self.operations
+			.iter()
+			.map(|op| op.parse::<S3Op>().map_err(Error::bad_request))
+			.collect()
+	}
+
+	/// Apply the changes requested by an allow (`allow = true`)
+	/// or deny (`allow = false`) request to a permission set
+	pub(crate) fn apply(&self, perm: &mut BucketKeyPerm, allow: bool) -> Result<(), Error> {
+		let operations = self.parse_operations()?;
+		if self.read {
+			perm.set_read(allow);
+		}
+		if self.write {
+			perm.set_write(allow);
+		}
+		if self.owner {
+			perm.set_owner(allow);
+		}
+		if self.bypass_governance {
+			perm.allow_bypass_governance = allow;
+		}
+		perm.set_ops(operations, allow);
+		Ok(())
+	}
 }
@@ -5,3 +5,4 @@ mod router;
 mod bucket;
 mod cluster;
 mod key;
+mod worker;
@@ -22,7 +22,10 @@ pub enum Endpoint {
 	Metrics,
 	GetClusterStatus,
 	GetClusterHealth,
+	GetClusterStatistics,
 	ConnectClusterNodes,
+	GetScrubStatus,
+	ListBackgroundWorkers,
 	// Layout
 	GetClusterLayout,
 	UpdateClusterLayout,
@@ -55,9 +58,29 @@ pub enum Endpoint {
 	UpdateBucket {
 		id: String,
 	},
+	// Bucket CORS rules
+	GetBucketCors {
+		id: String,
+	},
+	PutBucketCors {
+		id: String,
+	},
+	DeleteBucketCors {
+		id: String,
+	},
+	// Bucket replication
+	GetBucketReplication {
+		id: String,
+	},
 	// Bucket-Key Permissions
 	BucketAllowKey,
 	BucketDenyKey,
+	KeyAllowBucket {
+		id: String,
+	},
+	KeyDenyBucket {
+		id: String,
+	},
 	// Bucket aliases
 	GlobalAliasBucket {
 		id: String,
@@ -90,6 +113,14 @@ impl Endpoint {
 
 		let mut query = QueryParameters::from_query(query.unwrap_or_default())?;
 
+		if let Some(v1_path) = path.strip_prefix("/v1/") {
+			let res = Self::from_v1_path(req.method(), v1_path, &mut query)?;
+			if let Some(message) = query.nonempty_message() {
+				debug!("Unused query parameter: {}", message)
+			}
+			return Ok(res);
+		}
+
 		let res = router_match!(@gen_path_parser (req.method(), path, query) [
 			OPTIONS _ => Options,
 			GET "/check" => CheckDomain,
@@ -98,6 +129,8 @@ impl Endpoint {
 			GET "/v0/status" => GetClusterStatus,
 			GET "/v0/health" => GetClusterHealth,
 			POST "/v0/connect" => ConnectClusterNodes,
+			GET "/v0/scrub-status" => GetScrubStatus,
+			GET "/v0/background-workers" => ListBackgroundWorkers,
 			// Layout endpoints
 			GET "/v0/layout" => GetClusterLayout,
 			POST "/v0/layout" => UpdateClusterLayout,
@@ -118,6 +151,12 @@ impl Endpoint {
 			POST "/v0/bucket" => CreateBucket,
 			DELETE "/v0/bucket" if id => DeleteBucket (query::id),
 			PUT "/v0/bucket" if id => UpdateBucket (query::id),
+			// Bucket CORS rules
+			GET "/v0/bucket/cors" => GetBucketCors (query::id),
+			PUT "/v0/bucket/cors" => PutBucketCors (query::id),
+			DELETE "/v0/bucket/cors" => DeleteBucketCors (query::id),
+			// Bucket replication
+			GET "/v0/bucket/replication" => GetBucketReplication (query::id),
 			// Bucket-key permissions
 			POST "/v0/bucket/allow" => BucketAllowKey,
 			POST "/v0/bucket/deny" => BucketDenyKey,
@@ -134,6 +173,103 @@ impl Endpoint {
 
 		Ok(res)
 	}
+	/// Determine which endpoint a request to the v1 API is for. In this version
+	/// of the API, the ID of the bucket or key that is operated on is part of the path.
+	fn from_v1_path(
+		method: &Method,
+		path: &str,
+		query: &mut QueryParameters<'_>,
+	) -> Result<Self, Error> {
+		let segments = path.trim_end_matches('/').split('/').collect::<Vec<_>>();
+
+		let res = match (method, &segments[..]) {
+			(&Method::OPTIONS, _) => Self::Options,
+			(&Method::GET, ["status"]) => Self::GetClusterStatus,
+			(&Method::GET, ["health"]) => Self::GetClusterHealth,
+			(&Method::GET, ["stats"]) => Self::GetClusterStatistics,
+			(&Method::POST, ["connect"]) => Self::ConnectClusterNodes,
+			(&Method::GET, ["scrub-status"]) => Self::GetScrubStatus,
+			(&Method::GET, ["background-workers"]) => Self::ListBackgroundWorkers,
+			// Layout endpoints
+			(&Method::GET, ["layout"]) => Self::GetClusterLayout,
+			(&Method::POST, ["layout"]) => Self::UpdateClusterLayout,
+			(&Method::POST, ["layout", "apply"]) => Self::ApplyClusterLayout,
+			(&Method::POST, ["layout", "revert"]) => Self::RevertClusterLayout,
+			// API key endpoints
+			(&Method::GET, ["key"]) if query.search.is_some() => Self::GetKeyInfo {
+				id: None,
+				search: router_match!(@@parse_param query, query_opt, search),
+			},
+			(&Method::GET, ["key"]) => Self::ListKeys,
+			(&Method::POST, ["key"]) => Self::CreateKey,
+			(&Method::POST, ["key", "import"]) => Self::ImportKey,
+			(&Method::GET, ["key", id]) => Self::GetKeyInfo {
+				id: Some(id.to_string()),
+				search: None,
+			},
+			(&Method::POST, ["key", id]) => Self::UpdateKey { id: id.to_string() },
+			(&Method::DELETE, ["key", id]) => Self::DeleteKey { id: id.to_string() },
+			(&Method::POST, ["key", id, "allow"]) => Self::KeyAllowBucket { id: id.to_string() },
+			(&Method::POST, ["key", id, "deny"]) => Self::KeyDenyBucket { id: id.to_string() },
+			// Bucket endpoints
+			(&Method::GET, ["bucket"]) if query.global_alias.is_some() => Self::GetBucketInfo {
+				id: None,
+				global_alias: router_match!(@@parse_param query, query_opt, global_alias),
+			},
+			(&Method::GET, ["bucket"]) => Self::ListBuckets,
+			(&Method::POST, ["bucket"]) => Self::CreateBucket,
+			(&Method::GET, ["bucket", id]) | (&Method::GET, ["bucket", id, "alias"]) => {
+				Self::GetBucketInfo {
+					id: Some(id.to_string()),
+					global_alias: None,
+				}
+			}
+			(&Method::PUT, ["bucket", id]) => Self::UpdateBucket { id: id.to_string() },
+			(&Method::DELETE, ["bucket", id]) => Self::DeleteBucket { id: id.to_string() },
+			// Bucket CORS rules
+			(&Method::GET, ["bucket", id, "cors"]) => Self::GetBucketCors { id: id.to_string() },
+			(&Method::PUT, ["bucket", id, "cors"]) => Self::PutBucketCors { id: id.to_string() },
+			(&Method::DELETE, ["bucket", id, "cors"]) => {
+				Self::DeleteBucketCors { id: id.to_string() }
+			}
+			// Bucket replication
+			(&Method::GET, ["bucket", id, "replication"]) => {
+				Self::GetBucketReplication { id: id.to_string() }
+			}
+			// Bucket aliases: local aliases if an access key is given, global aliases otherwise
+			(&Method::POST, ["bucket", id, "alias"]) if query.access_key_id.is_some() => {
+				Self::LocalAliasBucket {
+					id: id.to_string(),
+					access_key_id: router_match!(@@parse_param query, query, access_key_id),
+					alias: router_match!(@@parse_param query, query, alias),
+				}
+			}
+			(&Method::POST, ["bucket", id, "alias"]) => Self::GlobalAliasBucket {
+				id: id.to_string(),
+				alias: router_match!(@@parse_param query, query, alias),
+			},
+			(&Method::DELETE, ["bucket", id, "alias"]) if query.access_key_id.is_some() => {
+				Self::LocalUnaliasBucket {
+					id: id.to_string(),
+					access_key_id: router_match!(@@parse_param query, query, access_key_id),
+					alias: router_match!(@@parse_param query, query, alias),
+				}
+			}
+			(&Method::DELETE, ["bucket", id, "alias"]) => Self::GlobalUnaliasBucket {
+				id: id.to_string(),
+				alias: router_match!(@@parse_param query, query, alias),
+			},
+			(m, _) => {
+				return Err(Error::bad_request(format!(
+					"Unknown API endpoint: {} /v1/{}",
+					m, path
+				)))
+			}
+		};
+
+		Ok(res)
+	}
+
 	/// Get the kind of authorization which is required to perform the operation.
 	pub fn authorization_type(&self) -> Authorization {
 		match self {
@@ -156,3 +292,80 @@ generateQueryParameters! {
 		"accessKeyId" => access_key_id
 	]
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn parse(method: &str, uri: &str) -> Result<Endpoint, Error> {
+		let req = Request::builder().method(method).uri(uri).body(()).unwrap();
+		Endpoint::from_request(&req)
+	}
+
+	#[test]
+	fn test_v1_endpoints() {
+		assert_eq!(parse("GET", "/v1/bucket").unwrap(), Endpoint::ListBuckets);
+		assert_eq!(
+			parse("GET", "/v1/bucket?globalAlias=site").unwrap(),
+			Endpoint::GetBucketInfo {
+				id: None,
+				global_alias: Some("site".into())
+			}
+		);
+		assert_eq!(
+			parse("DELETE", "/v1/bucket/f00d").unwrap(),
+			Endpoint::DeleteBucket { id: "f00d".into() }
+		);
+		assert_eq!(
+			parse("POST", "/v1/bucket/f00d/alias?alias=site").unwrap(),
+			Endpoint::GlobalAliasBucket {
+				id: "f00d".into(),
+				alias: "site".into()
+			}
+		);
+		assert_eq!(
+			parse("DELETE", "/v1/bucket/f00d/alias?alias=site&accessKeyId=GK1").unwrap(),
+			Endpoint::LocalUnaliasBucket {
+				id: "f00d".into(),
+				access_key_id: "GK1".into(),
+				alias: "site".into()
+			}
+		);
+		assert!(parse("POST", "/v1/bucket/f00d/alias").is_err());
+		assert_eq!(
+			parse("GET", "/v1/bucket/f00d/replication").unwrap(),
+			Endpoint::GetBucketReplication { id: "f00d".into() }
+		);
+		assert_eq!(
+			parse("GET", "/v1/background-workers").unwrap(),
+			Endpoint::ListBackgroundWorkers
+		);
+		assert_eq!(
+			parse("GET", "/v1/stats").unwrap(),
+			Endpoint::GetClusterStatistics
+		);
+		assert_eq!(parse("POST", "/v1/key").unwrap(), Endpoint::CreateKey);
+		assert_eq!(
+			parse("POST", "/v1/key/import").unwrap(),
+			Endpoint::ImportKey
+		);
+		assert_eq!(
+			parse("GET", "/v1/key/GK1/").unwrap(),
+			Endpoint::GetKeyInfo {
+				id: Some("GK1".into()),
+				search: None
+			}
+		);
+		assert_eq!(
+			parse("POST", "/v1/key/GK1/allow").unwrap(),
+			Endpoint::KeyAllowBucket { id: "GK1".into() }
+		);
+		assert!(parse("PUT", "/v1/key/GK1/allow").is_err());
+
+		// The v0 API is still available
+		assert_eq!(
+			parse("DELETE", "/v0/bucket?id=f00d").unwrap(),
+			Endpoint::DeleteBucket { id: "f00d".into() }
+		);
+	}
+}
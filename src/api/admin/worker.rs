@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+
+use hyper::{Body, Response};
+use serde::Serialize;
+
+use garage_util::background::*;
+use garage_util::time::msec_to_rfc3339;
+
+use crate::admin::error::*;
+use crate::helpers::*;
+
+pub async fn handle_list_background_workers(
+	background: &BackgroundRunner,
+) -> Result<Response<Body>, Error> {
+	let res = list_background_workers(background.get_worker_info());
+
+	Ok(json_ok_response(&res)?)
+}
+
+fn list_background_workers(workers: HashMap<usize, WorkerInfo>) -> Vec<BackgroundWorkerResp> {
+	let mut workers = workers.into_iter().collect::<Vec<_>>();
+	workers.sort_by_key(|(tid, _)| *tid);
+
+	workers
+		.into_iter()
+		.map(|(tid, info)| BackgroundWorkerResp {
+			id: tid,
+			name: info.name,
+			state: match info.state {
+				WorkerState::Busy => "running",
+				WorkerState::Throttled(_) => "waiting",
+				WorkerState::Idle => "idle",
+				WorkerState::Done => "done",
+			},
+			last_run: info.last_run.map(msec_to_rfc3339),
+			last_run_duration_msecs: info.last_run_duration.map(|d| d.as_millis() as u64),
+			errors: info.errors,
+			consecutive_errors: info.consecutive_errors,
+			last_error: info.last_error.map(|(message, time)| WorkerErrorResp {
+				message,
+				time: msec_to_rfc3339(time),
+			}),
+			tranquility: info.status.tranquility,
+			progress: info.status.progress,
+			progress_percent: info.status.progress_percent,
+			queue_length: info.status.queue_length,
+			persistent_errors: info.status.persistent_errors,
+			freeform: info.status.freeform,
+		})
+		.collect()
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct BackgroundWorkerResp {
+	id: usize,
+	name: String,
+	/// One of `running`, `waiting` (after an error or to limit its resource usage),
+	/// `idle` or `done`
+	state: &'static str,
+	last_run: Option<String>,
+	last_run_duration_msecs: Option<u64>,
+	errors: usize,
+	consecutive_errors: usize,
+	last_error: Option<WorkerErrorResp>,
+	tranquility: Option<u32>,
+	progress: Option<String>,
+	progress_percent: Option<f32>,
+	queue_length: Option<u64>,
+	persistent_errors: Option<u64>,
+	freeform: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct WorkerErrorResp {
+	message: String,
+	time: String,
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::time::Duration;
+
+	#[test]
+	fn test_list_background_workers() {
+		let worker = |name: &str, state| WorkerInfo {
+			name: name.into(),
+			status: WorkerStatus {
+				progress: Some("12.50%".into()),
+				progress_percent: Some(12.5),
+				..Default::default()
+			},
+			state,
+			errors: 1,
+			consecutive_errors: 0,
+			last_error: Some(("Network error".into(), 1700000000000)),
+			iterations: 10,
+			work_time: Duration::from_secs(2),
+			last_run: Some(1700000060000),
+			last_run_duration: Some(Duration::from_millis(150)),
+		};
+		let mut workers = HashMap::new();
+		workers.insert(2, worker("Block scrub worker", WorkerState::Throttled(1.5)));
+		workers.insert(1, worker("Block resync worker", WorkerState::Busy));
+
+		let res = serde_json::to_value(list_background_workers(workers)).unwrap();
+		assert_eq!(res[0]["id"], 1);
+		assert_eq!(res[0]["state"], "running");
+		assert_eq!(res[1]["name"], "Block scrub worker");
+		assert_eq!(res[1]["state"], "waiting");
+		assert_eq!(res[1]["lastRun"], "2023-11-14T22:14:20.000Z");
+		assert_eq!(res[1]["lastRunDurationMsecs"], 150);
+		assert_eq!(res[1]["progressPercent"], 12.5);
+		assert_eq!(res[1]["lastError"]["message"], "Network error");
+	}
+}
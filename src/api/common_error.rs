@@ -54,6 +54,7 @@ impl CommonError {
 		match self {
 			CommonError::InternalError(
 				GarageError::Timeout
+				| GarageError::Overloaded(_)
 				| GarageError::RemoteError(_)
 				| GarageError::Quorum(_, _, _, _),
 			) => StatusCode::SERVICE_UNAVAILABLE,
@@ -71,6 +72,7 @@ impl CommonError {
 	pub fn aws_code(&self) -> &'static str {
 		match self {
 			CommonError::Forbidden(_) => "AccessDenied",
+			CommonError::InternalError(GarageError::Overloaded(_)) => "SlowDown",
 			CommonError::InternalError(
 				GarageError::Timeout
 				| GarageError::RemoteError(_)
@@ -14,6 +14,8 @@ use hyper::{HeaderMap, StatusCode};
 use opentelemetry::{
 	global,
 	metrics::{Counter, ValueRecorder},
+	propagation::{Extractor, TextMapPropagator},
+	sdk::propagation::TraceContextPropagator,
 	trace::{FutureExt, SpanRef, TraceContextExt, Tracer},
 	Context, KeyValue,
 };
@@ -122,10 +124,12 @@ impl<A: ApiHandler> ApiServer<A> {
 
 	async fn handler(
 		self: Arc<Self>,
-		req: Request<Body>,
+		mut req: Request<Body>,
 		addr: SocketAddr,
 	) -> Result<Response<Body>, GarageError> {
 		let uri = req.uri().clone();
+		// Make the client address available to API handlers
+		req.extensions_mut().insert(addr);
 
 		if let Ok(forwarded_for_ip_addr) =
 			forwarded_headers::handle_forwarded_for_headers(req.headers())
@@ -143,18 +147,24 @@ impl<A: ApiHandler> ApiServer<A> {
 		debug!("{:?}", req);
 
 		let tracer = opentelemetry::global::tracer("garage");
-		let span = tracer
+		let parent_context = trace_parent_context(req.headers());
+		let span_builder = tracer
 			.span_builder(format!("{} API call (unknown)", A::API_NAME_DISPLAY))
-			.with_trace_id(gen_trace_id())
 			.with_attributes(vec![
 				KeyValue::new("method", format!("{}", req.method())),
 				KeyValue::new("uri", req.uri().to_string()),
-			])
-			.start(&tracer);
+			]);
+		// If the client sent the context of its own trace, our spans are added to it
+		// so that the whole request can be followed from the client
+		let span = if parent_context.span().span_context().is_valid() {
+			span_builder.start_with_context(&tracer, &parent_context)
+		} else {
+			span_builder.with_trace_id(gen_trace_id()).start(&tracer)
+		};
 
 		let res = self
 			.handler_stage2(req)
-			.with_context(Context::current_with_span(span))
+			.with_context(parent_context.with_span(span))
 			.await;
 
 		match res {
@@ -223,3 +233,57 @@ impl<A: ApiHandler> ApiServer<A> {
 		res
 	}
 }
+
+/// Context of the trace of the client, read from the W3C Trace Context
+/// headers (`traceparent` and `tracestate`) of a request
+fn trace_parent_context(headers: &HeaderMap) -> Context {
+	TraceContextPropagator::new().extract(&HeaderExtractor(headers))
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl<'a> Extractor for HeaderExtractor<'a> {
+	fn get(&self, key: &str) -> Option<&str> {
+		self.0.get(key).and_then(|v| v.to_str().ok())
+	}
+
+	fn keys(&self) -> Vec<&str> {
+		self.0.keys().map(|k| k.as_str()).collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_trace_parent_context() {
+		let mut headers = HeaderMap::new();
+		assert!(!trace_parent_context(&headers)
+			.span()
+			.span_context()
+			.is_valid());
+
+		headers.insert(
+			"traceparent",
+			HeaderValue::from_static("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01"),
+		);
+		headers.insert("tracestate", HeaderValue::from_static("congo=t61rcWkgMzE"));
+		let context = trace_parent_context(&headers);
+		let span_context = context.span().span_context().clone();
+		assert!(span_context.is_valid());
+		assert!(span_context.is_remote());
+		assert_eq!(
+			span_context.trace_id().to_string(),
+			"0af7651916cd43dd8448eb211c80319c"
+		);
+		assert_eq!(span_context.span_id().to_string(), "b7ad6b7169203331");
+		assert_eq!(span_context.trace_state().get("congo"), Some("t61rcWkgMzE"));
+
+		headers.insert("traceparent", HeaderValue::from_static("not-a-trace"));
+		assert!(!trace_parent_context(&headers)
+			.span()
+			.span_context()
+			.is_valid());
+	}
+}
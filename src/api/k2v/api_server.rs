@@ -132,7 +132,28 @@ impl ApiHandler for K2VApiServer {
 			Endpoint::InsertItem {
 				partition_key,
 				sort_key,
+				cas: None,
 			} => handle_insert_item(garage, req, bucket_id, &partition_key, &sort_key).await,
+			Endpoint::InsertItem {
+				partition_key,
+				sort_key,
+				cas: Some(cas),
+			}
+			| Endpoint::CasItem {
+				partition_key,
+				sort_key,
+				causal_token_is: cas,
+			} => {
+				handle_compare_and_swap_item(
+					garage,
+					req,
+					bucket_id,
+					&partition_key,
+					&sort_key,
+					&cas,
+				)
+				.await
+			}
 			Endpoint::ReadItem {
 				partition_key,
 				sort_key,
@@ -173,8 +194,8 @@ impl ApiHandler for K2VApiServer {
 		// If request was a success and we have a CORS rule that applies to it,
 		// add the corresponding CORS headers to the response
 		let mut resp_ok = resp?;
-		if let Some(rule) = matching_cors_rule {
-			add_cors_headers(&mut resp_ok, rule)
+		if let Some((rule, origin)) = matching_cors_rule {
+			add_cors_headers(&mut resp_ok, rule, &origin)
 				.ok_or_internal_error("Invalid bucket CORS configuration")?;
 		}
 
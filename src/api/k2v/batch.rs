@@ -199,6 +199,7 @@ async fn handle_delete_batch_query(
 						i.sort_key,
 						Some(cc),
 						DvvsValue::Deleted,
+						None,
 					)
 					.await?;
 				1
@@ -6,14 +6,17 @@ use http::header;
 use hyper::{Body, Request, Response, StatusCode};
 
 use garage_util::data::*;
+use garage_util::time::now_msec;
 
 use garage_model::garage::Garage;
 use garage_model::k2v::causality::*;
 use garage_model::k2v::item_table::*;
+use garage_model::k2v::rpc::CasResult;
 
 use crate::k2v::error::*;
 
 pub const X_GARAGE_CAUSALITY_TOKEN: &str = "X-Garage-Causality-Token";
+pub const X_GARAGE_TTL: &str = "X-Garage-TTL";
 
 pub enum ReturnFormat {
 	Json,
@@ -117,6 +120,7 @@ pub async fn handle_read_item(
 			sort_key,
 		)
 		.await?
+		.filter(|item| !item.is_expired(now_msec()))
 		.ok_or(Error::NoSuchKey)?;
 
 	format.make_response(&item)
@@ -137,6 +141,21 @@ pub async fn handle_insert_item(
 		.map(CausalContext::parse_helper)
 		.transpose()?;
 
+	// The TTL is given in seconds from now, and stored as an expiration time
+	let ttl = req
+		.headers()
+		.get(X_GARAGE_TTL)
+		.map(|s| s.to_str())
+		.transpose()?
+		.map(|s| {
+			s.parse::<u64>()
+				.ok()
+				.and_then(|secs| secs.checked_mul(1000))
+				.and_then(|msecs| now_msec().checked_add(msecs))
+				.ok_or_bad_request("Invalid X-Garage-TTL header")
+		})
+		.transpose()?;
+
 	let body = hyper::body::to_bytes(req.into_body()).await?;
 	let value = DvvsValue::Value(body.to_vec());
 
@@ -149,6 +168,7 @@ pub async fn handle_insert_item(
 			sort_key.to_string(),
 			causal_context,
 			value,
+			ttl,
 		)
 		.await?;
 
@@ -157,6 +177,44 @@ pub async fn handle_insert_item(
 		.body(Body::empty())?)
 }
 
+/// Handle InsertItem request with a `cas` query parameter: the value is only
+/// written if the item has not changed since the given causality token was read
+pub async fn handle_compare_and_swap_item(
+	garage: Arc<Garage>,
+	req: Request<Body>,
+	bucket_id: Uuid,
+	partition_key: &str,
+	sort_key: &str,
+	cas: &str,
+) -> Result<Response<Body>, Error> {
+	let expected = CausalContext::parse_helper(cas)?;
+
+	let body = hyper::body::to_bytes(req.into_body()).await?;
+	let value = DvvsValue::Value(body.to_vec());
+
+	let res = garage
+		.k2v
+		.rpc
+		.compare_and_swap(
+			bucket_id,
+			partition_key.to_string(),
+			sort_key.to_string(),
+			expected,
+			value,
+		)
+		.await?;
+
+	match res {
+		CasResult::Ok => Ok(Response::builder()
+			.status(StatusCode::NO_CONTENT)
+			.body(Body::empty())?),
+		CasResult::CasFailed { current_causality } => Ok(Response::builder()
+			.header(X_GARAGE_CAUSALITY_TOKEN, current_causality.serialize())
+			.status(StatusCode::CONFLICT)
+			.body(Body::empty())?),
+	}
+}
+
 pub async fn handle_delete_item(
 	garage: Arc<Garage>,
 	req: Request<Body>,
@@ -183,6 +241,7 @@ pub async fn handle_delete_item(
 			sort_key.to_string(),
 			causal_context,
 			value,
+			None,
 		)
 		.await?;
 
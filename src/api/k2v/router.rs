@@ -24,6 +24,12 @@ pub enum Endpoint {
 	InsertItem {
 		partition_key: String,
 		sort_key: String,
+		cas: Option<String>,
+	},
+	CasItem {
+		partition_key: String,
+		sort_key: String,
+		causal_token_is: String,
 	},
 	Options,
 	PollItem {
@@ -146,6 +152,7 @@ impl Endpoint {
 			@gen_parser
 			(query.keyword.take().unwrap_or_default(), partition_key, query, None),
 			key: [
+				EMPTY if causal_token_is => CasItem (query::sort_key, query::causal_token_is),
 				POLL_RANGE => PollRange,
 			],
 			no_key: [
@@ -162,7 +169,7 @@ impl Endpoint {
 			@gen_parser
 			(query.keyword.take().unwrap_or_default(), partition_key, query, None),
 			key: [
-				EMPTY => InsertItem (query::sort_key),
+				EMPTY => InsertItem (query::sort_key, query_opt::cas),
 
 			],
 			no_key: [
@@ -191,6 +198,7 @@ impl Endpoint {
 			self,
 			partition_key,
 			[
+				CasItem,
 				DeleteItem,
 				InsertItem,
 				PollItem,
@@ -207,6 +215,7 @@ impl Endpoint {
 			self,
 			sort_key,
 			[
+				CasItem,
 				DeleteItem,
 				InsertItem,
 				PollItem,
@@ -246,6 +255,8 @@ generateQueryParameters! {
 		"prefix" => prefix,
 		"start" => start,
 		"causality_token" => causality_token,
+		"cas" => cas,
+		"causaltokenIs" => causal_token_is,
 		"end" => end,
 		"limit" => limit,
 		"reverse" => reverse,
@@ -4,7 +4,7 @@ extern crate tracing;
 
 pub mod common_error;
 
-mod encoding;
+pub mod encoding;
 pub mod generic_server;
 pub mod helpers;
 mod router_macros;
@@ -0,0 +1,369 @@
+//! Server access logs: records of the requests made to buckets that have
+//! logging enabled are buffered in memory, and regularly written as
+//! gzip-compressed objects in the target bucket of their logging configuration
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::{TimeZone, Utc};
+use hyper::header::{HeaderMap, HeaderValue};
+use hyper::{Body, Request, Response};
+use tokio::sync::watch;
+
+use garage_util::data::*;
+use garage_util::forwarded_headers;
+use garage_util::time::*;
+
+use garage_model::bucket_table::*;
+use garage_model::garage::Garage;
+use garage_model::s3::object_table::*;
+
+use crate::common_error::CommonError;
+use crate::generic_server::ApiError;
+use crate::s3::error::*;
+use crate::s3::object_lock::get_object_lock;
+use crate::s3::put::save_stream;
+use crate::s3::router::Endpoint;
+
+/// A request made to a bucket that has logging enabled,
+/// whose record is written once its response is known
+pub(crate) struct PendingAccessLog {
+	config: LoggingConfig,
+	start: Instant,
+	record: AccessLogRecord,
+}
+
+/// A line of the access logs, in a format close to that of S3 server access logs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessLogRecord {
+	/// Timestamp (msec) at which the request was received
+	pub time: u64,
+	pub bucket: String,
+	pub remote_ip: String,
+	/// Access key ID of the requester
+	pub requester: String,
+	pub operation: &'static str,
+	pub key: Option<String>,
+	/// Request line, e.g. `GET /bucket/key HTTP/1.1`
+	pub request_uri: String,
+	pub status: u16,
+	pub error_code: Option<&'static str>,
+	/// Size of the response body, if known from its Content-Length
+	pub bytes_sent: Option<u64>,
+	/// Time (msec) taken to process the request, until the response headers were sent
+	pub total_time: u64,
+	pub user_agent: Option<String>,
+}
+
+impl AccessLogRecord {
+	/// Format the record as a single line, where absent fields are written as `-`
+	pub fn to_log_line(&self) -> String {
+		let time = Utc
+			.timestamp_millis_opt(self.time as i64)
+			.single()
+			.map(|t| t.format("%d/%b/%Y:%H:%M:%S %z").to_string())
+			.unwrap_or_else(|| "-".into());
+		format!(
+			"{} [{}] {} {} {} {} {} {} {} {} {} {}\n",
+			self.bucket,
+			time,
+			self.remote_ip,
+			self.requester,
+			self.operation,
+			log_field(self.key.as_deref()),
+			log_quoted(&self.request_uri),
+			self.status,
+			self.error_code.unwrap_or("-"),
+			self.bytes_sent
+				.map(|b| b.to_string())
+				.unwrap_or_else(|| "-".into()),
+			self.total_time,
+			log_quoted(self.user_agent.as_deref().unwrap_or("-")),
+		)
+	}
+}
+
+/// Fields are separated by spaces: they are percent-encoded if they contain any
+fn log_field(s: Option<&str>) -> String {
+	match s {
+		None | Some("") => "-".into(),
+		Some(s) => s.replace('%', "%25").replace(' ', "%20"),
+	}
+}
+
+fn log_quoted(s: &str) -> String {
+	format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Buffers the access log records of all buckets, grouped by logging target
+pub struct LogBuffer {
+	garage: Arc<Garage>,
+	buffer_size: usize,
+	flush_interval: Duration,
+	buffers: Mutex<HashMap<LoggingConfig, Vec<u8>>>,
+}
+
+impl LogBuffer {
+	pub fn new(garage: Arc<Garage>) -> Arc<Self> {
+		let buffer_size = garage.config.s3_api.access_log_buffer_size;
+		let flush_interval =
+			Duration::from_secs(garage.config.s3_api.access_log_flush_interval_secs);
+		Arc::new(Self {
+			garage,
+			buffer_size,
+			flush_interval,
+			buffers: Mutex::new(HashMap::new()),
+		})
+	}
+
+	/// Start recording a request, if it is made to a bucket that has logging enabled
+	pub(crate) fn start(
+		&self,
+		req: &Request<Body>,
+		start: Instant,
+		bucket: &Bucket,
+		bucket_name: &str,
+		requester: &str,
+		endpoint: &Endpoint,
+	) -> Option<PendingAccessLog> {
+		let config = bucket.params()?.logging_config.get().clone()?;
+		let record = AccessLogRecord {
+			time: now_msec() - start.elapsed().as_millis() as u64,
+			bucket: bucket_name.to_string(),
+			remote_ip: remote_ip(req),
+			requester: requester.to_string(),
+			operation: endpoint.name(),
+			key: endpoint.get_key().map(str::to_string),
+			request_uri: format!("{} {} {:?}", req.method(), req.uri(), req.version()),
+			status: 0,
+			error_code: None,
+			bytes_sent: None,
+			total_time: 0,
+			user_agent: req
+				.headers()
+				.get(hyper::header::USER_AGENT)
+				.and_then(|v| v.to_str().ok())
+				.map(str::to_string),
+		};
+		Some(PendingAccessLog {
+			config,
+			start,
+			record,
+		})
+	}
+
+	/// Complete the record of a request with its response, and add it to the buffer
+	/// of its logging target. This never fails: logs that cannot be written are dropped.
+	pub(crate) fn finish(
+		self: &Arc<Self>,
+		pending: Option<PendingAccessLog>,
+		resp: &Result<Response<Body>, Error>,
+	) {
+		let PendingAccessLog {
+			config,
+			start,
+			mut record,
+		} = match pending {
+			Some(p) => p,
+			None => return,
+		};
+
+		record.total_time = start.elapsed().as_millis() as u64;
+		match resp {
+			Ok(r) => {
+				record.status = r.status().as_u16();
+				record.bytes_sent = content_length(r.headers());
+			}
+			Err(e) => {
+				record.status = e.http_status_code().as_u16();
+				record.error_code = Some(e.aws_code());
+			}
+		}
+
+		let full_buffer = {
+			let mut buffers = self.buffers.lock().unwrap();
+			let buffer = buffers.entry(config.clone()).or_default();
+			buffer.extend_from_slice(record.to_log_line().as_bytes());
+			if buffer.len() >= self.buffer_size {
+				buffers.remove(&config)
+			} else {
+				None
+			}
+		};
+
+		if let Some(data) = full_buffer {
+			let this = self.clone();
+			tokio::spawn(async move { this.write_logs(config, data).await });
+		}
+	}
+
+	/// Write the buffered logs at a regular interval, and a last time when exiting
+	pub async fn flush_loop(self: Arc<Self>, mut must_exit: watch::Receiver<bool>) {
+		while !*must_exit.borrow() {
+			tokio::select! {
+				_ = tokio::time::sleep(self.flush_interval) => (),
+				_ = must_exit.changed() => (),
+			}
+			self.flush_all().await;
+		}
+	}
+
+	async fn flush_all(&self) {
+		let buffers = std::mem::take(&mut *self.buffers.lock().unwrap());
+		for (config, data) in buffers {
+			self.write_logs(config, data).await;
+		}
+	}
+
+	async fn write_logs(&self, config: LoggingConfig, data: Vec<u8>) {
+		if let Err(e) = self.write_logs_object(&config, &data).await {
+			warn!(
+				"Could not write access logs to bucket {}: {}",
+				config.target_bucket, e
+			);
+		}
+	}
+
+	/// Write logs in their target bucket, bypassing the permission checks of the S3 API
+	async fn write_logs_object(&self, config: &LoggingConfig, data: &[u8]) -> Result<(), Error> {
+		let bucket_id = self
+			.garage
+			.bucket_helper()
+			.resolve_global_bucket_name(&config.target_bucket)
+			.await?
+			.ok_or_else(|| {
+				Error::Common(CommonError::NoSuchBucket(config.target_bucket.clone()))
+			})?;
+		let bucket = self
+			.garage
+			.bucket_helper()
+			.get_existing_bucket(bucket_id)
+			.await?;
+
+		let key = format!(
+			"{}{}-{}.gz",
+			config.target_prefix,
+			Utc::now().format("%Y-%m-%d-%H-%M-%S"),
+			hex::encode(&gen_uuid().as_slice()[..8])
+		);
+		let headers = ObjectVersionHeaders {
+			content_type: "application/gzip".into(),
+			other: Default::default(),
+		};
+		let lock = get_object_lock(&bucket, &HeaderMap::new())?;
+		let body = futures::stream::iter(vec![Ok(gzip_compress(data).into())]);
+
+		save_stream(
+			self.garage.clone(),
+			headers,
+			lock,
+			ObjectTags::default(),
+			StorageClass::Standard,
+			body,
+			&bucket,
+			&key,
+			None,
+			None,
+			None,
+			// Logs are already compressed with gzip
+			None,
+			None,
+		)
+		.await?;
+
+		Ok(())
+	}
+}
+
+/// The address of the client, as given by the reverse proxy if there is one
+pub(crate) fn remote_ip(req: &Request<Body>) -> String {
+	match forwarded_headers::handle_forwarded_for_headers(req.headers()) {
+		Ok(ip) => ip,
+		Err(_) => req
+			.extensions()
+			.get::<SocketAddr>()
+			.map(|addr| addr.ip().to_string())
+			.unwrap_or_else(|| "-".into()),
+	}
+}
+
+pub(crate) fn content_length(headers: &HeaderMap<HeaderValue>) -> Option<u64> {
+	headers
+		.get(hyper::header::CONTENT_LENGTH)?
+		.to_str()
+		.ok()?
+		.parse()
+		.ok()
+}
+
+/// Compress data in the gzip format (RFC 1952), with a minimal header
+pub(crate) fn gzip_compress(data: &[u8]) -> Vec<u8> {
+	// Magic number, deflate method, no flags, no modification time,
+	// no extra flags, unknown OS
+	let mut gz = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+	gz.extend(miniz_oxide::deflate::compress_to_vec(data, 6));
+	gz.extend(crc32fast::hash(data).to_le_bytes());
+	gz.extend((data.len() as u32).to_le_bytes());
+	gz
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn record() -> AccessLogRecord {
+		AccessLogRecord {
+			time: 1700000000000,
+			bucket: "my-bucket".into(),
+			remote_ip: "192.0.2.3".into(),
+			requester: "GK31c2f218a2e44f485b94239e".into(),
+			operation: "GetObject",
+			key: Some("photos/my cat.jpg".into()),
+			request_uri: "GET /my-bucket/photos/my%20cat.jpg HTTP/1.1".into(),
+			status: 200,
+			error_code: None,
+			bytes_sent: Some(3462),
+			total_time: 12,
+			user_agent: Some("aws-cli/2.0 \"test\"".into()),
+		}
+	}
+
+	#[test]
+	fn test_log_line() {
+		assert_eq!(
+			record().to_log_line(),
+			"my-bucket [14/Nov/2023:22:13:20 +0000] 192.0.2.3 GK31c2f218a2e44f485b94239e \
+			GetObject photos/my%20cat.jpg \"GET /my-bucket/photos/my%20cat.jpg HTTP/1.1\" \
+			200 - 3462 12 \"aws-cli/2.0 \\\"test\\\"\"\n"
+		);
+
+		let error = AccessLogRecord {
+			operation: "ListObjects",
+			key: None,
+			status: 403,
+			error_code: Some("AccessDenied"),
+			bytes_sent: None,
+			user_agent: None,
+			..record()
+		};
+		assert!(error
+			.to_log_line()
+			.ends_with(" ListObjects - \"GET /my-bucket/photos/my%20cat.jpg HTTP/1.1\" 403 AccessDenied - 12 \"-\"\n"));
+	}
+
+	#[test]
+	fn test_gzip_compress() {
+		let data = record().to_log_line().repeat(100);
+		let gz = gzip_compress(data.as_bytes());
+		assert_eq!(&gz[..3], &[0x1f, 0x8b, 8]);
+		assert!(gz.len() < data.len());
+
+		let (deflated, trailer) = gz[10..].split_at(gz.len() - 18);
+		let inflated = miniz_oxide::inflate::decompress_to_vec(deflated).unwrap();
+		assert_eq!(inflated, data.as_bytes());
+		assert_eq!(trailer[..4], crc32fast::hash(data.as_bytes()).to_le_bytes());
+		assert_eq!(trailer[4..], (data.len() as u32).to_le_bytes());
+	}
+}
@@ -1,18 +1,25 @@
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Instant;
 
 use async_trait::async_trait;
 
 use futures::future::Future;
 use hyper::header;
 use hyper::{Body, Request, Response};
+use tokio::sync::watch;
 
-use opentelemetry::{trace::SpanRef, KeyValue};
+use opentelemetry::{
+	trace::{SpanRef, TraceContextExt},
+	Context, KeyValue,
+};
 
 use garage_util::error::Error as GarageError;
+use garage_util::metrics::gen_trace_id;
 
 use garage_model::garage::Garage;
 use garage_model::key_table::Key;
+use garage_model::permission::S3Op;
 
 use crate::generic_server::*;
 use crate::s3::error::*;
@@ -21,19 +28,32 @@ use crate::signature::payload::check_payload_signature;
 use crate::signature::streaming::*;
 
 use crate::helpers::*;
+use crate::s3::access_log::LogBuffer;
 use crate::s3::bucket::*;
 use crate::s3::copy::*;
 use crate::s3::cors::*;
 use crate::s3::delete::*;
+use crate::s3::encryption::*;
 use crate::s3::get::*;
+use crate::s3::json_access_log::{JsonAccessLog, RequestContext};
+use crate::s3::lifecycle::*;
 use crate::s3::list::*;
+use crate::s3::logging::*;
+use crate::s3::notification::*;
+use crate::s3::object_lock::*;
+use crate::s3::policy::*;
 use crate::s3::post_object::handle_post_object;
 use crate::s3::put::*;
+use crate::s3::restore::handle_restore_object;
 use crate::s3::router::Endpoint;
+use crate::s3::select::handle_select_object_content;
+use crate::s3::tagging::*;
 use crate::s3::website::*;
 
 pub struct S3ApiServer {
 	garage: Arc<Garage>,
+	access_log: Arc<LogBuffer>,
+	json_access_log: Option<Arc<JsonAccessLog>>,
 }
 
 pub(crate) struct S3ApiEndpoint {
@@ -48,9 +68,36 @@ impl S3ApiServer {
 		s3_region: String,
 		shutdown_signal: impl Future<Output = ()>,
 	) -> Result<(), GarageError> {
-		ApiServer::new(s3_region, S3ApiServer { garage })
+		check_trusted_proxies(&garage.config.s3_api.trusted_proxies)
+			.map_err(GarageError::Message)?;
+
+		let json_access_log = if garage.config.s3_api.json_access_log {
+			let path = garage.config.s3_api.access_log_path.clone();
+			Some(JsonAccessLog::new(path).await.map_err(|e| {
+				GarageError::Message(format!("Unable to open access log file: {}", e))
+			})?)
+		} else {
+			None
+		};
+
+		let access_log = LogBuffer::new(garage.clone());
+		let (send_exit, must_exit) = watch::channel(false);
+		let flush_task = tokio::spawn(access_log.clone().flush_loop(must_exit));
+
+		let server = S3ApiServer {
+			garage,
+			access_log,
+			json_access_log,
+		};
+		let res = ApiServer::new(s3_region, server)
 			.run_server(addr, shutdown_signal)
-			.await
+			.await;
+
+		// Write the access logs that are still buffered before exiting
+		send_exit.send(true).ok();
+		flush_task.await.ok();
+
+		res
 	}
 
 	async fn handle_request_without_bucket(
@@ -104,12 +151,49 @@ impl ApiHandler for S3ApiServer {
 		&self,
 		req: Request<Body>,
 		endpoint: S3ApiEndpoint,
+	) -> Result<Response<Body>, Error> {
+		let json_access_log = self.json_access_log.as_ref().map(|log| {
+			// The trace ID identifies the request in the traces of all nodes,
+			// if tracing is enabled
+			let span_context = Context::current().span().span_context().clone();
+			let request_id = if span_context.is_valid() {
+				span_context.trace_id().to_string()
+			} else {
+				gen_trace_id().to_string()
+			};
+			let pending = log.start(
+				&req,
+				Instant::now(),
+				request_id,
+				endpoint.bucket_name.as_deref(),
+				&endpoint.endpoint,
+			);
+			(log, pending)
+		});
+
+		let mut ctx = RequestContext::default();
+		let resp = self.handle_request(req, endpoint, &mut ctx).await;
+
+		if let Some((log, pending)) = json_access_log {
+			log.finish(pending, ctx, &resp);
+		}
+		resp
+	}
+}
+
+impl S3ApiServer {
+	async fn handle_request(
+		&self,
+		req: Request<Body>,
+		endpoint: S3ApiEndpoint,
+		ctx: &mut RequestContext,
 	) -> Result<Response<Body>, Error> {
 		let S3ApiEndpoint {
 			bucket_name,
 			endpoint,
 		} = endpoint;
 		let garage = self.garage.clone();
+		let start = Instant::now();
 
 		// Some endpoints are processed early, before we even check for an API key
 		if let Endpoint::PostObject = endpoint {
@@ -122,6 +206,7 @@ impl ApiHandler for S3ApiServer {
 		let (api_key, mut content_sha256) = check_payload_signature(&garage, "s3", &req).await?;
 		let api_key = api_key
 			.ok_or_else(|| Error::forbidden("Garage does not support anonymous access yet"))?;
+		ctx.access_key_id = Some(api_key.key_id.clone());
 
 		let req = parse_streaming_body(
 			&api_key,
@@ -154,26 +239,95 @@ impl ApiHandler for S3ApiServer {
 			.get_existing_bucket(bucket_id)
 			.await?;
 
-		let allowed = match endpoint.authorization_type() {
-			Authorization::Read => api_key.allow_read(&bucket_id),
-			Authorization::Write => api_key.allow_write(&bucket_id),
-			Authorization::Owner => api_key.allow_owner(&bucket_id),
-			_ => unreachable!(),
+		let key_allowed = match endpoint.s3_op() {
+			// HeadBucket only tells that the bucket exists, which any key that can do
+			// something on it may know, unless it was specifically denied to the key
+			Some(S3Op::HeadBucket) => {
+				let perm = api_key.bucket_permissions(&bucket_id);
+				!perm.allowed_ops().is_empty() && !perm.denied_ops.contains(S3Op::HeadBucket)
+			}
+			Some(op) => api_key.allow_op(&bucket_id, op),
+			// Endpoints that Garage does not implement are refused later on
+			None => match endpoint.authorization_type() {
+				Authorization::Read => api_key.allow_read(&bucket_id),
+				Authorization::Write => api_key.allow_write(&bucket_id),
+				Authorization::Owner => api_key.allow_owner(&bucket_id),
+				_ => unreachable!(),
+			},
+		};
+
+		let policy =
+			PolicyContext::new(&self.garage, &req, &bucket, &bucket_name, &api_key.key_id)?;
+		let allowed = match endpoint {
+			// Keys that can manage the bucket policy always keep this permission,
+			// so that a policy cannot lock everyone out of the bucket
+			Endpoint::GetBucketPolicy {}
+			| Endpoint::PutBucketPolicy {}
+			| Endpoint::DeleteBucketPolicy {}
+			| Endpoint::GetBucketPolicyStatus {}
+				if key_allowed =>
+			{
+				true
+			}
+			_ => policy.is_allowed(
+				policy_action(endpoint.name()),
+				endpoint.get_key(),
+				key_allowed,
+			),
 		};
 
+		let access_log = self.access_log.start(
+			&req,
+			start,
+			&bucket,
+			&bucket_name,
+			&api_key.key_id,
+			&endpoint,
+		);
+
 		if !allowed {
-			return Err(Error::forbidden("Operation is not allowed for this key."));
+			let resp = Err(Error::forbidden("Operation is not allowed for this key."));
+			self.access_log.finish(access_log, &resp);
+			return resp;
 		}
 
 		let matching_cors_rule = find_matching_cors_rule(&bucket, &req)?;
 
 		let resp = match endpoint {
 			Endpoint::HeadObject {
-				key, part_number, ..
-			} => handle_head(garage, &req, bucket_id, &key, part_number).await,
+				key,
+				part_number,
+				version_id,
+			} => {
+				handle_head(
+					garage,
+					&req,
+					bucket_id,
+					&key,
+					version_id.as_deref(),
+					part_number,
+				)
+				.await
+			}
 			Endpoint::GetObject {
-				key, part_number, ..
-			} => handle_get(garage, &req, bucket_id, &key, part_number).await,
+				key,
+				part_number,
+				version_id,
+			} => {
+				handle_get(
+					garage,
+					&req,
+					bucket_id,
+					&key,
+					version_id.as_deref(),
+					part_number,
+				)
+				.await
+			}
+			Endpoint::GetObjectAttributes { key, version_id } => {
+				handle_get_object_attributes(garage, &req, bucket_id, &key, version_id.as_deref())
+					.await
+			}
 			Endpoint::UploadPart {
 				key,
 				part_number,
@@ -182,7 +336,7 @@ impl ApiHandler for S3ApiServer {
 				handle_put_part(
 					garage,
 					req,
-					bucket_id,
+					&bucket,
 					&key,
 					part_number,
 					&upload_id,
@@ -191,7 +345,7 @@ impl ApiHandler for S3ApiServer {
 				.await
 			}
 			Endpoint::CopyObject { key } => {
-				handle_copy(garage, &api_key, &req, bucket_id, &key).await
+				handle_copy(garage, &api_key, &req, &bucket, &key).await
 			}
 			Endpoint::UploadPartCopy {
 				key,
@@ -202,7 +356,7 @@ impl ApiHandler for S3ApiServer {
 					garage,
 					&api_key,
 					&req,
-					bucket_id,
+					&bucket,
 					&key,
 					part_number,
 					&upload_id,
@@ -215,9 +369,20 @@ impl ApiHandler for S3ApiServer {
 			Endpoint::AbortMultipartUpload { key, upload_id } => {
 				handle_abort_multipart_upload(garage, bucket_id, &key, &upload_id).await
 			}
-			Endpoint::DeleteObject { key, .. } => handle_delete(garage, bucket_id, &key).await,
+			Endpoint::DeleteObject { key, version_id } => {
+				let bypass_governance =
+					bypass_governance_retention(req.headers(), &api_key, &bucket_id);
+				handle_delete(
+					garage,
+					&bucket,
+					&key,
+					version_id.as_deref(),
+					bypass_governance,
+				)
+				.await
+			}
 			Endpoint::CreateMultipartUpload { key } => {
-				handle_create_multipart_upload(garage, &req, &bucket_name, bucket_id, &key).await
+				handle_create_multipart_upload(garage, &req, &bucket_name, &bucket, &key).await
 			}
 			Endpoint::CompleteMultipartUpload { key, upload_id } => {
 				handle_complete_multipart_upload(
@@ -241,7 +406,10 @@ impl ApiHandler for S3ApiServer {
 				handle_delete_bucket(&garage, bucket_id, bucket_name, api_key).await
 			}
 			Endpoint::GetBucketLocation {} => handle_get_bucket_location(garage),
-			Endpoint::GetBucketVersioning {} => handle_get_bucket_versioning(),
+			Endpoint::GetBucketVersioning {} => handle_get_bucket_versioning(&bucket),
+			Endpoint::PutBucketVersioning {} => {
+				handle_put_bucket_versioning(garage, bucket_id, req, content_sha256).await
+			}
 			Endpoint::ListObjects {
 				delimiter,
 				encoding_type,
@@ -329,6 +497,31 @@ impl ApiHandler for S3ApiServer {
 				)
 				.await
 			}
+			Endpoint::ListObjectVersions {
+				delimiter,
+				encoding_type,
+				key_marker,
+				max_keys,
+				prefix,
+				version_id_marker,
+			} => {
+				handle_list_object_versions(
+					garage,
+					&ListObjectVersionsQuery {
+						common: ListQueryCommon {
+							bucket_name,
+							bucket_id,
+							delimiter: delimiter.map(|d| d.to_string()),
+							page_size: max_keys.map(|p| p.clamp(1, 1000) as usize).unwrap_or(1000),
+							prefix: prefix.unwrap_or_default(),
+							urlencode_resp: encoding_type.map(|e| e == "url").unwrap_or(false),
+						},
+						key_marker,
+						version_id_marker,
+					},
+				)
+				.await
+			}
 			Endpoint::ListParts {
 				key,
 				max_parts,
@@ -349,7 +542,17 @@ impl ApiHandler for S3ApiServer {
 				.await
 			}
 			Endpoint::DeleteObjects {} => {
-				handle_delete_objects(garage, bucket_id, req, content_sha256).await
+				let bypass_governance =
+					bypass_governance_retention(req.headers(), &api_key, &bucket_id);
+				handle_delete_objects(
+					garage,
+					&bucket,
+					&policy,
+					req,
+					content_sha256,
+					bypass_governance,
+				)
+				.await
 			}
 			Endpoint::GetBucketWebsite {} => handle_get_website(&bucket).await,
 			Endpoint::PutBucketWebsite {} => {
@@ -361,14 +564,117 @@ impl ApiHandler for S3ApiServer {
 				handle_put_cors(garage, bucket_id, req, content_sha256).await
 			}
 			Endpoint::DeleteBucketCors {} => handle_delete_cors(garage, bucket_id).await,
+			Endpoint::GetBucketEncryption {} => handle_get_encryption(&bucket).await,
+			Endpoint::PutBucketEncryption {} => {
+				handle_put_encryption(garage, bucket_id, req, content_sha256).await
+			}
+			Endpoint::DeleteBucketEncryption {} => {
+				handle_delete_encryption(garage, bucket_id).await
+			}
+			Endpoint::GetBucketLifecycleConfiguration {} => handle_get_lifecycle(&bucket).await,
+			Endpoint::PutBucketLifecycleConfiguration {} => {
+				handle_put_lifecycle(garage, bucket_id, req, content_sha256).await
+			}
+			Endpoint::DeleteBucketLifecycle {} => handle_delete_lifecycle(garage, bucket_id).await,
+			Endpoint::GetObjectLockConfiguration {} => handle_get_object_lock_config(&bucket).await,
+			Endpoint::PutObjectLockConfiguration {} => {
+				handle_put_object_lock_config(garage, bucket_id, req, content_sha256).await
+			}
+			Endpoint::GetObjectRetention { key, version_id } => {
+				handle_get_object_retention(garage, bucket_id, &key, version_id.as_deref()).await
+			}
+			Endpoint::PutObjectRetention { key, version_id } => {
+				handle_put_object_retention(
+					garage,
+					&bucket,
+					&api_key,
+					&key,
+					version_id.as_deref(),
+					req,
+					content_sha256,
+				)
+				.await
+			}
+			Endpoint::GetObjectLegalHold { key, version_id } => {
+				handle_get_object_legal_hold(garage, bucket_id, &key, version_id.as_deref()).await
+			}
+			Endpoint::PutObjectLegalHold { key, version_id } => {
+				handle_put_object_legal_hold(
+					garage,
+					&bucket,
+					&key,
+					version_id.as_deref(),
+					req,
+					content_sha256,
+				)
+				.await
+			}
+			Endpoint::GetObjectTagging { key, version_id } => {
+				handle_get_object_tagging(garage, bucket_id, &key, version_id.as_deref()).await
+			}
+			Endpoint::PutObjectTagging { key, version_id } => {
+				handle_put_object_tagging(
+					garage,
+					bucket_id,
+					&key,
+					version_id.as_deref(),
+					req,
+					content_sha256,
+				)
+				.await
+			}
+			Endpoint::RestoreObject { key, version_id } => {
+				handle_restore_object(
+					garage,
+					bucket_id,
+					&key,
+					version_id.as_deref(),
+					req,
+					content_sha256,
+				)
+				.await
+			}
+			Endpoint::SelectObjectContent { key, select_type } => {
+				handle_select_object_content(
+					garage,
+					bucket_id,
+					&key,
+					&select_type,
+					req,
+					content_sha256,
+				)
+				.await
+			}
+			Endpoint::DeleteObjectTagging { key, version_id } => {
+				handle_delete_object_tagging(garage, bucket_id, &key, version_id.as_deref()).await
+			}
+			Endpoint::GetBucketLogging {} => handle_get_logging(&bucket).await,
+			Endpoint::PutBucketLogging {} => {
+				handle_put_logging(garage, &api_key, bucket_id, req, content_sha256).await
+			}
+			Endpoint::DeleteBucketLogging {} => handle_delete_logging(garage, bucket_id).await,
+			Endpoint::GetBucketNotificationConfiguration {} => {
+				handle_get_notification(&bucket).await
+			}
+			Endpoint::PutBucketNotificationConfiguration {} => {
+				handle_put_notification(garage, bucket_id, req, content_sha256).await
+			}
+			Endpoint::GetBucketPolicy {} => handle_get_bucket_policy(&bucket).await,
+			Endpoint::GetBucketPolicyStatus {} => handle_get_bucket_policy_status(&bucket).await,
+			Endpoint::PutBucketPolicy {} => {
+				handle_put_bucket_policy(garage, bucket_id, req, content_sha256).await
+			}
+			Endpoint::DeleteBucketPolicy {} => handle_delete_bucket_policy(garage, bucket_id).await,
 			endpoint => Err(Error::NotImplemented(endpoint.name().to_owned())),
 		};
 
+		self.access_log.finish(access_log, &resp);
+
 		// If request was a success and we have a CORS rule that applies to it,
 		// add the corresponding CORS headers to the response
 		let mut resp_ok = resp?;
-		if let Some(rule) = matching_cors_rule {
-			add_cors_headers(&mut resp_ok, rule)
+		if let Some((rule, origin)) = matching_cors_rule {
+			add_cors_headers(&mut resp_ok, rule, &origin)
 				.ok_or_internal_error("Invalid bucket CORS configuration")?;
 		}
 
@@ -4,7 +4,7 @@ use std::sync::Arc;
 use hyper::{Body, Request, Response, StatusCode};
 
 use garage_model::bucket_alias_table::*;
-use garage_model::bucket_table::Bucket;
+use garage_model::bucket_table::{Bucket, ObjectLockConfig, VersioningState};
 use garage_model::garage::Garage;
 use garage_model::key_table::Key;
 use garage_model::permission::BucketKeyPerm;
@@ -15,6 +15,7 @@ use garage_util::time::*;
 
 use crate::common_error::CommonError;
 use crate::s3::error::*;
+use crate::s3::object_lock::BUCKET_OBJECT_LOCK_HEADER;
 use crate::s3::xml as s3_xml;
 use crate::signature::verify_signed_content;
 
@@ -30,10 +31,15 @@ pub fn handle_get_bucket_location(garage: Arc<Garage>) -> Result<Response<Body>,
 		.body(Body::from(xml.into_bytes()))?)
 }
 
-pub fn handle_get_bucket_versioning() -> Result<Response<Body>, Error> {
+pub fn handle_get_bucket_versioning(bucket: &Bucket) -> Result<Response<Body>, Error> {
+	let status = match bucket.params().map(|p| *p.versioning_state.get()) {
+		Some(VersioningState::Enabled) => Some("Enabled"),
+		Some(VersioningState::Suspended) => Some("Suspended"),
+		Some(VersioningState::Disabled) | None => None,
+	};
 	let versioning = s3_xml::VersioningConfiguration {
 		xmlns: (),
-		status: None,
+		status: status.map(s3_xml::Value::from),
 	};
 
 	let xml = s3_xml::to_xml_with_header(&versioning)?;
@@ -43,12 +49,51 @@ pub fn handle_get_bucket_versioning() -> Result<Response<Body>, Error> {
 		.body(Body::from(xml.into_bytes()))?)
 }
 
+pub async fn handle_put_bucket_versioning(
+	garage: Arc<Garage>,
+	bucket_id: Uuid,
+	req: Request<Body>,
+	content_sha256: Option<Hash>,
+) -> Result<Response<Body>, Error> {
+	let body = hyper::body::to_bytes(req.into_body()).await?;
+
+	if let Some(content_sha256) = content_sha256 {
+		verify_signed_content(content_sha256, &body[..])?;
+	}
+
+	let (status, mfa_delete) = parse_versioning_configuration_xml(&body)
+		.ok_or_bad_request("Invalid VersioningConfiguration XML")?;
+	if mfa_delete == Some(true) {
+		return Err(Error::NotImplemented("MFA delete".into()));
+	}
+
+	if let Some(status) = status {
+		let mut bucket = garage
+			.bucket_helper()
+			.get_existing_bucket(bucket_id)
+			.await?;
+		if status != VersioningState::Enabled && bucket.object_lock_enabled() {
+			return Err(Error::InvalidBucketState(
+				"Versioning cannot be suspended on a bucket with object lock enabled".into(),
+			));
+		}
+		bucket.params_mut().unwrap().versioning_state.update(status);
+		garage.bucket_table.insert(&bucket).await?;
+	}
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.body(Body::empty())?)
+}
+
 pub async fn handle_list_buckets(garage: &Garage, api_key: &Key) -> Result<Response<Body>, Error> {
 	let key_p = api_key.params().ok_or_internal_error(
 		"Key should not be in deleted state at this point (in handle_list_buckets)",
 	)?;
 
-	// Collect buckets user has access to
+	// Collect buckets user has access to, i.e. on which it can do
+	// at least one operation (being allowed to bypass governance-mode
+	// retention alone gives no access)
 	let ids = api_key
 		.state
 		.as_option()
@@ -56,7 +101,7 @@ pub async fn handle_list_buckets(garage: &Garage, api_key: &Key) -> Result<Respo
 		.authorized_buckets
 		.items()
 		.iter()
-		.filter(|(_, perms)| perms.is_any())
+		.filter(|(_, perms)| !perms.allowed_ops().is_empty())
 		.map(|(id, _)| *id)
 		.collect::<Vec<_>>();
 
@@ -119,6 +164,12 @@ pub async fn handle_create_bucket(
 	api_key: Key,
 	bucket_name: String,
 ) -> Result<Response<Body>, Error> {
+	let object_lock_enabled = req
+		.headers()
+		.get(BUCKET_OBJECT_LOCK_HEADER)
+		.map(|v| v.as_bytes().eq_ignore_ascii_case(b"true"))
+		.unwrap_or(false);
+
 	let body = hyper::body::to_bytes(req.into_body()).await?;
 
 	if let Some(content_sha256) = content_sha256 {
@@ -178,7 +229,15 @@ pub async fn handle_create_bucket(
 			)));
 		}
 
-		let bucket = Bucket::new();
+		let mut bucket = Bucket::new();
+		if object_lock_enabled {
+			// Object lock requires versioning, which can then no longer be suspended
+			let params = bucket.params_mut().unwrap();
+			params.versioning_state.update(VersioningState::Enabled);
+			params.object_lock_config.update(Some(ObjectLockConfig {
+				default_retention: None,
+			}));
+		}
 		garage.bucket_table.insert(&bucket).await?;
 
 		garage
@@ -317,6 +376,44 @@ fn parse_create_bucket_xml(xml_bytes: &[u8]) -> Option<Option<String>> {
 	Some(ret)
 }
 
+fn parse_versioning_configuration_xml(
+	xml_bytes: &[u8],
+) -> Option<(Option<VersioningState>, Option<bool>)> {
+	// Returns None if invalid data
+	// Otherwise returns the requested versioning state, if any,
+	// and whether MFA delete is requested, if specified
+
+	let xml_str = std::str::from_utf8(xml_bytes).ok()?;
+	let xml = roxmltree::Document::parse(xml_str).ok()?;
+
+	let vc = xml.root().first_child()?;
+	if !vc.has_tag_name("VersioningConfiguration") {
+		return None;
+	}
+
+	let mut status = None;
+	let mut mfa_delete = None;
+	for item in vc.children() {
+		if item.has_tag_name("Status") {
+			status = match item.text()? {
+				"Enabled" => Some(VersioningState::Enabled),
+				"Suspended" => Some(VersioningState::Suspended),
+				_ => return None,
+			};
+		} else if item.has_tag_name("MfaDelete") {
+			mfa_delete = match item.text()? {
+				"Enabled" => Some(true),
+				"Disabled" => Some(false),
+				_ => return None,
+			};
+		} else if !item.is_text() {
+			return None;
+		}
+	}
+
+	Some((status, mfa_delete))
+}
+
 #[cfg(test)]
 mod tests {
 	use super::*;
@@ -353,4 +450,39 @@ mod tests {
 			None
 		);
 	}
+
+	#[test]
+	fn versioning_configuration() {
+		assert_eq!(
+			parse_versioning_configuration_xml(
+				br#"
+            <VersioningConfiguration xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+                <Status>Enabled</Status>
+            </VersioningConfiguration >
+		"#
+			),
+			Some((Some(VersioningState::Enabled), None))
+		);
+		assert_eq!(
+			parse_versioning_configuration_xml(
+				br#"
+            <VersioningConfiguration xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+                <Status>Suspended</Status>
+                <MfaDelete>Disabled</MfaDelete>
+            </VersioningConfiguration >
+		"#
+			),
+			Some((Some(VersioningState::Suspended), Some(false)))
+		);
+		assert_eq!(
+			parse_versioning_configuration_xml(
+				br#"
+            <VersioningConfiguration xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+                <Status>Disabled</Status>
+            </VersioningConfiguration >
+		"#
+			),
+			None
+		);
+	}
 }
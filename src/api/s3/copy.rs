@@ -12,37 +12,62 @@ use serde::Serialize;
 use garage_rpc::netapp::bytes_buf::BytesBuf;
 use garage_rpc::rpc_helper::OrderTag;
 use garage_table::*;
+use garage_util::crdt::Lww;
 use garage_util::data::*;
 use garage_util::time::*;
 
+use garage_model::bucket_table::Bucket;
 use garage_model::garage::Garage;
 use garage_model::key_table::Key;
+use garage_model::permission::S3Op;
 use garage_model::s3::block_ref_table::*;
 use garage_model::s3::object_table::*;
 use garage_model::s3::version_table::*;
 
 use crate::helpers::parse_bucket_key;
+use crate::s3::encryption::{
+	check_encryption_required, check_upload_encryption_required, has_customer_key, CustomerKey,
+};
 use crate::s3::error::*;
-use crate::s3::put::{decode_upload_id, get_headers};
+use crate::s3::object_lock::get_object_lock;
+use crate::s3::policy::PolicyContext;
+use crate::s3::put::{check_max_object_size, check_quotas, decode_upload_id, get_headers};
+use crate::s3::restore::{check_object_readable, get_storage_class};
+use crate::s3::tagging::{get_tagging_header, TAGGING_DIRECTIVE_HEADER};
 use crate::s3::xml::{self as s3_xml, xmlns_tag};
 
 pub async fn handle_copy(
 	garage: Arc<Garage>,
 	api_key: &Key,
 	req: &Request<Body>,
-	dest_bucket_id: Uuid,
+	dest_bucket: &Bucket,
 	dest_key: &str,
 ) -> Result<Response<Body>, Error> {
+	let dest_bucket_id = dest_bucket.id;
+	check_encryption_required(dest_bucket, req.headers())?;
 	let copy_precondition = CopyPreconditionHeaders::parse(req)?;
 
-	let source_object = get_copy_source(&garage, api_key, req).await?;
+	let (source_object, source_version_id) = get_copy_source(&garage, api_key, req).await?;
 
 	let (source_version, source_version_data, source_version_meta) =
-		extract_source_info(&source_object)?;
+		extract_source_info(&source_object, source_version_id.as_deref())?;
 
 	// Check precondition, e.g. x-amz-copy-source-if-match
 	copy_precondition.check(source_version, &source_version_meta.etag)?;
 
+	// The data of objects encrypted with a key provided by the client (SSE-C)
+	// is not decrypted by copies, the copy must thus use the key of the source
+	let source_encryption = CustomerKey::check(
+		CustomerKey::from_copy_source_headers(req.headers())?,
+		&source_version_meta.headers,
+	)?;
+	let dest_encryption = CustomerKey::from_request_headers(req.headers())?;
+	if source_encryption != dest_encryption {
+		return Err(Error::NotImplemented(
+			"The copy of an object must be encrypted with the same SSE-C key as its source".into(),
+		));
+	}
+
 	// Generate parameters for copied object
 	let new_uuid = gen_uuid();
 	let new_timestamp = now_msec();
@@ -53,11 +78,37 @@ pub async fn handle_copy(
 			headers: get_headers(req.headers())?,
 			size: source_version_meta.size,
 			etag: source_version_meta.etag.clone(),
+			checksum: source_version_meta.checksum.clone(),
 		},
+		Some(v) if v != hyper::header::HeaderValue::from_static("COPY") => {
+			return Err(Error::bad_request(
+				"Unknown metadata directive, expected COPY or REPLACE",
+			));
+		}
 		_ => source_version_meta.clone(),
 	};
 
+	// Implement x-amz-tagging-directive: REPLACE
+	let tags = match req.headers().get(TAGGING_DIRECTIVE_HEADER) {
+		Some(v) if v == hyper::header::HeaderValue::from_static("REPLACE") => {
+			get_tagging_header(req.headers())?
+		}
+		Some(v) if v != hyper::header::HeaderValue::from_static("COPY") => {
+			return Err(Error::bad_request(
+				"Unknown tagging directive, expected COPY or REPLACE",
+			));
+		}
+		_ => source_version.tags.get().clone(),
+	};
+	let tags = Lww::new(tags);
+
 	let etag = new_meta.etag.to_string();
+	let versioned = dest_bucket.versioning_enabled();
+	let lock = get_object_lock(dest_bucket, req.headers())?;
+	let storage_class = get_storage_class(req.headers())?;
+
+	check_max_object_size(dest_bucket, new_meta.size)?;
+	check_quotas(&garage, dest_bucket, dest_key, new_meta.size).await?;
 
 	// Save object copy
 	match source_version_data {
@@ -70,6 +121,11 @@ pub async fn handle_copy(
 					new_meta,
 					bytes.clone(),
 				)),
+				versioned,
+				lock,
+				tags,
+				storage_class,
+				restore: Default::default(),
 			};
 			let dest_object = Object::new(
 				dest_bucket_id,
@@ -93,6 +149,11 @@ pub async fn handle_copy(
 				uuid: new_uuid,
 				timestamp: new_timestamp,
 				state: ObjectVersionState::Uploading(new_meta.headers.clone()),
+				versioned,
+				lock: lock.clone(),
+				tags: tags.clone(),
+				storage_class,
+				restore: Default::default(),
 			};
 			let tmp_dest_object = Object::new(
 				dest_bucket_id,
@@ -141,6 +202,11 @@ pub async fn handle_copy(
 					new_meta,
 					*first_block_hash,
 				)),
+				versioned,
+				lock,
+				tags,
+				storage_class,
+				restore: Default::default(),
 			};
 			let dest_object = Object::new(
 				dest_bucket_id,
@@ -158,31 +224,40 @@ pub async fn handle_copy(
 	};
 	let xml = s3_xml::to_xml_with_header(&result)?;
 
-	Ok(Response::builder()
+	let mut resp = Response::builder()
 		.header("Content-Type", "application/xml")
-		.header("x-amz-version-id", hex::encode(new_uuid))
 		.header(
-			"x-amz-copy-source-version-id",
-			hex::encode(source_version.uuid),
+			"x-amz-version-id",
+			if versioned {
+				hex::encode(new_uuid)
+			} else {
+				"null".into()
+			},
 		)
-		.body(Body::from(xml))?)
+		.header("x-amz-copy-source-version-id", source_version.version_id())
+		.body(Body::from(xml))?;
+	if let Some(key) = &dest_encryption {
+		key.add_response_headers(resp.headers_mut());
+	}
+	Ok(resp)
 }
 
 pub async fn handle_upload_part_copy(
 	garage: Arc<Garage>,
 	api_key: &Key,
 	req: &Request<Body>,
-	dest_bucket_id: Uuid,
+	dest_bucket: &Bucket,
 	dest_key: &str,
 	part_number: u64,
 	upload_id: &str,
 ) -> Result<Response<Body>, Error> {
+	let dest_bucket_id = dest_bucket.id;
 	let copy_precondition = CopyPreconditionHeaders::parse(req)?;
 
 	let dest_version_uuid = decode_upload_id(upload_id)?;
 
 	let dest_key = dest_key.to_string();
-	let (source_object, dest_object) = futures::try_join!(
+	let ((source_object, source_version_id), dest_object) = futures::try_join!(
 		get_copy_source(&garage, api_key, req),
 		garage
 			.object_table
@@ -192,7 +267,7 @@ pub async fn handle_upload_part_copy(
 	let dest_object = dest_object.ok_or(Error::NoSuchKey)?;
 
 	let (source_object_version, source_version_data, source_version_meta) =
-		extract_source_info(&source_object)?;
+		extract_source_info(&source_object, source_version_id.as_deref())?;
 
 	// Check precondition on source, e.g. x-amz-copy-source-if-match
 	copy_precondition.check(source_object_version, &source_version_meta.etag)?;
@@ -218,12 +293,23 @@ pub async fn handle_upload_part_copy(
 	};
 
 	// Check destination version is indeed in uploading state
-	if !dest_object
+	let dest_headers = dest_object
 		.versions()
 		.iter()
-		.any(|v| v.uuid == dest_version_uuid && v.is_uploading())
-	{
-		return Err(Error::NoSuchUpload);
+		.find(|v| v.uuid == dest_version_uuid)
+		.and_then(|v| match &v.state {
+			ObjectVersionState::Uploading(headers) => Some(headers),
+			_ => None,
+		})
+		.ok_or(Error::NoSuchUpload)?;
+	check_upload_encryption_required(dest_bucket, dest_headers)?;
+
+	// Copying parts of objects encrypted with SSE-C would require decrypting
+	// and re-encrypting their blocks, which is not implemented
+	if has_customer_key(&source_version_meta.headers) || has_customer_key(dest_headers) {
+		return Err(Error::NotImplemented(
+			"UploadPartCopy is not supported for objects encrypted with SSE-C".into(),
+		));
 	}
 
 	// Check source version is not inlined
@@ -260,7 +346,43 @@ pub async fn handle_upload_part_copy(
 		}
 	}
 
-	// We want to reuse blocks from the source version as much as possible.
+	// When the whole content of an object that was uploaded in a single part
+	// is copied, the MD5sum of the part is the ETag of the source object,
+	// so the blocks of the source can be referenced without reading them.
+	if source_range.start == 0
+		&& source_range.length == source_version_meta.size
+		&& !source_version_meta.etag.contains('-')
+	{
+		let mut version = Version::new(dest_version_uuid, dest_bucket_id, dest_key.clone(), false);
+		let mut block_refs = vec![];
+		let mut current_offset = 0;
+		for (_bk, block) in source_version.blocks.items().iter() {
+			version.blocks.put(
+				VersionBlockKey {
+					part_number,
+					offset: current_offset,
+				},
+				*block,
+			);
+			current_offset += block.size;
+			block_refs.push(BlockRef {
+				block: block.hash,
+				version: dest_version_uuid,
+				deleted: false.into(),
+			});
+		}
+		version
+			.parts_etags
+			.put(part_number, source_version_meta.etag.clone());
+		futures::try_join!(
+			garage.version_table.insert(&version),
+			garage.block_ref_table.insert_many(&block_refs[..]),
+		)?;
+
+		return copy_part_response(&source_version_meta.etag, source_object_version);
+	}
+
+	// Otherwise, we want to reuse blocks from the source version as much as possible.
 	// However, we still need to get the data from these blocks
 	// because we need to know it to calculate the MD5sum of the part
 	// which is used as its ETag.
@@ -397,6 +519,13 @@ pub async fn handle_upload_part_copy(
 	garage.version_table.insert(&version).await?;
 
 	// LGTM
+	copy_part_response(&etag, source_object_version)
+}
+
+fn copy_part_response(
+	etag: &str,
+	source_object_version: &ObjectVersion,
+) -> Result<Response<Body>, Error> {
 	let resp_xml = s3_xml::to_xml_with_header(&CopyPartResult {
 		xmlns: (),
 		etag: s3_xml::Value(format!("\"{}\"", etag)),
@@ -412,12 +541,18 @@ pub async fn handle_upload_part_copy(
 		.body(Body::from(resp_xml))?)
 }
 
+/// Get the source object of a copy, and the version id given in the
+/// copy source header if any
 async fn get_copy_source(
 	garage: &Garage,
 	api_key: &Key,
 	req: &Request<Body>,
-) -> Result<Object, Error> {
+) -> Result<(Object, Option<String>), Error> {
 	let copy_source = req.headers().get("x-amz-copy-source").unwrap().to_str()?;
+	let (copy_source, version_id) = match copy_source.split_once("?versionId=") {
+		Some((source, version_id)) => (source, Some(version_id.to_string())),
+		None => (copy_source, None),
+	};
 	let copy_source = percent_encoding::percent_decode_str(copy_source).decode_utf8()?;
 
 	let (source_bucket, source_key) = parse_bucket_key(&copy_source, None)?;
@@ -425,41 +560,71 @@ async fn get_copy_source(
 		.bucket_helper()
 		.resolve_bucket(&source_bucket.to_string(), api_key)
 		.await?;
+	let source_key = source_key.ok_or_bad_request("No source key specified")?;
 
-	if !api_key.allow_read(&source_bucket_id) {
+	let source_bucket_params = garage
+		.bucket_helper()
+		.get_existing_bucket(source_bucket_id)
+		.await?;
+	let policy = PolicyContext::new(
+		garage,
+		req,
+		&source_bucket_params,
+		source_bucket,
+		&api_key.key_id,
+	)?;
+	if !policy.is_allowed(
+		"GetObject",
+		Some(source_key),
+		api_key.allow_op(&source_bucket_id, S3Op::GetObject),
+	) {
 		return Err(Error::forbidden(format!(
 			"Reading from bucket {} not allowed for this key",
 			source_bucket
 		)));
 	}
 
-	let source_key = source_key.ok_or_bad_request("No source key specified")?;
-
 	let source_object = garage
 		.object_table
 		.get(&source_bucket_id, &source_key.to_string())
 		.await?
 		.ok_or(Error::NoSuchKey)?;
 
-	Ok(source_object)
+	Ok((source_object, version_id))
 }
 
-fn extract_source_info(
-	source_object: &Object,
-) -> Result<(&ObjectVersion, &ObjectVersionData, &ObjectVersionMeta), Error> {
-	let source_version = source_object
-		.versions()
-		.iter()
-		.rev()
-		.find(|v| v.is_complete())
-		.ok_or(Error::NoSuchKey)?;
+fn extract_source_info<'a>(
+	source_object: &'a Object,
+	version_id: Option<&str>,
+) -> Result<
+	(
+		&'a ObjectVersion,
+		&'a ObjectVersionData,
+		&'a ObjectVersionMeta,
+	),
+	Error,
+> {
+	let source_version = match version_id {
+		Some(vid) => source_object
+			.find_version(vid)
+			.ok_or(Error::NoSuchVersion)?,
+		None => source_object.current_version().ok_or(Error::NoSuchKey)?,
+	};
 
 	let source_version_data = match &source_version.state {
 		ObjectVersionState::Complete(x) => x,
 		_ => unreachable!(),
 	};
+	if source_version.is_data() {
+		check_object_readable(source_version)?;
+	}
 
 	let source_version_meta = match source_version_data {
+		ObjectVersionData::DeleteMarker if version_id.is_some() => {
+			return Err(Error::bad_request(
+				"The source of a copy request may not specifically refer to a delete marker by version id",
+			));
+		}
 		ObjectVersionData::DeleteMarker => {
 			return Err(Error::NoSuchKey);
 		}
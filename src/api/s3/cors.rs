@@ -3,12 +3,14 @@ use std::sync::Arc;
 
 use http::header::{
 	ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN,
-	ACCESS_CONTROL_EXPOSE_HEADERS, ACCESS_CONTROL_REQUEST_HEADERS, ACCESS_CONTROL_REQUEST_METHOD,
+	ACCESS_CONTROL_EXPOSE_HEADERS, ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS,
+	ACCESS_CONTROL_REQUEST_METHOD, ORIGIN, VARY,
 };
 use hyper::{header::HeaderName, Body, Method, Request, Response, StatusCode};
 
 use serde::{Deserialize, Serialize};
 
+use crate::common_error::CommonError;
 use crate::s3::error::*;
 use crate::s3::xml::{to_xml_with_header, xmlns_tag, IntValue, Value};
 use crate::signature::verify_signed_content;
@@ -147,7 +149,7 @@ pub fn handle_options_for_bucket(
 ) -> Result<Response<Body>, Error> {
 	let origin = req
 		.headers()
-		.get("Origin")
+		.get(ORIGIN)
 		.ok_or_bad_request("Missing Origin header")?
 		.to_str()?;
 	let request_method = req
@@ -165,10 +167,13 @@ pub fn handle_options_for_bucket(
 			.iter()
 			.find(|rule| cors_rule_matches(rule, origin, request_method, request_headers.iter()));
 		if let Some(rule) = matching_rule {
-			let mut resp = Response::builder()
-				.status(StatusCode::OK)
-				.body(Body::empty())?;
-			add_cors_headers(&mut resp, rule).ok_or_internal_error("Invalid CORS configuration")?;
+			let mut resp = Response::builder().status(StatusCode::OK);
+			if let Some(max_age) = rule.max_age_seconds {
+				resp = resp.header(ACCESS_CONTROL_MAX_AGE, max_age);
+			}
+			let mut resp = resp.body(Body::empty())?;
+			add_cors_headers(&mut resp, rule, origin)
+				.ok_or_internal_error("Invalid CORS configuration")?;
 			return Ok(resp);
 		}
 	}
@@ -176,20 +181,26 @@ pub fn handle_options_for_bucket(
 	Err(Error::forbidden("This CORS request is not allowed."))
 }
 
+/// Find the CORS rule of a bucket that applies to an actual (non-preflight) request.
+/// Returns the rule along with the origin of the request, which is needed
+/// to build the CORS headers of the response.
 pub fn find_matching_cors_rule<'a>(
 	bucket: &'a Bucket,
 	req: &Request<Body>,
-) -> Result<Option<&'a GarageCorsRule>, Error> {
+) -> Result<Option<(&'a GarageCorsRule, String)>, Error> {
 	if let Some(cors_config) = bucket.params().unwrap().cors_config.get() {
-		if let Some(origin) = req.headers().get("Origin") {
+		if let Some(origin) = req.headers().get(ORIGIN) {
 			let origin = origin.to_str()?;
 			let request_headers = match req.headers().get(ACCESS_CONTROL_REQUEST_HEADERS) {
 				Some(h) => h.to_str()?.split(',').map(|h| h.trim()).collect::<Vec<_>>(),
 				None => vec![],
 			};
-			return Ok(cors_config.iter().find(|rule| {
-				cors_rule_matches(rule, origin, req.method().as_ref(), request_headers.iter())
-			}));
+			return Ok(cors_config
+				.iter()
+				.find(|rule| {
+					cors_rule_matches(rule, origin, req.method().as_ref(), request_headers.iter())
+				})
+				.map(|rule| (rule, origin.to_string())));
 		}
 	}
 	Ok(None)
@@ -205,24 +216,44 @@ where
 	HI: Iterator<Item = S>,
 	S: AsRef<str>,
 {
-	rule.allow_origins.iter().any(|x| x == "*" || x == origin)
+	rule.allow_origins.iter().any(|x| wildcard_match(x, origin))
 		&& rule.allow_methods.iter().any(|x| x == "*" || x == method)
 		&& request_headers.all(|h| {
+			let h = h.as_ref().to_ascii_lowercase();
 			rule.allow_headers
 				.iter()
-				.any(|x| x == "*" || x == h.as_ref())
+				.any(|x| wildcard_match(&x.to_ascii_lowercase(), &h))
 		})
 }
 
+/// Check whether a value matches a pattern of a CORS rule,
+/// which may contain a single `*` wildcard (e.g. `https://*.example.com`)
+fn wildcard_match(pattern: &str, value: &str) -> bool {
+	match pattern.split_once('*') {
+		None => pattern == value,
+		Some((prefix, suffix)) => {
+			value.len() >= prefix.len() + suffix.len()
+				&& value.starts_with(prefix)
+				&& value.ends_with(suffix)
+		}
+	}
+}
+
+/// Add the CORS headers of a matching rule to the response to a request from `origin`
 pub fn add_cors_headers(
 	resp: &mut Response<Body>,
 	rule: &GarageCorsRule,
+	origin: &str,
 ) -> Result<(), http::header::InvalidHeaderValue> {
 	let h = resp.headers_mut();
-	h.insert(
-		ACCESS_CONTROL_ALLOW_ORIGIN,
-		rule.allow_origins.join(", ").parse()?,
-	);
+	// Browsers only accept a single origin here: answer with the origin of the request,
+	// unless the rule allows any origin
+	if rule.allow_origins.iter().any(|x| x == "*") {
+		h.insert(ACCESS_CONTROL_ALLOW_ORIGIN, "*".parse()?);
+	} else {
+		h.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin.parse()?);
+		h.append(VARY, ORIGIN.as_str().parse()?);
+	}
 	h.insert(
 		ACCESS_CONTROL_ALLOW_METHODS,
 		rule.allow_methods.join(", ").parse()?,
@@ -238,6 +269,41 @@ pub fn add_cors_headers(
 	Ok(())
 }
 
+/// Check that a CORS rule is valid. This is used both by the S3 API
+/// and by the admin API, which can also set the CORS rules of buckets.
+pub fn check_cors_rule(rule: &GarageCorsRule) -> Result<(), CommonError> {
+	if rule.allow_origins.is_empty() || rule.allow_methods.is_empty() {
+		return Err(CommonError::BadRequest(
+			"CORS rules must have at least one allowed origin and one allowed method".into(),
+		));
+	}
+	for pattern in rule.allow_origins.iter().chain(rule.allow_headers.iter()) {
+		if pattern.matches('*').count() > 1 {
+			return Err(CommonError::BadRequest(format!(
+				"CORS rule patterns can contain at most one wildcard: {}",
+				pattern
+			)));
+		}
+	}
+	for method in rule.allow_methods.iter() {
+		method
+			.parse::<Method>()
+			.ok_or_bad_request("Invalid CORSRule method")?;
+	}
+	for header in rule.expose_headers.iter() {
+		header
+			.parse::<HeaderName>()
+			.ok_or_bad_request("Invalid HTTP header name")?;
+	}
+	for header in rule.allow_headers.iter() {
+		header
+			.replace('*', "x")
+			.parse::<HeaderName>()
+			.ok_or_bad_request("Invalid HTTP header name")?;
+	}
+	Ok(())
+}
+
 // ---- SERIALIZATION AND DESERIALIZATION TO/FROM S3 XML ----
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
@@ -302,23 +368,7 @@ impl CorsConfiguration {
 
 impl CorsRule {
 	pub fn validate(&self) -> Result<(), Error> {
-		for method in self.allowed_methods.iter() {
-			method
-				.0
-				.parse::<Method>()
-				.ok_or_bad_request("Invalid CORSRule method")?;
-		}
-		for header in self
-			.allowed_headers
-			.iter()
-			.chain(self.expose_headers.iter())
-		{
-			header
-				.0
-				.parse::<HeaderName>()
-				.ok_or_bad_request("Invalid HTTP header name")?;
-		}
-		Ok(())
+		Ok(check_cors_rule(&self.to_garage_cors_rule())?)
 	}
 
 	pub fn to_garage_cors_rule(&self) -> GarageCorsRule {
@@ -427,4 +477,153 @@ mod tests {
 
 		Ok(())
 	}
+
+	fn test_bucket() -> Bucket {
+		let mut bucket = Bucket::new();
+		bucket.params_mut().unwrap().cors_config.update(Some(vec![
+			GarageCorsRule {
+				id: None,
+				max_age_seconds: Some(3600),
+				allow_origins: vec!["https://*.example.com".into()],
+				allow_methods: vec!["GET".into(), "PUT".into()],
+				allow_headers: vec!["x-amz-*".into(), "Content-Type".into()],
+				expose_headers: vec!["ETag".into()],
+			},
+			GarageCorsRule {
+				id: None,
+				max_age_seconds: None,
+				allow_origins: vec!["*".into()],
+				allow_methods: vec!["HEAD".into()],
+				allow_headers: vec![],
+				expose_headers: vec![],
+			},
+		]));
+		bucket
+	}
+
+	fn request(method: &str, origin: Option<&str>, headers: &[(&str, &str)]) -> Request<Body> {
+		let mut req = Request::builder().method(method).uri("/key");
+		if let Some(origin) = origin {
+			req = req.header(ORIGIN, origin);
+		}
+		for (k, v) in headers {
+			req = req.header(*k, *v);
+		}
+		req.body(Body::empty()).unwrap()
+	}
+
+	#[test]
+	fn test_wildcard_match() {
+		assert!(wildcard_match("*", "https://example.com"));
+		assert!(wildcard_match("https://example.com", "https://example.com"));
+		assert!(!wildcard_match(
+			"https://example.com",
+			"https://example.org"
+		));
+		assert!(wildcard_match(
+			"https://*.example.com",
+			"https://a.example.com"
+		));
+		assert!(!wildcard_match(
+			"https://*.example.com",
+			"https://example.com"
+		));
+		assert!(!wildcard_match(
+			"https://*.example.com",
+			"https://a.example.org"
+		));
+		assert!(wildcard_match("x-amz-*", "x-amz-date"));
+	}
+
+	#[test]
+	fn test_preflight() {
+		let bucket = test_bucket();
+
+		let req = request(
+			"OPTIONS",
+			Some("https://app.example.com"),
+			&[
+				("Access-Control-Request-Method", "PUT"),
+				("Access-Control-Request-Headers", "X-Amz-Date, content-type"),
+			],
+		);
+		let resp = handle_options_for_bucket(&req, &bucket).unwrap();
+		assert_eq!(resp.status(), StatusCode::OK);
+		let h = resp.headers();
+		assert_eq!(h[ACCESS_CONTROL_ALLOW_ORIGIN], "https://app.example.com");
+		assert_eq!(h[ACCESS_CONTROL_ALLOW_METHODS], "GET, PUT");
+		assert_eq!(h[ACCESS_CONTROL_MAX_AGE], "3600");
+		assert_eq!(h[VARY], "origin");
+
+		// Method, header or origin not allowed by any rule
+		for (origin, method, headers) in [
+			("https://app.example.com", "DELETE", ""),
+			("https://app.example.com", "PUT", "authorization"),
+			("https://example.org", "PUT", ""),
+		] {
+			let req = request(
+				"OPTIONS",
+				Some(origin),
+				&[
+					("Access-Control-Request-Method", method),
+					("Access-Control-Request-Headers", headers),
+				],
+			);
+			assert!(handle_options_for_bucket(&req, &bucket).is_err());
+		}
+
+		// Rule that allows any origin
+		let req = request(
+			"OPTIONS",
+			Some("https://example.org"),
+			&[("Access-Control-Request-Method", "HEAD")],
+		);
+		let resp = handle_options_for_bucket(&req, &bucket).unwrap();
+		assert_eq!(resp.headers()[ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+		assert!(resp.headers().get(ACCESS_CONTROL_MAX_AGE).is_none());
+	}
+
+	#[test]
+	fn test_simple_request() {
+		let bucket = test_bucket();
+
+		let req = request("GET", Some("https://app.example.com"), &[]);
+		let (rule, origin) = find_matching_cors_rule(&bucket, &req).unwrap().unwrap();
+		let mut resp = Response::new(Body::empty());
+		add_cors_headers(&mut resp, rule, &origin).unwrap();
+		assert_eq!(
+			resp.headers()[ACCESS_CONTROL_ALLOW_ORIGIN],
+			"https://app.example.com"
+		);
+		assert_eq!(resp.headers()[ACCESS_CONTROL_EXPOSE_HEADERS], "ETag");
+
+		// No matching rule, or no Origin header: no CORS headers
+		let req = request("GET", Some("https://example.org"), &[]);
+		assert!(find_matching_cors_rule(&bucket, &req).unwrap().is_none());
+		let req = request("GET", None, &[]);
+		assert!(find_matching_cors_rule(&bucket, &req).unwrap().is_none());
+
+		// No CORS configuration
+		let req = request("GET", Some("https://app.example.com"), &[]);
+		assert!(find_matching_cors_rule(&Bucket::new(), &req)
+			.unwrap()
+			.is_none());
+	}
+
+	#[test]
+	fn test_check_cors_rule() {
+		let rule = |origins: &[&str], methods: &[&str]| GarageCorsRule {
+			id: None,
+			max_age_seconds: None,
+			allow_origins: origins.iter().map(|x| x.to_string()).collect(),
+			allow_methods: methods.iter().map(|x| x.to_string()).collect(),
+			allow_headers: vec!["*".into()],
+			expose_headers: vec![],
+		};
+		assert!(check_cors_rule(&rule(&["https://*.example.com"], &["GET"])).is_ok());
+		assert!(check_cors_rule(&rule(&["https://*.*.example.com"], &["GET"])).is_err());
+		assert!(check_cors_rule(&rule(&[], &["GET"])).is_err());
+		assert!(check_cors_rule(&rule(&["*"], &[])).is_err());
+		assert!(check_cors_rule(&rule(&["*"], &["NOT A METHOD"])).is_err());
+	}
 }
@@ -5,24 +5,79 @@ use hyper::{Body, Request, Response, StatusCode};
 use garage_util::data::*;
 use garage_util::time::*;
 
+use garage_model::bucket_table::Bucket;
 use garage_model::garage::Garage;
 use garage_model::s3::object_table::*;
 
 use crate::s3::error::*;
+use crate::s3::policy::PolicyContext;
 use crate::s3::xml as s3_xml;
 use crate::signature::verify_signed_content;
 
-async fn handle_delete_internal(
+/// Outcome of a successful deletion
+pub struct DeleteOutcome {
+	/// S3 version id of the version that was deleted
+	pub deleted_version_id: String,
+	/// Whether the version that was deleted is a delete marker
+	pub deleted_delete_marker: bool,
+	/// S3 version id of the delete marker that was created, if any
+	pub delete_marker_version_id: Option<String>,
+}
+
+/// Delete an object, or one of its versions if `version_id` is given.
+///
+/// Deleting an object creates a delete marker, which is kept as a new
+/// version of the object if versioning is enabled on the bucket.
+/// Deleting a specific version removes it permanently, which is forbidden
+/// while it is protected by object lock (governance-mode retention can be
+/// bypassed if `bypass_governance` is set).
+pub async fn handle_delete_internal(
 	garage: &Garage,
-	bucket_id: Uuid,
+	bucket: &Bucket,
 	key: &str,
-) -> Result<(Uuid, Uuid), Error> {
+	version_id: Option<&str>,
+	bypass_governance: bool,
+) -> Result<DeleteOutcome, Error> {
 	let object = garage
 		.object_table
-		.get(&bucket_id, &key.to_string())
+		.get(&bucket.id, &key.to_string())
 		.await?
 		.ok_or(Error::NoSuchKey)?; // No need to delete
 
+	if let Some(version_id) = version_id {
+		let version = object
+			.find_version(version_id)
+			.ok_or(Error::NoSuchVersion)?;
+
+		if version.lock.is_locked(now_msec(), bypass_governance) {
+			return Err(Error::forbidden(
+				"The object version is protected by object lock",
+			));
+		}
+
+		let object = Object::new(
+			bucket.id,
+			key.into(),
+			vec![ObjectVersion {
+				uuid: version.uuid,
+				timestamp: version.timestamp,
+				state: ObjectVersionState::Aborted,
+				versioned: version.versioned,
+				lock: ObjectVersionLock::default(),
+				tags: Default::default(),
+				storage_class: Default::default(),
+				restore: Default::default(),
+			}],
+		);
+		garage.object_table.insert(&object).await?;
+
+		return Ok(DeleteOutcome {
+			deleted_version_id: version_id.to_string(),
+			deleted_delete_marker: version.is_delete_marker(),
+			delete_marker_version_id: None,
+		});
+	}
+
 	let interesting_versions = object.versions().iter().filter(|v| {
 		!matches!(
 			v.state,
@@ -35,49 +90,85 @@ async fn handle_delete_internal(
 	let mut timestamp = now_msec();
 	for v in interesting_versions {
 		if v.timestamp + 1 > timestamp || version_to_delete.is_none() {
-			version_to_delete = Some(v.uuid);
+			version_to_delete = Some(v.version_id());
 		}
 		timestamp = std::cmp::max(timestamp, v.timestamp + 1);
 	}
 
-	let deleted_version = version_to_delete.ok_or(Error::NoSuchKey)?;
+	// When versioning is enabled, a delete marker is added even if the
+	// current version already is one, so that it can be listed
+	let versioned = bucket.versioning_enabled();
+	let deleted_version_id = match version_to_delete {
+		Some(v) => v,
+		None if versioned => {
+			let current = object.current_version().ok_or(Error::NoSuchKey)?;
+			timestamp = std::cmp::max(timestamp, current.timestamp + 1);
+			current.version_id()
+		}
+		None => return Err(Error::NoSuchKey),
+	};
 
 	let version_uuid = gen_uuid();
 
 	let object = Object::new(
-		bucket_id,
+		bucket.id,
 		key.into(),
 		vec![ObjectVersion {
 			uuid: version_uuid,
 			timestamp,
 			state: ObjectVersionState::Complete(ObjectVersionData::DeleteMarker),
+			versioned,
+			lock: ObjectVersionLock::default(),
+			tags: Default::default(),
+			storage_class: Default::default(),
+			restore: Default::default(),
 		}],
 	);
+	let delete_marker_version_id = object.versions()[0].version_id();
 
 	garage.object_table.insert(&object).await?;
 
-	Ok((deleted_version, version_uuid))
+	Ok(DeleteOutcome {
+		deleted_version_id,
+		deleted_delete_marker: false,
+		delete_marker_version_id: Some(delete_marker_version_id),
+	})
 }
 
 pub async fn handle_delete(
 	garage: Arc<Garage>,
-	bucket_id: Uuid,
+	bucket: &Bucket,
 	key: &str,
+	version_id: Option<&str>,
+	bypass_governance: bool,
 ) -> Result<Response<Body>, Error> {
-	match handle_delete_internal(&garage, bucket_id, key).await {
-		Ok(_) | Err(Error::NoSuchKey) => Ok(Response::builder()
-			.status(StatusCode::NO_CONTENT)
-			.body(Body::from(vec![]))
-			.unwrap()),
-		Err(e) => Err(e),
+	let mut resp = Response::builder().status(StatusCode::NO_CONTENT);
+	match handle_delete_internal(&garage, bucket, key, version_id, bypass_governance).await {
+		Ok(outcome) => {
+			if let Some(dm_version_id) = outcome.delete_marker_version_id {
+				resp = resp
+					.header("x-amz-delete-marker", "true")
+					.header("x-amz-version-id", dm_version_id);
+			} else {
+				resp = resp.header("x-amz-version-id", outcome.deleted_version_id);
+				if outcome.deleted_delete_marker {
+					resp = resp.header("x-amz-delete-marker", "true");
+				}
+			}
+		}
+		Err(Error::NoSuchKey) | Err(Error::NoSuchVersion) => (),
+		Err(e) => return Err(e),
 	}
+	Ok(resp.body(Body::from(vec![]))?)
 }
 
 pub async fn handle_delete_objects(
 	garage: Arc<Garage>,
-	bucket_id: Uuid,
+	bucket: &Bucket,
+	policy: &PolicyContext,
 	req: Request<Body>,
 	content_sha256: Option<Hash>,
+	bypass_governance: bool,
 ) -> Result<Response<Body>, Error> {
 	let body = hyper::body::to_bytes(req.into_body()).await?;
 
@@ -92,23 +183,52 @@ pub async fn handle_delete_objects(
 	let mut ret_errors = Vec::new();
 
 	for obj in cmd.objects.iter() {
-		match handle_delete_internal(&garage, bucket_id, &obj.key).await {
-			Ok((deleted_version, delete_marker_version)) => {
+		// The bucket policy can deny the deletion of some of the objects
+		let res = if policy.is_allowed("DeleteObject", Some(&obj.key), true) {
+			handle_delete_internal(
+				&garage,
+				bucket,
+				&obj.key,
+				obj.version_id.as_deref(),
+				bypass_governance,
+			)
+			.await
+		} else {
+			Err(Error::forbidden(
+				"Deleting this object is denied by the bucket policy",
+			))
+		};
+		match res {
+			Ok(outcome) => {
 				if cmd.quiet {
 					continue;
 				}
 				ret_deleted.push(s3_xml::Deleted {
 					key: s3_xml::Value(obj.key.clone()),
-					version_id: s3_xml::Value(hex::encode(deleted_version)),
-					delete_marker_version_id: s3_xml::Value(hex::encode(delete_marker_version)),
+					version_id: s3_xml::Value(outcome.deleted_version_id),
+					delete_marker: outcome
+						.delete_marker_version_id
+						.is_some()
+						.then_some(s3_xml::Value("true".into())),
+					delete_marker_version_id: outcome.delete_marker_version_id.map(s3_xml::Value),
+				});
+			}
+			Err(Error::NoSuchVersion) if !cmd.quiet => {
+				// As on AWS, deleting a version that does not exist succeeds
+				ret_deleted.push(s3_xml::Deleted {
+					key: s3_xml::Value(obj.key.clone()),
+					version_id: s3_xml::Value(obj.version_id.clone().unwrap_or_default()),
+					delete_marker: None,
+					delete_marker_version_id: None,
 				});
 			}
+			Err(Error::NoSuchVersion) => (),
 			Err(e) => {
 				ret_errors.push(s3_xml::DeleteError {
 					code: s3_xml::Value(e.aws_code().to_string()),
 					key: Some(s3_xml::Value(obj.key.clone())),
 					message: s3_xml::Value(format!("{}", e)),
-					version_id: None,
+					version_id: obj.version_id.clone().map(s3_xml::Value),
 				});
 			}
 		}
@@ -132,6 +252,7 @@ struct DeleteRequest {
 
 struct DeleteObject {
 	key: String,
+	version_id: Option<String>,
 }
 
 fn parse_delete_objects_xml(xml: &roxmltree::Document) -> Option<DeleteRequest> {
@@ -151,8 +272,13 @@ fn parse_delete_objects_xml(xml: &roxmltree::Document) -> Option<DeleteRequest>
 		if item.has_tag_name("Object") {
 			let key = item.children().find(|e| e.has_tag_name("Key"))?;
 			let key_str = key.text()?;
+			let version_id = match item.children().find(|e| e.has_tag_name("VersionId")) {
+				Some(v) => Some(v.text()?.to_string()),
+				None => None,
+			};
 			ret.objects.push(DeleteObject {
 				key: key_str.to_string(),
+				version_id,
 			});
 		} else if item.has_tag_name("Quiet") {
 			if item.text()? == "true" {
@@ -0,0 +1,512 @@
+use quick_xml::de::from_reader;
+use std::convert::TryInto;
+use std::sync::Arc;
+
+use base64::prelude::*;
+use bytes::Bytes;
+use hyper::header::{HeaderMap, HeaderValue};
+use hyper::{Body, Request, Response, StatusCode};
+use md5::{Digest as Md5Digest, Md5};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+
+use crate::s3::error::*;
+use crate::s3::xml::{to_xml_with_header, xmlns_tag, Value};
+use crate::signature::verify_signed_content;
+
+use garage_model::bucket_table::*;
+use garage_model::garage::Garage;
+use garage_model::s3::object_table::ObjectVersionHeaders;
+use garage_util::data::*;
+
+pub(crate) const SSE_HEADER: &str = "x-amz-server-side-encryption";
+const SSE_ALGORITHMS: &[&str] = &["AES256", "aws:kms", "aws:kms:dsse"];
+
+pub(crate) const SSEC_ALGORITHM_HEADER: &str = "x-amz-server-side-encryption-customer-algorithm";
+pub(crate) const SSEC_KEY_HEADER: &str = "x-amz-server-side-encryption-customer-key";
+pub(crate) const SSEC_KEY_MD5_HEADER: &str = "x-amz-server-side-encryption-customer-key-md5";
+const COPY_SOURCE_SSEC_ALGORITHM_HEADER: &str =
+	"x-amz-copy-source-server-side-encryption-customer-algorithm";
+const COPY_SOURCE_SSEC_KEY_HEADER: &str = "x-amz-copy-source-server-side-encryption-customer-key";
+const COPY_SOURCE_SSEC_KEY_MD5_HEADER: &str =
+	"x-amz-copy-source-server-side-encryption-customer-key-md5";
+/// The only algorithm that can be used with SSE-C
+const SSEC_ALGORITHM: &str = "AES256";
+
+const SSEC_KEY_LEN: usize = 32;
+const TAG_LEN: usize = 16;
+/// Number of bytes added to each block of an SSE-C object by its encryption
+pub const SSEC_BLOCK_OVERHEAD: usize = NONCE_LEN + TAG_LEN;
+
+/// Returns true if an object with these headers is encrypted with a key
+/// provided by the client (SSE-C)
+pub fn is_ssec_encrypted(headers: &ObjectVersionHeaders) -> bool {
+	headers.other.contains_key(SSEC_KEY_MD5_HEADER)
+}
+
+pub async fn handle_get_encryption(bucket: &Bucket) -> Result<Response<Body>, Error> {
+	let param = bucket
+		.params()
+		.ok_or_internal_error("Bucket should not be deleted at this point")?;
+
+	if let Some(encryption) = param.encryption_config.get() {
+		let conf = ServerSideEncryptionConfiguration {
+			xmlns: (),
+			rules: vec![ServerSideEncryptionRule::from_garage_encryption_config(
+				encryption,
+			)],
+		};
+		let xml = to_xml_with_header(&conf)?;
+		Ok(Response::builder()
+			.status(StatusCode::OK)
+			.header(http::header::CONTENT_TYPE, "application/xml")
+			.body(Body::from(xml))?)
+	} else {
+		Err(Error::NoSuchEncryptionConfiguration)
+	}
+}
+
+pub async fn handle_delete_encryption(
+	garage: Arc<Garage>,
+	bucket_id: Uuid,
+) -> Result<Response<Body>, Error> {
+	let mut bucket = garage
+		.bucket_helper()
+		.get_existing_bucket(bucket_id)
+		.await?;
+
+	let param = bucket.params_mut().unwrap();
+
+	param.encryption_config.update(None);
+	garage.bucket_table.insert(&bucket).await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::NO_CONTENT)
+		.body(Body::empty())?)
+}
+
+pub async fn handle_put_encryption(
+	garage: Arc<Garage>,
+	bucket_id: Uuid,
+	req: Request<Body>,
+	content_sha256: Option<Hash>,
+) -> Result<Response<Body>, Error> {
+	let body = hyper::body::to_bytes(req.into_body()).await?;
+
+	if let Some(content_sha256) = content_sha256 {
+		verify_signed_content(content_sha256, &body[..])?;
+	}
+
+	let mut bucket = garage
+		.bucket_helper()
+		.get_existing_bucket(bucket_id)
+		.await?;
+
+	let param = bucket.params_mut().unwrap();
+
+	let conf: ServerSideEncryptionConfiguration = from_reader(&body as &[u8])?;
+	conf.validate()?;
+
+	param
+		.encryption_config
+		.update(Some(conf.into_garage_encryption_config()));
+	garage.bucket_table.insert(&bucket).await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.body(Body::empty())?)
+}
+
+fn encryption_required(bucket: &Bucket) -> Result<bool, Error> {
+	let param = bucket
+		.params()
+		.ok_or_internal_error("Bucket should not be deleted at this point")?;
+	Ok(*param.encryption_required.get())
+}
+
+/// Reject uploads that do not request server-side encryption
+/// if the bucket is configured to require it
+pub fn check_encryption_required(
+	bucket: &Bucket,
+	headers: &HeaderMap<HeaderValue>,
+) -> Result<(), Error> {
+	if !encryption_required(bucket)? {
+		return Ok(());
+	}
+
+	// Objects encrypted with a key provided by the client are never stored in plaintext
+	if headers.contains_key(SSEC_ALGORITHM_HEADER) {
+		return Ok(());
+	}
+
+	let algorithm = headers
+		.get(SSE_HEADER)
+		.ok_or(Error::EncryptionRequired)?
+		.to_str()?;
+	if !SSE_ALGORITHMS.contains(&algorithm) {
+		return Err(Error::bad_request(format!(
+			"Invalid value for {}: {}",
+			SSE_HEADER, algorithm
+		)));
+	}
+	Ok(())
+}
+
+/// Reject the parts of a multipart upload that was created without requesting
+/// server-side encryption if the bucket requires it, e.g. because it was
+/// configured to after the upload was created
+pub fn check_upload_encryption_required(
+	bucket: &Bucket,
+	upload_headers: &ObjectVersionHeaders,
+) -> Result<(), Error> {
+	if !encryption_required(bucket)?
+		|| upload_headers.other.contains_key(SSE_HEADER)
+		|| has_customer_key(upload_headers)
+	{
+		return Ok(());
+	}
+	Err(Error::EncryptionRequired)
+}
+
+/// Get the server-side encryption requested for a new object. Only SSE-S3 (AES256)
+/// is supported: block files are encrypted at rest by the storage nodes
+/// that have an `encryption_key`.
+pub(crate) fn get_sse_algorithm(headers: &HeaderMap<HeaderValue>) -> Result<Option<String>, Error> {
+	let algorithm = match headers.get(SSE_HEADER) {
+		Some(v) => v.to_str()?,
+		None => return Ok(None),
+	};
+	match algorithm {
+		"AES256" => Ok(Some(algorithm.to_string())),
+		"aws:kms" | "aws:kms:dsse" => Err(Error::NotImplemented(format!(
+			"Server-side encryption with {} is not supported, use AES256",
+			algorithm
+		))),
+		_ => Err(Error::bad_request(format!(
+			"Invalid value for {}: {}",
+			SSE_HEADER, algorithm
+		))),
+	}
+}
+
+/// Returns true if an object with these headers is encrypted
+/// with a key provided by the client (SSE-C)
+pub(crate) fn has_customer_key(headers: &ObjectVersionHeaders) -> bool {
+	headers.other.contains_key(SSEC_KEY_MD5_HEADER)
+}
+
+/// Encryption key provided by the client for server-side encryption (SSE-C).
+///
+/// The data of SSE-C objects is encrypted by the API nodes before it is sent
+/// to the storage nodes: the data blocks and the inline data of the object are
+/// encrypted separately with AES-256-GCM, each with a random nonce, and stored as
+/// `nonce (12) | encrypted data | tag (16)`. The key itself is never stored,
+/// only its MD5 which is used to check the key given to read the object.
+#[derive(Clone, PartialEq, Eq)]
+pub struct CustomerKey {
+	key: [u8; SSEC_KEY_LEN],
+	key_md5: String,
+}
+
+impl CustomerKey {
+	/// Get the key given in the `x-amz-server-side-encryption-customer-*` headers
+	/// of a request, if any
+	pub fn from_request_headers(headers: &HeaderMap<HeaderValue>) -> Result<Option<Self>, Error> {
+		Self::parse(
+			headers,
+			SSEC_ALGORITHM_HEADER,
+			SSEC_KEY_HEADER,
+			SSEC_KEY_MD5_HEADER,
+		)
+	}
+
+	/// Get the key of the source object of a copy, given in the
+	/// `x-amz-copy-source-server-side-encryption-customer-*` headers, if any
+	pub fn from_copy_source_headers(
+		headers: &HeaderMap<HeaderValue>,
+	) -> Result<Option<Self>, Error> {
+		Self::parse(
+			headers,
+			COPY_SOURCE_SSEC_ALGORITHM_HEADER,
+			COPY_SOURCE_SSEC_KEY_HEADER,
+			COPY_SOURCE_SSEC_KEY_MD5_HEADER,
+		)
+	}
+
+	fn parse(
+		headers: &HeaderMap<HeaderValue>,
+		algorithm_header: &str,
+		key_header: &str,
+		key_md5_header: &str,
+	) -> Result<Option<Self>, Error> {
+		let (algorithm, key, key_md5) = match (
+			headers.get(algorithm_header),
+			headers.get(key_header),
+			headers.get(key_md5_header),
+		) {
+			(None, None, None) => return Ok(None),
+			(Some(a), Some(k), Some(m)) => (a.to_str()?, k.to_str()?, m.to_str()?),
+			_ => {
+				return Err(Error::bad_request(format!(
+					"The {}, {} and {} headers must all be given",
+					algorithm_header, key_header, key_md5_header
+				)))
+			}
+		};
+
+		if algorithm != SSEC_ALGORITHM {
+			return Err(Error::bad_request(format!(
+				"Invalid value for {}: {}, the only supported algorithm is {}",
+				algorithm_header, algorithm, SSEC_ALGORITHM
+			)));
+		}
+		let key: [u8; SSEC_KEY_LEN] = BASE64_STANDARD
+			.decode(key)
+			.ok()
+			.and_then(|k| k.try_into().ok())
+			.ok_or_bad_request(format!(
+				"Invalid {}: a base64-encoded 256-bit key must be given",
+				key_header
+			))?;
+		let actual_md5 = BASE64_STANDARD.encode(Md5::digest(key));
+		if key_md5 != actual_md5 {
+			return Err(Error::bad_request(format!(
+				"The {} header does not match the MD5 of the key",
+				key_md5_header
+			)));
+		}
+
+		Ok(Some(Self {
+			key,
+			key_md5: actual_md5,
+		}))
+	}
+
+	/// Check that this key can be used to read an object with the given headers,
+	/// or to write a new part of a multipart upload
+	pub fn check(key: Option<Self>, headers: &ObjectVersionHeaders) -> Result<Option<Self>, Error> {
+		match (headers.other.get(SSEC_KEY_MD5_HEADER), key) {
+			(None, None) => Ok(None),
+			(Some(_), None) => Err(Error::bad_request(
+				"The object is encrypted with a key provided by the client (SSE-C), the key must be given",
+			)),
+			(None, Some(_)) => Err(Error::bad_request(
+				"The object is not encrypted with a key provided by the client (SSE-C)",
+			)),
+			(Some(key_md5), Some(key)) if *key_md5 == key.key_md5 => Ok(Some(key)),
+			(Some(_), Some(_)) => Err(Error::forbidden(
+				"The given key is not the key with which the object is encrypted",
+			)),
+		}
+	}
+
+	/// Store the algorithm and the MD5 of the key in the headers of a new object,
+	/// so that they are returned on reads
+	pub fn add_to_headers(&self, headers: &mut ObjectVersionHeaders) {
+		headers.other.insert(
+			SSEC_ALGORITHM_HEADER.to_string(),
+			SSEC_ALGORITHM.to_string(),
+		);
+		headers
+			.other
+			.insert(SSEC_KEY_MD5_HEADER.to_string(), self.key_md5.clone());
+	}
+
+	/// Add the headers that confirm the encryption of an object to a response
+	pub fn add_response_headers(&self, headers: &mut HeaderMap<HeaderValue>) {
+		headers.insert(
+			SSEC_ALGORITHM_HEADER,
+			HeaderValue::from_static(SSEC_ALGORITHM),
+		);
+		headers.insert(
+			SSEC_KEY_MD5_HEADER,
+			HeaderValue::from_str(&self.key_md5).unwrap(),
+		);
+	}
+
+	fn aead_key(&self) -> LessSafeKey {
+		LessSafeKey::new(UnboundKey::new(&AES_256_GCM, &self.key).unwrap())
+	}
+
+	/// Encrypt a data block or the inline data of an object
+	pub fn encrypt(&self, data: &[u8]) -> Result<Bytes, Error> {
+		let mut nonce = [0u8; NONCE_LEN];
+		SystemRandom::new()
+			.fill(&mut nonce)
+			.ok_or_internal_error("Cannot generate a random nonce")?;
+
+		let mut ret = Vec::with_capacity(NONCE_LEN + data.len() + TAG_LEN);
+		ret.extend_from_slice(&nonce);
+		ret.extend_from_slice(data);
+		let tag = self
+			.aead_key()
+			.seal_in_place_separate_tag(
+				Nonce::assume_unique_for_key(nonce),
+				Aad::empty(),
+				&mut ret[NONCE_LEN..],
+			)
+			.ok_or_internal_error("Encryption failed")?;
+		ret.extend_from_slice(tag.as_ref());
+		Ok(Bytes::from(ret))
+	}
+
+	/// Decrypt data that was encrypted with `encrypt`
+	pub fn decrypt(&self, data: &[u8]) -> Result<Bytes, Error> {
+		if data.len() < NONCE_LEN + TAG_LEN {
+			return Err(Error::internal_error("Encrypted data is too short"));
+		}
+		let (nonce, encrypted) = data.split_at(NONCE_LEN);
+		let nonce = Nonce::try_assume_unique_for_key(nonce).unwrap();
+		let mut buf = encrypted.to_vec();
+		let len = self
+			.aead_key()
+			.open_in_place(nonce, Aad::empty(), &mut buf)
+			.ok_or_internal_error("Decryption failed, the data may be corrupted")?
+			.len();
+		buf.truncate(len);
+		Ok(Bytes::from(buf))
+	}
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ServerSideEncryptionConfiguration {
+	#[serde(serialize_with = "xmlns_tag", skip_deserializing)]
+	pub xmlns: (),
+	#[serde(rename = "Rule")]
+	pub rules: Vec<ServerSideEncryptionRule>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ServerSideEncryptionRule {
+	#[serde(rename = "ApplyServerSideEncryptionByDefault")]
+	pub default: ServerSideEncryptionByDefault,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ServerSideEncryptionByDefault {
+	#[serde(rename = "SSEAlgorithm")]
+	pub sse_algorithm: Value,
+	#[serde(rename = "KMSMasterKeyID")]
+	pub kms_master_key_id: Option<Value>,
+}
+
+impl ServerSideEncryptionConfiguration {
+	pub fn validate(&self) -> Result<(), Error> {
+		if self.rules.len() != 1 {
+			return Err(Error::bad_request(
+				"Bad XML: exactly one encryption rule must be given",
+			));
+		}
+		let default = &self.rules[0].default;
+		if !SSE_ALGORITHMS.contains(&default.sse_algorithm.0.as_str()) {
+			return Err(Error::bad_request(format!(
+				"Bad XML: invalid SSEAlgorithm: {}",
+				default.sse_algorithm.0
+			)));
+		}
+		if default.kms_master_key_id.is_some() && default.sse_algorithm.0 == "AES256" {
+			return Err(Error::bad_request(
+				"Bad XML: KMSMasterKeyID can only be given with the aws:kms algorithm",
+			));
+		}
+		Ok(())
+	}
+
+	pub fn into_garage_encryption_config(self) -> EncryptionConfig {
+		let default = self.rules.into_iter().next().unwrap().default;
+		EncryptionConfig {
+			sse_algorithm: default.sse_algorithm.0,
+			kms_master_key_id: default.kms_master_key_id.map(|x| x.0),
+		}
+	}
+}
+
+impl ServerSideEncryptionRule {
+	pub fn from_garage_encryption_config(config: &EncryptionConfig) -> Self {
+		Self {
+			default: ServerSideEncryptionByDefault {
+				sse_algorithm: Value(config.sse_algorithm.clone()),
+				kms_master_key_id: config.kms_master_key_id.clone().map(Value),
+			},
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use quick_xml::de::from_str;
+
+	#[test]
+	fn test_deserialize() -> Result<(), Error> {
+		let message = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ServerSideEncryptionConfiguration xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+   <Rule>
+      <ApplyServerSideEncryptionByDefault>
+         <SSEAlgorithm>aws:kms</SSEAlgorithm>
+         <KMSMasterKeyID>my-key</KMSMasterKeyID>
+      </ApplyServerSideEncryptionByDefault>
+   </Rule>
+</ServerSideEncryptionConfiguration>"#;
+		let conf: ServerSideEncryptionConfiguration = from_str(message).unwrap();
+		let ref_value = ServerSideEncryptionConfiguration {
+			xmlns: (),
+			rules: vec![ServerSideEncryptionRule {
+				default: ServerSideEncryptionByDefault {
+					sse_algorithm: Value("aws:kms".to_owned()),
+					kms_master_key_id: Some(Value("my-key".to_owned())),
+				},
+			}],
+		};
+		assert_eq! {
+			ref_value,
+			conf
+		}
+		conf.validate()?;
+
+		let message2 = to_xml_with_header(&ref_value)?;
+
+		let cleanup = |c: &str| c.replace(char::is_whitespace, "");
+		assert_eq!(cleanup(message), cleanup(&message2));
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_customer_key() -> Result<(), Error> {
+		let mut headers = HeaderMap::new();
+		headers.insert(SSEC_ALGORITHM_HEADER, HeaderValue::from_static("AES256"));
+		headers.insert(
+			SSEC_KEY_HEADER,
+			HeaderValue::from_static("BwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwc="),
+		);
+		headers.insert(
+			SSEC_KEY_MD5_HEADER,
+			HeaderValue::from_static("y4HAEFCYWuvAXWFTtA1Qpg=="),
+		);
+		let key = CustomerKey::from_request_headers(&headers)?.unwrap();
+		assert!(CustomerKey::from_copy_source_headers(&headers)?.is_none());
+
+		let data = b"hello world";
+		let encrypted = key.encrypt(data)?;
+		assert_eq!(encrypted.len(), NONCE_LEN + data.len() + TAG_LEN);
+		assert_eq!(&key.decrypt(&encrypted)?[..], data);
+
+		let mut corrupted = encrypted.to_vec();
+		corrupted[NONCE_LEN] ^= 1;
+		assert!(key.decrypt(&corrupted).is_err());
+
+		// The MD5 must be that of the key
+		headers.insert(
+			SSEC_KEY_MD5_HEADER,
+			HeaderValue::from_static("ry9sFyn8WYF9Mz2zWfMQhQ=="),
+		);
+		assert!(CustomerKey::from_request_headers(&headers).is_err());
+		headers.remove(SSEC_KEY_MD5_HEADER);
+		assert!(CustomerKey::from_request_headers(&headers).is_err());
+
+		Ok(())
+	}
+}
@@ -9,6 +9,7 @@ use garage_model::helper::error::Error as HelperError;
 use crate::common_error::CommonError;
 pub use crate::common_error::{CommonErrorDerivative, OkOrBadRequest, OkOrInternalError};
 use crate::generic_server::ApiError;
+use crate::s3::select_sql::SqlError;
 use crate::s3::xml as s3_xml;
 use crate::signature::error::Error as SignatureError;
 
@@ -49,6 +50,83 @@ pub enum Error {
 	#[error(display = "Proposed upload is smaller than the minimum allowed object size")]
 	EntityTooSmall,
 
+	/// In PutObject, PostObject and CopyObject: the object is larger than the maximum
+	/// object size of the bucket
+	#[error(display = "Proposed upload exceeds the maximum allowed object size")]
+	EntityTooLarge,
+
+	/// The MD5 of the request body does not match its Content-MD5 header
+	#[error(display = "The Content-MD5 you specified did not match what was received")]
+	BadDigest,
+
+	/// The checksum of the request body does not match its x-amz-checksum-* header
+	#[error(display = "{}", _0)]
+	InvalidChecksum(String),
+
+	/// The user-defined metadata of the object (x-amz-meta-* headers) is too large
+	#[error(display = "Your metadata headers exceed the maximum allowed metadata size")]
+	MetadataTooLarge,
+
+	/// The tags given for an object are not valid
+	#[error(display = "Invalid tag: {}", _0)]
+	InvalidTag(String),
+
+	/// The write would exceed the object count or size quota of the bucket
+	#[error(display = "Quota exceeded: {}", _0)]
+	QuotaExceeded(String),
+
+	/// The bucket requires server-side encryption, but the upload did not request it
+	#[error(display = "Uploads to this bucket must request server-side encryption")]
+	EncryptionRequired,
+
+	/// The bucket has no server-side encryption configuration
+	#[error(display = "The server side encryption configuration was not found")]
+	NoSuchEncryptionConfiguration,
+
+	/// No version of the object has the given version id
+	#[error(display = "The specified version does not exist")]
+	NoSuchVersion,
+
+	/// The current version of the object is the delete marker with the given version id
+	#[error(display = "Key not found")]
+	DeletedKey(String),
+
+	/// The version requested is the delete marker with the given version id
+	#[error(display = "The specified method is not allowed against a delete marker")]
+	DeleteMarkerVersion(String),
+
+	/// The bucket has no lifecycle configuration
+	#[error(display = "The lifecycle configuration does not exist")]
+	NoSuchLifecycleConfiguration,
+
+	/// The bucket has no object lock configuration
+	#[error(display = "Object Lock configuration does not exist for this bucket")]
+	ObjectLockConfigurationNotFound,
+
+	/// The object version has no retention
+	#[error(display = "The specified object does not have a ObjectLock configuration")]
+	NoSuchObjectLockConfiguration,
+
+	/// The operation is not allowed in the current state of the bucket
+	#[error(display = "Invalid bucket state: {}", _0)]
+	InvalidBucketState(String),
+
+	/// The object is archived and must be restored before this operation
+	#[error(display = "Invalid object state: {}", _0)]
+	InvalidObjectState(String),
+
+	/// A restore of the object is already in progress
+	#[error(display = "Object restore is already in progress")]
+	RestoreAlreadyInProgress,
+
+	/// The bucket has no bucket policy
+	#[error(display = "The bucket policy does not exist")]
+	NoSuchBucketPolicy,
+
+	/// The bucket policy given to PutBucketPolicy is not valid
+	#[error(display = "Invalid bucket policy: {}", _0)]
+	MalformedPolicy(String),
+
 	// Category: bad request
 	/// The request contained an invalid UTF-8 sequence in its path or in other parameters
 	#[error(display = "Invalid UTF-8: {}", _0)]
@@ -62,6 +140,10 @@ pub enum Error {
 	#[error(display = "Invalid XML: {}", _0)]
 	InvalidXml(String),
 
+	/// The client asked for a storage class that is not supported
+	#[error(display = "Invalid storage class: {}", _0)]
+	InvalidStorageClass(String),
+
 	/// The client sent a header with invalid value
 	#[error(display = "Invalid header value: {}", _0)]
 	InvalidHeader(#[error(source)] hyper::header::ToStrError),
@@ -70,6 +152,11 @@ pub enum Error {
 	#[error(display = "Invalid HTTP range: {:?}", _0)]
 	InvalidRange(#[error(from)] (http_range::HttpRangeParseError, u64)),
 
+	/// The SQL expression of a SelectObjectContent request is invalid
+	/// or could not be evaluated, with the error code of S3 Select
+	#[error(display = "Invalid query: {}", _1)]
+	InvalidQuery(&'static str, String),
+
 	/// The client sent a request for an action not supported by garage
 	#[error(display = "Unimplemented action: {}", _0)]
 	NotImplemented(String),
@@ -110,6 +197,12 @@ impl From<quick_xml::de::DeError> for Error {
 	}
 }
 
+impl From<SqlError> for Error {
+	fn from(err: SqlError) -> Self {
+		Self::InvalidQuery(err.code, err.message)
+	}
+}
+
 impl From<SignatureError> for Error {
 	fn from(err: SignatureError) -> Self {
 		match err {
@@ -139,9 +232,31 @@ impl Error {
 			Error::InvalidPart => "InvalidPart",
 			Error::InvalidPartOrder => "InvalidPartOrder",
 			Error::EntityTooSmall => "EntityTooSmall",
+			Error::EntityTooLarge => "EntityTooLarge",
+			Error::MetadataTooLarge => "MetadataTooLarge",
+			Error::BadDigest => "BadDigest",
+			Error::InvalidChecksum(_) => "InvalidChecksum",
+			Error::InvalidTag(_) => "InvalidTag",
+			Error::QuotaExceeded(_) => "QuotaExceeded",
+			Error::EncryptionRequired | Error::NoSuchEncryptionConfiguration => {
+				"ServerSideEncryptionConfigurationNotFoundError"
+			}
 			Error::AuthorizationHeaderMalformed(_) => "AuthorizationHeaderMalformed",
+			Error::NoSuchLifecycleConfiguration => "NoSuchLifecycleConfiguration",
+			Error::NoSuchVersion => "NoSuchVersion",
+			Error::DeletedKey(_) => "NoSuchKey",
+			Error::DeleteMarkerVersion(_) => "MethodNotAllowed",
+			Error::ObjectLockConfigurationNotFound => "ObjectLockConfigurationNotFoundError",
+			Error::NoSuchObjectLockConfiguration => "NoSuchObjectLockConfiguration",
+			Error::InvalidBucketState(_) => "InvalidBucketState",
+			Error::InvalidObjectState(_) => "InvalidObjectState",
+			Error::RestoreAlreadyInProgress => "RestoreAlreadyInProgress",
+			Error::NoSuchBucketPolicy => "NoSuchBucketPolicy",
+			Error::MalformedPolicy(_) => "MalformedPolicy",
+			Error::InvalidStorageClass(_) => "InvalidStorageClass",
 			Error::NotImplemented(_) => "NotImplemented",
 			Error::InvalidXml(_) => "MalformedXML",
+			Error::InvalidQuery(code, _) => code,
 			Error::InvalidRange(_) => "InvalidRange",
 			Error::InvalidUtf8Str(_) | Error::InvalidUtf8String(_) | Error::InvalidHeader(_) => {
 				"InvalidRequest"
@@ -155,15 +270,36 @@ impl ApiError for Error {
 	fn http_status_code(&self) -> StatusCode {
 		match self {
 			Error::Common(c) => c.http_status_code(),
-			Error::NoSuchKey | Error::NoSuchUpload => StatusCode::NOT_FOUND,
+			Error::NoSuchKey
+			| Error::NoSuchUpload
+			| Error::NoSuchEncryptionConfiguration
+			| Error::NoSuchVersion
+			| Error::DeletedKey(_)
+			| Error::NoSuchLifecycleConfiguration
+			| Error::ObjectLockConfigurationNotFound
+			| Error::NoSuchObjectLockConfiguration
+			| Error::NoSuchBucketPolicy => StatusCode::NOT_FOUND,
+			Error::DeleteMarkerVersion(_) => StatusCode::METHOD_NOT_ALLOWED,
+			Error::InvalidBucketState(_) | Error::RestoreAlreadyInProgress => StatusCode::CONFLICT,
+			Error::InvalidObjectState(_) => StatusCode::FORBIDDEN,
 			Error::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
 			Error::InvalidRange(_) => StatusCode::RANGE_NOT_SATISFIABLE,
 			Error::NotImplemented(_) => StatusCode::NOT_IMPLEMENTED,
+			Error::QuotaExceeded(_) => StatusCode::FORBIDDEN,
 			Error::AuthorizationHeaderMalformed(_)
 			| Error::InvalidPart
 			| Error::InvalidPartOrder
 			| Error::EntityTooSmall
+			| Error::EntityTooLarge
+			| Error::MetadataTooLarge
+			| Error::BadDigest
+			| Error::InvalidChecksum(_)
+			| Error::InvalidTag(_)
+			| Error::MalformedPolicy(_)
+			| Error::EncryptionRequired
 			| Error::InvalidXml(_)
+			| Error::InvalidQuery(..)
+			| Error::InvalidStorageClass(_)
 			| Error::InvalidUtf8Str(_)
 			| Error::InvalidUtf8String(_)
 			| Error::InvalidHeader(_) => StatusCode::BAD_REQUEST,
@@ -175,7 +311,6 @@ impl ApiError for Error {
 
 		header_map.append(header::CONTENT_TYPE, "application/xml".parse().unwrap());
 
-		#[allow(clippy::single_match)]
 		match self {
 			Error::InvalidRange((_, len)) => {
 				header_map.append(
@@ -185,6 +320,16 @@ impl ApiError for Error {
 						.expect("header value only contain ascii"),
 				);
 			}
+			Error::DeletedKey(version_id) | Error::DeleteMarkerVersion(version_id) => {
+				header_map.append("x-amz-delete-marker", HeaderValue::from_static("true"));
+				header_map.append(
+					"x-amz-version-id",
+					version_id
+						.as_str()
+						.try_into()
+						.expect("version id only contains ascii"),
+				);
+			}
 			_ => (),
 		}
 	}
@@ -2,13 +2,15 @@
 use std::sync::Arc;
 use std::time::{Duration, UNIX_EPOCH};
 
+use bytes::Bytes;
 use futures::future;
-use futures::stream::{self, StreamExt};
+use futures::stream::{self, BoxStream, Stream, StreamExt};
 use http::header::{
-	ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE,
-	IF_NONE_MATCH, LAST_MODIFIED, RANGE,
+	HeaderValue, ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG,
+	IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, RANGE,
 };
 use hyper::{Body, Request, Response, StatusCode};
+use opentelemetry::global;
 use tokio::sync::mpsc;
 
 use garage_rpc::rpc_helper::{netapp::stream::ByteStream, OrderTag};
@@ -20,9 +22,32 @@ use garage_model::garage::Garage;
 use garage_model::s3::object_table::*;
 use garage_model::s3::version_table::*;
 
+use crate::s3::encryption::CustomerKey;
 use crate::s3::error::*;
+use crate::s3::object_lock::add_object_lock_headers;
+use crate::s3::put::checksum_to_xml;
+use crate::s3::restore::{add_restore_headers, check_object_readable};
+use crate::s3::tagging::add_tagging_headers;
+use crate::s3::xml as s3_xml;
 
 const X_AMZ_MP_PARTS_COUNT: &str = "x-amz-mp-parts-count";
+/// Header with which the client asks for the checksum of the object to be returned
+const X_AMZ_CHECKSUM_MODE: &str = "x-amz-checksum-mode";
+/// Header listing the attributes requested in a GetObjectAttributes request
+const X_AMZ_OBJECT_ATTRIBUTES: &str = "x-amz-object-attributes";
+const OBJECT_ATTRIBUTES: &[&str] = &[
+	"ETag",
+	"Checksum",
+	"ObjectParts",
+	"StorageClass",
+	"ObjectSize",
+];
+/// Maximum number of parts listed in the response to a GetObjectAttributes request
+const MAX_OBJECT_PARTS: u64 = 1000;
+
+/// Header used to request a strongly consistent read of an object (`strong`),
+/// instead of a read from the read quorum of the metadata tables (`eventual`)
+pub const CONSISTENCY_HEADER: &str = "x-garage-consistency";
 
 fn object_headers(
 	version: &ObjectVersion,
@@ -38,6 +63,10 @@ fn object_headers(
 		.header(LAST_MODIFIED, date_str)
 		.header(ACCEPT_RANGES, "bytes".to_string());
 
+	if version.versioned {
+		resp = resp.header("x-amz-version-id", version.version_id());
+	}
+
 	if !version_meta.etag.is_empty() {
 		resp = resp.header(ETAG, format!("\"{}\"", version_meta.etag));
 	}
@@ -46,7 +75,27 @@ fn object_headers(
 		resp = resp.header(k, v.to_string());
 	}
 
-	resp
+	let resp = add_tagging_headers(resp, version);
+	let resp = add_restore_headers(resp, version);
+	add_object_lock_headers(resp, version)
+}
+
+/// Add the checksum of the object to a response to a request for the whole object,
+/// if the request asks for it
+fn add_checksum_headers(
+	resp: http::response::Builder,
+	req: &Request<Body>,
+	version_meta: &ObjectVersionMeta,
+) -> http::response::Builder {
+	match (
+		&version_meta.checksum,
+		req.headers().get(X_AMZ_CHECKSUM_MODE),
+	) {
+		(Some(checksum), Some(mode)) if mode == "ENABLED" => {
+			resp.header(checksum.algorithm.header_name(), checksum.value.to_string())
+		}
+		_ => resp,
+	}
 }
 
 fn try_answer_cached(
@@ -88,26 +137,95 @@ fn try_answer_cached(
 	}
 }
 
+/// Find the version of an object targetted by a GET or HEAD request:
+/// the version with the given version id, or the current version.
+/// Delete markers cannot be read, the error tells the client that the
+/// object was deleted and gives the version id of the delete marker.
+fn find_requested_version<'a>(
+	object: &'a Object,
+	version_id: Option<&str>,
+) -> Result<&'a ObjectVersion, Error> {
+	let version = match version_id {
+		Some(version_id) => object
+			.find_version(version_id)
+			.ok_or(Error::NoSuchVersion)?,
+		None => object.current_version().ok_or(Error::NoSuchKey)?,
+	};
+	if version.is_delete_marker() {
+		return Err(match version_id {
+			Some(_) => Error::DeleteMarkerVersion(version.version_id()),
+			None => Error::DeletedKey(version.version_id()),
+		});
+	}
+	Ok(version)
+}
+
+/// Number of nodes that must answer the reads of the metadata of the object,
+/// if the request asks for a strongly consistent read
+fn get_read_quorum(garage: &Garage, req: &Request<Body>) -> Result<Option<usize>, Error> {
+	let consistency = match req.headers().get(CONSISTENCY_HEADER) {
+		Some(v) => v.to_str().ok_or_bad_request("Invalid consistency header")?,
+		None => return Ok(None),
+	};
+	match consistency {
+		"eventual" => Ok(None),
+		"strong" => Ok(Some(garage.replication_mode.strong_read_quorum())),
+		_ => Err(Error::bad_request(format!(
+			"Invalid consistency: {} (expected strong or eventual)",
+			consistency
+		))),
+	}
+}
+
+async fn read_object(
+	garage: &Garage,
+	read_quorum: Option<usize>,
+	bucket_id: Uuid,
+	key: &str,
+) -> Result<Object, Error> {
+	let key = key.to_string();
+	let object = match read_quorum {
+		Some(quorum) => {
+			garage
+				.object_table
+				.get_with_quorum(&bucket_id, &key, quorum)
+				.await?
+		}
+		None => garage.object_table.get(&bucket_id, &key).await?,
+	};
+	object.ok_or(Error::NoSuchKey)
+}
+
+async fn read_version(
+	garage: &Garage,
+	read_quorum: Option<usize>,
+	version_uuid: Uuid,
+) -> Result<Version, Error> {
+	let version = match read_quorum {
+		Some(quorum) => {
+			garage
+				.version_table
+				.get_with_quorum(&version_uuid, &EmptyKey, quorum)
+				.await?
+		}
+		None => garage.version_table.get(&version_uuid, &EmptyKey).await?,
+	};
+	version.ok_or(Error::NoSuchKey)
+}
+
 /// Handle HEAD request
 pub async fn handle_head(
 	garage: Arc<Garage>,
 	req: &Request<Body>,
 	bucket_id: Uuid,
 	key: &str,
+	version_id: Option<&str>,
 	part_number: Option<u64>,
 ) -> Result<Response<Body>, Error> {
-	let object = garage
-		.object_table
-		.get(&bucket_id, &key.to_string())
-		.await?
-		.ok_or(Error::NoSuchKey)?;
-
-	let object_version = object
-		.versions()
-		.iter()
-		.rev()
-		.find(|v| v.is_data())
-		.ok_or(Error::NoSuchKey)?;
+	let read_quorum = get_read_quorum(&garage, req)?;
+	let object = read_object(&garage, read_quorum, bucket_id, key).await?;
+
+	let object_version = find_requested_version(&object, version_id)?;
 
 	let version_data = match &object_version.state {
 		ObjectVersionState::Complete(c) => c,
@@ -120,32 +238,32 @@ pub async fn handle_head(
 		_ => unreachable!(),
 	};
 
+	CustomerKey::check(
+		CustomerKey::from_request_headers(req.headers())?,
+		&version_meta.headers,
+	)?;
+
 	if let Some(cached) = try_answer_cached(object_version, version_meta, req) {
 		return Ok(cached);
 	}
 
 	if let Some(pn) = part_number {
 		match version_data {
-			ObjectVersionData::Inline(_, bytes) => {
+			ObjectVersionData::Inline(_, _) => {
 				if pn != 1 {
 					return Err(Error::InvalidPart);
 				}
+				// The inline data of encrypted objects is larger than the object
+				let size = version_meta.size;
 				Ok(object_headers(object_version, version_meta)
-					.header(CONTENT_LENGTH, format!("{}", bytes.len()))
-					.header(
-						CONTENT_RANGE,
-						format!("bytes 0-{}/{}", bytes.len() - 1, bytes.len()),
-					)
+					.header(CONTENT_LENGTH, format!("{}", size))
+					.header(CONTENT_RANGE, format!("bytes 0-{}/{}", size - 1, size))
 					.header(X_AMZ_MP_PARTS_COUNT, "1")
 					.status(StatusCode::PARTIAL_CONTENT)
 					.body(Body::empty())?)
 			}
 			ObjectVersionData::FirstBlock(_, _) => {
-				let version = garage
-					.version_table
-					.get(&object_version.uuid, &EmptyKey)
-					.await?
-					.ok_or(Error::NoSuchKey)?;
+				let version = read_version(&garage, read_quorum, object_version.uuid).await?;
 
 				let (part_offset, part_end) =
 					calculate_part_bounds(&version, pn).ok_or(Error::InvalidPart)?;
@@ -169,33 +287,187 @@ pub async fn handle_head(
 			_ => unreachable!(),
 		}
 	} else {
-		Ok(object_headers(object_version, version_meta)
+		let resp_builder = object_headers(object_version, version_meta);
+		Ok(add_checksum_headers(resp_builder, req, version_meta)
 			.header(CONTENT_LENGTH, format!("{}", version_meta.size))
 			.status(StatusCode::OK)
 			.body(Body::empty())?)
 	}
 }
 
+/// Handle GetObjectAttributes request
+pub async fn handle_get_object_attributes(
+	garage: Arc<Garage>,
+	req: &Request<Body>,
+	bucket_id: Uuid,
+	key: &str,
+	version_id: Option<&str>,
+) -> Result<Response<Body>, Error> {
+	let attributes = req
+		.headers()
+		.get(X_AMZ_OBJECT_ATTRIBUTES)
+		.ok_or_bad_request(format!("Missing {} header", X_AMZ_OBJECT_ATTRIBUTES))?
+		.to_str()?
+		.split(',')
+		.map(str::trim)
+		.filter(|a| !a.is_empty())
+		.collect::<Vec<_>>();
+	for attribute in attributes.iter() {
+		if !OBJECT_ATTRIBUTES.contains(attribute) {
+			return Err(Error::bad_request(format!(
+				"Invalid object attribute: {}",
+				attribute
+			)));
+		}
+	}
+	let requested = |name: &str| attributes.contains(&name);
+
+	let read_quorum = get_read_quorum(&garage, req)?;
+	let object = read_object(&garage, read_quorum, bucket_id, key).await?;
+	let object_version = find_requested_version(&object, version_id)?;
+
+	let version_meta = match &object_version.state {
+		ObjectVersionState::Complete(ObjectVersionData::Inline(meta, _)) => meta,
+		ObjectVersionState::Complete(ObjectVersionData::FirstBlock(meta, _)) => meta,
+		_ => unreachable!(),
+	};
+
+	CustomerKey::check(
+		CustomerKey::from_request_headers(req.headers())?,
+		&version_meta.headers,
+	)?;
+
+	// The ETag of objects uploaded with a multipart upload ends with their number of parts
+	let parts_count = match &object_version.state {
+		ObjectVersionState::Complete(ObjectVersionData::FirstBlock(_, _)) => version_meta
+			.etag
+			.rsplit_once('-')
+			.and_then(|(_, n)| n.parse::<u64>().ok()),
+		_ => None,
+	};
+
+	let object_parts = match parts_count {
+		Some(n) if requested("ObjectParts") => {
+			Some(get_object_parts(&garage, req, read_quorum, object_version, n as i64).await?)
+		}
+		_ => None,
+	};
+
+	let result = s3_xml::GetObjectAttributesOutput {
+		xmlns: (),
+		etag: Some(s3_xml::Value(version_meta.etag.clone())).filter(|_| requested("ETag")),
+		checksum: version_meta
+			.checksum
+			.as_ref()
+			.filter(|_| requested("Checksum"))
+			.map(checksum_to_xml),
+		object_parts,
+		storage_class: Some(s3_xml::Value(
+			object_version.storage_class.as_str().to_string(),
+		))
+		.filter(|_| requested("StorageClass")),
+		object_size: Some(s3_xml::IntValue(version_meta.size as i64))
+			.filter(|_| requested("ObjectSize")),
+	};
+	let xml = s3_xml::to_xml_with_header(&result)?;
+
+	let date = UNIX_EPOCH + Duration::from_millis(object_version.timestamp);
+	let mut resp = Response::builder()
+		.header(CONTENT_TYPE, "application/xml")
+		.header(LAST_MODIFIED, httpdate::fmt_http_date(date));
+	if object_version.versioned {
+		resp = resp.header("x-amz-version-id", object_version.version_id());
+	}
+	Ok(resp.body(Body::from(xml))?)
+}
+
+/// List the parts of an object uploaded with a multipart upload for a GetObjectAttributes
+/// request: at most x-amz-max-parts parts, after the part given in x-amz-part-number-marker
+async fn get_object_parts(
+	garage: &Garage,
+	req: &Request<Body>,
+	read_quorum: Option<usize>,
+	object_version: &ObjectVersion,
+	total_parts_count: i64,
+) -> Result<s3_xml::ObjectParts, Error> {
+	let max_parts = get_u64_header(req, "x-amz-max-parts")?
+		.unwrap_or(MAX_OBJECT_PARTS)
+		.min(MAX_OBJECT_PARTS);
+	let part_number_marker = get_u64_header(req, "x-amz-part-number-marker")?;
+
+	// Compute the size of each part from the blocks of the version
+	let version = read_version(garage, read_quorum, object_version.uuid).await?;
+	let mut parts = Vec::<(u64, u64)>::new();
+	for (bk, vb) in version.blocks.items().iter() {
+		match parts.last_mut() {
+			Some((part_number, size)) if *part_number == bk.part_number => *size += vb.size,
+			_ => parts.push((bk.part_number, vb.size)),
+		}
+	}
+
+	let mut parts = parts
+		.into_iter()
+		.filter(|(part_number, _)| Some(*part_number) > part_number_marker)
+		.take(max_parts as usize + 1)
+		.collect::<Vec<_>>();
+	let is_truncated = parts.len() > max_parts as usize;
+	parts.truncate(max_parts as usize);
+
+	Ok(s3_xml::ObjectParts {
+		total_parts_count: s3_xml::IntValue(total_parts_count),
+		part_number_marker: part_number_marker.map(|m| s3_xml::IntValue(m as i64)),
+		next_part_number_marker: match parts.last() {
+			Some((part_number, _)) if is_truncated => Some(s3_xml::IntValue(*part_number as i64)),
+			_ => None,
+		},
+		max_parts: s3_xml::IntValue(max_parts as i64),
+		is_truncated: s3_xml::Value(format!("{}", is_truncated)),
+		parts: parts
+			.iter()
+			.map(|(part_number, size)| {
+				let checksum = version
+					.parts_checksums
+					.get(part_number)
+					.map(checksum_to_xml)
+					.unwrap_or_default();
+				s3_xml::ObjectPart {
+					part_number: s3_xml::IntValue(*part_number as i64),
+					size: s3_xml::IntValue(*size as i64),
+					checksum_crc32: checksum.checksum_crc32,
+					checksum_crc32c: checksum.checksum_crc32c,
+					checksum_sha1: checksum.checksum_sha1,
+					checksum_sha256: checksum.checksum_sha256,
+				}
+			})
+			.collect(),
+	})
+}
+
+fn get_u64_header(req: &Request<Body>, name: &str) -> Result<Option<u64>, Error> {
+	match req.headers().get(name) {
+		Some(v) => Ok(Some(
+			v.to_str()
+				.ok()
+				.and_then(|v| v.trim().parse::<u64>().ok())
+				.ok_or_bad_request(format!("Invalid {} header", name))?,
+		)),
+		None => Ok(None),
+	}
+}
+
 /// Handle GET request
 pub async fn handle_get(
 	garage: Arc<Garage>,
 	req: &Request<Body>,
 	bucket_id: Uuid,
 	key: &str,
+	version_id: Option<&str>,
 	part_number: Option<u64>,
 ) -> Result<Response<Body>, Error> {
-	let object = garage
-		.object_table
-		.get(&bucket_id, &key.to_string())
-		.await?
-		.ok_or(Error::NoSuchKey)?;
-
-	let last_v = object
-		.versions()
-		.iter()
-		.rev()
-		.find(|v| v.is_complete())
-		.ok_or(Error::NoSuchKey)?;
+	let read_quorum = get_read_quorum(&garage, req)?;
+	let object = read_object(&garage, read_quorum, bucket_id, key).await?;
+
+	let last_v = find_requested_version(&object, version_id)?;
 
 	let last_v_data = match &last_v.state {
 		ObjectVersionState::Complete(x) => x,
@@ -207,34 +479,74 @@ pub async fn handle_get(
 		ObjectVersionData::FirstBlock(meta, _) => meta,
 	};
 
+	check_object_readable(last_v)?;
+
+	let encryption = CustomerKey::check(
+		CustomerKey::from_request_headers(req.headers())?,
+		&last_v_meta.headers,
+	)?;
+	// The inline data of encrypted objects is decrypted here for all kinds of reads
+	let decrypted_data;
+	let last_v_data = match (last_v_data, &encryption) {
+		(ObjectVersionData::Inline(meta, bytes), Some(key)) => {
+			decrypted_data = ObjectVersionData::Inline(meta.clone(), key.decrypt(bytes)?.to_vec());
+			&decrypted_data
+		}
+		_ => last_v_data,
+	};
+
 	if let Some(cached) = try_answer_cached(last_v, last_v_meta, req) {
 		return Ok(cached);
 	}
 
-	match (part_number, parse_range_header(req, last_v_meta.size)?) {
-		(Some(_), Some(_)) => {
+	let ranges = parse_range_header(req, last_v_meta.size)?;
+	match (part_number, &ranges[..]) {
+		(Some(_), [_, ..]) => {
 			return Err(Error::bad_request(
 				"Cannot specify both partNumber and Range header",
 			));
 		}
-		(Some(pn), None) => {
-			return handle_get_part(garage, last_v, last_v_data, last_v_meta, pn).await;
+		(Some(pn), []) => {
+			return handle_get_part(
+				garage,
+				read_quorum,
+				last_v,
+				last_v_data,
+				last_v_meta,
+				pn,
+				encryption,
+			)
+			.await;
 		}
-		(None, Some(range)) => {
+		(None, [range]) => {
 			return handle_get_range(
 				garage,
+				read_quorum,
 				last_v,
 				last_v_data,
 				last_v_meta,
 				range.start,
 				range.start + range.length,
+				encryption,
 			)
 			.await;
 		}
-		(None, None) => (),
+		(None, [_, _, ..]) => {
+			return handle_get_multi_range(
+				garage,
+				read_quorum,
+				last_v,
+				last_v_data,
+				last_v_meta,
+				&ranges,
+				encryption,
+			)
+			.await;
+		}
+		(None, []) => (),
 	}
 
-	let resp_builder = object_headers(last_v, last_v_meta)
+	let resp_builder = add_checksum_headers(object_headers(last_v, last_v_meta), req, last_v_meta)
 		.header(CONTENT_LENGTH, format!("{}", last_v_meta.size))
 		.status(StatusCode::OK);
 
@@ -244,18 +556,33 @@ pub async fn handle_get(
 			let body: Body = Body::from(bytes.to_vec());
 			Ok(resp_builder.body(body)?)
 		}
+		ObjectVersionData::FirstBlock(_, _) if encryption.is_some() => {
+			let version = read_version(&garage, read_quorum, last_v.uuid).await?;
+			check_version_size(key, &version, last_v_meta.size);
+
+			let body = body_from_blocks_range(
+				garage,
+				version.blocks.items(),
+				0,
+				last_v_meta.size,
+				encryption,
+			);
+			Ok(resp_builder.body(body)?)
+		}
 		ObjectVersionData::FirstBlock(_, first_block_hash) => {
 			let (tx, rx) = mpsc::channel(2);
 
 			let order_stream = OrderTag::stream();
 			let first_block_hash = *first_block_hash;
 			let version_uuid = last_v.uuid;
+			let version_size = last_v_meta.size;
+			let key = key.to_string();
 
 			tokio::spawn(async move {
 				match async {
 					let garage2 = garage.clone();
 					let version_fut = tokio::spawn(async move {
-						garage2.version_table.get(&version_uuid, &EmptyKey).await
+						read_version(&garage2, read_quorum, version_uuid).await
 					});
 
 					let stream_block_0 = garage
@@ -266,7 +593,8 @@ pub async fn handle_get(
 						.await
 						.ok_or_message("channel closed")?;
 
-					let version = version_fut.await.unwrap()?.ok_or(Error::NoSuchKey)?;
+					let version = version_fut.await.unwrap()?;
+					check_version_size(&key, &version, version_size);
 					for (i, (_, vb)) in version.blocks.items().iter().enumerate().skip(1) {
 						let stream_block_i = garage
 							.block_manager
@@ -302,13 +630,34 @@ pub async fn handle_get(
 	}
 }
 
+/// Check that the size stored in the object table matches the total size
+/// of the blocks of the version; if not, `garage repair sync-version-sizes`
+/// should be run to fix it
+fn check_version_size(key: &str, version: &Version, expected_size: u64) {
+	let size = version.size();
+	if size != expected_size {
+		warn!(
+			"Size of object {} is {} but its blocks sum up to {} (version {:?})",
+			key, expected_size, size, version.uuid
+		);
+		global::meter("garage/api")
+			.u64_counter("s3.object_size_mismatch_counter")
+			.with_description("Number of objects read whose size does not match their blocks")
+			.init()
+			.add(1, &[]);
+	}
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn handle_get_range(
 	garage: Arc<Garage>,
+	read_quorum: Option<usize>,
 	version: &ObjectVersion,
 	version_data: &ObjectVersionData,
 	version_meta: &ObjectVersionMeta,
 	begin: u64,
 	end: u64,
+	encryption: Option<CustomerKey>,
 ) -> Result<Response<Body>, Error> {
 	let resp_builder = object_headers(version, version_meta)
 		.header(CONTENT_LENGTH, format!("{}", end - begin))
@@ -331,24 +680,108 @@ async fn handle_get_range(
 			}
 		}
 		ObjectVersionData::FirstBlock(_meta, _first_block_hash) => {
-			let version = garage
-				.version_table
-				.get(&version.uuid, &EmptyKey)
-				.await?
-				.ok_or(Error::NoSuchKey)?;
+			let version = read_version(&garage, read_quorum, version.uuid).await?;
 
-			let body = body_from_blocks_range(garage, version.blocks.items(), begin, end);
+			let body =
+				body_from_blocks_range(garage, version.blocks.items(), begin, end, encryption);
 			Ok(resp_builder.body(body)?)
 		}
 	}
 }
 
+/// Answer a request for several ranges of an object with a `multipart/byteranges`
+/// body (RFC 7233), in which each range is sent with its own `Content-Range` header
+async fn handle_get_multi_range(
+	garage: Arc<Garage>,
+	read_quorum: Option<usize>,
+	version: &ObjectVersion,
+	version_data: &ObjectVersionData,
+	version_meta: &ObjectVersionMeta,
+	ranges: &[http_range::HttpRange],
+	encryption: Option<CustomerKey>,
+) -> Result<Response<Body>, Error> {
+	// The boundary is random so that it is very unlikely to appear in the object
+	let boundary = hex::encode(&gen_uuid().as_slice()[..16]);
+	let ranges = ranges
+		.iter()
+		.map(|r| (r.start, r.start + r.length))
+		.collect::<Vec<_>>();
+	let part_headers = ranges
+		.iter()
+		.map(|(begin, end)| {
+			format!(
+				"\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+				boundary,
+				version_meta.headers.content_type,
+				begin,
+				end - 1,
+				version_meta.size
+			)
+		})
+		.collect::<Vec<_>>();
+	let closing = format!("\r\n--{}--\r\n", boundary);
+	let content_length = part_headers.iter().map(|h| h.len() as u64).sum::<u64>()
+		+ ranges.iter().map(|(begin, end)| end - begin).sum::<u64>()
+		+ closing.len() as u64;
+
+	let resp_builder = object_headers(version, version_meta)
+		.header(CONTENT_LENGTH, format!("{}", content_length))
+		.status(StatusCode::PARTIAL_CONTENT);
+
+	let body = match &version_data {
+		ObjectVersionData::DeleteMarker => unreachable!(),
+		ObjectVersionData::Inline(_meta, bytes) => {
+			let mut body = Vec::with_capacity(content_length as usize);
+			for ((begin, end), header) in ranges.iter().zip(part_headers.iter()) {
+				if *end as usize > bytes.len() {
+					return Err(Error::internal_error(
+						"Requested range not present in inline bytes when it should have been",
+					));
+				}
+				body.extend_from_slice(header.as_bytes());
+				body.extend_from_slice(&bytes[*begin as usize..*end as usize]);
+			}
+			body.extend_from_slice(closing.as_bytes());
+			Body::from(body)
+		}
+		ObjectVersionData::FirstBlock(_meta, _first_block_hash) => {
+			let version = read_version(&garage, read_quorum, version.uuid).await?;
+
+			let mut parts = vec![];
+			for ((begin, end), header) in ranges.into_iter().zip(part_headers) {
+				parts.push(stream::once(future::ready(Ok(Bytes::from(header)))).boxed());
+				parts.push(
+					stream_from_blocks_range(
+						garage.clone(),
+						version.blocks.items(),
+						begin,
+						end,
+						encryption.clone(),
+					)
+					.boxed(),
+				);
+			}
+			parts.push(stream::once(future::ready(Ok(Bytes::from(closing)))).boxed());
+			Body::wrap_stream(stream::iter(parts).flatten())
+		}
+	};
+
+	let mut resp = resp_builder.body(body)?;
+	resp.headers_mut().insert(
+		CONTENT_TYPE,
+		HeaderValue::from_str(&format!("multipart/byteranges; boundary={}", boundary)).unwrap(),
+	);
+	Ok(resp)
+}
+
 async fn handle_get_part(
 	garage: Arc<Garage>,
+	read_quorum: Option<usize>,
 	object_version: &ObjectVersion,
 	version_data: &ObjectVersionData,
 	version_meta: &ObjectVersionMeta,
 	part_number: u64,
+	encryption: Option<CustomerKey>,
 ) -> Result<Response<Body>, Error> {
 	let resp_builder =
 		object_headers(object_version, version_meta).status(StatusCode::PARTIAL_CONTENT);
@@ -368,17 +801,14 @@ async fn handle_get_part(
 				.body(Body::from(bytes.to_vec()))?)
 		}
 		ObjectVersionData::FirstBlock(_, _) => {
-			let version = garage
-				.version_table
-				.get(&object_version.uuid, &EmptyKey)
-				.await?
-				.ok_or(Error::NoSuchKey)?;
+			let version = read_version(&garage, read_quorum, object_version.uuid).await?;
 
 			let (begin, end) =
 				calculate_part_bounds(&version, part_number).ok_or(Error::InvalidPart)?;
 			let n_parts = version.parts_etags.items().len();
 
-			let body = body_from_blocks_range(garage, version.blocks.items(), begin, end);
+			let body =
+				body_from_blocks_range(garage, version.blocks.items(), begin, end, encryption);
 
 			Ok(resp_builder
 				.header(CONTENT_LENGTH, format!("{}", end - begin))
@@ -396,23 +826,27 @@ async fn handle_get_part(
 fn parse_range_header(
 	req: &Request<Body>,
 	total_size: u64,
-) -> Result<Option<http_range::HttpRange>, Error> {
-	let range = match req.headers().get(RANGE) {
-		Some(range) => {
-			let range_str = range.to_str()?;
-			let mut ranges =
-				http_range::HttpRange::parse(range_str, total_size).map_err(|e| (e, total_size))?;
-			if ranges.len() > 1 {
-				// garage does not support multi-range requests yet, so we respond with the entire
-				// object when multiple ranges are requested
-				None
-			} else {
-				ranges.pop()
-			}
-		}
-		None => None,
+) -> Result<Vec<http_range::HttpRange>, Error> {
+	let range_str = match req.headers().get(RANGE) {
+		Some(range) => range.to_str()?,
+		None => return Ok(vec![]),
 	};
-	Ok(range)
+	let ranges =
+		http_range::HttpRange::parse(range_str, total_size).map_err(|e| (e, total_size))?;
+
+	// Ranges that start after the end of the object are silently ignored by the parser
+	// as long as another range is satisfiable: we reject the whole request instead
+	let n_specs = range_str
+		.split(',')
+		.filter(|r| !r.trim().is_empty())
+		.count();
+	if ranges.len() < n_specs {
+		return Err(Error::InvalidRange((
+			http_range::HttpRangeParseError::NoOverlap,
+			total_size,
+		)));
+	}
+	Ok(ranges)
 }
 
 fn calculate_part_bounds(v: &Version, part_number: u64) -> Option<(u64, u64)> {
@@ -431,12 +865,54 @@ fn calculate_part_bounds(v: &Version, part_number: u64) -> Option<(u64, u64)> {
 	None
 }
 
+/// Stream the whole content of a version of an object, for requests
+/// that process the data of the object (SelectObjectContent)
+pub(crate) async fn object_version_stream(
+	garage: Arc<Garage>,
+	version: &ObjectVersion,
+	encryption: Option<CustomerKey>,
+) -> Result<BoxStream<'static, Result<Bytes, std::io::Error>>, Error> {
+	match &version.state {
+		ObjectVersionState::Complete(ObjectVersionData::Inline(meta, bytes)) => {
+			let bytes = match CustomerKey::check(encryption, &meta.headers)? {
+				Some(key) => key.decrypt(bytes)?,
+				None => Bytes::from(bytes.to_vec()),
+			};
+			Ok(stream::once(future::ready(Ok(bytes))).boxed())
+		}
+		ObjectVersionState::Complete(ObjectVersionData::FirstBlock(meta, _)) => {
+			let encryption = CustomerKey::check(encryption, &meta.headers)?;
+			let v = read_version(&garage, None, version.uuid).await?;
+			Ok(
+				stream_from_blocks_range(garage, v.blocks.items(), 0, meta.size, encryption)
+					.boxed(),
+			)
+		}
+		_ => Err(Error::NoSuchKey),
+	}
+}
+
 fn body_from_blocks_range(
 	garage: Arc<Garage>,
 	all_blocks: &[(VersionBlockKey, VersionBlock)],
 	begin: u64,
 	end: u64,
+	encryption: Option<CustomerKey>,
 ) -> Body {
+	hyper::body::Body::wrap_stream(stream_from_blocks_range(
+		garage, all_blocks, begin, end, encryption,
+	))
+}
+
+/// Stream the bytes of a range of an object, only fetching the blocks
+/// that have an intersection with the range
+fn stream_from_blocks_range(
+	garage: Arc<Garage>,
+	all_blocks: &[(VersionBlockKey, VersionBlock)],
+	begin: u64,
+	end: u64,
+	encryption: Option<CustomerKey>,
+) -> impl Stream<Item = Result<Bytes, std::io::Error>> + Send + 'static {
 	// We will store here the list of blocks that have an intersection with the requested
 	// range, as well as their "true offset", which is their actual offset in the complete
 	// file (whereas block.offset designates the offset of the block WITHIN THE PART
@@ -458,14 +934,14 @@ fn body_from_blocks_range(
 	}
 
 	let order_stream = OrderTag::stream();
-	let body_stream = futures::stream::iter(blocks)
+	futures::stream::iter(blocks)
 		.enumerate()
 		.map(move |(i, (block, block_offset))| {
 			let garage = garage.clone();
+			let encryption = encryption.clone();
+			let order_tag = order_stream.order(i as u64);
 			async move {
-				garage
-					.block_manager
-					.rpc_get_block_streaming(&block.hash, Some(order_stream.order(i as u64)))
+				get_block_stream(&garage, &block.hash, order_tag, encryption.as_ref())
 					.await
 					.unwrap_or_else(|e| error_stream(i, e))
 					.scan(block_offset, move |chunk_offset, chunk| {
@@ -508,12 +984,34 @@ fn body_from_blocks_range(
 			}
 		})
 		.buffered(2)
-		.flatten();
+		.flatten()
+}
 
-	hyper::body::Body::wrap_stream(body_stream)
+/// Get the data of a block as a stream. The blocks of objects encrypted with a key
+/// provided by the client can only be decrypted once they have been entirely received.
+async fn get_block_stream(
+	garage: &Garage,
+	hash: &Hash,
+	order_tag: OrderTag,
+	encryption: Option<&CustomerKey>,
+) -> Result<ByteStream, Error> {
+	match encryption {
+		None => Ok(garage
+			.block_manager
+			.rpc_get_block_streaming(hash, Some(order_tag))
+			.await?),
+		Some(key) => {
+			let block = garage
+				.block_manager
+				.rpc_get_block(hash, Some(order_tag))
+				.await?;
+			let data = key.decrypt(&block)?;
+			Ok(Box::pin(stream::once(future::ready(Ok(data)))))
+		}
+	}
 }
 
-fn error_stream(i: usize, e: garage_util::error::Error) -> ByteStream {
+fn error_stream(i: usize, e: Error) -> ByteStream {
 	Box::pin(futures::stream::once(async move {
 		Err(std::io::Error::new(
 			std::io::ErrorKind::Other,
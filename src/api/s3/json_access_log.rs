@@ -0,0 +1,210 @@
+//! Access logs of the S3 API written as one JSON object per line, to a file
+//! or to the standard output, for ingestion by log processing pipelines
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
+
+use hyper::{Body, Request, Response};
+use serde::Serialize;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+
+use garage_util::time::*;
+
+use crate::generic_server::ApiError;
+use crate::s3::access_log::{content_length, remote_ip};
+use crate::s3::error::*;
+use crate::s3::router::Endpoint;
+
+/// Version of the format of the log lines, increased on any change that
+/// is not the addition of a field
+pub const JSON_ACCESS_LOG_VERSION: u32 = 1;
+
+/// Maximum number of log lines waiting to be written, above which
+/// new lines are dropped
+const QUEUE_SIZE: usize = 4096;
+
+/// A line of the JSON access logs
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct JsonAccessLogEntry {
+	pub log_version: u32,
+	/// Time at which the request was received, in RFC 3339 format
+	pub time: String,
+	/// ID of the trace of the request
+	pub request_id: String,
+	pub remote_ip: String,
+	pub method: String,
+	/// Name of the S3 API endpoint, e.g. `GetObject`
+	pub operation: &'static str,
+	pub bucket: Option<String>,
+	pub key: Option<String>,
+	/// Access key ID of the requester, if the request was authenticated
+	pub access_key_id: Option<String>,
+	pub status_code: u16,
+	/// S3 error code of failed requests
+	pub error_code: Option<&'static str>,
+	/// Time taken to process the request, until the response headers were sent
+	pub elapsed_ms: u64,
+	/// Size of the request body, if given in its headers
+	pub bytes_in: Option<u64>,
+	/// Size of the response body, if given in its headers
+	pub bytes_out: Option<u64>,
+	pub user_agent: Option<String>,
+}
+
+/// A request whose log line is written once its response is known
+pub(crate) struct PendingJsonAccessLog {
+	start: Instant,
+	entry: JsonAccessLogEntry,
+}
+
+/// Information on a request that is only known once it has been authenticated
+#[derive(Default)]
+pub(crate) struct RequestContext {
+	pub access_key_id: Option<String>,
+}
+
+pub struct JsonAccessLog {
+	sender: mpsc::Sender<String>,
+}
+
+impl JsonAccessLog {
+	/// Start writing JSON access logs to the given file, or to the standard output
+	pub async fn new(path: Option<PathBuf>) -> Result<Arc<Self>, std::io::Error> {
+		let writer: Box<dyn AsyncWrite + Send + Unpin> = match path {
+			Some(path) => Box::new(
+				tokio::fs::OpenOptions::new()
+					.create(true)
+					.append(true)
+					.open(path)
+					.await?,
+			),
+			None => Box::new(tokio::io::stdout()),
+		};
+		let (sender, receiver) = mpsc::channel(QUEUE_SIZE);
+		tokio::spawn(write_loop(writer, receiver));
+		Ok(Arc::new(Self { sender }))
+	}
+
+	pub(crate) fn start(
+		&self,
+		req: &Request<Body>,
+		start: Instant,
+		request_id: String,
+		bucket_name: Option<&str>,
+		endpoint: &Endpoint,
+	) -> PendingJsonAccessLog {
+		let headers = req.headers();
+		let entry = JsonAccessLogEntry {
+			log_version: JSON_ACCESS_LOG_VERSION,
+			time: msec_to_rfc3339(now_msec() - start.elapsed().as_millis() as u64),
+			request_id,
+			remote_ip: remote_ip(req),
+			method: req.method().to_string(),
+			operation: endpoint.name(),
+			bucket: bucket_name.map(str::to_string),
+			key: endpoint.get_key().map(str::to_string),
+			access_key_id: None,
+			status_code: 0,
+			error_code: None,
+			elapsed_ms: 0,
+			// With streaming signatures, Content-Length includes the chunk signatures
+			bytes_in: headers
+				.get("x-amz-decoded-content-length")
+				.and_then(|v| v.to_str().ok()?.parse().ok())
+				.or_else(|| content_length(headers)),
+			bytes_out: None,
+			user_agent: headers
+				.get(hyper::header::USER_AGENT)
+				.and_then(|v| v.to_str().ok())
+				.map(str::to_string),
+		};
+		PendingJsonAccessLog { start, entry }
+	}
+
+	/// Complete the log line of a request with its response, and queue it for writing.
+	/// This never fails: lines that cannot be written are dropped.
+	pub(crate) fn finish(
+		&self,
+		pending: PendingJsonAccessLog,
+		ctx: RequestContext,
+		resp: &Result<Response<Body>, Error>,
+	) {
+		let PendingJsonAccessLog { start, mut entry } = pending;
+
+		entry.elapsed_ms = start.elapsed().as_millis() as u64;
+		entry.access_key_id = ctx.access_key_id;
+		match resp {
+			Ok(r) => {
+				entry.status_code = r.status().as_u16();
+				entry.bytes_out = content_length(r.headers());
+			}
+			Err(e) => {
+				entry.status_code = e.http_status_code().as_u16();
+				entry.error_code = Some(e.aws_code());
+			}
+		}
+
+		let mut line = match serde_json::to_string(&entry) {
+			Ok(line) => line,
+			Err(e) => {
+				warn!("Could not serialize access log: {}", e);
+				return;
+			}
+		};
+		line.push('\n');
+		if self.sender.try_send(line).is_err() {
+			warn!("Access log queue is full, dropping access log");
+		}
+	}
+}
+
+async fn write_loop(
+	mut writer: Box<dyn AsyncWrite + Send + Unpin>,
+	mut receiver: mpsc::Receiver<String>,
+) {
+	while let Some(mut lines) = receiver.recv().await {
+		// Write all the queued lines at once
+		while let Ok(line) = receiver.try_recv() {
+			lines.push_str(&line);
+		}
+		let res = async {
+			writer.write_all(lines.as_bytes()).await?;
+			writer.flush().await
+		};
+		if let Err(e) = res.await {
+			warn!("Could not write access logs: {}", e);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_json_access_log_entry() {
+		let entry = JsonAccessLogEntry {
+			log_version: JSON_ACCESS_LOG_VERSION,
+			time: "2023-11-14T22:13:20.000Z".into(),
+			request_id: "4bf92f3577b34da6a3ce929d0e0e4736".into(),
+			remote_ip: "192.0.2.3".into(),
+			method: "PUT".into(),
+			operation: "PutObject",
+			bucket: Some("my-bucket".into()),
+			key: Some("photos/cat.jpg".into()),
+			access_key_id: Some("GK31c2f218a2e44f485b94239e".into()),
+			status_code: 200,
+			error_code: None,
+			elapsed_ms: 12,
+			bytes_in: Some(3462),
+			bytes_out: Some(0),
+			user_agent: None,
+		};
+		assert_eq!(
+			serde_json::to_string(&entry).unwrap(),
+			r#"{"log_version":1,"time":"2023-11-14T22:13:20.000Z","request_id":"4bf92f3577b34da6a3ce929d0e0e4736","remote_ip":"192.0.2.3","method":"PUT","operation":"PutObject","bucket":"my-bucket","key":"photos/cat.jpg","access_key_id":"GK31c2f218a2e44f485b94239e","status_code":200,"error_code":null,"elapsed_ms":12,"bytes_in":3462,"bytes_out":0,"user_agent":null}"#
+		);
+	}
+}
@@ -0,0 +1,740 @@
+use quick_xml::de::from_reader;
+use std::sync::Arc;
+
+use hyper::{Body, Request, Response, StatusCode};
+use serde::de::IgnoredAny;
+use serde::{Deserialize, Serialize};
+
+use crate::s3::error::*;
+use crate::s3::tagging::Tag;
+use crate::s3::xml::{to_xml_with_header, xmlns_tag, IntValue, Value};
+use crate::signature::verify_signed_content;
+
+use garage_model::bucket_table::{
+	parse_lifecycle_date, Bucket, LifecycleExpiration as GarageLifecycleExpiration,
+	LifecycleFilter as GarageLifecycleFilter, LifecycleRule as GarageLifecycleRule,
+};
+use garage_model::garage::Garage;
+use garage_util::data::*;
+
+/// Maximum number of rules in a lifecycle configuration, as on AWS
+const MAX_LIFECYCLE_RULES: usize = 1000;
+
+pub async fn handle_get_lifecycle(bucket: &Bucket) -> Result<Response<Body>, Error> {
+	let param = bucket
+		.params()
+		.ok_or_internal_error("Bucket should not be deleted at this point")?;
+
+	if let Some(lifecycle) = param.lifecycle_config.get() {
+		let wc = LifecycleConfiguration::from_garage_lifecycle_config(lifecycle);
+		let xml = to_xml_with_header(&wc)?;
+		Ok(Response::builder()
+			.status(StatusCode::OK)
+			.header(http::header::CONTENT_TYPE, "application/xml")
+			.body(Body::from(xml))?)
+	} else {
+		Err(Error::NoSuchLifecycleConfiguration)
+	}
+}
+
+pub async fn handle_delete_lifecycle(
+	garage: Arc<Garage>,
+	bucket_id: Uuid,
+) -> Result<Response<Body>, Error> {
+	let mut bucket = garage
+		.bucket_helper()
+		.get_existing_bucket(bucket_id)
+		.await?;
+
+	let param = bucket.params_mut().unwrap();
+
+	param.lifecycle_config.update(None);
+	garage.bucket_table.insert(&bucket).await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::NO_CONTENT)
+		.body(Body::empty())?)
+}
+
+pub async fn handle_put_lifecycle(
+	garage: Arc<Garage>,
+	bucket_id: Uuid,
+	req: Request<Body>,
+	content_sha256: Option<Hash>,
+) -> Result<Response<Body>, Error> {
+	let body = hyper::body::to_bytes(req.into_body()).await?;
+
+	if let Some(content_sha256) = content_sha256 {
+		verify_signed_content(content_sha256, &body[..])?;
+	}
+
+	let mut bucket = garage
+		.bucket_helper()
+		.get_existing_bucket(bucket_id)
+		.await?;
+
+	let param = bucket.params_mut().unwrap();
+
+	let conf: LifecycleConfiguration = from_reader(&body as &[u8])?;
+	let config = conf.validate_into_garage_lifecycle_config()?;
+
+	param.lifecycle_config.update(Some(config));
+	garage.bucket_table.insert(&bucket).await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.body(Body::empty())?)
+}
+
+// ---- SERIALIZATION AND DESERIALIZATION TO/FROM S3 XML ----
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct LifecycleConfiguration {
+	#[serde(serialize_with = "xmlns_tag", skip_deserializing)]
+	pub xmlns: (),
+	#[serde(rename = "Rule")]
+	pub lifecycle_rules: Vec<LifecycleRule>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct LifecycleRule {
+	#[serde(rename = "ID")]
+	pub id: Option<Value>,
+	#[serde(rename = "Status")]
+	pub status: Value,
+	#[serde(rename = "Filter", default)]
+	pub filter: Option<Filter>,
+	/// Deprecated way of giving a prefix, outside of a Filter element
+	#[serde(rename = "Prefix", default, skip_serializing)]
+	pub legacy_prefix: Option<Value>,
+	#[serde(
+		rename = "Expiration",
+		default,
+		skip_serializing_if = "Option::is_none"
+	)]
+	pub expiration: Option<Expiration>,
+	#[serde(
+		rename = "AbortIncompleteMultipartUpload",
+		default,
+		skip_serializing_if = "Option::is_none"
+	)]
+	pub abort_incomplete_mpu: Option<AbortIncompleteMpu>,
+	#[serde(
+		rename = "NoncurrentVersionExpiration",
+		default,
+		skip_serializing_if = "Option::is_none"
+	)]
+	pub noncurrent_version_expiration: Option<NoncurrentVersionExpiration>,
+
+	// Actions that are not supported by Garage, as it has no storage classes
+	#[serde(rename = "Transition", default, skip_serializing)]
+	pub transitions: Vec<IgnoredAny>,
+	#[serde(rename = "NoncurrentVersionTransition", default, skip_serializing)]
+	pub noncurrent_version_transitions: Vec<IgnoredAny>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+pub struct Filter {
+	#[serde(rename = "And", skip_serializing_if = "Option::is_none")]
+	pub and: Option<Box<Filter>>,
+	#[serde(rename = "Prefix", skip_serializing_if = "Option::is_none")]
+	pub prefix: Option<Value>,
+	#[serde(
+		rename = "ObjectSizeGreaterThan",
+		skip_serializing_if = "Option::is_none"
+	)]
+	pub size_gt: Option<IntValue>,
+	#[serde(rename = "ObjectSizeLessThan", skip_serializing_if = "Option::is_none")]
+	pub size_lt: Option<IntValue>,
+	#[serde(rename = "Tag", default, skip_serializing_if = "Vec::is_empty")]
+	pub tags: Vec<Tag>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct Expiration {
+	#[serde(rename = "Days", skip_serializing_if = "Option::is_none")]
+	pub days: Option<IntValue>,
+	#[serde(rename = "Date", skip_serializing_if = "Option::is_none")]
+	pub at_date: Option<Value>,
+	#[serde(rename = "ExpiredObjectDeleteMarker", default, skip_serializing)]
+	pub expired_object_delete_marker: Option<Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct AbortIncompleteMpu {
+	#[serde(rename = "DaysAfterInitiation")]
+	pub days: IntValue,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct NoncurrentVersionExpiration {
+	#[serde(rename = "NoncurrentDays")]
+	pub days: IntValue,
+	#[serde(rename = "NewerNoncurrentVersions", default, skip_serializing)]
+	pub newer_noncurrent_versions: Option<IntValue>,
+}
+
+impl LifecycleConfiguration {
+	pub fn validate_into_garage_lifecycle_config(self) -> Result<Vec<GarageLifecycleRule>, Error> {
+		if self.lifecycle_rules.is_empty() {
+			return Err(Error::bad_request(
+				"Bad XML: at least one lifecycle rule must be given",
+			));
+		}
+		if self.lifecycle_rules.len() > MAX_LIFECYCLE_RULES {
+			return Err(Error::bad_request(format!(
+				"Bad XML: at most {} lifecycle rules can be given",
+				MAX_LIFECYCLE_RULES
+			)));
+		}
+
+		let mut ret = vec![];
+		for rule in self.lifecycle_rules {
+			ret.push(rule.validate_into_garage_lifecycle_rule()?);
+		}
+		Ok(ret)
+	}
+
+	pub fn from_garage_lifecycle_config(config: &[GarageLifecycleRule]) -> Self {
+		Self {
+			xmlns: (),
+			lifecycle_rules: config
+				.iter()
+				.map(LifecycleRule::from_garage_lifecycle_rule)
+				.collect(),
+		}
+	}
+}
+
+impl LifecycleRule {
+	pub fn validate_into_garage_lifecycle_rule(self) -> Result<GarageLifecycleRule, Error> {
+		if !self.transitions.is_empty() || !self.noncurrent_version_transitions.is_empty() {
+			return Err(Error::NotImplemented(
+				"Lifecycle transitions are not supported by Garage".into(),
+			));
+		}
+
+		if let Some(id) = &self.id {
+			if id.0.len() > 255 {
+				return Err(Error::bad_request(
+					"Bad XML: lifecycle rule ID cannot be longer than 255 characters",
+				));
+			}
+		}
+
+		let enabled = match self.status.0.as_str() {
+			"Enabled" => true,
+			"Disabled" => false,
+			_ => return Err(Error::bad_request("Bad XML: invalid lifecycle rule status")),
+		};
+
+		let filter = match (self.filter, self.legacy_prefix) {
+			(Some(filter), None) => filter.validate_into_garage_lifecycle_filter()?,
+			(None, Some(prefix)) => GarageLifecycleFilter {
+				prefix: Some(prefix.0),
+				..Default::default()
+			},
+			(None, None) => GarageLifecycleFilter::default(),
+			(Some(_), Some(_)) => {
+				return Err(Error::bad_request(
+					"Bad XML: Filter and Prefix cannot both be given in a lifecycle rule",
+				))
+			}
+		};
+
+		let abort_incomplete_mpu_days = self
+			.abort_incomplete_mpu
+			.map(|x| parse_days(&x.days, "DaysAfterInitiation"))
+			.transpose()?;
+
+		let expiration = self
+			.expiration
+			.map(Expiration::validate_into_garage_lifecycle_expiration)
+			.transpose()?;
+
+		let noncurrent_expiration_days = self
+			.noncurrent_version_expiration
+			.map(|x| {
+				if x.newer_noncurrent_versions.is_some() {
+					return Err(Error::NotImplemented(
+						"NewerNoncurrentVersions is not supported by Garage".into(),
+					));
+				}
+				parse_days(&x.days, "NoncurrentDays")
+			})
+			.transpose()?;
+
+		if abort_incomplete_mpu_days.is_some()
+			&& (filter.size_gt.is_some() || filter.size_lt.is_some())
+		{
+			return Err(Error::bad_request(
+				"Bad XML: AbortIncompleteMultipartUpload cannot be used with object size filters",
+			));
+		}
+		if abort_incomplete_mpu_days.is_some() && !filter.tags.is_empty() {
+			return Err(Error::bad_request(
+				"Bad XML: AbortIncompleteMultipartUpload cannot be used with object tag filters",
+			));
+		}
+
+		if expiration.is_none()
+			&& abort_incomplete_mpu_days.is_none()
+			&& noncurrent_expiration_days.is_none()
+		{
+			return Err(Error::bad_request(
+				"Bad XML: a lifecycle rule must contain at least one action",
+			));
+		}
+
+		Ok(GarageLifecycleRule {
+			id: self.id.map(|x| x.0),
+			enabled,
+			filter,
+			abort_incomplete_mpu_days,
+			expiration,
+			noncurrent_expiration_days,
+		})
+	}
+
+	pub fn from_garage_lifecycle_rule(rule: &GarageLifecycleRule) -> Self {
+		Self {
+			id: rule.id.as_deref().map(Value::from),
+			status: if rule.enabled {
+				Value::from("Enabled")
+			} else {
+				Value::from("Disabled")
+			},
+			filter: Some(Filter::from_garage_lifecycle_filter(&rule.filter))
+				.filter(|f| f.count() > 0 || f.and.is_some()),
+			legacy_prefix: None,
+			abort_incomplete_mpu: rule
+				.abort_incomplete_mpu_days
+				.map(|days| AbortIncompleteMpu {
+					days: IntValue(days as i64),
+				}),
+			expiration: rule
+				.expiration
+				.as_ref()
+				.map(Expiration::from_garage_lifecycle_expiration),
+			noncurrent_version_expiration: rule.noncurrent_expiration_days.map(|days| {
+				NoncurrentVersionExpiration {
+					days: IntValue(days as i64),
+					newer_noncurrent_versions: None,
+				}
+			}),
+			transitions: vec![],
+			noncurrent_version_transitions: vec![],
+		}
+	}
+}
+
+impl Filter {
+	pub fn count(&self) -> usize {
+		fn count<T>(x: &Option<T>) -> usize {
+			x.as_ref().map(|_| 1).unwrap_or(0)
+		}
+		count(&self.prefix) + count(&self.size_gt) + count(&self.size_lt) + self.tags.len()
+	}
+
+	pub fn validate_into_garage_lifecycle_filter(self) -> Result<GarageLifecycleFilter, Error> {
+		if self.count() > 0 && self.and.is_some() {
+			return Err(Error::bad_request(
+				"Bad XML: a lifecycle filter cannot have both <And> and other conditions",
+			));
+		}
+
+		let filter = match self.and {
+			Some(and) => {
+				if and.and.is_some() {
+					return Err(Error::bad_request(
+						"Bad XML: cannot have nested <And> in a lifecycle filter",
+					));
+				}
+				*and
+			}
+			None => {
+				if self.count() > 1 {
+					return Err(Error::bad_request(
+						"Bad XML: multiple lifecycle filter conditions must be wrapped in <And>",
+					));
+				}
+				self
+			}
+		};
+
+		Ok(GarageLifecycleFilter {
+			prefix: filter.prefix.map(|x| x.0),
+			size_gt: filter
+				.size_gt
+				.map(|x| parse_size(&x, "ObjectSizeGreaterThan"))
+				.transpose()?,
+			size_lt: filter
+				.size_lt
+				.map(|x| parse_size(&x, "ObjectSizeLessThan"))
+				.transpose()?,
+			tags: filter
+				.tags
+				.into_iter()
+				.map(|t| (t.key.0, t.value.0))
+				.collect(),
+		})
+	}
+
+	pub fn from_garage_lifecycle_filter(rule: &GarageLifecycleFilter) -> Self {
+		let filter = Filter {
+			and: None,
+			prefix: rule.prefix.as_deref().map(Value::from),
+			size_gt: rule.size_gt.map(|x| IntValue(x as i64)),
+			size_lt: rule.size_lt.map(|x| IntValue(x as i64)),
+			tags: rule.tags.iter().map(Tag::from_garage_tag).collect(),
+		};
+		if filter.count() > 1 {
+			Filter {
+				and: Some(Box::new(filter)),
+				..Default::default()
+			}
+		} else {
+			filter
+		}
+	}
+}
+
+impl Expiration {
+	pub fn validate_into_garage_lifecycle_expiration(
+		self,
+	) -> Result<GarageLifecycleExpiration, Error> {
+		if self
+			.expired_object_delete_marker
+			.map(|x| x.0 == "true")
+			.unwrap_or(false)
+		{
+			return Err(Error::NotImplemented(
+				"ExpiredObjectDeleteMarker is not supported by Garage".into(),
+			));
+		}
+		match (self.days, self.at_date) {
+			(Some(days), None) => Ok(GarageLifecycleExpiration::AfterDays(parse_days(
+				&days, "Days",
+			)?)),
+			(None, Some(date)) => {
+				// AWS requires dates at midnight UTC, e.g. 2023-01-01T00:00:00.000Z
+				let day = date
+					.0
+					.strip_suffix("T00:00:00Z")
+					.or_else(|| date.0.strip_suffix("T00:00:00.000Z"))
+					.unwrap_or(&date.0);
+				if parse_lifecycle_date(day).is_none() {
+					return Err(Error::bad_request(format!(
+						"Bad XML: invalid expiration date: {}",
+						date.0
+					)));
+				}
+				Ok(GarageLifecycleExpiration::AtDate(day.to_string()))
+			}
+			_ => Err(Error::bad_request(
+				"Bad XML: exactly one of Days and Date must be given in <Expiration>",
+			)),
+		}
+	}
+
+	pub fn from_garage_lifecycle_expiration(exp: &GarageLifecycleExpiration) -> Self {
+		match exp {
+			GarageLifecycleExpiration::AfterDays(days) => Expiration {
+				days: Some(IntValue(*days as i64)),
+				at_date: None,
+				expired_object_delete_marker: None,
+			},
+			GarageLifecycleExpiration::AtDate(day) => Expiration {
+				days: None,
+				at_date: Some(Value(format!("{}T00:00:00Z", day))),
+				expired_object_delete_marker: None,
+			},
+		}
+	}
+}
+
+fn parse_days(days: &IntValue, what: &str) -> Result<usize, Error> {
+	if days.0 > 0 {
+		Ok(days.0 as usize)
+	} else {
+		Err(Error::bad_request(format!(
+			"Bad XML: {} must be a positive number of days",
+			what
+		)))
+	}
+}
+
+fn parse_size(size: &IntValue, what: &str) -> Result<u64, Error> {
+	if size.0 >= 0 {
+		Ok(size.0 as u64)
+	} else {
+		Err(Error::bad_request(format!(
+			"Bad XML: {} cannot be negative",
+			what
+		)))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::common_error::CommonError;
+
+	use quick_xml::de::from_str;
+
+	#[test]
+	fn test_deserialize_lifecycle_config() -> Result<(), Error> {
+		let message = r#"<?xml version="1.0" encoding="UTF-8"?>
+<LifecycleConfiguration xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <Rule>
+    <ID>id1</ID>
+    <Status>Enabled</Status>
+    <Filter>
+      <Prefix>documents/</Prefix>
+    </Filter>
+    <AbortIncompleteMultipartUpload>
+      <DaysAfterInitiation>7</DaysAfterInitiation>
+    </AbortIncompleteMultipartUpload>
+  </Rule>
+  <Rule>
+    <ID>id2</ID>
+    <Status>Enabled</Status>
+    <Filter>
+      <And>
+        <Prefix>logs/</Prefix>
+        <ObjectSizeGreaterThan>1000000</ObjectSizeGreaterThan>
+      </And>
+    </Filter>
+    <Expiration>
+      <Days>365</Days>
+    </Expiration>
+  </Rule>
+</LifecycleConfiguration>"#;
+		let conf: LifecycleConfiguration = from_str(message).unwrap();
+		let ref_value = LifecycleConfiguration {
+			xmlns: (),
+			lifecycle_rules: vec![
+				LifecycleRule {
+					id: Some("id1".into()),
+					status: "Enabled".into(),
+					filter: Some(Filter {
+						prefix: Some("documents/".into()),
+						..Default::default()
+					}),
+					legacy_prefix: None,
+					expiration: None,
+					abort_incomplete_mpu: Some(AbortIncompleteMpu { days: IntValue(7) }),
+					transitions: vec![],
+					noncurrent_version_transitions: vec![],
+					noncurrent_version_expiration: None,
+				},
+				LifecycleRule {
+					id: Some("id2".into()),
+					status: "Enabled".into(),
+					filter: Some(Filter {
+						and: Some(Box::new(Filter {
+							prefix: Some("logs/".into()),
+							size_gt: Some(IntValue(1000000)),
+							..Default::default()
+						})),
+						..Default::default()
+					}),
+					legacy_prefix: None,
+					expiration: Some(Expiration {
+						days: Some(IntValue(365)),
+						at_date: None,
+						expired_object_delete_marker: None,
+					}),
+					abort_incomplete_mpu: None,
+					transitions: vec![],
+					noncurrent_version_transitions: vec![],
+					noncurrent_version_expiration: None,
+				},
+			],
+		};
+		assert_eq!(ref_value, conf);
+
+		let message2 = to_xml_with_header(&ref_value)?;
+
+		let cleanup = |c: &str| c.replace(char::is_whitespace, "");
+		assert_eq!(cleanup(message), cleanup(&message2));
+
+		// Check validation
+		let validated = ref_value.validate_into_garage_lifecycle_config()?;
+
+		let ref_config = vec![
+			GarageLifecycleRule {
+				id: Some("id1".into()),
+				enabled: true,
+				filter: GarageLifecycleFilter {
+					prefix: Some("documents/".into()),
+					..Default::default()
+				},
+				expiration: None,
+				abort_incomplete_mpu_days: Some(7),
+				noncurrent_expiration_days: None,
+			},
+			GarageLifecycleRule {
+				id: Some("id2".into()),
+				enabled: true,
+				filter: GarageLifecycleFilter {
+					prefix: Some("logs/".into()),
+					size_gt: Some(1000000),
+					..Default::default()
+				},
+				expiration: Some(GarageLifecycleExpiration::AfterDays(365)),
+				abort_incomplete_mpu_days: None,
+				noncurrent_expiration_days: None,
+			},
+		];
+		assert_eq!(validated, ref_config);
+
+		let message3 = to_xml_with_header(&LifecycleConfiguration::from_garage_lifecycle_config(
+			&validated,
+		))?;
+		assert_eq!(cleanup(message), cleanup(&message3));
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_reject_unsupported_lifecycle_config() {
+		let message = r#"<LifecycleConfiguration>
+  <Rule>
+    <Status>Enabled</Status>
+    <Filter><Prefix>a/</Prefix></Filter>
+    <Transition>
+      <Days>30</Days>
+      <StorageClass>GLACIER</StorageClass>
+    </Transition>
+  </Rule>
+</LifecycleConfiguration>"#;
+		let conf: LifecycleConfiguration = from_str(message).unwrap();
+		assert!(matches!(
+			conf.validate_into_garage_lifecycle_config(),
+			Err(Error::NotImplemented(_))
+		));
+
+		let message = r#"<LifecycleConfiguration>
+  <Rule>
+    <Status>Enabled</Status>
+    <Filter><Prefix>a/</Prefix><ObjectSizeLessThan>10</ObjectSizeLessThan></Filter>
+    <Expiration><Days>30</Days></Expiration>
+  </Rule>
+</LifecycleConfiguration>"#;
+		let conf: LifecycleConfiguration = from_str(message).unwrap();
+		assert!(matches!(
+			conf.validate_into_garage_lifecycle_config(),
+			Err(Error::Common(CommonError::BadRequest(_)))
+		));
+
+		let message = r#"<LifecycleConfiguration>
+  <Rule>
+    <Status>Enabled</Status>
+    <Prefix>a/</Prefix>
+    <Expiration><Date>2023-01-01T00:00:00.000Z</Date></Expiration>
+  </Rule>
+</LifecycleConfiguration>"#;
+		let conf: LifecycleConfiguration = from_str(message).unwrap();
+		let validated = conf.validate_into_garage_lifecycle_config().unwrap();
+		assert_eq!(
+			validated[0].expiration,
+			Some(GarageLifecycleExpiration::AtDate("2023-01-01".into()))
+		);
+	}
+
+	#[test]
+	fn test_lifecycle_tag_filter() -> Result<(), Error> {
+		let message = r#"<?xml version="1.0" encoding="UTF-8"?>
+<LifecycleConfiguration xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <Rule>
+    <Status>Enabled</Status>
+    <Filter>
+      <And>
+        <Prefix>logs/</Prefix>
+        <Tag><Key>retention</Key><Value>short</Value></Tag>
+      </And>
+    </Filter>
+    <Expiration>
+      <Days>7</Days>
+    </Expiration>
+  </Rule>
+</LifecycleConfiguration>"#;
+		let conf: LifecycleConfiguration = from_str(message).unwrap();
+		let validated = conf.validate_into_garage_lifecycle_config()?;
+		assert_eq!(
+			validated[0].filter,
+			GarageLifecycleFilter {
+				prefix: Some("logs/".into()),
+				tags: vec![("retention".into(), "short".into())],
+				..Default::default()
+			}
+		);
+
+		let message2 = to_xml_with_header(&LifecycleConfiguration::from_garage_lifecycle_config(
+			&validated,
+		))?;
+		let cleanup = |c: &str| c.replace(char::is_whitespace, "");
+		assert_eq!(cleanup(message), cleanup(&message2));
+
+		// Tag filters cannot be used to abort incomplete multipart uploads
+		let message = r#"<LifecycleConfiguration>
+  <Rule>
+    <Status>Enabled</Status>
+    <Filter><Tag><Key>a</Key><Value>b</Value></Tag></Filter>
+    <AbortIncompleteMultipartUpload><DaysAfterInitiation>1</DaysAfterInitiation></AbortIncompleteMultipartUpload>
+  </Rule>
+</LifecycleConfiguration>"#;
+		let conf: LifecycleConfiguration = from_str(message).unwrap();
+		assert!(matches!(
+			conf.validate_into_garage_lifecycle_config(),
+			Err(Error::Common(CommonError::BadRequest(_)))
+		));
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_lifecycle_noncurrent_expiration() -> Result<(), Error> {
+		let message = r#"<?xml version="1.0" encoding="UTF-8"?>
+<LifecycleConfiguration xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <Rule>
+    <Status>Enabled</Status>
+    <Filter>
+      <Prefix>backups/</Prefix>
+    </Filter>
+    <NoncurrentVersionExpiration>
+      <NoncurrentDays>30</NoncurrentDays>
+    </NoncurrentVersionExpiration>
+  </Rule>
+</LifecycleConfiguration>"#;
+		let conf: LifecycleConfiguration = from_str(message).unwrap();
+		let validated = conf.validate_into_garage_lifecycle_config()?;
+		assert_eq!(validated[0].noncurrent_expiration_days, Some(30));
+		assert_eq!(validated[0].expiration, None);
+
+		let message2 = to_xml_with_header(&LifecycleConfiguration::from_garage_lifecycle_config(
+			&validated,
+		))?;
+		let cleanup = |c: &str| c.replace(char::is_whitespace, "");
+		assert_eq!(cleanup(message), cleanup(&message2));
+
+		// Keeping a number of noncurrent versions is not supported
+		let message = r#"<LifecycleConfiguration>
+  <Rule>
+    <Status>Enabled</Status>
+    <NoncurrentVersionExpiration>
+      <NoncurrentDays>30</NoncurrentDays>
+      <NewerNoncurrentVersions>3</NewerNoncurrentVersions>
+    </NoncurrentVersionExpiration>
+  </Rule>
+</LifecycleConfiguration>"#;
+		let conf: LifecycleConfiguration = from_str(message).unwrap();
+		assert!(matches!(
+			conf.validate_into_garage_lifecycle_config(),
+			Err(Error::NotImplemented(_))
+		));
+
+		Ok(())
+	}
+}
@@ -51,6 +51,13 @@ pub struct ListMultipartUploadsQuery {
 	pub common: ListQueryCommon,
 }
 
+#[derive(Debug)]
+pub struct ListObjectVersionsQuery {
+	pub key_marker: Option<String>,
+	pub version_id_marker: Option<String>,
+	pub common: ListQueryCommon,
+}
+
 #[derive(Debug)]
 pub struct ListPartsQuery {
 	pub bucket_name: String,
@@ -128,14 +135,7 @@ pub async fn handle_list(
 			_ => None,
 		},
 		next_continuation_token: match (query.is_v2, &pagination) {
-			(true, Some(RangeBegin::AfterKey { key })) => Some(s3_xml::Value(format!(
-				"]{}",
-				BASE64_STANDARD.encode(key.as_bytes())
-			))),
-			(true, Some(RangeBegin::IncludingKey { key, .. })) => Some(s3_xml::Value(format!(
-				"[{}",
-				BASE64_STANDARD.encode(key.as_bytes())
-			))),
+			(true, Some(begin)) => continuation_token(begin).map(s3_xml::Value),
 			_ => None,
 		},
 
@@ -148,7 +148,7 @@ pub async fn handle_list(
 				last_modified: s3_xml::Value(msec_to_rfc3339(info.last_modified)),
 				size: s3_xml::IntValue(info.size as i64),
 				etag: s3_xml::Value(format!("\"{}\"", info.etag)),
-				storage_class: s3_xml::Value("STANDARD".to_string()),
+				storage_class: s3_xml::Value(info.storage_class.as_str().to_string()),
 			})
 			.collect(),
 		common_prefixes: acc
@@ -266,6 +266,195 @@ pub async fn handle_list_multipart_upload(
 		.body(Body::from(xml.into_bytes()))?)
 }
 
+pub async fn handle_list_object_versions(
+	garage: Arc<Garage>,
+	query: &ListObjectVersionsQuery,
+) -> Result<Response<Body>, Error> {
+	debug!("ListObjectVersions {:?}", query);
+	let common = &query.common;
+
+	let mut versions = vec![];
+	let mut delete_markers = vec![];
+	let mut common_prefixes = BTreeSet::new();
+	let mut count = 0;
+	let mut is_truncated = false;
+	let mut next_marker: Option<(String, Option<String>)> = None;
+
+	let mut cursor = match &query.key_marker {
+		Some(km) if *km > common.prefix => km.clone(),
+		_ => common.prefix.clone(),
+	};
+	let mut last_key: Option<String> = None;
+
+	'outer: loop {
+		let objects = garage
+			.object_table
+			.get_range(
+				&common.bucket_id,
+				Some(cursor.clone()),
+				None,
+				common.page_size + 1,
+				EnumerationOrder::Forward,
+			)
+			.await?;
+		let server_more = objects.len() > common.page_size;
+		let mut skip_to = None;
+
+		for object in objects.iter() {
+			// The range is inclusive, ignore keys that were already processed
+			if last_key.as_ref().map(|k| object.key <= *k).unwrap_or(false) {
+				continue;
+			}
+			if !object.key.starts_with(&common.prefix) {
+				break 'outer;
+			}
+			last_key = Some(object.key.clone());
+
+			if let Some(delimiter) = &common.delimiter {
+				let after_prefix = &object.key[common.prefix.len()..];
+				if let Some(i) = after_prefix.find(delimiter) {
+					let pfx = object.key[..common.prefix.len() + i + delimiter.len()].to_string();
+					let after_marker = match &query.key_marker {
+						Some(km) => pfx.as_str() > km.as_str(),
+						None => true,
+					};
+					if after_marker {
+						if count == common.page_size {
+							is_truncated = true;
+							break 'outer;
+						}
+						count += 1;
+						next_marker = Some((pfx.clone(), None));
+						common_prefixes.insert(pfx.clone());
+					}
+					// Skip all other keys in this common prefix
+					match key_after_prefix(&pfx) {
+						Some(next) => {
+							skip_to = Some(next);
+							break;
+						}
+						None => break 'outer,
+					}
+				}
+			}
+
+			let object_versions = object
+				.versions()
+				.iter()
+				.rev()
+				.filter(|v| v.is_complete())
+				.collect::<Vec<_>>();
+
+			// Versions of a key are listed from the newest to the oldest,
+			// skip those up to the marker when listing starts at this key
+			let skip = match (&query.key_marker, &query.version_id_marker) {
+				(Some(km), None) if *km == object.key => object_versions.len(),
+				(Some(km), Some(vm)) if *km == object.key => object_versions
+					.iter()
+					.position(|v| v.version_id() == *vm)
+					.map(|i| i + 1)
+					.unwrap_or(object_versions.len()),
+				_ => 0,
+			};
+
+			for (i, version) in object_versions.iter().enumerate().skip(skip) {
+				if count == common.page_size {
+					is_truncated = true;
+					break 'outer;
+				}
+				count += 1;
+				next_marker = Some((object.key.clone(), Some(version.version_id())));
+
+				let key = uriencode_maybe(&object.key, common.urlencode_resp);
+				let version_id = s3_xml::Value(version.version_id());
+				let is_latest = s3_xml::Value(format!("{}", i == 0));
+				let last_modified = s3_xml::Value(msec_to_rfc3339(version.timestamp));
+				let owner = s3_xml::Owner {
+					display_name: s3_xml::Value(DUMMY_NAME.to_string()),
+					id: s3_xml::Value(DUMMY_KEY.to_string()),
+				};
+				match &version.state {
+					ObjectVersionState::Complete(ObjectVersionData::DeleteMarker) => delete_markers
+						.push(s3_xml::ListDeleteMarkerItem {
+							key,
+							version_id,
+							is_latest,
+							last_modified,
+							owner,
+						}),
+					ObjectVersionState::Complete(ObjectVersionData::Inline(meta, _))
+					| ObjectVersionState::Complete(ObjectVersionData::FirstBlock(meta, _)) => {
+						versions.push(s3_xml::ListVersionItem {
+							key,
+							version_id,
+							is_latest,
+							last_modified,
+							etag: s3_xml::Value(format!("\"{}\"", meta.etag)),
+							size: s3_xml::IntValue(meta.size as i64),
+							storage_class: s3_xml::Value(
+								version.storage_class.as_str().to_string(),
+							),
+							owner,
+						})
+					}
+					_ => unreachable!(),
+				}
+			}
+		}
+
+		match skip_to {
+			Some(next) => cursor = next,
+			None if server_more => cursor = last_key.clone().expect("no key in a full page"),
+			None => break,
+		}
+	}
+
+	let result = s3_xml::ListVersionsResult {
+		xmlns: (),
+		name: s3_xml::Value(common.bucket_name.to_string()),
+		prefix: uriencode_maybe(&common.prefix, common.urlencode_resp),
+		key_marker: query
+			.key_marker
+			.as_ref()
+			.map(|m| uriencode_maybe(m, common.urlencode_resp)),
+		version_id_marker: query
+			.version_id_marker
+			.as_ref()
+			.map(|m| s3_xml::Value(m.to_string())),
+		next_key_marker: match (is_truncated, &next_marker) {
+			(true, Some((key, _))) => Some(uriencode_maybe(key, common.urlencode_resp)),
+			_ => None,
+		},
+		next_version_id_marker: match (is_truncated, &next_marker) {
+			(true, Some((_, Some(version_id)))) => Some(s3_xml::Value(version_id.to_string())),
+			_ => None,
+		},
+		max_keys: s3_xml::IntValue(common.page_size as i64),
+		delimiter: common
+			.delimiter
+			.as_ref()
+			.map(|d| uriencode_maybe(d, common.urlencode_resp)),
+		encoding_type: match common.urlencode_resp {
+			true => Some(s3_xml::Value("url".to_string())),
+			false => None,
+		},
+		is_truncated: s3_xml::Value(format!("{}", is_truncated)),
+		versions,
+		delete_markers,
+		common_prefixes: common_prefixes
+			.iter()
+			.map(|pfx| s3_xml::CommonPrefix {
+				prefix: uriencode_maybe(pfx, common.urlencode_resp),
+			})
+			.collect(),
+	};
+
+	let xml = s3_xml::to_xml_with_header(&result)?;
+	Ok(Response::builder()
+		.header("Content-Type", "application/xml")
+		.body(Body::from(xml.into_bytes()))?)
+}
+
 pub async fn handle_list_parts(
 	garage: Arc<Garage>,
 	query: &ListPartsQuery,
@@ -326,6 +515,7 @@ struct ObjectInfo {
 	last_modified: u64,
 	size: u64,
 	etag: String,
+	storage_class: StorageClass,
 }
 
 #[derive(Debug, PartialEq)]
@@ -581,7 +771,7 @@ impl ListObjectsQuery {
 				// string in the spec, so we can do whatever we want with it.
 				// In our case, it is defined as either [ or ] (for include
 				// representing the key to start with.
-				(Some(token), _) => match &token[..1] {
+				(Some(token), _) => match token.get(..1).unwrap_or_default() {
 					"[" => Ok(RangeBegin::IncludingKey {
 						key: String::from_utf8(
 							BASE64_STANDARD
@@ -723,15 +913,25 @@ impl<K: std::cmp::Ord, V> Accumulator<K, V> {
 			return Some(ExtractionResult::Filled);
 		}
 
-		// We consume the whole common prefix from the iterator
+		// We consume the whole common prefix from the iterator.
+		// Once done, the listing continues after all keys that could have
+		// this prefix, and not only after the last one we have seen: this way
+		// the prefix is not returned again in the next page if an object
+		// with this prefix is created between the two requests.
 		let mut last_pfx_key = &object.key;
 		loop {
 			last_pfx_key = match objects.peek() {
 				Some(o) if o.key.starts_with(pfx) => &o.key,
 				Some(_) => {
-					return Some(ExtractionResult::Extracted {
-						key: last_pfx_key.to_owned(),
-					})
+					return match key_after_prefix(pfx) {
+						Some(next) => Some(ExtractionResult::SkipTo {
+							key: next,
+							fallback_key: Some(last_pfx_key.to_owned()),
+						}),
+						None => Some(ExtractionResult::Extracted {
+							key: last_pfx_key.to_owned(),
+						}),
+					}
 				}
 				None => {
 					return match key_after_prefix(pfx) {
@@ -795,7 +995,7 @@ impl ExtractAccumulator for ObjectAccumulator {
 
 		let object = objects.next().expect("This iterator can not be empty as it is checked earlier in the code. This is a logic bug, please report it.");
 
-		let version = match object.versions().iter().find(|x| x.is_data()) {
+		let version = match object.current_data_version() {
 			Some(v) => v,
 			None => unreachable!(
 				"Expect to have objects having data due to earlier filtering. This is a logic bug."
@@ -811,6 +1011,7 @@ impl ExtractAccumulator for ObjectAccumulator {
 			last_modified: version.timestamp,
 			size: meta.size,
 			etag: meta.etag.to_string(),
+			storage_class: version.storage_class,
 		};
 
 		match self.try_insert_entry(object.key.clone(), info) {
@@ -926,6 +1127,21 @@ fn into_ok_or_err<T>(r: Result<T, T>) -> T {
 	}
 }
 
+/// Encode the position where the next page of a ListObjectsV2 request starts.
+/// The token only contains the key from where to continue and whether this key
+/// is included, so it stays valid whatever objects are added or removed.
+fn continuation_token(begin: &RangeBegin) -> Option<String> {
+	match begin {
+		RangeBegin::AfterKey { key } => {
+			Some(format!("]{}", BASE64_STANDARD.encode(key.as_bytes())))
+		}
+		RangeBegin::IncludingKey { key, .. } => {
+			Some(format!("[{}", BASE64_STANDARD.encode(key.as_bytes())))
+		}
+		RangeBegin::AfterUpload { .. } => None,
+	}
+}
+
 /// Returns the common prefix of the object given the query prefix and delimiter
 fn common_prefix<'a>(object: &'a Object, query: &ListQueryCommon) -> Option<&'a str> {
 	match &query.delimiter {
@@ -995,6 +1211,11 @@ mod tests {
 				content_type: "text/plain".to_string(),
 				other: BTreeMap::<String, String>::new(),
 			}),
+			versioned: false,
+			lock: ObjectVersionLock::default(),
+			tags: Default::default(),
+			storage_class: Default::default(),
+			restore: Default::default(),
 		}
 	}
 
@@ -1022,7 +1243,10 @@ mod tests {
 
 		let mut iter = objs.iter().peekable();
 		match acc.extract_common_prefix(&mut iter, &query.common) {
-			Some(ExtractionResult::Extracted { key }) => assert_eq!(key, "a/b/c".to_string()),
+			Some(ExtractionResult::SkipTo { key, fallback_key }) => {
+				assert_eq!(key, "a/b0".to_string());
+				assert_eq!(fallback_key, Some("a/b/c".to_string()));
+			}
 			_ => panic!("wrong result"),
 		}
 		assert_eq!(acc.common_prefixes.len(), 1);
@@ -1239,6 +1463,7 @@ mod tests {
 			deleted: false.into(),
 			blocks: crdt::Map::<VersionBlockKey, VersionBlock>::from_iter(blocks),
 			parts_etags: crdt::Map::<u64, String>::from_iter(etags),
+			parts_checksums: crdt::Map::new(),
 		}
 	}
 
@@ -1343,4 +1568,159 @@ mod tests {
 
 		Ok(())
 	}
+
+	fn objdata_version(timestamp: u64) -> ObjectVersion {
+		ObjectVersion {
+			uuid: gen_uuid(),
+			timestamp,
+			state: ObjectVersionState::Complete(ObjectVersionData::Inline(
+				ObjectVersionMeta {
+					headers: ObjectVersionHeaders {
+						content_type: "text/plain".to_string(),
+						other: BTreeMap::<String, String>::new(),
+					},
+					size: 0,
+					etag: "etag".to_string(),
+					checksum: None,
+				},
+				vec![],
+			)),
+			versioned: false,
+			lock: ObjectVersionLock::default(),
+			tags: Default::default(),
+			storage_class: Default::default(),
+			restore: Default::default(),
+		}
+	}
+
+	/// Small deterministic random generator (xorshift), so that failures
+	/// of the randomized test below can be reproduced
+	struct TestRng(u64);
+
+	impl TestRng {
+		fn below(&mut self, n: usize) -> usize {
+			self.0 ^= self.0 << 13;
+			self.0 ^= self.0 >> 7;
+			self.0 ^= self.0 << 17;
+			(self.0 % n as u64) as usize
+		}
+
+		fn key(&mut self) -> String {
+			let len = 1 + self.below(5);
+			(0..len).map(|_| ["a", "b", "/"][self.below(3)]).collect()
+		}
+	}
+
+	#[tokio::test]
+	async fn test_list_objects_pagination_with_concurrent_writes() -> Result<(), Error> {
+		let mut rng = TestRng(0x2545f4914f6cdd1d);
+
+		for _ in 0..500 {
+			let mut store = BTreeMap::<String, Object>::new();
+			for _ in 0..rng.below(40) {
+				let key = rng.key();
+				store.insert(
+					key.clone(),
+					Object::new(bucket(), key, vec![objdata_version(TS)]),
+				);
+			}
+			let initial_keys = store.keys().cloned().collect::<Vec<_>>();
+			let mut deleted = BTreeSet::new();
+
+			let mut query = ListObjectsQuery {
+				is_v2: true,
+				marker: None,
+				continuation_token: None,
+				start_after: None,
+				common: ListQueryCommon {
+					bucket_name: "a".to_string(),
+					bucket_id: bucket(),
+					delimiter: [None, Some("/".to_string())][rng.below(2)].clone(),
+					page_size: 1 + rng.below(4),
+					prefix: ["", "a", "a/", "b/"][rng.below(4)].to_string(),
+					urlencode_resp: false,
+				},
+			};
+
+			let mut keys = BTreeSet::new();
+			let mut prefixes = BTreeSet::new();
+			for _ in 0..1000 {
+				let mut acc = query.build_accumulator();
+				let io = |_, start: Option<String>, count: usize| {
+					let objects = store
+						.range(start.unwrap_or_default()..)
+						.take(count)
+						.map(|(_, o)| o.clone())
+						.collect::<Vec<_>>();
+					async move { Ok(objects) }
+				};
+				let pagination =
+					fetch_list_entries(&query.common, query.begin()?, &mut acc, io).await?;
+
+				let last_listed = std::cmp::max(
+					acc.keys.keys().next_back().cloned(),
+					acc.common_prefixes.iter().next_back().cloned(),
+				)
+				.unwrap_or_default();
+
+				// Objects and common prefixes are never returned twice
+				for key in acc.keys.into_keys() {
+					assert!(key.starts_with(&query.common.prefix));
+					assert!(
+						keys.insert(key.clone()),
+						"{} listed twice, {:?}",
+						key,
+						query
+					);
+				}
+				for pfx in acc.common_prefixes {
+					assert!(
+						prefixes.insert(pfx.clone()),
+						"{} listed twice, {:?}",
+						pfx,
+						query
+					);
+				}
+
+				let begin = match pagination {
+					Some(begin) => begin,
+					None => break,
+				};
+				query.continuation_token = continuation_token(&begin);
+
+				// Objects are created and deleted between two requests,
+				// in particular just after the end of the page
+				for _ in 0..rng.below(3) {
+					let key = match rng.below(2) {
+						0 => format!("{}{}", last_listed, rng.key()),
+						_ => rng.key(),
+					};
+					store.insert(
+						key.clone(),
+						Object::new(bucket(), key, vec![objdata_version(TS)]),
+					);
+				}
+				if !store.is_empty() && rng.below(2) == 0 {
+					let key = store.keys().nth(rng.below(store.len())).unwrap().clone();
+					store.remove(&key);
+					deleted.insert(key);
+				}
+			}
+
+			// All objects that existed during the whole listing are returned,
+			// either by themselves or in a common prefix
+			for key in initial_keys.iter().filter(|k| !deleted.contains(*k)) {
+				if !key.starts_with(&query.common.prefix) {
+					continue;
+				}
+				let object = Object::new(bucket(), key.clone(), vec![]);
+				match common_prefix(&object, &query.common) {
+					Some(pfx) => assert!(prefixes.contains(pfx), "{} missing, {:?}", pfx, query),
+					None => assert!(keys.contains(key), "{} missing, {:?}", key, query),
+				}
+			}
+		}
+
+		Ok(())
+	}
 }
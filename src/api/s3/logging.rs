@@ -0,0 +1,208 @@
+use quick_xml::de::from_reader;
+use std::sync::Arc;
+
+use hyper::{Body, Request, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::s3::error::*;
+use crate::s3::xml::{to_xml_with_header, xmlns_tag, Value};
+use crate::signature::verify_signed_content;
+
+use garage_model::bucket_table::*;
+use garage_model::garage::Garage;
+use garage_model::key_table::Key;
+use garage_model::permission::S3Op;
+use garage_util::data::*;
+
+pub async fn handle_get_logging(bucket: &Bucket) -> Result<Response<Body>, Error> {
+	let param = bucket
+		.params()
+		.ok_or_internal_error("Bucket should not be deleted at this point")?;
+
+	let status = BucketLoggingStatus {
+		xmlns: (),
+		logging_enabled: param
+			.logging_config
+			.get()
+			.as_ref()
+			.map(LoggingEnabled::from_garage_logging_config),
+	};
+	let xml = to_xml_with_header(&status)?;
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.header(http::header::CONTENT_TYPE, "application/xml")
+		.body(Body::from(xml))?)
+}
+
+pub async fn handle_delete_logging(
+	garage: Arc<Garage>,
+	bucket_id: Uuid,
+) -> Result<Response<Body>, Error> {
+	set_logging_config(&garage, bucket_id, None).await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::NO_CONTENT)
+		.body(Body::empty())?)
+}
+
+pub async fn handle_put_logging(
+	garage: Arc<Garage>,
+	api_key: &Key,
+	bucket_id: Uuid,
+	req: Request<Body>,
+	content_sha256: Option<Hash>,
+) -> Result<Response<Body>, Error> {
+	let body = hyper::body::to_bytes(req.into_body()).await?;
+
+	if let Some(content_sha256) = content_sha256 {
+		verify_signed_content(content_sha256, &body[..])?;
+	}
+
+	let status: BucketLoggingStatus = from_reader(&body as &[u8])?;
+
+	// A BucketLoggingStatus without a LoggingEnabled element disables logging
+	let config = match status.logging_enabled {
+		Some(enabled) => {
+			let config = enabled.into_garage_logging_config();
+			check_target_bucket(&garage, api_key, &config).await?;
+			Some(config)
+		}
+		None => None,
+	};
+
+	set_logging_config(&garage, bucket_id, config).await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.body(Body::empty())?)
+}
+
+/// Logs are written in the target bucket without any permission check,
+/// so only keys that can write to it are allowed to send logs there
+async fn check_target_bucket(
+	garage: &Garage,
+	api_key: &Key,
+	config: &LoggingConfig,
+) -> Result<(), Error> {
+	let target_id = garage
+		.bucket_helper()
+		.resolve_global_bucket_name(&config.target_bucket)
+		.await?
+		.ok_or_else(|| {
+			Error::bad_request(format!(
+				"Target bucket for logging does not exist: {}",
+				config.target_bucket
+			))
+		})?;
+	if !api_key.allow_op(&target_id, S3Op::PutObject) {
+		return Err(Error::forbidden(
+			"The key must be allowed to write to the target bucket for logging",
+		));
+	}
+	Ok(())
+}
+
+async fn set_logging_config(
+	garage: &Garage,
+	bucket_id: Uuid,
+	config: Option<LoggingConfig>,
+) -> Result<(), Error> {
+	let mut bucket = garage
+		.bucket_helper()
+		.get_existing_bucket(bucket_id)
+		.await?;
+
+	let param = bucket.params_mut().unwrap();
+
+	param.logging_config.update(config);
+	garage.bucket_table.insert(&bucket).await?;
+
+	Ok(())
+}
+
+// ---- SERIALIZATION AND DESERIALIZATION TO/FROM S3 XML ----
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BucketLoggingStatus {
+	#[serde(serialize_with = "xmlns_tag", skip_deserializing)]
+	pub xmlns: (),
+	#[serde(rename = "LoggingEnabled", skip_serializing_if = "Option::is_none")]
+	pub logging_enabled: Option<LoggingEnabled>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct LoggingEnabled {
+	#[serde(rename = "TargetBucket")]
+	pub target_bucket: Value,
+	#[serde(rename = "TargetPrefix", default)]
+	pub target_prefix: Option<Value>,
+}
+
+impl LoggingEnabled {
+	pub fn into_garage_logging_config(self) -> LoggingConfig {
+		LoggingConfig {
+			target_bucket: self.target_bucket.0,
+			target_prefix: self.target_prefix.map(|p| p.0).unwrap_or_default(),
+		}
+	}
+
+	pub fn from_garage_logging_config(config: &LoggingConfig) -> Self {
+		LoggingEnabled {
+			target_bucket: Value(config.target_bucket.clone()),
+			target_prefix: Some(Value(config.target_prefix.clone())),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use quick_xml::de::from_str;
+
+	#[test]
+	fn test_deserialize_logging_status() -> Result<(), Error> {
+		let message = r#"<?xml version="1.0" encoding="UTF-8"?>
+<BucketLoggingStatus xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <LoggingEnabled>
+    <TargetBucket>logs</TargetBucket>
+    <TargetPrefix>access/</TargetPrefix>
+  </LoggingEnabled>
+</BucketLoggingStatus>"#;
+		let status = from_str::<BucketLoggingStatus>(message)?;
+		let config = status.logging_enabled.unwrap().into_garage_logging_config();
+		assert_eq!(
+			config,
+			LoggingConfig {
+				target_bucket: "logs".into(),
+				target_prefix: "access/".into(),
+			}
+		);
+
+		let status2 = BucketLoggingStatus {
+			xmlns: (),
+			logging_enabled: Some(LoggingEnabled::from_garage_logging_config(&config)),
+		};
+		let message2 = to_xml_with_header(&status2)?;
+		let cleanup = |c: &str| c.replace(char::is_whitespace, "");
+		assert_eq!(cleanup(message), cleanup(&message2));
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_deserialize_logging_disabled() -> Result<(), Error> {
+		let message = r#"<?xml version="1.0" encoding="UTF-8"?>
+<BucketLoggingStatus xmlns="http://s3.amazonaws.com/doc/2006-03-01/" />"#;
+		let status = from_str::<BucketLoggingStatus>(message)?;
+		assert_eq!(status.logging_enabled, None);
+
+		let message2 = to_xml_with_header(&status)?;
+		assert_eq!(
+			from_str::<BucketLoggingStatus>(&message2)?.logging_enabled,
+			None
+		);
+
+		Ok(())
+	}
+}
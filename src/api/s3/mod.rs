@@ -1,14 +1,26 @@
 pub mod api_server;
 pub mod error;
 
+mod access_log;
 mod bucket;
 mod copy;
 pub mod cors;
-mod delete;
+pub mod delete;
+pub mod encryption;
 pub mod get;
+pub mod json_access_log;
+mod lifecycle;
 mod list;
+mod logging;
+mod notification;
+mod object_lock;
+mod policy;
 mod post_object;
-mod put;
+pub mod put;
+mod restore;
+mod select;
+mod select_sql;
+mod tagging;
 mod website;
 
 mod router;
@@ -0,0 +1,377 @@
+use quick_xml::de::from_reader;
+use std::sync::Arc;
+
+use hyper::{Body, Request, Response, StatusCode};
+use serde::de::IgnoredAny;
+use serde::{Deserialize, Serialize};
+
+use crate::s3::error::*;
+use crate::s3::xml::{to_xml_with_header, xmlns_tag, Value};
+use crate::signature::verify_signed_content;
+
+use garage_model::bucket_table::{
+	Bucket, NotificationConfig as GarageNotificationConfig, NotificationEvent,
+};
+use garage_model::garage::Garage;
+use garage_model::s3::notification::NatsTarget;
+use garage_util::data::*;
+
+/// Maximum number of configurations in the notification configuration of a bucket
+const MAX_NOTIFICATION_CONFIGS: usize = 100;
+
+pub async fn handle_get_notification(bucket: &Bucket) -> Result<Response<Body>, Error> {
+	let param = bucket
+		.params()
+		.ok_or_internal_error("Bucket should not be deleted at this point")?;
+
+	// A bucket without notifications has an empty notification configuration
+	let conf = NotificationConfiguration::from_garage_notification_config(
+		param
+			.notification_config
+			.get()
+			.as_deref()
+			.unwrap_or_default(),
+	);
+	let xml = to_xml_with_header(&conf)?;
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.header(http::header::CONTENT_TYPE, "application/xml")
+		.body(Body::from(xml))?)
+}
+
+pub async fn handle_put_notification(
+	garage: Arc<Garage>,
+	bucket_id: Uuid,
+	req: Request<Body>,
+	content_sha256: Option<Hash>,
+) -> Result<Response<Body>, Error> {
+	let body = hyper::body::to_bytes(req.into_body()).await?;
+
+	if let Some(content_sha256) = content_sha256 {
+		verify_signed_content(content_sha256, &body[..])?;
+	}
+
+	let conf: NotificationConfiguration = from_reader(&body as &[u8])?;
+	let config = conf.validate_into_garage_notification_config()?;
+
+	let mut bucket = garage
+		.bucket_helper()
+		.get_existing_bucket(bucket_id)
+		.await?;
+	let param = bucket.params_mut().unwrap();
+
+	// An empty notification configuration disables notifications
+	param
+		.notification_config
+		.update(Some(config).filter(|c| !c.is_empty()));
+	garage.bucket_table.insert(&bucket).await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.body(Body::empty())?)
+}
+
+// ---- SERIALIZATION AND DESERIALIZATION TO/FROM S3 XML ----
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct NotificationConfiguration {
+	#[serde(serialize_with = "xmlns_tag", skip_deserializing)]
+	pub xmlns: (),
+	/// Notifications sent to a webhook, whose URL is given
+	/// instead of the ARN of an Amazon SNS topic
+	#[serde(rename = "TopicConfiguration", default)]
+	pub topic_configurations: Vec<TopicConfiguration>,
+
+	// Destinations that are not supported by Garage
+	#[serde(rename = "QueueConfiguration", default, skip_serializing)]
+	pub queue_configurations: Vec<IgnoredAny>,
+	#[serde(rename = "CloudFunctionConfiguration", default, skip_serializing)]
+	pub cloud_function_configurations: Vec<IgnoredAny>,
+	#[serde(rename = "EventBridgeConfiguration", default, skip_serializing)]
+	pub event_bridge_configuration: Option<IgnoredAny>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct TopicConfiguration {
+	#[serde(rename = "Id", default)]
+	pub id: Option<Value>,
+	#[serde(rename = "Topic")]
+	pub topic: Value,
+	#[serde(rename = "Event")]
+	pub events: Vec<Value>,
+	#[serde(rename = "Filter", default, skip_serializing_if = "Option::is_none")]
+	pub filter: Option<Filter>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct Filter {
+	#[serde(rename = "S3Key")]
+	pub s3_key: S3KeyFilter,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct S3KeyFilter {
+	#[serde(rename = "FilterRule", default)]
+	pub filter_rules: Vec<FilterRule>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct FilterRule {
+	#[serde(rename = "Name")]
+	pub name: Value,
+	#[serde(rename = "Value")]
+	pub value: Value,
+}
+
+impl NotificationConfiguration {
+	pub fn validate_into_garage_notification_config(
+		self,
+	) -> Result<Vec<GarageNotificationConfig>, Error> {
+		if !self.queue_configurations.is_empty()
+			|| !self.cloud_function_configurations.is_empty()
+			|| self.event_bridge_configuration.is_some()
+		{
+			return Err(Error::NotImplemented(
+				"Garage only supports notifications sent to webhooks or NATS, with TopicConfiguration"
+					.into(),
+			));
+		}
+		if self.topic_configurations.len() > MAX_NOTIFICATION_CONFIGS {
+			return Err(Error::bad_request(format!(
+				"A bucket can have at most {} notification configurations",
+				MAX_NOTIFICATION_CONFIGS
+			)));
+		}
+
+		let mut ret: Vec<GarageNotificationConfig> = vec![];
+		for conf in self.topic_configurations {
+			let conf = conf.validate_into_garage_notification_config()?;
+			if ret.iter().any(|c| c.id == conf.id) {
+				return Err(Error::bad_request(format!(
+					"Duplicate notification configuration ID: {}",
+					conf.id
+				)));
+			}
+			ret.push(conf);
+		}
+		Ok(ret)
+	}
+
+	pub fn from_garage_notification_config(config: &[GarageNotificationConfig]) -> Self {
+		Self {
+			xmlns: (),
+			topic_configurations: config
+				.iter()
+				.map(TopicConfiguration::from_garage_notification_config)
+				.collect(),
+			queue_configurations: vec![],
+			cloud_function_configurations: vec![],
+			event_bridge_configuration: None,
+		}
+	}
+}
+
+impl TopicConfiguration {
+	pub fn validate_into_garage_notification_config(
+		self,
+	) -> Result<GarageNotificationConfig, Error> {
+		let id = match self.id {
+			Some(id) if id.0.len() > 255 => {
+				return Err(Error::bad_request(
+					"Notification configuration ID cannot be longer than 255 characters",
+				))
+			}
+			Some(id) => id.0,
+			None => hex::encode(&gen_uuid().as_slice()[..8]),
+		};
+
+		let endpoint_url = self.topic.0;
+		let uri = endpoint_url
+			.parse::<http::Uri>()
+			.ok_or_bad_request("Invalid URL for the notification topic")?;
+		let valid = match uri.scheme_str() {
+			Some("http") | Some("https") => uri.host().is_some(),
+			Some("nats") => NatsTarget::parse(&endpoint_url).is_some(),
+			Some("kafka") => {
+				return Err(Error::NotImplemented(
+					"Garage does not support publishing notifications to Kafka topics".into(),
+				))
+			}
+			_ => false,
+		};
+		if !valid {
+			return Err(Error::bad_request(format!(
+				"The topic of a notification configuration must be the http:// or https:// URL of a webhook, or a nats://host[:port]/subject URL, not {}",
+				endpoint_url
+			)));
+		}
+
+		if self.events.is_empty() {
+			return Err(Error::bad_request(
+				"A notification configuration must have at least one event",
+			));
+		}
+		let events = self
+			.events
+			.iter()
+			.map(|e| {
+				NotificationEvent::parse(&e.0).ok_or_else(|| {
+					Error::bad_request(format!("Unsupported notification event: {}", e.0))
+				})
+			})
+			.collect::<Result<Vec<_>, _>>()?;
+
+		let mut filter_prefix = None;
+		for rule in self
+			.filter
+			.map(|f| f.s3_key.filter_rules)
+			.unwrap_or_default()
+		{
+			match rule.name.0.to_lowercase().as_str() {
+				"prefix" if filter_prefix.is_none() => filter_prefix = Some(rule.value.0),
+				"suffix" => {
+					return Err(Error::NotImplemented(
+						"Garage only supports prefix filter rules in notification configurations"
+							.into(),
+					))
+				}
+				_ => {
+					return Err(Error::bad_request(format!(
+						"Invalid filter rule: {}",
+						rule.name.0
+					)))
+				}
+			}
+		}
+
+		Ok(GarageNotificationConfig {
+			id,
+			events,
+			endpoint_url,
+			filter_prefix,
+		})
+	}
+
+	pub fn from_garage_notification_config(config: &GarageNotificationConfig) -> Self {
+		Self {
+			id: Some(Value(config.id.clone())),
+			topic: Value(config.endpoint_url.clone()),
+			events: config
+				.events
+				.iter()
+				.map(|e| Value(format!("s3:{}", e.name())))
+				.collect(),
+			filter: config.filter_prefix.as_ref().map(|p| Filter {
+				s3_key: S3KeyFilter {
+					filter_rules: vec![FilterRule {
+						name: Value("prefix".into()),
+						value: Value(p.clone()),
+					}],
+				},
+			}),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use quick_xml::de::from_str;
+
+	#[test]
+	fn test_deserialize_notification_configuration() -> Result<(), Error> {
+		let message = r#"<?xml version="1.0" encoding="UTF-8"?>
+<NotificationConfiguration xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <TopicConfiguration>
+    <Id>new-images</Id>
+    <Topic>https://hooks.example.com/garage</Topic>
+    <Event>s3:ObjectCreated:*</Event>
+    <Event>s3:ObjectRemoved:Delete</Event>
+    <Filter>
+      <S3Key>
+        <FilterRule>
+          <Name>prefix</Name>
+          <Value>images/</Value>
+        </FilterRule>
+      </S3Key>
+    </Filter>
+  </TopicConfiguration>
+</NotificationConfiguration>"#;
+		let conf = from_str::<NotificationConfiguration>(message)?;
+		let config = conf.validate_into_garage_notification_config()?;
+		assert_eq!(
+			config,
+			vec![GarageNotificationConfig {
+				id: "new-images".into(),
+				events: vec![
+					NotificationEvent::ObjectCreatedAll,
+					NotificationEvent::ObjectRemovedDelete
+				],
+				endpoint_url: "https://hooks.example.com/garage".into(),
+				filter_prefix: Some("images/".into()),
+			}]
+		);
+		assert!(config[0].applies_to("images/a.png", NotificationEvent::ObjectCreatedPut));
+		assert!(!config[0].applies_to("docs/a.txt", NotificationEvent::ObjectCreatedPut));
+		assert!(!config[0].applies_to(
+			"images/a.png",
+			NotificationEvent::ObjectRemovedDeleteMarkerCreated
+		));
+
+		let conf2 = NotificationConfiguration::from_garage_notification_config(&config);
+		let message2 = to_xml_with_header(&conf2)?;
+		let cleanup = |c: &str| c.replace(char::is_whitespace, "");
+		assert_eq!(cleanup(message), cleanup(&message2));
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_invalid_notification_configuration() -> Result<(), Error> {
+		let conf = |topic: &str, event: &str| {
+			format!(
+				"<NotificationConfiguration><TopicConfiguration><Topic>{}</Topic><Event>{}</Event></TopicConfiguration></NotificationConfiguration>",
+				topic, event
+			)
+		};
+		let validate = |xml: String| {
+			from_str::<NotificationConfiguration>(&xml)
+				.unwrap()
+				.validate_into_garage_notification_config()
+		};
+
+		let config = validate(conf("http://localhost:8080/", "s3:ObjectCreated:Put"))?;
+		assert_eq!(config[0].events, vec![NotificationEvent::ObjectCreatedPut]);
+		assert!(!config[0].id.is_empty());
+
+		assert!(validate(conf(
+			"arn:aws:sns:us-east-1:123:topic",
+			"s3:ObjectCreated:Put"
+		))
+		.is_err());
+		let config = validate(conf(
+			"nats://localhost:4222/garage.events",
+			"s3:ObjectRemoved:*",
+		))?;
+		assert_eq!(
+			config[0].endpoint_url,
+			"nats://localhost:4222/garage.events"
+		);
+		assert!(validate(conf("nats://localhost:4222/", "s3:ObjectRemoved:*")).is_err());
+		assert!(matches!(
+			validate(conf("kafka://localhost:9092/events", "s3:ObjectRemoved:*")),
+			Err(Error::NotImplemented(_))
+		));
+		assert!(validate(conf("http://localhost:8080/", "s3:ObjectCreated:Copy")).is_err());
+		assert!(validate(conf("http://localhost:8080/", "ObjectCreated:Put")).is_err());
+
+		let queue = "<NotificationConfiguration><QueueConfiguration><Queue>arn</Queue><Event>s3:ObjectCreated:*</Event></QueueConfiguration></NotificationConfiguration>";
+		assert!(validate(queue.into()).is_err());
+
+		let empty = "<NotificationConfiguration></NotificationConfiguration>";
+		assert_eq!(validate(empty.into())?, vec![]);
+
+		Ok(())
+	}
+}
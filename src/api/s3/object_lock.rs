@@ -0,0 +1,614 @@
+use quick_xml::de::from_reader;
+use std::convert::TryInto;
+use std::sync::Arc;
+
+use chrono::DateTime;
+use hyper::header::{HeaderMap, HeaderValue};
+use hyper::{Body, Request, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::s3::error::*;
+use crate::s3::xml::{to_xml_with_header, xmlns_tag, IntValue, Value};
+use crate::signature::verify_signed_content;
+
+use garage_model::bucket_table::{
+	Bucket, DefaultRetention as GarageDefaultRetention, ObjectLockConfig, VersioningState,
+};
+use garage_model::garage::Garage;
+use garage_model::key_table::Key;
+use garage_model::s3::object_table::*;
+use garage_util::data::*;
+use garage_util::time::*;
+
+const LOCK_MODE_HEADER: &str = "x-amz-object-lock-mode";
+const RETAIN_UNTIL_HEADER: &str = "x-amz-object-lock-retain-until-date";
+const LEGAL_HOLD_HEADER: &str = "x-amz-object-lock-legal-hold";
+const BYPASS_GOVERNANCE_HEADER: &str = "x-amz-bypass-governance-retention";
+pub(crate) const BUCKET_OBJECT_LOCK_HEADER: &str = "x-amz-bucket-object-lock-enabled";
+
+const MSEC_PER_DAY: u64 = 24 * 3600 * 1000;
+
+// ---- BUCKET OBJECT LOCK CONFIGURATION ----
+
+pub async fn handle_get_object_lock_config(bucket: &Bucket) -> Result<Response<Body>, Error> {
+	let param = bucket
+		.params()
+		.ok_or_internal_error("Bucket should not be deleted at this point")?;
+
+	if let Some(config) = param.object_lock_config.get() {
+		let conf = ObjectLockConfiguration::from_garage_object_lock_config(config);
+		let xml = to_xml_with_header(&conf)?;
+		Ok(Response::builder()
+			.status(StatusCode::OK)
+			.header(http::header::CONTENT_TYPE, "application/xml")
+			.body(Body::from(xml))?)
+	} else {
+		Err(Error::ObjectLockConfigurationNotFound)
+	}
+}
+
+pub async fn handle_put_object_lock_config(
+	garage: Arc<Garage>,
+	bucket_id: Uuid,
+	req: Request<Body>,
+	content_sha256: Option<Hash>,
+) -> Result<Response<Body>, Error> {
+	let body = hyper::body::to_bytes(req.into_body()).await?;
+
+	if let Some(content_sha256) = content_sha256 {
+		verify_signed_content(content_sha256, &body[..])?;
+	}
+
+	let mut bucket = garage
+		.bucket_helper()
+		.get_existing_bucket(bucket_id)
+		.await?;
+
+	let param = bucket.params_mut().unwrap();
+
+	if *param.versioning_state.get() != VersioningState::Enabled {
+		return Err(Error::InvalidBucketState(
+			"Versioning must be enabled on the bucket to apply an object lock configuration".into(),
+		));
+	}
+
+	let conf: ObjectLockConfiguration = from_reader(&body as &[u8])?;
+	let config = conf.validate_into_garage_object_lock_config()?;
+
+	param.object_lock_config.update(Some(config));
+	garage.bucket_table.insert(&bucket).await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.body(Body::empty())?)
+}
+
+// ---- OBJECT RETENTION AND LEGAL HOLD ----
+
+pub async fn handle_get_object_retention(
+	garage: Arc<Garage>,
+	bucket_id: Uuid,
+	key: &str,
+	version_id: Option<&str>,
+) -> Result<Response<Body>, Error> {
+	let version = get_object_version(&garage, bucket_id, key, version_id).await?;
+	let retention = version
+		.lock
+		.retention
+		.get()
+		.ok_or(Error::NoSuchObjectLockConfiguration)?;
+
+	let xml = to_xml_with_header(&Retention {
+		xmlns: (),
+		mode: Some(Value(lock_mode_str(retention.mode).into())),
+		retain_until_date: Some(Value(msec_to_rfc3339(retention.retain_until))),
+	})?;
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.header(http::header::CONTENT_TYPE, "application/xml")
+		.body(Body::from(xml))?)
+}
+
+pub async fn handle_put_object_retention(
+	garage: Arc<Garage>,
+	bucket: &Bucket,
+	api_key: &Key,
+	key: &str,
+	version_id: Option<&str>,
+	req: Request<Body>,
+	content_sha256: Option<Hash>,
+) -> Result<Response<Body>, Error> {
+	check_object_lock_enabled(bucket)?;
+	let bypass_governance = bypass_governance_retention(req.headers(), api_key, &bucket.id);
+
+	let body = hyper::body::to_bytes(req.into_body()).await?;
+
+	if let Some(content_sha256) = content_sha256 {
+		verify_signed_content(content_sha256, &body[..])?;
+	}
+
+	let retention: Retention = from_reader(&body as &[u8])?;
+	let new_retention = retention.validate_into_garage_retention()?;
+
+	let mut version = get_object_version(&garage, bucket.id, key, version_id).await?;
+	check_retention_change(
+		version.lock.active_retention(now_msec()),
+		new_retention,
+		bypass_governance,
+	)?;
+
+	version.lock.retention.update(new_retention);
+	let object = Object::new(bucket.id, key.into(), vec![version]);
+	garage.object_table.insert(&object).await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.body(Body::empty())?)
+}
+
+pub async fn handle_get_object_legal_hold(
+	garage: Arc<Garage>,
+	bucket_id: Uuid,
+	key: &str,
+	version_id: Option<&str>,
+) -> Result<Response<Body>, Error> {
+	let version = get_object_version(&garage, bucket_id, key, version_id).await?;
+
+	let xml = to_xml_with_header(&LegalHold {
+		xmlns: (),
+		status: Value(legal_hold_str(*version.lock.legal_hold.get()).into()),
+	})?;
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.header(http::header::CONTENT_TYPE, "application/xml")
+		.body(Body::from(xml))?)
+}
+
+pub async fn handle_put_object_legal_hold(
+	garage: Arc<Garage>,
+	bucket: &Bucket,
+	key: &str,
+	version_id: Option<&str>,
+	req: Request<Body>,
+	content_sha256: Option<Hash>,
+) -> Result<Response<Body>, Error> {
+	check_object_lock_enabled(bucket)?;
+
+	let body = hyper::body::to_bytes(req.into_body()).await?;
+
+	if let Some(content_sha256) = content_sha256 {
+		verify_signed_content(content_sha256, &body[..])?;
+	}
+
+	let legal_hold: LegalHold = from_reader(&body as &[u8])?;
+	let legal_hold = parse_legal_hold_status(&legal_hold.status.0)?;
+
+	let mut version = get_object_version(&garage, bucket.id, key, version_id).await?;
+	version.lock.legal_hold.update(legal_hold);
+	let object = Object::new(bucket.id, key.into(), vec![version]);
+	garage.object_table.insert(&object).await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.body(Body::empty())?)
+}
+
+/// Get the version of an object on which object lock settings are read or applied:
+/// the given version, or the current version of the object
+async fn get_object_version(
+	garage: &Garage,
+	bucket_id: Uuid,
+	key: &str,
+	version_id: Option<&str>,
+) -> Result<ObjectVersion, Error> {
+	let object = garage
+		.object_table
+		.get(&bucket_id, &key.to_string())
+		.await?
+		.ok_or(Error::NoSuchKey)?;
+
+	let version = match version_id {
+		Some(version_id) => object
+			.find_version(version_id)
+			.ok_or(Error::NoSuchVersion)?,
+		None => object.current_data_version().ok_or(Error::NoSuchKey)?,
+	};
+	if !version.is_data() {
+		return Err(Error::bad_request(
+			"Object lock settings cannot be applied to delete markers",
+		));
+	}
+	if !version.versioned {
+		return Err(Error::bad_request(
+			"Object lock settings can only be applied to versions created while versioning was enabled",
+		));
+	}
+	Ok(version.clone())
+}
+
+fn check_object_lock_enabled(bucket: &Bucket) -> Result<(), Error> {
+	if bucket.object_lock_enabled() {
+		Ok(())
+	} else {
+		Err(Error::bad_request(
+			"Bucket is missing an object lock configuration",
+		))
+	}
+}
+
+/// Check that the retention of an object version can be changed from `current`
+/// (if it has not expired) to `new`. Retention can always be extended, but it can
+/// only be shortened or removed in governance mode, when bypassing it is allowed.
+fn check_retention_change(
+	current: Option<ObjectRetention>,
+	new: Option<ObjectRetention>,
+	bypass_governance: bool,
+) -> Result<(), Error> {
+	let current = match current {
+		Some(c) => c,
+		None => return Ok(()),
+	};
+	let extended = match new {
+		Some(n) => {
+			n.retain_until >= current.retain_until
+				&& (n.mode == current.mode || n.mode == ObjectLockMode::Compliance)
+		}
+		None => false,
+	};
+
+	match current.mode {
+		_ if extended => Ok(()),
+		ObjectLockMode::Governance if bypass_governance => Ok(()),
+		ObjectLockMode::Governance => Err(Error::forbidden(format!(
+			"Governance-mode retention can only be shortened or removed with the {} header, by keys allowed to bypass it",
+			BYPASS_GOVERNANCE_HEADER
+		))),
+		ObjectLockMode::Compliance => Err(Error::forbidden(
+			"Compliance-mode retention can only be extended",
+		)),
+	}
+}
+
+// ---- OBJECT LOCK OF OBJECT VERSIONS ----
+
+/// Get the object lock protection of a new object version, from the request headers
+/// or from the default retention of the bucket
+pub(crate) fn get_object_lock(
+	bucket: &Bucket,
+	headers: &HeaderMap<HeaderValue>,
+) -> Result<ObjectVersionLock, Error> {
+	let mode = match headers.get(LOCK_MODE_HEADER) {
+		Some(v) => Some(parse_lock_mode(v.to_str()?)?),
+		None => None,
+	};
+	let retain_until = match headers.get(RETAIN_UNTIL_HEADER) {
+		Some(v) => Some(parse_date(v.to_str()?)?),
+		None => None,
+	};
+	let legal_hold = match headers.get(LEGAL_HOLD_HEADER) {
+		Some(v) => Some(parse_legal_hold_status(v.to_str()?)?),
+		None => None,
+	};
+
+	let retention = match (mode, retain_until) {
+		(Some(mode), Some(retain_until)) => Some(ObjectRetention { mode, retain_until }),
+		(None, None) => None,
+		_ => {
+			return Err(Error::bad_request(format!(
+				"{} and {} must be given together",
+				LOCK_MODE_HEADER, RETAIN_UNTIL_HEADER
+			)))
+		}
+	};
+
+	let config = match bucket
+		.params()
+		.and_then(|p| p.object_lock_config.get().as_ref())
+	{
+		Some(config) => config,
+		None if retention.is_some() || legal_hold.is_some() => {
+			return Err(Error::bad_request(
+				"Bucket is missing an object lock configuration",
+			))
+		}
+		None => return Ok(ObjectVersionLock::default()),
+	};
+
+	let now = now_msec();
+	let retention = match retention {
+		Some(r) if r.retain_until <= now => {
+			return Err(Error::bad_request(
+				"The retain until date must be in the future",
+			))
+		}
+		Some(r) => Some(r),
+		None => config.default_retention.as_ref().map(|d| ObjectRetention {
+			mode: d.mode,
+			retain_until: now + d.days * MSEC_PER_DAY,
+		}),
+	};
+
+	Ok(ObjectVersionLock::new(
+		retention,
+		legal_hold.unwrap_or(false),
+	))
+}
+
+/// Add the headers describing the object lock protection of a version to a response
+pub(crate) fn add_object_lock_headers(
+	mut resp: http::response::Builder,
+	version: &ObjectVersion,
+) -> http::response::Builder {
+	if let Some(retention) = version.lock.retention.get() {
+		resp = resp
+			.header(LOCK_MODE_HEADER, lock_mode_str(retention.mode))
+			.header(RETAIN_UNTIL_HEADER, msec_to_rfc3339(retention.retain_until));
+	}
+	if *version.lock.legal_hold.get() {
+		resp = resp.header(LEGAL_HOLD_HEADER, legal_hold_str(true));
+	}
+	resp
+}
+
+/// Check whether a request asks to bypass governance-mode retention,
+/// and the key it was signed with is allowed to do so
+pub(crate) fn bypass_governance_retention(
+	headers: &HeaderMap<HeaderValue>,
+	api_key: &Key,
+	bucket_id: &Uuid,
+) -> bool {
+	headers
+		.get(BYPASS_GOVERNANCE_HEADER)
+		.map(|v| v.as_bytes().eq_ignore_ascii_case(b"true"))
+		.unwrap_or(false)
+		&& api_key.allow_bypass_governance(bucket_id)
+}
+
+fn lock_mode_str(mode: ObjectLockMode) -> &'static str {
+	match mode {
+		ObjectLockMode::Governance => "GOVERNANCE",
+		ObjectLockMode::Compliance => "COMPLIANCE",
+	}
+}
+
+fn parse_lock_mode(mode: &str) -> Result<ObjectLockMode, Error> {
+	match mode {
+		"GOVERNANCE" => Ok(ObjectLockMode::Governance),
+		"COMPLIANCE" => Ok(ObjectLockMode::Compliance),
+		_ => Err(Error::bad_request(format!(
+			"Invalid object lock mode: {}",
+			mode
+		))),
+	}
+}
+
+fn legal_hold_str(legal_hold: bool) -> &'static str {
+	if legal_hold {
+		"ON"
+	} else {
+		"OFF"
+	}
+}
+
+fn parse_legal_hold_status(status: &str) -> Result<bool, Error> {
+	match status {
+		"ON" => Ok(true),
+		"OFF" => Ok(false),
+		_ => Err(Error::bad_request(format!(
+			"Invalid legal hold status: {}",
+			status
+		))),
+	}
+}
+
+fn parse_date(date: &str) -> Result<u64, Error> {
+	DateTime::parse_from_rfc3339(date)
+		.ok()
+		.and_then(|d| d.timestamp_millis().try_into().ok())
+		.ok_or_bad_request(format!("Invalid date: {}", date))
+		.map_err(Error::from)
+}
+
+// ---- SERIALIZATION AND DESERIALIZATION TO/FROM S3 XML ----
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ObjectLockConfiguration {
+	#[serde(serialize_with = "xmlns_tag", skip_deserializing)]
+	pub xmlns: (),
+	#[serde(rename = "ObjectLockEnabled")]
+	pub object_lock_enabled: Option<Value>,
+	#[serde(rename = "Rule", skip_serializing_if = "Option::is_none")]
+	pub rule: Option<ObjectLockRule>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ObjectLockRule {
+	#[serde(rename = "DefaultRetention")]
+	pub default_retention: DefaultRetention,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DefaultRetention {
+	#[serde(rename = "Mode")]
+	pub mode: Value,
+	#[serde(rename = "Days", skip_serializing_if = "Option::is_none")]
+	pub days: Option<IntValue>,
+	#[serde(rename = "Years", skip_serializing_if = "Option::is_none")]
+	pub years: Option<IntValue>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename = "ObjectLockRetention")]
+pub struct Retention {
+	#[serde(serialize_with = "xmlns_tag", skip_deserializing)]
+	pub xmlns: (),
+	#[serde(rename = "Mode", skip_serializing_if = "Option::is_none")]
+	pub mode: Option<Value>,
+	#[serde(rename = "RetainUntilDate", skip_serializing_if = "Option::is_none")]
+	pub retain_until_date: Option<Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename = "ObjectLockLegalHold")]
+pub struct LegalHold {
+	#[serde(serialize_with = "xmlns_tag", skip_deserializing)]
+	pub xmlns: (),
+	#[serde(rename = "Status")]
+	pub status: Value,
+}
+
+impl ObjectLockConfiguration {
+	pub fn validate_into_garage_object_lock_config(self) -> Result<ObjectLockConfig, Error> {
+		if self.object_lock_enabled.as_ref().map(|v| v.0.as_str()) != Some("Enabled") {
+			return Err(Error::bad_request(
+				"Bad XML: ObjectLockEnabled must be Enabled",
+			));
+		}
+
+		let default_retention = match self.rule {
+			Some(rule) => {
+				let retention = rule.default_retention;
+				let days = match (retention.days, retention.years) {
+					(Some(IntValue(days)), None) if days > 0 => days as u64,
+					(None, Some(IntValue(years))) if years > 0 => years as u64 * 365,
+					_ => {
+						return Err(Error::bad_request(
+							"Bad XML: exactly one of Days or Years must be given, with a positive value",
+						))
+					}
+				};
+				Some(GarageDefaultRetention {
+					mode: parse_lock_mode(&retention.mode.0)?,
+					days,
+				})
+			}
+			None => None,
+		};
+
+		Ok(ObjectLockConfig { default_retention })
+	}
+
+	pub fn from_garage_object_lock_config(config: &ObjectLockConfig) -> Self {
+		Self {
+			xmlns: (),
+			object_lock_enabled: Some(Value("Enabled".into())),
+			rule: config.default_retention.as_ref().map(|r| ObjectLockRule {
+				default_retention: DefaultRetention {
+					mode: Value(lock_mode_str(r.mode).into()),
+					days: Some(IntValue(r.days as i64)),
+					years: None,
+				},
+			}),
+		}
+	}
+}
+
+impl Retention {
+	pub fn validate_into_garage_retention(self) -> Result<Option<ObjectRetention>, Error> {
+		match (self.mode, self.retain_until_date) {
+			(Some(mode), Some(date)) => {
+				let retain_until = parse_date(&date.0)?;
+				if retain_until <= now_msec() {
+					return Err(Error::bad_request(
+						"The retain until date must be in the future",
+					));
+				}
+				Ok(Some(ObjectRetention {
+					mode: parse_lock_mode(&mode.0)?,
+					retain_until,
+				}))
+			}
+			(None, None) => Ok(None),
+			_ => Err(Error::bad_request(
+				"Bad XML: Mode and RetainUntilDate must be given together",
+			)),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use quick_xml::de::from_str;
+
+	#[test]
+	fn test_deserialize_object_lock_config() -> Result<(), Error> {
+		let message = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ObjectLockConfiguration xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+   <ObjectLockEnabled>Enabled</ObjectLockEnabled>
+   <Rule>
+      <DefaultRetention>
+         <Mode>GOVERNANCE</Mode>
+         <Years>2</Years>
+      </DefaultRetention>
+   </Rule>
+</ObjectLockConfiguration>"#;
+		let conf: ObjectLockConfiguration = from_str(message).unwrap();
+		let config = conf.validate_into_garage_object_lock_config()?;
+		assert_eq!(
+			config,
+			ObjectLockConfig {
+				default_retention: Some(GarageDefaultRetention {
+					mode: ObjectLockMode::Governance,
+					days: 730,
+				}),
+			}
+		);
+
+		let message2 = to_xml_with_header(
+			&ObjectLockConfiguration::from_garage_object_lock_config(&config),
+		)?;
+		assert!(message2.contains("<Days>730</Days>"));
+
+		let disabled = r#"<ObjectLockConfiguration><ObjectLockEnabled>Disabled</ObjectLockEnabled></ObjectLockConfiguration>"#;
+		let conf: ObjectLockConfiguration = from_str(disabled).unwrap();
+		assert!(conf.validate_into_garage_object_lock_config().is_err());
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_retention_change() {
+		let retention = |mode, retain_until| Some(ObjectRetention { mode, retain_until });
+		let governance = retention(ObjectLockMode::Governance, 1000);
+		let compliance = retention(ObjectLockMode::Compliance, 1000);
+
+		// Without current retention, anything goes
+		assert!(check_retention_change(None, governance, false).is_ok());
+		assert!(check_retention_change(None, None, false).is_ok());
+
+		// Retention can be extended, and governance can become compliance
+		for current in [governance, compliance] {
+			assert!(check_retention_change(current, current, false).is_ok());
+			assert!(check_retention_change(
+				current,
+				retention(ObjectLockMode::Compliance, 2000),
+				false
+			)
+			.is_ok());
+		}
+
+		// Governance retention can only be shortened or removed when bypassing it
+		let shorter = retention(ObjectLockMode::Governance, 500);
+		assert!(check_retention_change(governance, shorter, false).is_err());
+		assert!(check_retention_change(governance, None, false).is_err());
+		assert!(check_retention_change(governance, shorter, true).is_ok());
+		assert!(check_retention_change(governance, None, true).is_ok());
+
+		// Compliance retention can never be shortened, removed or weakened
+		assert!(check_retention_change(compliance, None, true).is_err());
+		assert!(check_retention_change(
+			compliance,
+			retention(ObjectLockMode::Compliance, 500),
+			true
+		)
+		.is_err());
+		assert!(check_retention_change(
+			compliance,
+			retention(ObjectLockMode::Governance, 2000),
+			true
+		)
+		.is_err());
+	}
+}
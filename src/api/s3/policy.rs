@@ -0,0 +1,867 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use hyper::{Body, Request, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::s3::error::*;
+use crate::s3::xml::{to_xml_with_header, xmlns_tag, Value};
+use crate::signature::verify_signed_content;
+
+use garage_model::bucket_table::Bucket;
+use garage_model::garage::Garage;
+use garage_util::data::*;
+
+/// Maximum size of a bucket policy document, as on AWS
+const MAX_POLICY_SIZE: usize = 20 * 1024;
+
+pub async fn handle_get_bucket_policy(bucket: &Bucket) -> Result<Response<Body>, Error> {
+	let param = bucket
+		.params()
+		.ok_or_internal_error("Bucket should not be deleted at this point")?;
+
+	match param.policy.get() {
+		Some(policy) => Ok(Response::builder()
+			.status(StatusCode::OK)
+			.header(http::header::CONTENT_TYPE, "application/json")
+			.body(Body::from(policy.clone()))?),
+		None => Err(Error::NoSuchBucketPolicy),
+	}
+}
+
+pub async fn handle_get_bucket_policy_status(bucket: &Bucket) -> Result<Response<Body>, Error> {
+	let param = bucket
+		.params()
+		.ok_or_internal_error("Bucket should not be deleted at this point")?;
+
+	let policy = param
+		.policy
+		.get()
+		.as_deref()
+		.ok_or(Error::NoSuchBucketPolicy)?;
+	let status = PolicyStatus {
+		xmlns: (),
+		is_public: Value(BucketPolicy::parse(policy)?.is_public().to_string()),
+	};
+	let xml = to_xml_with_header(&status)?;
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.header(http::header::CONTENT_TYPE, "application/xml")
+		.body(Body::from(xml))?)
+}
+
+pub async fn handle_delete_bucket_policy(
+	garage: Arc<Garage>,
+	bucket_id: Uuid,
+) -> Result<Response<Body>, Error> {
+	set_bucket_policy(&garage, bucket_id, None).await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::NO_CONTENT)
+		.body(Body::empty())?)
+}
+
+pub async fn handle_put_bucket_policy(
+	garage: Arc<Garage>,
+	bucket_id: Uuid,
+	req: Request<Body>,
+	content_sha256: Option<Hash>,
+) -> Result<Response<Body>, Error> {
+	let body = hyper::body::to_bytes(req.into_body()).await?;
+
+	if let Some(content_sha256) = content_sha256 {
+		verify_signed_content(content_sha256, &body[..])?;
+	}
+
+	if body.len() > MAX_POLICY_SIZE {
+		return Err(Error::MalformedPolicy(format!(
+			"Policies cannot be larger than {} bytes",
+			MAX_POLICY_SIZE
+		)));
+	}
+	let policy = std::str::from_utf8(&body)?;
+	BucketPolicy::parse(policy)?;
+
+	set_bucket_policy(&garage, bucket_id, Some(policy.to_string())).await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::NO_CONTENT)
+		.body(Body::empty())?)
+}
+
+async fn set_bucket_policy(
+	garage: &Garage,
+	bucket_id: Uuid,
+	policy: Option<String>,
+) -> Result<(), Error> {
+	let mut bucket = garage
+		.bucket_helper()
+		.get_existing_bucket(bucket_id)
+		.await?;
+
+	let param = bucket.params_mut().unwrap();
+
+	param.policy.update(policy);
+	garage.bucket_table.insert(&bucket).await?;
+
+	Ok(())
+}
+
+/// Name of the IAM action, without its `s3:` prefix, that bucket policies
+/// give or deny for requests to an endpoint
+pub fn policy_action(endpoint_name: &str) -> &str {
+	match endpoint_name {
+		"HeadBucket" | "ListObjects" | "ListObjectsV2" => "ListBucket",
+		"ListObjectVersions" => "ListBucketVersions",
+		"ListMultipartUploads" => "ListBucketMultipartUploads",
+		"ListParts" => "ListMultipartUploadParts",
+		"HeadObject" | "SelectObjectContent" => "GetObject",
+		"CreateMultipartUpload"
+		| "UploadPart"
+		| "UploadPartCopy"
+		| "CompleteMultipartUpload"
+		| "CopyObject"
+		| "PostObject" => "PutObject",
+		"DeleteObjects" => "DeleteObject",
+		"GetBucketCors" => "GetBucketCORS",
+		"PutBucketCors" | "DeleteBucketCors" => "PutBucketCORS",
+		"GetBucketEncryption" => "GetEncryptionConfiguration",
+		"PutBucketEncryption" | "DeleteBucketEncryption" => "PutEncryptionConfiguration",
+		"GetBucketLifecycleConfiguration" => "GetLifecycleConfiguration",
+		"PutBucketLifecycleConfiguration" | "DeleteBucketLifecycle" => "PutLifecycleConfiguration",
+		"GetObjectLockConfiguration" => "GetBucketObjectLockConfiguration",
+		"PutObjectLockConfiguration" => "PutBucketObjectLockConfiguration",
+		"GetBucketNotificationConfiguration" => "GetBucketNotification",
+		"PutBucketNotificationConfiguration" => "PutBucketNotification",
+		"DeleteBucketLogging" => "PutBucketLogging",
+		name => name,
+	}
+}
+
+// ---- Evaluation of bucket policies ----
+
+/// A request to a bucket, against which the policy of the bucket is evaluated
+pub struct PolicyContext {
+	policy: Option<BucketPolicy>,
+	key_id: String,
+	/// ARNs of the bucket, one for each of the names it can be referred to by
+	bucket_arns: Vec<String>,
+	source_ip: Option<IpAddr>,
+	secure_transport: bool,
+	/// Prefix of the keys listed by the request
+	prefix: Option<String>,
+}
+
+impl PolicyContext {
+	pub fn new(
+		garage: &Garage,
+		req: &Request<Body>,
+		bucket: &Bucket,
+		bucket_name: &str,
+		key_id: &str,
+	) -> Result<Self, Error> {
+		let param = bucket
+			.params()
+			.ok_or_internal_error("Bucket should not be deleted at this point")?;
+
+		let policy = match param.policy.get() {
+			Some(p) => Some(BucketPolicy::parse(p).ok_or_internal_error("Invalid bucket policy")?),
+			None => None,
+		};
+
+		// The name in the request may be a local alias of the key,
+		// while policies refer to the global aliases of the bucket
+		let mut bucket_arns = vec![format!("arn:aws:s3:::{}", bucket_name)];
+		for (alias, _, active) in param.aliases.items() {
+			if *active && alias != bucket_name {
+				bucket_arns.push(format!("arn:aws:s3:::{}", alias));
+			}
+		}
+
+		let (source_ip, secure_transport) =
+			client_address(req, &garage.config.s3_api.trusted_proxies);
+
+		let prefix = req.uri().query().and_then(|q| {
+			form_urlencoded::parse(q.as_bytes())
+				.find(|(k, _)| k == "prefix")
+				.map(|(_, v)| v.into_owned())
+		});
+
+		Ok(Self {
+			policy,
+			key_id: key_id.to_string(),
+			bucket_arns,
+			source_ip,
+			secure_transport,
+			prefix,
+		})
+	}
+
+	/// Combine the permission given to the key for the action with the bucket
+	/// policy: a statement that denies the action always wins, and a statement
+	/// that allows it gives the permission to a key that does not have it.
+	/// The action is on the bucket if `object_key` is None, and on the object
+	/// with this key otherwise.
+	pub fn is_allowed(&self, action: &str, object_key: Option<&str>, key_allowed: bool) -> bool {
+		let policy = match &self.policy {
+			Some(p) => p,
+			None => return key_allowed,
+		};
+		let resources = self
+			.bucket_arns
+			.iter()
+			.map(|arn| match object_key {
+				Some(key) => format!("{}/{}", arn, key),
+				None => arn.clone(),
+			})
+			.collect::<Vec<_>>();
+
+		let mut allowed = key_allowed;
+		for statement in policy.statements.iter() {
+			if statement.applies_to(self, action, &resources) {
+				match statement.effect {
+					Effect::Deny => return false,
+					Effect::Allow => allowed = true,
+				}
+			}
+		}
+		allowed
+	}
+}
+
+/// A bucket policy, parsed from its JSON document
+#[derive(Debug)]
+struct BucketPolicy {
+	statements: Vec<Statement>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Effect {
+	Allow,
+	Deny,
+}
+
+#[derive(Debug)]
+struct Statement {
+	effect: Effect,
+	/// IDs of the access keys to which the statement applies, None for all keys
+	principals: Option<Vec<String>>,
+	/// Names of the actions without their `s3:` prefix, with wildcards
+	actions: Vec<String>,
+	/// ARNs of the resources, with wildcards
+	resources: Vec<String>,
+	/// Conditions that must all be true for the statement to apply
+	conditions: Vec<Condition>,
+}
+
+#[derive(Debug)]
+enum Condition {
+	/// StringEquals, StringNotEquals, StringLike and StringNotLike on s3:prefix
+	Prefix {
+		like: bool,
+		negated: bool,
+		values: Vec<String>,
+	},
+	/// IpAddress and NotIpAddress on aws:SourceIp
+	SourceIp { negated: bool, nets: Vec<IpNet> },
+	/// Bool on aws:SecureTransport
+	SecureTransport(bool),
+}
+
+/// Check that the trusted proxies of the configuration are valid
+/// IP addresses or CIDR ranges
+pub(crate) fn check_trusted_proxies(trusted_proxies: &[String]) -> Result<(), String> {
+	match trusted_proxies.iter().find(|p| IpNet::parse(p).is_none()) {
+		Some(p) => Err(format!("Invalid address in trusted_proxies: {}", p)),
+		None => Ok(()),
+	}
+}
+
+/// IP address of the client that sent a request, and whether it was sent over TLS.
+/// They are given by the X-Forwarded-For and X-Forwarded-Proto headers when
+/// the request comes from a trusted reverse proxy, as these headers could
+/// otherwise be forged by the client.
+fn client_address(req: &Request<Body>, trusted_proxies: &[String]) -> (Option<IpAddr>, bool) {
+	let peer = req
+		.extensions()
+		.get::<SocketAddr>()
+		.map(|addr| match addr.ip() {
+			IpAddr::V6(ip) => ip
+				.to_ipv4_mapped()
+				.map(IpAddr::V4)
+				.unwrap_or(IpAddr::V6(ip)),
+			ip => ip,
+		});
+	let trusted = peer
+		.map(|ip| {
+			trusted_proxies
+				.iter()
+				.filter_map(|p| IpNet::parse(p))
+				.any(|net| net.contains(ip))
+		})
+		.unwrap_or(false);
+	if !trusted {
+		return (peer, false);
+	}
+
+	// The last address is the one added by the proxy, the others
+	// might have been sent by the client
+	let forwarded_for = req
+		.headers()
+		.get("x-forwarded-for")
+		.and_then(|v| v.to_str().ok())
+		.and_then(|v| v.rsplit(',').next())
+		.and_then(|ip| ip.trim().parse().ok());
+	let secure_transport = req
+		.headers()
+		.get("x-forwarded-proto")
+		.map(|v| v.as_bytes().eq_ignore_ascii_case(b"https"))
+		.unwrap_or(false);
+	(forwarded_for.or(peer), secure_transport)
+}
+
+#[derive(Debug, Clone, Copy)]
+struct IpNet {
+	addr: IpAddr,
+	prefix_len: u8,
+}
+
+impl BucketPolicy {
+	fn parse(doc: &str) -> Result<Self, Error> {
+		let doc: PolicyDocument =
+			serde_json::from_str(doc).map_err(|e| Error::MalformedPolicy(e.to_string()))?;
+		match doc.version.as_deref() {
+			None | Some("2012-10-17") | Some("2008-10-17") => (),
+			Some(v) => return Err(Error::MalformedPolicy(format!("Invalid version: {}", v))),
+		}
+		let statements = doc
+			.statement
+			.into_vec()
+			.into_iter()
+			.map(Statement::parse)
+			.collect::<Result<Vec<_>, _>>()?;
+		if statements.is_empty() {
+			return Err(Error::MalformedPolicy(
+				"A policy must have at least one statement".into(),
+			));
+		}
+		Ok(Self { statements })
+	}
+
+	/// A policy is public if it allows all keys to do something,
+	/// from any IP address
+	fn is_public(&self) -> bool {
+		self.statements.iter().any(|s| {
+			s.effect == Effect::Allow
+				&& s.principals.is_none()
+				&& !s
+					.conditions
+					.iter()
+					.any(|c| matches!(c, Condition::SourceIp { negated: false, .. }))
+		})
+	}
+}
+
+impl Statement {
+	fn parse(doc: StatementDocument) -> Result<Self, Error> {
+		let effect = match doc.effect.as_str() {
+			"Allow" => Effect::Allow,
+			"Deny" => Effect::Deny,
+			e => return Err(Error::MalformedPolicy(format!("Invalid effect: {}", e))),
+		};
+
+		let principals = match doc.principal {
+			PrincipalDocument::All(p) if p == "*" => None,
+			PrincipalDocument::All(p) => {
+				return Err(Error::MalformedPolicy(format!("Invalid principal: {}", p)))
+			}
+			PrincipalDocument::Aws(principal) => {
+				let keys = principal.aws.into_vec();
+				if keys.iter().any(|k| k == "*") {
+					None
+				} else {
+					Some(keys)
+				}
+			}
+		};
+
+		let actions = doc
+			.action
+			.into_vec()
+			.into_iter()
+			.map(|a| {
+				if a == "*" {
+					return Ok(a);
+				}
+				match a.split_once(':') {
+					Some((service, action)) if service.eq_ignore_ascii_case("s3") => {
+						Ok(action.to_string())
+					}
+					_ => Err(Error::MalformedPolicy(format!("Invalid action: {}", a))),
+				}
+			})
+			.collect::<Result<Vec<_>, _>>()?;
+
+		let resources = doc.resource.into_vec();
+		if let Some(r) = resources.iter().find(|r| !r.starts_with("arn:aws:s3:::")) {
+			return Err(Error::MalformedPolicy(format!("Invalid resource: {}", r)));
+		}
+
+		let mut conditions = vec![];
+		for (operator, keys) in doc.condition {
+			for (key, values) in keys {
+				let values = values
+					.into_vec()
+					.into_iter()
+					.map(|v| match v {
+						serde_json::Value::String(s) => Ok(s),
+						serde_json::Value::Bool(b) => Ok(b.to_string()),
+						v => Err(Error::MalformedPolicy(format!(
+							"Invalid condition value: {}",
+							v
+						))),
+					})
+					.collect::<Result<Vec<_>, _>>()?;
+				conditions.push(Condition::parse(&operator, &key, values)?);
+			}
+		}
+
+		Ok(Self {
+			effect,
+			principals,
+			actions,
+			resources,
+			conditions,
+		})
+	}
+
+	fn applies_to(&self, ctx: &PolicyContext, action: &str, resources: &[String]) -> bool {
+		let principal_matches = match &self.principals {
+			Some(keys) => keys.contains(&ctx.key_id),
+			None => true,
+		};
+		// Action names are case-insensitive
+		let action = action.to_ascii_lowercase();
+		principal_matches
+			&& self
+				.actions
+				.iter()
+				.any(|a| wildcard_match(&a.to_ascii_lowercase(), &action))
+			&& self
+				.resources
+				.iter()
+				.any(|r| resources.iter().any(|res| wildcard_match(r, res)))
+			&& self.conditions.iter().all(|c| c.is_true(ctx))
+	}
+}
+
+impl Condition {
+	fn parse(operator: &str, key: &str, values: Vec<String>) -> Result<Self, Error> {
+		let invalid_key = || {
+			Error::MalformedPolicy(format!(
+				"Condition key {} is not supported with {}",
+				key, operator
+			))
+		};
+		let key = key.to_ascii_lowercase();
+		match operator {
+			"StringEquals" | "StringNotEquals" | "StringLike" | "StringNotLike" => {
+				if key != "s3:prefix" {
+					return Err(invalid_key());
+				}
+				Ok(Condition::Prefix {
+					like: operator.ends_with("Like"),
+					negated: operator.starts_with("StringNot"),
+					values,
+				})
+			}
+			"IpAddress" | "NotIpAddress" => {
+				if key != "aws:sourceip" {
+					return Err(invalid_key());
+				}
+				let nets = values
+					.iter()
+					.map(|v| {
+						IpNet::parse(v).ok_or_else(|| {
+							Error::MalformedPolicy(format!("Invalid IP address range: {}", v))
+						})
+					})
+					.collect::<Result<Vec<_>, _>>()?;
+				Ok(Condition::SourceIp {
+					negated: operator == "NotIpAddress",
+					nets,
+				})
+			}
+			"Bool" => {
+				if key != "aws:securetransport" {
+					return Err(invalid_key());
+				}
+				match values.as_slice() {
+					[v] if v.eq_ignore_ascii_case("true") => Ok(Condition::SecureTransport(true)),
+					[v] if v.eq_ignore_ascii_case("false") => Ok(Condition::SecureTransport(false)),
+					_ => Err(Error::MalformedPolicy(
+						"aws:SecureTransport must be true or false".into(),
+					)),
+				}
+			}
+			_ => Err(Error::MalformedPolicy(format!(
+				"Unsupported condition operator: {}",
+				operator
+			))),
+		}
+	}
+
+	/// Evaluate the condition for a request. As on AWS, a condition whose
+	/// key has no value for the request is false, unless it is negated.
+	fn is_true(&self, ctx: &PolicyContext) -> bool {
+		match self {
+			Condition::Prefix {
+				like,
+				negated,
+				values,
+			} => {
+				let matches = ctx
+					.prefix
+					.as_ref()
+					.map(|prefix| {
+						values.iter().any(|v| {
+							if *like {
+								wildcard_match(v, prefix)
+							} else {
+								v == prefix
+							}
+						})
+					})
+					.unwrap_or(false);
+				matches != *negated
+			}
+			Condition::SourceIp { negated, nets } => {
+				let matches = ctx
+					.source_ip
+					.map(|ip| nets.iter().any(|n| n.contains(ip)))
+					.unwrap_or(false);
+				matches != *negated
+			}
+			Condition::SecureTransport(secure) => ctx.secure_transport == *secure,
+		}
+	}
+}
+
+impl IpNet {
+	/// Parse an IP address range in CIDR notation, or a single IP address
+	fn parse(s: &str) -> Option<Self> {
+		let (addr, prefix_len) = match s.split_once('/') {
+			Some((addr, len)) => (addr, Some(len)),
+			None => (s, None),
+		};
+		let addr: IpAddr = addr.parse().ok()?;
+		let max_len = if addr.is_ipv4() { 32 } else { 128 };
+		let prefix_len = match prefix_len {
+			Some(len) => len.parse().ok().filter(|len| *len <= max_len)?,
+			None => max_len,
+		};
+		Some(Self { addr, prefix_len })
+	}
+
+	fn contains(&self, ip: IpAddr) -> bool {
+		match (self.addr, ip) {
+			(IpAddr::V4(net), IpAddr::V4(ip)) => {
+				let mask = u32::MAX
+					.checked_shl(32 - self.prefix_len as u32)
+					.unwrap_or(0);
+				u32::from(net) & mask == u32::from(ip) & mask
+			}
+			(IpAddr::V6(net), IpAddr::V6(ip)) => {
+				let mask = u128::MAX
+					.checked_shl(128 - self.prefix_len as u32)
+					.unwrap_or(0);
+				u128::from(net) & mask == u128::from(ip) & mask
+			}
+			_ => false,
+		}
+	}
+}
+
+/// Match a string against a pattern in which `*` matches any sequence
+/// of characters and `?` matches any single character
+fn wildcard_match(pattern: &str, s: &str) -> bool {
+	let pattern = pattern.chars().collect::<Vec<_>>();
+	let s = s.chars().collect::<Vec<_>>();
+	let (mut p, mut i) = (0, 0);
+	// Position in the pattern after the last `*`, and in the string
+	// of the first character matched by this `*`
+	let mut backtrack = None;
+	while i < s.len() {
+		if p < pattern.len() && (pattern[p] == '?' || pattern[p] == s[i]) {
+			p += 1;
+			i += 1;
+		} else if p < pattern.len() && pattern[p] == '*' {
+			p += 1;
+			backtrack = Some((p, i));
+		} else if let Some((bp, bi)) = backtrack {
+			p = bp;
+			i = bi + 1;
+			backtrack = Some((bp, bi + 1));
+		} else {
+			return false;
+		}
+	}
+	pattern[p..].iter().all(|c| *c == '*')
+}
+
+// ---- SERIALIZATION AND DESERIALIZATION ----
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PolicyDocument {
+	#[serde(rename = "Version")]
+	version: Option<String>,
+	#[serde(rename = "Id")]
+	_id: Option<String>,
+	#[serde(rename = "Statement")]
+	statement: OneOrMany<StatementDocument>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StatementDocument {
+	#[serde(rename = "Sid")]
+	_sid: Option<String>,
+	#[serde(rename = "Effect")]
+	effect: String,
+	#[serde(rename = "Principal")]
+	principal: PrincipalDocument,
+	#[serde(rename = "Action")]
+	action: OneOrMany<String>,
+	#[serde(rename = "Resource")]
+	resource: OneOrMany<String>,
+	/// Values of the condition keys, by condition operator
+	#[serde(rename = "Condition", default)]
+	condition: HashMap<String, HashMap<String, OneOrMany<serde_json::Value>>>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PrincipalDocument {
+	All(String),
+	Aws(AwsPrincipal),
+}
+
+/// Principals are identified by the IDs of their access keys
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AwsPrincipal {
+	#[serde(rename = "AWS")]
+	aws: OneOrMany<String>,
+}
+
+/// Most elements of policies can be either a single value or a list
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany<T> {
+	Many(Vec<T>),
+	One(T),
+}
+
+impl<T> OneOrMany<T> {
+	fn into_vec(self) -> Vec<T> {
+		match self {
+			OneOrMany::Many(v) => v,
+			OneOrMany::One(x) => vec![x],
+		}
+	}
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+struct PolicyStatus {
+	#[serde(serialize_with = "xmlns_tag")]
+	xmlns: (),
+	#[serde(rename = "IsPublic")]
+	is_public: Value,
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn context(policy: &str, key_id: &str, ip: &str, query: &str) -> PolicyContext {
+		PolicyContext {
+			policy: Some(BucketPolicy::parse(policy).unwrap()),
+			key_id: key_id.to_string(),
+			bucket_arns: vec![
+				"arn:aws:s3:::local-name".to_string(),
+				"arn:aws:s3:::my-bucket".to_string(),
+			],
+			source_ip: ip.parse().ok(),
+			secure_transport: false,
+			prefix: Some(query.to_string()).filter(|q| !q.is_empty()),
+		}
+	}
+
+	#[test]
+	fn test_wildcard_match() {
+		assert!(wildcard_match("*", ""));
+		assert!(wildcard_match("a*c", "abbbc"));
+		assert!(wildcard_match("a?c*", "abcdef"));
+		assert!(wildcard_match("*b*b", "abab"));
+		assert!(!wildcard_match("a*c", "abcd"));
+		assert!(!wildcard_match("a?c", "ac"));
+	}
+
+	#[test]
+	fn test_ip_net() {
+		let net = IpNet::parse("192.168.1.0/24").unwrap();
+		assert!(net.contains("192.168.1.42".parse().unwrap()));
+		assert!(!net.contains("192.168.2.1".parse().unwrap()));
+		assert!(!net.contains("::1".parse().unwrap()));
+		assert!(IpNet::parse("0.0.0.0/0")
+			.unwrap()
+			.contains("10.1.2.3".parse().unwrap()));
+		assert!(IpNet::parse("2001:db8::/32")
+			.unwrap()
+			.contains("2001:db8::f00d".parse().unwrap()));
+		assert!(IpNet::parse("10.0.0.1/33").is_none());
+		assert!(IpNet::parse("example.com").is_none());
+	}
+
+	#[test]
+	fn test_client_address() {
+		let request = |peer: &str, headers: &[(&'static str, &str)]| {
+			let mut req = Request::new(Body::empty());
+			req.extensions_mut()
+				.insert(peer.parse::<SocketAddr>().unwrap());
+			for (name, value) in headers {
+				req.headers_mut().insert(*name, value.parse().unwrap());
+			}
+			req
+		};
+		let trusted = vec!["10.0.0.1".to_string(), "fd00::/8".to_string()];
+		let spoofed = [
+			("x-forwarded-for", "10.1.2.3"),
+			("x-forwarded-proto", "https"),
+		];
+
+		// Headers sent directly by a client are ignored
+		let req = request("192.0.2.7:4321", &spoofed);
+		assert_eq!(
+			client_address(&req, &trusted),
+			(Some("192.0.2.7".parse().unwrap()), false)
+		);
+		assert_eq!(
+			client_address(&req, &[]),
+			(Some("192.0.2.7".parse().unwrap()), false)
+		);
+
+		// Headers sent by a trusted proxy are used
+		let req = request("10.0.0.1:4321", &spoofed);
+		assert_eq!(
+			client_address(&req, &trusted),
+			(Some("10.1.2.3".parse().unwrap()), true)
+		);
+		let req = request("[::ffff:10.0.0.1]:4321", &[]);
+		assert_eq!(
+			client_address(&req, &trusted),
+			(Some("10.0.0.1".parse().unwrap()), false)
+		);
+
+		// Only the address added by the proxy is trusted
+		let req = request(
+			"[fd00::1]:4321",
+			&[("x-forwarded-for", "10.1.2.3, 192.0.2.7")],
+		);
+		assert_eq!(
+			client_address(&req, &trusted),
+			(Some("192.0.2.7".parse().unwrap()), false)
+		);
+
+		assert!(check_trusted_proxies(&trusted).is_ok());
+		assert!(check_trusted_proxies(&["proxy.local".to_string()]).is_err());
+	}
+
+	#[test]
+	fn test_policy_evaluation() {
+		let policy = r#"{
+			"Version": "2012-10-17",
+			"Statement": [
+				{
+					"Sid": "ReadFromSubnet",
+					"Effect": "Allow",
+					"Principal": "*",
+					"Action": ["s3:GetObject", "s3:List*"],
+					"Resource": ["arn:aws:s3:::my-bucket", "arn:aws:s3:::my-bucket/public/*"],
+					"Condition": {"IpAddress": {"aws:SourceIp": "10.0.0.0/8"}}
+				},
+				{
+					"Effect": "Deny",
+					"Principal": {"AWS": ["GKwriter"]},
+					"Action": "s3:*",
+					"Resource": "arn:aws:s3:::my-bucket/private/*"
+				},
+				{
+					"Effect": "Deny",
+					"Principal": {"AWS": "*"},
+					"Action": "s3:ListBucket",
+					"Resource": "arn:aws:s3:::my-bucket",
+					"Condition": {"StringLike": {"s3:prefix": "private/*"}}
+				}
+			]
+		}"#;
+
+		let ctx = context(policy, "GKreader", "10.1.2.3", "");
+		assert!(ctx.is_allowed("GetObject", Some("public/a.txt"), false));
+		assert!(!ctx.is_allowed("GetObject", Some("other/a.txt"), false));
+		assert!(ctx.is_allowed("GetObject", Some("other/a.txt"), true));
+		assert!(!ctx.is_allowed("PutObject", Some("public/a.txt"), false));
+		assert!(ctx.is_allowed("ListBucket", None, false));
+
+		let ctx = context(policy, "GKreader", "192.168.1.1", "");
+		assert!(!ctx.is_allowed("GetObject", Some("public/a.txt"), false));
+
+		let ctx = context(policy, "GKwriter", "192.168.1.1", "");
+		assert!(ctx.is_allowed("PutObject", Some("public/a.txt"), true));
+		assert!(!ctx.is_allowed("PutObject", Some("private/a.txt"), true));
+
+		let ctx = context(policy, "GKreader", "10.1.2.3", "private/");
+		assert!(!ctx.is_allowed("ListBucket", None, true));
+
+		assert!(!BucketPolicy::parse(policy).unwrap().is_public());
+	}
+
+	#[test]
+	fn test_invalid_policies() {
+		let statement = |s: &str| format!(r#"{{"Statement": {}}}"#, s);
+		for policy in [
+			"{}".to_string(),
+			statement("[]"),
+			statement(
+				r#"{"Effect": "Maybe", "Principal": "*", "Action": "s3:*", "Resource": "arn:aws:s3:::b"}"#,
+			),
+			statement(
+				r#"{"Effect": "Allow", "Principal": "*", "Action": "ec2:*", "Resource": "arn:aws:s3:::b"}"#,
+			),
+			statement(
+				r#"{"Effect": "Allow", "Principal": "*", "Action": "s3:*", "Resource": "b/*"}"#,
+			),
+			statement(
+				r#"{"Effect": "Allow", "Principal": "*", "NotAction": "s3:*", "Resource": "arn:aws:s3:::b"}"#,
+			),
+			statement(
+				r#"{"Effect": "Allow", "Principal": "*", "Action": "s3:*", "Resource": "arn:aws:s3:::b",
+				"Condition": {"IpAddress": {"aws:SourceIp": "not-an-ip"}}}"#,
+			),
+			statement(
+				r#"{"Effect": "Allow", "Principal": "*", "Action": "s3:*", "Resource": "arn:aws:s3:::b",
+				"Condition": {"DateGreaterThan": {"aws:CurrentTime": "2020-01-01T00:00:00Z"}}}"#,
+			),
+		] {
+			assert!(BucketPolicy::parse(&policy).is_err(), "{}", policy);
+		}
+
+		let public = statement(
+			r#"{"Effect": "Allow", "Principal": {"AWS": "*"}, "Action": "s3:GetObject", "Resource": "arn:aws:s3:::b/*",
+			"Condition": {"Bool": {"aws:SecureTransport": true}}}"#,
+		);
+		assert!(BucketPolicy::parse(&public).unwrap().is_public());
+	}
+}
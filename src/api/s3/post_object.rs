@@ -14,9 +14,17 @@ use multer::{Constraints, Multipart, SizeLimit};
 use serde::Deserialize;
 
 use garage_model::garage::Garage;
+use garage_model::permission::S3Op;
+use garage_model::s3::object_table::ObjectTags;
 
+use crate::s3::cors::{add_cors_headers, find_matching_cors_rule};
+use crate::s3::encryption::{check_encryption_required, CustomerKey};
 use crate::s3::error::*;
-use crate::s3::put::{get_headers, save_stream};
+use crate::s3::object_lock::get_object_lock;
+use crate::s3::policy::PolicyContext;
+use crate::s3::put::{get_compression_level, get_headers, save_stream};
+use crate::s3::restore::get_storage_class;
+use crate::s3::tagging::parse_tagging_xml;
 use crate::s3::xml as s3_xml;
 use crate::signature::payload::{parse_date, verify_v4};
 
@@ -42,6 +50,8 @@ pub async fn handle_post_object(
 
 	let (head, body) = req.into_parts();
 	let mut multipart = Multipart::with_constraints(body, boundary, constraints);
+	// The rest of the request is still needed to find the CORS rule that applies to it
+	let req = Request::from_parts(head, Body::empty());
 
 	let mut params = HeaderMap::new();
 	let field = loop {
@@ -128,15 +138,21 @@ pub async fn handle_post_object(
 		.resolve_bucket(&bucket_name, &api_key)
 		.await?;
 
-	if !api_key.allow_write(&bucket_id) {
-		return Err(Error::forbidden("Operation is not allowed for this key."));
-	}
-
 	let bucket = garage
 		.bucket_helper()
 		.get_existing_bucket(bucket_id)
 		.await?;
 
+	let bucket_policy = PolicyContext::new(&garage, &req, &bucket, &bucket_name, &api_key.key_id)?;
+	if !bucket_policy.is_allowed(
+		"PutObject",
+		Some(&key),
+		api_key.allow_op(&bucket_id, S3Op::PostObject),
+	) {
+		return Err(Error::forbidden("Operation is not allowed for this key."));
+	}
+	let matching_cors_rule = find_matching_cors_rule(&bucket, &req)?;
+
 	let decoded_policy = BASE64_STANDARD
 		.decode(policy)
 		.ok_or_bad_request("Invalid policy")?;
@@ -219,6 +235,22 @@ pub async fn handle_post_object(
 		}
 	}
 
+	// The bucket is given in the URL rather than in a field of the form,
+	// but policies usually have a condition on it
+	if let Some(conds) = conditions.params.remove("bucket") {
+		for cond in conds {
+			let ok = match cond {
+				Operation::Equal(s) => s == bucket_name,
+				Operation::StartsWith(s) => bucket_name.starts_with(&s),
+			};
+			if !ok {
+				return Err(Error::bad_request(
+					"Key 'bucket' has value not allowed in policy",
+				));
+			}
+		}
+	}
+
 	if let Some((param_key, _)) = conditions.params.iter().next() {
 		return Err(Error::bad_request(format!(
 			"Key '{}' is required in policy, but no value was provided",
@@ -226,23 +258,38 @@ pub async fn handle_post_object(
 		)));
 	}
 
+	check_encryption_required(&bucket, &params)?;
 	let headers = get_headers(&params)?;
+	let encryption = CustomerKey::from_request_headers(&params)?;
+	let lock = get_object_lock(&bucket, &params)?;
+	let compression_level = get_compression_level(&garage, &bucket, &params)?;
+	let storage_class = get_storage_class(&params)?;
+	let tags = match params.get("tagging") {
+		Some(tagging) => parse_tagging_xml(tagging.as_bytes())?,
+		None => ObjectTags::default(),
+	};
 
 	let stream = field.map(|r| r.map_err(Into::into));
-	let (_, md5) = save_stream(
+	let (_, md5, _) = save_stream(
 		garage,
 		headers,
+		lock,
+		tags,
+		storage_class,
 		StreamLimiter::new(stream, conditions.content_length),
 		&bucket,
 		&key,
 		None,
 		None,
+		None,
+		compression_level,
+		encryption,
 	)
 	.await?;
 
 	let etag = format!("\"{}\"", md5);
 
-	let resp = if let Some(mut target) = params
+	let mut resp = if let Some(mut target) = params
 		.get("success_action_redirect")
 		.and_then(|h| h.to_str().ok())
 		.and_then(|u| url::Url::parse(u).ok())
@@ -260,14 +307,13 @@ pub async fn handle_post_object(
 			.header(header::ETAG, etag)
 			.body(target.into())?
 	} else {
-		let path = head
-			.uri
-			.into_parts()
-			.path_and_query
+		let path = req
+			.uri()
+			.path_and_query()
 			.map(|paq| paq.path().to_string())
 			.unwrap_or_else(|| "/".to_string());
-		let authority = head
-			.headers
+		let authority = req
+			.headers()
 			.get(header::HOST)
 			.and_then(|h| h.to_str().ok())
 			.unwrap_or_default();
@@ -308,6 +354,12 @@ pub async fn handle_post_object(
 		}
 	};
 
+	// Browsers upload with POST Object from the pages of other origins
+	if let Some((rule, origin)) = matching_cors_rule {
+		add_cors_headers(&mut resp, rule, &origin)
+			.ok_or_internal_error("Invalid bucket CORS configuration")?;
+	}
+
 	Ok(resp)
 }
 
@@ -17,6 +17,7 @@ use opentelemetry::{
 use garage_rpc::netapp::bytes_buf::BytesBuf;
 use garage_table::*;
 use garage_util::async_hash::*;
+use garage_util::crdt::Lww;
 use garage_util::data::*;
 use garage_util::error::Error as GarageError;
 use garage_util::time::*;
@@ -29,10 +30,28 @@ use garage_model::s3::block_ref_table::*;
 use garage_model::s3::object_table::*;
 use garage_model::s3::version_table::*;
 
+use crate::s3::encryption::{
+	check_encryption_required, check_upload_encryption_required, get_sse_algorithm, CustomerKey,
+	SSE_HEADER,
+};
 use crate::s3::error::*;
+use crate::s3::object_lock::get_object_lock;
+use crate::s3::restore::get_storage_class;
+use crate::s3::tagging::get_tagging_header;
 use crate::s3::xml as s3_xml;
+use crate::signature::streaming::PayloadChecksum;
 use crate::signature::verify_signed_content;
 
+const USER_META_PREFIX: &str = "x-amz-meta-";
+/// Maximum total size of the names and values of the user-defined metadata of an object
+const MAX_USER_META_SIZE: usize = 2048;
+/// Request header that disables the compression of the data blocks of an upload
+/// when set to `none`
+const COMPRESSION_HEADER: &str = "x-garage-compression";
+/// Header giving the algorithm of the checksums of the parts of a multipart upload,
+/// which is kept in the headers of the upload until it is completed
+pub const CHECKSUM_ALGORITHM_HEADER: &str = "x-amz-checksum-algorithm";
+
 pub async fn handle_put(
 	garage: Arc<Garage>,
 	req: Request<Body>,
@@ -40,14 +59,24 @@ pub async fn handle_put(
 	key: &str,
 	content_sha256: Option<Hash>,
 ) -> Result<Response<Body>, Error> {
+	check_encryption_required(bucket, req.headers())?;
+	check_content_length(bucket, req.headers())?;
+	let compression_level = get_compression_level(&garage, bucket, req.headers())?;
+	let encryption = CustomerKey::from_request_headers(req.headers())?;
+
 	// Retrieve interesting headers from request
 	let headers = get_headers(req.headers())?;
 	debug!("Object headers: {:?}", headers);
+	let sse_algorithm = headers.other.get(SSE_HEADER).cloned();
+	let lock = get_object_lock(bucket, req.headers())?;
+	let tags = get_tagging_header(req.headers())?;
+	let storage_class = get_storage_class(req.headers())?;
 
 	let content_md5 = match req.headers().get("content-md5") {
 		Some(x) => Some(x.to_str()?.to_string()),
 		None => None,
 	};
+	let checksum = get_expected_checksum(req.headers())?;
 
 	let (_head, body) = req.into_parts();
 	let body = body.map_err(Error::from);
@@ -55,30 +84,130 @@ pub async fn handle_put(
 	save_stream(
 		garage,
 		headers,
+		lock,
+		tags,
+		storage_class,
 		body,
 		bucket,
 		key,
 		content_md5,
 		content_sha256,
+		checksum,
+		compression_level,
+		encryption.clone(),
 	)
 	.await
-	.map(|(uuid, md5)| put_response(uuid, md5))
+	.map(|(version_uuid, md5, checksum)| {
+		let version_id = match bucket.versioning_enabled() {
+			true => hex::encode(version_uuid),
+			false => "null".into(),
+		};
+		let mut resp = put_response(version_id, md5);
+		if let Some(checksum) = &checksum {
+			insert_checksum_header(resp.headers_mut(), checksum);
+		}
+		if let Some(algorithm) = sse_algorithm {
+			resp.headers_mut()
+				.insert(SSE_HEADER, HeaderValue::from_str(&algorithm).unwrap());
+		}
+		if let Some(key) = &encryption {
+			key.add_response_headers(resp.headers_mut());
+		}
+		resp
+	})
+}
+
+/// Maximum size of the objects that can be uploaded to a bucket in a single request,
+/// if it has one
+fn max_object_size(bucket: &Bucket) -> Option<u64> {
+	bucket.params().and_then(|p| *p.max_object_size.get())
 }
 
-pub(crate) async fn save_stream<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
+/// Check that an object of this size can be written to the bucket without
+/// a multipart upload
+pub(crate) fn check_max_object_size(bucket: &Bucket, size: u64) -> Result<(), Error> {
+	match max_object_size(bucket) {
+		Some(m) if size > m => Err(Error::EntityTooLarge),
+		_ => Ok(()),
+	}
+}
+
+/// Reject single-part uploads whose announced size is larger than the maximum
+/// object size of the bucket, before any data is read from the request body.
+/// The size is checked again while the body is read, in `save_stream`.
+fn check_content_length(bucket: &Bucket, headers: &HeaderMap<HeaderValue>) -> Result<(), Error> {
+	if max_object_size(bucket).is_none() {
+		return Ok(());
+	}
+
+	// With streaming signatures, Content-Length includes the chunk signatures
+	// and the actual size of the object is given in x-amz-decoded-content-length
+	let length = headers
+		.get("x-amz-decoded-content-length")
+		.or_else(|| headers.get(hyper::header::CONTENT_LENGTH))
+		.map(|v| {
+			v.to_str()
+				.ok()
+				.and_then(|v| v.parse::<u64>().ok())
+				.ok_or_bad_request("Invalid content length")
+		})
+		.transpose()?;
+	match length {
+		Some(l) => check_max_object_size(bucket, l),
+		None => Ok(()),
+	}
+}
+
+/// Get the zstd compression level of the data blocks of an upload: the level of
+/// the bucket if it has one, that of this node otherwise, unless the request
+/// disables compression
+pub(crate) fn get_compression_level(
+	garage: &Garage,
+	bucket: &Bucket,
+	headers: &HeaderMap<HeaderValue>,
+) -> Result<Option<i32>, Error> {
+	match headers.get(COMPRESSION_HEADER) {
+		Some(v) if v == "none" => return Ok(None),
+		Some(v) => {
+			return Err(Error::bad_request(format!(
+				"Invalid {} header: {:?}, the only supported value is `none`",
+				COMPRESSION_HEADER, v
+			)))
+		}
+		None => (),
+	}
+	Ok(bucket
+		.params()
+		.and_then(|p| *p.compression_level.get())
+		.or(garage.block_manager.compression_level()))
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn save_stream<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 	garage: Arc<Garage>,
 	headers: ObjectVersionHeaders,
+	lock: ObjectVersionLock,
+	tags: ObjectTags,
+	storage_class: StorageClass,
 	body: S,
 	bucket: &Bucket,
 	key: &str,
 	content_md5: Option<String>,
 	content_sha256: Option<FixedBytes32>,
-) -> Result<(Uuid, String), Error> {
+	checksum: Option<ExpectedChecksum>,
+	compression_level: Option<i32>,
+	encryption: Option<CustomerKey>,
+) -> Result<(Uuid, String, Option<ObjectChecksum>), Error> {
 	// Generate identity of new version
 	let version_uuid = gen_uuid();
 	let version_timestamp = now_msec();
+	let versioned = bucket.versioning_enabled();
 
-	let mut chunker = StreamChunker::new(body, garage.config.block_size);
+	// The announced size of the body cannot be trusted (and is not known for
+	// PostObject): stop reading it as soon as it is too large
+	let mut chunker = StreamChunker::new(body, garage.config.block_size)
+		.with_checksum(checksum)
+		.with_max_size(max_object_size(bucket));
 	let first_block = chunker.next().await?.unwrap_or_default();
 
 	// If body is small enough, store it directly in the object table
@@ -101,23 +230,37 @@ pub(crate) async fn save_stream<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 
 		check_quotas(&garage, bucket, key, size).await?;
 
+		// The body was read entirely, so its checksum is known
+		let checksum = chunker.checksum();
+		let etag = make_etag(data_md5sum_hex, encryption.as_ref());
+		let inline_data = match &encryption {
+			Some(key) => key.encrypt(&first_block)?,
+			None => first_block,
+		};
+
 		let object_version = ObjectVersion {
 			uuid: version_uuid,
 			timestamp: version_timestamp,
+			versioned,
 			state: ObjectVersionState::Complete(ObjectVersionData::Inline(
 				ObjectVersionMeta {
 					headers,
 					size,
-					etag: data_md5sum_hex.clone(),
+					etag: etag.clone(),
+					checksum: checksum.clone(),
 				},
-				first_block.to_vec(),
+				inline_data.to_vec(),
 			)),
+			lock,
+			tags: Lww::new(tags),
+			storage_class,
+			restore: Default::default(),
 		};
 
 		let object = Object::new(bucket.id, key.into(), vec![object_version]);
 		garage.object_table.insert(&object).await?;
 
-		return Ok((version_uuid, data_md5sum_hex));
+		return Ok((version_uuid, etag, checksum));
 	}
 
 	// The following consists in many steps that can each fail.
@@ -129,6 +272,7 @@ pub(crate) async fn save_stream<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 		key.into(),
 		version_uuid,
 		version_timestamp,
+		versioned,
 	)));
 
 	// Write version identifier in object table so that we have a trace
@@ -137,6 +281,11 @@ pub(crate) async fn save_stream<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 		uuid: version_uuid,
 		timestamp: version_timestamp,
 		state: ObjectVersionState::Uploading(headers.clone()),
+		versioned,
+		lock,
+		tags: Lww::new(tags),
+		storage_class,
+		restore: Default::default(),
 	};
 	let object = Object::new(bucket.id, key.into(), vec![object_version.clone()]);
 	garage.object_table.insert(&object).await?;
@@ -149,15 +298,14 @@ pub(crate) async fn save_stream<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 	garage.version_table.insert(&version).await?;
 
 	// Transfer data and verify checksum
-	let first_block_hash = async_blake2sum(first_block.clone()).await;
-
-	let (total_size, data_md5sum, data_sha256sum) = read_and_put_blocks(
+	let (total_size, data_md5sum, data_sha256sum, first_block_hash) = read_and_put_blocks(
 		&garage,
 		&version,
 		1,
 		first_block,
-		first_block_hash,
 		&mut chunker,
+		compression_level,
+		encryption.as_ref(),
 	)
 	.await?;
 
@@ -171,12 +319,14 @@ pub(crate) async fn save_stream<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 	check_quotas(&garage, bucket, key, total_size).await?;
 
 	// Save final object state, marked as Complete
-	let md5sum_hex = hex::encode(data_md5sum);
+	let checksum = chunker.checksum();
+	let etag = make_etag(hex::encode(data_md5sum), encryption.as_ref());
 	object_version.state = ObjectVersionState::Complete(ObjectVersionData::FirstBlock(
 		ObjectVersionMeta {
 			headers,
 			size: total_size,
-			etag: md5sum_hex.clone(),
+			etag: etag.clone(),
+			checksum: checksum.clone(),
 		},
 		first_block_hash,
 	));
@@ -187,7 +337,17 @@ pub(crate) async fn save_stream<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 	// We won't have to clean up on drop.
 	interrupted_cleanup.cancel();
 
-	Ok((version_uuid, md5sum_hex))
+	Ok((version_uuid, etag, checksum))
+}
+
+/// ETag of an object or of a part: the MD5 of its data, unless it is encrypted with
+/// a key provided by the client, in which case the MD5 of the data must not be
+/// revealed and a random ETag is used instead, as on AWS
+fn make_etag(data_md5sum_hex: String, encryption: Option<&CustomerKey>) -> String {
+	match encryption {
+		Some(_) => hex::encode(&gen_uuid().as_slice()[..16]),
+		None => data_md5sum_hex,
+	}
 }
 
 /// Validate MD5 sum against content-md5 header
@@ -209,7 +369,7 @@ fn ensure_checksum_matches(
 	}
 	if let Some(expected_md5) = content_md5 {
 		if expected_md5.trim_matches('"') != BASE64_STANDARD.encode(data_md5sum) {
-			return Err(Error::bad_request("Unable to validate content-md5"));
+			return Err(Error::BadDigest);
 		} else {
 			trace!("Successfully validated content-md5");
 		}
@@ -217,8 +377,83 @@ fn ensure_checksum_matches(
 	Ok(())
 }
 
-/// Check that inserting this object with this size doesn't exceed bucket quotas
-async fn check_quotas(
+/// Checksum of the request body, computed while it is received so that it can be
+/// stored with the object. When its value is given in a `x-amz-checksum-*` header,
+/// it is verified once the whole body has been received.
+pub struct ExpectedChecksum {
+	hasher: PayloadChecksum,
+	value: Option<String>,
+}
+
+impl ExpectedChecksum {
+	fn compute(algorithm: ChecksumAlgorithm) -> Result<Self, Error> {
+		Ok(Self {
+			hasher: PayloadChecksum::new(algorithm.header_name())?,
+			value: None,
+		})
+	}
+}
+
+/// Get the checksum to compute on the request body, if any: that of the
+/// `x-amz-checksum-*` header given in the request, whose value is then verified,
+/// or that announced in the x-amz-trailer or x-amz-sdk-checksum-algorithm header.
+/// When the x-amz-sdk-checksum-algorithm header is given, only the header of that
+/// algorithm is looked at. Checksums given in the trailer of `aws-chunked` bodies
+/// are verified when the body is decoded.
+pub(crate) fn get_expected_checksum(
+	headers: &HeaderMap<HeaderValue>,
+) -> Result<Option<ExpectedChecksum>, Error> {
+	let sdk_algorithm = match headers.get("x-amz-sdk-checksum-algorithm") {
+		Some(algorithm) => Some(parse_checksum_algorithm(algorithm)?),
+		None => None,
+	};
+	let algorithms = match sdk_algorithm {
+		Some(algorithm) => vec![algorithm],
+		None => ChecksumAlgorithm::ALL.to_vec(),
+	};
+
+	for algorithm in algorithms {
+		if let Some(value) = headers.get(algorithm.header_name()) {
+			return Ok(Some(ExpectedChecksum {
+				hasher: PayloadChecksum::new(algorithm.header_name())?,
+				value: Some(value.to_str()?.trim().to_string()),
+			}));
+		}
+	}
+
+	let trailer = match headers.get("x-amz-trailer") {
+		Some(trailer) => Some(trailer.to_str()?.trim().to_lowercase()),
+		None => None,
+	};
+	let trailer_algorithm = ChecksumAlgorithm::ALL
+		.iter()
+		.find(|a| trailer.as_deref() == Some(a.header_name()))
+		.copied();
+	match trailer_algorithm.or(sdk_algorithm) {
+		Some(algorithm) => Ok(Some(ExpectedChecksum::compute(algorithm)?)),
+		None => Ok(None),
+	}
+}
+
+fn parse_checksum_algorithm(value: &HeaderValue) -> Result<ChecksumAlgorithm, Error> {
+	let name = value.to_str()?.trim();
+	ChecksumAlgorithm::parse(name)
+		.ok_or_else(|| Error::bad_request(format!("Unsupported checksum algorithm: {}", name)))
+}
+
+pub(crate) fn insert_checksum_header(
+	headers: &mut HeaderMap<HeaderValue>,
+	checksum: &ObjectChecksum,
+) {
+	if let Ok(value) = HeaderValue::from_str(&checksum.value) {
+		headers.insert(checksum.algorithm.header_name(), value);
+	}
+}
+
+/// Check that inserting this object with this size doesn't exceed bucket quotas.
+/// This is checked against the object counters, which are updated asynchronously:
+/// concurrent writes can all pass the check and together exceed the quota.
+pub(crate) async fn check_quotas(
 	garage: &Arc<Garage>,
 	bucket: &Bucket,
 	key: &str,
@@ -255,7 +490,7 @@ async fn check_quotas(
 	if let Some(mo) = quotas.max_objects {
 		let current_objects = counters.get(OBJECTS).cloned().unwrap_or_default();
 		if cnt_obj_diff > 0 && current_objects + cnt_obj_diff > mo as i64 {
-			return Err(Error::forbidden(format!(
+			return Err(Error::QuotaExceeded(format!(
 				"Object quota is reached, maximum objects for this bucket: {}",
 				mo
 			)));
@@ -265,7 +500,7 @@ async fn check_quotas(
 	if let Some(ms) = quotas.max_size {
 		let current_size = counters.get(BYTES).cloned().unwrap_or_default();
 		if cnt_size_diff > 0 && current_size + cnt_size_diff > ms as i64 {
-			return Err(Error::forbidden(format!(
+			return Err(Error::QuotaExceeded(format!(
 				"Bucket size quota is reached, maximum total size of objects for this bucket: {}. The bucket is already {} bytes, and this object would add {} bytes.",
 				ms, current_size, size
 			)));
@@ -275,67 +510,72 @@ async fn check_quotas(
 	Ok(())
 }
 
+/// Store the blocks of a part of a version, returning its size, the MD5 and SHA256
+/// of its data, and the hash of its first block
 async fn read_and_put_blocks<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 	garage: &Garage,
 	version: &Version,
 	part_number: u64,
 	first_block: Bytes,
-	first_block_hash: Hash,
 	chunker: &mut StreamChunker<S>,
-) -> Result<(u64, GenericArray<u8, typenum::U16>, Hash), Error> {
+	compression_level: Option<i32>,
+	encryption: Option<&CustomerKey>,
+) -> Result<(u64, GenericArray<u8, typenum::U16>, Hash, Hash), Error> {
 	let tracer = opentelemetry::global::tracer("garage");
 
+	// Encrypted data cannot be compressed
+	let compression_level = compression_level.filter(|_| encryption.is_none());
+
 	let md5hasher = AsyncHasher::<Md5>::new();
 	let sha256hasher = AsyncHasher::<Sha256>::new();
 
-	futures::future::join(
+	let (_, _, first_block_stored) = futures::future::join3(
 		md5hasher.update(first_block.clone()),
 		sha256hasher.update(first_block.clone()),
+		encrypt_and_hash(first_block.clone(), encryption),
 	)
 	.with_context(Context::current_with_span(
-		tracer.start("Hash first block (md5, sha256)"),
+		tracer.start("Hash first block (md5, sha256, blake2)"),
 	))
 	.await;
+	let (first_block_hash, first_block_data) = first_block_stored?;
 
 	let mut next_offset = first_block.len();
-	let mut put_curr_version_block = put_block_meta(
+	let mut put_curr_block = put_block_and_meta(
 		garage,
 		version,
 		part_number,
 		0,
 		first_block_hash,
+		first_block_data,
 		first_block.len() as u64,
+		compression_level,
 	);
-	let mut put_curr_block = garage
-		.block_manager
-		.rpc_put_block(first_block_hash, first_block);
 
 	loop {
-		let (_, _, next_block) = futures::try_join!(
-			put_curr_block.map_err(Error::from),
-			put_curr_version_block.map_err(Error::from),
-			chunker.next(),
-		)?;
+		let (_, next_block) = futures::try_join!(put_curr_block, chunker.next())?;
 		if let Some(block) = next_block {
-			let (_, _, block_hash) = futures::future::join3(
+			let (_, _, block_stored) = futures::future::join3(
 				md5hasher.update(block.clone()),
 				sha256hasher.update(block.clone()),
-				async_blake2sum(block.clone()),
+				encrypt_and_hash(block.clone(), encryption),
 			)
 			.with_context(Context::current_with_span(
 				tracer.start("Hash block (md5, sha256, blake2)"),
 			))
 			.await;
+			let (block_hash, block_data) = block_stored?;
 			let block_len = block.len();
-			put_curr_version_block = put_block_meta(
+			put_curr_block = put_block_and_meta(
 				garage,
 				version,
 				part_number,
 				next_offset as u64,
 				block_hash,
+				block_data,
 				block_len as u64,
+				compression_level,
 			);
-			put_curr_block = garage.block_manager.rpc_put_block(block_hash, block);
 			next_offset += block_len;
 		} else {
 			break;
@@ -348,7 +588,46 @@ async fn read_and_put_blocks<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 	let data_sha256sum = sha256hasher.finalize().await;
 	let data_sha256sum = Hash::try_from(&data_sha256sum[..]).unwrap();
 
-	Ok((total_size, data_md5sum, data_sha256sum))
+	Ok((total_size, data_md5sum, data_sha256sum, first_block_hash))
+}
+
+/// Encrypt a block of an object encrypted with a key provided by the client,
+/// and compute the hash of the block as it is stored
+async fn encrypt_and_hash(
+	block: Bytes,
+	encryption: Option<&CustomerKey>,
+) -> Result<(Hash, Bytes), Error> {
+	let block = match encryption {
+		Some(key) => key.encrypt(&block)?,
+		None => block,
+	};
+	let hash = async_blake2sum(block.clone()).await;
+	Ok((hash, block))
+}
+
+/// Add a block to a version, and send it to the storage nodes unless they already have it.
+/// `size` is the size of the data of the block, before its encryption if any.
+#[allow(clippy::too_many_arguments)]
+async fn put_block_and_meta(
+	garage: &Garage,
+	version: &Version,
+	part_number: u64,
+	offset: u64,
+	hash: Hash,
+	block: Bytes,
+	size: u64,
+	compression_level: Option<i32>,
+) -> Result<(), Error> {
+	// The block is referenced while checking whether the storage nodes already
+	// have it, the upload fails if that reference cannot be written
+	futures::try_join!(
+		put_block_meta(garage, version, part_number, offset, hash, size).map_err(Error::from),
+		garage
+			.block_manager
+			.rpc_put_block_deduplicated(hash, block, compression_level)
+			.map_err(Error::from),
+	)?;
+	Ok(())
 }
 
 async fn put_block_meta(
@@ -386,6 +665,10 @@ struct StreamChunker<S: Stream<Item = Result<Bytes, Error>>> {
 	read_all: bool,
 	block_size: usize,
 	buf: BytesBuf,
+	checksum: Option<ExpectedChecksum>,
+	computed_checksum: Option<ObjectChecksum>,
+	max_size: Option<u64>,
+	read_size: u64,
 }
 
 impl<S: Stream<Item = Result<Bytes, Error>> + Unpin> StreamChunker<S> {
@@ -395,17 +678,62 @@ impl<S: Stream<Item = Result<Bytes, Error>> + Unpin> StreamChunker<S> {
 			read_all: false,
 			block_size,
 			buf: BytesBuf::new(),
+			checksum: None,
+			computed_checksum: None,
+			max_size: None,
+			read_size: 0,
 		}
 	}
 
+	/// Compute the checksum of the whole stream, failing when reaching its end
+	/// if it does not match the expected value
+	fn with_checksum(mut self, checksum: Option<ExpectedChecksum>) -> Self {
+		self.checksum = checksum;
+		self
+	}
+
+	/// Fail with `EntityTooLarge` as soon as more than `max_size` bytes are read
+	fn with_max_size(mut self, max_size: Option<u64>) -> Self {
+		self.max_size = max_size;
+		self
+	}
+
+	/// Checksum of the whole stream, once it has been read entirely
+	fn checksum(&self) -> Option<ObjectChecksum> {
+		self.computed_checksum.clone()
+	}
+
 	async fn next(&mut self) -> Result<Option<Bytes>, Error> {
 		while !self.read_all && self.buf.len() < self.block_size {
 			if let Some(block) = self.stream.next().await {
 				let bytes = block?;
 				trace!("Body next: {} bytes", bytes.len());
+				self.read_size += bytes.len() as u64;
+				if matches!(self.max_size, Some(m) if self.read_size > m) {
+					return Err(Error::EntityTooLarge);
+				}
+				if let Some(checksum) = &mut self.checksum {
+					checksum.hasher.update(&bytes);
+				}
 				self.buf.extend(bytes);
 			} else {
 				self.read_all = true;
+				if let Some(checksum) = self.checksum.take() {
+					let value = checksum.hasher.finalize();
+					if let Some(expected) = &checksum.value {
+						if *expected != value {
+							return Err(Error::InvalidChecksum(format!(
+								"Value for {} header is invalid",
+								checksum.hasher.header_name()
+							)));
+						}
+						trace!("Successfully validated {}", checksum.hasher.header_name());
+					}
+					self.computed_checksum = Some(ObjectChecksum {
+						algorithm: checksum.hasher.algorithm(),
+						value,
+					});
+				}
 			}
 		}
 
@@ -417,15 +745,15 @@ impl<S: Stream<Item = Result<Bytes, Error>> + Unpin> StreamChunker<S> {
 	}
 }
 
-pub fn put_response(version_uuid: Uuid, md5sum_hex: String) -> Response<Body> {
+pub fn put_response(version_id: String, md5sum_hex: String) -> Response<Body> {
 	Response::builder()
-		.header("x-amz-version-id", hex::encode(version_uuid))
+		.header("x-amz-version-id", version_id)
 		.header("ETag", format!("\"{}\"", md5sum_hex))
 		.body(Body::from(vec![]))
 		.unwrap()
 }
 
-struct InterruptedCleanup(Option<(Arc<Garage>, Uuid, String, Uuid, u64)>);
+struct InterruptedCleanup(Option<(Arc<Garage>, Uuid, String, Uuid, u64, bool)>);
 
 impl InterruptedCleanup {
 	fn cancel(&mut self) {
@@ -434,12 +762,17 @@ impl InterruptedCleanup {
 }
 impl Drop for InterruptedCleanup {
 	fn drop(&mut self) {
-		if let Some((garage, bucket_id, key, version_uuid, version_ts)) = self.0.take() {
+		if let Some((garage, bucket_id, key, version_uuid, version_ts, versioned)) = self.0.take() {
 			tokio::spawn(async move {
 				let object_version = ObjectVersion {
 					uuid: version_uuid,
 					timestamp: version_ts,
 					state: ObjectVersionState::Aborted,
+					versioned,
+					lock: ObjectVersionLock::default(),
+					tags: Default::default(),
+					storage_class: Default::default(),
+					restore: Default::default(),
 				};
 				let object = Object::new(bucket_id, key, vec![object_version]);
 				if let Err(e) = garage.object_table.insert(&object).await {
@@ -456,17 +789,41 @@ pub async fn handle_create_multipart_upload(
 	garage: Arc<Garage>,
 	req: &Request<Body>,
 	bucket_name: &str,
-	bucket_id: Uuid,
+	bucket: &Bucket,
 	key: &str,
 ) -> Result<Response<Body>, Error> {
+	check_encryption_required(bucket, req.headers())?;
+
+	let bucket_id = bucket.id;
 	let version_uuid = gen_uuid();
-	let headers = get_headers(req.headers())?;
+	let mut headers = get_headers(req.headers())?;
+	let encryption = CustomerKey::from_request_headers(req.headers())?;
+	let lock = get_object_lock(bucket, req.headers())?;
+	let tags = get_tagging_header(req.headers())?;
+	let storage_class = get_storage_class(req.headers())?;
+
+	// The algorithm of the checksums of the parts is kept until the upload is completed
+	let checksum_algorithm = match req.headers().get(CHECKSUM_ALGORITHM_HEADER) {
+		Some(algorithm) => Some(parse_checksum_algorithm(algorithm)?),
+		None => None,
+	};
+	if let Some(algorithm) = checksum_algorithm {
+		headers.other.insert(
+			CHECKSUM_ALGORITHM_HEADER.to_string(),
+			algorithm.as_str().to_string(),
+		);
+	}
 
 	// Create object in object table
 	let object_version = ObjectVersion {
 		uuid: version_uuid,
 		timestamp: now_msec(),
 		state: ObjectVersionState::Uploading(headers),
+		versioned: bucket.versioning_enabled(),
+		lock,
+		tags: Lww::new(tags),
+		storage_class,
+		restore: Default::default(),
 	};
 	let object = Object::new(bucket_id, key.to_string(), vec![object_version]);
 	garage.object_table.insert(&object).await?;
@@ -487,54 +844,80 @@ pub async fn handle_create_multipart_upload(
 	};
 	let xml = s3_xml::to_xml_with_header(&result)?;
 
-	Ok(Response::new(Body::from(xml.into_bytes())))
+	let mut resp = Response::new(Body::from(xml.into_bytes()));
+	if let Some(key) = &encryption {
+		key.add_response_headers(resp.headers_mut());
+	}
+	if let Some(algorithm) = checksum_algorithm {
+		resp.headers_mut().insert(
+			CHECKSUM_ALGORITHM_HEADER,
+			HeaderValue::from_static(algorithm.as_str()),
+		);
+	}
+	Ok(resp)
 }
 
 pub async fn handle_put_part(
 	garage: Arc<Garage>,
 	req: Request<Body>,
-	bucket_id: Uuid,
+	bucket: &Bucket,
 	key: &str,
 	part_number: u64,
 	upload_id: &str,
 	content_sha256: Option<Hash>,
 ) -> Result<Response<Body>, Error> {
+	let bucket_id = bucket.id;
 	let version_uuid = decode_upload_id(upload_id)?;
+	let compression_level = get_compression_level(&garage, bucket, req.headers())?;
+	let encryption = CustomerKey::from_request_headers(req.headers())?;
 
 	let content_md5 = match req.headers().get("content-md5") {
 		Some(x) => Some(x.to_str()?.to_string()),
 		None => None,
 	};
+	let checksum = get_expected_checksum(req.headers())?;
 
-	// Read first chuck, and at the same time try to get object to see if it exists
+	// Get the object and version first: the checksum algorithm of the upload
+	// must be known before the body is read
 	let key = key.to_string();
-
-	let body = req.into_body().map_err(Error::from);
-	let mut chunker = StreamChunker::new(body, garage.config.block_size);
-
-	let (object, version, first_block) = futures::try_join!(
-		garage
-			.object_table
-			.get(&bucket_id, &key)
-			.map_err(Error::from),
-		garage
-			.version_table
-			.get(&version_uuid, &EmptyKey)
-			.map_err(Error::from),
-		chunker.next(),
+	let (object, version) = futures::try_join!(
+		garage.object_table.get(&bucket_id, &key),
+		garage.version_table.get(&version_uuid, &EmptyKey),
 	)?;
 
 	// Check object is valid and multipart block can be accepted
-	let first_block = first_block.ok_or_bad_request("Empty body")?;
 	let object = object.ok_or_bad_request("Object not found")?;
 
-	if !object
+	let upload_headers = object
 		.versions()
 		.iter()
-		.any(|v| v.uuid == version_uuid && v.is_uploading())
-	{
-		return Err(Error::NoSuchUpload);
-	}
+		.find(|v| v.uuid == version_uuid)
+		.and_then(|v| match &v.state {
+			ObjectVersionState::Uploading(headers) => Some(headers),
+			_ => None,
+		})
+		.ok_or(Error::NoSuchUpload)?;
+	check_upload_encryption_required(bucket, upload_headers)?;
+
+	// The parts of an upload encrypted with SSE-C must all be encrypted with its key
+	let encryption = CustomerKey::check(encryption, upload_headers)?;
+
+	// The parts of an upload created with a checksum algorithm are checksummed
+	// with it, even when the client gives no checksum
+	let upload_checksum_algorithm = upload_headers
+		.other
+		.get(CHECKSUM_ALGORITHM_HEADER)
+		.and_then(|a| ChecksumAlgorithm::parse(a));
+	let checksum = match (checksum, upload_checksum_algorithm) {
+		(Some(c), Some(a)) if c.hasher.algorithm() != a => {
+			return Err(Error::bad_request(format!(
+				"The checksum of the part must be computed with {}, the checksum algorithm of the upload",
+				a.as_str()
+			)));
+		}
+		(None, Some(a)) => Some(ExpectedChecksum::compute(a)?),
+		(c, _) => c,
+	};
 
 	// Check part hasn't already been uploaded
 	if let Some(v) = version {
@@ -546,18 +929,21 @@ pub async fn handle_put_part(
 		}
 	}
 
+	let body = req.into_body().map_err(Error::from);
+	let mut chunker = StreamChunker::new(body, garage.config.block_size).with_checksum(checksum);
+	let first_block = chunker.next().await?.ok_or_bad_request("Empty body")?;
+
 	// Copy block to store
 	let version = Version::new(version_uuid, bucket_id, key, false);
 
-	let first_block_hash = async_blake2sum(first_block.clone()).await;
-
-	let (_, data_md5sum, data_sha256sum) = read_and_put_blocks(
+	let (_, data_md5sum, data_sha256sum, _) = read_and_put_blocks(
 		&garage,
 		&version,
 		part_number,
 		first_block,
-		first_block_hash,
 		&mut chunker,
+		compression_level,
+		encryption.as_ref(),
 	)
 	.await?;
 
@@ -569,18 +955,26 @@ pub async fn handle_put_part(
 		content_sha256,
 	)?;
 
-	// Store part etag in version
-	let data_md5sum_hex = hex::encode(data_md5sum);
+	// Store part etag and checksum in version
+	let etag = make_etag(hex::encode(data_md5sum), encryption.as_ref());
+	let checksum = chunker.checksum();
 	let mut version = version;
-	version
-		.parts_etags
-		.put(part_number, data_md5sum_hex.clone());
+	version.parts_etags.put(part_number, etag.clone());
+	if let Some(checksum) = &checksum {
+		version.parts_checksums.put(part_number, checksum.clone());
+	}
 	garage.version_table.insert(&version).await?;
 
-	let response = Response::builder()
-		.header("ETag", format!("\"{}\"", data_md5sum_hex))
+	let mut response = Response::builder()
+		.header("ETag", format!("\"{}\"", etag))
 		.body(Body::empty())
 		.unwrap();
+	if let Some(key) = &encryption {
+		key.add_response_headers(response.headers_mut());
+	}
+	if let Some(checksum) = &checksum {
+		insert_checksum_header(response.headers_mut(), checksum);
+	}
 	Ok(response)
 }
 
@@ -629,10 +1023,11 @@ pub async fn handle_complete_multipart_upload(
 		return Err(Error::bad_request("No data was uploaded"));
 	}
 
-	let headers = match object_version.state {
+	let mut headers = match object_version.state {
 		ObjectVersionState::Uploading(headers) => headers,
 		_ => unreachable!(),
 	};
+	headers.other.remove(CHECKSUM_ALGORITHM_HEADER);
 
 	// Check that part numbers are an increasing sequence.
 	// (it doesn't need to start at 1 nor to be a continuous sequence,
@@ -675,6 +1070,15 @@ pub async fn handle_complete_multipart_upload(
 		return Err(Error::InvalidPart);
 	}
 
+	// Check the checksums of the parts given in the request, if any
+	for part in body_list_of_parts.iter() {
+		if let Some(checksum) = &part.checksum {
+			if version.parts_checksums.get(&part.part_number) != Some(checksum) {
+				return Err(Error::InvalidPart);
+			}
+		}
+	}
+
 	// Check that all blocks belong to one of the parts
 	let block_parts = version
 		.blocks
@@ -701,6 +1105,7 @@ pub async fn handle_complete_multipart_upload(
 		etag_md5_hasher.update(etag.as_bytes());
 	}
 	let etag = format!("{}-{}", hex::encode(etag_md5_hasher.finalize()), num_parts);
+	let checksum = multipart_checksum(version.parts_checksums.items(), num_parts)?;
 
 	// Calculate total size of final object
 	let total_size = version.blocks.items().iter().map(|x| x.1.size).sum();
@@ -719,24 +1124,87 @@ pub async fn handle_complete_multipart_upload(
 			headers,
 			size: total_size,
 			etag: etag.clone(),
+			checksum: checksum.clone(),
 		},
 		version.blocks.items()[0].1.hash,
 	));
 
+	let version_id = object_version.version_id();
 	let final_object = Object::new(bucket.id, key.clone(), vec![object_version]);
 	garage.object_table.insert(&final_object).await?;
 
 	// Send response saying ok we're done
+	let checksum = checksum.as_ref().map(checksum_to_xml).unwrap_or_default();
 	let result = s3_xml::CompleteMultipartUploadResult {
 		xmlns: (),
 		location: None,
 		bucket: s3_xml::Value(bucket_name.to_string()),
 		key: s3_xml::Value(key),
 		etag: s3_xml::Value(format!("\"{}\"", etag)),
+		checksum_crc32: checksum.checksum_crc32,
+		checksum_crc32c: checksum.checksum_crc32c,
+		checksum_sha1: checksum.checksum_sha1,
+		checksum_sha256: checksum.checksum_sha256,
 	};
 	let xml = s3_xml::to_xml_with_header(&result)?;
 
-	Ok(Response::new(Body::from(xml.into_bytes())))
+	Ok(Response::builder()
+		.header("x-amz-version-id", version_id)
+		.body(Body::from(xml.into_bytes()))?)
+}
+
+/// Checksum of an object uploaded with a multipart upload, when all its parts have
+/// a checksum computed with the same algorithm: the checksum of the concatenation
+/// of the checksums of its parts, followed by its number of parts
+fn multipart_checksum(
+	parts_checksums: &[(u64, ObjectChecksum)],
+	num_parts: usize,
+) -> Result<Option<ObjectChecksum>, Error> {
+	let algorithm = match parts_checksums.first() {
+		Some((_, checksum)) => checksum.algorithm,
+		None => return Ok(None),
+	};
+	if parts_checksums.len() != num_parts
+		|| parts_checksums
+			.iter()
+			.any(|(_, checksum)| checksum.algorithm != algorithm)
+	{
+		return Ok(None);
+	}
+
+	let mut hasher = PayloadChecksum::new(algorithm.header_name())?;
+	for (_, checksum) in parts_checksums.iter() {
+		let digest = BASE64_STANDARD
+			.decode(&checksum.value)
+			.ok_or_internal_error("Invalid checksum of part")?;
+		hasher.update(&digest);
+	}
+	Ok(Some(ObjectChecksum {
+		algorithm,
+		value: format!("{}-{}", hasher.finalize(), num_parts),
+	}))
+}
+
+pub(crate) fn checksum_to_xml(checksum: &ObjectChecksum) -> s3_xml::Checksum {
+	let value = Some(s3_xml::Value(checksum.value.clone()));
+	match checksum.algorithm {
+		ChecksumAlgorithm::Crc32 => s3_xml::Checksum {
+			checksum_crc32: value,
+			..Default::default()
+		},
+		ChecksumAlgorithm::Crc32c => s3_xml::Checksum {
+			checksum_crc32c: value,
+			..Default::default()
+		},
+		ChecksumAlgorithm::Sha1 => s3_xml::Checksum {
+			checksum_sha1: value,
+			..Default::default()
+		},
+		ChecksumAlgorithm::Sha256 => s3_xml::Checksum {
+			checksum_sha256: value,
+			..Default::default()
+		},
+	}
 }
 
 pub async fn handle_abort_multipart_upload(
@@ -802,11 +1270,31 @@ pub(crate) fn get_headers(headers: &HeaderMap<HeaderValue>) -> Result<ObjectVers
 		}
 	}
 
+	// aws-chunked is the encoding of the request body, not that of the object
+	if let Some(encoding) = other.remove(hyper::header::CONTENT_ENCODING.as_str()) {
+		let encoding = encoding
+			.split(',')
+			.map(str::trim)
+			.filter(|e| !e.is_empty() && *e != "aws-chunked")
+			.collect::<Vec<_>>()
+			.join(",");
+		if !encoding.is_empty() {
+			other.insert(hyper::header::CONTENT_ENCODING.to_string(), encoding);
+		}
+	}
+
+	// Preserve the requested server-side encryption, so that it is returned on reads
+	if let Some(algorithm) = get_sse_algorithm(headers)? {
+		other.insert(SSE_HEADER.to_string(), algorithm);
+	}
+
 	// Preserve x-amz-meta- headers
+	let mut user_meta_size = 0;
 	for (k, v) in headers.iter() {
-		if k.as_str().starts_with("x-amz-meta-") {
+		if let Some(name) = k.as_str().strip_prefix(USER_META_PREFIX) {
 			match v.to_str() {
 				Ok(v_str) => {
+					user_meta_size += name.len() + v_str.len();
 					other.insert(k.to_string(), v_str.to_string());
 				}
 				Err(e) => {
@@ -815,11 +1303,27 @@ pub(crate) fn get_headers(headers: &HeaderMap<HeaderValue>) -> Result<ObjectVers
 			}
 		}
 	}
+	if user_meta_size > MAX_USER_META_SIZE {
+		return Err(Error::MetadataTooLarge);
+	}
 
-	Ok(ObjectVersionHeaders {
+	let mut ret = ObjectVersionHeaders {
 		content_type,
 		other,
-	})
+	};
+
+	// For SSE-C, the MD5 of the key is kept to check the key given on reads
+	if let Some(key) = CustomerKey::from_request_headers(headers)? {
+		if ret.other.contains_key(SSE_HEADER) {
+			return Err(Error::bad_request(format!(
+				"The {} header cannot be given with a key provided by the client (SSE-C)",
+				SSE_HEADER
+			)));
+		}
+		key.add_to_headers(&mut ret);
+	}
+
+	Ok(ret)
 }
 
 pub fn decode_upload_id(id: &str) -> Result<Uuid, Error> {
@@ -836,6 +1340,7 @@ pub fn decode_upload_id(id: &str) -> Result<Uuid, Error> {
 struct CompleteMultipartUploadPart {
 	etag: String,
 	part_number: u64,
+	checksum: Option<ObjectChecksum>,
 }
 
 fn parse_complete_multipart_upload_body(
@@ -861,9 +1366,20 @@ fn parse_complete_multipart_upload_body(
 				.children()
 				.find(|e| e.has_tag_name("PartNumber"))?
 				.text()?;
+			let checksum = ChecksumAlgorithm::ALL.iter().find_map(|algorithm| {
+				let tag_name = format!("Checksum{}", algorithm.as_str());
+				let value = item
+					.children()
+					.find(|e| e.has_tag_name(tag_name.as_str()))?;
+				Some(ObjectChecksum {
+					algorithm: *algorithm,
+					value: value.text()?.trim().to_string(),
+				})
+			});
 			parts.push(CompleteMultipartUploadPart {
 				etag: etag.trim_matches('"').to_string(),
 				part_number: part_number.parse().ok()?,
+				checksum,
 			});
 		} else {
 			return None;
@@ -0,0 +1,175 @@
+use quick_xml::de::from_reader;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use hyper::header::{HeaderMap, HeaderValue};
+use hyper::{Body, Request, Response, StatusCode};
+use serde::Deserialize;
+
+use crate::s3::error::*;
+use crate::s3::xml::IntValue;
+use crate::signature::verify_signed_content;
+
+use garage_model::garage::Garage;
+use garage_model::s3::object_table::*;
+use garage_util::data::*;
+use garage_util::time::*;
+
+pub const STORAGE_CLASS_HEADER: &str = "x-amz-storage-class";
+pub const RESTORE_HEADER: &str = "x-amz-restore";
+
+/// Duration of a day of restore, in msec
+const DAY_MSEC: u64 = 24 * 3600 * 1000;
+
+pub async fn handle_restore_object(
+	garage: Arc<Garage>,
+	bucket_id: Uuid,
+	key: &str,
+	version_id: Option<&str>,
+	req: Request<Body>,
+	content_sha256: Option<Hash>,
+) -> Result<Response<Body>, Error> {
+	let body = hyper::body::to_bytes(req.into_body()).await?;
+
+	if let Some(content_sha256) = content_sha256 {
+		verify_signed_content(content_sha256, &body[..])?;
+	}
+
+	let restore_request: RestoreRequest = from_reader(&body as &[u8])?;
+	let days = match restore_request.days {
+		Some(IntValue(d)) if d > 0 => d as u64,
+		_ => {
+			return Err(Error::bad_request(
+				"RestoreRequest must specify a positive number of Days",
+			))
+		}
+	};
+
+	let object = garage
+		.object_table
+		.get(&bucket_id, &key.to_string())
+		.await?
+		.ok_or(Error::NoSuchKey)?;
+	let version = match version_id {
+		Some(version_id) => object
+			.find_version(version_id)
+			.ok_or(Error::NoSuchVersion)?,
+		None => object.current_data_version().ok_or(Error::NoSuchKey)?,
+	};
+	if !version.is_data() {
+		return Err(Error::bad_request("Delete markers cannot be restored"));
+	}
+	if version.storage_class != StorageClass::Glacier {
+		return Err(Error::InvalidObjectState(format!(
+			"Restore is not allowed for objects of the {} storage class",
+			version.storage_class.as_str()
+		)));
+	}
+
+	let mut version = version.clone();
+	let now = now_msec();
+	let status = match *version.restore.get() {
+		ObjectRestoreStatus::Pending { .. } => return Err(Error::RestoreAlreadyInProgress),
+		// The object is already readable: only extend the restore window
+		ObjectRestoreStatus::Available { until } if until > now => {
+			version.restore.update(ObjectRestoreStatus::Available {
+				until: now + days * DAY_MSEC,
+			});
+			StatusCode::OK
+		}
+		_ => {
+			version
+				.restore
+				.update(ObjectRestoreStatus::Pending { days });
+			StatusCode::ACCEPTED
+		}
+	};
+
+	let object = Object::new(bucket_id, key.into(), vec![version.clone()]);
+	garage.object_table.insert(&object).await?;
+	if status == StatusCode::ACCEPTED {
+		garage.restore_queue.push(
+			bucket_id,
+			key,
+			version.uuid,
+			garage.config.glacier_restore_delay_secs,
+		)?;
+	}
+
+	Ok(Response::builder()
+		.status(status)
+		.header("x-amz-version-id", version.version_id())
+		.body(Body::empty())?)
+}
+
+/// Get the storage class given in the x-amz-storage-class header of a request
+pub(crate) fn get_storage_class(headers: &HeaderMap<HeaderValue>) -> Result<StorageClass, Error> {
+	match headers.get(STORAGE_CLASS_HEADER) {
+		Some(v) => {
+			let v = v.to_str()?;
+			StorageClass::parse(v).ok_or_else(|| Error::InvalidStorageClass(v.to_string()))
+		}
+		None => Ok(StorageClass::Standard),
+	}
+}
+
+/// Return an InvalidObjectState error if the data of a version cannot be read
+/// because it is archived in the GLACIER storage class and not restored
+pub(crate) fn check_object_readable(version: &ObjectVersion) -> Result<(), Error> {
+	if version.is_readable(now_msec()) {
+		Ok(())
+	} else {
+		Err(Error::InvalidObjectState(
+			"The object is archived and must be restored before it can be read".into(),
+		))
+	}
+}
+
+/// Add the x-amz-storage-class and x-amz-restore headers to the response
+/// to a GetObject or HeadObject request on a GLACIER object
+pub(crate) fn add_restore_headers(
+	resp: http::response::Builder,
+	version: &ObjectVersion,
+) -> http::response::Builder {
+	if version.storage_class == StorageClass::Standard {
+		return resp;
+	}
+	let resp = resp.header(STORAGE_CLASS_HEADER, version.storage_class.as_str());
+	match version.restore.get() {
+		ObjectRestoreStatus::None => resp,
+		ObjectRestoreStatus::Pending { .. } => {
+			resp.header(RESTORE_HEADER, "ongoing-request=\"true\"")
+		}
+		ObjectRestoreStatus::Available { until } => {
+			let expiry = httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_millis(*until));
+			resp.header(
+				RESTORE_HEADER,
+				format!("ongoing-request=\"false\", expiry-date=\"{}\"", expiry),
+			)
+		}
+	}
+}
+
+#[derive(Debug, Deserialize)]
+struct RestoreRequest {
+	#[serde(rename = "Days")]
+	days: Option<IntValue>,
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_parse_restore_request() {
+		let xml = br#"<RestoreRequest xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+			<Days>2</Days>
+			<GlacierJobParameters><Tier>Standard</Tier></GlacierJobParameters>
+		</RestoreRequest>"#;
+		let req: RestoreRequest = from_reader(&xml[..]).unwrap();
+		assert_eq!(req.days, Some(IntValue(2)));
+
+		let req: RestoreRequest = from_reader(&b"<RestoreRequest></RestoreRequest>"[..]).unwrap();
+		assert_eq!(req.days, None);
+	}
+}
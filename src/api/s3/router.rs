@@ -3,6 +3,8 @@ use std::borrow::Cow;
 use hyper::header::HeaderValue;
 use hyper::{HeaderMap, Method, Request};
 
+use garage_model::permission::S3Op;
+
 use crate::helpers::Authorization;
 use crate::router_macros::{generateQueryParameters, router_match};
 use crate::s3::error::*;
@@ -50,6 +52,10 @@ pub enum Endpoint {
 	},
 	DeleteBucketLifecycle {
 	},
+	// DeleteBucketLogging is not an S3 endpoint: on AWS, logging is disabled
+	// with a PutBucketLogging request with an empty body
+	DeleteBucketLogging {
+	},
 	DeleteBucketMetricsConfiguration {
 		id: String,
 	},
@@ -130,6 +136,10 @@ pub enum Endpoint {
 		key: String,
 		version_id: Option<String>,
 	},
+	GetObjectAttributes {
+		key: String,
+		version_id: Option<String>,
+	},
 	GetObjectLegalHold {
 		key: String,
 		version_id: Option<String>,
@@ -360,6 +370,7 @@ impl Endpoint {
 				EMPTY if upload_id => ListParts (query::upload_id, opt_parse::max_parts, opt_parse::part_number_marker),
 				EMPTY => GetObject (query_opt::version_id, opt_parse::part_number),
 				ACL => GetObjectAcl (query_opt::version_id),
+				ATTRIBUTES => GetObjectAttributes (query_opt::version_id),
 				LEGAL_HOLD => GetObjectLegalHold (query_opt::version_id),
 				RETENTION => GetObjectRetention (query_opt::version_id),
 				TAGGING => GetObjectTagging (query_opt::version_id),
@@ -504,6 +515,7 @@ impl Endpoint {
 				INTELLIGENT_TIERING => DeleteBucketIntelligentTieringConfiguration (query::id),
 				INVENTORY => DeleteBucketInventoryConfiguration (query::id),
 				LIFECYCLE => DeleteBucketLifecycle,
+				LOGGING => DeleteBucketLogging,
 				METRICS => DeleteBucketMetricsConfiguration (query::id),
 				OWNERSHIP_CONTROLS => DeleteBucketOwnershipControls,
 				POLICY => DeleteBucketPolicy,
@@ -516,7 +528,6 @@ impl Endpoint {
 	}
 
 	/// Get the key the request target. Returns None for requests which don't use a key.
-	#[allow(dead_code)]
 	pub fn get_key(&self) -> Option<&str> {
 		router_match! {
 			@extract
@@ -531,6 +542,7 @@ impl Endpoint {
 				DeleteObjectTagging,
 				GetObject,
 				GetObjectAcl,
+				GetObjectAttributes,
 				GetObjectLegalHold,
 				GetObjectRetention,
 				GetObjectTagging,
@@ -551,6 +563,12 @@ impl Endpoint {
 	}
 
 	/// Get the kind of authorization which is required to perform the operation.
+	/// Operation whose permission is needed to call this endpoint,
+	/// for the endpoints that work on a bucket and that Garage implements
+	pub fn s3_op(&self) -> Option<S3Op> {
+		self.name().parse().ok()
+	}
+
 	pub fn authorization_type(&self) -> Authorization {
 		if let Endpoint::ListBuckets = self {
 			return Authorization::None;
@@ -567,18 +585,15 @@ impl Endpoint {
 				GetBucketInventoryConfiguration,
 				GetBucketLifecycleConfiguration,
 				GetBucketLocation,
-				GetBucketLogging,
 				GetBucketMetricsConfiguration,
-				GetBucketNotificationConfiguration,
 				GetBucketOwnershipControls,
-				GetBucketPolicy,
-				GetBucketPolicyStatus,
 				GetBucketReplication,
 				GetBucketRequestPayment,
 				GetBucketTagging,
 				GetBucketVersioning,
 				GetObject,
 				GetObjectAcl,
+				GetObjectAttributes,
 				GetObjectLegalHold,
 				GetObjectLockConfiguration,
 				GetObjectRetention,
@@ -610,6 +625,15 @@ impl Endpoint {
 				GetBucketCors,
 				PutBucketCors,
 				DeleteBucketCors,
+				GetBucketLogging,
+				PutBucketLogging,
+				DeleteBucketLogging,
+				GetBucketNotificationConfiguration,
+				PutBucketNotificationConfiguration,
+				GetBucketPolicy,
+				GetBucketPolicyStatus,
+				PutBucketPolicy,
+				DeleteBucketPolicy,
 			]
 		};
 		if readonly {
@@ -628,6 +652,7 @@ generateQueryParameters! {
 		"accelerate" => ACCELERATE,
 		"acl" => ACL,
 		"analytics" => ANALYTICS,
+		"attributes" => ATTRIBUTES,
 		"cors" => CORS,
 		"delete" => DELETE,
 		"encryption" => ENCRYPTION,
@@ -699,6 +724,22 @@ mod tests {
 		Endpoint::from_request(&req, bucket).unwrap()
 	}
 
+	/// The permission flag that gives an operation must be
+	/// the one that was required for its endpoint before
+	fn check_s3_op_group(endpoint: &Endpoint) {
+		use garage_model::permission::S3OpGroup;
+
+		if let Some(op) = endpoint.s3_op() {
+			let group = match endpoint.authorization_type() {
+				Authorization::Read => S3OpGroup::Read,
+				Authorization::Write => S3OpGroup::Write,
+				Authorization::Owner => S3OpGroup::Owner,
+				_ => panic!("{} has no permission flag", endpoint.name()),
+			};
+			assert_eq!(op.group(), group, "{}", endpoint.name());
+		}
+	}
+
 	macro_rules! test_cases {
         ($($method:ident $uri:expr => $variant:ident )*) => {{
             $(
@@ -716,6 +757,7 @@ mod tests {
             );
 
             test_cases!{@auth $method $uri}
+            check_s3_op_group(&parse(test_cases!{@actual_method $method}, concat!("/my_bucket", $uri), None, None).0);
             )*
         }};
 
@@ -848,10 +890,11 @@ mod tests {
 			DELETE "/?inventory&id=list1" => DeleteBucketInventoryConfiguration
 			DELETE "/?inventory&id=Id" => DeleteBucketInventoryConfiguration
 			DELETE "/?lifecycle" => DeleteBucketLifecycle
+			OWNER_DELETE "/?logging" => DeleteBucketLogging
 			DELETE "/?metrics&id=ExampleMetrics" => DeleteBucketMetricsConfiguration
 			DELETE "/?metrics&id=Id" => DeleteBucketMetricsConfiguration
 			DELETE "/?ownershipControls" => DeleteBucketOwnershipControls
-			DELETE "/?policy" => DeleteBucketPolicy
+			OWNER_DELETE "/?policy" => DeleteBucketPolicy
 			DELETE "/?replication" => DeleteBucketReplication
 			DELETE "/?tagging" => DeleteBucketTagging
 			OWNER_DELETE "/?website" => DeleteBucketWebsite
@@ -872,13 +915,13 @@ mod tests {
 			GET "/?inventory&id=Id" => GetBucketInventoryConfiguration
 			GET "/?lifecycle" => GetBucketLifecycleConfiguration
 			GET "/?location" => GetBucketLocation
-			GET "/?logging" => GetBucketLogging
+			OWNER_GET "/?logging" => GetBucketLogging
 			GET "/?metrics&id=Documents" => GetBucketMetricsConfiguration
 			GET "/?metrics&id=Id" => GetBucketMetricsConfiguration
-			GET "/?notification" => GetBucketNotificationConfiguration
+			OWNER_GET "/?notification" => GetBucketNotificationConfiguration
 			GET "/?ownershipControls" => GetBucketOwnershipControls
-			GET "/?policy" => GetBucketPolicy
-			GET "/?policyStatus" => GetBucketPolicyStatus
+			OWNER_GET "/?policy" => GetBucketPolicy
+			OWNER_GET "/?policyStatus" => GetBucketPolicyStatus
 			GET "/?replication" => GetBucketReplication
 			GET "/?requestPayment" => GetBucketRequestPayment
 			GET "/?tagging" => GetBucketTagging
@@ -891,6 +934,8 @@ mod tests {
 			GET "/my-image.jpg?acl" => GetObjectAcl
 			GET "/my-image.jpg?versionId=3/L4kqtJlcpXroDVBH40Nr8X8gdRQBpUMLUo&acl" => GetObjectAcl
 			GET "/{Key+}?acl&versionId=VersionId" => GetObjectAcl
+			GET "/example-object?attributes" => GetObjectAttributes
+			GET "/{Key+}?attributes&versionId=VersionId" => GetObjectAttributes
 			GET "/{Key+}?legal-hold&versionId=VersionId" => GetObjectLegalHold
 			GET "/?object-lock" => GetObjectLockConfiguration
 			GET "/{Key+}?retention&versionId=VersionId" => GetObjectRetention
@@ -947,12 +992,12 @@ mod tests {
 			PUT "/?inventory&id=report1" => PutBucketInventoryConfiguration
 			PUT "/?inventory&id=Id" => PutBucketInventoryConfiguration
 			PUT "/?lifecycle" => PutBucketLifecycleConfiguration
-			PUT "/?logging" => PutBucketLogging
+			OWNER_PUT "/?logging" => PutBucketLogging
 			PUT "/?metrics&id=EntireBucket" => PutBucketMetricsConfiguration
 			PUT "/?metrics&id=Id" => PutBucketMetricsConfiguration
-			PUT "/?notification" => PutBucketNotificationConfiguration
+			OWNER_PUT "/?notification" => PutBucketNotificationConfiguration
 			PUT "/?ownershipControls" => PutBucketOwnershipControls
-			PUT "/?policy" => PutBucketPolicy
+			OWNER_PUT "/?policy" => PutBucketPolicy
 			PUT "/?replication" => PutBucketReplication
 			PUT "/?requestPayment" => PutBucketRequestPayment
 			PUT "/?tagging" => PutBucketTagging
@@ -0,0 +1,1145 @@
+//! Implementation of SelectObjectContent (S3 Select), which runs a SQL query
+//! on the records of a CSV or JSON object and streams back the results
+//! in the binary event stream format of AWS.
+use quick_xml::de::from_reader;
+use std::convert::TryInto;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use futures::stream::StreamExt;
+use hyper::{Body, Request, Response, StatusCode};
+use miniz_oxide::inflate::stream::{inflate, InflateState};
+use miniz_oxide::{DataFormat, MZError, MZFlush, MZStatus};
+use serde::Deserialize;
+use tokio::sync::mpsc;
+
+use crate::s3::encryption::CustomerKey;
+use crate::s3::error::*;
+use crate::s3::get::object_version_stream;
+use crate::s3::restore::check_object_readable;
+use crate::s3::select_sql::{OutputRecord, Query, QueryExec, Record, SqlError, Value as SqlValue};
+use crate::s3::xml::Value;
+use crate::signature::verify_signed_content;
+
+use garage_model::garage::Garage;
+use garage_util::data::*;
+
+/// Maximum size of a record of the input or of the output
+const MAX_RECORD_SIZE: usize = 1024 * 1024;
+
+/// Size of the output after which a Records event is sent
+const RECORDS_EVENT_SIZE: usize = 256 * 1024;
+
+/// Size of the chunks in which compressed objects are decompressed
+const DECOMPRESSED_CHUNK_SIZE: usize = 64 * 1024;
+
+pub async fn handle_select_object_content(
+	garage: Arc<Garage>,
+	bucket_id: Uuid,
+	key: &str,
+	select_type: &str,
+	req: Request<Body>,
+	content_sha256: Option<Hash>,
+) -> Result<Response<Body>, Error> {
+	if select_type != "2" {
+		return Err(Error::bad_request("select-type must be 2"));
+	}
+	let encryption = CustomerKey::from_request_headers(req.headers())?;
+
+	let body = hyper::body::to_bytes(req.into_body()).await?;
+
+	if let Some(content_sha256) = content_sha256 {
+		verify_signed_content(content_sha256, &body[..])?;
+	}
+
+	let select_request: SelectObjectContentRequest = from_reader(&body as &[u8])?;
+	let progress = select_request
+		.request_progress
+		.as_ref()
+		.and_then(|p| p.enabled.as_ref())
+		.map(|e| e.0.eq_ignore_ascii_case("true"))
+		.unwrap_or(false);
+	let mut processor = SelectProcessor::new(&select_request)?;
+
+	let object = garage
+		.object_table
+		.get(&bucket_id, &key.to_string())
+		.await?
+		.ok_or(Error::NoSuchKey)?;
+	let version = object.current_data_version().ok_or(Error::NoSuchKey)?;
+	check_object_readable(version)?;
+	let mut object_stream = object_version_stream(garage.clone(), version, encryption).await?;
+
+	let (tx, rx) = mpsc::channel::<Result<Bytes, std::io::Error>>(2);
+
+	tokio::spawn(async move {
+		let result = async {
+			while let Some(chunk) = object_stream.next().await {
+				let chunk = chunk.map_err(|e| {
+					SqlError::new("InternalError", format!("Could not read object: {}", e))
+				})?;
+				processor.feed(&chunk)?;
+				loop {
+					if (processor.output.len() >= RECORDS_EVENT_SIZE || processor.is_done())
+						&& !send_records(&tx, &mut processor, progress).await
+					{
+						return Ok(());
+					}
+					if processor.is_done() || !processor.has_pending_input() {
+						break;
+					}
+					processor.feed(&[])?;
+				}
+				if processor.is_done() {
+					break;
+				}
+			}
+			processor.finish()?;
+			if !send_records(&tx, &mut processor, progress).await {
+				return Ok(());
+			}
+			let _ = tx.send(Ok(processor.stats_event())).await;
+			let _ = tx
+				.send(Ok(event_message(&[(":event-type", "End")], b"")))
+				.await;
+			Ok::<(), SqlError>(())
+		}
+		.await;
+
+		if let Err(e) = result {
+			let _ = tx.send(Ok(error_message(&e))).await;
+		}
+	});
+
+	let body_stream = tokio_stream::wrappers::ReceiverStream::new(rx);
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.body(Body::wrap_stream(body_stream))?)
+}
+
+/// Send the output that has not been sent yet as a Records event,
+/// followed by a Progress event if requested. Returns false if the
+/// client has closed the connection.
+async fn send_records(
+	tx: &mpsc::Sender<Result<Bytes, std::io::Error>>,
+	processor: &mut SelectProcessor,
+	progress: bool,
+) -> bool {
+	if !processor.output.is_empty() {
+		let payload = std::mem::take(&mut processor.output);
+		processor.bytes_returned += payload.len() as u64;
+		let event = event_message(
+			&[
+				(":event-type", "Records"),
+				(":content-type", "application/octet-stream"),
+			],
+			&payload,
+		);
+		if tx.send(Ok(event)).await.is_err() {
+			return false;
+		}
+	}
+	if progress && tx.send(Ok(processor.progress_event())).await.is_err() {
+		return false;
+	}
+	true
+}
+
+// ---- Processing of the records ----
+
+/// State of the processing of an object: parsing of the input into records,
+/// execution of the query and serialization of the output
+struct SelectProcessor {
+	/// Decoder of the object data if it is compressed
+	decoder: Option<GzipDecoder>,
+	input: InputReader,
+	exec: QueryExec,
+	output_format: OutputFormat,
+	records: Vec<Record>,
+	/// Serialized records that have not been sent yet
+	output: Vec<u8>,
+	bytes_scanned: u64,
+	bytes_processed: u64,
+	bytes_returned: u64,
+}
+
+impl SelectProcessor {
+	fn new(req: &SelectObjectContentRequest) -> Result<Self, Error> {
+		match req.expression_type.0.to_ascii_uppercase().as_str() {
+			"SQL" => (),
+			t => return Err(Error::bad_request(format!("Invalid ExpressionType: {}", t))),
+		}
+		if req.scan_range.is_some() {
+			return Err(Error::NotImplemented(
+				"ScanRange is not supported in SelectObjectContent".into(),
+			));
+		}
+		let query = Query::parse(&req.expression.0)?;
+		let input = &req.input_serialization;
+		let decoder = match input.compression_type.as_ref().map(|c| c.0.as_str()) {
+			None | Some("NONE") => None,
+			Some("GZIP") => Some(GzipDecoder::new()),
+			Some(c) => {
+				return Err(Error::NotImplemented(format!(
+					"Compression type {} is not supported in SelectObjectContent",
+					c
+				)))
+			}
+		};
+		Ok(Self {
+			decoder,
+			input: InputReader::new(input)?,
+			exec: QueryExec::new(query),
+			output_format: OutputFormat::new(&req.output_serialization)?,
+			records: vec![],
+			output: vec![],
+			bytes_scanned: 0,
+			bytes_processed: 0,
+			bytes_returned: 0,
+		})
+	}
+
+	fn is_done(&self) -> bool {
+		self.exec.is_done()
+	}
+
+	/// Feed a chunk of the object. If the object is compressed, decompression
+	/// stops once enough output is available to be sent: `feed` must then be
+	/// called with no data until `has_pending_input` returns false.
+	fn feed(&mut self, data: &[u8]) -> Result<(), SqlError> {
+		self.bytes_scanned += data.len() as u64;
+		match &mut self.decoder {
+			None => self.process_input(data),
+			Some(decoder) => {
+				decoder.push(data);
+				while self.output.len() < RECORDS_EVENT_SIZE
+					&& !self.is_done()
+					&& self.decompress_chunk()?
+				{}
+				Ok(())
+			}
+		}
+	}
+
+	fn has_pending_input(&self) -> bool {
+		self.decoder
+			.as_ref()
+			.map(|d| !d.needs_input)
+			.unwrap_or(false)
+	}
+
+	/// Decompress and process a chunk of the compressed data that has been fed,
+	/// returns false if more data is needed
+	fn decompress_chunk(&mut self) -> Result<bool, SqlError> {
+		match self.decoder.as_mut().map(|d| d.next_chunk()).transpose()? {
+			Some(Some(chunk)) => {
+				self.process_input(&chunk)?;
+				Ok(true)
+			}
+			_ => Ok(false),
+		}
+	}
+
+	fn process_input(&mut self, data: &[u8]) -> Result<(), SqlError> {
+		self.bytes_processed += data.len() as u64;
+		self.input.feed(data, &mut self.records)?;
+		self.process_records()
+	}
+
+	fn finish(&mut self) -> Result<(), SqlError> {
+		while !self.is_done() && self.decompress_chunk()? {}
+		if !self.is_done() {
+			if let Some(decoder) = &self.decoder {
+				decoder.finish()?;
+			}
+			self.input.finish(&mut self.records)?;
+			self.process_records()?;
+		}
+		if let Some(out) = self.exec.finish() {
+			self.output_format.write(&out, &mut self.output)?;
+		}
+		Ok(())
+	}
+
+	fn process_records(&mut self) -> Result<(), SqlError> {
+		for record in self.records.drain(..) {
+			if let Some(out) = self.exec.process(&record)? {
+				self.output_format.write(&out, &mut self.output)?;
+			}
+			if self.exec.is_done() {
+				break;
+			}
+		}
+		self.records.clear();
+		Ok(())
+	}
+
+	fn stats_xml(&self, tag: &str) -> String {
+		format!(
+			"<?xml version=\"1.0\" encoding=\"UTF-8\"?><{tag} xmlns=\"\"><BytesScanned>{}</BytesScanned><BytesProcessed>{}</BytesProcessed><BytesReturned>{}</BytesReturned></{tag}>",
+			self.bytes_scanned,
+			self.bytes_processed,
+			self.bytes_returned,
+			tag = tag,
+		)
+	}
+
+	fn stats_event(&self) -> Bytes {
+		event_message(
+			&[(":event-type", "Stats"), (":content-type", "text/xml")],
+			self.stats_xml("Stats").as_bytes(),
+		)
+	}
+
+	fn progress_event(&self) -> Bytes {
+		event_message(
+			&[(":event-type", "Progress"), (":content-type", "text/xml")],
+			self.stats_xml("Progress").as_bytes(),
+		)
+	}
+}
+
+fn too_large_record() -> SqlError {
+	SqlError::new(
+		"OverMaxRecordSize",
+		format!("Records cannot be larger than {} bytes", MAX_RECORD_SIZE),
+	)
+}
+
+/// Get the only character of a setting of the input or output serialization
+fn single_byte(v: &Option<Value>, name: &str, default: u8) -> Result<u8, Error> {
+	match v {
+		None => Ok(default),
+		Some(Value(s)) if s.len() == 1 => Ok(s.as_bytes()[0]),
+		Some(Value(s)) => Err(Error::bad_request(format!(
+			"{} must be a single ASCII character, got {:?}",
+			name, s
+		))),
+	}
+}
+
+enum InputReader {
+	Csv(CsvReader),
+	Json(JsonReader),
+}
+
+impl InputReader {
+	fn new(input: &InputSerialization) -> Result<Self, Error> {
+		match (&input.csv, &input.json, &input.parquet) {
+			(Some(csv), None, None) => Ok(InputReader::Csv(CsvReader::new(csv)?)),
+			(None, Some(json), None) => {
+				match json.type_.as_ref().map(|t| t.0.to_ascii_uppercase()) {
+					None => (),
+					Some(t) if t == "DOCUMENT" || t == "LINES" => (),
+					Some(t) => return Err(Error::bad_request(format!("Invalid JSON Type: {}", t))),
+				}
+				Ok(InputReader::Json(JsonReader { buf: vec![] }))
+			}
+			(None, None, Some(_)) => Err(Error::NotImplemented(
+				"Parquet objects are not supported in SelectObjectContent".into(),
+			)),
+			_ => Err(Error::bad_request(
+				"InputSerialization must specify exactly one of CSV, JSON or Parquet",
+			)),
+		}
+	}
+
+	fn feed(&mut self, data: &[u8], out: &mut Vec<Record>) -> Result<(), SqlError> {
+		match self {
+			InputReader::Csv(r) => r.feed(data, out),
+			InputReader::Json(r) => r.feed(data, out, false),
+		}
+	}
+
+	fn finish(&mut self, out: &mut Vec<Record>) -> Result<(), SqlError> {
+		match self {
+			InputReader::Csv(r) => r.finish(out),
+			InputReader::Json(r) => r.feed(&[], out, true),
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GzipStage {
+	Header,
+	Deflate,
+	Trailer,
+}
+
+/// Incremental gzip decoder. Like gunzip, it decodes the members of a
+/// multi-member file one after the other.
+struct GzipDecoder {
+	inflate: Box<InflateState>,
+	/// Compressed data, of which the first `pos` bytes have been decoded
+	buf: Vec<u8>,
+	pos: usize,
+	stage: GzipStage,
+	crc: crc32fast::Hasher,
+	size: u32,
+	/// All the data that has been pushed has been decoded
+	needs_input: bool,
+}
+
+impl GzipDecoder {
+	fn new() -> Self {
+		Self {
+			inflate: InflateState::new_boxed(DataFormat::Raw),
+			buf: vec![],
+			pos: 0,
+			stage: GzipStage::Header,
+			crc: crc32fast::Hasher::new(),
+			size: 0,
+			needs_input: true,
+		}
+	}
+
+	fn push(&mut self, data: &[u8]) {
+		self.buf.drain(..self.pos);
+		self.pos = 0;
+		self.buf.extend_from_slice(data);
+		self.needs_input = false;
+	}
+
+	/// Decode the next chunk of data, returns None if more input is needed
+	fn next_chunk(&mut self) -> Result<Option<Vec<u8>>, SqlError> {
+		loop {
+			let buf = &self.buf[self.pos..];
+			match self.stage {
+				GzipStage::Header => match gzip_header_len(buf)? {
+					Some(len) => {
+						self.pos += len;
+						self.inflate.reset(DataFormat::Raw);
+						self.crc = crc32fast::Hasher::new();
+						self.size = 0;
+						self.stage = GzipStage::Deflate;
+					}
+					None => break,
+				},
+				GzipStage::Deflate => {
+					let mut out = vec![0u8; DECOMPRESSED_CHUNK_SIZE];
+					let res = inflate(&mut self.inflate, buf, &mut out, MZFlush::None);
+					self.pos += res.bytes_consumed;
+					match res.status {
+						Ok(MZStatus::StreamEnd) => self.stage = GzipStage::Trailer,
+						Ok(_) | Err(MZError::Buf) => (),
+						Err(_) => return Err(invalid_compressed_data()),
+					}
+					if res.bytes_written > 0 {
+						out.truncate(res.bytes_written);
+						self.crc.update(&out);
+						self.size = self.size.wrapping_add(out.len() as u32);
+						return Ok(Some(out));
+					}
+					if self.stage == GzipStage::Deflate && res.bytes_consumed == 0 {
+						break;
+					}
+				}
+				GzipStage::Trailer => {
+					if buf.len() < 8 {
+						break;
+					}
+					let crc = u32::from_le_bytes(buf[0..4].try_into().unwrap());
+					let size = u32::from_le_bytes(buf[4..8].try_into().unwrap());
+					if crc != self.crc.clone().finalize() || size != self.size {
+						return Err(invalid_compressed_data());
+					}
+					self.pos += 8;
+					self.stage = GzipStage::Header;
+				}
+			}
+		}
+		self.needs_input = true;
+		Ok(None)
+	}
+
+	/// Check that the data ends at the end of a gzip member
+	fn finish(&self) -> Result<(), SqlError> {
+		if self.stage == GzipStage::Header && self.pos == self.buf.len() {
+			Ok(())
+		} else {
+			Err(invalid_compressed_data())
+		}
+	}
+}
+
+/// Get the length of the gzip member header (RFC 1952) at the beginning
+/// of the data, or None if it is not complete
+fn gzip_header_len(buf: &[u8]) -> Result<Option<usize>, SqlError> {
+	if buf.len() < 10 {
+		return Ok(None);
+	}
+	if buf[0..3] != [0x1f, 0x8b, 8] {
+		return Err(invalid_compressed_data());
+	}
+	let flags = buf[3];
+	let mut len = 10;
+	// FEXTRA
+	if flags & 0x04 != 0 {
+		if buf.len() < len + 2 {
+			return Ok(None);
+		}
+		len += 2 + u16::from_le_bytes([buf[len], buf[len + 1]]) as usize;
+	}
+	// FNAME and FCOMMENT, which are zero-terminated
+	for flag in [0x08, 0x10] {
+		if flags & flag != 0 {
+			match buf.get(len..).and_then(|b| b.iter().position(|c| *c == 0)) {
+				Some(i) => len += i + 1,
+				None => return Ok(None),
+			}
+		}
+	}
+	// FHCRC
+	if flags & 0x02 != 0 {
+		len += 2;
+	}
+	if buf.len() < len {
+		Ok(None)
+	} else {
+		Ok(Some(len))
+	}
+}
+
+fn invalid_compressed_data() -> SqlError {
+	SqlError::new(
+		"TruncatedInput",
+		"Object decompression failed. Check that the object is properly compressed using the format specified in the request.",
+	)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FileHeaderInfo {
+	Use,
+	Ignore,
+	None,
+}
+
+/// Incremental CSV parser. Quoted fields can contain field and record delimiters,
+/// and quotes are escaped by doubling them or with the QuoteEscapeCharacter.
+struct CsvReader {
+	field_delimiter: u8,
+	record_delimiter: u8,
+	quote: u8,
+	quote_escape: u8,
+	comments: Option<u8>,
+	header_info: FileHeaderInfo,
+	headers: Option<Arc<Vec<String>>>,
+	first_record: bool,
+
+	fields: Vec<String>,
+	field: Vec<u8>,
+	record_size: usize,
+	in_quotes: bool,
+	/// A quote was found in a quoted field: it either ends the field
+	/// or is the first character of a doubled quote
+	quote_pending: bool,
+	/// The QuoteEscapeCharacter was found in a quoted field
+	escape_pending: bool,
+	in_comment: bool,
+}
+
+impl CsvReader {
+	fn new(csv: &CsvInput) -> Result<Self, Error> {
+		let header_info = match csv
+			.file_header_info
+			.as_ref()
+			.map(|h| h.0.to_ascii_uppercase())
+		{
+			None => FileHeaderInfo::None,
+			Some(h) if h == "NONE" => FileHeaderInfo::None,
+			Some(h) if h == "USE" => FileHeaderInfo::Use,
+			Some(h) if h == "IGNORE" => FileHeaderInfo::Ignore,
+			Some(h) => return Err(Error::bad_request(format!("Invalid FileHeaderInfo: {}", h))),
+		};
+		let record_delimiter = match &csv.record_delimiter {
+			// Windows line endings are handled by removing the \r at the end of records
+			Some(Value(d)) if d == "\r\n" => b'\n',
+			d => single_byte(d, "RecordDelimiter", b'\n')?,
+		};
+		let quote = single_byte(&csv.quote_character, "QuoteCharacter", b'"')?;
+		let comments = match &csv.comments {
+			Some(Value(c)) if c.is_empty() => None,
+			c => Some(single_byte(c, "Comments", b'#')?),
+		};
+		Ok(Self {
+			field_delimiter: single_byte(&csv.field_delimiter, "FieldDelimiter", b',')?,
+			record_delimiter,
+			quote,
+			quote_escape: single_byte(&csv.quote_escape_character, "QuoteEscapeCharacter", quote)?,
+			comments,
+			header_info,
+			headers: None,
+			first_record: true,
+			fields: vec![],
+			field: vec![],
+			record_size: 0,
+			in_quotes: false,
+			quote_pending: false,
+			escape_pending: false,
+			in_comment: false,
+		})
+	}
+
+	fn feed(&mut self, data: &[u8], out: &mut Vec<Record>) -> Result<(), SqlError> {
+		for &b in data {
+			if self.in_comment {
+				if b == self.record_delimiter {
+					self.in_comment = false;
+					self.record_size = 0;
+				}
+				continue;
+			}
+
+			self.record_size += 1;
+			if self.record_size > MAX_RECORD_SIZE {
+				return Err(too_large_record());
+			}
+
+			if self.in_quotes {
+				if self.escape_pending {
+					self.escape_pending = false;
+					self.field.push(b);
+					continue;
+				} else if self.quote_pending {
+					self.quote_pending = false;
+					if b == self.quote {
+						self.field.push(b);
+						continue;
+					}
+					// The previous quote ended the quoted part of the field
+					self.in_quotes = false;
+				} else if b == self.quote_escape && self.quote_escape != self.quote {
+					self.escape_pending = true;
+					continue;
+				} else if b == self.quote {
+					self.quote_pending = true;
+					continue;
+				} else {
+					self.field.push(b);
+					continue;
+				}
+			}
+
+			if b == self.quote && self.field.is_empty() {
+				self.in_quotes = true;
+			} else if Some(b) == self.comments && self.fields.is_empty() && self.field.is_empty() {
+				self.in_comment = true;
+			} else if b == self.field_delimiter {
+				self.end_field();
+			} else if b == self.record_delimiter {
+				self.end_record(out);
+			} else {
+				self.field.push(b);
+			}
+		}
+		Ok(())
+	}
+
+	fn finish(&mut self, out: &mut Vec<Record>) -> Result<(), SqlError> {
+		if !self.in_comment && (self.in_quotes || !self.fields.is_empty() || !self.field.is_empty())
+		{
+			self.in_quotes = false;
+			self.quote_pending = false;
+			self.end_record(out);
+		}
+		Ok(())
+	}
+
+	fn end_field(&mut self) {
+		let field = std::mem::take(&mut self.field);
+		self.fields
+			.push(String::from_utf8_lossy(&field).into_owned());
+	}
+
+	fn end_record(&mut self, out: &mut Vec<Record>) {
+		if self.record_delimiter == b'\n' && self.field.last() == Some(&b'\r') {
+			self.field.pop();
+		}
+		self.record_size = 0;
+		// Empty lines are skipped
+		if self.fields.is_empty() && self.field.is_empty() {
+			return;
+		}
+		self.end_field();
+		let fields = std::mem::take(&mut self.fields);
+
+		if self.first_record {
+			self.first_record = false;
+			match self.header_info {
+				FileHeaderInfo::Use => {
+					self.headers = Some(Arc::new(fields));
+					return;
+				}
+				FileHeaderInfo::Ignore => return,
+				FileHeaderInfo::None => (),
+			}
+		}
+		out.push(Record::Csv {
+			fields,
+			headers: self.headers.clone(),
+		});
+	}
+}
+
+/// Incremental JSON parser, for objects that contain a sequence of JSON values,
+/// separated by whitespace (one per line in the case of JSON Lines)
+struct JsonReader {
+	buf: Vec<u8>,
+}
+
+impl JsonReader {
+	fn feed(&mut self, data: &[u8], out: &mut Vec<Record>, eof: bool) -> Result<(), SqlError> {
+		self.buf.extend_from_slice(data);
+
+		let mut consumed = 0;
+		let mut values =
+			serde_json::Deserializer::from_slice(&self.buf).into_iter::<serde_json::Value>();
+		loop {
+			match values.next() {
+				Some(Ok(v)) => {
+					// A number at the end of the data might be continued in the next chunk
+					if !eof && v.is_number() && values.byte_offset() == self.buf.len() {
+						break;
+					}
+					consumed = values.byte_offset();
+					out.push(Record::Json(v));
+				}
+				Some(Err(e)) if e.is_eof() && !eof => break,
+				Some(Err(e)) => {
+					return Err(SqlError::new(
+						"InvalidJsonType",
+						format!("Invalid JSON record: {}", e),
+					))
+				}
+				None => {
+					consumed = self.buf.len();
+					break;
+				}
+			}
+		}
+
+		self.buf.drain(..consumed);
+		if self.buf.len() > MAX_RECORD_SIZE {
+			return Err(too_large_record());
+		}
+		Ok(())
+	}
+}
+
+/// Serialization of the records of the result
+enum OutputFormat {
+	Csv {
+		field_delimiter: u8,
+		record_delimiter: Vec<u8>,
+		quote: u8,
+		quote_escape: u8,
+		always_quote: bool,
+	},
+	Json {
+		record_delimiter: Vec<u8>,
+	},
+}
+
+impl OutputFormat {
+	fn new(output: &OutputSerialization) -> Result<Self, Error> {
+		match (&output.csv, &output.json) {
+			(Some(csv), None) => {
+				let always_quote = match csv.quote_fields.as_ref().map(|q| q.0.to_ascii_uppercase())
+				{
+					None => false,
+					Some(q) if q == "ASNEEDED" => false,
+					Some(q) if q == "ALWAYS" => true,
+					Some(q) => {
+						return Err(Error::bad_request(format!("Invalid QuoteFields: {}", q)))
+					}
+				};
+				let quote = single_byte(&csv.quote_character, "QuoteCharacter", b'"')?;
+				Ok(OutputFormat::Csv {
+					field_delimiter: single_byte(&csv.field_delimiter, "FieldDelimiter", b',')?,
+					record_delimiter: record_delimiter(&csv.record_delimiter),
+					quote,
+					quote_escape: single_byte(
+						&csv.quote_escape_character,
+						"QuoteEscapeCharacter",
+						quote,
+					)?,
+					always_quote,
+				})
+			}
+			(None, Some(json)) => Ok(OutputFormat::Json {
+				record_delimiter: record_delimiter(&json.record_delimiter),
+			}),
+			_ => Err(Error::bad_request(
+				"OutputSerialization must specify exactly one of CSV or JSON",
+			)),
+		}
+	}
+
+	fn write(&self, record: &OutputRecord, out: &mut Vec<u8>) -> Result<(), SqlError> {
+		let start = out.len();
+		match self {
+			OutputFormat::Csv {
+				field_delimiter,
+				record_delimiter,
+				quote,
+				quote_escape,
+				always_quote,
+			} => {
+				let fields: Vec<String> = match record {
+					OutputRecord::Columns(columns) => {
+						columns.iter().map(|(_, v)| v.to_csv_field()).collect()
+					}
+					OutputRecord::Json(serde_json::Value::Object(o)) => o
+						.values()
+						.map(|v| SqlValue::from_json(v).to_csv_field())
+						.collect(),
+					OutputRecord::Json(v) => vec![SqlValue::from_json(v).to_csv_field()],
+				};
+				for (i, field) in fields.iter().enumerate() {
+					if i > 0 {
+						out.push(*field_delimiter);
+					}
+					let needs_quotes = *always_quote
+						|| field.bytes().any(|b| {
+							b == *field_delimiter
+								|| b == *quote || b == b'\n'
+								|| b == b'\r' || record_delimiter.contains(&b)
+						});
+					if needs_quotes {
+						out.push(*quote);
+						for b in field.bytes() {
+							if b == *quote || (b == *quote_escape && quote_escape != quote) {
+								out.push(*quote_escape);
+							}
+							out.push(b);
+						}
+						out.push(*quote);
+					} else {
+						out.extend_from_slice(field.as_bytes());
+					}
+				}
+				out.extend_from_slice(record_delimiter);
+			}
+			OutputFormat::Json { record_delimiter } => {
+				let json = match record {
+					OutputRecord::Columns(columns) => serde_json::Value::Object(
+						columns
+							.iter()
+							.map(|(k, v)| (k.clone(), v.to_json()))
+							.collect(),
+					),
+					OutputRecord::Json(v) => v.clone(),
+				};
+				// Serializing a serde_json::Value cannot fail
+				serde_json::to_writer(&mut *out, &json).unwrap();
+				out.extend_from_slice(record_delimiter);
+			}
+		}
+		if out.len() - start > MAX_RECORD_SIZE {
+			return Err(too_large_record());
+		}
+		Ok(())
+	}
+}
+
+fn record_delimiter(v: &Option<Value>) -> Vec<u8> {
+	match v {
+		Some(Value(d)) if !d.is_empty() => d.as_bytes().to_vec(),
+		_ => b"\n".to_vec(),
+	}
+}
+
+// ---- Event stream encoding ----
+
+/// Encode a message of the AWS event stream format: a prelude with the total length
+/// and the length of the headers and its CRC32, the headers (all of type string),
+/// the payload, and a CRC32 of the whole message
+fn event_message(headers: &[(&str, &str)], payload: &[u8]) -> Bytes {
+	let mut all_headers = vec![];
+	let message_type = match headers.iter().any(|(k, _)| *k == ":error-code") {
+		true => "error",
+		false => "event",
+	};
+	for (name, value) in [(":message-type", message_type)]
+		.iter()
+		.chain(headers.iter())
+	{
+		all_headers.push(name.len() as u8);
+		all_headers.extend_from_slice(name.as_bytes());
+		// Type of the header value: 7 is a string
+		all_headers.push(7);
+		all_headers.extend_from_slice(&(value.len() as u16).to_be_bytes());
+		all_headers.extend_from_slice(value.as_bytes());
+	}
+
+	let total_len = 12 + all_headers.len() + payload.len() + 4;
+	let mut msg = Vec::with_capacity(total_len);
+	msg.extend_from_slice(&(total_len as u32).to_be_bytes());
+	msg.extend_from_slice(&(all_headers.len() as u32).to_be_bytes());
+	msg.extend_from_slice(&crc32fast::hash(&msg).to_be_bytes());
+	msg.extend_from_slice(&all_headers);
+	msg.extend_from_slice(payload);
+	msg.extend_from_slice(&crc32fast::hash(&msg).to_be_bytes());
+	Bytes::from(msg)
+}
+
+fn error_message(e: &SqlError) -> Bytes {
+	event_message(
+		&[
+			(":error-code", e.code),
+			(":error-message", e.message.as_str()),
+		],
+		b"",
+	)
+}
+
+// ---- Request XML ----
+
+#[derive(Debug, Deserialize)]
+struct SelectObjectContentRequest {
+	#[serde(rename = "Expression")]
+	expression: Value,
+	#[serde(rename = "ExpressionType")]
+	expression_type: Value,
+	#[serde(rename = "RequestProgress")]
+	request_progress: Option<RequestProgress>,
+	#[serde(rename = "InputSerialization")]
+	input_serialization: InputSerialization,
+	#[serde(rename = "OutputSerialization")]
+	output_serialization: OutputSerialization,
+	#[serde(rename = "ScanRange")]
+	scan_range: Option<ScanRange>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RequestProgress {
+	#[serde(rename = "Enabled")]
+	enabled: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ScanRange {
+	#[serde(rename = "Start")]
+	_start: Option<Value>,
+	#[serde(rename = "End")]
+	_end: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct InputSerialization {
+	#[serde(rename = "CompressionType")]
+	compression_type: Option<Value>,
+	#[serde(rename = "CSV")]
+	csv: Option<CsvInput>,
+	#[serde(rename = "JSON")]
+	json: Option<JsonInput>,
+	#[serde(rename = "Parquet")]
+	parquet: Option<ParquetInput>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CsvInput {
+	#[serde(rename = "FileHeaderInfo")]
+	file_header_info: Option<Value>,
+	#[serde(rename = "Comments")]
+	comments: Option<Value>,
+	#[serde(rename = "QuoteEscapeCharacter")]
+	quote_escape_character: Option<Value>,
+	#[serde(rename = "RecordDelimiter")]
+	record_delimiter: Option<Value>,
+	#[serde(rename = "FieldDelimiter")]
+	field_delimiter: Option<Value>,
+	#[serde(rename = "QuoteCharacter")]
+	quote_character: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonInput {
+	#[serde(rename = "Type")]
+	type_: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ParquetInput {}
+
+#[derive(Debug, Deserialize)]
+struct OutputSerialization {
+	#[serde(rename = "CSV")]
+	csv: Option<CsvOutput>,
+	#[serde(rename = "JSON")]
+	json: Option<JsonOutput>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CsvOutput {
+	#[serde(rename = "QuoteFields")]
+	quote_fields: Option<Value>,
+	#[serde(rename = "QuoteEscapeCharacter")]
+	quote_escape_character: Option<Value>,
+	#[serde(rename = "RecordDelimiter")]
+	record_delimiter: Option<Value>,
+	#[serde(rename = "FieldDelimiter")]
+	field_delimiter: Option<Value>,
+	#[serde(rename = "QuoteCharacter")]
+	quote_character: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonOutput {
+	#[serde(rename = "RecordDelimiter")]
+	record_delimiter: Option<Value>,
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::s3::access_log::gzip_compress;
+
+	fn request(input: &str, output: &str, sql: &str) -> SelectObjectContentRequest {
+		let xml = format!(
+			r#"<SelectObjectContentRequest xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+				<Expression>{}</Expression>
+				<ExpressionType>SQL</ExpressionType>
+				<InputSerialization>{}</InputSerialization>
+				<OutputSerialization>{}</OutputSerialization>
+			</SelectObjectContentRequest>"#,
+			sql, input, output
+		);
+		from_reader(xml.as_bytes()).unwrap()
+	}
+
+	/// Run a query on an object sent in chunks of the given size
+	fn run(req: &SelectObjectContentRequest, data: &[u8], chunk_size: usize) -> String {
+		let mut processor = SelectProcessor::new(req).unwrap();
+		for chunk in data.chunks(chunk_size) {
+			processor.feed(chunk).unwrap();
+			if processor.is_done() {
+				break;
+			}
+		}
+		processor.finish().unwrap();
+		String::from_utf8(processor.output).unwrap()
+	}
+
+	#[test]
+	fn test_select_csv() {
+		let data = b"# comment\r\nname,city,age\r\n\"Smith, Alice\",Paris,31\r\nBob,\"Ly\"\"on\",25\r\n\r\nCarol,Paris,47";
+		let req = request(
+			"<CSV><FileHeaderInfo>USE</FileHeaderInfo></CSV>",
+			"<CSV/>",
+			"SELECT name, city FROM S3Object WHERE CAST(age AS INT) &lt; 40",
+		);
+		for chunk_size in [1, 3, 1000] {
+			assert_eq!(
+				run(&req, data, chunk_size),
+				"\"Smith, Alice\",Paris\nBob,\"Ly\"\"on\"\n"
+			);
+		}
+
+		let req = request(
+			"<CSV><FileHeaderInfo>IGNORE</FileHeaderInfo></CSV>",
+			"<JSON><RecordDelimiter>;</RecordDelimiter></JSON>",
+			"SELECT s._1 AS n FROM S3Object s WHERE s._2 = 'Paris' LIMIT 1",
+		);
+		assert_eq!(run(&req, data, 2), r#"{"n":"Smith, Alice"};"#);
+
+		let req = request(
+			"<CSV><FileHeaderInfo>NONE</FileHeaderInfo><Comments></Comments></CSV>",
+			"<CSV><QuoteFields>ALWAYS</QuoteFields></CSV>",
+			"SELECT COUNT(*) FROM S3Object",
+		);
+		assert_eq!(run(&req, data, 5), "\"5\"\n");
+	}
+
+	#[test]
+	fn test_select_json() {
+		let data = br#"{"id": 1, "tags": ["a"]}
+{"id": 22, "tags": []}
+{"id": 3,
+ "tags": ["b", "c"]} 4"#;
+		let req = request(
+			"<JSON><Type>DOCUMENT</Type></JSON>",
+			"<JSON/>",
+			"SELECT * FROM S3Object s WHERE s.id &gt; 1",
+		);
+		for chunk_size in [1, 7, 1000] {
+			assert_eq!(
+				run(&req, data, chunk_size),
+				"{\"id\":22,\"tags\":[]}\n{\"id\":3,\"tags\":[\"b\",\"c\"]}\n"
+			);
+		}
+
+		let req = request(
+			"<JSON><Type>LINES</Type></JSON>",
+			"<CSV/>",
+			"SELECT SUM(s.id), MAX(s.id) FROM S3Object s",
+		);
+		// The last value is a number that is not a record, but it is counted as NULL
+		assert_eq!(run(&req, data, 1), "26,22\n");
+	}
+
+	#[test]
+	fn test_select_gzip() {
+		let mut data = gzip_compress(b"a,b\n1,x\n2,y\n");
+		// Second member with the FNAME flag set
+		let mut member = gzip_compress(b"3,z\n4,");
+		member[3] = 0x08;
+		member.splice(10..10, b"part2.csv\0".iter().copied());
+		data.extend(member);
+		let req = request(
+			"<CompressionType>GZIP</CompressionType><CSV><FileHeaderInfo>USE</FileHeaderInfo></CSV>",
+			"<CSV/>",
+			"SELECT b FROM S3Object WHERE CAST(a AS INT) &gt; 1",
+		);
+		for chunk_size in [1, 5, 1000] {
+			assert_eq!(run(&req, &data, chunk_size), "y\nz\n\n");
+		}
+
+		// Truncated or corrupted data
+		for bad in [&data[..data.len() - 1], &data[1..]] {
+			let mut processor = SelectProcessor::new(&req).unwrap();
+			assert!(processor
+				.feed(bad)
+				.and_then(|_| processor.finish())
+				.is_err());
+		}
+	}
+
+	#[test]
+	fn test_invalid_requests() {
+		let sql = "SELECT * FROM S3Object";
+		for (input, output) in [
+			("<Parquet/>", "<CSV/>"),
+			("<CSV/><JSON/>", "<CSV/>"),
+			("<CSV/>", ""),
+			("<CompressionType>BZIP2</CompressionType><CSV/>", "<CSV/>"),
+			("<CSV><FieldDelimiter>ab</FieldDelimiter></CSV>", "<CSV/>"),
+			("<JSON><Type>XML</Type></JSON>", "<JSON/>"),
+		] {
+			assert!(SelectProcessor::new(&request(input, output, sql)).is_err());
+		}
+		assert!(SelectProcessor::new(&request("<CSV/>", "<CSV/>", "SELECT FROM")).is_err());
+	}
+
+	#[test]
+	fn test_event_message() {
+		let msg = event_message(&[(":event-type", "End")], b"");
+		let total_len = u32::from_be_bytes(msg[0..4].try_into().unwrap()) as usize;
+		let headers_len = u32::from_be_bytes(msg[4..8].try_into().unwrap()) as usize;
+		assert_eq!(total_len, msg.len());
+		assert_eq!(headers_len, total_len - 16);
+		assert_eq!(msg[8..12], crc32fast::hash(&msg[0..8]).to_be_bytes()[..]);
+		assert_eq!(
+			msg[total_len - 4..],
+			crc32fast::hash(&msg[..total_len - 4]).to_be_bytes()[..]
+		);
+		assert_eq!(
+			&msg[12..12 + headers_len],
+			&b"\x0d:message-type\x07\x00\x05event\x0b:event-type\x07\x00\x03End"[..]
+		);
+
+		let err = error_message(&SqlError::new("CastFailed", "bad cast"));
+		assert!(err.windows(5).any(|w| w == b"error"));
+		assert!(err.windows(10).any(|w| w == b"CastFailed"));
+	}
+}
@@ -0,0 +1,1719 @@
+//! Parser and evaluator for the SQL expressions of SelectObjectContent requests.
+//!
+//! Only a subset of the SQL dialect of Amazon S3 Select is supported:
+//! `SELECT <projection> FROM S3Object [alias] [WHERE <condition>] [LIMIT <n>]`,
+//! where the projection is `*`, a list of expressions, or a list of aggregates
+//! (`COUNT`, `SUM`, `AVG`, `MIN`, `MAX`).
+use std::cmp::Ordering;
+use std::fmt;
+
+/// Error in the parsing or evaluation of a query,
+/// with the error code returned by Amazon S3 in this case
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SqlError {
+	pub code: &'static str,
+	pub message: String,
+}
+
+impl SqlError {
+	pub fn new<M: Into<String>>(code: &'static str, message: M) -> Self {
+		Self {
+			code,
+			message: message.into(),
+		}
+	}
+
+	fn syntax<M: Into<String>>(message: M) -> Self {
+		Self::new("ParseUnexpectedToken", message)
+	}
+
+	fn unsupported<M: Into<String>>(message: M) -> Self {
+		Self::new("UnsupportedSyntax", message)
+	}
+
+	fn invalid_arguments<M: Into<String>>(message: M) -> Self {
+		Self::new("EvaluatorInvalidArguments", message)
+	}
+}
+
+impl fmt::Display for SqlError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", self.message)
+	}
+}
+
+// ---- Values ----
+
+/// Value of an expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+	Null,
+	Bool(bool),
+	Int(i64),
+	Float(f64),
+	String(String),
+	/// JSON object or array, from JSON records
+	Json(serde_json::Value),
+}
+
+impl Value {
+	pub fn from_json(v: &serde_json::Value) -> Self {
+		match v {
+			serde_json::Value::Null => Value::Null,
+			serde_json::Value::Bool(b) => Value::Bool(*b),
+			serde_json::Value::Number(n) => match n.as_i64() {
+				Some(i) => Value::Int(i),
+				None => Value::Float(n.as_f64().unwrap_or(f64::NAN)),
+			},
+			serde_json::Value::String(s) => Value::String(s.clone()),
+			v => Value::Json(v.clone()),
+		}
+	}
+
+	pub fn to_json(&self) -> serde_json::Value {
+		match self {
+			Value::Null => serde_json::Value::Null,
+			Value::Bool(b) => serde_json::Value::Bool(*b),
+			Value::Int(i) => serde_json::Value::from(*i),
+			Value::Float(f) => serde_json::Number::from_f64(*f)
+				.map(serde_json::Value::Number)
+				.unwrap_or(serde_json::Value::Null),
+			Value::String(s) => serde_json::Value::String(s.clone()),
+			Value::Json(v) => v.clone(),
+		}
+	}
+
+	/// Text of the value in a CSV field
+	pub fn to_csv_field(&self) -> String {
+		match self {
+			Value::Null => String::new(),
+			Value::Bool(b) => b.to_string(),
+			Value::Int(i) => i.to_string(),
+			Value::Float(f) => f.to_string(),
+			Value::String(s) => s.clone(),
+			Value::Json(v) => v.to_string(),
+		}
+	}
+
+	fn type_name(&self) -> &'static str {
+		match self {
+			Value::Null => "NULL",
+			Value::Bool(_) => "BOOL",
+			Value::Int(_) => "INT",
+			Value::Float(_) => "FLOAT",
+			Value::String(_) => "STRING",
+			Value::Json(serde_json::Value::Array(_)) => "LIST",
+			Value::Json(_) => "STRUCT",
+		}
+	}
+
+	/// Numeric value, strings being parsed as numbers so that
+	/// the fields of CSV records can be used in arithmetic and comparisons
+	fn as_number(&self) -> Option<Value> {
+		match self {
+			Value::Int(_) | Value::Float(_) => Some(self.clone()),
+			Value::String(s) => {
+				let s = s.trim();
+				s.parse::<i64>()
+					.map(Value::Int)
+					.or_else(|_| s.parse::<f64>().map(Value::Float))
+					.ok()
+			}
+			_ => None,
+		}
+	}
+
+	fn as_f64(&self) -> Option<f64> {
+		match self.as_number()? {
+			Value::Int(i) => Some(i as f64),
+			Value::Float(f) => Some(f),
+			_ => None,
+		}
+	}
+
+	/// Truth value in a condition: `None` if unknown (NULL)
+	fn as_bool(&self) -> Result<Option<bool>, SqlError> {
+		match self {
+			Value::Null => Ok(None),
+			Value::Bool(b) => Ok(Some(*b)),
+			Value::String(s) if s.eq_ignore_ascii_case("true") => Ok(Some(true)),
+			Value::String(s) if s.eq_ignore_ascii_case("false") => Ok(Some(false)),
+			v => Err(SqlError::new(
+				"EvaluatorTypeMismatch",
+				format!("Expected a boolean value, got {}", v.type_name()),
+			)),
+		}
+	}
+
+	/// Comparison of two non-null values, if they can be compared
+	fn compare(&self, other: &Value) -> Option<Ordering> {
+		match (self, other) {
+			(Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+			(Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+			(Value::Json(a), Value::Json(b)) if a == b => Some(Ordering::Equal),
+			(Value::Json(_), Value::Json(_)) => None,
+			(a, b) => match (a.as_number()?, b.as_number()?) {
+				(Value::Int(a), Value::Int(b)) => Some(a.cmp(&b)),
+				(a, b) => a.as_f64()?.partial_cmp(&b.as_f64()?),
+			},
+		}
+	}
+
+	fn cast(self, ty: CastType) -> Result<Value, SqlError> {
+		let failed = |v: &Value| {
+			SqlError::new(
+				"CastFailed",
+				format!("Cannot cast {} to {}", v.to_csv_field(), ty.name()),
+			)
+		};
+		Ok(match (ty, self) {
+			(_, Value::Null) => Value::Null,
+			(CastType::Int, Value::Float(f)) => Value::Int(f.trunc() as i64),
+			(CastType::Int, Value::Bool(b)) => Value::Int(b as i64),
+			(CastType::Int, v) => match v.as_number() {
+				Some(Value::Int(i)) => Value::Int(i),
+				Some(Value::Float(f)) => Value::Int(f.trunc() as i64),
+				_ => return Err(failed(&v)),
+			},
+			(CastType::Float, v) => v.as_f64().map(Value::Float).ok_or_else(|| failed(&v))?,
+			(CastType::String, v) => Value::String(v.to_csv_field()),
+			(CastType::Bool, Value::Int(i)) => Value::Bool(i != 0),
+			(CastType::Bool, v) => match v.as_bool() {
+				Ok(Some(b)) => Value::Bool(b),
+				_ => return Err(failed(&v)),
+			},
+		})
+	}
+}
+
+// ---- Records ----
+
+/// A record read from the object
+#[derive(Debug, Clone, PartialEq)]
+pub enum Record {
+	/// Fields of a CSV line, and names of the columns if
+	/// the file has a header line that is used
+	Csv {
+		fields: Vec<String>,
+		headers: Option<std::sync::Arc<Vec<String>>>,
+	},
+	Json(serde_json::Value),
+}
+
+impl Record {
+	fn get(&self, path: &[PathSegment]) -> Value {
+		match self {
+			Record::Csv { fields, headers } => {
+				let name = match path {
+					[PathSegment::Field(name, quoted)] => (name, *quoted),
+					_ => return Value::Null,
+				};
+				let index = headers
+					.as_ref()
+					.and_then(|h| find_name(h.iter().map(|s| s.as_str()), name.0, name.1))
+					.or_else(|| {
+						let i = name.0.strip_prefix('_')?.parse::<usize>().ok()?;
+						i.checked_sub(1)
+					});
+				match index.and_then(|i| fields.get(i)) {
+					Some(f) => Value::String(f.clone()),
+					None => Value::Null,
+				}
+			}
+			Record::Json(v) => {
+				let mut v = v;
+				for seg in path {
+					let next = match (seg, v) {
+						(PathSegment::Field(name, quoted), serde_json::Value::Object(o)) => {
+							o.get(name.as_str()).or_else(|| match *quoted {
+								false => find_name(o.keys().map(|k| k.as_str()), name, false)
+									.and_then(|i| o.values().nth(i)),
+								true => None,
+							})
+						}
+						(PathSegment::Index(i), serde_json::Value::Array(a)) => a.get(*i),
+						_ => None,
+					};
+					match next {
+						Some(n) => v = n,
+						None => return Value::Null,
+					}
+				}
+				Value::from_json(v)
+			}
+		}
+	}
+
+	/// Columns of the record, for `SELECT *`
+	fn columns(&self) -> OutputRecord {
+		match self {
+			Record::Csv { fields, headers } => OutputRecord::Columns(
+				fields
+					.iter()
+					.enumerate()
+					.map(|(i, f)| {
+						let name = headers
+							.as_ref()
+							.and_then(|h| h.get(i).cloned())
+							.unwrap_or_else(|| format!("_{}", i + 1));
+						(name, Value::String(f.clone()))
+					})
+					.collect(),
+			),
+			Record::Json(v) => OutputRecord::Json(v.clone()),
+		}
+	}
+}
+
+/// Position of a name in a list of names: identifiers that are not
+/// quoted are matched case-insensitively
+fn find_name<'a, I: Iterator<Item = &'a str>>(names: I, name: &str, quoted: bool) -> Option<usize> {
+	let names = names.collect::<Vec<_>>();
+	names
+		.iter()
+		.position(|n| *n == name)
+		.or_else(|| match quoted {
+			false => names.iter().position(|n| n.eq_ignore_ascii_case(name)),
+			true => None,
+		})
+}
+
+/// A record of the result of a query
+#[derive(Debug, Clone, PartialEq)]
+pub enum OutputRecord {
+	/// Named columns
+	Columns(Vec<(String, Value)>),
+	/// A whole JSON record, for `SELECT *` on a JSON object
+	Json(serde_json::Value),
+}
+
+// ---- Syntax tree ----
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PathSegment {
+	/// Name of a field, and whether it was quoted
+	Field(String, bool),
+	Index(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CastType {
+	Int,
+	Float,
+	String,
+	Bool,
+}
+
+impl CastType {
+	fn parse(name: &str) -> Option<Self> {
+		match name.to_ascii_uppercase().as_str() {
+			"INT" | "INTEGER" | "BIGINT" | "SMALLINT" => Some(CastType::Int),
+			"FLOAT" | "DOUBLE" | "REAL" | "DECIMAL" | "NUMERIC" => Some(CastType::Float),
+			"STRING" | "VARCHAR" | "CHAR" => Some(CastType::String),
+			"BOOL" | "BOOLEAN" => Some(CastType::Bool),
+			_ => None,
+		}
+	}
+
+	fn name(self) -> &'static str {
+		match self {
+			CastType::Int => "INT",
+			CastType::Float => "FLOAT",
+			CastType::String => "STRING",
+			CastType::Bool => "BOOL",
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+	And,
+	Or,
+	Eq,
+	Ne,
+	Lt,
+	Le,
+	Gt,
+	Ge,
+	Add,
+	Sub,
+	Mul,
+	Div,
+	Mod,
+	Concat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Function {
+	Lower,
+	Upper,
+	CharLength,
+	Trim,
+	Substring,
+	Coalesce,
+	NullIf,
+}
+
+impl Function {
+	fn parse(name: &str) -> Option<Self> {
+		match name.to_ascii_uppercase().as_str() {
+			"LOWER" => Some(Function::Lower),
+			"UPPER" => Some(Function::Upper),
+			"CHAR_LENGTH" | "CHARACTER_LENGTH" => Some(Function::CharLength),
+			"TRIM" => Some(Function::Trim),
+			"SUBSTRING" => Some(Function::Substring),
+			"COALESCE" => Some(Function::Coalesce),
+			"NULLIF" => Some(Function::NullIf),
+			_ => None,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregate {
+	Count,
+	Sum,
+	Avg,
+	Min,
+	Max,
+}
+
+impl Aggregate {
+	fn parse(name: &str) -> Option<Self> {
+		match name.to_ascii_uppercase().as_str() {
+			"COUNT" => Some(Aggregate::Count),
+			"SUM" => Some(Aggregate::Sum),
+			"AVG" => Some(Aggregate::Avg),
+			"MIN" => Some(Aggregate::Min),
+			"MAX" => Some(Aggregate::Max),
+			_ => None,
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+	Literal(Value),
+	Column(Vec<PathSegment>),
+	Not(Box<Expr>),
+	Neg(Box<Expr>),
+	Binary(BinaryOp, Box<Expr>, Box<Expr>),
+	IsNull(Box<Expr>, bool),
+	Like {
+		expr: Box<Expr>,
+		pattern: Box<Expr>,
+		escape: Option<Box<Expr>>,
+		negated: bool,
+	},
+	In(Box<Expr>, Vec<Expr>, bool),
+	Between(Box<Expr>, Box<Expr>, Box<Expr>, bool),
+	Cast(Box<Expr>, CastType),
+	Function(Function, Vec<Expr>),
+	/// Aggregate function, with its argument (`None` for `COUNT(*)`)
+	Aggregate(Aggregate, Option<Box<Expr>>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Projection {
+	All,
+	/// Expressions, and their alias
+	Items(Vec<(Expr, Option<String>)>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query {
+	pub projection: Projection,
+	pub table_alias: Option<String>,
+	pub condition: Option<Expr>,
+	pub limit: Option<u64>,
+}
+
+// ---- Tokenizer ----
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+	Ident(String),
+	QuotedIdent(String),
+	Str(String),
+	Int(i64),
+	Float(f64),
+	Symbol(&'static str),
+}
+
+const SYMBOLS: &[&str] = &[
+	"<>", "!=", "<=", ">=", "||", "=", "<", ">", "(", ")", ",", ".", "*", "+", "-", "/", "%", "[",
+	"]", ";",
+];
+
+fn tokenize(sql: &str) -> Result<Vec<Token>, SqlError> {
+	let chars = sql.chars().collect::<Vec<_>>();
+	let mut tokens = vec![];
+	let mut i = 0;
+	while i < chars.len() {
+		let c = chars[i];
+		if c.is_whitespace() {
+			i += 1;
+		} else if c == '\'' || c == '"' {
+			// Quotes are escaped by doubling them
+			let mut s = String::new();
+			i += 1;
+			loop {
+				match chars.get(i) {
+					None => return Err(SqlError::syntax("Unterminated quoted string")),
+					Some(q) if *q == c && chars.get(i + 1) == Some(&c) => {
+						s.push(c);
+						i += 2;
+					}
+					Some(q) if *q == c => {
+						i += 1;
+						break;
+					}
+					Some(x) => {
+						s.push(*x);
+						i += 1;
+					}
+				}
+			}
+			tokens.push(match c {
+				'\'' => Token::Str(s),
+				_ => Token::QuotedIdent(s),
+			});
+		} else if c.is_ascii_digit() {
+			let start = i;
+			while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+				i += 1;
+			}
+			if i < chars.len() && (chars[i] == 'e' || chars[i] == 'E') {
+				i += 1;
+				if i < chars.len() && (chars[i] == '+' || chars[i] == '-') {
+					i += 1;
+				}
+				while i < chars.len() && chars[i].is_ascii_digit() {
+					i += 1;
+				}
+			}
+			let s = chars[start..i].iter().collect::<String>();
+			tokens.push(match s.parse::<i64>() {
+				Ok(n) => Token::Int(n),
+				Err(_) => Token::Float(
+					s.parse::<f64>()
+						.map_err(|_| SqlError::syntax(format!("Invalid number: {}", s)))?,
+				),
+			});
+		} else if c.is_alphabetic() || c == '_' {
+			let start = i;
+			while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+				i += 1;
+			}
+			tokens.push(Token::Ident(chars[start..i].iter().collect()));
+		} else {
+			let sym = SYMBOLS
+				.iter()
+				.find(|s| {
+					s.chars()
+						.enumerate()
+						.all(|(j, sc)| chars.get(i + j) == Some(&sc))
+				})
+				.ok_or_else(|| SqlError::syntax(format!("Unexpected character: {}", c)))?;
+			i += sym.len();
+			tokens.push(Token::Symbol(sym));
+		}
+	}
+	Ok(tokens)
+}
+
+// ---- Parser ----
+
+const RESERVED: &[&str] = &[
+	"SELECT", "FROM", "WHERE", "LIMIT", "AS", "AND", "OR", "NOT", "IS", "NULL", "LIKE", "ESCAPE",
+	"IN", "BETWEEN", "TRUE", "FALSE", "CAST",
+];
+
+struct Parser {
+	tokens: Vec<Token>,
+	pos: usize,
+}
+
+impl Parser {
+	fn peek(&self) -> Option<&Token> {
+		self.tokens.get(self.pos)
+	}
+
+	fn next(&mut self) -> Option<Token> {
+		let t = self.tokens.get(self.pos).cloned();
+		self.pos += 1;
+		t
+	}
+
+	fn peek_keyword(&self, kw: &str) -> bool {
+		matches!(self.peek(), Some(Token::Ident(i)) if i.eq_ignore_ascii_case(kw))
+	}
+
+	fn eat_keyword(&mut self, kw: &str) -> bool {
+		let found = self.peek_keyword(kw);
+		if found {
+			self.pos += 1;
+		}
+		found
+	}
+
+	fn expect_keyword(&mut self, kw: &str) -> Result<(), SqlError> {
+		match self.eat_keyword(kw) {
+			true => Ok(()),
+			false => Err(self.unexpected(kw)),
+		}
+	}
+
+	fn eat_symbol(&mut self, sym: &str) -> bool {
+		let found = matches!(self.peek(), Some(Token::Symbol(s)) if *s == sym);
+		if found {
+			self.pos += 1;
+		}
+		found
+	}
+
+	fn expect_symbol(&mut self, sym: &str) -> Result<(), SqlError> {
+		match self.eat_symbol(sym) {
+			true => Ok(()),
+			false => Err(self.unexpected(sym)),
+		}
+	}
+
+	fn unexpected(&self, expected: &str) -> SqlError {
+		match self.peek() {
+			Some(t) => SqlError::syntax(format!("Expected {}, found {:?}", expected, t)),
+			None => SqlError::syntax(format!("Expected {}, found end of query", expected)),
+		}
+	}
+
+	/// An identifier that is not a reserved word, and whether it was quoted
+	fn identifier(&mut self) -> Result<(String, bool), SqlError> {
+		match self.peek().cloned() {
+			Some(Token::Ident(i)) if !RESERVED.iter().any(|r| r.eq_ignore_ascii_case(&i)) => {
+				self.pos += 1;
+				Ok((i, false))
+			}
+			Some(Token::QuotedIdent(i)) => {
+				self.pos += 1;
+				Ok((i, true))
+			}
+			_ => Err(self.unexpected("an identifier")),
+		}
+	}
+
+	fn query(&mut self) -> Result<Query, SqlError> {
+		self.expect_keyword("SELECT")?;
+		let projection = if self.eat_symbol("*") {
+			Projection::All
+		} else {
+			let mut items = vec![];
+			loop {
+				let expr = self.expr()?;
+				let alias = if self.eat_keyword("AS") {
+					Some(self.identifier()?.0)
+				} else {
+					match self.peek() {
+						Some(Token::Ident(_)) | Some(Token::QuotedIdent(_))
+							if !self.peek_keyword("FROM") =>
+						{
+							Some(self.identifier()?.0)
+						}
+						_ => None,
+					}
+				};
+				items.push((expr, alias));
+				if !self.eat_symbol(",") {
+					break;
+				}
+			}
+			Projection::Items(items)
+		};
+
+		self.expect_keyword("FROM")?;
+		match self.identifier()? {
+			(t, false) if t.eq_ignore_ascii_case("S3Object") => (),
+			(t, _) => {
+				return Err(SqlError::unsupported(format!(
+					"Queries can only select FROM S3Object, not {}",
+					t
+				)))
+			}
+		}
+		if matches!(
+			self.peek(),
+			Some(Token::Symbol("[")) | Some(Token::Symbol("."))
+		) {
+			return Err(SqlError::unsupported(
+				"Paths in the FROM clause are not supported",
+			));
+		}
+		let has_alias = self.eat_keyword("AS")
+			|| (matches!(
+				self.peek(),
+				Some(Token::Ident(_)) | Some(Token::QuotedIdent(_))
+			) && !self.peek_keyword("WHERE")
+				&& !self.peek_keyword("LIMIT"));
+		let table_alias = if has_alias {
+			Some(self.identifier()?.0)
+		} else {
+			None
+		};
+
+		let condition = match self.eat_keyword("WHERE") {
+			true => Some(self.expr()?),
+			false => None,
+		};
+		let limit = match self.eat_keyword("LIMIT") {
+			true => match self.next() {
+				Some(Token::Int(n)) if n >= 0 => Some(n as u64),
+				_ => {
+					return Err(SqlError::syntax(
+						"LIMIT must be followed by a non-negative integer",
+					))
+				}
+			},
+			false => None,
+		};
+		self.eat_symbol(";");
+		if self.peek().is_some() {
+			return Err(self.unexpected("end of query"));
+		}
+
+		let mut query = Query {
+			projection,
+			table_alias,
+			condition,
+			limit,
+		};
+		query.check()?;
+		Ok(query)
+	}
+
+	fn expr(&mut self) -> Result<Expr, SqlError> {
+		let mut left = self.and_expr()?;
+		while self.eat_keyword("OR") {
+			let right = self.and_expr()?;
+			left = Expr::Binary(BinaryOp::Or, Box::new(left), Box::new(right));
+		}
+		Ok(left)
+	}
+
+	fn and_expr(&mut self) -> Result<Expr, SqlError> {
+		let mut left = self.not_expr()?;
+		while self.eat_keyword("AND") {
+			let right = self.not_expr()?;
+			left = Expr::Binary(BinaryOp::And, Box::new(left), Box::new(right));
+		}
+		Ok(left)
+	}
+
+	fn not_expr(&mut self) -> Result<Expr, SqlError> {
+		if self.eat_keyword("NOT") {
+			Ok(Expr::Not(Box::new(self.not_expr()?)))
+		} else {
+			self.comparison()
+		}
+	}
+
+	fn comparison(&mut self) -> Result<Expr, SqlError> {
+		let left = self.additive()?;
+
+		if self.eat_keyword("IS") {
+			let negated = self.eat_keyword("NOT");
+			if !self.eat_keyword("NULL") && !self.eat_keyword("MISSING") {
+				return Err(self.unexpected("NULL"));
+			}
+			return Ok(Expr::IsNull(Box::new(left), negated));
+		}
+
+		let negated = self.eat_keyword("NOT");
+		if self.eat_keyword("LIKE") {
+			let pattern = self.additive()?;
+			let escape = match self.eat_keyword("ESCAPE") {
+				true => Some(Box::new(self.additive()?)),
+				false => None,
+			};
+			return Ok(Expr::Like {
+				expr: Box::new(left),
+				pattern: Box::new(pattern),
+				escape,
+				negated,
+			});
+		}
+		if self.eat_keyword("IN") {
+			self.expect_symbol("(")?;
+			let mut list = vec![self.expr()?];
+			while self.eat_symbol(",") {
+				list.push(self.expr()?);
+			}
+			self.expect_symbol(")")?;
+			return Ok(Expr::In(Box::new(left), list, negated));
+		}
+		if self.eat_keyword("BETWEEN") {
+			let low = self.additive()?;
+			self.expect_keyword("AND")?;
+			let high = self.additive()?;
+			return Ok(Expr::Between(
+				Box::new(left),
+				Box::new(low),
+				Box::new(high),
+				negated,
+			));
+		}
+		if negated {
+			return Err(self.unexpected("LIKE, IN or BETWEEN"));
+		}
+
+		let op = match self.peek() {
+			Some(Token::Symbol("=")) => BinaryOp::Eq,
+			Some(Token::Symbol("!=")) | Some(Token::Symbol("<>")) => BinaryOp::Ne,
+			Some(Token::Symbol("<")) => BinaryOp::Lt,
+			Some(Token::Symbol("<=")) => BinaryOp::Le,
+			Some(Token::Symbol(">")) => BinaryOp::Gt,
+			Some(Token::Symbol(">=")) => BinaryOp::Ge,
+			_ => return Ok(left),
+		};
+		self.pos += 1;
+		let right = self.additive()?;
+		Ok(Expr::Binary(op, Box::new(left), Box::new(right)))
+	}
+
+	fn additive(&mut self) -> Result<Expr, SqlError> {
+		let mut left = self.multiplicative()?;
+		loop {
+			let op = match self.peek() {
+				Some(Token::Symbol("+")) => BinaryOp::Add,
+				Some(Token::Symbol("-")) => BinaryOp::Sub,
+				Some(Token::Symbol("||")) => BinaryOp::Concat,
+				_ => return Ok(left),
+			};
+			self.pos += 1;
+			let right = self.multiplicative()?;
+			left = Expr::Binary(op, Box::new(left), Box::new(right));
+		}
+	}
+
+	fn multiplicative(&mut self) -> Result<Expr, SqlError> {
+		let mut left = self.unary()?;
+		loop {
+			let op = match self.peek() {
+				Some(Token::Symbol("*")) => BinaryOp::Mul,
+				Some(Token::Symbol("/")) => BinaryOp::Div,
+				Some(Token::Symbol("%")) => BinaryOp::Mod,
+				_ => return Ok(left),
+			};
+			self.pos += 1;
+			let right = self.unary()?;
+			left = Expr::Binary(op, Box::new(left), Box::new(right));
+		}
+	}
+
+	fn unary(&mut self) -> Result<Expr, SqlError> {
+		if self.eat_symbol("-") {
+			Ok(Expr::Neg(Box::new(self.unary()?)))
+		} else if self.eat_symbol("+") {
+			self.unary()
+		} else {
+			self.primary()
+		}
+	}
+
+	fn primary(&mut self) -> Result<Expr, SqlError> {
+		match self.peek().cloned() {
+			Some(Token::Int(n)) => {
+				self.pos += 1;
+				Ok(Expr::Literal(Value::Int(n)))
+			}
+			Some(Token::Float(f)) => {
+				self.pos += 1;
+				Ok(Expr::Literal(Value::Float(f)))
+			}
+			Some(Token::Str(s)) => {
+				self.pos += 1;
+				Ok(Expr::Literal(Value::String(s)))
+			}
+			Some(Token::Symbol("(")) => {
+				self.pos += 1;
+				let e = self.expr()?;
+				self.expect_symbol(")")?;
+				Ok(e)
+			}
+			Some(Token::Ident(i)) if i.eq_ignore_ascii_case("TRUE") => {
+				self.pos += 1;
+				Ok(Expr::Literal(Value::Bool(true)))
+			}
+			Some(Token::Ident(i)) if i.eq_ignore_ascii_case("FALSE") => {
+				self.pos += 1;
+				Ok(Expr::Literal(Value::Bool(false)))
+			}
+			Some(Token::Ident(i)) if i.eq_ignore_ascii_case("NULL") => {
+				self.pos += 1;
+				Ok(Expr::Literal(Value::Null))
+			}
+			Some(Token::Ident(i)) if i.eq_ignore_ascii_case("CAST") => {
+				self.pos += 1;
+				self.expect_symbol("(")?;
+				let e = self.expr()?;
+				self.expect_keyword("AS")?;
+				let (ty, _) = self.identifier()?;
+				let ty = CastType::parse(&ty)
+					.ok_or_else(|| SqlError::unsupported(format!("Unsupported type: {}", ty)))?;
+				self.expect_symbol(")")?;
+				Ok(Expr::Cast(Box::new(e), ty))
+			}
+			Some(Token::Ident(i))
+				if matches!(self.tokens.get(self.pos + 1), Some(Token::Symbol("("))) =>
+			{
+				self.pos += 2;
+				self.call(&i)
+			}
+			_ => self.path(),
+		}
+	}
+
+	/// Arguments of a function call, after the opening parenthesis
+	fn call(&mut self, name: &str) -> Result<Expr, SqlError> {
+		if let Some(agg) = Aggregate::parse(name) {
+			if agg == Aggregate::Count && self.eat_symbol("*") {
+				self.expect_symbol(")")?;
+				return Ok(Expr::Aggregate(agg, None));
+			}
+			let arg = self.expr()?;
+			self.expect_symbol(")")?;
+			return Ok(Expr::Aggregate(agg, Some(Box::new(arg))));
+		}
+
+		let fun = Function::parse(name).ok_or_else(|| {
+			SqlError::new(
+				"UnsupportedFunction",
+				format!("Unsupported function: {}", name),
+			)
+		})?;
+		let mut args = vec![self.expr()?];
+		if fun == Function::Substring && self.eat_keyword("FROM") {
+			// SUBSTRING(string FROM start [FOR length])
+			args.push(self.expr()?);
+			if self.eat_keyword("FOR") {
+				args.push(self.expr()?);
+			}
+		} else {
+			while self.eat_symbol(",") {
+				args.push(self.expr()?);
+			}
+		}
+		self.expect_symbol(")")?;
+
+		let arity_ok = match fun {
+			Function::Lower | Function::Upper | Function::CharLength | Function::Trim => {
+				args.len() == 1
+			}
+			Function::Substring => args.len() == 2 || args.len() == 3,
+			Function::Coalesce => !args.is_empty(),
+			Function::NullIf => args.len() == 2,
+		};
+		if !arity_ok {
+			return Err(SqlError::new(
+				"IncorrectSqlFunctionArgumentType",
+				format!(
+					"Wrong number of arguments for {}",
+					name.to_ascii_uppercase()
+				),
+			));
+		}
+		Ok(Expr::Function(fun, args))
+	}
+
+	fn path(&mut self) -> Result<Expr, SqlError> {
+		let mut path = vec![];
+		let (name, quoted) = self.identifier()?;
+		path.push(PathSegment::Field(name, quoted));
+		loop {
+			if self.eat_symbol(".") {
+				let (name, quoted) = self.identifier()?;
+				path.push(PathSegment::Field(name, quoted));
+			} else if self.eat_symbol("[") {
+				match self.next() {
+					Some(Token::Int(i)) if i >= 0 => path.push(PathSegment::Index(i as usize)),
+					Some(Token::Str(s)) => path.push(PathSegment::Field(s, true)),
+					_ => return Err(SqlError::syntax("Invalid path index")),
+				}
+				self.expect_symbol("]")?;
+			} else {
+				return Ok(Expr::Column(path));
+			}
+		}
+	}
+}
+
+impl Query {
+	pub fn parse(sql: &str) -> Result<Self, SqlError> {
+		let mut parser = Parser {
+			tokens: tokenize(sql)?,
+			pos: 0,
+		};
+		parser.query()
+	}
+
+	/// Whether the query returns a single record of aggregates
+	pub fn is_aggregate(&self) -> bool {
+		match &self.projection {
+			Projection::All => false,
+			Projection::Items(items) => items.iter().any(|(e, _)| e.has_aggregate()),
+		}
+	}
+
+	/// Check the structure of the query, and remove the table alias
+	/// from the paths of the columns
+	fn check(&mut self) -> Result<(), SqlError> {
+		if let Some(cond) = &self.condition {
+			if cond.has_aggregate() {
+				return Err(SqlError::unsupported(
+					"Aggregate functions cannot be used in the WHERE clause",
+				));
+			}
+		}
+		let aggregate = self.is_aggregate();
+		if let Projection::Items(items) = &self.projection {
+			for (e, _) in items.iter() {
+				match e {
+					Expr::Aggregate(_, arg)
+						if arg.as_ref().map(|a| a.has_aggregate()) == Some(true) =>
+					{
+						return Err(SqlError::unsupported(
+							"Aggregate functions cannot be nested",
+						));
+					}
+					Expr::Aggregate(..) => (),
+					e if aggregate || e.has_aggregate() => {
+						return Err(SqlError::unsupported(
+							"Aggregate functions cannot be mixed with other expressions in the projection",
+						));
+					}
+					_ => (),
+				}
+			}
+		}
+
+		let alias = self.table_alias.clone();
+		let strip = |e: &mut Expr| e.strip_alias(alias.as_deref());
+		if let Projection::Items(items) = &mut self.projection {
+			for (e, _) in items.iter_mut() {
+				strip(e);
+			}
+		}
+		if let Some(cond) = &mut self.condition {
+			strip(cond);
+		}
+		Ok(())
+	}
+}
+
+impl Expr {
+	fn children_mut(&mut self) -> Vec<&mut Expr> {
+		match self {
+			Expr::Literal(_) | Expr::Column(_) | Expr::Aggregate(_, None) => vec![],
+			Expr::Not(e) | Expr::Neg(e) | Expr::IsNull(e, _) | Expr::Cast(e, _) => vec![e],
+			Expr::Aggregate(_, Some(e)) => vec![e],
+			Expr::Binary(_, a, b) => vec![a, b],
+			Expr::Like {
+				expr,
+				pattern,
+				escape,
+				..
+			} => {
+				let mut v: Vec<&mut Expr> = vec![expr, pattern];
+				if let Some(e) = escape {
+					v.push(e);
+				}
+				v
+			}
+			Expr::In(e, list, _) => {
+				let mut v: Vec<&mut Expr> = vec![e];
+				v.extend(list.iter_mut());
+				v
+			}
+			Expr::Between(a, b, c, _) => vec![a, b, c],
+			Expr::Function(_, args) => args.iter_mut().collect(),
+		}
+	}
+
+	fn has_aggregate(&self) -> bool {
+		match self {
+			Expr::Aggregate(..) => true,
+			e => e
+				.clone()
+				.children_mut()
+				.into_iter()
+				.any(|c| c.has_aggregate()),
+		}
+	}
+
+	fn strip_alias(&mut self, alias: Option<&str>) {
+		if let Expr::Column(path) = self {
+			if path.len() > 1 {
+				if let PathSegment::Field(first, false) = &path[0] {
+					let is_table = first.eq_ignore_ascii_case("S3Object")
+						|| alias.map(|a| a.eq_ignore_ascii_case(first)) == Some(true);
+					if is_table {
+						path.remove(0);
+					}
+				}
+			}
+			return;
+		}
+		for c in self.children_mut() {
+			c.strip_alias(alias);
+		}
+	}
+
+	/// Name of the column of the result for this expression, if it is a column
+	fn column_name(&self) -> Option<String> {
+		match self {
+			Expr::Column(path) => path.iter().rev().find_map(|s| match s {
+				PathSegment::Field(name, _) => Some(name.clone()),
+				PathSegment::Index(_) => None,
+			}),
+			_ => None,
+		}
+	}
+
+	fn eval(&self, record: &Record) -> Result<Value, SqlError> {
+		Ok(match self {
+			Expr::Literal(v) => v.clone(),
+			Expr::Column(path) => record.get(path),
+			Expr::Not(e) => match e.eval(record)?.as_bool()? {
+				Some(b) => Value::Bool(!b),
+				None => Value::Null,
+			},
+			Expr::Neg(e) => match e.eval(record)? {
+				Value::Null => Value::Null,
+				v => match v.as_number() {
+					Some(Value::Int(i)) => i
+						.checked_neg()
+						.map(Value::Int)
+						.unwrap_or(Value::Float(-(i as f64))),
+					Some(Value::Float(f)) => Value::Float(-f),
+					_ => return Err(type_mismatch("-", &v)),
+				},
+			},
+			Expr::Binary(BinaryOp::And, a, b) => {
+				match (a.eval(record)?.as_bool()?, b.eval(record)?.as_bool()?) {
+					(Some(false), _) | (_, Some(false)) => Value::Bool(false),
+					(Some(true), Some(true)) => Value::Bool(true),
+					_ => Value::Null,
+				}
+			}
+			Expr::Binary(BinaryOp::Or, a, b) => {
+				match (a.eval(record)?.as_bool()?, b.eval(record)?.as_bool()?) {
+					(Some(true), _) | (_, Some(true)) => Value::Bool(true),
+					(Some(false), Some(false)) => Value::Bool(false),
+					_ => Value::Null,
+				}
+			}
+			Expr::Binary(op, a, b) => binary(*op, a.eval(record)?, b.eval(record)?)?,
+			Expr::IsNull(e, negated) => Value::Bool((e.eval(record)? == Value::Null) != *negated),
+			Expr::Like {
+				expr,
+				pattern,
+				escape,
+				negated,
+			} => {
+				let escape = match escape {
+					Some(e) => match e.eval(record)? {
+						Value::String(s) if s.chars().count() == 1 => s.chars().next(),
+						_ => {
+							return Err(SqlError::invalid_arguments(
+								"The ESCAPE of LIKE must be a single character",
+							))
+						}
+					},
+					None => None,
+				};
+				match (expr.eval(record)?, pattern.eval(record)?) {
+					(Value::Null, _) | (_, Value::Null) => Value::Null,
+					(v, Value::String(p)) => {
+						let s = v.to_csv_field();
+						Value::Bool(like(&s, &p, escape) != *negated)
+					}
+					(_, p) => return Err(type_mismatch("LIKE", &p)),
+				}
+			}
+			Expr::In(e, list, negated) => {
+				let v = e.eval(record)?;
+				if v == Value::Null {
+					return Ok(Value::Null);
+				}
+				let mut found = false;
+				let mut unknown = false;
+				for item in list.iter() {
+					match item.eval(record)? {
+						Value::Null => unknown = true,
+						i if v.compare(&i) == Some(Ordering::Equal) => {
+							found = true;
+							break;
+						}
+						_ => (),
+					}
+				}
+				match (found, unknown) {
+					(true, _) => Value::Bool(!*negated),
+					(false, true) => Value::Null,
+					(false, false) => Value::Bool(*negated),
+				}
+			}
+			Expr::Between(e, low, high, negated) => {
+				let v = e.eval(record)?;
+				let ge = binary(BinaryOp::Ge, v.clone(), low.eval(record)?)?;
+				let le = binary(BinaryOp::Le, v, high.eval(record)?)?;
+				match (ge.as_bool()?, le.as_bool()?) {
+					(Some(a), Some(b)) => Value::Bool((a && b) != *negated),
+					(Some(false), None) | (None, Some(false)) => Value::Bool(*negated),
+					_ => Value::Null,
+				}
+			}
+			Expr::Cast(e, ty) => e.eval(record)?.cast(*ty)?,
+			Expr::Function(fun, args) => {
+				let args = args
+					.iter()
+					.map(|a| a.eval(record))
+					.collect::<Result<Vec<_>, _>>()?;
+				function(*fun, args)?
+			}
+			Expr::Aggregate(..) => {
+				return Err(SqlError::unsupported(
+					"Aggregate functions can only be used in the projection",
+				))
+			}
+		})
+	}
+}
+
+fn type_mismatch(op: &str, v: &Value) -> SqlError {
+	SqlError::new(
+		"EvaluatorTypeMismatch",
+		format!("Invalid operand of type {} for {}", v.type_name(), op),
+	)
+}
+
+fn binary(op: BinaryOp, a: Value, b: Value) -> Result<Value, SqlError> {
+	if a == Value::Null || b == Value::Null {
+		return Ok(Value::Null);
+	}
+	let cmp = |f: fn(Ordering) -> bool| match a.compare(&b) {
+		Some(o) => Value::Bool(f(o)),
+		None => Value::Null,
+	};
+	Ok(match op {
+		BinaryOp::Eq => cmp(|o| o == Ordering::Equal),
+		BinaryOp::Ne => cmp(|o| o != Ordering::Equal),
+		BinaryOp::Lt => cmp(|o| o == Ordering::Less),
+		BinaryOp::Le => cmp(|o| o != Ordering::Greater),
+		BinaryOp::Gt => cmp(|o| o == Ordering::Greater),
+		BinaryOp::Ge => cmp(|o| o != Ordering::Less),
+		BinaryOp::Concat => Value::String(a.to_csv_field() + &b.to_csv_field()),
+		BinaryOp::And | BinaryOp::Or => unreachable!(),
+		_ => {
+			let (x, y) = match (a.as_number(), b.as_number()) {
+				(Some(x), Some(y)) => (x, y),
+				(None, _) => return Err(type_mismatch("arithmetic", &a)),
+				(_, None) => return Err(type_mismatch("arithmetic", &b)),
+			};
+			arithmetic(op, x, y)?
+		}
+	})
+}
+
+fn arithmetic(op: BinaryOp, x: Value, y: Value) -> Result<Value, SqlError> {
+	if let (Value::Int(x), Value::Int(y)) = (&x, &y) {
+		let (x, y) = (*x, *y);
+		if (op == BinaryOp::Div || op == BinaryOp::Mod) && y == 0 {
+			return Err(SqlError::invalid_arguments("Division by zero"));
+		}
+		let r = match op {
+			BinaryOp::Add => x.checked_add(y),
+			BinaryOp::Sub => x.checked_sub(y),
+			BinaryOp::Mul => x.checked_mul(y),
+			BinaryOp::Div => x.checked_div(y),
+			BinaryOp::Mod => x.checked_rem(y),
+			_ => unreachable!(),
+		};
+		if let Some(r) = r {
+			return Ok(Value::Int(r));
+		}
+	}
+	let (x, y) = (x.as_f64().unwrap(), y.as_f64().unwrap());
+	if (op == BinaryOp::Div || op == BinaryOp::Mod) && y == 0.0 {
+		return Err(SqlError::invalid_arguments("Division by zero"));
+	}
+	Ok(Value::Float(match op {
+		BinaryOp::Add => x + y,
+		BinaryOp::Sub => x - y,
+		BinaryOp::Mul => x * y,
+		BinaryOp::Div => x / y,
+		BinaryOp::Mod => x % y,
+		_ => unreachable!(),
+	}))
+}
+
+fn function(fun: Function, mut args: Vec<Value>) -> Result<Value, SqlError> {
+	match fun {
+		Function::Coalesce => {
+			return Ok(args
+				.into_iter()
+				.find(|v| *v != Value::Null)
+				.unwrap_or(Value::Null))
+		}
+		Function::NullIf => {
+			let b = args.pop().unwrap();
+			let a = args.pop().unwrap();
+			return Ok(match a.compare(&b) {
+				Some(Ordering::Equal) => Value::Null,
+				_ => a,
+			});
+		}
+		_ => (),
+	}
+	if args.contains(&Value::Null) {
+		return Ok(Value::Null);
+	}
+	let s = args[0].to_csv_field();
+	Ok(match fun {
+		Function::Lower => Value::String(s.to_lowercase()),
+		Function::Upper => Value::String(s.to_uppercase()),
+		Function::CharLength => Value::Int(s.chars().count() as i64),
+		Function::Trim => Value::String(s.trim().to_string()),
+		Function::Substring => {
+			let int_arg = |v: &Value| match v.as_number() {
+				Some(Value::Int(i)) => Ok(i),
+				_ => Err(SqlError::new(
+					"IncorrectSqlFunctionArgumentType",
+					"The position and length of SUBSTRING must be integers",
+				)),
+			};
+			// Positions start at 1, and characters before the first one are counted
+			// in the length, as in the SQL standard
+			let start = int_arg(&args[1])?;
+			let end = match args.get(2) {
+				Some(l) => {
+					let len = int_arg(l)?;
+					if len < 0 {
+						return Err(SqlError::invalid_arguments(
+							"The length of SUBSTRING cannot be negative",
+						));
+					}
+					start.saturating_add(len)
+				}
+				None => i64::MAX,
+			};
+			let start = std::cmp::max(start, 1);
+			Value::String(
+				s.chars()
+					.enumerate()
+					.filter(|(i, _)| {
+						let pos = *i as i64 + 1;
+						pos >= start && pos < end
+					})
+					.map(|(_, c)| c)
+					.collect(),
+			)
+		}
+		Function::Coalesce | Function::NullIf => unreachable!(),
+	})
+}
+
+/// Match a string against a LIKE pattern, where `%` matches any sequence
+/// of characters and `_` matches any single character
+fn like(s: &str, pattern: &str, escape: Option<char>) -> bool {
+	enum P {
+		Char(char),
+		Any,
+		Many,
+	}
+	let mut pat = vec![];
+	let mut chars = pattern.chars();
+	while let Some(c) = chars.next() {
+		pat.push(match c {
+			c if Some(c) == escape => match chars.next() {
+				Some(n) => P::Char(n),
+				None => P::Char(c),
+			},
+			'%' => P::Many,
+			'_' => P::Any,
+			c => P::Char(c),
+		});
+	}
+	let s = s.chars().collect::<Vec<_>>();
+
+	// matches[j]: whether the first i characters of s match the first j items of the pattern
+	let mut matches = vec![false; pat.len() + 1];
+	matches[0] = true;
+	for j in 0..pat.len() {
+		matches[j + 1] = matches[j] && matches!(pat[j], P::Many);
+	}
+	for c in s.iter() {
+		let mut next = vec![false; pat.len() + 1];
+		for j in 0..pat.len() {
+			next[j + 1] = match pat[j] {
+				P::Many => next[j] || matches[j + 1],
+				P::Any => matches[j],
+				P::Char(p) => matches[j] && p == *c,
+			};
+		}
+		matches = next;
+	}
+	matches[pat.len()]
+}
+
+// ---- Execution ----
+
+/// State of the aggregate of a column of an aggregate query
+#[derive(Debug, Clone)]
+struct AggregateState {
+	count: u64,
+	sum: Option<Value>,
+	best: Option<Value>,
+}
+
+/// Execution of a query on the records of an object
+pub struct QueryExec {
+	query: Query,
+	aggregates: Vec<AggregateState>,
+	returned: u64,
+}
+
+impl QueryExec {
+	pub fn new(query: Query) -> Self {
+		let n = match &query.projection {
+			Projection::Items(items) if query.is_aggregate() => items.len(),
+			_ => 0,
+		};
+		Self {
+			query,
+			aggregates: vec![
+				AggregateState {
+					count: 0,
+					sum: None,
+					best: None
+				};
+				n
+			],
+			returned: 0,
+		}
+	}
+
+	/// Whether the query has returned as many records as its LIMIT
+	pub fn is_done(&self) -> bool {
+		match self.query.limit {
+			Some(limit) => !self.query.is_aggregate() && self.returned >= limit,
+			None => false,
+		}
+	}
+
+	/// Process a record, returning the corresponding output record if it matches the
+	/// condition of the query (aggregate queries only return a record in `finish`)
+	pub fn process(&mut self, record: &Record) -> Result<Option<OutputRecord>, SqlError> {
+		if self.is_done() {
+			return Ok(None);
+		}
+		if let Some(cond) = &self.query.condition {
+			if cond.eval(record)?.as_bool()? != Some(true) {
+				return Ok(None);
+			}
+		}
+
+		let items = match &self.query.projection {
+			Projection::All => {
+				self.returned += 1;
+				return Ok(Some(record.columns()));
+			}
+			Projection::Items(items) => items,
+		};
+
+		if self.query.is_aggregate() {
+			for ((expr, _), state) in items.iter().zip(self.aggregates.iter_mut()) {
+				let (agg, arg) = match expr {
+					Expr::Aggregate(agg, arg) => (*agg, arg),
+					_ => unreachable!(),
+				};
+				let v = match arg {
+					Some(arg) => arg.eval(record)?,
+					None => Value::Bool(true),
+				};
+				state.update(agg, v)?;
+			}
+			return Ok(None);
+		}
+
+		let mut columns = Vec::with_capacity(items.len());
+		for (i, (expr, alias)) in items.iter().enumerate() {
+			let name = alias
+				.clone()
+				.or_else(|| expr.column_name())
+				.unwrap_or_else(|| format!("_{}", i + 1));
+			columns.push((name, expr.eval(record)?));
+		}
+		self.returned += 1;
+		Ok(Some(OutputRecord::Columns(columns)))
+	}
+
+	/// Result of an aggregate query, once all the records have been processed
+	pub fn finish(&mut self) -> Option<OutputRecord> {
+		let items = match &self.query.projection {
+			Projection::Items(items) if self.query.is_aggregate() => items,
+			_ => return None,
+		};
+		let columns = items
+			.iter()
+			.zip(self.aggregates.iter())
+			.enumerate()
+			.map(|(i, ((expr, alias), state))| {
+				let agg = match expr {
+					Expr::Aggregate(agg, _) => *agg,
+					_ => unreachable!(),
+				};
+				let name = alias.clone().unwrap_or_else(|| format!("_{}", i + 1));
+				(name, state.result(agg))
+			})
+			.collect();
+		Some(OutputRecord::Columns(columns))
+	}
+}
+
+impl AggregateState {
+	fn update(&mut self, agg: Aggregate, v: Value) -> Result<(), SqlError> {
+		if v == Value::Null {
+			return Ok(());
+		}
+		self.count += 1;
+		match agg {
+			Aggregate::Count => (),
+			Aggregate::Sum | Aggregate::Avg => {
+				let v = v
+					.as_number()
+					.ok_or_else(|| type_mismatch("SUM or AVG", &v))?;
+				self.sum = Some(match self.sum.take() {
+					Some(s) => arithmetic(BinaryOp::Add, s, v)?,
+					None => v,
+				});
+			}
+			Aggregate::Min | Aggregate::Max => {
+				// Fields of CSV records are compared as numbers if they are numbers
+				let v = v.as_number().unwrap_or(v);
+				let replace = match &self.best {
+					None => true,
+					Some(b) => matches!(
+						(agg, v.compare(b)),
+						(Aggregate::Min, Some(Ordering::Less))
+							| (Aggregate::Max, Some(Ordering::Greater))
+					),
+				};
+				if replace {
+					self.best = Some(v);
+				}
+			}
+		}
+		Ok(())
+	}
+
+	fn result(&self, agg: Aggregate) -> Value {
+		match agg {
+			Aggregate::Count => Value::Int(self.count as i64),
+			Aggregate::Sum => self.sum.clone().unwrap_or(Value::Null),
+			Aggregate::Avg => match self.sum.as_ref().and_then(|s| s.as_f64()) {
+				Some(s) if self.count > 0 => Value::Float(s / self.count as f64),
+				_ => Value::Null,
+			},
+			Aggregate::Min | Aggregate::Max => self.best.clone().unwrap_or(Value::Null),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::sync::Arc;
+
+	fn csv(fields: &[&str]) -> Record {
+		Record::Csv {
+			fields: fields.iter().map(|s| s.to_string()).collect(),
+			headers: Some(Arc::new(vec!["name".into(), "city".into(), "age".into()])),
+		}
+	}
+
+	fn run(sql: &str, records: &[Record]) -> Result<Vec<OutputRecord>, SqlError> {
+		let mut exec = QueryExec::new(Query::parse(sql)?);
+		let mut out = vec![];
+		for r in records {
+			if let Some(o) = exec.process(r)? {
+				out.push(o);
+			}
+		}
+		out.extend(exec.finish());
+		Ok(out)
+	}
+
+	fn values(out: &[OutputRecord]) -> Vec<Vec<Value>> {
+		out.iter()
+			.map(|o| match o {
+				OutputRecord::Columns(c) => c.iter().map(|(_, v)| v.clone()).collect(),
+				OutputRecord::Json(j) => vec![Value::Json(j.clone())],
+			})
+			.collect()
+	}
+
+	#[test]
+	fn test_parse_query() {
+		let q = Query::parse(
+			"select s.name, age + 1 AS next FROM S3Object s WHERE s.city = 'Paris' LIMIT 10;",
+		)
+		.unwrap();
+		assert_eq!(q.table_alias.as_deref(), Some("s"));
+		assert_eq!(q.limit, Some(10));
+		assert_eq!(
+			q.projection,
+			Projection::Items(vec![
+				(
+					Expr::Column(vec![PathSegment::Field("name".into(), false)]),
+					None
+				),
+				(
+					Expr::Binary(
+						BinaryOp::Add,
+						Box::new(Expr::Column(vec![PathSegment::Field("age".into(), false)])),
+						Box::new(Expr::Literal(Value::Int(1)))
+					),
+					Some("next".into())
+				),
+			])
+		);
+		assert!(!q.is_aggregate());
+
+		assert!(Query::parse("SELECT COUNT(*), MAX(age) FROM S3Object")
+			.unwrap()
+			.is_aggregate());
+
+		for invalid in [
+			"SELECT",
+			"SELECT * FROM",
+			"SELECT * FROM other_table",
+			"SELECT * FROM S3Object WHERE",
+			"SELECT * FROM S3Object LIMIT -1",
+			"SELECT name, COUNT(*) FROM S3Object",
+			"SELECT * FROM S3Object WHERE COUNT(*) > 1",
+			"SELECT 'unterminated FROM S3Object",
+			"SELECT UNKNOWN_FUNCTION(name) FROM S3Object",
+			"SELECT * FROM S3Object s extra",
+		] {
+			assert!(Query::parse(invalid).is_err(), "{} was accepted", invalid);
+		}
+	}
+
+	#[test]
+	fn test_csv_query() {
+		let records = [
+			csv(&["Alice", "Paris", "31"]),
+			csv(&["Bob", "Lyon", "25"]),
+			csv(&["Carol", "Paris", "47"]),
+			csv(&["Dan", "", "9"]),
+		];
+
+		let out = run(
+			"SELECT s.name, s._3 FROM S3Object s WHERE s.city = 'Paris' AND age > 40",
+			&records,
+		)
+		.unwrap();
+		assert_eq!(
+			out,
+			vec![OutputRecord::Columns(vec![
+				("name".into(), Value::String("Carol".into())),
+				("_3".into(), Value::String("47".into())),
+			])]
+		);
+
+		let out = run(
+			"SELECT UPPER(name) || '!', CAST(age AS INT) * 2 FROM S3Object WHERE name LIKE '_o%' OR age BETWEEN 1 AND 10",
+			&records,
+		)
+		.unwrap();
+		assert_eq!(
+			values(&out),
+			vec![
+				vec![Value::String("BOB!".into()), Value::Int(50)],
+				vec![Value::String("DAN!".into()), Value::Int(18)],
+			]
+		);
+
+		let out = run("SELECT * FROM S3Object LIMIT 2", &records).unwrap();
+		assert_eq!(out.len(), 2);
+		assert_eq!(out[0], records[0].columns());
+
+		let out = run(
+			"SELECT COUNT(*), SUM(CAST(age AS INT)), MIN(age), MAX(name), AVG(age) FROM S3Object WHERE city IN ('Paris', 'Lyon')",
+			&records,
+		)
+		.unwrap();
+		assert_eq!(
+			values(&out),
+			vec![vec![
+				Value::Int(3),
+				Value::Int(103),
+				Value::Int(25),
+				Value::String("Carol".into()),
+				Value::Float(103.0 / 3.0),
+			]]
+		);
+
+		assert_eq!(
+			run("SELECT age / 0 FROM S3Object", &records)
+				.unwrap_err()
+				.code,
+			"EvaluatorInvalidArguments"
+		);
+		assert_eq!(
+			run("SELECT CAST(name AS INT) FROM S3Object", &records)
+				.unwrap_err()
+				.code,
+			"CastFailed"
+		);
+	}
+
+	#[test]
+	fn test_json_query() {
+		let records = [
+			Record::Json(
+				serde_json::json!({"id": 1, "user": {"name": "Alice", "tags": ["a", "b"]}}),
+			),
+			Record::Json(serde_json::json!({"id": 2, "user": {"name": "Bob"}, "deleted": true})),
+		];
+
+		let out = run(
+			"SELECT s.id, s.user.name, s.user.tags[1] AS tag FROM S3Object s WHERE s.deleted IS NULL",
+			&records,
+		)
+		.unwrap();
+		assert_eq!(
+			out,
+			vec![OutputRecord::Columns(vec![
+				("id".into(), Value::Int(1)),
+				("name".into(), Value::String("Alice".into())),
+				("tag".into(), Value::String("b".into())),
+			])]
+		);
+
+		let out = run("SELECT * FROM S3Object WHERE deleted = TRUE", &records).unwrap();
+		assert_eq!(out, vec![records[1].columns()]);
+
+		let out = run("SELECT COUNT(s.user.tags) FROM S3Object s", &records).unwrap();
+		assert_eq!(values(&out), vec![vec![Value::Int(1)]]);
+	}
+
+	#[test]
+	fn test_like() {
+		assert!(like("hello", "h%o", None));
+		assert!(like("hello", "_ello", None));
+		assert!(like("", "%", None));
+		assert!(!like("hello", "h_o", None));
+		assert!(like("50%", "50!%", Some('!')));
+		assert!(!like("500", "50!%", Some('!')));
+	}
+}
@@ -0,0 +1,284 @@
+use quick_xml::de::from_reader;
+use std::sync::Arc;
+
+use hyper::header::{HeaderMap, HeaderValue};
+use hyper::{Body, Request, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::s3::error::*;
+use crate::s3::xml::{to_xml_with_header, xmlns_tag, Value};
+use crate::signature::verify_signed_content;
+
+use garage_model::garage::Garage;
+use garage_model::s3::object_table::*;
+use garage_util::data::*;
+
+pub const TAGGING_HEADER: &str = "x-amz-tagging";
+pub const TAGGING_DIRECTIVE_HEADER: &str = "x-amz-tagging-directive";
+pub const TAGGING_COUNT_HEADER: &str = "x-amz-tagging-count";
+
+const MAX_TAGS: usize = 10;
+const MAX_TAG_KEY_LEN: usize = 128;
+const MAX_TAG_VALUE_LEN: usize = 256;
+
+pub async fn handle_get_object_tagging(
+	garage: Arc<Garage>,
+	bucket_id: Uuid,
+	key: &str,
+	version_id: Option<&str>,
+) -> Result<Response<Body>, Error> {
+	let version = get_object_version(&garage, bucket_id, key, version_id).await?;
+
+	let xml = to_xml_with_header(&Tagging::from_garage_tags(version.tags.get()))?;
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.header(http::header::CONTENT_TYPE, "application/xml")
+		.header("x-amz-version-id", version.version_id())
+		.body(Body::from(xml))?)
+}
+
+pub async fn handle_put_object_tagging(
+	garage: Arc<Garage>,
+	bucket_id: Uuid,
+	key: &str,
+	version_id: Option<&str>,
+	req: Request<Body>,
+	content_sha256: Option<Hash>,
+) -> Result<Response<Body>, Error> {
+	let body = hyper::body::to_bytes(req.into_body()).await?;
+
+	if let Some(content_sha256) = content_sha256 {
+		verify_signed_content(content_sha256, &body[..])?;
+	}
+
+	let tags = parse_tagging_xml(&body)?;
+
+	set_object_tags(&garage, bucket_id, key, version_id, tags).await
+}
+
+pub async fn handle_delete_object_tagging(
+	garage: Arc<Garage>,
+	bucket_id: Uuid,
+	key: &str,
+	version_id: Option<&str>,
+) -> Result<Response<Body>, Error> {
+	let mut resp =
+		set_object_tags(&garage, bucket_id, key, version_id, ObjectTags::default()).await?;
+	*resp.status_mut() = StatusCode::NO_CONTENT;
+	Ok(resp)
+}
+
+async fn set_object_tags(
+	garage: &Garage,
+	bucket_id: Uuid,
+	key: &str,
+	version_id: Option<&str>,
+	tags: ObjectTags,
+) -> Result<Response<Body>, Error> {
+	let mut version = get_object_version(garage, bucket_id, key, version_id).await?;
+	version.tags.update(tags);
+	let object = Object::new(bucket_id, key.into(), vec![version.clone()]);
+	garage.object_table.insert(&object).await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.header("x-amz-version-id", version.version_id())
+		.body(Body::empty())?)
+}
+
+/// Get the version of an object whose tags are read or set:
+/// the given version, or the current version of the object
+async fn get_object_version(
+	garage: &Garage,
+	bucket_id: Uuid,
+	key: &str,
+	version_id: Option<&str>,
+) -> Result<ObjectVersion, Error> {
+	let object = garage
+		.object_table
+		.get(&bucket_id, &key.to_string())
+		.await?
+		.ok_or(Error::NoSuchKey)?;
+
+	let version = match version_id {
+		Some(version_id) => object
+			.find_version(version_id)
+			.ok_or(Error::NoSuchVersion)?,
+		None => object.current_data_version().ok_or(Error::NoSuchKey)?,
+	};
+	if !version.is_data() {
+		return Err(Error::bad_request("Delete markers cannot be tagged"));
+	}
+	Ok(version.clone())
+}
+
+/// Get the tags given in the x-amz-tagging header of a request,
+/// encoded as URL query parameters
+pub(crate) fn get_tagging_header(headers: &HeaderMap<HeaderValue>) -> Result<ObjectTags, Error> {
+	match headers.get(TAGGING_HEADER) {
+		Some(v) => {
+			let tags = form_urlencoded::parse(v.to_str()?.as_bytes())
+				.into_owned()
+				.collect::<Vec<_>>();
+			validate_tags(tags)
+		}
+		None => Ok(ObjectTags::default()),
+	}
+}
+
+/// Parse a Tagging XML document, as found in the body of PutObjectTagging
+/// requests and in the `tagging` field of POST object uploads
+pub(crate) fn parse_tagging_xml(xml: &[u8]) -> Result<ObjectTags, Error> {
+	let tagging: Tagging = from_reader(xml)?;
+	tagging.validate_into_garage_tags()
+}
+
+/// Add the x-amz-tagging-count header to the response to a GetObject request
+pub(crate) fn add_tagging_headers(
+	resp: http::response::Builder,
+	version: &ObjectVersion,
+) -> http::response::Builder {
+	match version.tags.get().0.len() {
+		0 => resp,
+		n => resp.header(TAGGING_COUNT_HEADER, n.to_string()),
+	}
+}
+
+fn validate_tags(tags: Vec<(String, String)>) -> Result<ObjectTags, Error> {
+	if tags.len() > MAX_TAGS {
+		return Err(Error::InvalidTag(format!(
+			"Object tags cannot be greater than {}",
+			MAX_TAGS
+		)));
+	}
+	for (i, (k, v)) in tags.iter().enumerate() {
+		if k.is_empty() || k.chars().count() > MAX_TAG_KEY_LEN {
+			return Err(Error::InvalidTag(format!(
+				"The tag key must be between 1 and {} characters long",
+				MAX_TAG_KEY_LEN
+			)));
+		}
+		if v.chars().count() > MAX_TAG_VALUE_LEN {
+			return Err(Error::InvalidTag(format!(
+				"The tag value must be at most {} characters long",
+				MAX_TAG_VALUE_LEN
+			)));
+		}
+		if k.starts_with("aws:") {
+			return Err(Error::InvalidTag(
+				"Tag keys starting with aws: are reserved".into(),
+			));
+		}
+		if tags[..i].iter().any(|(k2, _)| k2 == k) {
+			return Err(Error::InvalidTag(format!("Duplicate tag key: {}", k)));
+		}
+	}
+	Ok(ObjectTags(tags))
+}
+
+// ---- SERIALIZATION AND DESERIALIZATION TO/FROM S3 XML ----
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Tagging {
+	#[serde(serialize_with = "xmlns_tag", skip_deserializing)]
+	pub xmlns: (),
+	#[serde(rename = "TagSet")]
+	pub tag_set: TagSet,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct TagSet {
+	#[serde(rename = "Tag", default)]
+	pub tags: Vec<Tag>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Tag {
+	#[serde(rename = "Key")]
+	pub key: Value,
+	#[serde(rename = "Value")]
+	pub value: Value,
+}
+
+impl Tagging {
+	pub fn validate_into_garage_tags(self) -> Result<ObjectTags, Error> {
+		validate_tags(
+			self.tag_set
+				.tags
+				.into_iter()
+				.map(|t| (t.key.0, t.value.0))
+				.collect(),
+		)
+	}
+
+	pub fn from_garage_tags(tags: &ObjectTags) -> Self {
+		Tagging {
+			xmlns: (),
+			tag_set: TagSet {
+				tags: tags.0.iter().map(Tag::from_garage_tag).collect(),
+			},
+		}
+	}
+}
+
+impl Tag {
+	pub fn from_garage_tag((key, value): &(String, String)) -> Self {
+		Tag {
+			key: Value(key.clone()),
+			value: Value(value.clone()),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use quick_xml::de::from_str;
+
+	#[test]
+	fn test_deserialize_tagging() -> Result<(), Error> {
+		let message = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Tagging xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <TagSet>
+    <Tag>
+      <Key>project</Key>
+      <Value>garage</Value>
+    </Tag>
+    <Tag>
+      <Key>empty</Key>
+      <Value></Value>
+    </Tag>
+  </TagSet>
+</Tagging>"#;
+		let tagging = from_str::<Tagging>(message)?;
+		let tags = tagging.validate_into_garage_tags()?;
+		assert_eq!(
+			tags.0,
+			vec![
+				("project".to_string(), "garage".to_string()),
+				("empty".to_string(), "".to_string())
+			]
+		);
+
+		let message2 = to_xml_with_header(&Tagging::from_garage_tags(&tags))?;
+		let tagging2 = from_str::<Tagging>(&message2)?;
+		assert_eq!(tagging2.validate_into_garage_tags()?, tags);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_validate_tags() {
+		let tag = |k: &str, v: &str| (k.to_string(), v.to_string());
+
+		assert!(validate_tags(vec![tag("a", "b"), tag("c", "")]).is_ok());
+		assert!(validate_tags((0..10).map(|i| tag(&i.to_string(), "x")).collect()).is_ok());
+		assert!(validate_tags((0..11).map(|i| tag(&i.to_string(), "x")).collect()).is_err());
+		assert!(validate_tags(vec![tag("", "b")]).is_err());
+		assert!(validate_tags(vec![tag(&"k".repeat(129), "b")]).is_err());
+		assert!(validate_tags(vec![tag("a", &"v".repeat(257))]).is_err());
+		assert!(validate_tags(vec![tag("aws:reserved", "b")]).is_err());
+		assert!(validate_tags(vec![tag("a", "b"), tag("a", "c")]).is_err());
+	}
+}
@@ -8,7 +8,10 @@ use crate::s3::error::*;
 use crate::s3::xml::{to_xml_with_header, xmlns_tag, IntValue, Value};
 use crate::signature::verify_signed_content;
 
-use garage_model::bucket_table::*;
+use garage_model::bucket_table::{
+	Bucket, Redirect as GarageRedirect, RedirectAll, RedirectCondition, RedirectReplaceKey,
+	RoutingRule as GarageRoutingRule, WebsiteConfig,
+};
 use garage_model::garage::Garage;
 use garage_util::data::*;
 
@@ -18,17 +21,7 @@ pub async fn handle_get_website(bucket: &Bucket) -> Result<Response<Body>, Error
 		.ok_or_internal_error("Bucket should not be deleted at this point")?;
 
 	if let Some(website) = param.website_config.get() {
-		let wc = WebsiteConfiguration {
-			xmlns: (),
-			error_document: website.error_document.as_ref().map(|v| Key {
-				key: Value(v.to_string()),
-			}),
-			index_document: Some(Suffix {
-				suffix: Value(website.index_document.to_string()),
-			}),
-			redirect_all_requests_to: None,
-			routing_rules: None,
-		};
+		let wc = WebsiteConfiguration::from_garage_website_config(website);
 		let xml = to_xml_with_header(&wc)?;
 		Ok(Response::builder()
 			.status(StatusCode::OK)
@@ -103,17 +96,17 @@ pub struct WebsiteConfiguration {
 	#[serde(rename = "RedirectAllRequestsTo")]
 	pub redirect_all_requests_to: Option<Target>,
 	#[serde(rename = "RoutingRules")]
-	pub routing_rules: Option<Vec<RoutingRule>>,
+	pub routing_rules: Option<RoutingRules>,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
-pub struct RoutingRule {
+pub struct RoutingRules {
 	#[serde(rename = "RoutingRule")]
-	pub inner: RoutingRuleInner,
+	pub rules: Vec<RoutingRule>,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
-pub struct RoutingRuleInner {
+pub struct RoutingRule {
 	#[serde(rename = "Condition")]
 	pub condition: Option<Condition>,
 	#[serde(rename = "Redirect")]
@@ -183,8 +176,8 @@ impl WebsiteConfiguration {
 			rart.validate()?;
 		}
 		if let Some(ref rrs) = self.routing_rules {
-			for rr in rrs {
-				rr.inner.validate()?;
+			for rr in rrs.rules.iter() {
+				rr.validate()?;
 			}
 		}
 
@@ -192,22 +185,56 @@ impl WebsiteConfiguration {
 	}
 
 	pub fn into_garage_website_config(self) -> Result<WebsiteConfig, Error> {
-		if self.redirect_all_requests_to.is_some() {
-			Err(Error::NotImplemented(
-				"S3 website redirects are not currently implemented in Garage.".into(),
-			))
-		} else if self.routing_rules.map(|x| !x.is_empty()).unwrap_or(false) {
-			Err(Error::NotImplemented(
-				"S3 routing rules are not currently implemented in Garage.".into(),
-			))
-		} else {
-			Ok(WebsiteConfig {
-				index_document: self
-					.index_document
-					.map(|x| x.suffix.0)
-					.unwrap_or_else(|| "index.html".to_string()),
-				error_document: self.error_document.map(|x| x.key.0),
+		Ok(WebsiteConfig {
+			index_document: self
+				.index_document
+				.map(|x| x.suffix.0)
+				.unwrap_or_else(|| "index.html".to_string()),
+			error_document: self.error_document.map(|x| x.key.0),
+			redirect_all: self.redirect_all_requests_to.map(|x| RedirectAll {
+				hostname: x.hostname.0,
+				protocol: x.protocol.map(|p| p.0),
+			}),
+			routing_rules: self
+				.routing_rules
+				.map(|r| r.rules)
+				.unwrap_or_default()
+				.into_iter()
+				.map(RoutingRule::into_garage_routing_rule)
+				.collect(),
+		})
+	}
+
+	pub fn from_garage_website_config(config: &WebsiteConfig) -> Self {
+		if let Some(redirect_all) = &config.redirect_all {
+			return Self {
+				xmlns: (),
+				error_document: None,
+				index_document: None,
+				redirect_all_requests_to: Some(Target {
+					hostname: Value(redirect_all.hostname.clone()),
+					protocol: redirect_all.protocol.clone().map(Value),
+				}),
+				routing_rules: None,
+			};
+		}
+		Self {
+			xmlns: (),
+			error_document: config.error_document.as_ref().map(|v| Key {
+				key: Value(v.to_string()),
+			}),
+			index_document: Some(Suffix {
+				suffix: Value(config.index_document.to_string()),
+			}),
+			redirect_all_requests_to: None,
+			routing_rules: Some(RoutingRules {
+				rules: config
+					.routing_rules
+					.iter()
+					.map(RoutingRule::from_garage_routing_rule)
+					.collect(),
 			})
+			.filter(|r| !r.rules.is_empty()),
 		}
 	}
 }
@@ -247,8 +274,11 @@ impl Target {
 	}
 }
 
-impl RoutingRuleInner {
+impl RoutingRule {
 	pub fn validate(&self) -> Result<(), Error> {
+		if let Some(condition) = &self.condition {
+			condition.validate()?;
+		}
 		let has_prefix = self
 			.condition
 			.as_ref()
@@ -256,6 +286,69 @@ impl RoutingRuleInner {
 			.is_some();
 		self.redirect.validate(has_prefix)
 	}
+
+	fn into_garage_routing_rule(self) -> GarageRoutingRule {
+		let replace_key = match (self.redirect.replace_prefix, self.redirect.replace_full) {
+			(Some(prefix), _) => Some(RedirectReplaceKey::Prefix(prefix.0)),
+			(None, Some(full)) => Some(RedirectReplaceKey::Full(full.0)),
+			(None, None) => None,
+		};
+		GarageRoutingRule {
+			condition: self.condition.map(|c| RedirectCondition {
+				http_error_code: c.http_error_code.map(|x| x.0 as u16),
+				prefix: c.prefix.map(|x| x.0),
+			}),
+			redirect: GarageRedirect {
+				hostname: self.redirect.hostname.map(|x| x.0),
+				protocol: self.redirect.protocol.map(|x| x.0),
+				http_redirect_code: self
+					.redirect
+					.http_redirect_code
+					.map(|x| x.0 as u16)
+					.unwrap_or(301),
+				replace_key,
+			},
+		}
+	}
+
+	fn from_garage_routing_rule(rule: &GarageRoutingRule) -> Self {
+		let (replace_prefix, replace_full) = match &rule.redirect.replace_key {
+			Some(RedirectReplaceKey::Prefix(p)) => (Some(Value(p.clone())), None),
+			Some(RedirectReplaceKey::Full(k)) => (None, Some(Value(k.clone()))),
+			None => (None, None),
+		};
+		Self {
+			condition: rule.condition.as_ref().map(|c| Condition {
+				http_error_code: c.http_error_code.map(|x| IntValue(x as i64)),
+				prefix: c.prefix.clone().map(Value),
+			}),
+			redirect: Redirect {
+				hostname: rule.redirect.hostname.clone().map(Value),
+				protocol: rule.redirect.protocol.clone().map(Value),
+				http_redirect_code: Some(IntValue(rule.redirect.http_redirect_code as i64)),
+				replace_prefix,
+				replace_full,
+			},
+		}
+	}
+}
+
+impl Condition {
+	pub fn validate(&self) -> Result<(), Error> {
+		if self.http_error_code.is_none() && self.prefix.is_none() {
+			return Err(Error::bad_request(
+				"Bad XML: routing rule condition must have HttpErrorCodeReturnedEquals or KeyPrefixEquals",
+			));
+		}
+		if let Some(IntValue(code)) = self.http_error_code {
+			if !(400..600).contains(&code) {
+				return Err(Error::bad_request(
+					"Bad XML: HttpErrorCodeReturnedEquals must be a 4XX or 5XX code",
+				));
+			}
+		}
+		Ok(())
+	}
 }
 
 impl Redirect {
@@ -277,6 +370,13 @@ impl Redirect {
 				return Err(Error::bad_request("Bad XML: invalid protocol"));
 			}
 		}
+		if let Some(IntValue(code)) = self.http_redirect_code {
+			if !(300..400).contains(&code) {
+				return Err(Error::bad_request(
+					"Bad XML: HttpRedirectCode must be a 3XX code",
+				));
+			}
+		}
 		// TODO there are probably more invalide cases, but which ones?
 		Ok(())
 	}
@@ -331,8 +431,8 @@ mod tests {
 				hostname: Value("garage.tld".to_owned()),
 				protocol: Some(Value("https".to_owned())),
 			}),
-			routing_rules: Some(vec![RoutingRule {
-				inner: RoutingRuleInner {
+			routing_rules: Some(RoutingRules {
+				rules: vec![RoutingRule {
 					condition: Some(Condition {
 						http_error_code: Some(IntValue(404)),
 						prefix: Some(Value("prefix1".to_owned())),
@@ -344,8 +444,8 @@ mod tests {
 						replace_prefix: Some(Value("prefix2".to_owned())),
 						replace_full: Some(Value("fullkey".to_owned())),
 					},
-				},
-			}]),
+				}],
+			}),
 		};
 		assert_eq! {
 			ref_value,
@@ -359,4 +459,88 @@ mod tests {
 
 		Ok(())
 	}
+
+	#[test]
+	fn test_routing_rules() -> Result<(), Error> {
+		let message = r#"<?xml version="1.0" encoding="UTF-8"?>
+<WebsiteConfiguration xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+   <IndexDocument>
+      <Suffix>index.html</Suffix>
+   </IndexDocument>
+   <RoutingRules>
+      <RoutingRule>
+         <Condition>
+            <KeyPrefixEquals>docs/</KeyPrefixEquals>
+         </Condition>
+         <Redirect>
+            <ReplaceKeyPrefixWith>documents/</ReplaceKeyPrefixWith>
+         </Redirect>
+      </RoutingRule>
+      <RoutingRule>
+         <Condition>
+            <HttpErrorCodeReturnedEquals>404</HttpErrorCodeReturnedEquals>
+         </Condition>
+         <Redirect>
+            <HostName>example.com</HostName>
+            <HttpRedirectCode>302</HttpRedirectCode>
+            <ReplaceKeyWith>not-found.html</ReplaceKeyWith>
+         </Redirect>
+      </RoutingRule>
+   </RoutingRules>
+</WebsiteConfiguration>"#;
+		let conf: WebsiteConfiguration = from_str(message).unwrap();
+		conf.validate()?;
+		let config = conf.into_garage_website_config()?;
+		assert_eq!(
+			config.routing_rules,
+			vec![
+				GarageRoutingRule {
+					condition: Some(RedirectCondition {
+						http_error_code: None,
+						prefix: Some("docs/".into()),
+					}),
+					redirect: GarageRedirect {
+						hostname: None,
+						protocol: None,
+						http_redirect_code: 301,
+						replace_key: Some(RedirectReplaceKey::Prefix("documents/".into())),
+					},
+				},
+				GarageRoutingRule {
+					condition: Some(RedirectCondition {
+						http_error_code: Some(404),
+						prefix: None,
+					}),
+					redirect: GarageRedirect {
+						hostname: Some("example.com".into()),
+						protocol: None,
+						http_redirect_code: 302,
+						replace_key: Some(RedirectReplaceKey::Full("not-found.html".into())),
+					},
+				},
+			]
+		);
+
+		// Round-trip through the S3 XML representation
+		let message2 =
+			to_xml_with_header(&WebsiteConfiguration::from_garage_website_config(&config))?;
+		let conf2: WebsiteConfiguration = from_str(&message2).unwrap();
+		assert_eq!(conf2.into_garage_website_config()?, config);
+
+		// Invalid redirect and error codes
+		for (code_tag, code) in [
+			("HttpRedirectCode", 200),
+			("HttpErrorCodeReturnedEquals", 302),
+		] {
+			let message = format!(
+				"<WebsiteConfiguration><RoutingRules><RoutingRule><Condition><{t}>{c}</{t}></Condition><Redirect><{t}>{c}</{t}></Redirect></RoutingRule></RoutingRules></WebsiteConfiguration>",
+				t = code_tag,
+				c = code
+			);
+			let conf: WebsiteConfiguration = from_str(&message).unwrap();
+			assert!(conf.validate().is_err());
+		}
+
+		Ok(())
+	}
 }
@@ -69,8 +69,10 @@ pub struct Deleted {
 	pub key: Value,
 	#[serde(rename = "VersionId")]
 	pub version_id: Value,
+	#[serde(rename = "DeleteMarker")]
+	pub delete_marker: Option<Value>,
 	#[serde(rename = "DeleteMarkerVersionId")]
-	pub delete_marker_version_id: Value,
+	pub delete_marker_version_id: Option<Value>,
 }
 
 #[derive(Debug, Serialize, PartialEq, Eq)]
@@ -131,6 +133,27 @@ pub struct CompleteMultipartUploadResult {
 	pub key: Value,
 	#[serde(rename = "ETag")]
 	pub etag: Value,
+	#[serde(rename = "ChecksumCRC32")]
+	pub checksum_crc32: Option<Value>,
+	#[serde(rename = "ChecksumCRC32C")]
+	pub checksum_crc32c: Option<Value>,
+	#[serde(rename = "ChecksumSHA1")]
+	pub checksum_sha1: Option<Value>,
+	#[serde(rename = "ChecksumSHA256")]
+	pub checksum_sha256: Option<Value>,
+}
+
+/// Checksum of an object, in the element of the algorithm it was computed with
+#[derive(Debug, Serialize, PartialEq, Eq, Default)]
+pub struct Checksum {
+	#[serde(rename = "ChecksumCRC32")]
+	pub checksum_crc32: Option<Value>,
+	#[serde(rename = "ChecksumCRC32C")]
+	pub checksum_crc32c: Option<Value>,
+	#[serde(rename = "ChecksumSHA1")]
+	pub checksum_sha1: Option<Value>,
+	#[serde(rename = "ChecksumSHA256")]
+	pub checksum_sha256: Option<Value>,
 }
 
 #[derive(Debug, Serialize, PartialEq, Eq)]
@@ -187,6 +210,72 @@ pub struct ListMultipartUploadsResult {
 	pub encoding_type: Option<Value>,
 }
 
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct ListVersionsResult {
+	#[serde(serialize_with = "xmlns_tag")]
+	pub xmlns: (),
+	#[serde(rename = "Name")]
+	pub name: Value,
+	#[serde(rename = "Prefix")]
+	pub prefix: Value,
+	#[serde(rename = "KeyMarker")]
+	pub key_marker: Option<Value>,
+	#[serde(rename = "VersionIdMarker")]
+	pub version_id_marker: Option<Value>,
+	#[serde(rename = "NextKeyMarker")]
+	pub next_key_marker: Option<Value>,
+	#[serde(rename = "NextVersionIdMarker")]
+	pub next_version_id_marker: Option<Value>,
+	#[serde(rename = "MaxKeys")]
+	pub max_keys: IntValue,
+	#[serde(rename = "Delimiter")]
+	pub delimiter: Option<Value>,
+	#[serde(rename = "EncodingType")]
+	pub encoding_type: Option<Value>,
+	#[serde(rename = "IsTruncated")]
+	pub is_truncated: Value,
+	#[serde(rename = "Version")]
+	pub versions: Vec<ListVersionItem>,
+	#[serde(rename = "DeleteMarker")]
+	pub delete_markers: Vec<ListDeleteMarkerItem>,
+	#[serde(rename = "CommonPrefixes")]
+	pub common_prefixes: Vec<CommonPrefix>,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct ListVersionItem {
+	#[serde(rename = "Key")]
+	pub key: Value,
+	#[serde(rename = "VersionId")]
+	pub version_id: Value,
+	#[serde(rename = "IsLatest")]
+	pub is_latest: Value,
+	#[serde(rename = "LastModified")]
+	pub last_modified: Value,
+	#[serde(rename = "ETag")]
+	pub etag: Value,
+	#[serde(rename = "Size")]
+	pub size: IntValue,
+	#[serde(rename = "StorageClass")]
+	pub storage_class: Value,
+	#[serde(rename = "Owner")]
+	pub owner: Owner,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct ListDeleteMarkerItem {
+	#[serde(rename = "Key")]
+	pub key: Value,
+	#[serde(rename = "VersionId")]
+	pub version_id: Value,
+	#[serde(rename = "IsLatest")]
+	pub is_latest: Value,
+	#[serde(rename = "LastModified")]
+	pub last_modified: Value,
+	#[serde(rename = "Owner")]
+	pub owner: Owner,
+}
+
 #[derive(Debug, Serialize, PartialEq, Eq)]
 pub struct PartItem {
 	#[serde(rename = "ETag")]
@@ -303,6 +392,54 @@ pub struct PostObject {
 	pub etag: Value,
 }
 
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct GetObjectAttributesOutput {
+	#[serde(serialize_with = "xmlns_tag")]
+	pub xmlns: (),
+	#[serde(rename = "ETag")]
+	pub etag: Option<Value>,
+	#[serde(rename = "Checksum")]
+	pub checksum: Option<Checksum>,
+	#[serde(rename = "ObjectParts")]
+	pub object_parts: Option<ObjectParts>,
+	#[serde(rename = "StorageClass")]
+	pub storage_class: Option<Value>,
+	#[serde(rename = "ObjectSize")]
+	pub object_size: Option<IntValue>,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct ObjectParts {
+	#[serde(rename = "TotalPartsCount")]
+	pub total_parts_count: IntValue,
+	#[serde(rename = "PartNumberMarker")]
+	pub part_number_marker: Option<IntValue>,
+	#[serde(rename = "NextPartNumberMarker")]
+	pub next_part_number_marker: Option<IntValue>,
+	#[serde(rename = "MaxParts")]
+	pub max_parts: IntValue,
+	#[serde(rename = "IsTruncated")]
+	pub is_truncated: Value,
+	#[serde(rename = "Part")]
+	pub parts: Vec<ObjectPart>,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct ObjectPart {
+	#[serde(rename = "PartNumber")]
+	pub part_number: IntValue,
+	#[serde(rename = "Size")]
+	pub size: IntValue,
+	#[serde(rename = "ChecksumCRC32")]
+	pub checksum_crc32: Option<Value>,
+	#[serde(rename = "ChecksumCRC32C")]
+	pub checksum_crc32c: Option<Value>,
+	#[serde(rename = "ChecksumSHA1")]
+	pub checksum_sha1: Option<Value>,
+	#[serde(rename = "ChecksumSHA256")]
+	pub checksum_sha256: Option<Value>,
+}
+
 #[cfg(test)]
 mod tests {
 	use super::*;
@@ -419,12 +556,14 @@ mod tests {
 				Deleted {
 					key: Value("a/plop".to_string()),
 					version_id: Value("qsdfjklm".to_string()),
-					delete_marker_version_id: Value("wxcvbn".to_string()),
+					delete_marker: Some(Value("true".to_string())),
+					delete_marker_version_id: Some(Value("wxcvbn".to_string())),
 				},
 				Deleted {
 					key: Value("b/plip".to_string()),
 					version_id: Value("1234".to_string()),
-					delete_marker_version_id: Value("4321".to_string()),
+					delete_marker: None,
+					delete_marker_version_id: None,
 				},
 			],
 			errors: vec![
@@ -449,12 +588,12 @@ mod tests {
     <Deleted>\
         <Key>a/plop</Key>\
         <VersionId>qsdfjklm</VersionId>\
+        <DeleteMarker>true</DeleteMarker>\
         <DeleteMarkerVersionId>wxcvbn</DeleteMarkerVersionId>\
     </Deleted>\
     <Deleted>\
         <Key>b/plip</Key>\
         <VersionId>1234</VersionId>\
-        <DeleteMarkerVersionId>4321</DeleteMarkerVersionId>\
     </Deleted>\
     <Error>\
         <Code>NotFound</Code>\
@@ -500,6 +639,12 @@ mod tests {
 			bucket: Value("mybucket".to_string()),
 			key: Value("a/plop".to_string()),
 			etag: Value("\"3858f62230ac3c915f300c664312c11f-9\"".to_string()),
+			checksum_crc32: None,
+			checksum_crc32c: None,
+			checksum_sha1: None,
+			checksum_sha256: Some(Value(
+				"ZkKEwVuLcBSXTxuQRaL1+xFvIcDT1GaGlj5P4ppBVvY=-9".to_string(),
+			)),
 		};
 		assert_eq!(
 			to_xml_with_header(&result)?,
@@ -509,6 +654,7 @@ mod tests {
 	<Bucket>mybucket</Bucket>\
 	<Key>a/plop</Key>\
 	<ETag>&quot;3858f62230ac3c915f300c664312c11f-9&quot;</ETag>\
+	<ChecksumSHA256>ZkKEwVuLcBSXTxuQRaL1+xFvIcDT1GaGlj5P4ppBVvY=-9</ChecksumSHA256>\
 </CompleteMultipartUploadResult>"
 		);
 		Ok(())
@@ -841,4 +987,57 @@ mod tests {
 
 		Ok(())
 	}
+
+	#[test]
+	fn get_object_attributes_output() -> Result<(), ApiError> {
+		let result = GetObjectAttributesOutput {
+			xmlns: (),
+			etag: Some(Value("3858f62230ac3c915f300c664312c11f-2".to_string())),
+			checksum: Some(Checksum {
+				checksum_crc32: Some(Value("3KBqxg==-2".to_string())),
+				..Default::default()
+			}),
+			object_parts: Some(ObjectParts {
+				total_parts_count: IntValue(2),
+				part_number_marker: None,
+				next_part_number_marker: Some(IntValue(1)),
+				max_parts: IntValue(1),
+				is_truncated: Value("true".to_string()),
+				parts: vec![ObjectPart {
+					part_number: IntValue(1),
+					size: IntValue(5242880),
+					checksum_crc32: Some(Value("DUoRhQ==".to_string())),
+					checksum_crc32c: None,
+					checksum_sha1: None,
+					checksum_sha256: None,
+				}],
+			}),
+			storage_class: Some(Value("STANDARD".to_string())),
+			object_size: Some(IntValue(10485760)),
+		};
+		assert_eq!(
+			to_xml_with_header(&result)?,
+			"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+<GetObjectAttributesOutput xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\">\
+	<ETag>3858f62230ac3c915f300c664312c11f-2</ETag>\
+	<Checksum>\
+		<ChecksumCRC32>3KBqxg==-2</ChecksumCRC32>\
+	</Checksum>\
+	<ObjectParts>\
+		<TotalPartsCount>2</TotalPartsCount>\
+		<NextPartNumberMarker>1</NextPartNumberMarker>\
+		<MaxParts>1</MaxParts>\
+		<IsTruncated>true</IsTruncated>\
+		<Part>\
+			<PartNumber>1</PartNumber>\
+			<Size>5242880</Size>\
+			<ChecksumCRC32>DUoRhQ==</ChecksumCRC32>\
+		</Part>\
+	</ObjectParts>\
+	<StorageClass>STANDARD</StorageClass>\
+	<ObjectSize>10485760</ObjectSize>\
+</GetObjectAttributesOutput>"
+		);
+		Ok(())
+	}
 }
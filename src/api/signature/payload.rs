@@ -11,6 +11,9 @@ use garage_util::data::Hash;
 use garage_model::garage::Garage;
 use garage_model::key_table::*;
 
+use super::streaming::{
+	STREAMING_SIGNED_PAYLOAD, STREAMING_SIGNED_PAYLOAD_TRAILER, STREAMING_UNSIGNED_PAYLOAD_TRAILER,
+};
 use super::LONG_DATETIME;
 use super::{compute_scope, signing_hmac};
 
@@ -74,9 +77,14 @@ pub async fn check_payload_signature(
 	)
 	.await?;
 
-	let content_sha256 = if authorization.content_sha256 == "UNSIGNED-PAYLOAD" {
+	let content_sha256 = if authorization.content_sha256 == "UNSIGNED-PAYLOAD"
+		|| authorization.content_sha256 == STREAMING_UNSIGNED_PAYLOAD_TRAILER
+	{
 		None
-	} else if authorization.content_sha256 == "STREAMING-AWS4-HMAC-SHA256-PAYLOAD" {
+	} else if authorization.content_sha256 == STREAMING_SIGNED_PAYLOAD
+		|| authorization.content_sha256 == STREAMING_SIGNED_PAYLOAD_TRAILER
+	{
+		// The signature of the request is the seed of the signatures of the chunks
 		let bytes = hex::decode(authorization.signature).ok_or_bad_request("Invalid signature")?;
 		Some(Hash::try_from(&bytes).ok_or_bad_request("Invalid signature")?)
 	} else {
@@ -340,11 +348,14 @@ pub async fn verify_v4(
 		.await?
 		.filter(|k| !k.state.is_deleted())
 		.ok_or_else(|| Error::forbidden(format!("No such key: {}", &key_id)))?;
+	if !key.is_enabled() {
+		return Err(Error::forbidden(format!("Key is disabled: {}", &key_id)));
+	}
 	let key_p = key.params().unwrap();
 
 	let mut hmac = signing_hmac(
 		date,
-		&key_p.secret_key,
+		key_p.secret_key.get(),
 		&garage.config.s3_api.s3_region,
 		service,
 	)
@@ -355,5 +366,7 @@ pub async fn verify_v4(
 		return Err(Error::forbidden("Invalid signature".to_string()));
 	}
 
+	garage.key_helper().record_key_use(&key);
+
 	Ok(key)
 }
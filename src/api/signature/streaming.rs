@@ -1,19 +1,33 @@
 use std::pin::Pin;
 
+use base64::prelude::*;
 use chrono::{DateTime, NaiveDateTime, Utc};
 use futures::prelude::*;
 use futures::task;
 use garage_model::key_table::Key;
 use hmac::Mac;
 use hyper::body::Bytes;
+use hyper::header::HeaderMap;
 use hyper::{Body, Request};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
 
+use garage_model::s3::object_table::ChecksumAlgorithm;
 use garage_util::data::Hash;
 
 use super::{compute_scope, sha256sum, HmacSha256, LONG_DATETIME};
 
 use crate::signature::error::*;
 
+/// Chunks are signed, the payload has no trailer
+pub const STREAMING_SIGNED_PAYLOAD: &str = "STREAMING-AWS4-HMAC-SHA256-PAYLOAD";
+/// Chunks are signed, and followed by a signed trailer
+pub const STREAMING_SIGNED_PAYLOAD_TRAILER: &str = "STREAMING-AWS4-HMAC-SHA256-PAYLOAD-TRAILER";
+/// Chunks are not signed, and followed by a trailer that is not signed either
+pub const STREAMING_UNSIGNED_PAYLOAD_TRAILER: &str = "STREAMING-UNSIGNED-PAYLOAD-TRAILER";
+
+/// Decode the body of requests sent with the `aws-chunked` content encoding,
+/// verifying the signatures of its chunks and the checksum given in its trailer if any
 pub fn parse_streaming_body(
 	api_key: &Key,
 	req: Request<Body>,
@@ -21,46 +35,62 @@ pub fn parse_streaming_body(
 	region: &str,
 	service: &str,
 ) -> Result<Request<Body>, Error> {
-	match req.headers().get("x-amz-content-sha256") {
-		Some(header) if header == "STREAMING-AWS4-HMAC-SHA256-PAYLOAD" => {
-			let signature = content_sha256
-				.take()
-				.ok_or_bad_request("No signature provided")?;
-
-			let secret_key = &api_key
-				.state
-				.as_option()
-				.ok_or_internal_error("Deleted key state")?
-				.secret_key;
-
-			let date = req
-				.headers()
-				.get("x-amz-date")
-				.ok_or_bad_request("Missing X-Amz-Date field")?
-				.to_str()?;
-			let date: NaiveDateTime = NaiveDateTime::parse_from_str(date, LONG_DATETIME)
-				.ok_or_bad_request("Invalid date")?;
-			let date: DateTime<Utc> = DateTime::from_utc(date, Utc);
-
-			let scope = compute_scope(&date, region, service);
-			let signing_hmac = crate::signature::signing_hmac(&date, secret_key, region, service)
-				.ok_or_internal_error("Unable to build signing HMAC")?;
-
-			Ok(req.map(move |body| {
-				Body::wrap_stream(
-					SignedPayloadStream::new(
-						body.map_err(Error::from),
-						signing_hmac,
-						date,
-						&scope,
-						signature,
-					)
+	let (signed, has_trailer) = match req.headers().get("x-amz-content-sha256") {
+		Some(header) if header == STREAMING_SIGNED_PAYLOAD => (true, false),
+		Some(header) if header == STREAMING_SIGNED_PAYLOAD_TRAILER => (true, true),
+		Some(header) if header == STREAMING_UNSIGNED_PAYLOAD_TRAILER => (false, true),
+		_ => return Ok(req),
+	};
+
+	let trailer = if has_trailer {
+		Some(PayloadChecksum::from_headers(req.headers())?)
+	} else {
+		None
+	};
+
+	if !signed {
+		return Ok(req.map(move |body| {
+			Body::wrap_stream(
+				SignedPayloadStream::new_unsigned(body.map_err(Error::from), trailer)
 					.map_err(Error::from),
-				)
-			}))
-		}
-		_ => Ok(req),
+			)
+		}));
 	}
+
+	let signature = content_sha256
+		.take()
+		.ok_or_bad_request("No signature provided")?;
+
+	let secret_key = &api_key
+		.state
+		.as_option()
+		.ok_or_internal_error("Deleted key state")?
+		.secret_key
+		.get();
+
+	let date = req
+		.headers()
+		.get("x-amz-date")
+		.ok_or_bad_request("Missing X-Amz-Date field")?
+		.to_str()?;
+	let date: NaiveDateTime =
+		NaiveDateTime::parse_from_str(date, LONG_DATETIME).ok_or_bad_request("Invalid date")?;
+	let date: DateTime<Utc> = DateTime::from_utc(date, Utc);
+
+	let scope = compute_scope(&date, region, service);
+	let signing_hmac = crate::signature::signing_hmac(&date, secret_key, region, service)
+		.ok_or_internal_error("Unable to build signing HMAC")?;
+
+	Ok(req.map(move |body| {
+		let stream = SignedPayloadStream::new(
+			body.map_err(Error::from),
+			signing_hmac,
+			date,
+			&scope,
+			signature,
+		);
+		Body::wrap_stream(stream.with_trailer(trailer).map_err(Error::from))
+	}))
 }
 
 /// Result of `sha256("")`
@@ -90,6 +120,115 @@ fn compute_streaming_payload_signature(
 	Ok(Hash::try_from(&hmac.finalize().into_bytes()).ok_or_internal_error("Invalid signature")?)
 }
 
+/// The signature of the trailer covers its headers, each of them written as
+/// `name:value\n`, chained to the signature of the last chunk
+fn compute_streaming_trailer_signature(
+	signing_hmac: &HmacSha256,
+	date: DateTime<Utc>,
+	scope: &str,
+	previous_signature: Hash,
+	trailer: &[(String, String)],
+) -> Result<Hash, Error> {
+	let mut canonical_trailer = String::new();
+	for (name, value) in trailer {
+		canonical_trailer.push_str(&format!("{}:{}\n", name, value));
+	}
+
+	let string_to_sign = [
+		"AWS4-HMAC-SHA256-TRAILER",
+		&date.format(LONG_DATETIME).to_string(),
+		scope,
+		&hex::encode(previous_signature),
+		&hex::encode(sha256sum(canonical_trailer.as_bytes())),
+	]
+	.join("\n");
+
+	let mut hmac = signing_hmac.clone();
+	hmac.update(string_to_sign.as_bytes());
+
+	Ok(Hash::try_from(&hmac.finalize().into_bytes()).ok_or_internal_error("Invalid signature")?)
+}
+
+/// Checksum of the decoded payload, announced in the `x-amz-trailer` header
+/// and whose expected value is given in the trailer of the payload, or whose
+/// expected value is given directly in a `x-amz-checksum-*` header
+pub struct PayloadChecksum {
+	header_name: String,
+	hasher: ChecksumHasher,
+}
+
+enum ChecksumHasher {
+	Crc32(crc32fast::Hasher),
+	Crc32c(u32),
+	Sha1(Sha1),
+	Sha256(Sha256),
+}
+
+impl PayloadChecksum {
+	fn from_headers(headers: &HeaderMap) -> Result<Self, Error> {
+		let header_name = headers
+			.get("x-amz-trailer")
+			.ok_or_bad_request("Missing x-amz-trailer header")?
+			.to_str()?
+			.trim()
+			.to_lowercase();
+		Self::new(&header_name)
+	}
+
+	pub fn new(header_name: &str) -> Result<Self, Error> {
+		let hasher = match header_name {
+			"x-amz-checksum-crc32" => ChecksumHasher::Crc32(crc32fast::Hasher::new()),
+			"x-amz-checksum-crc32c" => ChecksumHasher::Crc32c(0),
+			"x-amz-checksum-sha1" => ChecksumHasher::Sha1(Sha1::new()),
+			"x-amz-checksum-sha256" => ChecksumHasher::Sha256(Sha256::new()),
+			_ => {
+				return Err(Error::bad_request(format!(
+					"Unsupported trailer: {}",
+					header_name
+				)))
+			}
+		};
+		Ok(Self {
+			header_name: header_name.to_string(),
+			hasher,
+		})
+	}
+
+	/// Name of the header giving the expected value of this checksum
+	pub fn header_name(&self) -> &str {
+		&self.header_name
+	}
+
+	pub fn algorithm(&self) -> ChecksumAlgorithm {
+		match &self.hasher {
+			ChecksumHasher::Crc32(_) => ChecksumAlgorithm::Crc32,
+			ChecksumHasher::Crc32c(_) => ChecksumAlgorithm::Crc32c,
+			ChecksumHasher::Sha1(_) => ChecksumAlgorithm::Sha1,
+			ChecksumHasher::Sha256(_) => ChecksumAlgorithm::Sha256,
+		}
+	}
+
+	pub fn update(&mut self, data: &[u8]) {
+		match &mut self.hasher {
+			ChecksumHasher::Crc32(h) => h.update(data),
+			ChecksumHasher::Crc32c(crc) => *crc = crc32c::crc32c_append(*crc, data),
+			ChecksumHasher::Sha1(h) => h.update(data),
+			ChecksumHasher::Sha256(h) => h.update(data),
+		}
+	}
+
+	/// Checksum of the data seen so far, encoded in base64 as in the trailer
+	pub fn finalize(&self) -> String {
+		let digest = match &self.hasher {
+			ChecksumHasher::Crc32(h) => h.clone().finalize().to_be_bytes().to_vec(),
+			ChecksumHasher::Crc32c(crc) => crc.to_be_bytes().to_vec(),
+			ChecksumHasher::Sha1(h) => h.clone().finalize().to_vec(),
+			ChecksumHasher::Sha256(h) => h.clone().finalize().to_vec(),
+		};
+		BASE64_STANDARD.encode(digest)
+	}
+}
+
 mod payload {
 	use garage_util::data::Hash;
 
@@ -110,15 +249,17 @@ mod payload {
 	#[derive(Debug, Clone)]
 	pub struct Header {
 		pub size: usize,
-		pub signature: Hash,
+		/// Absent from the chunks of payloads that are not signed
+		pub signature: Option<Hash>,
 	}
 
 	impl Header {
 		pub fn parse(input: &[u8]) -> nom::IResult<&[u8], Self, Error<&[u8]>> {
 			use nom::bytes::streaming::tag;
 			use nom::character::streaming::hex_digit1;
-			use nom::combinator::map_res;
+			use nom::combinator::{map_res, opt};
 			use nom::number::streaming::hex_u32;
+			use nom::sequence::preceded;
 
 			macro_rules! try_parse {
 				($expr:expr) => {
@@ -127,11 +268,16 @@ mod payload {
 			}
 
 			let (input, size) = try_parse!(hex_u32(input));
-			let (input, _) = try_parse!(tag(";")(input));
-
-			let (input, _) = try_parse!(tag("chunk-signature=")(input));
-			let (input, data) = try_parse!(map_res(hex_digit1, hex::decode)(input));
-			let signature = Hash::try_from(&data).ok_or(nom::Err::Failure(Error::BadSignature))?;
+			let (input, data) = try_parse!(opt(preceded(
+				tag(";chunk-signature="),
+				map_res(hex_digit1, hex::decode)
+			))(input));
+			let signature = match data {
+				Some(data) => {
+					Some(Hash::try_from(&data).ok_or(nom::Err::Failure(Error::BadSignature))?)
+				}
+				None => None,
+			};
 
 			let (input, _) = try_parse!(tag("\r\n")(input));
 
@@ -143,6 +289,46 @@ mod payload {
 			Ok((input, header))
 		}
 	}
+
+	/// Headers of the trailer, as pairs of a lowercase name and a value
+	pub type Trailer = Vec<(String, String)>;
+
+	/// The trailer is a list of `name:value` headers, each on its own line,
+	/// followed by an empty line
+	pub fn parse_trailer(input: &[u8]) -> nom::IResult<&[u8], Trailer, Error<&[u8]>> {
+		use nom::bytes::streaming::{tag, take_until};
+
+		macro_rules! try_parse {
+			($expr:expr) => {
+				$expr.map_err(|e: nom::Err<nom::error::Error<_>>| e.map(Error::Parser))?
+			};
+		}
+
+		let mut input = input;
+		let mut trailer = vec![];
+		loop {
+			let (rest, line) = try_parse!(take_until("\r\n")(input));
+			let (rest, _) = try_parse!(tag("\r\n")(rest));
+			if line.is_empty() {
+				return Ok((rest, trailer));
+			}
+
+			let line = std::str::from_utf8(line).map_err(|_| {
+				nom::Err::Failure(Error::Parser(nom::error::Error::new(
+					line,
+					nom::error::ErrorKind::Char,
+				)))
+			})?;
+			let (name, value) = line.split_once(':').ok_or_else(|| {
+				nom::Err::Failure(Error::Parser(nom::error::Error::new(
+					input,
+					nom::error::ErrorKind::Tag,
+				)))
+			})?;
+			trailer.push((name.trim().to_lowercase(), value.trim().to_string()));
+			input = rest;
+		}
+	}
 }
 
 #[derive(Debug)]
@@ -189,6 +375,16 @@ struct SignedPayload {
 	data: Bytes,
 }
 
+/// State needed to verify the signatures of the chunks of a signed payload
+struct PayloadSigning {
+	datetime: DateTime<Utc>,
+	scope: String,
+	signing_hmac: HmacSha256,
+	previous_signature: Hash,
+}
+
+/// Stream of the decoded chunks of an `aws-chunked` payload. Despite its name,
+/// it also decodes payloads whose chunks are not signed.
 #[pin_project::pin_project]
 pub struct SignedPayloadStream<S>
 where
@@ -197,10 +393,10 @@ where
 	#[pin]
 	stream: S,
 	buf: bytes::BytesMut,
-	datetime: DateTime<Utc>,
-	scope: String,
-	signing_hmac: HmacSha256,
-	previous_signature: Hash,
+	signing: Option<PayloadSigning>,
+	trailer: Option<PayloadChecksum>,
+	/// Whether the last chunk was read, and only the trailer remains
+	last_chunk_read: bool,
 }
 
 impl<S> SignedPayloadStream<S>
@@ -217,13 +413,33 @@ where
 		Self {
 			stream,
 			buf: bytes::BytesMut::new(),
-			datetime,
-			scope: scope.into(),
-			signing_hmac,
-			previous_signature: seed_signature,
+			signing: Some(PayloadSigning {
+				datetime,
+				scope: scope.into(),
+				signing_hmac,
+				previous_signature: seed_signature,
+			}),
+			trailer: None,
+			last_chunk_read: false,
 		}
 	}
 
+	pub fn new_unsigned(stream: S, trailer: Option<PayloadChecksum>) -> Self {
+		Self {
+			stream,
+			buf: bytes::BytesMut::new(),
+			signing: None,
+			trailer,
+			last_chunk_read: false,
+		}
+	}
+
+	/// Expect the payload to be followed by a trailer giving its checksum
+	pub fn with_trailer(mut self, trailer: Option<PayloadChecksum>) -> Self {
+		self.trailer = trailer;
+		self
+	}
+
 	fn parse_next(input: &[u8]) -> nom::IResult<&[u8], SignedPayload, SignedPayloadStreamError> {
 		use nom::bytes::streaming::{tag, take};
 
@@ -255,6 +471,91 @@ where
 	}
 }
 
+impl PayloadSigning {
+	/// Check the signature of a chunk, which is chained to that of the previous chunk
+	fn verify_chunk(&mut self, payload: &SignedPayload) -> Result<(), SignedPayloadStreamError> {
+		let signature = payload
+			.header
+			.signature
+			.ok_or_else(|| SignedPayloadStreamError::message("Missing chunk signature"))?;
+
+		let expected_signature = compute_streaming_payload_signature(
+			&self.signing_hmac,
+			self.datetime,
+			&self.scope,
+			self.previous_signature,
+			sha256sum(&payload.data),
+		)
+		.map_err(|e| {
+			SignedPayloadStreamError::Message(format!("Could not build signature: {}", e))
+		})?;
+
+		if signature != expected_signature {
+			return Err(SignedPayloadStreamError::InvalidSignature);
+		}
+
+		self.previous_signature = signature;
+		Ok(())
+	}
+
+	/// Check the signature of the trailer, which is given in its last header
+	fn verify_trailer(
+		&self,
+		trailer: &mut Vec<(String, String)>,
+	) -> Result<(), SignedPayloadStreamError> {
+		let signature = match trailer.pop() {
+			Some((name, value)) if name == "x-amz-trailer-signature" => value,
+			_ => {
+				return Err(SignedPayloadStreamError::message(
+					"Missing trailer signature",
+				))
+			}
+		};
+		let signature = hex::decode(signature)
+			.ok()
+			.and_then(|s| Hash::try_from(&s))
+			.ok_or(SignedPayloadStreamError::InvalidSignature)?;
+
+		let expected_signature = compute_streaming_trailer_signature(
+			&self.signing_hmac,
+			self.datetime,
+			&self.scope,
+			self.previous_signature,
+			trailer,
+		)
+		.map_err(|e| {
+			SignedPayloadStreamError::Message(format!("Could not build signature: {}", e))
+		})?;
+
+		if signature != expected_signature {
+			return Err(SignedPayloadStreamError::InvalidSignature);
+		}
+		Ok(())
+	}
+}
+
+impl PayloadChecksum {
+	fn verify(&self, trailer: &[(String, String)]) -> Result<(), SignedPayloadStreamError> {
+		let expected = trailer
+			.iter()
+			.find(|(name, _)| *name == self.header_name)
+			.map(|(_, value)| value)
+			.ok_or_else(|| {
+				SignedPayloadStreamError::Message(format!(
+					"Missing {} in trailer",
+					self.header_name
+				))
+			})?;
+		if *expected != self.finalize() {
+			return Err(SignedPayloadStreamError::Message(format!(
+				"Invalid {}: the checksum of the data does not match",
+				self.header_name
+			)));
+		}
+		Ok(())
+	}
+}
+
 impl<S> Stream for SignedPayloadStream<S>
 where
 	S: Stream<Item = Result<Bytes, Error>> + Unpin,
@@ -270,7 +571,15 @@ where
 		let mut this = self.project();
 
 		loop {
-			let (input, payload) = match Self::parse_next(this.buf) {
+			let parsed = if *this.last_chunk_read {
+				payload::parse_trailer(this.buf)
+					.map(|(input, trailer)| (input, Err(trailer)))
+					.map_err(nom::Err::convert)
+			} else {
+				Self::parse_next(this.buf).map(|(input, payload)| (input, Ok(payload)))
+			};
+
+			let (input, payload) = match parsed {
 				Ok(res) => res,
 				Err(nom::Err::Incomplete(_)) => {
 					match futures::ready!(this.stream.as_mut().poll_next(cx)) {
@@ -293,30 +602,37 @@ where
 				}
 			};
 
-			// 0-sized chunk is the last
-			if payload.data.is_empty() {
-				return Poll::Ready(None);
-			}
-
-			let data_sha256sum = sha256sum(&payload.data);
-
-			let expected_signature = compute_streaming_payload_signature(
-				this.signing_hmac,
-				*this.datetime,
-				this.scope,
-				*this.previous_signature,
-				data_sha256sum,
-			)
-			.map_err(|e| {
-				SignedPayloadStreamError::Message(format!("Could not build signature: {}", e))
-			})?;
+			let payload = match payload {
+				Ok(payload) => payload,
+				Err(mut trailer) => {
+					if let Some(signing) = this.signing.as_ref() {
+						signing.verify_trailer(&mut trailer)?;
+					}
+					if let Some(checksum) = this.trailer.as_ref() {
+						checksum.verify(&trailer)?;
+					}
+					*this.buf = input.into();
+					return Poll::Ready(None);
+				}
+			};
 
-			if payload.header.signature != expected_signature {
-				return Poll::Ready(Some(Err(SignedPayloadStreamError::InvalidSignature)));
+			if let Some(signing) = this.signing.as_mut() {
+				signing.verify_chunk(&payload)?;
+			}
+			if let Some(checksum) = this.trailer.as_mut() {
+				checksum.update(&payload.data);
 			}
 
 			*this.buf = input.into();
-			*this.previous_signature = payload.header.signature;
+
+			// 0-sized chunk is the last
+			if payload.data.is_empty() {
+				if this.trailer.is_none() {
+					return Poll::Ready(None);
+				}
+				*this.last_chunk_read = true;
+				continue;
+			}
 
 			return Poll::Ready(Some(Ok(payload.data)));
 		}
@@ -365,4 +681,150 @@ mod tests {
 			),
 		}
 	}
+
+	fn crc32_base64(data: &[u8]) -> String {
+		use base64::prelude::*;
+		BASE64_STANDARD.encode(crc32fast::hash(data).to_be_bytes())
+	}
+
+	/// Split the payload in pieces of 3 bytes, to check that chunks and
+	/// trailers split across several reads of the body are parsed correctly
+	fn body_stream(
+		payload: Vec<u8>,
+	) -> impl Stream<Item = Result<hyper::body::Bytes, crate::signature::error::Error>> + Unpin {
+		let pieces = payload
+			.chunks(3)
+			.map(|c| Ok(c.to_vec().into()))
+			.collect::<Vec<_>>();
+		futures::stream::iter(pieces)
+	}
+
+	#[tokio::test]
+	async fn test_unsigned_payload_with_trailer() {
+		use super::PayloadChecksum;
+
+		let payload = |checksum: &str| {
+			format!(
+				"5\r\nhello\r\n6\r\n world\r\n0\r\nx-amz-checksum-crc32:{}\r\n\r\n",
+				checksum
+			)
+			.into_bytes()
+		};
+
+		let trailer = PayloadChecksum::new("x-amz-checksum-crc32").unwrap();
+		let stream = SignedPayloadStream::new_unsigned(
+			body_stream(payload(&crc32_base64(b"hello world"))),
+			Some(trailer),
+		);
+		let data = stream.try_collect::<Vec<_>>().await.unwrap();
+		assert_eq!(data.concat(), b"hello world");
+
+		let trailer = PayloadChecksum::new("x-amz-checksum-crc32").unwrap();
+		let stream = SignedPayloadStream::new_unsigned(
+			body_stream(payload(&crc32_base64(b"hello"))),
+			Some(trailer),
+		);
+		assert!(stream.try_collect::<Vec<_>>().await.is_err());
+
+		assert!(PayloadChecksum::new("x-amz-checksum-md5").is_err());
+	}
+
+	#[tokio::test]
+	async fn test_signed_payload_with_trailer() {
+		use chrono::{DateTime, Utc};
+
+		use garage_util::data::{sha256sum, Hash};
+
+		use super::{
+			compute_streaming_payload_signature, compute_streaming_trailer_signature,
+			PayloadChecksum,
+		};
+
+		let datetime = DateTime::parse_from_rfc3339("2023-11-14T22:13:20Z")
+			.unwrap()
+			.with_timezone(&Utc);
+		let scope = crate::signature::compute_scope(&datetime, "garage", "s3");
+		let signing_hmac =
+			crate::signature::signing_hmac(&datetime, "secret", "garage", "s3").unwrap();
+		let seed_signature = Hash::default();
+
+		let payload = |trailer_signature: Option<&str>| {
+			let mut payload = vec![];
+			let mut signature = seed_signature;
+			for chunk in [&b"hello world"[..], b""] {
+				signature = compute_streaming_payload_signature(
+					&signing_hmac,
+					datetime,
+					&scope,
+					signature,
+					sha256sum(chunk),
+				)
+				.unwrap();
+				payload.extend(
+					format!(
+						"{:x};chunk-signature={}\r\n",
+						chunk.len(),
+						hex::encode(signature)
+					)
+					.as_bytes(),
+				);
+				if !chunk.is_empty() {
+					payload.extend(chunk);
+					payload.extend(b"\r\n");
+				}
+			}
+
+			let trailer = vec![(
+				"x-amz-checksum-crc32".to_string(),
+				crc32_base64(b"hello world"),
+			)];
+			let trailer_signature = match trailer_signature {
+				Some(s) => s.to_string(),
+				None => hex::encode(
+					compute_streaming_trailer_signature(
+						&signing_hmac,
+						datetime,
+						&scope,
+						signature,
+						&trailer,
+					)
+					.unwrap(),
+				),
+			};
+			payload.extend(
+				format!(
+					"{}:{}\r\nx-amz-trailer-signature:{}\r\n\r\n",
+					trailer[0].0, trailer[0].1, trailer_signature
+				)
+				.as_bytes(),
+			);
+			payload
+		};
+
+		let stream = |payload| {
+			SignedPayloadStream::new(
+				body_stream(payload),
+				signing_hmac.clone(),
+				datetime,
+				&scope,
+				seed_signature,
+			)
+			.with_trailer(Some(PayloadChecksum::new("x-amz-checksum-crc32").unwrap()))
+		};
+
+		let data = stream(payload(None)).try_collect::<Vec<_>>().await.unwrap();
+		assert_eq!(data.concat(), b"hello world");
+
+		let invalid = hex::encode(Hash::default());
+		match stream(payload(Some(&invalid)))
+			.try_collect::<Vec<_>>()
+			.await
+		{
+			Err(SignedPayloadStreamError::InvalidSignature) => {}
+			item => panic!(
+				"Unexpected result, expected invalid signature, got {:?}",
+				item
+			),
+		}
+	}
 }
@@ -5,10 +5,27 @@ use zstd::stream::{decode_all as zstd_decode, Encoder};
 use garage_util::data::*;
 use garage_util::error::*;
 
-#[derive(Debug, Serialize, Deserialize, Copy, Clone)]
+use crate::encryption::BlockEncryption;
+use crate::erasure::*;
+
+/// Magic bytes at the beginning of block files that have a format header.
+/// The header is followed by one byte indicating the format version.
+pub(crate) const BLOCK_FILE_MAGIC: &[u8; 4] = b"GRGB";
+
+/// Format version of block files written without a header (Garage <= v0.8)
+pub(crate) const BLOCK_FORMAT_LEGACY: u8 = 0;
+/// Format version of block files containing the raw (possibly compressed) block
+/// after the header
+pub(crate) const BLOCK_FORMAT_V1: u8 = 1;
+/// Format version of block files containing the encrypted (possibly compressed)
+/// block after the header, see [`crate::encryption`]
+pub(crate) const BLOCK_FORMAT_V2: u8 = 2;
+
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
 pub enum DataBlockHeader {
 	Plain,
 	Compressed,
+	Shard,
 }
 
 /// A possibly compressed block of data
@@ -17,6 +34,8 @@ pub enum DataBlock {
 	Plain(Bytes),
 	/// Data compressed with zstd
 	Compressed(Bytes),
+	/// One of the erasure-coded shards of a block, see [`crate::erasure`]
+	Shard(Bytes),
 }
 
 impl DataBlock {
@@ -29,13 +48,18 @@ impl DataBlock {
 	/// instead
 	pub fn inner_buffer(&self) -> &[u8] {
 		use DataBlock::*;
-		let (Plain(ref res) | Compressed(ref res)) = self;
+		let (Plain(ref res) | Compressed(ref res) | Shard(ref res)) = self;
 		res
 	}
 
+	/// Query whether this is an erasure-coded shard instead of a whole block
+	pub fn is_shard(&self) -> bool {
+		matches!(self, DataBlock::Shard(_))
+	}
+
 	/// Get the buffer, possibly decompressing it, and verify it's integrity.
 	/// For Plain block, data is compared to hash, for Compressed block, zstd checksumming system
-	/// is used instead.
+	/// is used instead. Shards cannot be read on their own.
 	pub fn verify_get(self, hash: Hash) -> Result<Bytes, Error> {
 		match self {
 			DataBlock::Plain(data) => {
@@ -48,6 +72,10 @@ impl DataBlock {
 			DataBlock::Compressed(data) => zstd_decode(&data[..])
 				.map_err(|_| Error::CorruptData(hash))
 				.map(Bytes::from),
+			DataBlock::Shard(_) => Err(Error::Message(format!(
+				"Block {:?} is an erasure-coded shard and cannot be read on its own",
+				hash
+			))),
 		}
 	}
 
@@ -64,6 +92,7 @@ impl DataBlock {
 			}
 			DataBlock::Compressed(data) => zstd::stream::copy_decode(&data[..], std::io::sink())
 				.map_err(|_| Error::CorruptData(hash)),
+			DataBlock::Shard(data) => verify_shard(data, &hash).map(|_| ()),
 		}
 	}
 
@@ -84,6 +113,7 @@ impl DataBlock {
 		match self {
 			DataBlock::Plain(data) => (DataBlockHeader::Plain, data),
 			DataBlock::Compressed(data) => (DataBlockHeader::Compressed, data),
+			DataBlock::Shard(data) => (DataBlockHeader::Shard, data),
 		}
 	}
 
@@ -91,6 +121,123 @@ impl DataBlock {
 		match h {
 			DataBlockHeader::Plain => DataBlock::Plain(bytes),
 			DataBlockHeader::Compressed => DataBlock::Compressed(bytes),
+			DataBlockHeader::Shard => DataBlock::Shard(bytes),
+		}
+	}
+
+	/// Get the header to write at the beginning of a block file in the given format version
+	pub(crate) fn file_header(version: u8) -> Vec<u8> {
+		match version {
+			BLOCK_FORMAT_LEGACY => vec![],
+			v => {
+				let mut header = BLOCK_FILE_MAGIC.to_vec();
+				header.push(v);
+				header
+			}
+		}
+	}
+
+	/// Get the size of the data contained in a block file, or `None` if the file
+	/// cannot be decrypted or decompressed (e.g. because it is truncated). As in
+	/// [`DataBlock::from_file`], a file that starts with a format header might also
+	/// be a legacy block: the interpretation that gives the `expected` size is preferred.
+	/// A valid erasure-coded shard is considered to have the `expected` size.
+	pub(crate) fn file_data_len(
+		data: &[u8],
+		compressed: bool,
+		hash: &Hash,
+		expected: u64,
+		encryption: Option<&BlockEncryption>,
+	) -> Option<u64> {
+		let data_len = |bytes: &[u8]| {
+			if compressed {
+				zstd_decode(bytes).ok().map(|d| d.len() as u64)
+			} else if ShardInfo::parse(bytes, hash).is_some() {
+				verify_shard(bytes, hash).ok().map(|_| expected)
+			} else {
+				Some(bytes.len() as u64)
+			}
+		};
+
+		match data.get(..BLOCK_FILE_MAGIC.len() + 1) {
+			Some(header)
+				if header.starts_with(BLOCK_FILE_MAGIC)
+					&& [BLOCK_FORMAT_V1, BLOCK_FORMAT_V2]
+						.contains(&header[BLOCK_FILE_MAGIC.len()]) =>
+			{
+				let content = &data[header.len()..];
+				let len = if header[BLOCK_FILE_MAGIC.len()] == BLOCK_FORMAT_V2 {
+					encryption
+						.and_then(|e| e.decrypt(hash, content).ok().flatten())
+						.and_then(|plain| data_len(&plain))
+				} else {
+					data_len(content)
+				};
+				if len == Some(expected) {
+					return len;
+				}
+				data_len(data).filter(|l| *l == expected).or(len)
+			}
+			_ => data_len(data),
+		}
+	}
+
+	/// Decode the content of a block file and verify its integrity.
+	/// Files that start with a format header are decoded according to their
+	/// version, other files are read as legacy blocks without a header.
+	/// Encrypted block files can only be decoded if `encryption` is given.
+	pub(crate) fn from_file(
+		data: Bytes,
+		compressed: bool,
+		hash: Hash,
+		encryption: Option<&BlockEncryption>,
+	) -> Result<Self, Error> {
+		let make_block = |bytes: Bytes| {
+			if compressed {
+				DataBlock::Compressed(bytes)
+			} else if ShardInfo::parse(&bytes, &hash).is_some() {
+				DataBlock::Shard(bytes)
+			} else {
+				DataBlock::Plain(bytes)
+			}
+		};
+
+		match data.get(..BLOCK_FILE_MAGIC.len() + 1) {
+			Some(header) if header.starts_with(BLOCK_FILE_MAGIC) => {
+				let content = data.slice(header.len()..);
+				let block = match header[BLOCK_FILE_MAGIC.len()] {
+					BLOCK_FORMAT_V1 => Ok(make_block(content)),
+					BLOCK_FORMAT_V2 => match encryption {
+						Some(encryption) => encryption
+							.decrypt(&hash, &content)?
+							.map(|plain| make_block(plain.into()))
+							.ok_or(Error::CorruptData(hash)),
+						None => Err(Error::Message(format!(
+							"Block {:?} is encrypted, but no encryption_key is configured",
+							hash
+						))),
+					},
+					v => Err(Error::Message(format!(
+						"Block {:?} has unsupported format version {}",
+						hash, v
+					))),
+				};
+				match block {
+					Ok(block) if block.verify(hash).is_ok() => return Ok(block),
+					Err(Error::CorruptData(_)) | Ok(_) => (),
+					Err(e) => return Err(e),
+				}
+				// The content of a legacy block might start with the magic bytes
+				// by chance, check this before declaring the block as corrupted
+				let legacy = make_block(data);
+				legacy.verify(hash)?;
+				Ok(legacy)
+			}
+			_ => {
+				let block = make_block(data);
+				block.verify(hash)?;
+				Ok(block)
+			}
 		}
 	}
 }
@@ -103,3 +250,122 @@ fn zstd_encode<R: std::io::Read>(mut source: R, level: i32) -> std::io::Result<V
 	encoder.finish()?;
 	Ok(result)
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn block_file(version: u8, content: &[u8]) -> Bytes {
+		let mut file = DataBlock::file_header(version);
+		file.extend_from_slice(content);
+		file.into()
+	}
+
+	#[test]
+	fn test_block_file_versions() {
+		let content = b"hello, world";
+		let hash = blake2sum(content);
+
+		for version in [BLOCK_FORMAT_LEGACY, BLOCK_FORMAT_V1] {
+			let block =
+				DataBlock::from_file(block_file(version, content), false, hash, None).unwrap();
+			assert_eq!(block.inner_buffer(), content);
+		}
+
+		assert!(matches!(
+			DataBlock::from_file(block_file(3, content), false, hash, None),
+			Err(Error::Message(_))
+		));
+		assert!(matches!(
+			DataBlock::from_file(block_file(BLOCK_FORMAT_V1, b"corrupted"), false, hash, None),
+			Err(Error::CorruptData(_))
+		));
+	}
+
+	#[test]
+	fn test_block_header_roundtrip() {
+		let content = Bytes::from(b"hello, world".repeat(100));
+		let hash = blake2sum(&content);
+
+		// The header sent with a block tells whether it is compressed,
+		// independently of the compression settings of the receiving node
+		let compressed = DataBlock::Compressed(zstd_encode(&content[..], 19).unwrap().into());
+		for block in [compressed, DataBlock::Plain(content.clone())] {
+			let is_compressed = block.is_compressed();
+			let (header, bytes) = block.into_parts();
+			let block = DataBlock::from_parts(header, bytes);
+			assert_eq!(block.is_compressed(), is_compressed);
+			assert_eq!(block.verify_get(hash).unwrap(), content);
+		}
+	}
+
+	#[test]
+	fn test_legacy_block_starting_with_magic() {
+		let content = block_file(BLOCK_FORMAT_V1, b"not a header");
+		let hash = blake2sum(&content);
+
+		let block = DataBlock::from_file(content.clone(), false, hash, None).unwrap();
+		assert_eq!(block.inner_buffer(), &content[..]);
+	}
+
+	#[test]
+	fn test_shard_block_file() {
+		let content = Bytes::from(b"hello, world".repeat(100));
+		let hash = blake2sum(&content);
+		let shards = ErasureCode::new(2, 1)
+			.unwrap()
+			.encode(&hash, &DataBlock::Plain(content.clone()))
+			.unwrap();
+
+		for version in [BLOCK_FORMAT_LEGACY, BLOCK_FORMAT_V1] {
+			let file = block_file(version, &shards[2]);
+			let block = DataBlock::from_file(file.clone(), false, hash, None).unwrap();
+			assert!(block.is_shard());
+			assert_eq!(block.inner_buffer(), &shards[2][..]);
+			assert_eq!(
+				DataBlock::file_data_len(&file, false, &hash, 1200, None),
+				Some(1200)
+			);
+		}
+
+		let mut corrupted = shards[0].to_vec();
+		*corrupted.last_mut().unwrap() ^= 1;
+		assert!(matches!(
+			DataBlock::from_file(corrupted.into(), false, hash, None),
+			Err(Error::CorruptData(_))
+		));
+	}
+
+	#[test]
+	fn test_encrypted_block_file() {
+		let encryption = BlockEncryption::new(
+			"0101010101010101010101010101010101010101010101010101010101010101",
+			&[],
+		)
+		.unwrap();
+		let content = b"hello, world";
+		let hash = blake2sum(content);
+		let file = block_file(
+			BLOCK_FORMAT_V2,
+			&encryption.encrypt(&hash, content).unwrap(),
+		);
+
+		let block = DataBlock::from_file(file.clone(), false, hash, Some(&encryption)).unwrap();
+		assert_eq!(block.inner_buffer(), content);
+		assert_eq!(
+			DataBlock::file_data_len(&file, false, &hash, 12, Some(&encryption)),
+			Some(12)
+		);
+
+		assert!(matches!(
+			DataBlock::from_file(file.clone(), false, hash, None),
+			Err(Error::Message(_))
+		));
+		let mut corrupted = file.to_vec();
+		*corrupted.last_mut().unwrap() ^= 1;
+		assert!(matches!(
+			DataBlock::from_file(corrupted.into(), false, hash, Some(&encryption)),
+			Err(Error::CorruptData(_))
+		));
+	}
+}
@@ -0,0 +1,249 @@
+//! Encryption of block files at rest, using AES-256-GCM.
+//!
+//! The content of each block file is encrypted with a random data key
+//! that is specific to this block file. The data key is stored in the block
+//! file, wrapped (i.e. encrypted) with the master key of the node, which is
+//! set by `encryption_key` in the configuration. Changing the master key thus
+//! only requires re-wrapping the data keys, which is done by the
+//! `garage repair rekey-blocks` operation.
+//!
+//! Encrypted block files have the following content after their format header:
+//! `key id (8) | wrap nonce (12) | wrapped data key (32 + 16) | data nonce (12) | encrypted data + tag (16)`
+
+use std::convert::TryInto;
+
+use rand::prelude::*;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+
+use garage_util::data::*;
+use garage_util::error::*;
+
+const KEY_LEN: usize = 32;
+const KEY_ID_LEN: usize = 8;
+const TAG_LEN: usize = 16;
+
+const WRAPPED_KEY_LEN: usize = NONCE_LEN + KEY_LEN + TAG_LEN;
+/// Length of the encryption header, that comes before the encrypted data
+const HEADER_LEN: usize = KEY_ID_LEN + WRAPPED_KEY_LEN + NONCE_LEN;
+
+struct MasterKey {
+	id: [u8; KEY_ID_LEN],
+	key: LessSafeKey,
+}
+
+/// The master keys used to encrypt block files on this node
+pub struct BlockEncryption {
+	current: MasterKey,
+	previous: Vec<MasterKey>,
+}
+
+impl BlockEncryption {
+	/// Create the block encryption from the hex-encoded keys of the configuration:
+	/// new block files are encrypted with `key`, `previous_keys` are only used
+	/// to read block files that have not yet been re-keyed
+	pub fn new(key: &str, previous_keys: &[String]) -> Result<Self, Error> {
+		Ok(Self {
+			current: MasterKey::parse(key)?,
+			previous: previous_keys
+				.iter()
+				.map(|k| MasterKey::parse(k))
+				.collect::<Result<Vec<_>, _>>()?,
+		})
+	}
+
+	/// Encrypt the (possibly compressed) content of a block with a new data key
+	pub(crate) fn encrypt(&self, hash: &Hash, data: &[u8]) -> Result<Vec<u8>, Error> {
+		let data_key = thread_rng().gen::<[u8; KEY_LEN]>();
+		let data_nonce = thread_rng().gen::<[u8; NONCE_LEN]>();
+
+		let mut ret = Vec::with_capacity(HEADER_LEN + data.len() + TAG_LEN);
+		ret.extend_from_slice(&self.current.id);
+		ret.extend(self.current.wrap(&data_key)?);
+		ret.extend_from_slice(&data_nonce);
+
+		let mut encrypted = data.to_vec();
+		aead_key(&data_key)
+			.seal_in_place_append_tag(
+				Nonce::assume_unique_for_key(data_nonce),
+				Aad::from(hash.as_slice()),
+				&mut encrypted,
+			)
+			.ok_or_message("Block encryption failed")?;
+		ret.extend(encrypted);
+
+		Ok(ret)
+	}
+
+	/// Decrypt the content of a block file. Returns `None` if the data cannot be
+	/// decrypted because it is corrupted, and an error if it was encrypted
+	/// with an unknown key.
+	pub(crate) fn decrypt(&self, hash: &Hash, data: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+		if data.len() < HEADER_LEN + TAG_LEN {
+			return Ok(None);
+		}
+		let data_key = match self
+			.master_key(&data[..KEY_ID_LEN])?
+			.unwrap(&data[KEY_ID_LEN..KEY_ID_LEN + WRAPPED_KEY_LEN])
+		{
+			Some(k) => k,
+			None => return Ok(None),
+		};
+
+		let data_nonce = &data[KEY_ID_LEN + WRAPPED_KEY_LEN..HEADER_LEN];
+		let mut decrypted = data[HEADER_LEN..].to_vec();
+		let len = match aead_key(&data_key).open_in_place(
+			Nonce::try_assume_unique_for_key(data_nonce).unwrap(),
+			Aad::from(hash.as_slice()),
+			&mut decrypted,
+		) {
+			Ok(plain) => plain.len(),
+			Err(_) => return Ok(None),
+		};
+		decrypted.truncate(len);
+
+		Ok(Some(decrypted))
+	}
+
+	/// Re-wrap the data key of an encrypted block file with the current master key.
+	/// Returns `None` if the data key is already wrapped with the current master key.
+	pub(crate) fn rewrap(&self, data: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+		if data.len() < HEADER_LEN + TAG_LEN {
+			return Err(Error::Message("Encrypted block file is too short".into()));
+		}
+		if data[..KEY_ID_LEN] == self.current.id {
+			return Ok(None);
+		}
+		let data_key = self
+			.master_key(&data[..KEY_ID_LEN])?
+			.unwrap(&data[KEY_ID_LEN..KEY_ID_LEN + WRAPPED_KEY_LEN])
+			.ok_or_message("Could not unwrap the data key of the block file")?;
+
+		let mut ret = Vec::with_capacity(data.len());
+		ret.extend_from_slice(&self.current.id);
+		ret.extend(self.current.wrap(&data_key)?);
+		ret.extend_from_slice(&data[KEY_ID_LEN + WRAPPED_KEY_LEN..]);
+		Ok(Some(ret))
+	}
+
+	fn master_key(&self, id: &[u8]) -> Result<&MasterKey, Error> {
+		std::iter::once(&self.current)
+			.chain(self.previous.iter())
+			.find(|k| k.id[..] == *id)
+			.ok_or_else(|| {
+				Error::Message(format!(
+					"Block file is encrypted with unknown key {} (is it in previous_encryption_keys?)",
+					hex::encode(id)
+				))
+			})
+	}
+}
+
+impl MasterKey {
+	fn parse(key_hex: &str) -> Result<Self, Error> {
+		let key = hex::decode(key_hex)
+			.ok()
+			.filter(|k| k.len() == KEY_LEN)
+			.ok_or_message("Invalid encryption key, expected 32 hex-encoded bytes")?;
+		let mut id = [0u8; KEY_ID_LEN];
+		id.copy_from_slice(&blake2sum(&key).as_slice()[..KEY_ID_LEN]);
+		Ok(Self {
+			id,
+			key: aead_key(&key),
+		})
+	}
+
+	fn wrap(&self, data_key: &[u8; KEY_LEN]) -> Result<Vec<u8>, Error> {
+		let nonce = thread_rng().gen::<[u8; NONCE_LEN]>();
+		let mut wrapped = data_key.to_vec();
+		self.key
+			.seal_in_place_append_tag(
+				Nonce::assume_unique_for_key(nonce),
+				Aad::from(&self.id[..]),
+				&mut wrapped,
+			)
+			.ok_or_message("Data key encryption failed")?;
+
+		let mut ret = nonce.to_vec();
+		ret.extend(wrapped);
+		Ok(ret)
+	}
+
+	fn unwrap(&self, wrapped: &[u8]) -> Option<[u8; KEY_LEN]> {
+		let nonce = Nonce::try_assume_unique_for_key(&wrapped[..NONCE_LEN]).ok()?;
+		let mut data_key = wrapped[NONCE_LEN..].to_vec();
+		let plain = self
+			.key
+			.open_in_place(nonce, Aad::from(&self.id[..]), &mut data_key)
+			.ok()?;
+		plain.try_into().ok()
+	}
+}
+
+fn aead_key(key: &[u8]) -> LessSafeKey {
+	LessSafeKey::new(UnboundKey::new(&AES_256_GCM, key).expect("Invalid AES-256 key length"))
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	const KEY1: &str = "0101010101010101010101010101010101010101010101010101010101010101";
+	const KEY2: &str = "0202020202020202020202020202020202020202020202020202020202020202";
+
+	#[test]
+	fn test_encrypt_decrypt() {
+		let enc = BlockEncryption::new(KEY1, &[]).unwrap();
+		let data = b"hello, world";
+		let hash = blake2sum(data);
+
+		let encrypted = enc.encrypt(&hash, data).unwrap();
+		assert_eq!(encrypted.len(), HEADER_LEN + data.len() + TAG_LEN);
+		assert_eq!(enc.decrypt(&hash, &encrypted).unwrap().unwrap(), data);
+
+		// Corrupted, truncated and misplaced data cannot be decrypted
+		let mut corrupted = encrypted.clone();
+		*corrupted.last_mut().unwrap() ^= 1;
+		assert!(enc.decrypt(&hash, &corrupted).unwrap().is_none());
+		assert!(enc
+			.decrypt(&hash, &encrypted[..encrypted.len() / 2])
+			.unwrap()
+			.is_none());
+		assert!(enc
+			.decrypt(&blake2sum(b"other"), &encrypted)
+			.unwrap()
+			.is_none());
+
+		// Data encrypted with an unknown key is an error
+		let enc2 = BlockEncryption::new(KEY2, &[]).unwrap();
+		assert!(enc2.decrypt(&hash, &encrypted).is_err());
+	}
+
+	#[test]
+	fn test_rewrap() {
+		let old = BlockEncryption::new(KEY1, &[]).unwrap();
+		let data = b"hello, world";
+		let hash = blake2sum(data);
+		let encrypted = old.encrypt(&hash, data).unwrap();
+
+		assert!(BlockEncryption::new(KEY2, &[])
+			.unwrap()
+			.rewrap(&encrypted)
+			.is_err());
+
+		let new = BlockEncryption::new(KEY2, &[KEY1.to_string()]).unwrap();
+		assert_eq!(new.decrypt(&hash, &encrypted).unwrap().unwrap(), data);
+		let rewrapped = new.rewrap(&encrypted).unwrap().unwrap();
+		assert!(new.rewrap(&rewrapped).unwrap().is_none());
+
+		let new_only = BlockEncryption::new(KEY2, &[]).unwrap();
+		assert_eq!(new_only.decrypt(&hash, &rewrapped).unwrap().unwrap(), data);
+		assert!(old.decrypt(&hash, &rewrapped).is_err());
+	}
+
+	#[test]
+	fn test_invalid_keys() {
+		assert!(BlockEncryption::new("not hex", &[]).is_err());
+		assert!(BlockEncryption::new("0101", &[]).is_err());
+		assert!(BlockEncryption::new(KEY1, &["0101".to_string()]).is_err());
+	}
+}
@@ -0,0 +1,397 @@
+//! Erasure coding of data blocks, used when `replication_mode` is `ec:K+M`.
+//!
+//! A block is split into K data shards, to which M parity shards are added
+//! using a systematic Reed-Solomon code over GF(2^8) whose parity rows form
+//! a Cauchy matrix, so that the block can be rebuilt from any K of its shards.
+//! Each shard is stored by a different node, in a container that identifies
+//! the block it belongs to and that includes a checksum of the shard data.
+
+use std::convert::TryInto;
+
+use bytes::Bytes;
+
+use garage_util::data::*;
+use garage_util::error::*;
+
+use crate::block::*;
+
+/// Magic bytes at the beginning of a shard container
+pub(crate) const SHARD_MAGIC: &[u8; 4] = b"GRGS";
+
+/// Size of the header of a shard container: magic bytes, index of the shard,
+/// number of data and parity shards, header and length of the block,
+/// hash of the block and checksum of the shard data
+const SHARD_HEADER_LEN: usize = 4 + 3 + 1 + 8 + 32 + 32;
+
+/// Description of a shard, as read from the header of its container
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ShardInfo {
+	/// Position of the shard, the first `data_shards` ones contain the block itself
+	pub(crate) index: usize,
+	pub(crate) data_shards: usize,
+	pub(crate) parity_shards: usize,
+	/// Whether the block that was split is compressed
+	pub(crate) block_header: DataBlockHeader,
+	/// Length of the (possibly compressed) block that was split
+	pub(crate) block_len: usize,
+}
+
+impl ShardInfo {
+	/// Read the header of a shard container, returning `None` if the buffer
+	/// is not a well-formed shard of the block with the given hash.
+	/// The checksum of the shard data is not verified, see [`verify_shard`].
+	pub(crate) fn parse(buf: &[u8], hash: &Hash) -> Option<Self> {
+		if buf.len() < SHARD_HEADER_LEN || !buf.starts_with(SHARD_MAGIC) {
+			return None;
+		}
+		let index = buf[4] as usize;
+		let data_shards = buf[5] as usize;
+		let parity_shards = buf[6] as usize;
+		let block_header = match buf[7] {
+			0 => DataBlockHeader::Plain,
+			1 => DataBlockHeader::Compressed,
+			_ => return None,
+		};
+		let block_len = u64::from_be_bytes(buf[8..16].try_into().unwrap()) as usize;
+		if &buf[16..48] != hash.as_slice()
+			|| data_shards == 0
+			|| index >= data_shards + parity_shards
+			|| buf.len() - SHARD_HEADER_LEN != shard_len(block_len, data_shards)
+		{
+			return None;
+		}
+		Some(Self {
+			index,
+			data_shards,
+			parity_shards,
+			block_header,
+			block_len,
+		})
+	}
+}
+
+/// Check that a buffer is a shard container of the block with the given hash,
+/// and that its data matches its checksum
+pub(crate) fn verify_shard(buf: &[u8], hash: &Hash) -> Result<ShardInfo, Error> {
+	let info = ShardInfo::parse(buf, hash).ok_or(Error::CorruptData(*hash))?;
+	if blake2sum(&buf[SHARD_HEADER_LEN..]).as_slice() != &buf[48..80] {
+		return Err(Error::CorruptData(*hash));
+	}
+	Ok(info)
+}
+
+/// Length of each shard of a block of `block_len` bytes split in `data_shards` parts
+#[allow(clippy::manual_div_ceil)]
+fn shard_len(block_len: usize, data_shards: usize) -> usize {
+	// usize::div_ceil is not available with the Rust version used to build Garage
+	(block_len + data_shards - 1) / data_shards
+}
+
+/// A Reed-Solomon code with a given number of data and parity shards
+#[derive(Clone)]
+pub(crate) struct ErasureCode {
+	data_shards: usize,
+	parity_shards: usize,
+	gf: Gf256,
+}
+
+impl ErasureCode {
+	pub(crate) fn new(data_shards: usize, parity_shards: usize) -> Result<Self, Error> {
+		if data_shards == 0 || data_shards + parity_shards > 255 {
+			return Err(Error::Message(format!(
+				"Invalid erasure code with {} data shards and {} parity shards",
+				data_shards, parity_shards
+			)));
+		}
+		Ok(Self {
+			data_shards,
+			parity_shards,
+			gf: Gf256::new(),
+		})
+	}
+
+	/// Number of shards needed to rebuild a block
+	pub(crate) fn data_shards(&self) -> usize {
+		self.data_shards
+	}
+
+	/// Split a block in data and parity shards, returned in order of their index
+	pub(crate) fn encode(&self, hash: &Hash, block: &DataBlock) -> Result<Vec<Bytes>, Error> {
+		let (block_header, buf) = match block {
+			DataBlock::Plain(buf) => (0u8, buf),
+			DataBlock::Compressed(buf) => (1u8, buf),
+			DataBlock::Shard(_) => {
+				return Err(Error::Message(format!(
+					"Block {:?} is already an erasure-coded shard",
+					hash
+				)))
+			}
+		};
+		let len = shard_len(buf.len(), self.data_shards);
+
+		let mut shards = (0..self.data_shards)
+			.map(|i| {
+				let start = std::cmp::min(i * len, buf.len());
+				let end = std::cmp::min((i + 1) * len, buf.len());
+				let mut shard = buf[start..end].to_vec();
+				shard.resize(len, 0);
+				shard
+			})
+			.collect::<Vec<_>>();
+		for i in 0..self.parity_shards {
+			let mut parity = vec![0u8; len];
+			for (j, data) in shards[..self.data_shards].iter().enumerate() {
+				let mul = self.gf.mul_table(self.coefficient(self.data_shards + i, j));
+				for (p, d) in parity.iter_mut().zip(data.iter()) {
+					*p ^= mul[*d as usize];
+				}
+			}
+			shards.push(parity);
+		}
+
+		Ok(shards
+			.into_iter()
+			.enumerate()
+			.map(|(index, data)| {
+				let mut container = Vec::with_capacity(SHARD_HEADER_LEN + data.len());
+				container.extend_from_slice(SHARD_MAGIC);
+				container.extend_from_slice(&[
+					index as u8,
+					self.data_shards as u8,
+					self.parity_shards as u8,
+					block_header,
+				]);
+				container.extend_from_slice(&(buf.len() as u64).to_be_bytes());
+				container.extend_from_slice(hash.as_slice());
+				container.extend_from_slice(blake2sum(&data).as_slice());
+				container.extend_from_slice(&data);
+				Bytes::from(container)
+			})
+			.collect())
+	}
+
+	/// Rebuild a block from verified shard containers, of which at least
+	/// `data_shards` must have distinct indices
+	pub(crate) fn decode(&self, hash: &Hash, shards: &[Bytes]) -> Result<DataBlock, Error> {
+		let mut chosen: Vec<(ShardInfo, &[u8])> = vec![];
+		for shard in shards.iter() {
+			let info = ShardInfo::parse(shard, hash).ok_or(Error::CorruptData(*hash))?;
+			if info.data_shards != self.data_shards || info.parity_shards != self.parity_shards {
+				return Err(Error::Message(format!(
+					"Shard of block {:?} was encoded with {}+{} shards instead of {}+{}",
+					hash,
+					info.data_shards,
+					info.parity_shards,
+					self.data_shards,
+					self.parity_shards
+				)));
+			}
+			if let Some((first, _)) = chosen.first() {
+				if (info.block_header, info.block_len) != (first.block_header, first.block_len) {
+					return Err(Error::CorruptData(*hash));
+				}
+			}
+			if chosen.len() < self.data_shards && !chosen.iter().any(|(i, _)| i.index == info.index)
+			{
+				chosen.push((info, &shard[SHARD_HEADER_LEN..]));
+			}
+		}
+		if chosen.len() < self.data_shards {
+			return Err(Error::Message(format!(
+				"Block {:?} needs {} shards to be rebuilt, only {} are available",
+				hash,
+				self.data_shards,
+				chosen.len()
+			)));
+		}
+		chosen.sort_by_key(|(info, _)| info.index);
+		let (block_header, block_len) = (chosen[0].0.block_header, chosen[0].0.block_len);
+
+		let mut block = Vec::with_capacity(block_len);
+		if chosen.iter().all(|(info, _)| info.index < self.data_shards) {
+			// All data shards are available, no need to decode anything
+			for (_, data) in chosen.iter() {
+				block.extend_from_slice(data);
+			}
+		} else {
+			let matrix = chosen
+				.iter()
+				.map(|(info, _)| {
+					(0..self.data_shards)
+						.map(|j| self.coefficient(info.index, j))
+						.collect()
+				})
+				.collect();
+			let inverse = self
+				.gf
+				.invert(matrix)
+				.ok_or_message("Erasure code matrix is not invertible")?;
+			for row in inverse.iter() {
+				let mut data = vec![0u8; chosen[0].1.len()];
+				for (coef, (_, shard)) in row.iter().zip(chosen.iter()) {
+					let mul = self.gf.mul_table(*coef);
+					for (d, s) in data.iter_mut().zip(shard.iter()) {
+						*d ^= mul[*s as usize];
+					}
+				}
+				block.extend(data);
+			}
+		}
+		block.truncate(block_len);
+
+		Ok(DataBlock::from_parts(block_header, block.into()))
+	}
+
+	/// Coefficient of the `j`-th data shard in the shard with the given index:
+	/// data shards are copied as is, parity shards use a Cauchy matrix
+	fn coefficient(&self, index: usize, j: usize) -> u8 {
+		if index < self.data_shards {
+			(index == j) as u8
+		} else {
+			self.gf.inv(index as u8 ^ j as u8)
+		}
+	}
+}
+
+/// Arithmetic in GF(2^8) with the polynomial x^8 + x^4 + x^3 + x^2 + 1
+#[derive(Clone)]
+struct Gf256 {
+	exp: [u8; 510],
+	log: [u8; 256],
+}
+
+impl Gf256 {
+	fn new() -> Self {
+		let mut exp = [0u8; 510];
+		let mut log = [0u8; 256];
+		let mut x: u16 = 1;
+		for (i, e) in exp.iter_mut().take(255).enumerate() {
+			*e = x as u8;
+			log[x as usize] = i as u8;
+			x <<= 1;
+			if x & 0x100 != 0 {
+				x ^= 0x11d;
+			}
+		}
+		// Powers are repeated so that products can be looked up without a modulo
+		exp.copy_within(0..255, 255);
+		Self { exp, log }
+	}
+
+	fn mul(&self, a: u8, b: u8) -> u8 {
+		if a == 0 || b == 0 {
+			0
+		} else {
+			self.exp[self.log[a as usize] as usize + self.log[b as usize] as usize]
+		}
+	}
+
+	fn inv(&self, a: u8) -> u8 {
+		assert!(a != 0);
+		self.exp[255 - self.log[a as usize] as usize]
+	}
+
+	/// Products of all bytes with `a`
+	fn mul_table(&self, a: u8) -> [u8; 256] {
+		let mut table = [0u8; 256];
+		for (b, t) in table.iter_mut().enumerate() {
+			*t = self.mul(a, b as u8);
+		}
+		table
+	}
+
+	/// Invert a square matrix by Gauss-Jordan elimination
+	fn invert(&self, mut m: Vec<Vec<u8>>) -> Option<Vec<Vec<u8>>> {
+		let n = m.len();
+		let mut inv = (0..n)
+			.map(|i| (0..n).map(|j| (i == j) as u8).collect::<Vec<_>>())
+			.collect::<Vec<_>>();
+		for col in 0..n {
+			let pivot = (col..n).find(|r| m[*r][col] != 0)?;
+			m.swap(col, pivot);
+			inv.swap(col, pivot);
+
+			let scale = self.inv(m[col][col]);
+			for j in 0..n {
+				m[col][j] = self.mul(m[col][j], scale);
+				inv[col][j] = self.mul(inv[col][j], scale);
+			}
+			for row in 0..n {
+				let factor = m[row][col];
+				if row != col && factor != 0 {
+					for j in 0..n {
+						m[row][j] ^= self.mul(factor, m[col][j]);
+						inv[row][j] ^= self.mul(factor, inv[col][j]);
+					}
+				}
+			}
+		}
+		Some(inv)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn subsets(n: usize, k: usize) -> Vec<Vec<usize>> {
+		(0u32..(1 << n))
+			.filter(|s| s.count_ones() as usize == k)
+			.map(|s| (0..n).filter(|i| s & (1 << i) != 0).collect())
+			.collect()
+	}
+
+	#[test]
+	fn test_erasure_code_roundtrip() {
+		for (k, m) in [(1, 1), (2, 1), (2, 2), (3, 3), (4, 2)] {
+			let code = ErasureCode::new(k, m).unwrap();
+			for len in [0, 1, 7, 1000, 4099] {
+				let content =
+					Bytes::from((0..len).map(|i| (i * 7 + i / 13) as u8).collect::<Vec<_>>());
+				let hash = blake2sum(&content);
+				let shards = code
+					.encode(&hash, &DataBlock::Plain(content.clone()))
+					.unwrap();
+				assert_eq!(shards.len(), k + m);
+
+				for (i, shard) in shards.iter().enumerate() {
+					let info = verify_shard(shard, &hash).unwrap();
+					assert_eq!(info.index, i);
+					assert_eq!(info.block_len, len);
+				}
+
+				for subset in subsets(k + m, k) {
+					let available = subset
+						.iter()
+						.map(|i| shards[*i].clone())
+						.collect::<Vec<_>>();
+					let block = code.decode(&hash, &available).unwrap();
+					assert!(!block.is_compressed());
+					assert_eq!(block.verify_get(hash).unwrap(), content);
+				}
+
+				let missing = shards[..k - 1].to_vec();
+				assert!(code.decode(&hash, &missing).is_err());
+			}
+		}
+	}
+
+	#[test]
+	fn test_corrupted_shard() {
+		let code = ErasureCode::new(2, 1).unwrap();
+		let content = Bytes::from(b"hello, world".repeat(10));
+		let hash = blake2sum(&content);
+		let shards = code
+			.encode(&hash, &DataBlock::Plain(content.clone()))
+			.unwrap();
+
+		let mut corrupted = shards[1].to_vec();
+		*corrupted.last_mut().unwrap() ^= 1;
+		assert!(matches!(
+			verify_shard(&corrupted, &hash),
+			Err(Error::CorruptData(_))
+		));
+		assert!(ShardInfo::parse(&shards[0], &blake2sum(b"other")).is_none());
+		assert!(ShardInfo::parse(&content, &hash).is_none());
+	}
+}
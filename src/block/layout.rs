@@ -0,0 +1,344 @@
+//! Placement of block files between the data directories of a node
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use garage_util::config::{DataDir, DataDirEnum, DataDirPlacement};
+use garage_util::data::*;
+use garage_util::error::*;
+
+/// Space used and available on the filesystem of a data directory,
+/// limited to the capacity of the directory when one is configured
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DataDirUsage {
+	/// Bytes used on the filesystem
+	pub used: u64,
+	/// Bytes that can still be written by Garage
+	pub avail: u64,
+	/// Bytes that Garage may use, used or not
+	pub total: u64,
+}
+
+impl DataDirUsage {
+	fn new(used: u64, avail: u64, capacity: Option<u64>) -> Self {
+		let total = match capacity {
+			Some(c) => c.min(used + avail),
+			None => used + avail,
+		};
+		Self {
+			used,
+			avail: avail.min(total.saturating_sub(used)),
+			total,
+		}
+	}
+
+	/// Fraction of the usable space that is used, between 0 and 1
+	pub fn fill_level(&self) -> f64 {
+		if self.total == 0 {
+			1.0
+		} else {
+			(self.used as f64 / self.total as f64).min(1.0)
+		}
+	}
+
+	/// Whether no new block can be written in the directory
+	pub fn is_full(&self) -> bool {
+		self.avail == 0
+	}
+}
+
+/// The directories in which a node stores block files, and the policy
+/// that chooses in which of them a new block is written
+pub struct DataLayout {
+	dirs: Vec<DataDir>,
+	placement: DataDirPlacement,
+	next_dir: AtomicUsize,
+}
+
+impl DataLayout {
+	pub fn new(data_dir: &DataDirEnum, placement: DataDirPlacement) -> Result<Self, Error> {
+		let dirs = data_dir.dirs();
+		if dirs.is_empty() {
+			return Err(Error::Message(
+				"data_dir must contain at least one directory".into(),
+			));
+		}
+		if dirs.iter().all(|d| d.read_only) {
+			return Err(Error::Message(
+				"At least one directory of data_dir must not be read-only".into(),
+			));
+		}
+		for (i, dir) in dirs.iter().enumerate() {
+			if dirs[..i].iter().any(|d| d.path == dir.path) {
+				return Err(Error::Message(format!(
+					"Directory {} appears several times in data_dir",
+					dir.path.display()
+				)));
+			}
+			if dir.capacity == Some(0) {
+				return Err(Error::Message(format!(
+					"Data directory {} has a capacity of 0, set read_only = true instead",
+					dir.path.display()
+				)));
+			}
+		}
+		Ok(Self {
+			dirs,
+			placement,
+			next_dir: AtomicUsize::new(0),
+		})
+	}
+
+	/// Create the data directories that don't exist already
+	pub fn create_dirs(&self) -> Result<(), Error> {
+		for dir in self.dirs.iter() {
+			std::fs::create_dir_all(&dir.path).ok_or_message(format!(
+				"Unable to create Garage data directory {}",
+				dir.path.display()
+			))?;
+		}
+		Ok(())
+	}
+
+	/// The data directories, in the order in which they are searched for blocks
+	pub fn dirs(&self) -> &[DataDir] {
+		&self.dirs
+	}
+
+	/// Index of the data directory with the given path
+	pub(crate) fn dir_index(&self, path: &Path) -> Option<usize> {
+		self.dirs.iter().position(|d| d.path == path)
+	}
+
+	/// Current usage of the filesystem of a data directory
+	// The types of the fields of statvfs depend on the platform
+	#[allow(clippy::useless_conversion)]
+	pub fn usage(&self, index: usize) -> Option<DataDirUsage> {
+		let dir = &self.dirs[index];
+		let path = CString::new(dir.path.as_os_str().as_bytes()).ok()?;
+		let mut st: libc::statvfs = unsafe { std::mem::zeroed() };
+		if unsafe { libc::statvfs(path.as_ptr(), &mut st) } != 0 {
+			return None;
+		}
+		let frsize = u64::from(st.f_frsize);
+		let total = u64::from(st.f_blocks) * frsize;
+		let free = u64::from(st.f_bfree) * frsize;
+		let avail = u64::from(st.f_bavail) * frsize;
+		Some(DataDirUsage::new(
+			total.saturating_sub(free),
+			avail,
+			dir.capacity,
+		))
+	}
+
+	/// Index of the directory in which a new block should be written.
+	/// Directories that are full are avoided, unless they all are.
+	pub(crate) fn choose_dir(&self, hash: &Hash) -> usize {
+		let writable = self.writable_dirs();
+		let usage = writable
+			.iter()
+			.map(|i| (*i, self.usage(*i)))
+			.collect::<Vec<_>>();
+		let mut candidates = usage
+			.iter()
+			.filter(|(_, u)| !u.map(|u| u.is_full()).unwrap_or(false))
+			.copied()
+			.collect::<Vec<_>>();
+		if candidates.is_empty() {
+			candidates = usage;
+		}
+
+		match self.placement {
+			DataDirPlacement::ConsistentHash => self.rendezvous(hash, &candidates),
+			DataDirPlacement::RoundRobin => {
+				let n = self.next_dir.fetch_add(1, Ordering::Relaxed);
+				candidates[n % candidates.len()].0
+			}
+			DataDirPlacement::LeastFull => {
+				candidates
+					.iter()
+					.min_by(|(_, a), (_, b)| {
+						let a = a.map(|u| u.fill_level()).unwrap_or(0.);
+						let b = b.map(|u| u.fill_level()).unwrap_or(0.);
+						a.total_cmp(&b)
+					})
+					.unwrap()
+					.0
+			}
+		}
+	}
+
+	/// Index of the directory to which a block stored in directory `current`
+	/// should be moved, if any: blocks are moved out of read-only directories,
+	/// and with the consistent-hash placement, to the directory chosen from their hash
+	pub(crate) fn rebalance_target(&self, current: usize, hash: &Hash) -> Option<usize> {
+		if self.dirs[current].read_only {
+			return Some(self.choose_dir(hash));
+		}
+		if self.placement != DataDirPlacement::ConsistentHash {
+			return None;
+		}
+
+		let writable = self
+			.writable_dirs()
+			.into_iter()
+			.map(|i| (i, self.usage(i)))
+			.collect::<Vec<_>>();
+		let target = self.rendezvous(hash, &writable);
+		let target_full = writable
+			.iter()
+			.any(|(i, u)| *i == target && u.map(|u| u.is_full()).unwrap_or(false));
+		if target == current || target_full {
+			None
+		} else {
+			Some(target)
+		}
+	}
+
+	fn writable_dirs(&self) -> Vec<usize> {
+		(0..self.dirs.len())
+			.filter(|i| !self.dirs[*i].read_only)
+			.collect()
+	}
+
+	/// Weighted rendezvous hashing: each directory gets a share of the blocks
+	/// proportional to its capacity (or to the size of its filesystem), and adding
+	/// or removing a directory only changes the placement of the blocks it gains or loses
+	fn rendezvous(&self, hash: &Hash, candidates: &[(usize, Option<DataDirUsage>)]) -> usize {
+		let score = |(i, usage): &(usize, Option<DataDirUsage>)| {
+			let dir = &self.dirs[*i];
+			let weight = dir
+				.capacity
+				.or_else(|| usage.map(|u| u.total))
+				.unwrap_or(1)
+				.max(1) as f64;
+			let mut key = hash.as_slice().to_vec();
+			key.extend_from_slice(dir.path.as_os_str().as_bytes());
+			let mut h = [0u8; 8];
+			h.copy_from_slice(&blake2sum(&key).as_slice()[..8]);
+			let u = (u64::from_be_bytes(h) as f64 + 0.5) / (u64::MAX as f64 + 1.0);
+			weight / -u.ln()
+		};
+		candidates
+			.iter()
+			.map(|c| (c.0, score(c)))
+			.max_by(|(_, a), (_, b)| a.total_cmp(b))
+			.unwrap()
+			.0
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn layout(dirs: Vec<DataDir>, placement: DataDirPlacement) -> DataLayout {
+		DataLayout::new(&DataDirEnum::Multiple(dirs), placement).unwrap()
+	}
+
+	fn dir(path: &str, capacity: Option<u64>, read_only: bool) -> DataDir {
+		DataDir {
+			path: path.into(),
+			capacity,
+			read_only,
+		}
+	}
+
+	#[test]
+	fn test_data_layout_config() {
+		assert!(DataLayout::new(&DataDirEnum::Multiple(vec![]), Default::default()).is_err());
+		assert!(DataLayout::new(
+			&DataDirEnum::Multiple(vec![dir("/a", None, true)]),
+			Default::default()
+		)
+		.is_err());
+		assert!(DataLayout::new(
+			&DataDirEnum::Multiple(vec![dir("/a", None, false), dir("/a", None, false)]),
+			Default::default()
+		)
+		.is_err());
+		assert!(DataLayout::new(
+			&DataDirEnum::Multiple(vec![dir("/a", Some(0), false)]),
+			Default::default()
+		)
+		.is_err());
+	}
+
+	#[test]
+	fn test_data_dir_usage() {
+		let u = DataDirUsage::new(600, 400, None);
+		assert_eq!(u.total, 1000);
+		assert_eq!(u.avail, 400);
+		assert!(!u.is_full());
+
+		let u = DataDirUsage::new(600, 400, Some(800));
+		assert_eq!(u.total, 800);
+		assert_eq!(u.avail, 200);
+		assert_eq!(u.fill_level(), 0.75);
+
+		let u = DataDirUsage::new(600, 400, Some(500));
+		assert_eq!(u.avail, 0);
+		assert_eq!(u.fill_level(), 1.0);
+		assert!(u.is_full());
+	}
+
+	#[test]
+	fn test_consistent_hash_placement() {
+		// Directories that don't exist have no known usage,
+		// so only their configured capacity is taken into account
+		let two = layout(
+			vec![
+				dir("/nonexistent/a", Some(1000), false),
+				dir("/nonexistent/b", Some(3000), false),
+			],
+			DataDirPlacement::ConsistentHash,
+		);
+		let three = layout(
+			vec![
+				dir("/nonexistent/a", Some(1000), false),
+				dir("/nonexistent/b", Some(3000), false),
+				dir("/nonexistent/c", Some(4000), false),
+			],
+			DataDirPlacement::ConsistentHash,
+		);
+
+		let mut counts = [0usize; 3];
+		for i in 0..4000u32 {
+			let hash = blake2sum(&i.to_be_bytes());
+			let before = two.choose_dir(&hash);
+			assert_eq!(before, two.choose_dir(&hash));
+			let after = three.choose_dir(&hash);
+			counts[after] += 1;
+			// Adding a directory only moves blocks to that directory
+			assert!(after == before || after == 2);
+			assert_eq!(
+				three.rebalance_target(before, &hash),
+				if after == before { None } else { Some(2) }
+			);
+		}
+		// Blocks are spread proportionally to the capacity of the directories
+		assert!((400..600).contains(&counts[0]), "{:?}", counts);
+		assert!((1300..1700).contains(&counts[1]), "{:?}", counts);
+		assert!((1800..2200).contains(&counts[2]), "{:?}", counts);
+	}
+
+	#[test]
+	fn test_round_robin_placement() {
+		let layout = layout(
+			vec![
+				dir("/nonexistent/a", None, false),
+				dir("/nonexistent/old", None, true),
+				dir("/nonexistent/b", None, false),
+			],
+			DataDirPlacement::RoundRobin,
+		);
+		let hash = blake2sum(b"block");
+		let chosen = (0..4).map(|_| layout.choose_dir(&hash)).collect::<Vec<_>>();
+		assert_eq!(chosen, vec![0, 2, 0, 2]);
+
+		// Blocks are only moved out of read-only directories
+		assert_eq!(layout.rebalance_target(0, &hash), None);
+		assert!(matches!(layout.rebalance_target(1, &hash), Some(0 | 2)));
+	}
+}
@@ -1,10 +1,13 @@
 #[macro_use]
 extern crate tracing;
 
+pub mod encryption;
+pub mod layout;
 pub mod manager;
 pub mod repair;
 pub mod resync;
 
 mod block;
+mod erasure;
 mod metrics;
 mod rc;
@@ -1,7 +1,8 @@
-use std::path::PathBuf;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use arc_swap::ArcSwapOption;
 use async_trait::async_trait;
@@ -9,6 +10,7 @@ use bytes::Bytes;
 use rand::prelude::*;
 use serde::{Deserialize, Serialize};
 
+use futures::stream::FuturesUnordered;
 use futures::Stream;
 use futures_util::stream::StreamExt;
 use tokio::fs;
@@ -32,12 +34,15 @@ use garage_util::persister::PersisterShared;
 use garage_util::time::msec_to_rfc3339;
 
 use garage_rpc::rpc_helper::OrderTag;
-use garage_rpc::system::System;
+use garage_rpc::system::{NodeStats, NodeStatsSource, System};
 use garage_rpc::*;
 
 use garage_table::replication::{TableReplication, TableShardedReplication};
 
 use crate::block::*;
+use crate::encryption::*;
+use crate::erasure::*;
+use crate::layout::*;
 use crate::metrics::*;
 use crate::rc::*;
 use crate::repair::*;
@@ -46,11 +51,23 @@ use crate::resync::*;
 /// Size under which data will be stored inlined in database instead of as files
 pub const INLINE_THRESHOLD: usize = 3072;
 
+/// Default Unix permissions of block files
+pub const DEFAULT_BLOCK_FILE_MODE: u32 = 0o600;
+
+/// Default format version of newly written block files
+pub const DEFAULT_BLOCK_FORMAT_VERSION: u8 = BLOCK_FORMAT_V1;
+/// Most recent format version of block files that can be written
+pub const MAX_BLOCK_FORMAT_VERSION: u8 = BLOCK_FORMAT_V1;
+
 // The delay between the moment when the reference counter
 // drops to zero, and the moment where we allow ourselves
 // to delete the block locally.
 pub(crate) const BLOCK_GC_DELAY: Duration = Duration::from_secs(600);
 
+// Time to wait for storage nodes to tell whether they already have a block
+// that is being uploaded, after which the block is sent to them anyway
+const BLOCK_DEDUP_CHECK_TIMEOUT: Duration = Duration::from_secs(1);
+
 /// RPC messages used to share blocks of data between nodes
 #[derive(Debug, Serialize, Deserialize)]
 pub enum BlockRpc {
@@ -67,6 +84,10 @@ pub enum BlockRpc {
 	NeedBlockQuery(Hash),
 	/// Response : whether the node do require that block
 	NeedBlockReply(bool),
+	/// Ask other node if they have a block stored locally
+	HasBlockQuery(Hash),
+	/// Response : whether the node has that block
+	HasBlockReply(bool),
 }
 
 impl Rpc for BlockRpc {
@@ -77,10 +98,23 @@ impl Rpc for BlockRpc {
 pub struct BlockManager {
 	/// Replication strategy, allowing to find on which node blocks should be located
 	pub replication: TableShardedReplication,
-	/// Directory in which block are stored
-	pub data_dir: PathBuf,
-
-	compression_level: Option<i32>,
+	/// Directories in which block are stored
+	pub data_layout: Arc<DataLayout>,
+	/// Directory in which each block is stored, when there are several
+	block_dir: db::Tree,
+
+	/// Compression level of newly written blocks, which can be changed
+	/// at runtime by reloading the configuration file
+	compression_level: Arc<ArcSwapOption<i32>>,
+	/// Unix permissions of block files
+	pub block_file_mode: u32,
+	/// Format version of newly written block files
+	pub block_format_version: u8,
+	/// Master keys used to encrypt newly written block files, if encryption at rest is enabled
+	pub(crate) encryption: Option<Arc<BlockEncryption>>,
+	/// Erasure code used to split blocks in shards stored on different nodes,
+	/// if blocks are not simply replicated
+	pub(crate) erasure: Option<ErasureCode>,
 
 	mutation_lock: [Mutex<BlockManagerLocked>; 256],
 
@@ -93,6 +127,7 @@ pub struct BlockManager {
 	pub(crate) metrics: BlockManagerMetrics,
 
 	pub scrub_persister: PersisterShared<ScrubWorkerPersisted>,
+	pub(crate) scrub_progress: ScrubProgress,
 	tx_scrub_command: ArcSwapOption<mpsc::Sender<ScrubWorkerCommand>>,
 }
 
@@ -111,10 +146,15 @@ pub struct BlockResyncErrorInfo {
 struct BlockManagerLocked();
 
 impl BlockManager {
+	#[allow(clippy::too_many_arguments)]
 	pub fn new(
 		db: &db::Db,
-		data_dir: PathBuf,
+		data_layout: DataLayout,
 		compression_level: Option<i32>,
+		block_file_mode: Option<u32>,
+		block_format_version: Option<u8>,
+		encryption: Option<BlockEncryption>,
+		erasure_coding: Option<(usize, usize)>,
 		replication: TableShardedReplication,
 		system: Arc<System>,
 	) -> Arc<Self> {
@@ -123,14 +163,22 @@ impl BlockManager {
 			.expect("Unable to open block_local_rc tree");
 		let rc = BlockRc::new(rc);
 
+		let block_dir = db
+			.open_tree("block_local_dir")
+			.expect("Unable to open block_local_dir tree");
+		let data_layout = Arc::new(data_layout);
+
 		let resync = BlockResyncManager::new(db, &system);
 
 		let endpoint = system
 			.netapp
 			.endpoint("garage_block/manager.rs/Rpc".to_string());
 
+		let compression_level = Arc::new(ArcSwapOption::new(compression_level.map(Arc::new)));
+
 		let metrics = BlockManagerMetrics::new(
-			compression_level,
+			compression_level.clone(),
+			data_layout.clone(),
 			rc.rc.clone(),
 			resync.queue.clone(),
 			resync.errors.clone(),
@@ -140,8 +188,15 @@ impl BlockManager {
 
 		let block_manager = Arc::new(Self {
 			replication,
-			data_dir,
+			data_layout,
+			block_dir,
 			compression_level,
+			block_file_mode: block_file_mode.unwrap_or(DEFAULT_BLOCK_FILE_MODE),
+			block_format_version: block_format_version.unwrap_or(DEFAULT_BLOCK_FORMAT_VERSION),
+			encryption: encryption.map(Arc::new),
+			erasure: erasure_coding.map(|(data_shards, parity_shards)| {
+				ErasureCode::new(data_shards, parity_shards).expect("Invalid erasure code")
+			}),
 			mutation_lock: [(); 256].map(|_| Mutex::new(BlockManagerLocked())),
 			rc,
 			resync,
@@ -149,11 +204,17 @@ impl BlockManager {
 			endpoint,
 			metrics,
 			scrub_persister,
+			scrub_progress: ScrubProgress::default(),
 			tx_scrub_command: ArcSwapOption::new(None),
 		});
 		block_manager.endpoint.set_handler(block_manager.clone());
 		block_manager.scrub_persister.set_with(|_| ()).unwrap();
 
+		let stats_source: Arc<dyn NodeStatsSource> = block_manager.clone();
+		block_manager
+			.system
+			.register_node_stats_source(Arc::downgrade(&stats_source));
+
 		block_manager
 	}
 
@@ -174,6 +235,18 @@ impl BlockManager {
 		));
 	}
 
+	/// Compression level of the blocks written by this node
+	pub fn compression_level(&self) -> Option<i32> {
+		self.compression_level.load().as_deref().copied()
+	}
+
+	/// Change the compression level of the blocks written from now on,
+	/// blocks already stored are not modified
+	pub fn set_compression_level(&self, compression_level: Option<i32>) {
+		self.compression_level
+			.store(compression_level.map(Arc::new));
+	}
+
 	pub fn register_bg_vars(&self, vars: &mut vars::BgVars) {
 		self.resync.register_bg_vars(vars);
 
@@ -183,6 +256,12 @@ impl BlockManager {
 			|p| p.get_with(|x| x.tranquility),
 			|p, tranquility| p.set_with(|x| x.tranquility = tranquility),
 		);
+		vars.register_rw(
+			&self.scrub_persister,
+			"scrub-speed-mb-per-sec",
+			|p| p.get_with(|x| x.speed_mb_per_sec),
+			|p, speed| p.set_with(|x| x.speed_mb_per_sec = speed),
+		);
 		vars.register_ro(&self.scrub_persister, "scrub-last-completed", |p| {
 			p.get_with(|x| msec_to_rfc3339(x.time_last_complete_scrub))
 		});
@@ -201,13 +280,21 @@ impl BlockManager {
 		hash: &Hash,
 		order_tag: Option<OrderTag>,
 	) -> Result<(DataBlockHeader, ByteStream), Error> {
+		if self.erasure.is_some() {
+			// Erasure-coded blocks have to be rebuilt entirely before being sent
+			let (header, bytes) = self.rpc_get_raw_block(hash, order_tag).await?.into_parts();
+			let stream: ByteStream =
+				Box::pin(futures::stream::once(futures::future::ready(Ok(bytes))));
+			return Ok((header, stream));
+		}
+
 		let who = self.replication.read_nodes(hash);
 		let who = self.system.rpc.request_order(&who);
 
 		for node in who.iter() {
-			let node_id = NodeID::from(*node);
-			let rpc = self.endpoint.call_streaming(
-				&node_id,
+			let rpc = self.system.rpc.call_streaming(
+				&self.endpoint,
+				*node,
 				BlockRpc::GetBlock(*hash, order_tag),
 				PRIO_NORMAL | PRIO_SECONDARY,
 			);
@@ -220,7 +307,7 @@ impl BlockManager {
 							continue;
 						}
 					};
-					let (header, stream) = match res.into_parts() {
+					let (header, stream) = match res {
 						(Ok(BlockRpc::PutBlock { hash: _, header }), Some(stream)) => (header, stream),
 						_ => {
 							debug!("Node {:?} returned a malformed response", node);
@@ -248,13 +335,19 @@ impl BlockManager {
 		hash: &Hash,
 		order_tag: Option<OrderTag>,
 	) -> Result<DataBlock, Error> {
+		if let Some(erasure) = &self.erasure {
+			return self
+				.rpc_get_erasure_coded_block(hash, order_tag, erasure)
+				.await;
+		}
+
 		let who = self.replication.read_nodes(hash);
 		let who = self.system.rpc.request_order(&who);
 
 		for node in who.iter() {
-			let node_id = NodeID::from(*node);
-			let rpc = self.endpoint.call_streaming(
-				&node_id,
+			let rpc = self.system.rpc.call_streaming(
+				&self.endpoint,
+				*node,
 				BlockRpc::GetBlock(*hash, order_tag),
 				PRIO_NORMAL | PRIO_SECONDARY,
 			);
@@ -267,7 +360,7 @@ impl BlockManager {
 							continue;
 						}
 					};
-					let (header, stream) = match res.into_parts() {
+					let (header, stream) = match res {
 						(Ok(BlockRpc::PutBlock { hash: _, header }), Some(stream)) => (header, stream),
 						_ => {
 							debug!("Node {:?} returned a malformed response", node);
@@ -293,8 +386,177 @@ impl BlockManager {
 		)))
 	}
 
+	/// Ask nodes that store the shards of an erasure-coded block for them,
+	/// and rebuild the block as soon as enough shards were received.
+	/// Only as many nodes as the number of shards needed are asked at first,
+	/// the next ones are asked when some of them fail to return a valid shard.
+	/// A whole block stored by a node (e.g. one written before erasure coding
+	/// was enabled) is returned as is.
+	async fn rpc_get_erasure_coded_block(
+		&self,
+		hash: &Hash,
+		order_tag: Option<OrderTag>,
+		erasure: &ErasureCode,
+	) -> Result<DataBlock, Error> {
+		let who = self.replication.read_nodes(hash);
+		let mut who = self.system.rpc.request_order(&who).into_iter();
+
+		let get_from = |node: Uuid| async move {
+			let rpc = self.system.rpc.call_streaming(
+				&self.endpoint,
+				node,
+				BlockRpc::GetBlock(*hash, order_tag),
+				PRIO_NORMAL | PRIO_SECONDARY,
+			);
+			let res = tokio::time::timeout(self.system.rpc.rpc_timeout(), rpc)
+				.await
+				.ok_or_message("Node didn't return block in time")??;
+			let (header, stream) = match res {
+				(Ok(BlockRpc::PutBlock { hash: _, header }), Some(stream)) => (header, stream),
+				(Ok(m), _) => return Err(Error::unexpected_rpc_message(m)),
+				(Err(e), _) => return Err(e),
+			};
+			let bytes = read_stream_to_end(stream).await?;
+			Ok::<_, Error>((node, DataBlock::from_parts(header, bytes)))
+		};
+
+		let mut requests = who
+			.by_ref()
+			.take(erasure.data_shards())
+			.map(&get_from)
+			.collect::<FuturesUnordered<_>>();
+		let mut shards = vec![];
+		let mut indices = vec![];
+		while let Some(res) = requests.next().await {
+			match res {
+				Ok((_, block)) if !block.is_shard() => return Ok(block),
+				Ok((node, block)) => match verify_shard(block.inner_buffer(), hash) {
+					Ok(info) if !indices.contains(&info.index) => {
+						indices.push(info.index);
+						shards.push(block.into_parts().1);
+						if shards.len() == erasure.data_shards() {
+							let erasure = erasure.clone();
+							let hash = *hash;
+							return tokio::task::spawn_blocking(move || {
+								erasure.decode(&hash, &shards)
+							})
+							.await
+							.ok_or_message("Block decoding failed")?;
+						}
+						continue;
+					}
+					Ok(_) => debug!("Node {:?} returned a shard we already have", node),
+					Err(e) => debug!("Node {:?} returned an invalid shard: {}", node, e),
+				},
+				Err(e) => debug!("Could not get shard of block {:?}: {}", hash, e),
+			}
+			if let Some(node) = who.next() {
+				requests.push(get_from(node));
+			}
+		}
+
+		Err(Error::Message(format!(
+			"Unable to read block {:?}: only {} of the {} shards needed could be read",
+			hash,
+			shards.len(),
+			erasure.data_shards()
+		)))
+	}
+
+	/// Send to each of the given nodes the shard of an erasure-coded block
+	/// that it should store, which is given by its position in the ring, and
+	/// fail if less than `quorum` of them stored it (all of the nodes to which
+	/// a shard was sent if `None`). If `block` is itself a shard, it is only
+	/// sent to the node that should store this shard.
+	pub(crate) async fn rpc_put_block_shards(
+		&self,
+		hash: Hash,
+		block: DataBlock,
+		nodes: &[Uuid],
+		quorum: Option<usize>,
+		prio: RequestPriority,
+	) -> Result<(), Error> {
+		let erasure = self
+			.erasure
+			.clone()
+			.ok_or_message("Erasure coding is not enabled")?;
+		let who = self.replication.write_nodes(&hash);
+
+		let shards = match block {
+			DataBlock::Shard(bytes) => vec![(verify_shard(&bytes, &hash)?.index, bytes)],
+			block => tokio::task::spawn_blocking(move || erasure.encode(&hash, &block))
+				.await
+				.ok_or_message("Block encoding failed")??
+				.into_iter()
+				.enumerate()
+				.collect(),
+		};
+		let mut calls = vec![];
+		for (index, bytes) in shards {
+			match who.get(index) {
+				Some(node) if nodes.contains(node) => {
+					let req = Req::new(BlockRpc::PutBlock {
+						hash,
+						header: DataBlockHeader::Shard,
+					})?
+					.with_stream_from_buffer(bytes);
+					calls.push(self.system.rpc.call(
+						&self.endpoint,
+						*node,
+						req,
+						RequestStrategy::with_priority(prio),
+					));
+				}
+				_ => (),
+			}
+		}
+		let n_calls = calls.len();
+		let quorum = quorum.unwrap_or(n_calls);
+		let resps = futures::future::join_all(calls).await;
+
+		let successes = resps.iter().filter(|r| r.is_ok()).count();
+		if successes < quorum {
+			let errors = resps
+				.into_iter()
+				.filter_map(|r| r.err())
+				.map(|e| e.to_string())
+				.collect::<Vec<_>>();
+			return Err(Error::Quorum(quorum, successes, n_calls, errors));
+		}
+		Ok(())
+	}
+
 	// ---- Public interface ----
 
+	/// Position of this node among the nodes storing the shards of a block,
+	/// which is the index of the shard that it should store
+	pub(crate) fn local_shard_index(&self, hash: &Hash) -> Option<usize> {
+		self.replication
+			.write_nodes(hash)
+			.iter()
+			.position(|node| *node == self.system.id)
+	}
+
+	/// Get the shard of a whole block that this node should store,
+	/// or the block itself if this node does not store any of its shards
+	pub(crate) async fn local_shard(
+		&self,
+		hash: &Hash,
+		block: DataBlock,
+	) -> Result<DataBlock, Error> {
+		match (&self.erasure, self.local_shard_index(hash)) {
+			(Some(erasure), Some(index)) if !block.is_shard() => {
+				let erasure = erasure.clone();
+				let hash = *hash;
+				let shards = tokio::task::spawn_blocking(move || erasure.encode(&hash, &block))
+					.await
+					.ok_or_message("Block encoding failed")??;
+				Ok(DataBlock::Shard(shards[index].clone()))
+			}
+			_ => Ok(block),
+		}
+	}
+
 	/// Ask nodes that might have a block for it,
 	/// return it as a stream
 	pub async fn rpc_get_block_streaming(
@@ -315,6 +577,10 @@ impl BlockManager {
 				let reader = async_compression::tokio::bufread::ZstdDecoder::new(reader);
 				Ok(Box::pin(tokio_util::io::ReaderStream::new(reader)))
 			}
+			DataBlockHeader::Shard => Err(Error::Message(format!(
+				"Block {:?} is erasure-coded, but erasure coding is not enabled",
+				hash
+			))),
 		}
 	}
 
@@ -329,13 +595,60 @@ impl BlockManager {
 			.verify_get(*hash)
 	}
 
+	/// Ask a single node for a block, without trying other nodes if it fails
+	pub async fn rpc_get_block_from(&self, hash: &Hash, node: Uuid) -> Result<Bytes, Error> {
+		let rpc = self.system.rpc.call_streaming(
+			&self.endpoint,
+			node,
+			BlockRpc::GetBlock(*hash, None),
+			PRIO_NORMAL | PRIO_SECONDARY,
+		);
+		let res = tokio::time::timeout(self.system.rpc.rpc_timeout(), rpc)
+			.await
+			.map_err(|_| {
+				Error::Message(format!("Node {:?} didn't return block in time", node))
+			})??;
+		let (header, stream) = match res {
+			(Ok(BlockRpc::PutBlock { hash: _, header }), Some(stream)) => (header, stream),
+			(Ok(m), _) => return Err(Error::unexpected_rpc_message(m)),
+			(Err(e), _) => return Err(e),
+		};
+		let bytes = read_stream_to_end(stream).await?;
+		DataBlock::from_parts(header, bytes).verify_get(*hash)
+	}
+
 	/// Send block to nodes that should have it
 	pub async fn rpc_put_block(&self, hash: Hash, data: Bytes) -> Result<(), Error> {
+		self.rpc_put_block_with_compression(hash, data, self.compression_level())
+			.await
+	}
+
+	/// Send block to nodes that should have it, compressed with the given
+	/// zstd level instead of the `compression_level` of this node.
+	/// Whether the block is compressed is stored along with it by the nodes
+	/// that receive it, so it can be read whatever the current compression settings.
+	pub async fn rpc_put_block_with_compression(
+		&self,
+		hash: Hash,
+		data: Bytes,
+		compression_level: Option<i32>,
+	) -> Result<(), Error> {
 		let who = self.replication.write_nodes(&hash);
 
-		let (header, bytes) = DataBlock::from_buffer(data, self.compression_level)
-			.await
-			.into_parts();
+		let block = DataBlock::from_buffer(data, compression_level).await;
+		if self.erasure.is_some() {
+			return self
+				.rpc_put_block_shards(
+					hash,
+					block,
+					&who,
+					Some(self.replication.write_quorum()),
+					PRIO_NORMAL | PRIO_SECONDARY,
+				)
+				.await;
+		}
+
+		let (header, bytes) = block.into_parts();
 		let put_block_rpc =
 			Req::new(BlockRpc::PutBlock { hash, header })?.with_stream_from_buffer(bytes);
 
@@ -353,6 +666,68 @@ impl BlockManager {
 		Ok(())
 	}
 
+	/// Send block to nodes that should have it, unless all of them already store it,
+	/// and return whether the block was sent.
+	///
+	/// The block must be referenced in the block_ref table by the caller, before or
+	/// concurrently with this call, and the upload must fail if that reference could
+	/// not be written: the nodes that store the block then keep it, as they do not
+	/// delete an unused block before BLOCK_GC_DELAY, long after the reference reached them.
+	pub async fn rpc_put_block_deduplicated(
+		&self,
+		hash: Hash,
+		data: Bytes,
+		compression_level: Option<i32>,
+	) -> Result<bool, Error> {
+		if self.is_block_stored_on_all_nodes(&hash).await {
+			self.metrics.deduplicated_blocks.add(1);
+			self.metrics.bytes_deduplicated.add(data.len() as u64);
+			return Ok(false);
+		}
+
+		self.rpc_put_block_with_compression(hash, data, compression_level)
+			.await?;
+		Ok(true)
+	}
+
+	/// Check whether all nodes that should have a block store it. Nodes that are
+	/// down, or that do not answer within BLOCK_DEDUP_CHECK_TIMEOUT, are assumed
+	/// not to have it, so that the block is then sent without further delay.
+	async fn is_block_stored_on_all_nodes(&self, hash: &Hash) -> bool {
+		let who = self.replication.write_nodes(hash);
+		let nodes_up = self
+			.system
+			.get_known_nodes()
+			.into_iter()
+			.filter(|n| n.is_up)
+			.map(|n| n.id)
+			.collect::<Vec<_>>();
+		if !who.iter().all(|node| nodes_up.contains(node)) {
+			return false;
+		}
+
+		let resps = self
+			.system
+			.rpc
+			.call_many(
+				&self.endpoint,
+				&who,
+				BlockRpc::HasBlockQuery(*hash),
+				RequestStrategy::with_priority(PRIO_NORMAL)
+					.with_custom_timeout(BLOCK_DEDUP_CHECK_TIMEOUT),
+			)
+			.await;
+		match resps {
+			Ok(resps) => resps
+				.iter()
+				.all(|(_, resp)| matches!(resp, Ok(BlockRpc::HasBlockReply(true)))),
+			Err(e) => {
+				debug!("Could not get locations of block {:?}: {}", hash, e);
+				false
+			}
+		}
+	}
+
 	/// Get number of items in the refcount table
 	pub fn rc_len(&self) -> Result<usize, Error> {
 		Ok(self.rc.rc.len()?)
@@ -363,6 +738,11 @@ impl BlockManager {
 		Ok(self.rc.rc.fast_len()?)
 	}
 
+	/// Get the progress of the current scrub pass
+	pub fn scrub_stats(&self) -> ScrubStats {
+		self.scrub_progress.stats()
+	}
+
 	/// Send command to start/stop/manager scrub worker
 	pub async fn send_scrub_command(&self, cmd: ScrubWorkerCommand) -> Result<(), Error> {
 		let tx = self.tx_scrub_command.load();
@@ -396,6 +776,137 @@ impl BlockManager {
 		Ok(blocks)
 	}
 
+	/// Ask the nodes that should store a block whether they actually have it,
+	/// and return the list of nodes on which the block file exists.
+	/// Nodes that could not be reached are not included.
+	pub async fn get_block_locations(&self, hash: &Hash) -> Result<Vec<Uuid>, Error> {
+		let who = self.replication.write_nodes(hash);
+		let resps = self
+			.system
+			.rpc
+			.call_many(
+				&self.endpoint,
+				&who,
+				BlockRpc::HasBlockQuery(*hash),
+				RequestStrategy::with_priority(PRIO_NORMAL),
+			)
+			.await?;
+
+		let mut ret = vec![];
+		for (node, resp) in resps {
+			match resp {
+				Ok(BlockRpc::HasBlockReply(true)) => ret.push(node),
+				Ok(BlockRpc::HasBlockReply(false)) => (),
+				Ok(m) => return Err(Error::unexpected_rpc_message(m)),
+				Err(e) => debug!("HasBlockQuery to {:?} failed: {}", node, e),
+			}
+		}
+		Ok(ret)
+	}
+
+	/// Ask a single node whether it has a block, recording the time spent
+	/// in each step of the RPC call (used by `garage debug trace-rpc`)
+	pub async fn traced_has_block(&self, node: Uuid, hash: &Hash) -> Result<RpcTiming, Error> {
+		let (resp, timing) = TracedRpc::new(&self.system.rpc, &*self.endpoint)
+			.call(node, BlockRpc::HasBlockQuery(*hash), PRIO_NORMAL)
+			.await?;
+		match resp? {
+			BlockRpc::HasBlockReply(_) => Ok(timing),
+			m => Err(Error::unexpected_rpc_message(m)),
+		}
+	}
+
+	/// Find the blocks stored on this node whose hash starts with the given
+	/// prefix, by listing the block files in the data directories
+	pub async fn find_blocks_by_hash_prefix(&self, prefix: &[u8]) -> Result<Vec<Hash>, Error> {
+		// Block files are stored in two levels of directories named after
+		// the first two bytes of their hash, only list the ones we need
+		let mut dirs = self
+			.data_layout
+			.dirs()
+			.iter()
+			.map(|d| d.path.clone())
+			.collect::<Vec<_>>();
+		for depth in 0..2 {
+			let mut next_dirs = vec![];
+			for dir in dirs {
+				if let Some(b) = prefix.get(depth) {
+					next_dirs.push(dir.join(hex::encode([*b])));
+					continue;
+				}
+				let mut entries = match fs::read_dir(&dir).await {
+					Ok(entries) => entries,
+					Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+					Err(e) => return Err(e.into()),
+				};
+				while let Some(ent) = entries.next_entry().await? {
+					if ent.file_type().await?.is_dir() {
+						next_dirs.push(ent.path());
+					}
+				}
+			}
+			dirs = next_dirs;
+		}
+
+		let mut ret = vec![];
+		for dir in dirs {
+			let mut entries = match fs::read_dir(&dir).await {
+				Ok(entries) => entries,
+				Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+				Err(e) => return Err(e.into()),
+			};
+			while let Some(ent) = entries.next_entry().await? {
+				let name = ent.file_name();
+				let name = match name.to_str() {
+					Some(name) => name.strip_suffix(".zst").unwrap_or(name),
+					None => continue,
+				};
+				let hash = match hex::decode(name).ok().and_then(|h| Hash::try_from(&h)) {
+					Some(hash) => hash,
+					None => continue,
+				};
+				if hash.as_slice().starts_with(prefix) && !ret.contains(&hash) {
+					ret.push(hash);
+				}
+			}
+		}
+		Ok(ret)
+	}
+
+	/// Read and verify a random sample of up to `n` blocks stored on this node,
+	/// as the scrub worker does. Returns the number of blocks read,
+	/// their total size on disk and the time it took.
+	pub async fn sample_read_speed(&self, n: usize) -> Result<(usize, u64, Duration), Error> {
+		let mut blocks = 0;
+		let mut bytes = 0;
+		let mut elapsed = Duration::ZERO;
+		for _ in 0..2 * n {
+			if blocks >= n {
+				break;
+			}
+			let pos = gen_uuid();
+			let hash = match self.rc.rc.get_gt(pos)? {
+				Some((k, _)) => Hash::try_from(&k[..]).unwrap(),
+				None => continue,
+			};
+
+			let start = Instant::now();
+			let data = match self.read_block(&hash).await {
+				Ok(data) => data,
+				Err(e) => {
+					debug!("Could not read block {:?} for sampling: {}", hash, e);
+					continue;
+				}
+			};
+			data.verify(hash)?;
+			elapsed += start.elapsed();
+
+			blocks += 1;
+			bytes += data.inner_buffer().len() as u64;
+		}
+		Ok((blocks, bytes, elapsed))
+	}
+
 	//// ----- Managing the reference counter ----
 
 	/// Increment the number of time a block is used, putting it to resynchronization if it is
@@ -484,6 +995,16 @@ impl BlockManager {
 		Ok(())
 	}
 
+	/// Write to disk the content of a block that was obtained from outside
+	/// of the cluster, after checking that it matches the block's hash
+	pub async fn write_fetched_block(&self, hash: &Hash, data: Bytes) -> Result<(), Error> {
+		if blake2sum(&data[..]) != *hash {
+			return Err(Error::CorruptData(*hash));
+		}
+		let data = DataBlock::from_buffer(data, self.compression_level()).await;
+		self.write_block(hash, &data).await
+	}
+
 	async fn handle_get_block(&self, hash: &Hash, order_tag: Option<OrderTag>) -> Resp<BlockRpc> {
 		let block = match self.read_block(hash).await {
 			Ok(data) => data,
@@ -507,9 +1028,14 @@ impl BlockManager {
 
 	/// Read block from disk, verifying it's integrity
 	pub(crate) async fn read_block(&self, hash: &Hash) -> Result<DataBlock, Error> {
+		let tracer = opentelemetry::global::tracer("garage");
+
 		let data = self
 			.read_block_internal(hash)
 			.bound_record_duration(&self.metrics.block_read_duration)
+			.with_context(Context::current_with_span(
+				tracer.start("BlockManager::read_block"),
+			))
 			.await?;
 
 		self.metrics
@@ -520,43 +1046,34 @@ impl BlockManager {
 	}
 
 	async fn read_block_internal(&self, hash: &Hash) -> Result<DataBlock, Error> {
-		let mut path = self.block_path(hash);
-		let compressed = match self.is_block_compressed(hash).await {
-			Ok(c) => c,
+		let (path, compressed) = match self.find_block(hash).await {
+			Ok(found) => found,
 			Err(e) => {
 				// Not found but maybe we should have had it ??
 				self.resync
 					.put_to_resync(hash, 2 * self.system.rpc.rpc_timeout())?;
-				return Err(Into::into(e));
+				return Err(e);
 			}
 		};
-		if compressed {
-			path.set_extension("zst");
-		}
 		let mut f = fs::File::open(&path).await?;
 
 		let mut data = vec![];
 		f.read_to_end(&mut data).await?;
 		drop(f);
 
-		let data = if compressed {
-			DataBlock::Compressed(data.into())
-		} else {
-			DataBlock::Plain(data.into())
-		};
-
-		if data.verify(*hash).is_err() {
-			self.metrics.corruption_counter.add(1);
+		match DataBlock::from_file(data.into(), compressed, *hash, self.encryption.as_deref()) {
+			Err(Error::CorruptData(_)) => {
+				self.metrics.corruption_counter.add(1);
 
-			self.lock_mutate(hash)
-				.await
-				.move_block_to_corrupted(hash, self)
-				.await?;
-			self.resync.put_to_resync(hash, Duration::from_millis(0))?;
-			return Err(Error::CorruptData(*hash));
+				self.lock_mutate(hash)
+					.await
+					.move_block_to_corrupted(hash, self)
+					.await?;
+				self.resync.put_to_resync(hash, Duration::from_millis(0))?;
+				Err(Error::CorruptData(*hash))
+			}
+			res => res,
 		}
-
-		Ok(data)
 	}
 
 	/// Check if this node has a block and whether it needs it
@@ -568,7 +1085,7 @@ impl BlockManager {
 	}
 
 	/// Check if this node should have a block, but don't actually have it
-	async fn need_block(&self, hash: &Hash) -> Result<bool, Error> {
+	pub async fn need_block(&self, hash: &Hash) -> Result<bool, Error> {
 		let BlockStatus { exists, needed } = self.check_block_status(hash).await?;
 		Ok(needed.is_nonzero() && !exists)
 	}
@@ -581,53 +1098,226 @@ impl BlockManager {
 			.await
 	}
 
-	/// Utility: gives the path of the directory in which a block should be found
-	fn block_dir(&self, hash: &Hash) -> PathBuf {
-		let mut path = self.data_dir.clone();
+	/// Encrypt the file storing a block with the current encryption key, if it is
+	/// not encrypted or was encrypted with a previous key.
+	/// Returns true if the file was rewritten.
+	pub(crate) async fn rekey_block(&self, hash: &Hash) -> Result<bool, Error> {
+		self.lock_mutate(hash).await.rekey_block(hash, self).await
+	}
+
+	/// Check that the file storing a block is owned by the user running Garage
+	/// and has the configured permissions, and fix it if `fix` is set.
+	/// Returns true if the permissions were wrong.
+	pub(crate) async fn check_block_permissions(
+		&self,
+		hash: &Hash,
+		fix: bool,
+	) -> Result<bool, Error> {
+		self.lock_mutate(hash)
+			.await
+			.check_block_permissions(hash, fix, self)
+			.await
+	}
+
+	/// Check that the file storing a block contains as much data as the block should,
+	/// e.g. to find files that were only partially written when a node crashed.
+	/// If it does not and `fix` is set, the file is deleted so that the block
+	/// is fetched again from other nodes by the resync worker.
+	/// Returns `None` if the size is correct, or the path of the file and the size
+	/// of its data (`None` if it cannot be decompressed) otherwise.
+	pub async fn check_block_file_size(
+		&self,
+		hash: &Hash,
+		expected_size: u64,
+		fix: bool,
+	) -> Result<Option<(PathBuf, Option<u64>)>, Error> {
+		self.lock_mutate(hash)
+			.await
+			.check_block_file_size(hash, expected_size, fix, self)
+			.await
+	}
+
+	/// Read a block file and check that its content matches its hash, without
+	/// moving it away if it is corrupted. Compressed blocks are only checked
+	/// using their zstd checksum, unless `rehash` is set, in which case they
+	/// are decompressed and their content is hashed.
+	/// Returns the path of the block file and whether its content is valid.
+	pub(crate) async fn check_block_hash(
+		&self,
+		hash: &Hash,
+		rehash: bool,
+	) -> Result<(PathBuf, bool), Error> {
+		let (path, compressed) = self.find_block(hash).await?;
+		let data = fs::read(&path).await?;
+
+		let hash = *hash;
+		let encryption = self.encryption.clone();
+		let valid = tokio::task::spawn_blocking(move || {
+			match DataBlock::from_file(data.into(), compressed, hash, encryption.as_deref()) {
+				Ok(DataBlock::Compressed(data)) if rehash => zstd::stream::decode_all(&data[..])
+					.map(|plain| blake2sum(&plain) == hash)
+					.map_err(Error::from),
+				Ok(_) => Ok(true),
+				Err(Error::CorruptData(_)) => Ok(false),
+				Err(e) => Err(e),
+			}
+		})
+		.await
+		.ok_or_message("Block hash computation failed")??;
+
+		Ok((path, valid))
+	}
+
+	/// Store a block from a file found outside of its expected location, after
+	/// checking that its content matches its hash, and remove that file.
+	/// Returns false if the block is already stored at its expected location,
+	/// in which case the file is left untouched.
+	pub(crate) async fn import_block_file(
+		&self,
+		hash: &Hash,
+		path: &Path,
+		compressed: bool,
+	) -> Result<bool, Error> {
+		if self.is_block_compressed(hash).await.is_ok() {
+			return Ok(false);
+		}
+
+		let data = fs::read(path).await?;
+		let hash = *hash;
+		let encryption = self.encryption.clone();
+		let block = tokio::task::spawn_blocking(move || {
+			let block = DataBlock::from_file(data.into(), compressed, hash, encryption.as_deref())?;
+			if let DataBlock::Compressed(data) = &block {
+				if blake2sum(&zstd::stream::decode_all(&data[..])?) != hash {
+					return Err(Error::CorruptData(hash));
+				}
+			}
+			Ok(block)
+		})
+		.await
+		.ok_or_message("Block hash computation failed")??;
+
+		self.write_block(&hash, &block).await?;
+		fs::remove_file(path).await?;
+		Ok(true)
+	}
+
+	/// Move the file of a block to another data directory if it is in a read-only
+	/// directory, or if it is not in the directory given by the placement policy.
+	/// Returns true if the file was moved.
+	pub(crate) async fn rebalance_block(&self, hash: &Hash) -> Result<bool, Error> {
+		self.lock_mutate(hash)
+			.await
+			.rebalance_block(hash, self)
+			.await
+	}
+
+	/// Utility: gives the path of the directory in which a block should be found,
+	/// in the data directory with the given index
+	fn block_dir(&self, dir_index: usize, hash: &Hash) -> PathBuf {
+		let mut path = self.data_layout.dirs()[dir_index].path.clone();
 		path.push(hex::encode(&hash.as_slice()[0..1]));
 		path.push(hex::encode(&hash.as_slice()[1..2]));
 		path
 	}
 
-	/// Utility: give the full path where a block should be found, minus extension if block is
-	/// compressed
-	fn block_path(&self, hash: &Hash) -> PathBuf {
-		let mut path = self.block_dir(hash);
-		path.push(hex::encode(hash.as_ref()));
-		path
+	/// Utility: index of the data directory in which a block was last written,
+	/// when there are several
+	fn recorded_block_dir(&self, hash: &Hash) -> Option<usize> {
+		if self.data_layout.dirs().len() < 2 {
+			return Some(0);
+		}
+		match self.block_dir.get(hash) {
+			Ok(Some(path)) => self
+				.data_layout
+				.dir_index(Path::new(std::ffi::OsStr::from_bytes(&path))),
+			Ok(None) => None,
+			Err(e) => {
+				warn!("Could not read data directory of block {:?}: {}", hash, e);
+				None
+			}
+		}
 	}
 
-	/// Utility: check if block is stored compressed. Error if block is not stored
-	async fn is_block_compressed(&self, hash: &Hash) -> Result<bool, Error> {
-		let mut path = self.block_path(hash);
+	/// Utility: remember in which data directory a block is stored, when there are several
+	fn record_block_dir(&self, hash: &Hash, dir_index: Option<usize>) -> Result<(), Error> {
+		if self.data_layout.dirs().len() < 2 {
+			return Ok(());
+		}
+		match dir_index {
+			Some(i) => {
+				let path = self.data_layout.dirs()[i].path.as_os_str().as_bytes();
+				self.block_dir.insert(hash, path)?;
+			}
+			None => {
+				self.block_dir.remove(hash)?;
+			}
+		}
+		Ok(())
+	}
+
+	/// Utility: find the file storing a block, and whether it is compressed.
+	/// The data directory in which the block was written is checked first,
+	/// then all other data directories. Error if block is not stored
+	async fn find_block(&self, hash: &Hash) -> Result<(PathBuf, bool), Error> {
+		let recorded = self.recorded_block_dir(hash);
+		let others = (0..self.data_layout.dirs().len()).filter(|i| Some(*i) != recorded);
+
+		let mut not_found = None;
+		for dir_index in recorded.into_iter().chain(others) {
+			match self.find_block_in(dir_index, hash).await {
+				Ok(found) => return Ok(found),
+				Err(e) => {
+					not_found.get_or_insert(e);
+				}
+			}
+		}
+		Err(not_found.unwrap().into())
+	}
+
+	/// Utility: find the file storing a block in a given data directory
+	async fn find_block_in(
+		&self,
+		dir_index: usize,
+		hash: &Hash,
+	) -> Result<(PathBuf, bool), std::io::Error> {
+		let mut path = self.block_dir(dir_index, hash);
+		path.push(hex::encode(hash.as_ref()));
 
 		// If compression is disabled on node - check for the raw block
 		// first and then a compressed one (as compression may have been
 		// previously enabled).
-		match self.compression_level {
+		match self.compression_level() {
 			None => {
 				if fs::metadata(&path).await.is_ok() {
-					return Ok(false);
+					return Ok((path, false));
 				}
 
 				path.set_extension("zst");
 
-				fs::metadata(&path).await.map(|_| true).map_err(Into::into)
+				fs::metadata(&path).await.map(|_| (path, true))
 			}
 			_ => {
 				path.set_extension("zst");
 
 				if fs::metadata(&path).await.is_ok() {
-					return Ok(true);
+					return Ok((path, true));
 				}
 
 				path.set_extension("");
 
-				fs::metadata(&path).await.map(|_| false).map_err(Into::into)
+				fs::metadata(&path).await.map(|_| (path, false))
 			}
 		}
 	}
 
+	/// Utility: check if block is stored compressed. Error if block is not stored
+	async fn is_block_compressed(&self, hash: &Hash) -> Result<bool, Error> {
+		self.find_block(hash)
+			.await
+			.map(|(_, compressed)| compressed)
+	}
+
 	async fn lock_mutate(&self, hash: &Hash) -> MutexGuard<'_, BlockManagerLocked> {
 		let tracer = opentelemetry::global::tracer("garage");
 		self.mutation_lock[hash.as_slice()[0] as usize]
@@ -639,6 +1329,13 @@ impl BlockManager {
 	}
 }
 
+impl NodeStatsSource for BlockManager {
+	fn fill_node_stats(&self, stats: &mut NodeStats) {
+		stats.block_resync_queue_len = self.resync.queue_len().ok();
+		stats.block_resync_errors = self.resync.errors_len().ok();
+	}
+}
+
 #[async_trait]
 impl StreamingEndpointHandler<BlockRpc> for BlockManager {
 	async fn handle(self: &Arc<Self>, mut message: Req<BlockRpc>, _from: NodeID) -> Resp<BlockRpc> {
@@ -652,6 +1349,9 @@ impl StreamingEndpointHandler<BlockRpc> for BlockManager {
 			BlockRpc::NeedBlockQuery(h) => {
 				Resp::new(self.need_block(h).await.map(BlockRpc::NeedBlockReply))
 			}
+			BlockRpc::HasBlockQuery(h) => Resp::new(Ok(BlockRpc::HasBlockReply(
+				self.is_block_compressed(h).await.is_ok(),
+			))),
 			m => Resp::new(Err(Error::unexpected_rpc_message(m))),
 		}
 	}
@@ -681,38 +1381,121 @@ impl BlockManagerLocked {
 		mgr: &BlockManager,
 	) -> Result<(), Error> {
 		let compressed = data.is_compressed();
+		let shard = data.is_shard();
 		let data = data.inner_buffer();
 
-		let mut path = mgr.block_dir(hash);
-		let directory = path.clone();
-		path.push(hex::encode(hash));
+		// A compressed block replaces an uncompressed one in the same data directory,
+		// and a shard replaces whatever was stored for the block (a whole block
+		// written before erasure coding was enabled, or a shard that this node
+		// stored at another position in the ring).
+		// A new block goes to the data directory chosen by the placement policy
+		let (mut path, new_dir, to_delete) = match (mgr.find_block(hash).await, compressed) {
+			(Ok((existing, existing_compressed)), _) if shard => {
+				let mut path = existing.clone();
+				path.set_extension("");
+				let to_delete = if existing_compressed {
+					Some(existing)
+				} else {
+					None
+				};
+				(path, None, to_delete)
+			}
+			(Ok((_, true)), _) => return Ok(()),
+			(Ok((_, false)), false) => return Ok(()),
+			(Ok((path_to_delete, false)), true) => {
+				(path_to_delete.clone(), None, Some(path_to_delete))
+			}
+			(Err(_), _) => {
+				let dir_index = mgr.data_layout.choose_dir(hash);
+				let mut path = mgr.block_dir(dir_index, hash);
+				path.push(hex::encode(hash));
+				(path, Some(dir_index), None)
+			}
+		};
+		if compressed {
+			path.set_extension("zst");
+		}
+		let directory = path.parent().unwrap().to_path_buf();
 
 		fs::create_dir_all(&directory).await?;
 
-		let to_delete = match (mgr.is_block_compressed(hash).await, compressed) {
-			(Ok(true), _) => return Ok(()),
-			(Ok(false), false) => return Ok(()),
-			(Ok(false), true) => {
-				let path_to_delete = path.clone();
-				path.set_extension("zst");
-				Some(path_to_delete)
+		let content = match &mgr.encryption {
+			Some(encryption) => {
+				let mut content = DataBlock::file_header(BLOCK_FORMAT_V2);
+				content.extend(encryption.encrypt(hash, data)?);
+				content
 			}
-			(Err(_), compressed) => {
-				if compressed {
-					path.set_extension("zst");
-				}
-				None
+			None => {
+				let mut content = DataBlock::file_header(mgr.block_format_version);
+				content.extend_from_slice(data);
+				content
 			}
 		};
+		self.write_file(&path, &directory, &content, mgr).await?;
+
+		if new_dir.is_some() {
+			mgr.record_block_dir(hash, new_dir)?;
+		}
+		if let Some(to_delete) = to_delete {
+			fs::remove_file(to_delete).await?;
+		}
+
+		Ok(())
+	}
+
+	async fn rebalance_block(&self, hash: &Hash, mgr: &BlockManager) -> Result<bool, Error> {
+		let (path, compressed) = mgr.find_block(hash).await?;
+		// Block files are two levels of directories below their data directory
+		let current = path
+			.ancestors()
+			.nth(3)
+			.and_then(|dir| mgr.data_layout.dir_index(dir))
+			.ok_or_message("Block file is not in a data directory")?;
+		let target = match mgr.data_layout.rebalance_target(current, hash) {
+			Some(target) => target,
+			None => return Ok(false),
+		};
+
+		let directory = mgr.block_dir(target, hash);
+		let mut new_path = directory.join(hex::encode(hash));
+		if compressed {
+			new_path.set_extension("zst");
+		}
+		fs::create_dir_all(&directory).await?;
 
-		let mut path_tmp = path.clone();
+		// The block stays readable from its previous location until
+		// the new one is fully written and recorded
+		let content = fs::read(&path).await?;
+		self.write_file(&new_path, &directory, &content, mgr)
+			.await?;
+		mgr.record_block_dir(hash, Some(target))?;
+		fs::remove_file(&path).await?;
+		Ok(true)
+	}
+
+	/// Atomically replace the content of a block file, by writing it to a temporary file
+	/// that is then renamed
+	async fn write_file(
+		&self,
+		path: &Path,
+		directory: &Path,
+		content: &[u8],
+		mgr: &BlockManager,
+	) -> Result<(), Error> {
+		let mut path_tmp = path.to_path_buf();
 		let tmp_extension = format!("tmp{}", hex::encode(thread_rng().gen::<[u8; 4]>()));
 		path_tmp.set_extension(tmp_extension);
 
 		let mut delete_on_drop = DeleteOnDrop(Some(path_tmp.clone()));
 
-		let mut f = fs::File::create(&path_tmp).await?;
-		f.write_all(data).await?;
+		let mut f = fs::OpenOptions::new()
+			.write(true)
+			.create(true)
+			.truncate(true)
+			.mode(mgr.block_file_mode)
+			.open(&path_tmp)
+			.await?;
+		f.write_all(content).await?;
 		f.sync_all().await?;
 		drop(f);
 
@@ -720,10 +1503,6 @@ impl BlockManagerLocked {
 
 		delete_on_drop.cancel();
 
-		if let Some(to_delete) = to_delete {
-			fs::remove_file(to_delete).await?;
-		}
-
 		// We want to ensure that when this function returns, data is properly persisted
 		// to disk. The first step is the sync_all above that does an fsync on the data file.
 		// Now, we do an fsync on the containing directory, to ensure that the rename
@@ -740,15 +1519,115 @@ impl BlockManagerLocked {
 		Ok(())
 	}
 
+	async fn rekey_block(&self, hash: &Hash, mgr: &BlockManager) -> Result<bool, Error> {
+		let encryption = mgr
+			.encryption
+			.as_deref()
+			.ok_or_message("No encryption_key is configured")?;
+
+		let (path, compressed) = mgr.find_block(hash).await?;
+		let data = fs::read(&path).await?;
+
+		let header = DataBlock::file_header(BLOCK_FORMAT_V2);
+		let encrypted = match data.strip_prefix(&header[..]) {
+			Some(content) if encryption.decrypt(hash, content)?.is_some() => {
+				match encryption.rewrap(content)? {
+					Some(content) => content,
+					None => return Ok(false),
+				}
+			}
+			_ => {
+				let block = DataBlock::from_file(data.into(), compressed, *hash, Some(encryption))?;
+				encryption.encrypt(hash, block.inner_buffer())?
+			}
+		};
+
+		let mut content = header;
+		content.extend(encrypted);
+		self.write_file(&path, path.parent().unwrap(), &content, mgr)
+			.await?;
+		Ok(true)
+	}
+
+	async fn check_block_file_size(
+		&self,
+		hash: &Hash,
+		expected_size: u64,
+		fix: bool,
+		mgr: &BlockManager,
+	) -> Result<Option<(PathBuf, Option<u64>)>, Error> {
+		let (path, compressed) = mgr.find_block(hash).await?;
+
+		let data = fs::read(&path).await?;
+		let hash = *hash;
+		let encryption = mgr.encryption.clone();
+		let size = tokio::task::spawn_blocking(move || {
+			DataBlock::file_data_len(
+				&data,
+				compressed,
+				&hash,
+				expected_size,
+				encryption.as_deref(),
+			)
+		})
+		.await
+		.ok_or_message("Block size computation failed")?;
+		if size == Some(expected_size) {
+			return Ok(None);
+		}
+
+		if fix {
+			warn!(
+				"Block {:?} has {:?} bytes instead of {}, deleting {} and resyncing.",
+				hash,
+				size,
+				expected_size,
+				path.display()
+			);
+			fs::remove_file(&path).await?;
+			mgr.resync.put_to_resync(&hash, Duration::from_millis(0))?;
+		}
+		Ok(Some((path, size)))
+	}
+
+	async fn check_block_permissions(
+		&self,
+		hash: &Hash,
+		fix: bool,
+		mgr: &BlockManager,
+	) -> Result<bool, Error> {
+		use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+		let (path, _) = mgr.find_block(hash).await?;
+
+		let metadata = fs::metadata(&path).await?;
+		let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+		let wrong_owner = metadata.uid() != uid;
+		let wrong_mode = metadata.mode() & 0o7777 != mgr.block_file_mode;
+
+		if fix && wrong_owner {
+			let path_c = std::ffi::CString::new(path.as_os_str().as_bytes())
+				.ok_or_message("invalid block path")?;
+			if unsafe { libc::chown(path_c.as_ptr(), uid, gid) } != 0 {
+				return Err(std::io::Error::last_os_error().into());
+			}
+		}
+		if fix && wrong_mode {
+			fs::set_permissions(&path, std::fs::Permissions::from_mode(mgr.block_file_mode))
+				.await?;
+		}
+
+		Ok(wrong_owner || wrong_mode)
+	}
+
 	async fn move_block_to_corrupted(&self, hash: &Hash, mgr: &BlockManager) -> Result<(), Error> {
 		warn!(
 			"Block {:?} is corrupted. Renaming to .corrupted and resyncing.",
 			hash
 		);
-		let mut path = mgr.block_path(hash);
+		let (path, compressed) = mgr.find_block(hash).await?;
 		let mut path2 = path.clone();
-		if mgr.is_block_compressed(hash).await? {
-			path.set_extension("zst");
+		if compressed {
 			path2.set_extension("zst.corrupted");
 		} else {
 			path2.set_extension("corrupted");
@@ -761,11 +1640,12 @@ impl BlockManagerLocked {
 		let BlockStatus { exists, needed } = self.check_block_status(hash, mgr).await?;
 
 		if exists && needed.is_deletable() {
-			let mut path = mgr.block_path(hash);
-			if mgr.is_block_compressed(hash).await? {
-				path.set_extension("zst");
+			// Also delete copies left in other data directories,
+			// e.g. if Garage was interrupted while moving the block
+			while let Ok((path, _)) = mgr.find_block(hash).await {
+				fs::remove_file(path).await?;
 			}
-			fs::remove_file(path).await?;
+			mgr.record_block_dir(hash, None)?;
 			mgr.metrics.delete_counter.add(1);
 		}
 		Ok(())
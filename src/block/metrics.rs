@@ -1,14 +1,21 @@
-use opentelemetry::{global, metrics::*};
+use std::sync::Arc;
+
+use arc_swap::ArcSwapOption;
+use opentelemetry::{global, metrics::*, KeyValue};
 
 use garage_db as db;
 use garage_db::counted_tree_hack::CountedTree;
 
+use crate::layout::DataLayout;
+
 /// TableMetrics reference all counter used for metrics
 pub struct BlockManagerMetrics {
 	pub(crate) _compression_level: ValueObserver<u64>,
 	pub(crate) _rc_size: ValueObserver<u64>,
 	pub(crate) _resync_queue_len: ValueObserver<u64>,
 	pub(crate) _resync_errored_blocks: ValueObserver<u64>,
+	pub(crate) _data_dir_used: ValueObserver<u64>,
+	pub(crate) _data_dir_total: ValueObserver<u64>,
 
 	pub(crate) resync_counter: BoundCounter<u64>,
 	pub(crate) resync_error_counter: BoundCounter<u64>,
@@ -20,14 +27,21 @@ pub struct BlockManagerMetrics {
 	pub(crate) block_read_duration: BoundValueRecorder<f64>,
 	pub(crate) bytes_written: BoundCounter<u64>,
 	pub(crate) block_write_duration: BoundValueRecorder<f64>,
+	pub(crate) deduplicated_blocks: BoundCounter<u64>,
+	pub(crate) bytes_deduplicated: BoundCounter<u64>,
 	pub(crate) delete_counter: BoundCounter<u64>,
 
 	pub(crate) corruption_counter: BoundCounter<u64>,
+
+	pub(crate) scrub_blocks_checked: BoundCounter<u64>,
+	pub(crate) scrub_bytes_read: BoundCounter<u64>,
+	pub(crate) scrub_errors: BoundCounter<u64>,
 }
 
 impl BlockManagerMetrics {
 	pub fn new(
-		compression_level: Option<i32>,
+		compression_level: Arc<ArcSwapOption<i32>>,
+		data_layout: Arc<DataLayout>,
 		rc_tree: db::Tree,
 		resync_queue: CountedTree,
 		resync_errors: CountedTree,
@@ -36,8 +50,8 @@ impl BlockManagerMetrics {
 		Self {
 			_compression_level: meter
 				.u64_value_observer("block.compression_level", move |observer| {
-					match compression_level {
-						Some(v) => observer.observe(v as u64, &[]),
+					match compression_level.load().as_deref() {
+						Some(v) => observer.observe(*v as u64, &[]),
 						None => observer.observe(0_u64, &[]),
 					}
 				})
@@ -65,6 +79,37 @@ impl BlockManagerMetrics {
 				})
 				.with_description("Number of block hashes whose last resync resulted in an error")
 				.init(),
+			_data_dir_used: {
+				let data_layout = data_layout.clone();
+				meter
+					.u64_value_observer("block.data_dir_used", move |observer| {
+						for (i, dir) in data_layout.dirs().iter().enumerate() {
+							if let Some(usage) = data_layout.usage(i) {
+								observer.observe(
+									usage.used,
+									&[KeyValue::new("dir", dir.path.display().to_string())],
+								)
+							}
+						}
+					})
+					.with_description("Bytes used on the filesystem of each data directory")
+					.init()
+			},
+			_data_dir_total: meter
+				.u64_value_observer("block.data_dir_total", move |observer| {
+					for (i, dir) in data_layout.dirs().iter().enumerate() {
+						if let Some(usage) = data_layout.usage(i) {
+							observer.observe(
+								usage.total,
+								&[KeyValue::new("dir", dir.path.display().to_string())],
+							)
+						}
+					}
+				})
+				.with_description(
+					"Bytes that can be used on the filesystem of each data directory, limited to its capacity",
+				)
+				.init(),
 
 			resync_counter: meter
 				.u64_counter("block.resync_counter")
@@ -111,6 +156,20 @@ impl BlockManagerMetrics {
 				.with_description("Duration of block write operations")
 				.init()
 				.bind(&[]),
+			deduplicated_blocks: meter
+				.u64_counter("block.deduplicated_blocks")
+				.with_description(
+					"Number of uploaded blocks that were not sent to storage nodes, as they already had them",
+				)
+				.init()
+				.bind(&[]),
+			bytes_deduplicated: meter
+				.u64_counter("block.bytes_deduplicated")
+				.with_description(
+					"Number of uploaded bytes that were not sent to storage nodes, as they already had them",
+				)
+				.init()
+				.bind(&[]),
 			delete_counter: meter
 				.u64_counter("block.delete_counter")
 				.with_description("Number of blocks deleted")
@@ -122,6 +181,22 @@ impl BlockManagerMetrics {
 				.with_description("Data corruptions detected on block reads")
 				.init()
 				.bind(&[]),
+
+			scrub_blocks_checked: meter
+				.u64_counter("block.scrub_blocks_checked")
+				.with_description("Number of blocks checked by the scrub worker")
+				.init()
+				.bind(&[]),
+			scrub_bytes_read: meter
+				.u64_counter("block.scrub_bytes_read")
+				.with_description("Number of bytes read from disk by the scrub worker")
+				.init()
+				.bind(&[]),
+			scrub_errors: meter
+				.u64_counter("block.scrub_errors")
+				.with_description("Number of blocks that could not be verified by the scrub worker")
+				.init()
+				.bind(&[]),
 		}
 	}
 }
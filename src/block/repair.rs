@@ -1,10 +1,14 @@
 use core::ops::Bound;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use arc_swap::ArcSwapOption;
 use async_trait::async_trait;
+use futures::future::join_all;
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 use tokio::fs;
 use tokio::select;
 use tokio::sync::mpsc;
@@ -24,6 +28,9 @@ const SCRUB_INTERVAL: Duration = Duration::from_secs(3600 * 24 * 25);
 // Scrub tranquility is initially set to 4, but can be changed in the CLI
 // and the updated version is persisted over Garage restarts
 const INITIAL_SCRUB_TRANQUILITY: u32 = 4;
+// Interval at which the position of an ongoing scrub is saved,
+// so that it can be resumed if Garage is restarted
+const SCRUB_CHECKPOINT_INTERVAL: Duration = Duration::from_secs(60);
 
 // ---- ---- ----
 // FIRST KIND OF REPAIR: FINDING MISSING BLOCKS/USELESS BLOCKS
@@ -68,6 +75,7 @@ impl Worker for RepairWorker {
 				};
 				WorkerStatus {
 					progress: Some("0.00%".into()),
+					progress_percent: Some(0.),
 					freeform: vec![format!(
 						"Currently in phase 1, iterator position: {}",
 						hex::encode(idx_bytes)
@@ -77,6 +85,7 @@ impl Worker for RepairWorker {
 			}
 			Some(bi) => WorkerStatus {
 				progress: Some(format!("{:.2}%", bi.progress() * 100.)),
+				progress_percent: Some(bi.progress() * 100.),
 				freeform: vec!["Currently in phase 2".into()],
 				..Default::default()
 			},
@@ -204,7 +213,54 @@ mod v082 {
 	}
 }
 
-pub use v082::*;
+mod v090 {
+	use garage_util::data::Hash;
+	use serde::{Deserialize, Serialize};
+
+	use super::v082;
+
+	#[derive(Serialize, Deserialize)]
+	pub struct ScrubWorkerPersisted {
+		pub tranquility: u32,
+		/// Maximum speed at which block files are read, in MB/s (0 = unlimited)
+		pub speed_mb_per_sec: u32,
+		pub(crate) time_last_complete_scrub: u64,
+		pub(crate) time_next_run_scrub: u64,
+		pub(crate) corruptions_detected: u64,
+		/// Position and progress of the ongoing scrub, if any
+		pub(crate) checkpoint: Option<ScrubCheckpoint>,
+	}
+
+	#[derive(Serialize, Deserialize, Clone, Debug)]
+	pub struct ScrubCheckpoint {
+		/// Hash of the last block that was checked
+		pub(crate) last_hash: Hash,
+		pub(crate) blocks_checked: u64,
+		pub(crate) bytes_scanned: u64,
+		pub(crate) bytes_total: u64,
+		pub(crate) errors_found: u64,
+		/// Time spent scrubbing (excluding pauses), in msec
+		pub(crate) time_spent: u64,
+	}
+
+	impl garage_util::migrate::Migrate for ScrubWorkerPersisted {
+		type Previous = v082::ScrubWorkerPersisted;
+		const VERSION_MARKER: &'static [u8] = b"G090bswp";
+
+		fn migrate(old: v082::ScrubWorkerPersisted) -> ScrubWorkerPersisted {
+			ScrubWorkerPersisted {
+				tranquility: old.tranquility,
+				speed_mb_per_sec: 0,
+				time_last_complete_scrub: old.time_last_complete_scrub,
+				time_next_run_scrub: old.time_next_run_scrub,
+				corruptions_detected: old.corruptions_detected,
+				checkpoint: None,
+			}
+		}
+	}
+}
+
+pub use v090::*;
 
 pub struct ScrubWorker {
 	manager: Arc<BlockManager>,
@@ -212,6 +268,7 @@ pub struct ScrubWorker {
 
 	work: ScrubWorkerState,
 	tranquilizer: Tranquilizer,
+	last_checkpoint: Instant,
 
 	persister: PersisterShared<ScrubWorkerPersisted>,
 }
@@ -234,11 +291,198 @@ impl Default for ScrubWorkerPersisted {
 			time_last_complete_scrub: 0,
 			time_next_run_scrub: randomize_next_scrub_run_time(now_msec()),
 			tranquility: INITIAL_SCRUB_TRANQUILITY,
+			speed_mb_per_sec: 0,
 			corruptions_detected: 0,
+			checkpoint: None,
+		}
+	}
+}
+
+/// State of the scrub worker, as reported by `BlockManager::scrub_stats`
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ScrubState {
+	Finished,
+	Running,
+	Paused,
+}
+
+impl std::fmt::Display for ScrubState {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			ScrubState::Finished => write!(f, "finished"),
+			ScrubState::Running => write!(f, "running"),
+			ScrubState::Paused => write!(f, "paused"),
 		}
 	}
 }
 
+/// Progress of the current (or last) scrub pass
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ScrubStats {
+	pub state: ScrubState,
+	#[serde(default)]
+	pub blocks_checked: u64,
+	pub bytes_scanned: u64,
+	/// Total size of the block files, computed when the scrub started
+	pub bytes_total: u64,
+	pub errors_found: u64,
+	pub last_error: Option<String>,
+	/// Estimated time until the end of the scrub, at the current speed
+	pub eta: Option<Duration>,
+}
+
+/// Progress of the scrub, updated by the scrub worker and read by
+/// `BlockManager::scrub_stats` without having to lock anything
+#[derive(Default)]
+pub(crate) struct ScrubProgress {
+	state: AtomicU8,
+	blocks_checked: AtomicU64,
+	bytes_scanned: AtomicU64,
+	bytes_total: AtomicU64,
+	errors_found: AtomicU64,
+	// Time at which the scrub started, moved forward by the duration
+	// of the pauses so that it can be used to compute the scrub speed
+	started_at: AtomicU64,
+	paused_at: AtomicU64,
+	last_error: ArcSwapOption<String>,
+}
+
+impl ScrubProgress {
+	fn set_state(&self, state: ScrubState) {
+		self.state.store(state as u8, Ordering::Relaxed);
+	}
+
+	fn start(&self, bytes_total: u64) {
+		self.blocks_checked.store(0, Ordering::Relaxed);
+		self.bytes_scanned.store(0, Ordering::Relaxed);
+		self.bytes_total.store(bytes_total, Ordering::Relaxed);
+		self.errors_found.store(0, Ordering::Relaxed);
+		self.started_at.store(now_msec(), Ordering::Relaxed);
+		self.last_error.store(None);
+		self.set_state(ScrubState::Running);
+	}
+
+	fn restore(&self, checkpoint: &ScrubCheckpoint) {
+		self.blocks_checked
+			.store(checkpoint.blocks_checked, Ordering::Relaxed);
+		self.bytes_scanned
+			.store(checkpoint.bytes_scanned, Ordering::Relaxed);
+		self.bytes_total
+			.store(checkpoint.bytes_total, Ordering::Relaxed);
+		self.errors_found
+			.store(checkpoint.errors_found, Ordering::Relaxed);
+		self.started_at.store(
+			now_msec().saturating_sub(checkpoint.time_spent),
+			Ordering::Relaxed,
+		);
+		self.last_error.store(None);
+		self.set_state(ScrubState::Running);
+	}
+
+	fn checkpoint(&self, last_hash: Hash) -> ScrubCheckpoint {
+		ScrubCheckpoint {
+			last_hash,
+			blocks_checked: self.blocks_checked.load(Ordering::Relaxed),
+			bytes_scanned: self.bytes_scanned.load(Ordering::Relaxed),
+			bytes_total: self.bytes_total.load(Ordering::Relaxed),
+			errors_found: self.errors_found.load(Ordering::Relaxed),
+			time_spent: now_msec().saturating_sub(self.started_at.load(Ordering::Relaxed)),
+		}
+	}
+
+	fn pause(&self) {
+		if self.state() == ScrubState::Running {
+			self.paused_at.store(now_msec(), Ordering::Relaxed);
+		}
+		self.set_state(ScrubState::Paused);
+	}
+
+	fn resume(&self) {
+		let paused_for = now_msec().saturating_sub(self.paused_at.load(Ordering::Relaxed));
+		self.started_at.fetch_add(paused_for, Ordering::Relaxed);
+		self.set_state(ScrubState::Running);
+	}
+
+	fn add_checked(&self, bytes: u64) {
+		self.blocks_checked.fetch_add(1, Ordering::Relaxed);
+		self.bytes_scanned.fetch_add(bytes, Ordering::Relaxed);
+	}
+
+	fn add_error(&self, error: String) {
+		self.errors_found.fetch_add(1, Ordering::Relaxed);
+		self.last_error.store(Some(Arc::new(error)));
+	}
+
+	fn state(&self) -> ScrubState {
+		match self.state.load(Ordering::Relaxed) {
+			x if x == ScrubState::Running as u8 => ScrubState::Running,
+			x if x == ScrubState::Paused as u8 => ScrubState::Paused,
+			_ => ScrubState::Finished,
+		}
+	}
+
+	pub(crate) fn stats(&self) -> ScrubStats {
+		let state = self.state();
+		let bytes_scanned = self.bytes_scanned.load(Ordering::Relaxed);
+		let bytes_total = self.bytes_total.load(Ordering::Relaxed);
+
+		let eta = match state {
+			ScrubState::Running if bytes_scanned > 0 => {
+				let elapsed =
+					now_msec().saturating_sub(self.started_at.load(Ordering::Relaxed)) as f64;
+				let remaining = bytes_total.saturating_sub(bytes_scanned) as f64;
+				Some(Duration::from_millis(
+					(elapsed * remaining / bytes_scanned as f64) as u64,
+				))
+			}
+			_ => None,
+		};
+
+		ScrubStats {
+			state,
+			blocks_checked: self.blocks_checked.load(Ordering::Relaxed),
+			bytes_scanned,
+			bytes_total,
+			errors_found: self.errors_found.load(Ordering::Relaxed),
+			last_error: self.last_error.load().as_ref().map(|e| e.to_string()),
+			eta,
+		}
+	}
+}
+
+/// Total size of the block files in the data directories
+async fn block_files_size(data_dirs: Vec<PathBuf>) -> Result<u64, Error> {
+	let mut total = 0;
+	let mut dirs = data_dirs;
+	while let Some(dir) = dirs.pop() {
+		let mut reader = fs::read_dir(&dir).await?;
+		while let Some(ent) = reader.next_entry().await? {
+			let name = match ent.file_name().into_string() {
+				Ok(n) => n,
+				Err(_) => continue,
+			};
+			let name = name.strip_suffix(".zst").unwrap_or(&name);
+			let ent_type = ent.file_type().await?;
+			if name.len() == 2 && hex::decode(name).is_ok() && ent_type.is_dir() {
+				dirs.push(ent.path());
+			} else if name.len() == 64 && hex::decode(name).is_ok() && ent_type.is_file() {
+				total += ent.metadata().await?.len();
+			}
+		}
+	}
+	Ok(total)
+}
+
+/// Minimum duration of the scrub of a block of `bytes` bytes,
+/// so that block files are not read faster than `speed_mb_per_sec`
+fn scrub_speed_delay(bytes: u64, speed_mb_per_sec: u32) -> Duration {
+	match speed_mb_per_sec {
+		0 => Duration::ZERO,
+		speed => Duration::from_secs_f64(bytes as f64 / (speed as f64 * 1_000_000.)),
+	}
+}
+
 #[derive(Default)]
 enum ScrubWorkerState {
 	Running(BlockStoreIterator),
@@ -261,11 +505,26 @@ impl ScrubWorker {
 		rx_cmd: mpsc::Receiver<ScrubWorkerCommand>,
 		persister: PersisterShared<ScrubWorkerPersisted>,
 	) -> Self {
+		let work = match persister.get_with(|p| p.checkpoint.clone()) {
+			Some(checkpoint) => {
+				info!(
+					"Resuming interrupted datastore scrub after block {:?}",
+					checkpoint.last_hash
+				);
+				manager.scrub_progress.restore(&checkpoint);
+				ScrubWorkerState::Running(BlockStoreIterator::new_after(
+					&manager,
+					checkpoint.last_hash,
+				))
+			}
+			None => ScrubWorkerState::Finished,
+		};
 		Self {
 			manager,
 			rx_cmd,
-			work: ScrubWorkerState::Finished,
+			work,
 			tranquilizer: Tranquilizer::new(30),
+			last_checkpoint: Instant::now(),
 			persister,
 		}
 	}
@@ -276,6 +535,20 @@ impl ScrubWorker {
 				self.work = match std::mem::take(&mut self.work) {
 					ScrubWorkerState::Finished => {
 						info!("Scrub worker initializing, now performing datastore scrub");
+						let bytes_total = match block_files_size(data_dir_paths(&self.manager))
+							.await
+						{
+							Ok(size) => size,
+							Err(e) => {
+								warn!("Could not compute the size of the data directories: {}", e);
+								0
+							}
+						};
+						self.manager.scrub_progress.start(bytes_total);
+						if let Err(e) = self.persister.set_with(|p| p.checkpoint = None) {
+							error!("Could not save scrub worker state: {}", e);
+						}
+						self.last_checkpoint = Instant::now();
 						let iterator = BlockStoreIterator::new(&self.manager);
 						ScrubWorkerState::Running(iterator)
 					}
@@ -288,6 +561,7 @@ impl ScrubWorker {
 			ScrubWorkerCommand::Pause(dur) => {
 				self.work = match std::mem::take(&mut self.work) {
 					ScrubWorkerState::Running(it) | ScrubWorkerState::Paused(it, _) => {
+						self.manager.scrub_progress.pause();
 						ScrubWorkerState::Paused(it, now_msec() + dur.as_millis() as u64)
 					}
 					work => {
@@ -298,7 +572,10 @@ impl ScrubWorker {
 			}
 			ScrubWorkerCommand::Resume => {
 				self.work = match std::mem::take(&mut self.work) {
-					ScrubWorkerState::Paused(it, _) => ScrubWorkerState::Running(it),
+					ScrubWorkerState::Paused(it, _) => {
+						self.manager.scrub_progress.resume();
+						ScrubWorkerState::Running(it)
+					}
 					work => {
 						error!("Cannot resume scrub worker: not paused!");
 						work
@@ -308,6 +585,10 @@ impl ScrubWorker {
 			ScrubWorkerCommand::Cancel => {
 				self.work = match std::mem::take(&mut self.work) {
 					ScrubWorkerState::Running(_) | ScrubWorkerState::Paused(_, _) => {
+						self.manager.scrub_progress.set_state(ScrubState::Finished);
+						if let Err(e) = self.persister.set_with(|p| p.checkpoint = None) {
+							error!("Could not save scrub worker state: {}", e);
+						}
 						ScrubWorkerState::Finished
 					}
 					work => {
@@ -345,9 +626,11 @@ impl Worker for ScrubWorker {
 		match &self.work {
 			ScrubWorkerState::Running(bsi) => {
 				s.progress = Some(format!("{:.2}%", bsi.progress() * 100.));
+				s.progress_percent = Some(bsi.progress() * 100.);
 			}
 			ScrubWorkerState::Paused(bsi, rt) => {
 				s.progress = Some(format!("{:.2}%", bsi.progress() * 100.));
+				s.progress_percent = Some(bsi.progress() * 100.);
 				s.freeform = vec![format!("Scrub paused, resumes at {}", msec_to_rfc3339(*rt))];
 			}
 			ScrubWorkerState::Finished => {
@@ -376,18 +659,59 @@ impl Worker for ScrubWorker {
 		match &mut self.work {
 			ScrubWorkerState::Running(bsi) => {
 				self.tranquilizer.reset();
+				let step_begin = Instant::now();
 				if let Some(hash) = bsi.next().await? {
-					match self.manager.read_block(&hash).await {
+					let metrics = &self.manager.metrics;
+					// Reading the block verifies its checksum. A corrupted block is moved
+					// out of the way and queued for resync, so that it is fetched again
+					// from the other nodes storing it.
+					let bytes_read = match self.manager.read_block(&hash).await {
 						Err(Error::CorruptData(_)) => {
 							error!("Found corrupt data block during scrub: {:?}", hash);
+							metrics.scrub_errors.add(1);
 							self.persister.set_with(|p| p.corruptions_detected += 1)?;
+							self.manager
+								.scrub_progress
+								.add_error(format!("Corrupt data block {:?}", hash));
+							0
+						}
+						Err(e) => {
+							metrics.scrub_errors.add(1);
+							self.manager
+								.scrub_progress
+								.add_error(format!("Could not read block {:?}: {}", hash, e));
+							return Err(e);
+						}
+						Ok(data) => {
+							let len = data.inner_buffer().len() as u64;
+							self.manager.scrub_progress.add_checked(len);
+							len
 						}
-						Err(e) => return Err(e),
-						_ => (),
 					};
-					Ok(self
-						.tranquilizer
-						.tranquilize_worker(self.persister.get_with(|p| p.tranquility)))
+					metrics.scrub_blocks_checked.add(1);
+					metrics.scrub_bytes_read.add(bytes_read);
+
+					if self.last_checkpoint.elapsed() >= SCRUB_CHECKPOINT_INTERVAL {
+						let checkpoint = self.manager.scrub_progress.checkpoint(hash);
+						self.persister
+							.set_with(|p| p.checkpoint = Some(checkpoint))?;
+						self.last_checkpoint = Instant::now();
+					}
+
+					let (tranquility, speed_mb_per_sec) = self
+						.persister
+						.get_with(|p| (p.tranquility, p.speed_mb_per_sec));
+					let speed_delay = scrub_speed_delay(bytes_read, speed_mb_per_sec)
+						.saturating_sub(step_begin.elapsed());
+					Ok(match self.tranquilizer.tranquilize_worker(tranquility) {
+						WorkerState::Throttled(delay) => {
+							WorkerState::Throttled(delay.max(speed_delay.as_secs_f32()))
+						}
+						_ if speed_delay > Duration::ZERO => {
+							WorkerState::Throttled(speed_delay.as_secs_f32())
+						}
+						state => state,
+					})
 				} else {
 					let now = now_msec();
 					let next_scrub_timestamp = randomize_next_scrub_run_time(now);
@@ -395,12 +719,18 @@ impl Worker for ScrubWorker {
 					self.persister.set_with(|p| {
 						p.time_last_complete_scrub = now;
 						p.time_next_run_scrub = next_scrub_timestamp;
+						p.checkpoint = None;
 					})?;
 					self.work = ScrubWorkerState::Finished;
+					self.manager.scrub_progress.set_state(ScrubState::Finished);
 					self.tranquilizer.clear();
 
+					let stats = self.manager.scrub_progress.stats();
 					info!(
-						"Datastore scrub completed, next scrub scheduled for {}",
+						"Datastore scrub completed: {} blocks checked, {} bytes read, {} errors found. Next scrub scheduled for {}",
+						stats.blocks_checked,
+						stats.bytes_scanned,
+						stats.errors_found,
 						msec_to_rfc3339(next_scrub_timestamp)
 					);
 
@@ -443,30 +773,597 @@ impl Worker for ScrubWorker {
 	}
 }
 
+// ---- ---- ----
+// THIRD KIND OF REPAIR: CHECKING PERMISSIONS OF BLOCK FILES
+// This is a one-shot repair operation that checks that all block files
+// are owned by the user running Garage and have the configured mode,
+// and optionally corrects them.
+// ---- ---- ----
+
+pub struct BlockPermissionsWorker {
+	manager: Arc<BlockManager>,
+	fix: bool,
+	block_iter: BlockStoreIterator,
+	checked: u64,
+	wrong: u64,
+	failed: u64,
+}
+
+impl BlockPermissionsWorker {
+	pub fn new(manager: Arc<BlockManager>, fix: bool) -> Self {
+		let block_iter = BlockStoreIterator::new(&manager);
+		Self {
+			manager,
+			fix,
+			block_iter,
+			checked: 0,
+			wrong: 0,
+			failed: 0,
+		}
+	}
+
+	fn report(&self) -> Vec<String> {
+		let wrong = if self.fix {
+			format!("Files corrected: {}", self.wrong)
+		} else {
+			format!("Files with wrong permissions: {}", self.wrong)
+		};
+		vec![
+			format!("Files checked: {}", self.checked),
+			wrong,
+			format!("Files failed: {}", self.failed),
+		]
+	}
+}
+
+#[async_trait]
+impl Worker for BlockPermissionsWorker {
+	fn name(&self) -> String {
+		"Block permissions repair worker".into()
+	}
+
+	fn status(&self) -> WorkerStatus {
+		WorkerStatus {
+			progress: Some(format!("{:.2}%", self.block_iter.progress() * 100.)),
+			progress_percent: Some(self.block_iter.progress() * 100.),
+			persistent_errors: Some(self.failed),
+			freeform: self.report(),
+			..Default::default()
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		if let Some(hash) = self.block_iter.next().await? {
+			self.checked += 1;
+			match self.manager.check_block_permissions(&hash, self.fix).await {
+				Ok(true) => self.wrong += 1,
+				Ok(false) => (),
+				Err(e) => {
+					warn!("Could not check permissions of block {:?}: {}", hash, e);
+					self.failed += 1;
+				}
+			}
+			Ok(WorkerState::Busy)
+		} else {
+			info!(
+				"Block permissions repair finished: {}",
+				self.report().join(", ")
+			);
+			Ok(WorkerState::Done)
+		}
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		unreachable!()
+	}
+}
+
+// ---- ---- ----
+// FOURTH KIND OF REPAIR: CHECKING HASHES OF BLOCK FILES
+// This is a one-shot verification that reads all block files and checks
+// that their content matches the hash encoded in their file name.
+// Corrupted blocks are only reported, they are not moved or resynced.
+// ---- ---- ----
+
+pub struct BlockHashCheckWorker {
+	manager: Arc<BlockManager>,
+	rehash_all: bool,
+	threads: usize,
+	report_file: PathBuf,
+	block_iter: BlockStoreIterator,
+	report: BlockHashCheckReport,
+}
+
+#[derive(Serialize, Default)]
+struct BlockHashCheckReport {
+	total: u64,
+	valid: u64,
+	hash_mismatch: u64,
+	unreadable: u64,
+	mismatched_files: Vec<PathBuf>,
+	unreadable_blocks: Vec<(String, String)>,
+}
+
+impl BlockHashCheckWorker {
+	pub fn new(
+		manager: Arc<BlockManager>,
+		rehash_all: bool,
+		threads: usize,
+		report_file: Option<PathBuf>,
+	) -> Self {
+		let block_iter = BlockStoreIterator::new(&manager);
+		let report_file = report_file.unwrap_or_else(|| {
+			manager.data_layout.dirs()[0]
+				.path
+				.join("block-hashes-report.json")
+		});
+		Self {
+			manager,
+			rehash_all,
+			threads: threads.max(1),
+			report_file,
+			block_iter,
+			report: BlockHashCheckReport::default(),
+		}
+	}
+
+	fn summary(&self) -> Vec<String> {
+		vec![
+			format!("Files checked: {}", self.report.total),
+			format!("Valid: {}", self.report.valid),
+			format!("Hash mismatch: {}", self.report.hash_mismatch),
+			format!("Unreadable: {}", self.report.unreadable),
+		]
+	}
+}
+
+#[async_trait]
+impl Worker for BlockHashCheckWorker {
+	fn name(&self) -> String {
+		"Block hash check worker".into()
+	}
+
+	fn status(&self) -> WorkerStatus {
+		WorkerStatus {
+			progress: Some(format!("{:.2}%", self.block_iter.progress() * 100.)),
+			progress_percent: Some(self.block_iter.progress() * 100.),
+			persistent_errors: Some(self.report.hash_mismatch + self.report.unreadable),
+			freeform: self.summary(),
+			..Default::default()
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		let mut hashes = Vec::with_capacity(self.threads);
+		while hashes.len() < self.threads {
+			match self.block_iter.next().await? {
+				Some(hash) => hashes.push(hash),
+				None => break,
+			}
+		}
+
+		if hashes.is_empty() {
+			let report = serde_json::to_vec_pretty(&self.report)
+				.ok_or_message("Unable to serialize report")?;
+			fs::write(&self.report_file, report).await?;
+			info!(
+				"Block hash check finished: {}. Report written to {}",
+				self.summary().join(", "),
+				self.report_file.display()
+			);
+			return Ok(WorkerState::Done);
+		}
+
+		let results = join_all(
+			hashes
+				.iter()
+				.map(|hash| self.manager.check_block_hash(hash, self.rehash_all)),
+		)
+		.await;
+		for (hash, res) in hashes.iter().zip(results) {
+			self.report.total += 1;
+			match res {
+				Ok((_, true)) => self.report.valid += 1,
+				Ok((path, false)) => {
+					error!(
+						"Block {:?} does not match its hash: {}",
+						hash,
+						path.display()
+					);
+					self.report.hash_mismatch += 1;
+					self.report.mismatched_files.push(path);
+				}
+				Err(e) => {
+					warn!("Could not read block {:?}: {}", hash, e);
+					self.report.unreadable += 1;
+					self.report
+						.unreadable_blocks
+						.push((hex::encode(hash), e.to_string()));
+				}
+			}
+		}
+		Ok(WorkerState::Busy)
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		unreachable!()
+	}
+}
+
+// ---- ---- ----
+// FIFTH KIND OF REPAIR: REBUILDING THE BLOCK DIRECTORY STRUCTURE
+// This is a one-shot disaster recovery operation that looks for block files
+// anywhere under a given directory (e.g. after the data directory was flattened),
+// checks their content and moves them to their expected location.
+// ---- ---- ----
+
+pub struct RebuildBlockIndexWorker {
+	manager: Arc<BlockManager>,
+	scan_dir: PathBuf,
+	pending_dirs: Vec<PathBuf>,
+	pending_files: Vec<(PathBuf, Hash, bool)>,
+	found: u64,
+	verified: u64,
+	moved: u64,
+	failed: u64,
+}
+
+impl RebuildBlockIndexWorker {
+	pub fn new(manager: Arc<BlockManager>, scan_dir: PathBuf) -> Self {
+		Self {
+			manager,
+			pending_dirs: vec![scan_dir.clone()],
+			scan_dir,
+			pending_files: vec![],
+			found: 0,
+			verified: 0,
+			moved: 0,
+			failed: 0,
+		}
+	}
+
+	fn report(&self) -> Vec<String> {
+		vec![
+			format!("Files found: {}", self.found),
+			format!("Verified: {}", self.verified),
+			format!("Moved: {}", self.moved),
+			format!("Failed: {}", self.failed),
+		]
+	}
+
+	/// Returns the next file under the scanned directory whose name is a block hash
+	async fn next_file(&mut self) -> Result<Option<(PathBuf, Hash, bool)>, Error> {
+		loop {
+			if let Some(file) = self.pending_files.pop() {
+				return Ok(Some(file));
+			}
+			let dir = match self.pending_dirs.pop() {
+				Some(dir) => dir,
+				None => return Ok(None),
+			};
+
+			let mut reader = fs::read_dir(&dir).await?;
+			while let Some(ent) = reader.next_entry().await? {
+				let ent_type = ent.file_type().await?;
+				if ent_type.is_dir() {
+					self.pending_dirs.push(ent.path());
+					continue;
+				} else if !ent_type.is_file() {
+					continue;
+				}
+
+				let name = match ent.file_name().into_string() {
+					Ok(n) => n,
+					Err(_) => continue,
+				};
+				let (name, compressed) = match name.strip_suffix(".zst") {
+					Some(n) => (n, true),
+					None => (&name[..], false),
+				};
+				if name.len() != 64 {
+					continue;
+				}
+				if let Ok(h) = hex::decode(name) {
+					let mut hash = [0u8; 32];
+					hash.copy_from_slice(&h);
+					self.pending_files
+						.push((ent.path(), hash.into(), compressed));
+				}
+			}
+		}
+	}
+}
+
+#[async_trait]
+impl Worker for RebuildBlockIndexWorker {
+	fn name(&self) -> String {
+		"Block index rebuild worker".into()
+	}
+
+	fn status(&self) -> WorkerStatus {
+		WorkerStatus {
+			progress: Some(self.found.to_string()),
+			persistent_errors: Some(self.failed),
+			freeform: self.report(),
+			..Default::default()
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		let (path, hash, compressed) = match self.next_file().await? {
+			Some(file) => file,
+			None => {
+				info!(
+					"Block index rebuild from {} finished: {}",
+					self.scan_dir.display(),
+					self.report().join(", ")
+				);
+				return Ok(WorkerState::Done);
+			}
+		};
+
+		self.found += 1;
+		match self
+			.manager
+			.import_block_file(&hash, &path, compressed)
+			.await
+		{
+			Ok(moved) => {
+				self.verified += 1;
+				if moved {
+					self.moved += 1;
+				}
+			}
+			Err(e) => {
+				warn!("Could not import block file {}: {}", path.display(), e);
+				self.failed += 1;
+			}
+		}
+		Ok(WorkerState::Busy)
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		unreachable!()
+	}
+}
+
+// ---- ---- ----
+// SIXTH KIND OF REPAIR: RE-KEYING BLOCK FILES
+// This is a one-shot repair operation that encrypts all block files with
+// the current encryption key, i.e. block files that were written before
+// encryption was enabled and block files encrypted with a previous key.
+// ---- ---- ----
+
+pub struct BlockRekeyWorker {
+	manager: Arc<BlockManager>,
+	block_iter: BlockStoreIterator,
+	checked: u64,
+	rekeyed: u64,
+	failed: u64,
+}
+
+impl BlockRekeyWorker {
+	pub fn new(manager: Arc<BlockManager>) -> Result<Self, Error> {
+		if manager.encryption.is_none() {
+			return Err(Error::Message(
+				"No encryption_key is configured, cannot re-key block files".into(),
+			));
+		}
+		let block_iter = BlockStoreIterator::new(&manager);
+		Ok(Self {
+			manager,
+			block_iter,
+			checked: 0,
+			rekeyed: 0,
+			failed: 0,
+		})
+	}
+
+	fn report(&self) -> Vec<String> {
+		vec![
+			format!("Files checked: {}", self.checked),
+			format!("Files re-keyed: {}", self.rekeyed),
+			format!("Files failed: {}", self.failed),
+		]
+	}
+}
+
+#[async_trait]
+impl Worker for BlockRekeyWorker {
+	fn name(&self) -> String {
+		"Block re-keying worker".into()
+	}
+
+	fn status(&self) -> WorkerStatus {
+		WorkerStatus {
+			progress: Some(format!("{:.2}%", self.block_iter.progress() * 100.)),
+			progress_percent: Some(self.block_iter.progress() * 100.),
+			persistent_errors: Some(self.failed),
+			freeform: self.report(),
+			..Default::default()
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		if let Some(hash) = self.block_iter.next().await? {
+			self.checked += 1;
+			match self.manager.rekey_block(&hash).await {
+				Ok(true) => self.rekeyed += 1,
+				Ok(false) => (),
+				Err(e) => {
+					warn!("Could not re-key block {:?}: {}", hash, e);
+					self.failed += 1;
+				}
+			}
+			Ok(WorkerState::Busy)
+		} else {
+			info!("Block re-keying finished: {}", self.report().join(", "));
+			Ok(WorkerState::Done)
+		}
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		unreachable!()
+	}
+}
+
+// ---- ---- ----
+// SEVENTH KIND OF REPAIR: REBALANCING BLOCK FILES BETWEEN DATA DIRECTORIES
+// This is a one-shot repair operation that moves block files out of read-only
+// data directories, e.g. to migrate from a single data directory to several,
+// and with the consistent-hash placement, to the directory given by their hash.
+// ---- ---- ----
+
+pub struct BlockRebalanceWorker {
+	manager: Arc<BlockManager>,
+	block_iter: BlockStoreIterator,
+	checked: u64,
+	moved: u64,
+	failed: u64,
+}
+
+impl BlockRebalanceWorker {
+	pub fn new(manager: Arc<BlockManager>) -> Self {
+		let block_iter = BlockStoreIterator::new(&manager);
+		Self {
+			manager,
+			block_iter,
+			checked: 0,
+			moved: 0,
+			failed: 0,
+		}
+	}
+
+	fn report(&self) -> Vec<String> {
+		vec![
+			format!("Files checked: {}", self.checked),
+			format!("Files moved: {}", self.moved),
+			format!("Files failed: {}", self.failed),
+		]
+	}
+}
+
+#[async_trait]
+impl Worker for BlockRebalanceWorker {
+	fn name(&self) -> String {
+		"Block rebalance worker".into()
+	}
+
+	fn status(&self) -> WorkerStatus {
+		WorkerStatus {
+			progress: Some(format!("{:.2}%", self.block_iter.progress() * 100.)),
+			progress_percent: Some(self.block_iter.progress() * 100.),
+			persistent_errors: Some(self.failed),
+			freeform: self.report(),
+			..Default::default()
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		if let Some(hash) = self.block_iter.next().await? {
+			self.checked += 1;
+			match self.manager.rebalance_block(&hash).await {
+				Ok(true) => self.moved += 1,
+				Ok(false) => (),
+				Err(e) => {
+					warn!("Could not move block {:?}: {}", hash, e);
+					self.failed += 1;
+				}
+			}
+			Ok(WorkerState::Busy)
+		} else {
+			info!("Block rebalance finished: {}", self.report().join(", "));
+			Ok(WorkerState::Done)
+		}
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		unreachable!()
+	}
+}
+
 // ---- ---- ----
 // UTILITY FOR ENUMERATING THE BLOCK STORE
 // ---- ---- ----
 
-struct BlockStoreIterator {
+/// Paths of the data directories of a block manager
+fn data_dir_paths(manager: &BlockManager) -> Vec<PathBuf> {
+	manager
+		.data_layout
+		.dirs()
+		.iter()
+		.map(|d| d.path.clone())
+		.collect()
+}
+
+/// Enumerates the hashes of all blocks that have a file in the data directories,
+/// in the order of their hashes. The data directories are read in parallel,
+/// so that a block is returned only once even if it has a file in several of them.
+pub struct BlockStoreIterator {
 	path: Vec<ReadingDir>,
+	/// Hex-encoded hash of the last block of a previous scan, when resuming it:
+	/// all blocks up to this one are skipped
+	start_after: Option<String>,
 }
 
 enum ReadingDir {
-	Pending(PathBuf),
+	/// The same subdirectory in each of the data directories
+	Pending(Vec<PathBuf>),
 	Read {
-		subpaths: Vec<fs::DirEntry>,
+		subpaths: Vec<(String, StoreEntry)>,
 		pos: usize,
 	},
 }
 
+enum StoreEntry {
+	Dir(Vec<PathBuf>),
+	Block,
+}
+
 impl BlockStoreIterator {
-	fn new(manager: &BlockManager) -> Self {
-		let root_dir = manager.data_dir.clone();
+	pub fn new(manager: &BlockManager) -> Self {
+		Self::from_dirs(data_dir_paths(manager), None)
+	}
+
+	/// Iterate over the blocks that come after `hash`, in the order in which
+	/// they are returned by this iterator
+	pub fn new_after(manager: &BlockManager, hash: Hash) -> Self {
+		Self::from_dirs(data_dir_paths(manager), Some(hash))
+	}
+
+	fn from_dirs(root_dirs: Vec<PathBuf>, start_after: Option<Hash>) -> Self {
 		Self {
-			path: vec![ReadingDir::Pending(root_dir)],
+			path: vec![ReadingDir::Pending(root_dirs)],
+			start_after: start_after.map(hex::encode),
 		}
 	}
 
+	/// When resuming a scan, returns true if the entry called `name` in the
+	/// directory currently being read contains only blocks that were already seen
+	fn skip_entry(&mut self, name: &str, is_dir: bool) -> bool {
+		let start_after = match &self.start_after {
+			None => return false,
+			Some(h) => h,
+		};
+		let skip = if is_dir {
+			// Directories are named after the bytes of the hashes
+			// of the blocks they contain, one byte per level
+			let depth = self.path.len() - 1;
+			match start_after.get(2 * depth..2 * depth + 2) {
+				Some(prefix) if name == prefix => return false,
+				Some(prefix) => name < prefix,
+				None => false,
+			}
+		} else {
+			name <= start_after.as_str()
+		};
+		if !skip {
+			// Entries are sorted, all the next ones come after the resume point
+			self.start_after = None;
+		}
+		skip
+	}
+
 	/// Returns progress done, between 0 and 1
 	fn progress(&self) -> f32 {
 		if self.path.is_empty() {
@@ -487,19 +1384,53 @@ impl BlockStoreIterator {
 		}
 	}
 
-	async fn next(&mut self) -> Result<Option<Hash>, Error> {
+	/// Lists the subdirectories and block files of the same directory
+	/// in each of the data directories, sorted by name
+	async fn read_dirs(paths: &[PathBuf]) -> Result<Vec<(String, StoreEntry)>, Error> {
+		let mut entries = std::collections::BTreeMap::<String, StoreEntry>::new();
+		for path in paths {
+			let mut reader = match fs::read_dir(path).await {
+				Ok(reader) => reader,
+				Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+				Err(e) => return Err(e.into()),
+			};
+			while let Some(ent) = reader.next_entry().await? {
+				let name = match ent.file_name().into_string() {
+					Ok(n) => n,
+					Err(_) => continue,
+				};
+				let name = match name.strip_suffix(".zst") {
+					Some(n) => n.to_string(),
+					None => name,
+				};
+				if hex::decode(&name).is_err() {
+					continue;
+				}
+				let ent_type = ent.file_type().await?;
+				if name.len() == 2 && ent_type.is_dir() {
+					if let StoreEntry::Dir(dirs) = entries
+						.entry(name)
+						.or_insert_with(|| StoreEntry::Dir(vec![]))
+					{
+						dirs.push(ent.path());
+					}
+				} else if name.len() == 64 {
+					entries.insert(name, StoreEntry::Block);
+				}
+			}
+		}
+		Ok(entries.into_iter().collect())
+	}
+
+	pub async fn next(&mut self) -> Result<Option<Hash>, Error> {
 		loop {
 			let last_path = match self.path.last_mut() {
 				None => return Ok(None),
 				Some(lp) => lp,
 			};
 
-			if let ReadingDir::Pending(path) = last_path {
-				let mut reader = fs::read_dir(&path).await?;
-				let mut subpaths = vec![];
-				while let Some(ent) = reader.next_entry().await? {
-					subpaths.push(ent);
-				}
+			if let ReadingDir::Pending(paths) = last_path {
+				let subpaths = Self::read_dirs(paths).await?;
 				*last_path = ReadingDir::Read { subpaths, pos: 0 };
 			}
 
@@ -511,7 +1442,7 @@ impl BlockStoreIterator {
 				ReadingDir::Pending(_) => unreachable!(),
 			};
 
-			let data_dir_ent = match subpaths.get(*pos) {
+			let (name, entry) = match subpaths.get(*pos) {
 				None => {
 					self.path.pop();
 					continue;
@@ -521,26 +1452,128 @@ impl BlockStoreIterator {
 					ent
 				}
 			};
+			let name = name.clone();
 
-			let name = data_dir_ent.file_name();
-			let name = if let Ok(n) = name.into_string() {
-				n
-			} else {
-				continue;
-			};
-			let ent_type = data_dir_ent.file_type().await?;
-
-			let name = name.strip_suffix(".zst").unwrap_or(&name);
-			if name.len() == 2 && hex::decode(name).is_ok() && ent_type.is_dir() {
-				let path = data_dir_ent.path();
-				self.path.push(ReadingDir::Pending(path));
-			} else if name.len() == 64 {
-				if let Ok(h) = hex::decode(name) {
+			match entry {
+				StoreEntry::Dir(paths) => {
+					let paths = paths.clone();
+					if !self.skip_entry(&name, true) {
+						self.path.push(ReadingDir::Pending(paths));
+					}
+				}
+				StoreEntry::Block => {
+					if self.skip_entry(&name, false) {
+						continue;
+					}
 					let mut hash = [0u8; 32];
-					hash.copy_from_slice(&h);
+					hash.copy_from_slice(&hex::decode(&name).unwrap());
 					return Ok(Some(hash.into()));
 				}
 			}
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_scrub_speed_delay() {
+		assert_eq!(scrub_speed_delay(1_000_000, 0), Duration::ZERO);
+		assert_eq!(scrub_speed_delay(1_000_000, 1), Duration::from_secs(1));
+		assert_eq!(scrub_speed_delay(1_000_000, 10), Duration::from_millis(100));
+		assert_eq!(scrub_speed_delay(0, 10), Duration::ZERO);
+	}
+
+	#[tokio::test]
+	async fn test_block_store_iterator_resume() {
+		let root = std::env::temp_dir().join(format!("garage-test-{}", hex::encode(gen_uuid())));
+		let mut hashes = vec![];
+		for first in [0x00u8, 0x12, 0xff] {
+			for second in [0x00u8, 0x34] {
+				for last in [0x01u8, 0x02] {
+					let mut h = [0u8; 32];
+					h[0] = first;
+					h[1] = second;
+					h[31] = last;
+					hashes.push(Hash::from(h));
+				}
+			}
+		}
+		for (i, h) in hashes.iter().enumerate() {
+			let dir = root
+				.join(hex::encode(&h.as_slice()[..1]))
+				.join(hex::encode(&h.as_slice()[1..2]));
+			fs::create_dir_all(&dir).await.unwrap();
+			let name = if i % 2 == 0 {
+				hex::encode(h)
+			} else {
+				format!("{}.zst", hex::encode(h))
+			};
+			fs::write(dir.join(name), b"").await.unwrap();
+		}
+
+		let mut all = vec![];
+		let mut it = BlockStoreIterator::from_dirs(vec![root.clone()], None);
+		while let Some(h) = it.next().await.unwrap() {
+			all.push(h);
+		}
+		assert_eq!(all, hashes);
+
+		for i in 0..hashes.len() {
+			let mut rest = vec![];
+			let mut it = BlockStoreIterator::from_dirs(vec![root.clone()], Some(hashes[i]));
+			while let Some(h) = it.next().await.unwrap() {
+				rest.push(h);
+			}
+			assert_eq!(rest, &hashes[i + 1..]);
+		}
+
+		fs::remove_dir_all(&root).await.unwrap();
+	}
+
+	#[tokio::test]
+	async fn test_block_store_iterator_several_dirs() {
+		let root = std::env::temp_dir().join(format!("garage-test-{}", hex::encode(gen_uuid())));
+		let roots = vec![root.join("a"), root.join("b"), root.join("empty")];
+		let mut hashes = vec![];
+		for i in 0..20u8 {
+			let h = blake2sum(&[i]);
+			hashes.push(h);
+			let dir = roots[(i % 2) as usize]
+				.join(hex::encode(&h.as_slice()[..1]))
+				.join(hex::encode(&h.as_slice()[1..2]));
+			fs::create_dir_all(&dir).await.unwrap();
+			fs::write(dir.join(hex::encode(h)), b"").await.unwrap();
+			if i % 5 == 0 {
+				// A copy of the block in the other directory, as left
+				// when a block is being moved between directories
+				let dir = roots[1 - (i % 2) as usize]
+					.join(hex::encode(&h.as_slice()[..1]))
+					.join(hex::encode(&h.as_slice()[1..2]));
+				fs::create_dir_all(&dir).await.unwrap();
+				fs::write(dir.join(format!("{}.zst", hex::encode(h))), b"")
+					.await
+					.unwrap();
+			}
+		}
+		hashes.sort();
+
+		let mut all = vec![];
+		let mut it = BlockStoreIterator::from_dirs(roots.clone(), None);
+		while let Some(h) = it.next().await.unwrap() {
+			all.push(h);
+		}
+		assert_eq!(all, hashes);
+
+		let mut rest = vec![];
+		let mut it = BlockStoreIterator::from_dirs(roots.clone(), Some(hashes[9]));
+		while let Some(h) = it.next().await.unwrap() {
+			rest.push(h);
+		}
+		assert_eq!(rest, &hashes[10..]);
+
+		fs::remove_dir_all(&root).await.unwrap();
+	}
+}
@@ -30,6 +30,8 @@ use garage_rpc::*;
 
 use garage_table::replication::TableReplication;
 
+use crate::block::*;
+use crate::erasure::*;
 use crate::manager::*;
 
 // The delay between the time where a resync operation fails
@@ -420,24 +422,34 @@ impl BlockResyncManager {
 				}
 
 				let block = manager.read_block(hash).await?;
-				let (header, bytes) = block.into_parts();
-				let put_block_message = Req::new(BlockRpc::PutBlock {
-					hash: *hash,
-					header,
-				})?
-				.with_stream_from_buffer(bytes);
-				manager
-					.system
-					.rpc
-					.try_call_many(
-						&manager.endpoint,
-						&need_nodes[..],
-						put_block_message,
-						RequestStrategy::with_priority(PRIO_BACKGROUND)
-							.with_quorum(need_nodes.len()),
-					)
-					.await
-					.err_context("PutBlock RPC")?;
+				if manager.erasure.is_some() {
+					// Each node needs its own shard: we send it to the nodes whose
+					// shard we have (or all of them if we have the whole block),
+					// the others rebuild theirs from the shards of other nodes
+					manager
+						.rpc_put_block_shards(*hash, block, &need_nodes, None, PRIO_BACKGROUND)
+						.await
+						.err_context("PutBlock RPC")?;
+				} else {
+					let (header, bytes) = block.into_parts();
+					let put_block_message = Req::new(BlockRpc::PutBlock {
+						hash: *hash,
+						header,
+					})?
+					.with_stream_from_buffer(bytes);
+					manager
+						.system
+						.rpc
+						.try_call_many(
+							&manager.endpoint,
+							&need_nodes[..],
+							put_block_message,
+							RequestStrategy::with_priority(PRIO_BACKGROUND)
+								.with_quorum(need_nodes.len()),
+						)
+						.await
+						.err_context("PutBlock RPC")?;
+				}
 			}
 			info!(
 				"Deleting unneeded block {:?}, offload finished ({} / {})",
@@ -461,11 +473,63 @@ impl BlockResyncManager {
 
 			manager.metrics.resync_recv_counter.add(1);
 
+			let block_data = manager.local_shard(hash, block_data).await?;
 			manager.write_block(hash, &block_data).await?;
 		}
 
+		if needed.is_nonzero() && exists && manager.erasure.is_some() {
+			self.convert_to_shard(manager, hash).await?;
+		}
+
 		Ok(())
 	}
+
+	/// With erasure coding, replace the whole block stored by this node
+	/// (written before erasure coding was enabled) or the shard that it stored
+	/// at another position in the ring by the shard that it should store.
+	/// The shards of a whole block are first sent to the other nodes, so that
+	/// the block can still be rebuilt once this node only stores its shard.
+	async fn convert_to_shard(&self, manager: &BlockManager, hash: &Hash) -> Result<(), Error> {
+		let index = match manager.local_shard_index(hash) {
+			Some(index) => index,
+			None => return Ok(()),
+		};
+		let block = manager.read_block(hash).await?;
+		let block = match ShardInfo::parse(block.inner_buffer(), hash) {
+			Some(info) if block.is_shard() && info.index == index => return Ok(()),
+			Some(_) if block.is_shard() => {
+				info!(
+					"Resync block {:?}: rebuilding shard {} from other nodes",
+					hash, index
+				);
+				manager.rpc_get_raw_block(hash, None).await?
+			}
+			_ => {
+				info!(
+					"Resync block {:?}: converting whole block to erasure-coded shards",
+					hash
+				);
+				let mut who = manager.replication.write_nodes(hash);
+				who.retain(|id| *id != manager.system.id);
+				let quorum = manager.replication.write_quorum() - 1;
+				let (header, bytes) = block.into_parts();
+				manager
+					.rpc_put_block_shards(
+						*hash,
+						DataBlock::from_parts(header, bytes.clone()),
+						&who,
+						Some(quorum),
+						PRIO_BACKGROUND,
+					)
+					.await
+					.err_context("PutBlock RPC")?;
+				DataBlock::from_parts(header, bytes)
+			}
+		};
+
+		let shard = manager.local_shard(hash, block).await?;
+		manager.write_block(hash, &shard).await
+	}
 }
 
 impl Drop for BusyBlock {
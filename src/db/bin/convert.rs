@@ -57,11 +57,9 @@ fn open_db(path: PathBuf, engine: String) -> Result<Db> {
 
 			let map_size = lmdb_adapter::recommended_map_size();
 
-			let db = lmdb_adapter::heed::EnvOpenOptions::new()
-				.max_dbs(100)
-				.map_size(map_size)
-				.open(&path)
-				.unwrap();
+			let mut env_builder = lmdb_adapter::heed::EnvOpenOptions::new();
+			env_builder.max_dbs(100);
+			let db = lmdb_adapter::ResizableLmdbEnv::open(env_builder, &path, map_size)?;
 			Ok(lmdb_adapter::LmdbDb::init(db))
 		}
 		e => Err(Error(format!("Invalid DB engine: {}", e).into())),
@@ -14,6 +14,8 @@ pub mod sqlite_adapter;
 
 pub mod counted_tree_hack;
 
+mod metrics;
+
 #[cfg(test)]
 pub mod test;
 
@@ -22,8 +24,12 @@ use core::ops::{Bound, RangeBounds};
 use std::borrow::Cow;
 use std::cell::Cell;
 use std::sync::Arc;
+use std::time::Instant;
 
 use err_derive::Error;
+use opentelemetry::KeyValue;
+
+use metrics::DB_METRICS;
 
 #[derive(Clone)]
 pub struct Db(pub(crate) Arc<dyn IDb>);
@@ -94,7 +100,13 @@ impl Db {
 			function: fun,
 			result: Cell::new(None),
 		};
+		let start = Instant::now();
 		let tx_res = self.0.transaction(&f);
+		let attrs = [KeyValue::new("engine", self.0.engine())];
+		DB_METRICS.transaction_counter.add(1, &attrs);
+		DB_METRICS
+			.transaction_duration
+			.record(start.elapsed().as_secs_f64(), &attrs);
 		let ret = f
 			.result
 			.into_inner()
@@ -121,6 +133,14 @@ impl Db {
 		}
 	}
 
+	/// Reclaim disk space left unused after deletions, in place.
+	/// Not all engines support this while the database is open, and an error
+	/// is returned for those: the database has to be copied into a new one
+	/// with `import` instead.
+	pub fn compact(&self) -> Result<()> {
+		self.0.compact()
+	}
+
 	pub fn import(&self, other: &Db) -> Result<()> {
 		let existing_trees = self.list_trees()?;
 		if !existing_trees.is_empty() {
@@ -216,6 +236,12 @@ impl Tree {
 	pub fn clear(&self) -> Result<()> {
 		self.0.clear(self.1)
 	}
+	/// Reclaim the space left unused after deletions in this tree only.
+	/// Returns an error if the engine does not support it.
+	#[inline]
+	pub fn compact(&self) -> Result<()> {
+		self.0.compact_tree(self.1)
+	}
 
 	#[inline]
 	pub fn iter(&self) -> Result<ValueIter<'_>> {
@@ -352,6 +378,9 @@ pub(crate) trait IDb: Send + Sync {
 	) -> Result<ValueIter<'_>>;
 
 	fn transaction(&self, f: &dyn ITxFn) -> TxResult<(), ()>;
+
+	fn compact(&self) -> Result<()>;
+	fn compact_tree(&self, tree: usize) -> Result<()>;
 }
 
 pub(crate) trait ITx {
@@ -3,10 +3,13 @@ use core::ptr::NonNull;
 
 use std::collections::HashMap;
 use std::convert::TryInto;
-use std::sync::{Arc, RwLock};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Duration;
 
 use heed::types::ByteSlice;
-use heed::{BytesDecode, Env, RoTxn, RwTxn, UntypedDatabase as Database};
+use heed::{BytesDecode, Env, EnvOpenOptions, RoTxn, RwTxn, UntypedDatabase as Database};
+use opentelemetry::{global, metrics::Counter};
 
 use crate::{
 	Db, Error, IDb, ITx, ITxFn, Result, TxError, TxFnResult, TxOpError, TxOpResult, TxResult,
@@ -29,15 +32,160 @@ impl From<heed::Error> for TxOpError {
 	}
 }
 
+fn is_map_full(e: &heed::Error) -> bool {
+	matches!(e, heed::Error::Mdb(heed::MdbError::MapFull))
+}
+
+// -- env
+
+/// Number of attempts at getting exclusive access to the environment to resize it
+const RESIZE_LOCK_ATTEMPTS: usize = 100;
+/// Delay between two attempts at getting exclusive access to the environment
+const RESIZE_LOCK_RETRY_DELAY: Duration = Duration::from_millis(10);
+
+/// An LMDB environment that is re-opened with a larger map size when it is full.
+///
+/// heed does not allow changing the map size of an open environment, so it is
+/// closed and opened again, which can only be done when no transaction or
+/// iterator is using it.
+pub struct ResizableLmdbEnv {
+	state: RwLock<EnvState>,
+	options: EnvOpenOptions,
+	path: PathBuf,
+	resize_counter: Counter<u64>,
+}
+
+struct EnvState {
+	// None only if re-opening the environment failed
+	env: Option<Env>,
+	map_size: usize,
+}
+
+impl EnvState {
+	fn env(&self) -> Result<&Env> {
+		self.env
+			.as_ref()
+			.ok_or_else(|| Error("LMDB environment could not be re-opened".into()))
+	}
+}
+
+impl ResizableLmdbEnv {
+	pub fn open<P: AsRef<Path>>(
+		mut options: EnvOpenOptions,
+		path: P,
+		map_size: usize,
+	) -> heed::Result<Self> {
+		let env = options.map_size(map_size).open(&path)?;
+		let resize_counter = global::meter("garage_db")
+			.u64_counter("db.lmdb_map_resize_counter")
+			.with_description("Number of times the LMDB map was full and was made larger")
+			.init();
+		Ok(Self {
+			state: RwLock::new(EnvState {
+				env: Some(env),
+				map_size,
+			}),
+			options,
+			path: path.as_ref().to_path_buf(),
+			resize_counter,
+		})
+	}
+
+	/// Current map size of the environment, in bytes
+	pub fn map_size(&self) -> usize {
+		self.read().map_size
+	}
+
+	fn read(&self) -> RwLockReadGuard<'_, EnvState> {
+		self.state.read().unwrap()
+	}
+
+	/// Re-open the environment with twice its map size, unless it has already
+	/// been resized since it was found full with `full_map_size`.
+	/// `reopen_trees` is called on the new environment before it can be used
+	/// by other threads.
+	fn grow(
+		&self,
+		full_map_size: usize,
+		reopen_trees: impl FnOnce(&Env) -> Result<()>,
+	) -> Result<()> {
+		let mut state = self.lock_for_resize()?;
+		if state.map_size != full_map_size {
+			return Ok(());
+		}
+
+		let new_map_size = full_map_size.saturating_mul(2).min(max_map_size());
+		if new_map_size <= full_map_size {
+			return Err(Error(
+				format!(
+					"LMDB map is full and cannot be made larger than {} bytes",
+					full_map_size
+				)
+				.into(),
+			));
+		}
+		tracing::warn!(
+			"LMDB map is full, re-opening environment with map size {} bytes (was {} bytes)",
+			new_map_size,
+			full_map_size
+		);
+
+		if let Some(env) = state.env.take() {
+			env.prepare_for_closing().wait();
+		}
+		let (env, map_size) = match self.options.clone().map_size(new_map_size).open(&self.path) {
+			Ok(env) => (env, new_map_size),
+			Err(e) => {
+				tracing::error!(
+					"Unable to re-open LMDB environment with map size {} bytes: {}",
+					new_map_size,
+					e
+				);
+				let env = self
+					.options
+					.clone()
+					.map_size(full_map_size)
+					.open(&self.path)?;
+				(env, full_map_size)
+			}
+		};
+		state.map_size = map_size;
+		reopen_trees(state.env.insert(env))?;
+
+		if map_size == new_map_size {
+			self.resize_counter.add(1, &[]);
+			Ok(())
+		} else {
+			Err(Error(
+				"LMDB map is full and could not be made larger".into(),
+			))
+		}
+	}
+
+	fn lock_for_resize(&self) -> Result<RwLockWriteGuard<'_, EnvState>> {
+		// Don't block on the lock: the current thread could be holding
+		// an iterator on the database, which would never be released
+		for _ in 0..RESIZE_LOCK_ATTEMPTS {
+			if let Ok(state) = self.state.try_write() {
+				return Ok(state);
+			}
+			std::thread::sleep(RESIZE_LOCK_RETRY_DELAY);
+		}
+		Err(Error(
+			"LMDB map is full, and it could not be made larger as the database is in use".into(),
+		))
+	}
+}
+
 // -- db
 
 pub struct LmdbDb {
-	db: heed::Env,
+	db: ResizableLmdbEnv,
 	trees: RwLock<(Vec<Database>, HashMap<String, usize>)>,
 }
 
 impl LmdbDb {
-	pub fn init(db: Env) -> Db {
+	pub fn init(db: ResizableLmdbEnv) -> Db {
 		let s = Self {
 			db,
 			trees: RwLock::new((Vec::new(), HashMap::new())),
@@ -45,6 +193,8 @@ impl LmdbDb {
 		Db(Arc::new(s))
 	}
 
+	// Must be called while holding a read guard on the environment,
+	// as tree handles change when it is re-opened
 	fn get_tree(&self, i: usize) -> Result<Database> {
 		self.trees
 			.read()
@@ -54,6 +204,79 @@ impl LmdbDb {
 			.cloned()
 			.ok_or_else(|| Error("invalid tree id".into()))
 	}
+
+	fn grow(&self, full_map_size: usize) -> Result<()> {
+		self.db.grow(full_map_size, |env| {
+			let mut trees = self.trees.write().unwrap();
+			let (handles, names) = &mut *trees;
+			for (name, i) in names.iter() {
+				handles[*i] = env.create_database(Some(name))?;
+			}
+			Ok(())
+		})
+	}
+
+	/// Run a transaction once. Returns the map size if the transaction
+	/// failed because the map is full.
+	fn try_transaction(&self, f: &dyn ITxFn) -> TxResult<Option<usize>, ()> {
+		let state = self.db.read();
+		let trees = self.trees.read().unwrap();
+		let mut tx = LmdbTx {
+			trees: &trees.0[..],
+			tx: state
+				.env()
+				.map_err(TxError::Db)?
+				.write_txn()
+				.map_err(Error::from)
+				.map_err(TxError::Db)?,
+			map_full: false,
+		};
+
+		let res = f.try_on(&mut tx);
+		let map_full = tx.map_full;
+		match res {
+			TxFnResult::Ok => match tx.tx.commit() {
+				Ok(()) => Ok(None),
+				Err(e) if map_full || is_map_full(&e) => Ok(Some(state.map_size)),
+				Err(e) => Err(TxError::Db(e.into())),
+			},
+			TxFnResult::Abort => {
+				tx.tx.abort().map_err(Error::from).map_err(TxError::Db)?;
+				Err(TxError::Abort(()))
+			}
+			TxFnResult::DbErr => {
+				tx.tx.abort().map_err(Error::from).map_err(TxError::Db)?;
+				if map_full {
+					Ok(Some(state.map_size))
+				} else {
+					Err(TxError::Db(Error(
+						"(this message will be discarded)".into(),
+					)))
+				}
+			}
+		}
+	}
+
+	/// Run a write operation in its own transaction,
+	/// growing the map and trying again if it is full
+	fn write_op<T>(
+		&self,
+		tree: usize,
+		op: impl Fn(&Env, Database) -> heed::Result<T>,
+	) -> Result<T> {
+		loop {
+			let state = self.db.read();
+			let map_size = state.map_size;
+			let tree = self.get_tree(tree)?;
+			match op(state.env()?, tree) {
+				Err(e) if is_map_full(&e) => {
+					drop(state);
+					self.grow(map_size)?;
+				}
+				res => return Ok(res?),
+			}
+		}
+	}
 }
 
 impl IDb for LmdbDb {
@@ -62,11 +285,12 @@ impl IDb for LmdbDb {
 	}
 
 	fn open_tree(&self, name: &str) -> Result<usize> {
+		let state = self.db.read();
 		let mut trees = self.trees.write().unwrap();
 		if let Some(i) = trees.1.get(name) {
 			Ok(*i)
 		} else {
-			let tree = self.db.create_database(Some(name))?;
+			let tree = state.env()?.create_database(Some(name))?;
 			let i = trees.0.len();
 			trees.0.push(tree);
 			trees.1.insert(name.to_string(), i);
@@ -75,13 +299,15 @@ impl IDb for LmdbDb {
 	}
 
 	fn list_trees(&self) -> Result<Vec<String>> {
-		let tree0 = match self.db.open_database::<heed::types::Str, ByteSlice>(None)? {
+		let state = self.db.read();
+		let env = state.env()?;
+		let tree0 = match env.open_database::<heed::types::Str, ByteSlice>(None)? {
 			Some(x) => x,
 			None => return Ok(vec![]),
 		};
 
 		let mut ret = vec![];
-		let tx = self.db.read_txn()?;
+		let tx = env.read_txn()?;
 		for item in tree0.iter(&tx)? {
 			let (tree_name, _) = item?;
 			ret.push(tree_name.to_string());
@@ -90,8 +316,7 @@ impl IDb for LmdbDb {
 
 		let mut ret2 = vec![];
 		for tree_name in ret {
-			if self
-				.db
+			if env
 				.open_database::<ByteSlice, ByteSlice>(Some(&tree_name))?
 				.is_some()
 			{
@@ -105,9 +330,10 @@ impl IDb for LmdbDb {
 	// ----
 
 	fn get(&self, tree: usize, key: &[u8]) -> Result<Option<Value>> {
+		let state = self.db.read();
 		let tree = self.get_tree(tree)?;
 
-		let tx = self.db.read_txn()?;
+		let tx = state.env()?.read_txn()?;
 		let val = tree.get(&tx, key)?;
 		match val {
 			None => Ok(None),
@@ -116,8 +342,9 @@ impl IDb for LmdbDb {
 	}
 
 	fn len(&self, tree: usize) -> Result<usize> {
+		let state = self.db.read();
 		let tree = self.get_tree(tree)?;
-		let tx = self.db.read_txn()?;
+		let tx = state.env()?.read_txn()?;
 		Ok(tree.len(&tx)?.try_into().unwrap())
 	}
 
@@ -126,41 +353,43 @@ impl IDb for LmdbDb {
 	}
 
 	fn insert(&self, tree: usize, key: &[u8], value: &[u8]) -> Result<Option<Value>> {
-		let tree = self.get_tree(tree)?;
-		let mut tx = self.db.write_txn()?;
-		let old_val = tree.get(&tx, key)?.map(Vec::from);
-		tree.put(&mut tx, key, value)?;
-		tx.commit()?;
-		Ok(old_val)
+		self.write_op(tree, |env, tree| {
+			let mut tx = env.write_txn()?;
+			let old_val = tree.get(&tx, key)?.map(Vec::from);
+			tree.put(&mut tx, key, value)?;
+			tx.commit()?;
+			Ok(old_val)
+		})
 	}
 
 	fn remove(&self, tree: usize, key: &[u8]) -> Result<Option<Value>> {
-		let tree = self.get_tree(tree)?;
-		let mut tx = self.db.write_txn()?;
-		let old_val = tree.get(&tx, key)?.map(Vec::from);
-		tree.delete(&mut tx, key)?;
-		tx.commit()?;
-		Ok(old_val)
+		self.write_op(tree, |env, tree| {
+			let mut tx = env.write_txn()?;
+			let old_val = tree.get(&tx, key)?.map(Vec::from);
+			tree.delete(&mut tx, key)?;
+			tx.commit()?;
+			Ok(old_val)
+		})
 	}
 
 	fn clear(&self, tree: usize) -> Result<()> {
-		let tree = self.get_tree(tree)?;
-		let mut tx = self.db.write_txn()?;
-		tree.clear(&mut tx)?;
-		tx.commit()?;
-		Ok(())
+		self.write_op(tree, |env, tree| {
+			let mut tx = env.write_txn()?;
+			tree.clear(&mut tx)?;
+			tx.commit()
+		})
 	}
 
 	fn iter(&self, tree: usize) -> Result<ValueIter<'_>> {
+		let state = self.db.read();
 		let tree = self.get_tree(tree)?;
-		let tx = self.db.read_txn()?;
-		TxAndIterator::make(tx, |tx| Ok(tree.iter(tx)?))
+		TxAndIterator::make(state, |tx| Ok(tree.iter(tx)?))
 	}
 
 	fn iter_rev(&self, tree: usize) -> Result<ValueIter<'_>> {
+		let state = self.db.read();
 		let tree = self.get_tree(tree)?;
-		let tx = self.db.read_txn()?;
-		TxAndIterator::make(tx, |tx| Ok(tree.rev_iter(tx)?))
+		TxAndIterator::make(state, |tx| Ok(tree.rev_iter(tx)?))
 	}
 
 	fn range<'r>(
@@ -169,9 +398,9 @@ impl IDb for LmdbDb {
 		low: Bound<&'r [u8]>,
 		high: Bound<&'r [u8]>,
 	) -> Result<ValueIter<'_>> {
+		let state = self.db.read();
 		let tree = self.get_tree(tree)?;
-		let tx = self.db.read_txn()?;
-		TxAndIterator::make(tx, |tx| Ok(tree.range(tx, &(low, high))?))
+		TxAndIterator::make(state, |tx| Ok(tree.range(tx, &(low, high))?))
 	}
 	fn range_rev<'r>(
 		&self,
@@ -179,42 +408,37 @@ impl IDb for LmdbDb {
 		low: Bound<&'r [u8]>,
 		high: Bound<&'r [u8]>,
 	) -> Result<ValueIter<'_>> {
+		let state = self.db.read();
 		let tree = self.get_tree(tree)?;
-		let tx = self.db.read_txn()?;
-		TxAndIterator::make(tx, |tx| Ok(tree.rev_range(tx, &(low, high))?))
+		TxAndIterator::make(state, |tx| Ok(tree.rev_range(tx, &(low, high))?))
 	}
 
 	// ----
 
 	fn transaction(&self, f: &dyn ITxFn) -> TxResult<(), ()> {
-		let trees = self.trees.read().unwrap();
-		let mut tx = LmdbTx {
-			trees: &trees.0[..],
-			tx: self
-				.db
-				.write_txn()
-				.map_err(Error::from)
-				.map_err(TxError::Db)?,
-		};
-
-		let res = f.try_on(&mut tx);
-		match res {
-			TxFnResult::Ok => {
-				tx.tx.commit().map_err(Error::from).map_err(TxError::Db)?;
-				Ok(())
-			}
-			TxFnResult::Abort => {
-				tx.tx.abort().map_err(Error::from).map_err(TxError::Db)?;
-				Err(TxError::Abort(()))
-			}
-			TxFnResult::DbErr => {
-				tx.tx.abort().map_err(Error::from).map_err(TxError::Db)?;
-				Err(TxError::Db(Error(
-					"(this message will be discarded)".into(),
-				)))
+		loop {
+			match self.try_transaction(f)? {
+				None => return Ok(()),
+				// The map is full: make it larger and run the transaction again
+				Some(full_map_size) => self.grow(full_map_size).map_err(TxError::Db)?,
 			}
 		}
 	}
+
+	// ----
+
+	fn compact(&self) -> Result<()> {
+		Err(Error(
+			"LMDB does not support in-place compaction, the database must be rewritten offline"
+				.into(),
+		))
+	}
+
+	fn compact_tree(&self, _tree: usize) -> Result<()> {
+		Err(Error(
+			"LMDB does not support the compaction of a single tree".into(),
+		))
+	}
 }
 
 // ----
@@ -222,6 +446,8 @@ impl IDb for LmdbDb {
 struct LmdbTx<'a> {
 	trees: &'a [Database],
 	tx: RwTxn<'a, 'a>,
+	// Set when a write failed because the map is full
+	map_full: bool,
 }
 
 impl<'a> LmdbTx<'a> {
@@ -232,6 +458,13 @@ impl<'a> LmdbTx<'a> {
 			))
 		})
 	}
+
+	fn check_map_full<T>(&mut self, res: heed::Result<T>) -> TxOpResult<T> {
+		if let Err(e) = &res {
+			self.map_full |= is_map_full(e);
+		}
+		Ok(res?)
+	}
 }
 
 impl<'a> ITx for LmdbTx<'a> {
@@ -249,13 +482,15 @@ impl<'a> ITx for LmdbTx<'a> {
 	fn insert(&mut self, tree: usize, key: &[u8], value: &[u8]) -> TxOpResult<Option<Value>> {
 		let tree = *self.get_tree(tree)?;
 		let old_val = tree.get(&self.tx, key)?.map(Vec::from);
-		tree.put(&mut self.tx, key, value)?;
+		let res = tree.put(&mut self.tx, key, value);
+		self.check_map_full(res)?;
 		Ok(old_val)
 	}
 	fn remove(&mut self, tree: usize, key: &[u8]) -> TxOpResult<Option<Value>> {
 		let tree = *self.get_tree(tree)?;
 		let old_val = tree.get(&self.tx, key)?.map(Vec::from);
-		tree.delete(&mut self.tx, key)?;
+		let res = tree.delete(&mut self.tx, key);
+		self.check_map_full(res)?;
 		Ok(old_val)
 	}
 
@@ -297,17 +532,25 @@ where
 {
 	tx: RoTxn<'a>,
 	iter: Option<I>,
+	// The environment is not re-opened while this guard is held
+	_state: RwLockReadGuard<'a, EnvState>,
 }
 
 impl<'a, I> TxAndIterator<'a, I>
 where
 	I: Iterator<Item = IteratorItem<'a>> + 'a,
 {
-	fn make<F>(tx: RoTxn<'a>, iterfun: F) -> Result<ValueIter<'a>>
+	fn make<F>(state: RwLockReadGuard<'a, EnvState>, iterfun: F) -> Result<ValueIter<'a>>
 	where
 		F: FnOnce(&'a RoTxn<'a>) -> Result<I>,
 	{
-		let mut res = TxAndIterator { tx, iter: None };
+		let env = unsafe { NonNull::from(state.env()?).as_ref() };
+		let tx = env.read_txn()?;
+		let mut res = TxAndIterator {
+			tx,
+			iter: None,
+			_state: state,
+		};
 
 		let tx = unsafe { NonNull::from(&res.tx).as_ref() };
 		res.iter = Some(iterfun(tx)?);
@@ -352,3 +595,14 @@ pub fn recommended_map_size() -> usize {
 	tracing::warn!("LMDB is not recommended on 32-bit systems, database size will be limited");
 	1usize << 30
 }
+
+/// Map size up to which the map is made larger when it is full
+#[cfg(target_pointer_width = "64")]
+pub fn max_map_size() -> usize {
+	1usize << 46
+}
+
+#[cfg(target_pointer_width = "32")]
+pub fn max_map_size() -> usize {
+	1usize << 31
+}
@@ -0,0 +1,27 @@
+use opentelemetry::{global, metrics::*};
+
+lazy_static::lazy_static! {
+	pub(crate) static ref DB_METRICS: DbMetrics = DbMetrics::new();
+}
+
+/// DbMetrics reference all counters used for metrics of the db abstraction layer
+pub(crate) struct DbMetrics {
+	pub(crate) transaction_counter: Counter<u64>,
+	pub(crate) transaction_duration: ValueRecorder<f64>,
+}
+
+impl DbMetrics {
+	fn new() -> Self {
+		let meter = global::meter("garage_db");
+		DbMetrics {
+			transaction_counter: meter
+				.u64_counter("db.transaction_counter")
+				.with_description("Number of transactions on the metadata database")
+				.init(),
+			transaction_duration: meter
+				.f64_value_recorder("db.transaction_duration")
+				.with_description("Duration of transactions on the metadata database, in seconds")
+				.init(),
+		}
+	}
+}
@@ -186,6 +186,21 @@ impl IDb for SledDb {
 			Err(TransactionError::Storage(s)) => Err(TxError::Db(s.into())),
 		}
 	}
+
+	// ----
+
+	fn compact(&self) -> Result<()> {
+		Err(Error(
+			"Sled does not support in-place compaction, the database must be rewritten offline"
+				.into(),
+		))
+	}
+
+	fn compact_tree(&self, _tree: usize) -> Result<()> {
+		Err(Error(
+			"Sled does not support the compaction of a single tree".into(),
+		))
+	}
 }
 
 // ----
@@ -56,6 +56,13 @@ impl SqliteDbInner {
 			.ok_or_else(|| Error("invalid tree id".into()))
 	}
 
+	fn incremental_vacuum(&self) -> Result<bool> {
+		let auto_vacuum = self
+			.db
+			.query_row("PRAGMA auto_vacuum", [], |row| row.get::<_, i64>(0))?;
+		Ok(auto_vacuum == 2)
+	}
+
 	fn internal_get(&self, tree: &str, key: &[u8]) -> Result<Option<Value>> {
 		let mut stmt = self
 			.db
@@ -296,6 +303,47 @@ impl IDb for SqliteDb {
 		trace!("transaction done");
 		res
 	}
+
+	fn compact(&self) -> Result<()> {
+		trace!("compact: lock db");
+		let this = self.0.lock().unwrap();
+		trace!("compact: lock acquired");
+
+		// With auto_vacuum = INCREMENTAL (2), free pages can be released
+		// without rebuilding the whole database file
+		if this.incremental_vacuum()? {
+			this.db.execute_batch("PRAGMA incremental_vacuum")?;
+		} else {
+			this.db.execute_batch("VACUUM")?;
+		}
+		Ok(())
+	}
+
+	fn compact_tree(&self, tree: usize) -> Result<()> {
+		trace!("compact_tree {}: lock db", tree);
+		let mut this = self.0.lock().unwrap();
+		trace!("compact_tree {}: lock acquired", tree);
+
+		// VACUUM only works on the whole database: rebuild the table instead,
+		// which releases its unused pages. They are given back to the
+		// filesystem with auto_vacuum = INCREMENTAL, and reused for new data
+		// otherwise.
+		let this_mut_ref: &mut SqliteDbInner = this.borrow_mut();
+		let tree = this_mut_ref.get_tree(tree)?.to_string();
+		let tx = this_mut_ref.db.transaction()?;
+		tx.execute_batch(&format!(
+			"CREATE TABLE {tree}_compact (k BLOB PRIMARY KEY, v BLOB);
+			INSERT INTO {tree}_compact (k, v) SELECT k, v FROM {tree} ORDER BY k;
+			DROP TABLE {tree};
+			ALTER TABLE {tree}_compact RENAME TO {tree};",
+			tree = tree
+		))?;
+		tx.commit()?;
+		if this.incremental_vacuum()? {
+			this.db.execute_batch("PRAGMA incremental_vacuum")?;
+		}
+		Ok(())
+	}
 }
 
 // ----
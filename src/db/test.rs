@@ -78,18 +78,53 @@ fn test_suite(db: Db) {
 #[test]
 #[cfg(feature = "lmdb")]
 fn test_lmdb_db() {
-	use crate::lmdb_adapter::LmdbDb;
+	use crate::lmdb_adapter::{LmdbDb, ResizableLmdbEnv};
 
 	let path = mktemp::Temp::new_dir().unwrap();
-	let db = heed::EnvOpenOptions::new()
-		.max_dbs(100)
-		.open(&path)
-		.unwrap();
+	let mut env_builder = heed::EnvOpenOptions::new();
+	env_builder.max_dbs(100);
+	let db = ResizableLmdbEnv::open(env_builder, &path, 1 << 30).unwrap();
 	let db = LmdbDb::init(db);
 	test_suite(db);
 	drop(path);
 }
 
+#[test]
+#[cfg(feature = "lmdb")]
+fn test_lmdb_map_resize() {
+	use crate::lmdb_adapter::{LmdbDb, ResizableLmdbEnv};
+
+	// Start with a map that is much too small for what is written
+	let path = mktemp::Temp::new_dir().unwrap();
+	let mut env_builder = heed::EnvOpenOptions::new();
+	env_builder.max_dbs(100);
+	let db = ResizableLmdbEnv::open(env_builder, &path, 1 << 20).unwrap();
+	let db = LmdbDb::init(db);
+
+	let tree = db.open_tree("tree").unwrap();
+	let tree2 = db.open_tree("tree2").unwrap();
+	let value = vec![42u8; 64 << 10];
+	for i in 0u32..64 {
+		tree.insert(i.to_be_bytes(), &value).unwrap();
+	}
+	let res = db.transaction::<_, (), _>(|mut tx| {
+		for i in 0u32..64 {
+			tx.insert(&tree2, i.to_be_bytes(), &value)?;
+		}
+		tx.commit(())
+	});
+	assert!(matches!(res, Ok(())));
+
+	assert_eq!(tree.len().unwrap(), 64);
+	assert_eq!(tree2.len().unwrap(), 64);
+	assert_eq!(
+		tree.get(7u32.to_be_bytes()).unwrap().as_deref(),
+		Some(&value[..])
+	);
+	assert_eq!(tree2.iter().unwrap().count(), 64);
+	drop(path);
+}
+
 #[test]
 #[cfg(feature = "sled")]
 fn test_sled_db() {
@@ -109,3 +144,58 @@ fn test_sqlite_db() {
 	let db = SqliteDb::init(rusqlite::Connection::open_in_memory().unwrap());
 	test_suite(db);
 }
+
+#[test]
+#[cfg(feature = "sqlite")]
+fn test_sqlite_compact() {
+	use crate::sqlite_adapter::SqliteDb;
+
+	let db = SqliteDb::init(rusqlite::Connection::open_in_memory().unwrap());
+	let tree = db.open_tree("tree").unwrap();
+	tree.insert(b"test", b"value").unwrap();
+	tree.remove(b"test").unwrap();
+	db.compact().unwrap();
+	assert_eq!(tree.len().unwrap(), 0);
+}
+
+#[test]
+#[cfg(feature = "sqlite")]
+fn test_sqlite_compact_tree() {
+	use crate::sqlite_adapter::SqliteDb;
+
+	let db = SqliteDb::init(rusqlite::Connection::open_in_memory().unwrap());
+	let tree = db.open_tree("tree:a").unwrap();
+	let tree2 = db.open_tree("tree:b").unwrap();
+	for i in 0u32..100 {
+		tree.insert(i.to_be_bytes(), b"value").unwrap();
+		tree2.insert(i.to_be_bytes(), b"value").unwrap();
+	}
+	for i in 0u32..50 {
+		tree.remove(i.to_be_bytes()).unwrap();
+	}
+	tree.compact().unwrap();
+	assert_eq!(tree.len().unwrap(), 50);
+	assert_eq!(tree2.len().unwrap(), 100);
+	assert_eq!(
+		tree.get(70u32.to_be_bytes()).unwrap().as_deref(),
+		Some(&b"value"[..])
+	);
+	assert!(tree.get(10u32.to_be_bytes()).unwrap().is_none());
+
+	// The tree is still usable after its table has been rebuilt
+	tree.insert(b"test", b"value").unwrap();
+	assert_eq!(tree.len().unwrap(), 51);
+}
+
+#[test]
+#[cfg(feature = "sled")]
+fn test_sled_compact_unsupported() {
+	use crate::sled_adapter::SledDb;
+
+	let path = mktemp::Temp::new_dir().unwrap();
+	let db = SledDb::init(sled::open(path.to_path_buf()).unwrap());
+	let tree = db.open_tree("tree").unwrap();
+	assert!(db.compact().is_err());
+	assert!(tree.compact().is_err());
+	drop(path);
+}
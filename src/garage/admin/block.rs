@@ -24,6 +24,72 @@ impl AdminRpcHandler {
 		}
 	}
 
+	pub(super) async fn handle_scrub_cmd(&self, cmd: &ScrubOperation) -> Result<AdminRpc, Error> {
+		match cmd {
+			ScrubOperation::Status { all_nodes } => self.handle_scrub_status(*all_nodes).await,
+		}
+	}
+
+	async fn handle_scrub_status(&self, all_nodes: bool) -> Result<AdminRpc, Error> {
+		let nodes = if all_nodes {
+			let ring = self.garage.system.ring.borrow().clone();
+			ring.layout.node_ids().to_vec()
+		} else {
+			vec![self.garage.system.id]
+		};
+
+		let mut table =
+			vec!["Node\tState\tScanned\tTotal\tProgress\tErrors\tETA\tLast error".to_string()];
+		for node in nodes {
+			let stats = if node == self.garage.system.id {
+				Ok(self.garage.block_manager.scrub_stats())
+			} else {
+				match self
+					.endpoint
+					.call(&node.into(), AdminRpc::GetScrubStats, PRIO_NORMAL)
+					.await
+				{
+					Ok(Ok(AdminRpc::ScrubStats(stats))) => Ok(stats),
+					Ok(Ok(x)) => Err(format!("Bad answer: {:?}", x)),
+					Ok(Err(e)) => Err(format!("Remote error: {}", e)),
+					Err(e) => Err(format!("Network error: {}", e)),
+				}
+			};
+			match stats {
+				Ok(stats) => {
+					let progress = if stats.bytes_total > 0 {
+						format!(
+							"{:.2}%",
+							stats.bytes_scanned as f64 * 100. / stats.bytes_total as f64
+						)
+					} else {
+						"-".into()
+					};
+					table.push(format!(
+						"{:?}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+						node,
+						stats.state,
+						bytesize::ByteSize::b(stats.bytes_scanned),
+						bytesize::ByteSize::b(stats.bytes_total),
+						progress,
+						stats.errors_found,
+						stats
+							.eta
+							.map(|eta| {
+								let secs = eta.as_secs();
+								format!("{}h{:02}m{:02}s", secs / 3600, (secs / 60) % 60, secs % 60)
+							})
+							.unwrap_or_else(|| "-".into()),
+						stats.last_error.as_deref().unwrap_or("-"),
+					));
+				}
+				Err(e) => table.push(format!("{:?}\t{}", node, e)),
+			}
+		}
+
+		Ok(AdminRpc::Ok(format_table_to_string(table)))
+	}
+
 	async fn handle_block_info(&self, hash: &String) -> Result<AdminRpc, Error> {
 		let hash = hex::decode(hash).ok_or_bad_request("invalid hash")?;
 		let hash = Hash::try_from(&hash).ok_or_bad_request("invalid hash")?;
@@ -134,6 +200,11 @@ impl AdminRpcHandler {
 									state: ObjectVersionState::Complete(
 										ObjectVersionData::DeleteMarker,
 									),
+									versioned: false,
+									lock: ObjectVersionLock::default(),
+									tags: Default::default(),
+									storage_class: Default::default(),
+									restore: Default::default(),
 								}],
 							);
 							self.garage.object_table.insert(&deleted_object).await?;
@@ -1,5 +1,6 @@
 use std::collections::HashMap;
 use std::fmt::Write;
+use std::time::{Duration, Instant};
 
 use garage_util::crdt::*;
 use garage_util::time::*;
@@ -10,11 +11,15 @@ use garage_model::bucket_alias_table::*;
 use garage_model::bucket_table::*;
 use garage_model::helper::error::{Error, OkOrBadRequest};
 use garage_model::permission::*;
+use garage_model::s3::object_table::*;
 
 use crate::cli::*;
 
 use super::*;
 
+/// Storage usage of buckets is cached to avoid repeated scans of large buckets
+const STORAGE_USAGE_CACHE_DURATION: Duration = Duration::from_secs(5 * 60);
+
 impl AdminRpcHandler {
 	pub(super) async fn handle_bucket_cmd(&self, cmd: &BucketOperation) -> Result<AdminRpc, Error> {
 		match cmd {
@@ -28,9 +33,28 @@ impl AdminRpcHandler {
 			BucketOperation::Deny(query) => self.handle_bucket_deny(query).await,
 			BucketOperation::Website(query) => self.handle_bucket_website(query).await,
 			BucketOperation::SetQuotas(query) => self.handle_bucket_set_quotas(query).await,
+			BucketOperation::SetEncryptionRequired(query) => {
+				self.handle_bucket_set_encryption_required(query).await
+			}
+			BucketOperation::SetMaxObjectSize(query) => {
+				self.handle_bucket_set_max_object_size(query).await
+			}
+			BucketOperation::SetCompressionLevel(query) => {
+				self.handle_bucket_set_compression_level(query).await
+			}
+			BucketOperation::AddReplicationRule(query) => {
+				self.handle_bucket_add_replication_rule(query).await
+			}
+			BucketOperation::RemoveReplicationRule(query) => {
+				self.handle_bucket_remove_replication_rule(query).await
+			}
 			BucketOperation::CleanupIncompleteUploads(query) => {
 				self.handle_bucket_cleanup_incomplete_uploads(query).await
 			}
+			BucketOperation::GetStorageUsage(query) => {
+				self.handle_bucket_get_storage_usage(query).await
+			}
+			BucketOperation::ListObjects(query) => self.handle_bucket_list_objects(query).await,
 		}
 	}
 
@@ -293,41 +317,18 @@ impl AdminRpcHandler {
 	}
 
 	async fn handle_bucket_allow(&self, query: &PermBucketOpt) -> Result<AdminRpc, Error> {
-		let helper = self.garage.bucket_helper();
-		let key_helper = self.garage.key_helper();
-
-		let bucket_id = helper
-			.resolve_global_bucket_name(&query.bucket)
-			.await?
-			.ok_or_bad_request("Bucket not found")?;
-		let key = key_helper
-			.get_existing_matching_key(&query.key_pattern)
-			.await?;
-
-		let allow_read = query.read || key.allow_read(&bucket_id);
-		let allow_write = query.write || key.allow_write(&bucket_id);
-		let allow_owner = query.owner || key.allow_owner(&bucket_id);
-
-		helper
-			.set_bucket_key_permissions(
-				bucket_id,
-				&key.key_id,
-				BucketKeyPerm {
-					timestamp: now_msec(),
-					allow_read,
-					allow_write,
-					allow_owner,
-				},
-			)
-			.await?;
-
-		Ok(AdminRpc::Ok(format!(
-			"New permissions for {} on {}: read {}, write {}, owner {}.",
-			&key.key_id, &query.bucket, allow_read, allow_write, allow_owner
-		)))
+		self.handle_bucket_change_perm(query, true).await
 	}
 
 	async fn handle_bucket_deny(&self, query: &PermBucketOpt) -> Result<AdminRpc, Error> {
+		self.handle_bucket_change_perm(query, false).await
+	}
+
+	async fn handle_bucket_change_perm(
+		&self,
+		query: &PermBucketOpt,
+		allow: bool,
+	) -> Result<AdminRpc, Error> {
 		let helper = self.garage.bucket_helper();
 		let key_helper = self.garage.key_helper();
 
@@ -339,27 +340,48 @@ impl AdminRpcHandler {
 			.get_existing_matching_key(&query.key_pattern)
 			.await?;
 
-		let allow_read = !query.read && key.allow_read(&bucket_id);
-		let allow_write = !query.write && key.allow_write(&bucket_id);
-		let allow_owner = !query.owner && key.allow_owner(&bucket_id);
+		let ops = query
+			.ops
+			.iter()
+			.map(|op| op.parse::<S3Op>().map_err(Error::BadRequest))
+			.collect::<Result<S3OpSet, _>>()?;
+
+		let mut perm = key.bucket_permissions(&bucket_id);
+		perm.timestamp = now_msec();
+		if query.read {
+			perm.set_read(allow);
+		}
+		if query.write {
+			perm.set_write(allow);
+		}
+		if query.owner {
+			perm.set_owner(allow);
+		}
+		if query.bypass_governance {
+			perm.allow_bypass_governance = allow;
+		}
+		perm.set_ops(ops, allow);
 
 		helper
-			.set_bucket_key_permissions(
-				bucket_id,
-				&key.key_id,
-				BucketKeyPerm {
-					timestamp: now_msec(),
-					allow_read,
-					allow_write,
-					allow_owner,
-				},
-			)
+			.set_bucket_key_permissions(bucket_id, &key.key_id, perm)
 			.await?;
 
-		Ok(AdminRpc::Ok(format!(
-			"New permissions for {} on {}: read {}, write {}, owner {}.",
-			&key.key_id, &query.bucket, allow_read, allow_write, allow_owner
-		)))
+		let mut msg = format!(
+			"New permissions for {} on {}: read {}, write {}, owner {}, bypass governance {}.",
+			&key.key_id,
+			&query.bucket,
+			perm.allow_read,
+			perm.allow_write,
+			perm.allow_owner,
+			perm.allow_bypass_governance
+		);
+		if !perm.denied_ops.is_empty() {
+			write!(&mut msg, "\nDenied operations: {}", perm.denied_ops).unwrap();
+		}
+		if !perm.extra_ops.is_empty() {
+			write!(&mut msg, "\nAdditional operations: {}", perm.extra_ops).unwrap();
+		}
+		Ok(AdminRpc::Ok(msg))
 	}
 
 	async fn handle_bucket_website(&self, query: &WebsiteOpt) -> Result<AdminRpc, Error> {
@@ -387,6 +409,8 @@ impl AdminRpcHandler {
 			Some(WebsiteConfig {
 				index_document: query.index_document.clone(),
 				error_document: query.error_document.clone(),
+				redirect_all: None,
+				routing_rules: vec![],
 			})
 		} else {
 			None
@@ -458,6 +482,215 @@ impl AdminRpcHandler {
 		)))
 	}
 
+	async fn handle_bucket_set_encryption_required(
+		&self,
+		query: &SetEncryptionRequiredOpt,
+	) -> Result<AdminRpc, Error> {
+		let bucket_id = self
+			.garage
+			.bucket_helper()
+			.resolve_global_bucket_name(&query.bucket)
+			.await?
+			.ok_or_bad_request("Bucket not found")?;
+
+		let mut bucket = self
+			.garage
+			.bucket_helper()
+			.get_existing_bucket(bucket_id)
+			.await?;
+		let bucket_state = bucket.state.as_option_mut().unwrap();
+
+		let msg = if query.disable {
+			bucket_state.encryption_required.update(false);
+			format!(
+				"Server-side encryption is no longer required for {}",
+				&query.bucket
+			)
+		} else {
+			let config = match &query.key_id {
+				Some(key_id) => EncryptionConfig {
+					sse_algorithm: "aws:kms".into(),
+					kms_master_key_id: Some(key_id.clone()),
+				},
+				None => EncryptionConfig {
+					sse_algorithm: "AES256".into(),
+					kms_master_key_id: None,
+				},
+			};
+			bucket_state.encryption_required.update(true);
+			bucket_state.encryption_config.update(Some(config));
+			format!(
+				"Server-side encryption is now required for {}",
+				&query.bucket
+			)
+		};
+		self.garage.bucket_table.insert(&bucket).await?;
+
+		Ok(AdminRpc::Ok(msg))
+	}
+
+	async fn handle_bucket_set_max_object_size(
+		&self,
+		query: &SetMaxObjectSizeOpt,
+	) -> Result<AdminRpc, Error> {
+		let bucket_id = self
+			.garage
+			.bucket_helper()
+			.resolve_global_bucket_name(&query.bucket)
+			.await?
+			.ok_or_bad_request("Bucket not found")?;
+
+		let max_object_size = match query.max_bytes.as_str() {
+			"none" => None,
+			v => Some(
+				v.parse::<u64>()
+					.ok_or_bad_request(format!("Invalid number of bytes specified: {}", v))?,
+			),
+		};
+
+		let mut bucket = self
+			.garage
+			.bucket_helper()
+			.get_existing_bucket(bucket_id)
+			.await?;
+		let bucket_state = bucket.state.as_option_mut().unwrap();
+		bucket_state.max_object_size.update(max_object_size);
+		self.garage.bucket_table.insert(&bucket).await?;
+
+		Ok(AdminRpc::Ok(format!(
+			"Maximum object size updated for {}",
+			&query.bucket
+		)))
+	}
+
+	async fn handle_bucket_set_compression_level(
+		&self,
+		query: &SetCompressionLevelOpt,
+	) -> Result<AdminRpc, Error> {
+		let bucket_id = self
+			.garage
+			.bucket_helper()
+			.resolve_global_bucket_name(&query.bucket)
+			.await?
+			.ok_or_bad_request("Bucket not found")?;
+
+		let compression_level = match query.level.as_str() {
+			"default" => None,
+			v => Some(
+				v.parse::<i32>()
+					.ok_or_bad_request(format!("Invalid compression level: {}", v))?,
+			),
+		};
+
+		let mut bucket = self
+			.garage
+			.bucket_helper()
+			.get_existing_bucket(bucket_id)
+			.await?;
+		let bucket_state = bucket.state.as_option_mut().unwrap();
+		bucket_state.compression_level.update(compression_level);
+		self.garage.bucket_table.insert(&bucket).await?;
+
+		Ok(AdminRpc::Ok(format!(
+			"Compression level updated for {}",
+			&query.bucket
+		)))
+	}
+
+	async fn handle_bucket_add_replication_rule(
+		&self,
+		query: &AddReplicationRuleOpt,
+	) -> Result<AdminRpc, Error> {
+		let bucket_id = self
+			.garage
+			.bucket_helper()
+			.resolve_global_bucket_name(&query.bucket)
+			.await?
+			.ok_or_bad_request("Bucket not found")?;
+
+		if !query.endpoint.starts_with("http://") && !query.endpoint.starts_with("https://") {
+			return Err(Error::BadRequest(format!(
+				"Invalid endpoint {}: it must start with http:// or https://",
+				query.endpoint
+			)));
+		}
+
+		let rule = ReplicationRule {
+			id: query.id.clone(),
+			enabled: !query.disabled,
+			prefixes: query.prefixes.clone(),
+			replicate_deletes: query.replicate_deletes,
+			destination: ReplicationDestination {
+				endpoint: query.endpoint.clone(),
+				region: query.region.clone(),
+				bucket: query.target_bucket.clone(),
+				access_key_id: query.access_key_id.clone(),
+				secret_access_key: query.secret_access_key.clone(),
+			},
+		};
+
+		let mut bucket = self
+			.garage
+			.bucket_helper()
+			.get_existing_bucket(bucket_id)
+			.await?;
+		let bucket_state = bucket.state.as_option_mut().unwrap();
+		let mut rules = bucket_state
+			.replication_config
+			.get()
+			.clone()
+			.unwrap_or_default();
+		rules.retain(|r| r.id != rule.id);
+		rules.push(rule);
+		bucket_state.replication_config.update(Some(rules));
+		self.garage.bucket_table.insert(&bucket).await?;
+
+		Ok(AdminRpc::Ok(format!(
+			"Replication rule {} added to bucket {}",
+			query.id, query.bucket
+		)))
+	}
+
+	async fn handle_bucket_remove_replication_rule(
+		&self,
+		query: &RemoveReplicationRuleOpt,
+	) -> Result<AdminRpc, Error> {
+		let bucket_id = self
+			.garage
+			.bucket_helper()
+			.resolve_global_bucket_name(&query.bucket)
+			.await?
+			.ok_or_bad_request("Bucket not found")?;
+
+		let mut bucket = self
+			.garage
+			.bucket_helper()
+			.get_existing_bucket(bucket_id)
+			.await?;
+		let bucket_state = bucket.state.as_option_mut().unwrap();
+		let mut rules = bucket_state
+			.replication_config
+			.get()
+			.clone()
+			.unwrap_or_default();
+		if !rules.iter().any(|r| r.id == query.id) {
+			return Err(Error::BadRequest(format!(
+				"Bucket {} has no replication rule with ID {}",
+				query.bucket, query.id
+			)));
+		}
+		rules.retain(|r| r.id != query.id);
+		bucket_state
+			.replication_config
+			.update(if rules.is_empty() { None } else { Some(rules) });
+		self.garage.bucket_table.insert(&bucket).await?;
+
+		Ok(AdminRpc::Ok(format!(
+			"Replication rule {} removed from bucket {}",
+			query.id, query.bucket
+		)))
+	}
+
 	async fn handle_bucket_cleanup_incomplete_uploads(
 		&self,
 		query: &CleanupIncompleteUploadsOpt,
@@ -493,4 +726,185 @@ impl AdminRpcHandler {
 
 		Ok(AdminRpc::Ok(ret))
 	}
+
+	async fn handle_bucket_get_storage_usage(
+		&self,
+		query: &StorageUsageOpt,
+	) -> Result<AdminRpc, Error> {
+		let bucket_id = self
+			.garage
+			.bucket_helper()
+			.resolve_global_bucket_name(&query.bucket)
+			.await?
+			.ok_or_bad_request("Bucket not found")?;
+
+		let cache_key = (bucket_id, query.detail.clone());
+		let cached = self
+			.storage_usage_cache
+			.lock()
+			.unwrap()
+			.get(&cache_key)
+			.filter(|(t, _)| t.elapsed() < STORAGE_USAGE_CACHE_DURATION)
+			.cloned();
+		let (computed_at, usage) = match cached {
+			Some(c) => c,
+			None => {
+				let usage = self
+					.garage
+					.bucket_helper()
+					.get_storage_usage(
+						&bucket_id,
+						matches!(query.detail.as_str(), "version" | "all"),
+						matches!(query.detail.as_str(), "block" | "all"),
+					)
+					.await?;
+				let c = (Instant::now(), usage);
+				self.storage_usage_cache
+					.lock()
+					.unwrap()
+					.insert(cache_key, c.clone());
+				c
+			}
+		};
+
+		let mut table = vec![
+			format!("Current objects:\t{}", usage.objects),
+			format!(
+				"Current object size:\t{}",
+				bytesize::ByteSize::b(usage.object_bytes)
+			),
+			format!("Delete markers:\t{}", usage.delete_markers),
+		];
+		if let Some(v) = &usage.versions {
+			table.push(format!("Incomplete uploads:\t{}", v.incomplete_uploads));
+			table.push(format!(
+				"Incomplete upload size:\t{}",
+				bytesize::ByteSize::b(v.incomplete_upload_bytes)
+			));
+			table.push(format!("Non-current versions:\t{}", v.noncurrent_versions));
+			table.push(format!(
+				"Non-current version size:\t{}",
+				bytesize::ByteSize::b(v.noncurrent_version_bytes)
+			));
+		}
+		if let Some(b) = &usage.blocks {
+			table.push(format!("Data blocks:\t{}", b.blocks));
+			table.push(format!(
+				"Data block size:\t{}",
+				bytesize::ByteSize::b(b.block_bytes)
+			));
+		}
+
+		let mut ret = format!("Storage usage of bucket {:?}:\n", bucket_id);
+		ret += &format_table_to_string(table);
+		let age = computed_at.elapsed().as_secs();
+		if age > 0 {
+			write!(&mut ret, "\n(computed {}s ago)", age).unwrap();
+		}
+		Ok(AdminRpc::Ok(ret))
+	}
+
+	async fn handle_bucket_list_objects(&self, query: &ListObjectsOpt) -> Result<AdminRpc, Error> {
+		let bucket_id = self
+			.garage
+			.bucket_helper()
+			.resolve_global_bucket_name(&query.bucket)
+			.await?
+			.ok_or_bad_request("Bucket not found")?;
+
+		let mut lines = vec![];
+		if !query.json {
+			lines.push("Key\tSize\tLast modified\tETag".to_string());
+		}
+		let mut last_common_prefix: Option<String> = None;
+		let mut start = query.prefix.clone();
+		let mut first_page = true;
+
+		loop {
+			let objects = self
+				.garage
+				.object_table
+				.get_range(
+					&bucket_id,
+					Some(start.clone()),
+					Some(ObjectFilter::IsData),
+					1000,
+					EnumerationOrder::Forward,
+				)
+				.await?;
+			let n_objects = objects.len();
+			let last_key = objects.last().map(|o| o.key.clone());
+
+			for object in objects {
+				if !object.key.starts_with(&query.prefix) {
+					return Ok(AdminRpc::Ok(format_object_list(lines, query.json)));
+				}
+				// The first object of a page is the last one of the previous page
+				if !first_page && object.key == start {
+					continue;
+				}
+
+				if let Some(delimiter) = &query.delimiter {
+					if let Some(i) = object.key[query.prefix.len()..].find(delimiter.as_str()) {
+						let common_prefix = &object.key[..query.prefix.len() + i + delimiter.len()];
+						if last_common_prefix.as_deref() != Some(common_prefix) {
+							lines.push(if query.json {
+								serde_json::json!({ "prefix": common_prefix }).to_string()
+							} else {
+								format!("{}\t(prefix)\t\t", common_prefix)
+							});
+							last_common_prefix = Some(common_prefix.to_string());
+						}
+						continue;
+					}
+				}
+
+				let (version, meta) = match object.versions().iter().rev().find(|v| v.is_complete())
+				{
+					Some(v) => match &v.state {
+						ObjectVersionState::Complete(ObjectVersionData::Inline(meta, _))
+						| ObjectVersionState::Complete(ObjectVersionData::FirstBlock(meta, _)) => (v, meta),
+						_ => continue,
+					},
+					None => continue,
+				};
+				let last_modified = msec_to_rfc3339(version.timestamp);
+				lines.push(if query.json {
+					serde_json::json!({
+						"key": object.key,
+						"etag": meta.etag,
+						"size": meta.size,
+						"last_modified": last_modified,
+						"storage_class": "STANDARD",
+						"version_id": hex::encode(version.uuid),
+					})
+					.to_string()
+				} else {
+					format!(
+						"{}\t{}\t{}\t{}",
+						object.key,
+						bytesize::ByteSize::b(meta.size),
+						last_modified,
+						meta.etag
+					)
+				});
+			}
+
+			match last_key {
+				Some(k) if n_objects >= 1000 => start = k,
+				_ => break,
+			}
+			first_page = false;
+		}
+
+		Ok(AdminRpc::Ok(format_object_list(lines, query.json)))
+	}
+}
+
+fn format_object_list(lines: Vec<String>, json: bool) -> String {
+	if json {
+		lines.join("\n")
+	} else {
+		format_table_to_string(lines)
+	}
 }
@@ -0,0 +1,1628 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use futures::future::join_all;
+use rand::Rng;
+use serde::Serialize;
+
+use format_table::format_table_to_string;
+
+use garage_util::background::WorkerState;
+use garage_util::data::*;
+use garage_util::time::*;
+
+use garage_block::repair::BlockStoreIterator;
+
+use garage_table::replication::TableReplication;
+use garage_table::*;
+
+use garage_rpc::ring::Ring;
+use garage_rpc::system::KnownNodeInfo;
+
+use garage_model::helper::error::{Error, OkOrBadRequest};
+use garage_model::key_table::*;
+use garage_model::s3::object_table::*;
+use garage_model::s3::version_table::Version;
+
+use garage_api::s3::delete::handle_delete_internal;
+use garage_api::s3::encryption::{is_ssec_encrypted, SSEC_BLOCK_OVERHEAD};
+use garage_api::s3::put::save_stream;
+
+use crate::cli::*;
+use crate::repair::online::DbCompactionWorker;
+
+use super::*;
+
+/// Number of keys read at once by `garage debug analyze-keys`
+const ANALYZE_KEYS_BATCH_SIZE: usize = 1000;
+
+impl AdminRpcHandler {
+	pub(super) async fn handle_debug_cmd(&self, cmd: &DebugOperation) -> Result<AdminRpc, Error> {
+		match cmd {
+			DebugOperation::ExplainRing { bucket, object } => {
+				self.handle_explain_ring(bucket, object).await
+			}
+			DebugOperation::ShowPartitionLayout { partition_key } => {
+				self.handle_show_partition_layout(partition_key).await
+			}
+			DebugOperation::CompactAllTables(opt) => self.handle_compact_tables(opt).await,
+			DebugOperation::AnalyzeKeys(opt) => self.handle_analyze_keys(opt).await,
+			DebugOperation::CheckLayoutConsistency => self.handle_check_layout_consistency().await,
+			DebugOperation::CheckNetworkPartition => self.handle_check_network_partition().await,
+			DebugOperation::TraceRpc {
+				node,
+				operation,
+				count,
+			} => self.handle_trace_rpc(node, operation, *count).await,
+			DebugOperation::TestReplication { bucket, size_bytes } => {
+				self.handle_test_replication(bucket, *size_bytes).await
+			}
+			DebugOperation::CheckVersionBlockRefConsistency { node } => {
+				self.handle_check_version_block_ref_consistency(node).await
+			}
+			DebugOperation::CheckPartialWrites { fix } => {
+				self.handle_check_partial_writes(*fix).await
+			}
+			DebugOperation::PartitionStats { table, top } => {
+				self.handle_partition_stats(table, *top)
+			}
+			DebugOperation::DumpBgVars { json } => self.handle_dump_bg_vars(*json),
+			DebugOperation::ListBgWorkers => self.handle_list_bg_workers(),
+			DebugOperation::CheckSledIntegrity(_) => Err(Error::BadRequest(
+				"The Sled integrity check must be run on a stopped node.".to_string(),
+			)),
+			DebugOperation::EstimateRepairTime { repair_type } => {
+				self.handle_estimate_repair_time(repair_type).await
+			}
+			DebugOperation::ListNodesByCapacity {
+				descending,
+				threshold,
+			} => {
+				self.handle_list_nodes_by_capacity(*descending, *threshold)
+					.await
+			}
+		}
+	}
+
+	async fn handle_compact_tables(&self, opt: &CompactTablesOpt) -> Result<AdminRpc, Error> {
+		if !opt.background {
+			return Err(Error::BadRequest(
+				"Compaction must be run offline, or with the --background flag.".to_string(),
+			));
+		}
+		if !opt.yes {
+			return Err(Error::BadRequest(
+				"Please provide the --yes flag to launch the compaction.".to_string(),
+			));
+		}
+		if let Some(table) = &opt.table {
+			crate::repair::offline::table_trees(&self.garage.db, table)
+				.map_err(|e| Error::BadRequest(e.to_string()))?;
+		}
+
+		self.background.spawn_worker(DbCompactionWorker::new(
+			self.garage.clone(),
+			opt.table.clone(),
+		));
+		Ok(AdminRpc::Ok(format!(
+			"Compaction of {} launched on {:?}. Use `garage worker list` to follow its progress.",
+			match &opt.table {
+				Some(table) => format!("table {}", table),
+				None => "the metadata database".into(),
+			},
+			self.garage.system.id,
+		)))
+	}
+
+	async fn handle_analyze_keys(&self, opt: &AnalyzeKeysOpt) -> Result<AdminRpc, Error> {
+		if opt.delete_unused && !opt.confirm {
+			return Err(Error::BadRequest(
+				"Please provide the --confirm flag to disable unused keys.".to_string(),
+			));
+		}
+		let duration = parse_duration::parse::parse(&opt.unused_since)
+			.ok_or_bad_request("Invalid duration passed for --unused-since parameter")?;
+		let threshold = now_msec().saturating_sub(duration.as_millis() as u64);
+
+		let mut ret = vec![];
+		let mut start: Option<String> = None;
+		loop {
+			let keys = self
+				.garage
+				.key_table
+				.get_range(
+					&EmptyKey,
+					start.clone(),
+					Some(KeyFilter::Deleted(DeletedFilter::NotDeleted)),
+					ANALYZE_KEYS_BATCH_SIZE,
+					EnumerationOrder::Forward,
+				)
+				.await?;
+			let n_keys = keys.len();
+			let last_key_id = keys.last().map(|k| k.key_id.clone());
+
+			for key in keys {
+				// The first key of a page is the last one of the previous page
+				if start.as_ref() == Some(&key.key_id) {
+					continue;
+				}
+				if let Some(info) = self.analyze_key(opt, threshold, key).await? {
+					ret.push(info);
+				}
+			}
+
+			if n_keys < ANALYZE_KEYS_BATCH_SIZE {
+				break;
+			}
+			start = last_key_id;
+		}
+
+		Ok(AdminRpc::UnusedKeys(ret))
+	}
+
+	/// Report a key if it has not been used since `threshold`,
+	/// and disable it if requested
+	async fn analyze_key(
+		&self,
+		opt: &AnalyzeKeysOpt,
+		threshold: u64,
+		key: Key,
+	) -> Result<Option<UnusedKeyInfo>, Error> {
+		let params = key.params().unwrap();
+		// Keys that were created before usage was recorded and that were not
+		// used since then are reported, but never disabled automatically
+		let last_activity = std::cmp::max(params.created, params.last_used);
+		if last_activity >= threshold {
+			return Ok(None);
+		}
+
+		let mut disabled = false;
+		if opt.delete_unused && last_activity > 0 && key.is_enabled() {
+			self.garage
+				.key_table
+				.atomic_update(&EmptyKey, &key.key_id, |key| {
+					let mut key = key?;
+					key.params_mut()?.enabled.update(false);
+					Some(key)
+				})
+				.await?;
+			disabled = true;
+		}
+
+		let date = |t: u64| {
+			if t > 0 {
+				Some(msec_to_rfc3339(t))
+			} else {
+				None
+			}
+		};
+		Ok(Some(UnusedKeyInfo {
+			key_id: key.key_id.clone(),
+			name: params.name.get().clone(),
+			created: date(params.created),
+			last_used: date(params.last_used),
+			authorized_buckets: params.authorized_buckets.items().len(),
+			allow_create_bucket: *params.allow_create_bucket.get(),
+			disabled,
+		}))
+	}
+
+	async fn handle_list_nodes_by_capacity(
+		&self,
+		descending: bool,
+		threshold: Option<u64>,
+	) -> Result<AdminRpc, Error> {
+		let ring = self.garage.system.ring.borrow().clone();
+		let mut nodes = ring
+			.layout
+			.roles
+			.items()
+			.iter()
+			.filter_map(|(id, _, role)| {
+				let role = role.0.as_ref()?;
+				Some((*id, role.zone.clone(), role.capacity?))
+			})
+			.collect::<Vec<_>>();
+		nodes.sort_by_key(|(_, _, capacity)| *capacity);
+		if descending {
+			nodes.reverse();
+		}
+
+		// Ask each node for its current disk usage, instead of relying
+		// on the status it last advertised
+		let statuses = join_all(nodes.iter().map(|(id, _, _)| async move {
+			self.endpoint
+				.call(&(*id).into(), AdminRpc::GetNodeStatus, PRIO_NORMAL)
+				.await
+		}))
+		.await;
+
+		let mut table = vec!["ID\tZone\tCapacity\tDataFree\tDataUsed\tUtilization\t".to_string()];
+		let mut above_threshold = 0;
+		for ((id, zone, capacity), status) in nodes.iter().zip(statuses) {
+			let disk_avail = match status {
+				Ok(Ok(AdminRpc::NodeStatus(s))) => s.data_disk_avail,
+				_ => None,
+			};
+			let (free, used, utilization, flag) = match disk_avail {
+				Some((avail, total)) => {
+					let used = total.saturating_sub(avail);
+					let pct = (used as f64) / (total as f64) * 100.;
+					let over = threshold.map(|t| pct > t as f64).unwrap_or(false);
+					if over {
+						above_threshold += 1;
+					}
+					(
+						bytesize::ByteSize::b(avail).to_string(),
+						bytesize::ByteSize::b(used).to_string(),
+						format!("{:.1}%", pct),
+						if over { "*" } else { "" },
+					)
+				}
+				None => ("?".into(), "?".into(), "?".into(), ""),
+			};
+			table.push(format!(
+				"{:?}\t{}\t{}\t{}\t{}\t{}\t{}",
+				id, zone, capacity, free, used, utilization, flag
+			));
+		}
+
+		let mut ret = format_table_to_string(table);
+		if let Some(t) = threshold {
+			writeln!(
+				&mut ret,
+				"\n{} node(s) above {}% utilization (marked with *).",
+				above_threshold, t
+			)
+			.unwrap();
+		}
+		Ok(AdminRpc::Ok(ret))
+	}
+
+	async fn handle_check_layout_consistency(&self) -> Result<AdminRpc, Error> {
+		let nodes = self
+			.garage
+			.system
+			.get_known_nodes()
+			.into_iter()
+			.filter(|n| n.is_up)
+			.collect::<Vec<_>>();
+
+		let statuses = join_all(nodes.iter().map(|n| async move {
+			self.endpoint
+				.call(&n.id.into(), AdminRpc::GetLayoutStatus, PRIO_NORMAL)
+				.await
+		}))
+		.await;
+
+		let mut table = vec!["ID\tHostname\tLayout version\tStaged changes".to_string()];
+		let mut layouts = vec![];
+		let mut failed = vec![];
+		for (node, status) in nodes.iter().zip(statuses) {
+			match status {
+				Ok(Ok(AdminRpc::LayoutStatus(s))) => {
+					table.push(format!(
+						"{:?}\t{}\t{}\t{}",
+						node.id, node.status.hostname, s.version, s.staged_changes
+					));
+					layouts.push((node.id, s));
+				}
+				_ => {
+					table.push(format!("{:?}\t{}\t?\t?", node.id, node.status.hostname));
+					failed.push(node.id);
+				}
+			}
+		}
+
+		let mut ret = format_table_to_string(table);
+		if !failed.is_empty() {
+			writeln!(
+				&mut ret,
+				"\nCould not get layout from {} node(s): {:?}",
+				failed.len(),
+				failed
+			)
+			.unwrap();
+		}
+		if layouts.is_empty() {
+			return Ok(AdminRpc::Ok(ret));
+		}
+
+		let mut versions = HashMap::<u64, usize>::new();
+		for (_, s) in layouts.iter() {
+			*versions.entry(s.version).or_default() += 1;
+		}
+		let latest = layouts.iter().map(|(_, s)| s.version).max().unwrap();
+		if versions.len() == 1 {
+			writeln!(
+				&mut ret,
+				"\nAll {} node(s) have layout version {}.",
+				layouts.len(),
+				latest
+			)
+			.unwrap();
+		} else {
+			let (majority, count) = versions
+				.iter()
+				.max_by_key(|(v, c)| (**c, **v))
+				.map(|(v, c)| (*v, *c))
+				.unwrap();
+			writeln!(
+				&mut ret,
+				"\nWARNING: nodes do not agree on the cluster layout: {} of {} node(s) have version {}, the latest version is {}.",
+				count,
+				layouts.len(),
+				majority,
+				latest
+			)
+			.unwrap();
+			let stale = layouts
+				.iter()
+				.filter(|(_, s)| s.version < latest)
+				.map(|(id, s)| format!("  {:?} (version {})", id, s.version))
+				.collect::<Vec<_>>();
+			writeln!(&mut ret, "Nodes with a stale layout:\n{}", stale.join("\n")).unwrap();
+		}
+
+		let staged = layouts
+			.iter()
+			.filter(|(_, s)| s.staged_changes > 0)
+			.collect::<Vec<_>>();
+		if !staged.is_empty() {
+			writeln!(
+				&mut ret,
+				"\n{} node(s) have staged layout changes that are not applied:",
+				staged.len()
+			)
+			.unwrap();
+			for (id, s) in staged.iter() {
+				writeln!(
+					&mut ret,
+					"  {:?}: {} change(s), staging hash {}",
+					id,
+					s.staged_changes,
+					hex::encode(s.staging_hash)
+				)
+				.unwrap();
+			}
+			if staged.len() == layouts.len()
+				&& staged
+					.iter()
+					.all(|(_, s)| s.staging_hash == staged[0].1.staging_hash)
+			{
+				writeln!(
+					&mut ret,
+					"Staged changes are present on all nodes, use `garage layout show` to review them and `garage layout apply --version {}` to apply them.",
+					latest + 1
+				)
+				.unwrap();
+			}
+		}
+
+		Ok(AdminRpc::Ok(ret))
+	}
+
+	async fn handle_check_network_partition(&self) -> Result<AdminRpc, Error> {
+		let layout = self.garage.system.get_cluster_layout();
+		let cluster_nodes = layout
+			.roles
+			.items()
+			.iter()
+			.filter(|(_, _, v)| v.0.is_some())
+			.map(|(id, _, _)| *id)
+			.collect::<BTreeSet<Uuid>>();
+		let majority = cluster_nodes.len() / 2 + 1;
+
+		let nodes = self
+			.garage
+			.system
+			.get_known_nodes()
+			.into_iter()
+			.filter(|n| n.is_up)
+			.collect::<Vec<_>>();
+		let views = join_all(nodes.iter().map(|n| async move {
+			self.endpoint
+				.call(&n.id.into(), AdminRpc::GetOnlineNodes, PRIO_NORMAL)
+				.await
+		}))
+		.await;
+
+		// For each node that answered: the nodes of the cluster it sees online,
+		// and whether they are a majority of the cluster
+		let mut table = vec!["ID\tHostname\tSees\tMajority".to_string()];
+		let mut node_views = vec![];
+		let mut failed = vec![];
+		for (node, view) in nodes.iter().zip(views) {
+			match view {
+				Ok(Ok(AdminRpc::OnlineNodes(online))) => {
+					let view = online
+						.into_iter()
+						.filter(|id| cluster_nodes.contains(id))
+						.collect::<BTreeSet<Uuid>>();
+					let has_majority = view.len() >= majority;
+					table.push(format!(
+						"{:?}\t{}\t{}/{}\t{}",
+						node.id,
+						node.status.hostname,
+						view.len(),
+						cluster_nodes.len(),
+						if has_majority { "yes" } else { "no" }
+					));
+					node_views.push((node.id, view, has_majority));
+				}
+				_ => {
+					table.push(format!("{:?}\t{}\t?\t?", node.id, node.status.hostname));
+					failed.push(node.id);
+				}
+			}
+		}
+
+		let mut ret = format_table_to_string(table);
+		if !failed.is_empty() {
+			writeln!(
+				&mut ret,
+				"\nCould not get the view of {} node(s): {:?}",
+				failed.len(),
+				failed
+			)
+			.unwrap();
+		}
+
+		// Nodes that see the same set of nodes form a group
+		let mut groups = HashMap::<&BTreeSet<Uuid>, Vec<Uuid>>::new();
+		for (id, view, _) in node_views.iter() {
+			groups.entry(view).or_default().push(*id);
+		}
+
+		// Two nodes that both see a majority of the cluster but don't see
+		// each other could both accept writes: this is a split-brain
+		let mut split = vec![];
+		for (i, (a, view_a, majority_a)) in node_views.iter().enumerate() {
+			for (b, view_b, majority_b) in node_views.iter().skip(i + 1) {
+				if *majority_a && *majority_b && !view_a.contains(b) && !view_b.contains(a) {
+					split.push((*a, *b));
+				}
+			}
+		}
+
+		if split.is_empty() && groups.len() <= 1 {
+			writeln!(
+				&mut ret,
+				"\nNo network partition detected: all {} node(s) that answered see the same nodes.",
+				node_views.len()
+			)
+			.unwrap();
+		} else if split.is_empty() {
+			writeln!(
+				&mut ret,
+				"\nNodes do not all see the same nodes, but no two groups both see a majority of the cluster ({} of {} nodes):",
+				majority,
+				cluster_nodes.len()
+			)
+			.unwrap();
+		} else {
+			writeln!(
+				&mut ret,
+				"\nWARNING: SPLIT-BRAIN DETECTED: some nodes that do not see each other both see a majority of the cluster ({} of {} nodes):",
+				majority,
+				cluster_nodes.len()
+			)
+			.unwrap();
+			for (a, b) in split.iter() {
+				writeln!(&mut ret, "  {:?} <-/-> {:?}", a, b).unwrap();
+			}
+		}
+		if groups.len() > 1 {
+			for (i, (view, members)) in groups.iter().enumerate() {
+				writeln!(
+					&mut ret,
+					"  Group {}: {:?} see {} node(s): {:?}",
+					i + 1,
+					members,
+					view.len(),
+					view
+				)
+				.unwrap();
+			}
+		}
+
+		Ok(AdminRpc::Ok(ret))
+	}
+
+	async fn handle_check_version_block_ref_consistency(
+		&self,
+		node: &Option<String>,
+	) -> Result<AdminRpc, Error> {
+		if let Some(node) = node {
+			let ring = self.garage.system.ring.borrow().clone();
+			let node_id = find_matching_node(ring.layout.node_ids().iter().cloned(), node)?;
+			if node_id != self.garage.system.id {
+				let cmd = DebugOperation::CheckVersionBlockRefConsistency { node: None };
+				return match self
+					.endpoint
+					.call(&node_id.into(), AdminRpc::DebugOperation(cmd), PRIO_NORMAL)
+					.await?
+				{
+					Ok(resp) => Ok(resp),
+					Err(e) => Err(Error::BadRequest(format!("Remote error: {}", e))),
+				};
+			}
+		}
+
+		let mut report = VersionBlockRefReport {
+			node: hex::encode(self.garage.system.id),
+			versions: 0,
+			pairs: 0,
+			missing_block_refs: vec![],
+			missing_blocks: vec![],
+		};
+		let mut block_stored = HashMap::<Hash, bool>::new();
+
+		let mut pos = vec![];
+		while let Some((k, v)) = self
+			.garage
+			.version_table
+			.data
+			.store
+			.get_gt(&pos)
+			.map_err(GarageError::from)?
+		{
+			pos = k;
+			let version = self.garage.version_table.data.decode_entry(&v)?;
+			if version.deleted.get() {
+				continue;
+			}
+			report.versions += 1;
+
+			for (_, vb) in version.blocks.items().iter() {
+				report.pairs += 1;
+				let pair = VersionBlockPair {
+					version: hex::encode(version.uuid),
+					block: hex::encode(vb.hash),
+				};
+
+				let block_ref = self
+					.garage
+					.block_ref_table
+					.get(&vb.hash, &version.uuid)
+					.await?;
+				if !block_ref.map(|br| !br.deleted.get()).unwrap_or(false) {
+					report.missing_block_refs.push(pair.clone());
+				}
+
+				let stored = match block_stored.get(&vb.hash) {
+					Some(stored) => *stored,
+					None => {
+						let locations = self
+							.garage
+							.block_manager
+							.get_block_locations(&vb.hash)
+							.await?;
+						block_stored.insert(vb.hash, !locations.is_empty());
+						!locations.is_empty()
+					}
+				};
+				if !stored {
+					report.missing_blocks.push(pair);
+				}
+			}
+		}
+
+		let json = serde_json::to_string_pretty(&report).map_err(GarageError::from)?;
+		Ok(AdminRpc::Ok(json))
+	}
+
+	async fn handle_check_partial_writes(&self, fix: bool) -> Result<AdminRpc, Error> {
+		let mut block_iter = BlockStoreIterator::new(&self.garage.block_manager);
+		let mut checked = 0;
+		let mut unreferenced = 0;
+		let mut errors = vec![];
+		let mut table = vec!["Block\tExpected size\tData size\tFile".to_string()];
+
+		while let Some(hash) = block_iter.next().await? {
+			let expected_size = match self.referenced_block_size(&hash).await? {
+				Some(s) => s,
+				None => {
+					unreferenced += 1;
+					continue;
+				}
+			};
+			checked += 1;
+
+			match self
+				.garage
+				.block_manager
+				.check_block_file_size(&hash, expected_size, fix)
+				.await
+			{
+				Ok(None) => (),
+				Ok(Some((path, size))) => table.push(format!(
+					"{}\t{}\t{}\t{}",
+					hex::encode(hash),
+					expected_size,
+					size.map(|s| s.to_string())
+						.unwrap_or_else(|| "unreadable".into()),
+					path.display()
+				)),
+				// The block might have been deleted since it was listed
+				Err(e) => errors.push(format!("{}: {}", hex::encode(hash), e)),
+			}
+		}
+
+		let partial = table.len() - 1;
+		let mut ret = format!(
+			"{} block files checked, {} partially written.\n",
+			checked, partial
+		);
+		if unreferenced > 0 {
+			writeln!(
+				&mut ret,
+				"{} block files were not checked, as no version references them.",
+				unreferenced
+			)
+			.unwrap();
+		}
+		if !errors.is_empty() {
+			writeln!(&mut ret, "\nCould not check {} block files:", errors.len()).unwrap();
+			for e in errors {
+				writeln!(&mut ret, "  {}", e).unwrap();
+			}
+		}
+		if partial > 0 {
+			write!(&mut ret, "\n{}", format_table_to_string(table)).unwrap();
+			if fix {
+				writeln!(
+					&mut ret,
+					"\nThese files were deleted, the blocks will be fetched again by the resync worker."
+				)
+				.unwrap();
+			} else {
+				writeln!(
+					&mut ret,
+					"\nUse --fix to delete these files and fetch the blocks again from other nodes."
+				)
+				.unwrap();
+			}
+		}
+		Ok(AdminRpc::Ok(ret))
+	}
+
+	/// Size of a block, as recorded in a version that references it
+	async fn referenced_block_size(&self, hash: &Hash) -> Result<Option<u64>, Error> {
+		let block_refs = self
+			.garage
+			.block_ref_table
+			.get_range(
+				hash,
+				None,
+				Some(DeletedFilter::NotDeleted),
+				10,
+				EnumerationOrder::Forward,
+			)
+			.await?;
+		for block_ref in block_refs {
+			let version = match self
+				.garage
+				.version_table
+				.get(&block_ref.version, &EmptyKey)
+				.await?
+			{
+				Some(v) => v,
+				None => continue,
+			};
+			let size = version
+				.blocks
+				.items()
+				.iter()
+				.find(|(_, vb)| vb.hash == *hash)
+				.map(|(_, vb)| vb.size);
+			if let Some(size) = size {
+				// The size of a version block is the size of its data before
+				// its encryption, which adds a nonce and a tag to SSE-C blocks
+				return match self.is_ssec_version(&version).await? {
+					true => Ok(Some(size + SSEC_BLOCK_OVERHEAD as u64)),
+					false => Ok(Some(size)),
+				};
+			}
+		}
+		Ok(None)
+	}
+
+	/// Check whether a version belongs to an object encrypted with SSE-C
+	async fn is_ssec_version(&self, version: &Version) -> Result<bool, Error> {
+		let object = self
+			.garage
+			.object_table
+			.get(&version.bucket_id, &version.key)
+			.await?;
+		let object_version = object
+			.as_ref()
+			.and_then(|o| o.versions().iter().find(|v| v.uuid == version.uuid));
+		Ok(match object_version.map(|v| &v.state) {
+			Some(ObjectVersionState::Uploading(headers)) => is_ssec_encrypted(headers),
+			Some(ObjectVersionState::Complete(ObjectVersionData::FirstBlock(meta, _))) => {
+				is_ssec_encrypted(&meta.headers)
+			}
+			_ => false,
+		})
+	}
+
+	fn handle_partition_stats(&self, table: &str, top: usize) -> Result<AdminRpc, Error> {
+		let counts = match table {
+			"bucket_v2" => self.garage.bucket_table.count_by_partition(),
+			"bucket_alias" => self.garage.bucket_alias_table.count_by_partition(),
+			"key" => self.garage.key_table.count_by_partition(),
+			"object" => self.garage.object_table.count_by_partition(),
+			"version" => self.garage.version_table.count_by_partition(),
+			"block_ref" => self.garage.block_ref_table.count_by_partition(),
+			_ => return Err(Error::BadRequest(format!("No such table: {}", table))),
+		}?;
+
+		let total = counts.values().sum::<u64>();
+		let mut counts = counts.into_iter().collect::<Vec<_>>();
+		counts.sort_by_key(|(_, c)| std::cmp::Reverse(*c));
+
+		let ring = self.garage.system.ring.borrow().clone();
+		let mut ret = String::new();
+		writeln!(
+			&mut ret,
+			"Table {}: {} entries in {} partition keys on this node\n",
+			table,
+			total,
+			counts.len()
+		)
+		.unwrap();
+		let mut out = vec!["Partition key hash\tPartition\tEntries\tShare".to_string()];
+		for (hash, count) in counts.iter().take(top) {
+			out.push(format!(
+				"{}\t{}\t{}\t{:.1}%",
+				hex::encode(hash),
+				ring.partition_of(hash),
+				count,
+				(*count as f64) / (total as f64) * 100.
+			));
+		}
+		write!(&mut ret, "{}", format_table_to_string(out)).unwrap();
+
+		Ok(AdminRpc::Ok(ret))
+	}
+
+	fn handle_dump_bg_vars(&self, json: bool) -> Result<AdminRpc, Error> {
+		let vars = self.garage.bg_vars.dump()?;
+
+		if json {
+			let ret = serde_json::to_string_pretty(&vars).map_err(GarageError::from)?;
+			return Ok(AdminRpc::Ok(ret));
+		}
+
+		let mut table = vec!["Name\tValue\tDefault\tSource\tExpires".to_string()];
+		for v in vars.iter() {
+			let expires = match v.expires_at {
+				Some(t) => msec_to_rfc3339(t),
+				None => String::new(),
+			};
+			table.push(format!(
+				"{}\t{}\t{}\t{}\t{}",
+				v.name, v.value, v.default, v.source, expires
+			));
+		}
+		Ok(AdminRpc::Ok(format_table_to_string(table)))
+	}
+
+	fn handle_list_bg_workers(&self) -> Result<AdminRpc, Error> {
+		let mut workers = self
+			.background
+			.get_worker_info()
+			.into_iter()
+			.collect::<Vec<_>>();
+		workers.sort_by_key(|(tid, _)| *tid);
+
+		let tf = timeago::Formatter::new();
+		let mut table = vec!["TID\tName\tState\tIterations\tAvg. duration\tLast run".to_string()];
+		for (tid, info) in workers.iter() {
+			let state = match info.state {
+				WorkerState::Busy => "running",
+				WorkerState::Throttled(_) if info.consecutive_errors > 0 => "failed",
+				WorkerState::Throttled(_) => "paused",
+				WorkerState::Idle => "idle",
+				WorkerState::Done => "done",
+			};
+			let avg_duration = match info.iterations {
+				0 => "-".to_string(),
+				n => format!("{:.3}ms", info.work_time.as_secs_f64() * 1000. / n as f64),
+			};
+			let last_run = match info.last_run {
+				Some(t) => tf.convert(Duration::from_millis(now_msec().saturating_sub(t))),
+				None => "never".to_string(),
+			};
+			table.push(format!(
+				"{}\t{}\t{}\t{}\t{}\t{}",
+				tid, info.name, state, info.iterations, avg_duration, last_run
+			));
+		}
+		Ok(AdminRpc::Ok(format_table_to_string(table)))
+	}
+
+	async fn handle_estimate_repair_time(&self, repair_type: &str) -> Result<AdminRpc, Error> {
+		let mut estimate = RepairTimeEstimate {
+			repair_type: repair_type.to_string(),
+			node: hex::encode(self.garage.system.id),
+			entries: 0,
+			sampled_entries: 0,
+			sample_duration_msec: 0,
+			estimated_duration_secs: 0,
+			estimated_duration: String::new(),
+			assumptions: vec![],
+		};
+		let mut sample_duration = Duration::ZERO;
+
+		match repair_type {
+			"full-sync" => {
+				let tables = [
+					self.time_table_reads(&self.garage.bucket_table)?,
+					self.time_table_reads(&self.garage.bucket_alias_table)?,
+					self.time_table_reads(&self.garage.key_table)?,
+					self.time_table_reads(&self.garage.object_table)?,
+					self.time_table_reads(&self.garage.version_table)?,
+					self.time_table_reads(&self.garage.block_ref_table)?,
+				];
+				let mut estimated = 0f64;
+				for (entries, sampled, elapsed) in tables {
+					estimate.entries += entries as u64;
+					estimate.sampled_entries += sampled as u64;
+					sample_duration += elapsed;
+					estimated += extrapolate(entries, sampled, elapsed);
+				}
+				estimate.estimated_duration_secs = estimated as u64;
+				estimate.assumptions = vec![
+					"all entries of the bucket, bucket alias, key, object, version and block ref tables stored on this node are compared with other nodes".into(),
+					"the time to compare an entry is the time to read and decode it from the metadata database".into(),
+					"network transfers of entries that differ between nodes are not taken into account".into(),
+				];
+			}
+			"versions" => {
+				let (entries, sample) = self.sample_table(&self.garage.version_table)?;
+				let start = Instant::now();
+				for (_, v) in sample.iter() {
+					let version = self.garage.version_table.data.decode_entry(v)?;
+					self.garage
+						.object_table
+						.get(&version.bucket_id, &version.key)
+						.await?;
+				}
+				let elapsed = start.elapsed();
+				estimate.entries = entries as u64;
+				estimate.sampled_entries = sample.len() as u64;
+				sample_duration = elapsed;
+				estimate.estimated_duration_secs =
+					extrapolate(entries, sample.len(), elapsed) as u64;
+				estimate.assumptions = vec![
+					"each version stored on this node requires a quorum read in the object table"
+						.into(),
+					"the cluster load stays the same as during sampling".into(),
+				];
+			}
+			"block-refs" => {
+				let (entries, sample) = self.sample_table(&self.garage.block_ref_table)?;
+				let start = Instant::now();
+				for (_, v) in sample.iter() {
+					let block_ref = self.garage.block_ref_table.data.decode_entry(v)?;
+					self.garage
+						.version_table
+						.get(&block_ref.version, &EmptyKey)
+						.await?;
+				}
+				let elapsed = start.elapsed();
+				estimate.entries = entries as u64;
+				estimate.sampled_entries = sample.len() as u64;
+				sample_duration = elapsed;
+				estimate.estimated_duration_secs =
+					extrapolate(entries, sample.len(), elapsed) as u64;
+				estimate.assumptions = vec![
+					"each block ref stored on this node requires a quorum read in the version table".into(),
+					"the cluster load stays the same as during sampling".into(),
+				];
+			}
+			"scrub" => {
+				let entries = self.garage.block_manager.rc_len()?;
+				let (blocks, bytes, elapsed) = self
+					.garage
+					.block_manager
+					.sample_read_speed(sample_size(entries))
+					.await?;
+				let tranquility = self
+					.garage
+					.block_manager
+					.scrub_persister
+					.get_with(|x| x.tranquility);
+				estimate.entries = entries as u64;
+				estimate.sampled_entries = blocks as u64;
+				sample_duration = elapsed;
+				estimate.estimated_duration_secs =
+					(extrapolate(entries, blocks, elapsed) * (1 + tranquility) as f64) as u64;
+				let throughput = if elapsed.is_zero() {
+					0
+				} else {
+					(bytes as f64 / elapsed.as_secs_f64()) as u64
+				};
+				estimate.assumptions = vec![
+					format!(
+						"sequential read throughput of {}/s, measured by reading the sampled blocks",
+						bytesize::ByteSize::b(throughput)
+					),
+					"the number of blocks on this node is the number of entries of the block reference counter table".into(),
+					format!(
+						"the scrub worker waits {} times the time spent reading each block (current scrub tranquility)",
+						tranquility
+					),
+				];
+			}
+			_ => {
+				return Err(Error::BadRequest(format!(
+					"Unknown repair type: {}",
+					repair_type
+				)))
+			}
+		}
+
+		if estimate.sampled_entries == 0 && estimate.entries > 0 {
+			return Err(Error::BadRequest(
+				"Could not sample any entry, no estimate can be made".into(),
+			));
+		}
+		estimate.sample_duration_msec = sample_duration.as_millis() as u64;
+		estimate.estimated_duration = format!(
+			"{}h{:02}m{:02}s",
+			estimate.estimated_duration_secs / 3600,
+			(estimate.estimated_duration_secs / 60) % 60,
+			estimate.estimated_duration_secs % 60
+		);
+
+		let json = serde_json::to_string_pretty(&estimate).map_err(GarageError::from)?;
+		Ok(AdminRpc::Ok(json))
+	}
+
+	/// Pick a random sample of the entries of a table stored on this node,
+	/// returns the total number of entries and the sampled (key, value) pairs
+	#[allow(clippy::type_complexity)]
+	fn sample_table<F: TableSchema, R: TableReplication>(
+		&self,
+		table: &Table<F, R>,
+	) -> Result<(usize, Vec<(Vec<u8>, Vec<u8>)>), Error> {
+		let entries = table.data.store.len().map_err(GarageError::from)?;
+		let mut sample = vec![];
+		for _ in 0..sample_size(entries) {
+			let pos = gen_uuid();
+			if let Some((k, v)) = table
+				.data
+				.store
+				.get_gt(pos.as_slice())
+				.map_err(GarageError::from)?
+			{
+				sample.push((k, v));
+			}
+		}
+		Ok((entries, sample))
+	}
+
+	/// Time reading and decoding a random sample of the entries of a table,
+	/// returns the number of entries, the number of sampled entries and the time taken
+	fn time_table_reads<F: TableSchema, R: TableReplication>(
+		&self,
+		table: &Table<F, R>,
+	) -> Result<(usize, usize, Duration), Error> {
+		let (entries, sample) = self.sample_table(table)?;
+		let start = Instant::now();
+		for (k, _) in sample.iter() {
+			if let Some(v) = table.data.store.get(k).map_err(GarageError::from)? {
+				table.data.decode_entry(&v)?;
+			}
+		}
+		Ok((entries, sample.len(), start.elapsed()))
+	}
+
+	async fn handle_trace_rpc(
+		&self,
+		node: &str,
+		operation: &str,
+		count: usize,
+	) -> Result<AdminRpc, Error> {
+		let node_id = find_matching_node(
+			self.garage
+				.system
+				.get_known_nodes()
+				.into_iter()
+				.map(|n| n.id),
+			node,
+		)?;
+
+		let mut timings = vec![];
+		let mut failed = 0;
+		let mut last_error = None;
+		for _ in 0..count {
+			let res = match operation {
+				"TableGet" => {
+					// Reading a random key, which is most probably absent
+					self.garage
+						.object_table
+						.traced_get(node_id, &gen_uuid(), &"trace-rpc".to_string())
+						.await
+				}
+				"BlockHas" => {
+					self.garage
+						.block_manager
+						.traced_has_block(node_id, &gen_uuid())
+						.await
+				}
+				"NodeStatus" => TracedRpc::new(&self.garage.system.rpc, &*self.endpoint)
+					.call(node_id, AdminRpc::GetNodeStatus, PRIO_NORMAL)
+					.await
+					.and_then(|(resp, timing)| match resp {
+						Ok(AdminRpc::NodeStatus(_)) => Ok(timing),
+						Ok(m) => Err(GarageError::unexpected_rpc_message(m)),
+						Err(e) => Err(GarageError::Message(e.to_string())),
+					}),
+				op => return Err(Error::BadRequest(format!("Unknown operation: {}", op))),
+			};
+			match res {
+				Ok(timing) => timings.push(timing),
+				Err(e) => {
+					failed += 1;
+					last_error = Some(e);
+				}
+			}
+		}
+
+		let mut ret = String::new();
+		writeln!(&mut ret, "Operation: {}", operation).unwrap();
+		writeln!(&mut ret, "Node: {:?}", node_id).unwrap();
+		writeln!(
+			&mut ret,
+			"Calls: {}, successful: {}, failed: {}",
+			count,
+			timings.len(),
+			failed
+		)
+		.unwrap();
+		if let Some(e) = last_error {
+			writeln!(&mut ret, "Last error: {}", e).unwrap();
+		}
+
+		if !timings.is_empty() {
+			let mut table = vec!["\tp50\tp95\tp99".to_string()];
+			let steps: [(&str, Vec<Duration>); 4] = [
+				("Round trip", timings.iter().map(|t| t.round_trip).collect()),
+				(
+					"Serialization",
+					timings.iter().map(|t| t.serialization).collect(),
+				),
+				(
+					"Deserialization",
+					timings.iter().map(|t| t.deserialization).collect(),
+				),
+				("Network", timings.iter().map(|t| t.network()).collect()),
+			];
+			for (name, mut values) in steps {
+				values.sort();
+				table.push(format!(
+					"{}\t{}\t{}\t{}",
+					name,
+					format_msec(percentile(&values, 50)),
+					format_msec(percentile(&values, 95)),
+					format_msec(percentile(&values, 99)),
+				));
+			}
+			writeln!(&mut ret).unwrap();
+			ret += &format_table_to_string(table);
+		}
+
+		Ok(AdminRpc::Ok(ret))
+	}
+
+	async fn handle_test_replication(
+		&self,
+		bucket: &String,
+		size_bytes: u64,
+	) -> Result<AdminRpc, Error> {
+		if size_bytes < garage_block::manager::INLINE_THRESHOLD as u64 {
+			return Err(Error::BadRequest(format!(
+				"The test object must be at least {} bytes long, smaller objects are not stored in data blocks.",
+				garage_block::manager::INLINE_THRESHOLD
+			)));
+		}
+		let bucket_id = self
+			.garage
+			.bucket_helper()
+			.resolve_global_bucket_name(bucket)
+			.await?
+			.ok_or_bad_request("Bucket not found")?;
+		let bucket = self
+			.garage
+			.bucket_helper()
+			.get_existing_bucket(bucket_id)
+			.await?;
+
+		let mut data = vec![0u8; size_bytes as usize];
+		rand::thread_rng().fill(&mut data[..]);
+		let data = Bytes::from(data);
+		let key = format!("garage-test-replication/{}", hex::encode(gen_uuid()));
+
+		let (version_uuid, _etag, _checksum) = save_stream(
+			self.garage.clone(),
+			ObjectVersionHeaders {
+				content_type: "application/octet-stream".into(),
+				other: Default::default(),
+			},
+			ObjectVersionLock::default(),
+			ObjectTags::default(),
+			StorageClass::Standard,
+			futures::stream::iter([Ok(data.clone())]),
+			&bucket,
+			&key,
+			None,
+			None,
+			None,
+			self.garage.block_manager.compression_level(),
+			None,
+		)
+		.await
+		.map_err(|e| GarageError::Message(format!("Could not write test object: {}", e)))?;
+
+		let res = self.check_test_replication(version_uuid, &data).await;
+
+		// Delete the test object whether the check succeeded or not,
+		// its blocks will then be garbage collected
+		let delete_res = handle_delete_internal(&self.garage, &bucket, &key, None, false).await;
+
+		let mut ret = res?;
+		match delete_res {
+			Ok(_) => writeln!(&mut ret, "\nTest object {} deleted.", key).unwrap(),
+			Err(e) => writeln!(&mut ret, "\nCould not delete test object {}: {}", key, e).unwrap(),
+		}
+		Ok(AdminRpc::Ok(ret))
+	}
+
+	/// Read each block of a version from each node that should store it,
+	/// and compare it with the data that was written
+	async fn check_test_replication(
+		&self,
+		version_uuid: Uuid,
+		data: &[u8],
+	) -> Result<String, Error> {
+		let version = self
+			.garage
+			.version_table
+			.get(&version_uuid, &EmptyKey)
+			.await?
+			.ok_or_else(|| GarageError::Message("Version of test object not found".into()))?;
+
+		// Number of blocks read, number of blocks that were correct,
+		// and total read time, for each node
+		let mut per_node: BTreeMap<Uuid, (usize, usize, Duration)> = BTreeMap::new();
+		let mut errors = vec![];
+		let mut offset = 0;
+		for (_, block) in version.blocks.items().iter() {
+			let expected = &data[offset..offset + block.size as usize];
+			offset += block.size as usize;
+
+			let nodes = self
+				.garage
+				.block_manager
+				.replication
+				.write_nodes(&block.hash);
+			let reads = nodes.iter().map(|node| async move {
+				let start = Instant::now();
+				let res = self
+					.garage
+					.block_manager
+					.rpc_get_block_from(&block.hash, *node)
+					.await;
+				(*node, res, start.elapsed())
+			});
+			for (node, res, time) in join_all(reads).await {
+				let stats = per_node.entry(node).or_default();
+				stats.0 += 1;
+				stats.2 += time;
+				match res {
+					Ok(bytes) if bytes[..] == *expected => stats.1 += 1,
+					Ok(_) => errors.push(format!(
+						"{:?}: block {:?} has wrong content",
+						node, block.hash
+					)),
+					Err(e) => errors.push(format!(
+						"{:?}: could not read block {:?}: {}",
+						node, block.hash, e
+					)),
+				}
+			}
+		}
+
+		let mut ret = String::new();
+		writeln!(
+			&mut ret,
+			"Test object of {} bytes written in {} blocks, replication factor {}.\n",
+			data.len(),
+			version.blocks.len(),
+			self.garage.replication_mode.replication_factor()
+		)
+		.unwrap();
+
+		let mut table = vec!["Node\tBlocks\tCorrect\tAvg. latency\tResult".to_string()];
+		for (node, (read, ok, time)) in per_node.iter() {
+			table.push(format!(
+				"{:?}\t{}\t{}\t{:.1}ms\t{}",
+				node,
+				read,
+				ok,
+				time.as_secs_f64() * 1000. / *read as f64,
+				if read == ok { "ok" } else { "FAILED" }
+			));
+		}
+		ret += &format_table_to_string(table);
+
+		if errors.is_empty() {
+			writeln!(
+				&mut ret,
+				"\nAll {} nodes returned the correct data.",
+				per_node.len()
+			)
+			.unwrap();
+		} else {
+			writeln!(&mut ret, "\nErrors:").unwrap();
+			for e in errors {
+				writeln!(&mut ret, "  {}", e).unwrap();
+			}
+		}
+		Ok(ret)
+	}
+
+	async fn handle_explain_ring(
+		&self,
+		bucket: &String,
+		object: &String,
+	) -> Result<AdminRpc, Error> {
+		// The bucket table is fully replicated, so this is resolved locally
+		// even if other nodes of the cluster are unreachable
+		let bucket_id = self
+			.garage
+			.bucket_helper()
+			.resolve_global_bucket_name(bucket)
+			.await?
+			.ok_or_bad_request("Bucket not found")?;
+
+		// Placement is computed from the ring cached on this node,
+		// no other node needs to be contacted for this part.
+		let ring = self.garage.system.ring.borrow().clone();
+		let known_nodes = self
+			.garage
+			.system
+			.get_known_nodes()
+			.into_iter()
+			.map(|n| (n.id, n))
+			.collect::<HashMap<_, _>>();
+
+		let mut ret = String::new();
+		writeln!(&mut ret, "Bucket: {}", hex::encode(bucket_id)).unwrap();
+		writeln!(&mut ret, "Object: {}", object).unwrap();
+		writeln!(&mut ret, "Cluster layout version: {}", ring.layout.version).unwrap();
+
+		let object_hash = bucket_id.hash();
+		let object_nodes = self
+			.garage
+			.object_table
+			.data
+			.replication
+			.write_nodes(&object_hash);
+		writeln!(&mut ret, "\nObject metadata (object table):").unwrap();
+		write_partition_info(&mut ret, &ring, &known_nodes, &object_hash, &object_nodes);
+
+		let object_entry = match self.garage.object_table.get(&bucket_id, object).await {
+			Ok(o) => o,
+			Err(e) => {
+				writeln!(
+					&mut ret,
+					"\nCould not read object metadata, some nodes might be unreachable: {}",
+					e
+				)
+				.unwrap();
+				return Ok(AdminRpc::Ok(ret));
+			}
+		};
+		let version = match object_entry.as_ref().and_then(|o| o.current_data_version()) {
+			Some(v) => v,
+			None => {
+				writeln!(&mut ret, "\nObject does not exist in bucket.").unwrap();
+				return Ok(AdminRpc::Ok(ret));
+			}
+		};
+
+		let version_hash = version.uuid.hash();
+		let version_nodes = self
+			.garage
+			.version_table
+			.data
+			.replication
+			.write_nodes(&version_hash);
+		writeln!(
+			&mut ret,
+			"\nCurrent version {} (version table):",
+			hex::encode(version.uuid)
+		)
+		.unwrap();
+		write_partition_info(&mut ret, &ring, &known_nodes, &version_hash, &version_nodes);
+
+		if let ObjectVersionState::Complete(ObjectVersionData::Inline(_, _)) = &version.state {
+			writeln!(
+				&mut ret,
+				"\nObject data is stored inline in object metadata."
+			)
+			.unwrap();
+			return Ok(AdminRpc::Ok(ret));
+		}
+
+		let version_entry = match self
+			.garage
+			.version_table
+			.get(&version.uuid, &EmptyKey)
+			.await
+		{
+			Ok(Some(v)) => v,
+			Ok(None) => {
+				writeln!(&mut ret, "\nVersion entry not found in version table.").unwrap();
+				return Ok(AdminRpc::Ok(ret));
+			}
+			Err(e) => {
+				writeln!(
+					&mut ret,
+					"\nCould not read version metadata, some nodes might be unreachable: {}",
+					e
+				)
+				.unwrap();
+				return Ok(AdminRpc::Ok(ret));
+			}
+		};
+
+		writeln!(
+			&mut ret,
+			"\nData blocks ({}):",
+			version_entry.blocks.items().len()
+		)
+		.unwrap();
+		let mut table = vec!["  Part\tOffset\tHash\tPartition\tNodes".to_string()];
+		for (vk, vb) in version_entry.blocks.items().iter() {
+			let nodes = self.garage.block_manager.replication.write_nodes(&vb.hash);
+			table.push(format!(
+				"  {}\t{}\t{}\t{}\t{}",
+				vk.part_number,
+				vk.offset,
+				hex::encode(vb.hash),
+				ring.partition_of(&vb.hash),
+				nodes
+					.iter()
+					.map(|n| format!("{:?}", n))
+					.collect::<Vec<_>>()
+					.join(" "),
+			));
+		}
+		write!(&mut ret, "{}", format_table_to_string(table)).unwrap();
+
+		Ok(AdminRpc::Ok(ret))
+	}
+
+	async fn handle_show_partition_layout(&self, partition_key: &str) -> Result<AdminRpc, Error> {
+		let (hash, key_desc) = match partition_key.split_once('/') {
+			Some((bucket, object)) => {
+				let bucket_id = self
+					.garage
+					.bucket_helper()
+					.resolve_global_bucket_name(&bucket.to_string())
+					.await?
+					.ok_or_bad_request("Bucket not found")?;
+				// Objects are partitioned by bucket: all objects of a bucket
+				// are in the same partition of the object table
+				(
+					bucket_id.hash(),
+					format!(
+						"{} (bucket {}, object {})",
+						partition_key,
+						hex::encode(bucket_id),
+						object
+					),
+				)
+			}
+			None => {
+				let bytes = hex::decode(partition_key)
+					.ok()
+					.filter(|b| b.len() == 32)
+					.ok_or_bad_request(
+						"Partition key must be a hex-encoded 32-byte hash or BUCKET/OBJECT",
+					)?;
+				(
+					Hash::try_from(&bytes[..]).unwrap(),
+					partition_key.to_string(),
+				)
+			}
+		};
+
+		let ring = self.garage.system.ring.borrow().clone();
+		let known_nodes = self
+			.garage
+			.system
+			.get_known_nodes()
+			.into_iter()
+			.map(|n| (n.id, n))
+			.collect::<HashMap<_, _>>();
+		let partition = ring.partition_of(&hash);
+		let nodes = self.garage.object_table.data.replication.write_nodes(&hash);
+
+		let mut ret = String::new();
+		writeln!(&mut ret, "Partition key: {}", key_desc).unwrap();
+		writeln!(&mut ret, "├── Hash: {}", hex::encode(hash)).unwrap();
+		writeln!(
+			&mut ret,
+			"├── Ring position: {:#06x}",
+			u16::from_be_bytes([hash.as_slice()[0], hash.as_slice()[1]])
+		)
+		.unwrap();
+		writeln!(
+			&mut ret,
+			"├── Partition: {} (of {}, cluster layout version {})",
+			partition,
+			1usize << garage_rpc::ring::PARTITION_BITS,
+			ring.layout.version
+		)
+		.unwrap();
+		writeln!(&mut ret, "└── Replicas ({}):", nodes.len()).unwrap();
+
+		for (i, node) in nodes.iter().enumerate() {
+			let (branch, indent) = if i + 1 == nodes.len() {
+				("└──", "    ")
+			} else {
+				("├──", "│   ")
+			};
+			let (zone, capacity) = match ring.layout.node_role(node) {
+				Some(role) => (role.zone.clone(), role.capacity_string()),
+				None => ("?".to_string(), "?".to_string()),
+			};
+			let (hostname, status) = match known_nodes.get(node) {
+				Some(n) => (
+					n.status.hostname.clone(),
+					if n.is_up { "reachable" } else { "unreachable" },
+				),
+				None => ("?".to_string(), "unknown"),
+			};
+
+			let (object_root, version_root, block_ref_root) = futures::join!(
+				self.garage
+					.object_table
+					.syncer
+					.get_root_hash_from(*node, partition),
+				self.garage
+					.version_table
+					.syncer
+					.get_root_hash_from(*node, partition),
+				self.garage
+					.block_ref_table
+					.syncer
+					.get_root_hash_from(*node, partition),
+			);
+
+			writeln!(&mut ret, "    {} {:?} ({})", branch, node, hostname).unwrap();
+			writeln!(&mut ret, "    {}├── Zone: {}", indent, zone).unwrap();
+			writeln!(&mut ret, "    {}├── Capacity: {}", indent, capacity).unwrap();
+			writeln!(&mut ret, "    {}├── Status: {}", indent, status).unwrap();
+			writeln!(&mut ret, "    {}└── Merkle roots:", indent).unwrap();
+			let roots = [
+				("object", object_root),
+				("version", version_root),
+				("block_ref", block_ref_root),
+			];
+			for (j, (table, root)) in roots.iter().enumerate() {
+				let branch = if j + 1 == roots.len() {
+					"└──"
+				} else {
+					"├──"
+				};
+				let root = match root {
+					Ok(Some(h)) => hex::encode(h),
+					Ok(None) => "(empty)".to_string(),
+					Err(e) => format!("error: {}", e),
+				};
+				writeln!(&mut ret, "    {}    {} {}: {}", indent, branch, table, root).unwrap();
+			}
+		}
+
+		Ok(AdminRpc::Ok(ret))
+	}
+}
+
+#[derive(Serialize)]
+struct VersionBlockRefReport {
+	node: String,
+	/// Number of non-deleted versions checked
+	versions: u64,
+	/// Number of (version, block) pairs checked
+	pairs: u64,
+	/// Pairs for which no block ref exists
+	missing_block_refs: Vec<VersionBlockPair>,
+	/// Pairs whose block is not stored on any of the nodes that should have it
+	missing_blocks: Vec<VersionBlockPair>,
+}
+
+#[derive(Serialize, Clone)]
+struct VersionBlockPair {
+	version: String,
+	block: String,
+}
+
+#[derive(Serialize)]
+struct RepairTimeEstimate {
+	repair_type: String,
+	node: String,
+	/// Number of entries the repair goes through on this node
+	entries: u64,
+	/// Number of entries whose processing was timed
+	sampled_entries: u64,
+	sample_duration_msec: u64,
+	estimated_duration_secs: u64,
+	estimated_duration: String,
+	assumptions: Vec<String>,
+}
+
+/// Number of entries to sample out of `entries`: 0.1%, but at least 10
+fn sample_size(entries: usize) -> usize {
+	std::cmp::min(entries, std::cmp::max(entries / 1000, 10))
+}
+
+/// Value at the given percentile of a sorted, non-empty list
+fn percentile(sorted: &[Duration], p: usize) -> Duration {
+	sorted[(sorted.len() - 1) * p / 100]
+}
+
+fn format_msec(d: Duration) -> String {
+	format!("{:.3}ms", d.as_secs_f64() * 1000.)
+}
+
+/// Estimated time in seconds to process `entries` entries,
+/// if processing `sampled` entries took `elapsed`
+fn extrapolate(entries: usize, sampled: usize, elapsed: Duration) -> f64 {
+	if sampled == 0 {
+		0.
+	} else {
+		elapsed.as_secs_f64() * entries as f64 / sampled as f64
+	}
+}
+
+fn write_partition_info(
+	to: &mut String,
+	ring: &Ring,
+	known_nodes: &HashMap<Uuid, KnownNodeInfo>,
+	hash: &Hash,
+	nodes: &[Uuid],
+) {
+	writeln!(to, "  Partition key hash: {}", hex::encode(hash)).unwrap();
+	writeln!(to, "  Partition: {}", ring.partition_of(hash)).unwrap();
+
+	let mut table = vec!["  ID\tHostname\tAddress\tZone\tCapacity\tStatus".to_string()];
+	for node in nodes.iter() {
+		let (zone, capacity) = match ring.layout.node_role(node) {
+			Some(role) => (role.zone.clone(), role.capacity_string()),
+			None => ("?".to_string(), "?".to_string()),
+		};
+		let (hostname, addr, status) = match known_nodes.get(node) {
+			Some(n) => (
+				n.status.hostname.clone(),
+				n.addr.to_string(),
+				if n.is_up { "reachable" } else { "unreachable" },
+			),
+			None => ("?".to_string(), "?".to_string(), "unknown"),
+		};
+		table.push(format!(
+			"  {:?}\t{}\t{}\t{}\t{}\t{}",
+			node, hostname, addr, zone, capacity, status
+		));
+	}
+	write!(to, "{}", format_table_to_string(table)).unwrap();
+}
@@ -12,7 +12,7 @@ use super::*;
 impl AdminRpcHandler {
 	pub(super) async fn handle_key_cmd(&self, cmd: &KeyOperation) -> Result<AdminRpc, Error> {
 		match cmd {
-			KeyOperation::List => self.handle_list_keys().await,
+			KeyOperation::List(opt) => self.handle_list_keys(opt).await,
 			KeyOperation::Info(query) => self.handle_key_info(query).await,
 			KeyOperation::New(query) => self.handle_create_key(query).await,
 			KeyOperation::Rename(query) => self.handle_rename_key(query).await,
@@ -20,11 +20,14 @@ impl AdminRpcHandler {
 			KeyOperation::Allow(query) => self.handle_allow_key(query).await,
 			KeyOperation::Deny(query) => self.handle_deny_key(query).await,
 			KeyOperation::Import(query) => self.handle_import_key(query).await,
+			KeyOperation::RotateSecret(query) => self.handle_rotate_key_secret(query).await,
+			KeyOperation::Enable(query) => self.handle_set_key_enabled(query, true).await,
+			KeyOperation::Disable(query) => self.handle_set_key_enabled(query, false).await,
 		}
 	}
 
-	async fn handle_list_keys(&self) -> Result<AdminRpc, Error> {
-		let key_ids = self
+	async fn handle_list_keys(&self, opt: &KeyListOpt) -> Result<AdminRpc, Error> {
+		let keys = self
 			.garage
 			.key_table
 			.get_range(
@@ -34,11 +37,49 @@ impl AdminRpcHandler {
 				10000,
 				EnumerationOrder::Forward,
 			)
-			.await?
+			.await?;
+
+		if !opt.bucket_count && opt.sort_by.is_none() {
+			let key_ids = keys
+				.iter()
+				.map(|k| (k.key_id.to_string(), k.params().unwrap().name.get().clone()))
+				.collect::<Vec<_>>();
+			return Ok(AdminRpc::KeyList(key_ids));
+		}
+
+		// All permissions of a key are stored in its entry of the key table,
+		// so no other table needs to be read here
+		let mut counts = keys
 			.iter()
-			.map(|k| (k.key_id.to_string(), k.params().unwrap().name.get().clone()))
+			.map(|k| {
+				let params = k.params().unwrap();
+				let perms = params
+					.authorized_buckets
+					.items()
+					.iter()
+					.map(|(_, perm)| perm)
+					.filter(|perm| perm.is_any())
+					.collect::<Vec<_>>();
+				KeyBucketCounts {
+					key_id: k.key_id.to_string(),
+					name: params.name.get().clone(),
+					read_write: perms
+						.iter()
+						.filter(|p| p.allow_read && p.allow_write)
+						.count(),
+					read_only: perms
+						.iter()
+						.filter(|p| p.allow_read && !p.allow_write)
+						.count(),
+					owner: perms.iter().filter(|p| p.allow_owner).count(),
+					total: perms.len(),
+				}
+			})
 			.collect::<Vec<_>>();
-		Ok(AdminRpc::KeyList(key_ids))
+		if opt.sort_by.as_deref() == Some("bucket-count") {
+			counts.sort_by_key(|c| std::cmp::Reverse(c.total));
+		}
+		Ok(AdminRpc::KeyListBucketCounts(counts))
 	}
 
 	async fn handle_key_info(&self, query: &KeyOpt) -> Result<AdminRpc, Error> {
@@ -128,6 +169,45 @@ impl AdminRpcHandler {
 		self.key_info_result(imported_key).await
 	}
 
+	async fn handle_rotate_key_secret(&self, query: &KeyOpt) -> Result<AdminRpc, Error> {
+		let key_helper = self.garage.key_helper();
+		let key = key_helper
+			.get_existing_matching_key(&query.key_pattern)
+			.await?;
+		let new_secret = Key::generate_secret_key();
+		self.garage
+			.key_table
+			.atomic_update(&EmptyKey, &key.key_id, |key| {
+				let mut key = key?;
+				key.params_mut()?.secret_key.update(new_secret.clone());
+				Some(key)
+			})
+			.await?;
+		let key = key_helper.get_existing_key(&key.key_id).await?;
+		self.key_info_result(key).await
+	}
+
+	async fn handle_set_key_enabled(
+		&self,
+		query: &KeyOpt,
+		enabled: bool,
+	) -> Result<AdminRpc, Error> {
+		let key_helper = self.garage.key_helper();
+		let key = key_helper
+			.get_existing_matching_key(&query.key_pattern)
+			.await?;
+		self.garage
+			.key_table
+			.atomic_update(&EmptyKey, &key.key_id, |key| {
+				let mut key = key?;
+				key.params_mut()?.enabled.update(enabled);
+				Some(key)
+			})
+			.await?;
+		let key = key_helper.get_existing_key(&key.key_id).await?;
+		self.key_info_result(key).await
+	}
+
 	async fn key_info_result(&self, key: Key) -> Result<AdminRpc, Error> {
 		let mut relevant_buckets = HashMap::new();
 
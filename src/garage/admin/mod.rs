@@ -1,10 +1,12 @@
 mod block;
 mod bucket;
+mod debug;
 mod key;
 
 use std::collections::HashMap;
 use std::fmt::Write;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime};
 
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
@@ -19,18 +21,23 @@ use garage_table::replication::*;
 use garage_table::*;
 
 use garage_rpc::ring::PARTITION_BITS;
+use garage_rpc::system::NodeStatus;
 use garage_rpc::*;
 
 use garage_block::manager::BlockResyncErrorInfo;
+use garage_block::repair::ScrubStats;
 
 use garage_model::bucket_table::*;
 use garage_model::garage::Garage;
+use garage_model::helper::bucket::BucketStorageUsage;
 use garage_model::helper::error::{Error, OkOrBadRequest};
 use garage_model::key_table::*;
 use garage_model::migrate::Migrate;
 use garage_model::s3::version_table::Version;
+use garage_model::stats::gather_cluster_stats;
 
 use crate::cli::*;
+use crate::log_filter::LogFilter;
 use crate::repair::online::launch_online_repair;
 
 pub const ADMIN_RPC_PATH: &str = "garage/admin_rpc.rs/Rpc";
@@ -45,6 +52,14 @@ pub enum AdminRpc {
 	Stats(StatsOpt),
 	Worker(WorkerOperation),
 	BlockOperation(BlockOperation),
+	ScrubOperation(ScrubOperation),
+	DebugOperation(DebugOperation),
+	GetNodeStatus,
+	GetLayoutStatus,
+	SetLogLevel(SetLogLevelOpt),
+	SetVar(SetVarOpt),
+	GetOnlineNodes,
+	GetScrubStats,
 
 	// Replies
 	Ok(String),
@@ -55,6 +70,7 @@ pub enum AdminRpc {
 		counters: HashMap<String, i64>,
 	},
 	KeyList(Vec<(String, String)>),
+	KeyListBucketCounts(Vec<KeyBucketCounts>),
 	KeyInfo(Key, HashMap<Uuid, Bucket>),
 	WorkerList(
 		HashMap<usize, garage_util::background::WorkerInfo>,
@@ -68,6 +84,50 @@ pub enum AdminRpc {
 		refcount: u64,
 		versions: Vec<Result<Version, Uuid>>,
 	},
+	NodeStatus(NodeStatus),
+	UnusedKeys(Vec<UnusedKeyInfo>),
+	LayoutStatus(LayoutStatus),
+	OnlineNodes(Vec<Uuid>),
+	ScrubStats(ScrubStats),
+}
+
+/// Cluster layout as currently known by a node
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LayoutStatus {
+	pub version: u64,
+	pub staging_hash: Hash,
+	/// Number of role changes staged but not yet applied
+	pub staged_changes: usize,
+}
+
+/// Number of buckets an access key has access to, by kind of permission
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeyBucketCounts {
+	pub key_id: String,
+	pub name: String,
+	/// Buckets the key can read and write
+	pub read_write: usize,
+	/// Buckets the key can read but not write
+	pub read_only: usize,
+	/// Buckets the key owns (counted independently of read and write permissions)
+	pub owner: usize,
+	/// Buckets the key has any permission on
+	pub total: usize,
+}
+
+/// Information about an access key reported by `garage debug analyze-keys`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UnusedKeyInfo {
+	pub key_id: String,
+	pub name: String,
+	/// Creation date (RFC 3339), unknown for keys created by old versions of Garage
+	pub created: Option<String>,
+	/// Date of last use (RFC 3339), unknown if the key was never used since it is recorded
+	pub last_used: Option<String>,
+	pub authorized_buckets: usize,
+	pub allow_create_bucket: bool,
+	/// Whether the key was disabled by `--delete-unused`
+	pub disabled: bool,
 }
 
 impl Rpc for AdminRpc {
@@ -77,16 +137,24 @@ impl Rpc for AdminRpc {
 pub struct AdminRpcHandler {
 	garage: Arc<Garage>,
 	background: Arc<BackgroundRunner>,
+	log_filter: Arc<LogFilter>,
 	endpoint: Arc<Endpoint<AdminRpc, Self>>,
+	storage_usage_cache: Mutex<HashMap<(Uuid, String), (Instant, BucketStorageUsage)>>,
 }
 
 impl AdminRpcHandler {
-	pub fn new(garage: Arc<Garage>, background: Arc<BackgroundRunner>) -> Arc<Self> {
+	pub fn new(
+		garage: Arc<Garage>,
+		background: Arc<BackgroundRunner>,
+		log_filter: Arc<LogFilter>,
+	) -> Arc<Self> {
 		let endpoint = garage.system.netapp.endpoint(ADMIN_RPC_PATH.into());
 		let admin = Arc::new(Self {
 			garage,
 			background,
+			log_filter,
 			endpoint,
+			storage_usage_cache: Mutex::new(HashMap::new()),
 		});
 		admin.endpoint.set_handler(admin.clone());
 		admin
@@ -158,6 +226,12 @@ impl AdminRpcHandler {
 	// ================ STATS COMMANDS ====================
 
 	async fn handle_stats(&self, opt: StatsOpt) -> Result<AdminRpc, Error> {
+		if opt.json {
+			let stats = gather_cluster_stats(&self.garage).await?;
+			let json = serde_json::to_string_pretty(&stats).map_err(GarageError::from)?;
+			return Ok(AdminRpc::Ok(json));
+		}
+
 		if opt.all_nodes {
 			let mut ret = String::new();
 			let ring = self.garage.system.ring.borrow().clone();
@@ -187,17 +261,17 @@ impl AdminRpcHandler {
 			write!(
 				&mut ret,
 				"Cluster statistics:\n\n{}",
-				self.gather_cluster_stats()
+				self.gather_cluster_stats().await?
 			)
 			.unwrap();
 
 			Ok(AdminRpc::Ok(ret))
 		} else {
-			Ok(AdminRpc::Ok(self.gather_stats_local(opt)?))
+			Ok(AdminRpc::Ok(self.gather_stats_local(opt).await?))
 		}
 	}
 
-	fn gather_stats_local(&self, opt: StatsOpt) -> Result<String, Error> {
+	async fn gather_stats_local(&self, opt: StatsOpt) -> Result<String, Error> {
 		let mut ret = String::new();
 		writeln!(
 			&mut ret,
@@ -219,6 +293,7 @@ impl AdminRpcHandler {
 		table.push(self.gather_table_stats(&self.garage.object_table, opt.detailed)?);
 		table.push(self.gather_table_stats(&self.garage.version_table, opt.detailed)?);
 		table.push(self.gather_table_stats(&self.garage.block_ref_table, opt.detailed)?);
+		table.push(self.gather_table_stats(&self.garage.replication_state_table, opt.detailed)?);
 		write!(
 			&mut ret,
 			"\nTable stats:\n{}",
@@ -257,22 +332,47 @@ impl AdminRpcHandler {
 		)
 		.unwrap();
 
+		let data_layout = &self.garage.block_manager.data_layout;
+		if data_layout.dirs().len() > 1 {
+			writeln!(&mut ret, "\nData directories:").unwrap();
+			let mut table = vec!["  Path\tUsed\tTotal\tFill level\t".to_string()];
+			for (i, dir) in data_layout.dirs().iter().enumerate() {
+				let usage = match data_layout.usage(i) {
+					Some(u) => format!(
+						"{}\t{}\t{:.1}%",
+						bytesize::ByteSize::b(u.used),
+						bytesize::ByteSize::b(u.total),
+						u.fill_level() * 100.
+					),
+					None => "?\t?\t?".to_string(),
+				};
+				table.push(format!(
+					"  {}\t{}\t{}",
+					dir.path.display(),
+					usage,
+					if dir.read_only { "read-only" } else { "" }
+				));
+			}
+			write!(&mut ret, "{}", format_table_to_string(table)).unwrap();
+		}
+
 		if !opt.detailed {
 			writeln!(&mut ret, "\nIf values are missing above (marked as NC), consider adding the --detailed flag (this will be slow).").unwrap();
 		}
 
 		if !opt.skip_global {
-			write!(&mut ret, "\n{}", self.gather_cluster_stats()).unwrap();
+			write!(&mut ret, "\n{}", self.gather_cluster_stats().await?).unwrap();
 		}
 
 		Ok(ret)
 	}
 
-	fn gather_cluster_stats(&self) -> String {
+	async fn gather_cluster_stats(&self) -> Result<String, Error> {
 		let mut ret = String::new();
 
 		// Gather storage node and free space statistics
-		let layout = &self.garage.system.ring.borrow().layout;
+		let ring = self.garage.system.ring.borrow().clone();
+		let layout = &ring.layout;
 		let mut node_partition_count = HashMap::<Uuid, u64>::new();
 		for short_id in layout.ring_assignation_data.iter() {
 			let id = layout.node_id_vec[*short_id as usize];
@@ -359,7 +459,55 @@ impl AdminRpcHandler {
 			}
 		}
 
-		ret
+		// Gather the statistics that each node gives about itself,
+		// and the object counters of buckets
+		let stats = gather_cluster_stats(&self.garage).await?;
+
+		writeln!(&mut ret, "\nReplication mode: {}", stats.replication_mode).unwrap();
+
+		let mut table = vec!["  ID\tVersion\tResyncQueue\tResyncErrors\tTableSyncs".into()];
+		for node in stats.nodes.iter() {
+			let opt_str = |x: Option<usize>| x.map(|x| x.to_string()).unwrap_or_else(|| "?".into());
+			table.push(format!(
+				"  {:.16}\t{}\t{}\t{}\t{}",
+				node.id,
+				node.garage_version.as_deref().unwrap_or("?"),
+				opt_str(node.block_resync_queue_len),
+				opt_str(node.block_resync_errors),
+				opt_str(node.table_syncs_in_flight),
+			));
+		}
+		write!(
+			&mut ret,
+			"\nNode status:\n{}",
+			format_table_to_string(table)
+		)
+		.unwrap();
+		for node in stats.nodes.iter() {
+			if let Some(e) = &node.error {
+				writeln!(
+					&mut ret,
+					"  Could not get status of node {:.16}: {}",
+					node.id, e
+				)
+				.unwrap();
+			}
+		}
+
+		let mut table = vec!["  ID\tGlobal aliases\tObjects\tSize\tUnfinished uploads".into()];
+		for bucket in stats.buckets.iter() {
+			table.push(format!(
+				"  {:.16}\t{}\t{}\t{}\t{}",
+				bucket.id,
+				bucket.global_aliases.join(","),
+				bucket.objects,
+				bytesize::ByteSize::b(bucket.bytes.max(0) as u64),
+				bucket.unfinished_uploads,
+			));
+		}
+		write!(&mut ret, "\nBuckets:\n{}", format_table_to_string(table)).unwrap();
+
+		Ok(ret)
 	}
 
 	fn gather_table_stats<F, R>(
@@ -426,7 +574,10 @@ impl AdminRpcHandler {
 				all_nodes,
 				variable,
 				value,
-			} => self.handle_set_var(*all_nodes, variable, value).await,
+			} => {
+				self.handle_set_var(*all_nodes, variable, value, &None)
+					.await
+			}
 		}
 	}
 
@@ -482,6 +633,7 @@ impl AdminRpcHandler {
 		all_nodes: bool,
 		variable: &str,
 		value: &str,
+		expires_in: &Option<String>,
 	) -> Result<AdminRpc, Error> {
 		if all_nodes {
 			let mut ret = vec![];
@@ -492,10 +644,11 @@ impl AdminRpcHandler {
 					.endpoint
 					.call(
 						&node,
-						AdminRpc::Worker(WorkerOperation::Set {
+						AdminRpc::SetVar(SetVarOpt {
 							all_nodes: false,
 							variable: variable.to_string(),
 							value: value.to_string(),
+							expires_in: expires_in.clone(),
 						}),
 						PRIO_NORMAL,
 					)
@@ -507,7 +660,18 @@ impl AdminRpcHandler {
 			}
 			Ok(AdminRpc::WorkerVars(ret))
 		} else {
-			self.garage.bg_vars.set(variable, value)?;
+			match expires_in {
+				Some(d) => {
+					let duration = parse_duration::parse::parse(d)
+						.ok_or_bad_request("Invalid duration passed for --expires-in parameter")?;
+					self.garage.bg_vars.set_with_expiry(
+						variable,
+						value.to_string(),
+						SystemTime::now() + duration,
+					)?;
+				}
+				None => self.garage.bg_vars.set(variable, value)?,
+			}
 			Ok(AdminRpc::WorkerVars(vec![(
 				self.garage.system.id,
 				variable.to_string(),
@@ -515,16 +679,45 @@ impl AdminRpcHandler {
 			)]))
 		}
 	}
+
+	// ================ SERVER COMMANDS ====================
+
+	fn handle_set_log_level(&self, opt: &SetLogLevelOpt, from: NodeID) -> Result<AdminRpc, Error> {
+		let revert_after = opt
+			.revert_after
+			.as_deref()
+			.map(parse_duration::parse::parse)
+			.transpose()
+			.ok_or_bad_request("Invalid duration passed for --revert-after parameter")?;
+
+		let old_filter = self.log_filter.set_level(&opt.level, revert_after)?;
+		warn!(
+			"Log filter changed from `{}` to level {} by admin node {:?}{}",
+			old_filter,
+			opt.level,
+			Uuid::from(from),
+			match &opt.revert_after {
+				Some(d) => format!(", will be reverted after {}", d),
+				None => String::new(),
+			}
+		);
+
+		Ok(AdminRpc::Ok(format!(
+			"Log level of node {:?} set to {}.",
+			self.garage.system.id, opt.level
+		)))
+	}
 }
 
 #[async_trait]
 impl EndpointHandler<AdminRpc> for AdminRpcHandler {
-	async fn handle(
-		self: &Arc<Self>,
-		message: &AdminRpc,
-		_from: NodeID,
-	) -> Result<AdminRpc, Error> {
+	async fn handle(self: &Arc<Self>, message: &AdminRpc, from: NodeID) -> Result<AdminRpc, Error> {
 		match message {
+			AdminRpc::SetLogLevel(opt) => self.handle_set_log_level(opt, from),
+			AdminRpc::SetVar(opt) => {
+				self.handle_set_var(opt.all_nodes, &opt.variable, &opt.value, &opt.expires_in)
+					.await
+			}
 			AdminRpc::BucketOperation(bo) => self.handle_bucket_cmd(bo).await,
 			AdminRpc::KeyOperation(ko) => self.handle_key_cmd(ko).await,
 			AdminRpc::Migrate(opt) => self.handle_migrate(opt.clone()).await,
@@ -532,6 +725,29 @@ impl EndpointHandler<AdminRpc> for AdminRpcHandler {
 			AdminRpc::Stats(opt) => self.handle_stats(opt.clone()).await,
 			AdminRpc::Worker(wo) => self.handle_worker_cmd(wo).await,
 			AdminRpc::BlockOperation(bo) => self.handle_block_cmd(bo).await,
+			AdminRpc::ScrubOperation(so) => self.handle_scrub_cmd(so).await,
+			AdminRpc::DebugOperation(dbg) => self.handle_debug_cmd(dbg).await,
+			AdminRpc::GetNodeStatus => Ok(AdminRpc::NodeStatus(self.garage.system.local_status())),
+			AdminRpc::GetLayoutStatus => {
+				let layout = self.garage.system.get_cluster_layout();
+				Ok(AdminRpc::LayoutStatus(LayoutStatus {
+					version: layout.version,
+					staging_hash: layout.staging_hash,
+					staged_changes: layout.staging.items().len(),
+				}))
+			}
+			AdminRpc::GetOnlineNodes => Ok(AdminRpc::OnlineNodes(
+				self.garage
+					.system
+					.get_known_nodes()
+					.into_iter()
+					.filter(|n| n.is_up)
+					.map(|n| n.id)
+					.collect(),
+			)),
+			AdminRpc::GetScrubStats => Ok(AdminRpc::ScrubStats(
+				self.garage.block_manager.scrub_stats(),
+			)),
 			m => Err(GarageError::unexpected_rpc_message(m).into()),
 		}
 	}
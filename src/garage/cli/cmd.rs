@@ -44,6 +44,21 @@ pub async fn cli_command_dispatch(
 		Command::Block(bo) => {
 			cmd_admin(admin_rpc_endpoint, rpc_host, AdminRpc::BlockOperation(bo)).await
 		}
+		Command::Scrub(so) => {
+			cmd_admin(admin_rpc_endpoint, rpc_host, AdminRpc::ScrubOperation(so)).await
+		}
+		Command::Debug(DebugOperation::AnalyzeKeys(opt)) => {
+			cmd_analyze_keys(admin_rpc_endpoint, rpc_host, opt).await
+		}
+		Command::Debug(dbg) => {
+			cmd_admin(admin_rpc_endpoint, rpc_host, AdminRpc::DebugOperation(dbg)).await
+		}
+		Command::Server(ServerOpt {
+			cmd: Some(ServerOperation::SetLogLevel(opt)),
+		}) => cmd_admin(admin_rpc_endpoint, rpc_host, AdminRpc::SetLogLevel(opt)).await,
+		Command::Server(ServerOpt {
+			cmd: Some(ServerOperation::SetVar(opt)),
+		}) => cmd_admin(admin_rpc_endpoint, rpc_host, AdminRpc::SetVar(opt)).await,
 		_ => unreachable!(),
 	}
 }
@@ -174,6 +189,49 @@ pub async fn cmd_connect(
 	}
 }
 
+pub async fn cmd_analyze_keys(
+	rpc_cli: &Endpoint<AdminRpc, ()>,
+	rpc_host: NodeID,
+	opt: AnalyzeKeysOpt,
+) -> Result<(), HelperError> {
+	let report_file = opt.report_file.clone();
+	let keys = match rpc_cli
+		.call(
+			&rpc_host,
+			AdminRpc::DebugOperation(DebugOperation::AnalyzeKeys(opt)),
+			PRIO_NORMAL,
+		)
+		.await??
+	{
+		AdminRpc::UnusedKeys(keys) => keys,
+		r => {
+			return Err(Error::Message(format!("Unexpected response: {:?}", r)).into());
+		}
+	};
+
+	let report = serde_json::to_string_pretty(&keys).map_err(Error::from)?;
+	std::fs::write(&report_file, report).map_err(Error::from)?;
+
+	let mut table = vec!["ID\tName\tLast used\tBuckets\tDisabled".to_string()];
+	for k in keys.iter() {
+		table.push(format!(
+			"{}\t{}\t{}\t{}\t{}",
+			k.key_id,
+			k.name,
+			k.last_used.as_deref().unwrap_or("never"),
+			k.authorized_buckets,
+			if k.disabled { "yes" } else { "" }
+		));
+	}
+	format_table(table);
+	println!(
+		"\n{} unused key(s), report written to {}",
+		keys.len(),
+		report_file.display()
+	);
+	Ok(())
+}
+
 pub async fn cmd_admin(
 	rpc_cli: &Endpoint<AdminRpc, ()>,
 	rpc_host: NodeID,
@@ -196,6 +254,9 @@ pub async fn cmd_admin(
 		AdminRpc::KeyList(kl) => {
 			print_key_list(kl);
 		}
+		AdminRpc::KeyListBucketCounts(kl) => {
+			print_key_list_bucket_counts(kl);
+		}
 		AdminRpc::KeyInfo(key, rb) => {
 			print_key_info(&key, &rb);
 		}
@@ -1,6 +1,7 @@
 use format_table::format_table;
 use garage_util::crdt::Crdt;
 use garage_util::error::*;
+use garage_util::time::msec_to_rfc3339;
 
 use garage_rpc::layout::*;
 use garage_rpc::system::*;
@@ -27,6 +28,10 @@ pub async fn cli_layout_command_dispatch(
 		LayoutOperation::Revert(revert_opt) => {
 			cmd_revert_layout(system_rpc_endpoint, rpc_host, revert_opt).await
 		}
+		LayoutOperation::History => cmd_layout_history(system_rpc_endpoint, rpc_host).await,
+		LayoutOperation::Rollback(rollback_opt) => {
+			cmd_rollback_layout(system_rpc_endpoint, rpc_host, rollback_opt).await
+		}
 	}
 }
 
@@ -124,6 +129,10 @@ pub async fn cmd_assign_role(
 			}
 		};
 
+		if let Some(adv) = status.iter().find(|adv| adv.id == added_node) {
+			adv.status.check_layout_role(&new_entry)?;
+		}
+
 		layout
 			.staging
 			.merge(&roles.update_mutator(added_node, NodeRoleV(Some(new_entry))));
@@ -238,8 +247,107 @@ pub async fn cmd_revert_layout(
 	Ok(())
 }
 
+pub async fn cmd_layout_history(
+	rpc_cli: &Endpoint<SystemRpc, ()>,
+	rpc_host: NodeID,
+) -> Result<(), Error> {
+	let layout = fetch_layout(rpc_cli, rpc_host).await?;
+	let history = fetch_layout_history(rpc_cli, rpc_host).await?;
+
+	let mut table = vec!["Version\tReceived at\tNodes\tZones\tTotal capacity".to_string()];
+	for entry in history.entries.iter().rev() {
+		let l = &entry.layout;
+		let roles = l
+			.roles
+			.items()
+			.iter()
+			.filter_map(|(_, _, r)| r.0.as_ref())
+			.collect::<Vec<_>>();
+		let zones = roles
+			.iter()
+			.map(|r| r.zone.as_str())
+			.collect::<std::collections::HashSet<_>>();
+		let capacity = roles.iter().filter_map(|r| r.capacity).sum::<u32>();
+		table.push(format!(
+			"{}{}\t{}\t{}\t{}\t{}",
+			l.version,
+			if l.version == layout.version {
+				" (current)"
+			} else {
+				""
+			},
+			msec_to_rfc3339(entry.received_at),
+			roles.len(),
+			zones.len(),
+			capacity
+		));
+	}
+	format_table(table);
+
+	println!();
+	println!("To apply again a previous version of the layout, use: garage layout rollback --version <version>");
+	Ok(())
+}
+
+pub async fn cmd_rollback_layout(
+	rpc_cli: &Endpoint<SystemRpc, ()>,
+	rpc_host: NodeID,
+	rollback_opt: RollbackLayoutOpt,
+) -> Result<(), Error> {
+	let layout = fetch_layout(rpc_cli, rpc_host).await?;
+	let history = fetch_layout_history(rpc_cli, rpc_host).await?;
+
+	let previous = history.get(rollback_opt.version).ok_or_message(format!(
+		"Layout version {} is not in the layout history of this node, see `garage layout history`",
+		rollback_opt.version
+	))?;
+
+	if !rollback_opt.force {
+		let pending = match rpc_cli
+			.call(&rpc_host, SystemRpc::GetLayoutMigrationStatus, PRIO_NORMAL)
+			.await??
+		{
+			SystemRpc::ReturnLayoutMigrationStatus(pending) => pending,
+			resp => return Err(Error::Message(format!("Invalid RPC response: {:?}", resp))),
+		};
+		if !pending.is_empty() {
+			println!("Data may still be moving after the last change of the cluster layout:");
+			for reason in pending.iter() {
+				println!("  {}", reason);
+			}
+			return Err(Error::Message("Rolling back the layout now could make some data temporarily unavailable. Use --force to roll back anyway.".into()));
+		}
+	}
+
+	let layout = layout.rollback_to(previous)?;
+	let new_version = layout.version;
+
+	send_layout(rpc_cli, rpc_host, layout).await?;
+
+	println!(
+		"Layout version {} has been applied again in cluster as layout version {}.",
+		rollback_opt.version, new_version
+	);
+	println!("Data will now be moved around between nodes accordingly.");
+
+	Ok(())
+}
+
 // --- utility ---
 
+pub async fn fetch_layout_history(
+	rpc_cli: &Endpoint<SystemRpc, ()>,
+	rpc_host: NodeID,
+) -> Result<LayoutHistory, Error> {
+	match rpc_cli
+		.call(&rpc_host, SystemRpc::GetClusterLayoutHistory, PRIO_NORMAL)
+		.await??
+	{
+		SystemRpc::ReturnClusterLayoutHistory(h) => Ok(h),
+		resp => Err(Error::Message(format!("Invalid RPC response: {:?}", resp))),
+	}
+}
+
 pub async fn fetch_layout(
 	rpc_cli: &Endpoint<SystemRpc, ()>,
 	rpc_host: NodeID,
@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+
 use serde::{Deserialize, Serialize};
 use structopt::StructOpt;
 
@@ -5,9 +7,9 @@ use garage_util::version::garage_version;
 
 #[derive(StructOpt, Debug)]
 pub enum Command {
-	/// Run Garage server
+	/// Run Garage server, or change the settings of a running server
 	#[structopt(name = "server", version = garage_version())]
-	Server,
+	Server(ServerOpt),
 
 	/// Get network status
 	#[structopt(name = "status", version = garage_version())]
@@ -54,6 +56,67 @@ pub enum Command {
 	/// Low-level debug operations on data blocks
 	#[structopt(name = "block", version = garage_version())]
 	Block(BlockOperation),
+
+	/// Show the progress of the scrub of the data directory
+	#[structopt(name = "scrub", version = garage_version())]
+	Scrub(ScrubOperation),
+
+	/// Low-level debug operations on cluster metadata and data placement
+	#[structopt(name = "debug", version = garage_version())]
+	Debug(DebugOperation),
+
+	/// Operations on the metadata database of this node
+	/// (these operations must be run offline, directly on the server node)
+	#[structopt(name = "db", version = garage_version())]
+	Db(DbOperation),
+}
+
+#[derive(StructOpt, Debug)]
+pub struct ServerOpt {
+	#[structopt(subcommand)]
+	pub cmd: Option<ServerOperation>,
+}
+
+#[derive(Serialize, Deserialize, StructOpt, Debug, Clone)]
+pub enum ServerOperation {
+	/// Change the log level of a running server, without restarting it
+	#[structopt(name = "set-log-level", version = garage_version())]
+	SetLogLevel(SetLogLevelOpt),
+
+	/// Change a variable of a running server (see `garage worker get`)
+	#[structopt(name = "set-var", version = garage_version())]
+	SetVar(SetVarOpt),
+}
+
+#[derive(Serialize, Deserialize, StructOpt, Debug, Clone)]
+pub struct SetLogLevelOpt {
+	/// Level of the log messages of Garage to display
+	#[structopt(
+		long = "level",
+		possible_values = &["trace", "debug", "info", "warn", "error"]
+	)]
+	pub level: String,
+
+	/// Go back to the log level the server was started with after this duration (e.g. `1h`)
+	#[structopt(long = "revert-after")]
+	pub revert_after: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, StructOpt, Debug, Clone)]
+pub struct SetVarOpt {
+	/// Set variable values on all nodes
+	#[structopt(short = "a", long = "all-nodes")]
+	pub all_nodes: bool,
+
+	/// Variable name to set
+	pub variable: String,
+
+	/// Value to set the variable to
+	pub value: String,
+
+	/// Revert the variable to its default value after this duration (e.g. `2h`)
+	#[structopt(long = "expires-in")]
+	pub expires_in: Option<String>,
 }
 
 #[derive(StructOpt, Debug)]
@@ -102,6 +165,14 @@ pub enum LayoutOperation {
 	/// Revert staged changes to cluster layout
 	#[structopt(name = "revert", version = garage_version())]
 	Revert(RevertLayoutOpt),
+
+	/// Show the previous versions of the cluster layout kept by the node
+	#[structopt(name = "history", version = garage_version())]
+	History,
+
+	/// Apply again a previous version of the cluster layout
+	#[structopt(name = "rollback", version = garage_version())]
+	Rollback(RollbackLayoutOpt),
 }
 
 #[derive(StructOpt, Debug)]
@@ -152,6 +223,17 @@ pub struct RevertLayoutOpt {
 	pub(crate) version: Option<u64>,
 }
 
+#[derive(StructOpt, Debug)]
+pub struct RollbackLayoutOpt {
+	/// Version number of the layout to apply again, as shown by `garage layout history`
+	#[structopt(long = "version")]
+	pub(crate) version: u64,
+
+	/// Roll back even if data is still being moved after the last layout change
+	#[structopt(long = "force")]
+	pub(crate) force: bool,
+}
+
 #[derive(Serialize, Deserialize, StructOpt, Debug)]
 pub enum BucketOperation {
 	/// List buckets
@@ -194,9 +276,79 @@ pub enum BucketOperation {
 	#[structopt(name = "set-quotas", version = garage_version())]
 	SetQuotas(SetQuotasOpt),
 
+	/// Require server-side encryption for all uploads to this bucket
+	#[structopt(name = "set-encryption-required", version = garage_version())]
+	SetEncryptionRequired(SetEncryptionRequiredOpt),
+
+	/// Set the maximum size of objects uploaded with a single PutObject request
+	/// (larger objects must be uploaded with multipart uploads)
+	#[structopt(name = "set-max-object-size", version = garage_version())]
+	SetMaxObjectSize(SetMaxObjectSizeOpt),
+
+	/// Set the zstd compression level of the data blocks of objects uploaded to this bucket
+	#[structopt(name = "set-compression-level", version = garage_version())]
+	SetCompressionLevel(SetCompressionLevelOpt),
+
+	/// Add a rule replicating the objects of this bucket to a bucket of another cluster
+	/// (replaces the rule with the same ID if there is one)
+	#[structopt(name = "add-replication-rule", version = garage_version())]
+	AddReplicationRule(AddReplicationRuleOpt),
+
+	/// Remove a replication rule from this bucket
+	#[structopt(name = "remove-replication-rule", version = garage_version())]
+	RemoveReplicationRule(RemoveReplicationRuleOpt),
+
 	/// Clean up (abort) old incomplete multipart uploads
 	#[structopt(name = "cleanup-incomplete-uploads", version = garage_version())]
 	CleanupIncompleteUploads(CleanupIncompleteUploadsOpt),
+
+	/// Show a breakdown of the storage space used by a bucket
+	#[structopt(name = "get-storage-usage", version = garage_version())]
+	GetStorageUsage(StorageUsageOpt),
+
+	/// List the objects of a bucket by reading the object table directly
+	#[structopt(
+		name = "list-objects",
+		alias = "list-objects-recursive",
+		version = garage_version()
+	)]
+	ListObjects(ListObjectsOpt),
+}
+
+#[derive(Serialize, Deserialize, StructOpt, Debug)]
+pub struct ListObjectsOpt {
+	/// Bucket name
+	pub bucket: String,
+
+	/// Only list objects whose key starts with this prefix
+	#[structopt(long = "prefix", default_value = "")]
+	pub prefix: String,
+
+	/// Group the keys that contain this delimiter after the prefix
+	/// into common prefixes, as the S3 ListObjects call does
+	#[structopt(long = "delimiter")]
+	pub delimiter: Option<String>,
+
+	/// Output one JSON object per line instead of a table
+	#[structopt(long = "json")]
+	pub json: bool,
+}
+
+#[derive(Serialize, Deserialize, StructOpt, Debug)]
+pub struct StorageUsageOpt {
+	/// Bucket name
+	#[structopt(long = "bucket")]
+	pub bucket: String,
+
+	/// Level of detail: `object` only counts current objects, `version` adds
+	/// incomplete uploads and non-current versions, `block` adds the data blocks
+	/// of current objects, `all` shows everything
+	#[structopt(
+		long = "detail",
+		default_value = "object",
+		possible_values = &["object", "version", "block", "all"]
+	)]
+	pub detail: String,
 }
 
 #[derive(Serialize, Deserialize, StructOpt, Debug)]
@@ -279,6 +431,16 @@ pub struct PermBucketOpt {
 	#[structopt(long = "owner")]
 	pub owner: bool,
 
+	/// Allow/deny shortening or removing the governance-mode object lock retention
+	/// of object versions, and deleting them while it applies
+	#[structopt(long = "bypass-governance")]
+	pub bypass_governance: bool,
+
+	/// Allow/deny a single S3 operation, named as in the S3 API (e.g. DeleteObject),
+	/// independently of the read, write and owner permissions (can be repeated)
+	#[structopt(long = "op", number_of_values = 1)]
+	pub ops: Vec<String>,
+
 	/// Bucket name
 	pub bucket: String,
 }
@@ -298,6 +460,98 @@ pub struct SetQuotasOpt {
 	pub max_objects: Option<String>,
 }
 
+#[derive(Serialize, Deserialize, StructOpt, Debug)]
+pub struct SetEncryptionRequiredOpt {
+	/// Bucket name
+	pub bucket: String,
+
+	/// KMS key to use for server-side encryption (the aws:kms algorithm
+	/// is used if set, AES256 otherwise)
+	#[structopt(long = "key-id")]
+	pub key_id: Option<String>,
+
+	/// Stop requiring server-side encryption (the bucket's encryption
+	/// configuration is kept)
+	#[structopt(long = "disable", conflicts_with = "key-id")]
+	pub disable: bool,
+}
+
+#[derive(Serialize, Deserialize, StructOpt, Debug)]
+pub struct SetMaxObjectSizeOpt {
+	/// Bucket name
+	#[structopt(long = "bucket")]
+	pub bucket: String,
+
+	/// Maximum object size in bytes (or `none` for no restriction)
+	#[structopt(long = "max-bytes")]
+	pub max_bytes: String,
+}
+
+#[derive(Serialize, Deserialize, StructOpt, Debug)]
+pub struct SetCompressionLevelOpt {
+	/// Bucket name
+	#[structopt(long = "bucket")]
+	pub bucket: String,
+
+	/// Compression level (or `default` to use the `compression_level`
+	/// of the node that receives the upload)
+	#[structopt(long = "level", allow_hyphen_values = true)]
+	pub level: String,
+}
+
+#[derive(Serialize, Deserialize, StructOpt, Debug)]
+pub struct AddReplicationRuleOpt {
+	/// Bucket name
+	pub bucket: String,
+
+	/// ID of the rule
+	#[structopt(long = "id")]
+	pub id: String,
+
+	/// S3 endpoint of the destination cluster, e.g. `https://s3.example.com`
+	#[structopt(long = "endpoint")]
+	pub endpoint: String,
+
+	/// S3 region of the destination cluster
+	#[structopt(long = "region", default_value = "garage")]
+	pub region: String,
+
+	/// Name of the destination bucket
+	#[structopt(long = "target-bucket")]
+	pub target_bucket: String,
+
+	/// Access key ID used to write to the destination bucket
+	#[structopt(long = "access-key-id")]
+	pub access_key_id: String,
+
+	/// Secret key used to write to the destination bucket
+	#[structopt(long = "secret-access-key")]
+	pub secret_access_key: String,
+
+	/// Only replicate objects whose key starts with this prefix
+	/// (can be given several times; all objects are replicated if not given)
+	#[structopt(long = "prefix")]
+	pub prefixes: Vec<String>,
+
+	/// Also replicate deletions
+	#[structopt(long = "replicate-deletes")]
+	pub replicate_deletes: bool,
+
+	/// Add the rule in a disabled state
+	#[structopt(long = "disabled")]
+	pub disabled: bool,
+}
+
+#[derive(Serialize, Deserialize, StructOpt, Debug)]
+pub struct RemoveReplicationRuleOpt {
+	/// Bucket name
+	pub bucket: String,
+
+	/// ID of the rule
+	#[structopt(long = "id")]
+	pub id: String,
+}
+
 #[derive(Serialize, Deserialize, StructOpt, Debug)]
 pub struct CleanupIncompleteUploadsOpt {
 	/// Abort multipart uploads older than this value
@@ -313,7 +567,7 @@ pub struct CleanupIncompleteUploadsOpt {
 pub enum KeyOperation {
 	/// List keys
 	#[structopt(name = "list", version = garage_version())]
-	List,
+	List(KeyListOpt),
 
 	/// Get key info
 	#[structopt(name = "info", version = garage_version())]
@@ -342,6 +596,30 @@ pub enum KeyOperation {
 	/// Import key
 	#[structopt(name = "import", version = garage_version())]
 	Import(KeyImportOpt),
+
+	/// Generate a new secret for a key, invalidating the previous one
+	#[structopt(name = "rotate-secret", version = garage_version())]
+	RotateSecret(KeyOpt),
+
+	/// Allow a key to be used for authenticating requests
+	#[structopt(name = "enable", version = garage_version())]
+	Enable(KeyOpt),
+
+	/// Prevent a key from being used for authenticating requests (without deleting it)
+	#[structopt(name = "disable", version = garage_version())]
+	Disable(KeyOpt),
+}
+
+#[derive(Serialize, Deserialize, StructOpt, Debug)]
+pub struct KeyListOpt {
+	/// Show the number of buckets each key has access to
+	#[structopt(long = "bucket-count")]
+	pub bucket_count: bool,
+
+	/// Sort keys (only `bucket-count` is supported, which sorts keys by
+	/// decreasing number of authorized buckets and implies --bucket-count)
+	#[structopt(long = "sort-by", possible_values = &["bucket-count"])]
+	pub sort_by: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, StructOpt, Debug)]
@@ -437,19 +715,147 @@ pub enum RepairWhat {
 	Tables,
 	/// Only repair (resync/rebalance) the set of stored blocks
 	#[structopt(name = "blocks", version = garage_version())]
-	Blocks,
+	Blocks {
+		/// Instead, download the blocks that this node should store but does not have
+		/// from the objects served by this S3 endpoint (e.g. to recover from the loss
+		/// of the data directory while the metadata is intact)
+		#[structopt(long = "fetch-from-gateway", requires_all = &["bucket", "access-key", "secret-key"])]
+		fetch_from_gateway: Option<String>,
+		/// Name of the bucket whose blocks to download, which must have
+		/// the same name on the gateway
+		#[structopt(long = "bucket")]
+		bucket: Option<String>,
+		/// Access key ID used to read from the gateway
+		#[structopt(long = "access-key")]
+		access_key: Option<String>,
+		/// Secret key used to read from the gateway
+		#[structopt(long = "secret-key")]
+		secret_key: Option<String>,
+		/// Region of the gateway
+		#[structopt(long = "region", default_value = "garage")]
+		region: String,
+		/// Maximum number of requests sent to the gateway per second
+		#[structopt(long = "max-requests-per-sec", default_value = "10")]
+		max_requests_per_sec: u32,
+	},
 	/// Only redo the propagation of object deletions to the version table (slow)
 	#[structopt(name = "versions", version = garage_version())]
-	Versions,
+	Versions {
+		/// Instead, check that the parts of completed multipart versions are numbered
+		/// without gaps, and that all their blocks belong to one of the parts
+		#[structopt(long = "check-part-ordering")]
+		check_part_ordering: bool,
+		/// Mark the multipart uploads that fail these checks as aborted
+		/// (this deletes completed objects whose parts are invalid)
+		#[structopt(long = "abort-invalid", requires = "check-part-ordering")]
+		abort_invalid: bool,
+		/// Instead, look for multipart versions that are still marked as being uploaded,
+		/// and report those whose parts have all been received
+		#[structopt(long = "check-complete-flag", conflicts_with = "check-part-ordering")]
+		check_complete_flag: bool,
+		/// Only consider versions whose upload started at least this long ago (default: 24h)
+		#[structopt(long = "min-age", requires = "check-complete-flag")]
+		min_age: Option<String>,
+		/// Mark the versions whose parts have all been received as complete,
+		/// unless a newer version of the object has been completed since
+		#[structopt(long = "promote", requires = "check-complete-flag")]
+		promote: bool,
+	},
 	/// Only redo the propagation of version deletions to the block ref table (extremely slow)
-	#[structopt(name = "block_refs", version = garage_version())]
-	BlockRefs,
+	#[structopt(name = "block_refs", alias = "block-ref-table", version = garage_version())]
+	BlockRefs {
+		/// Instead, look for block refs whose block hash was stored truncated,
+		/// and fix them if a single stored block matches the truncated hash
+		#[structopt(long = "fix-broken-links")]
+		fix_broken_links: bool,
+		/// Instead, delete the block ref tombstones that the table GC has not been
+		/// able to collect because some nodes were unavailable
+		/// (run with --all-nodes to process all partitions)
+		#[structopt(long = "remove-stale-tombstones", conflicts_with = "fix-broken-links")]
+		remove_stale_tombstones: bool,
+		/// Only delete tombstones created at least this long ago, which should be longer
+		/// than any node could have been disconnected from the cluster (default: 7d)
+		#[structopt(long = "older-than", requires = "remove-stale-tombstones")]
+		older_than: Option<String>,
+	},
+	/// Check objects in the object table
+	#[structopt(name = "objects", alias = "object-table", version = garage_version())]
+	Objects {
+		/// Remove delete markers that no longer hide any object version
+		/// (requires `enable_delete_marker_gc` in the configuration;
+		/// run with --all-nodes to process all partitions)
+		#[structopt(long = "reconcile-delete-markers")]
+		reconcile_delete_markers: bool,
+		/// Report objects whose current version is missing from the version table
+		/// (run with --all-nodes to process all partitions)
+		#[structopt(
+			long = "check-version-refs",
+			conflicts_with = "reconcile-delete-markers"
+		)]
+		check_version_refs: bool,
+		/// Mark the versions that are missing on all nodes as aborted, so that
+		/// the objects fall back to their previous version, if any
+		#[structopt(long = "fix", requires = "check-version-refs")]
+		fix: bool,
+	},
+	/// Check that the versions, block refs and blocks of every object are consistent,
+	/// and that each block can be read with a correct hash from the nodes storing it
+	/// (extremely slow, reads all data; run with --all-nodes to process all partitions)
+	#[structopt(name = "object-integrity", version = garage_version())]
+	ObjectIntegrity {
+		/// Recreate missing block refs, and send blocks again to the nodes where they
+		/// are missing or corrupted when a valid copy exists on another node
+		#[structopt(long = "auto-repair")]
+		auto_repair: bool,
+	},
+	/// Check that the size of the current version of each object matches the total size
+	/// of its blocks, and fix the objects whose size is wrong
+	/// (run with --all-nodes to process all partitions)
+	#[structopt(name = "sync-version-sizes", version = garage_version())]
+	SyncVersionSizes,
 	/// Verify integrity of all blocks on disc (extremely slow, i/o intensive)
 	#[structopt(name = "scrub", version = garage_version())]
 	Scrub {
 		#[structopt(subcommand)]
 		cmd: ScrubCmd,
 	},
+	/// Check owner and permissions of all block files in the data directory
+	#[structopt(name = "block-directory", version = garage_version())]
+	BlockDirectory {
+		/// Correct the owner and mode of block files that differ from the expected ones
+		#[structopt(long = "fix-permissions")]
+		fix_permissions: bool,
+	},
+	/// Look for block files anywhere under a directory, and move those whose content
+	/// matches their name to their expected location in the data directory
+	#[structopt(name = "rebuild-block-index", version = garage_version())]
+	RebuildBlockIndex {
+		/// Directory to scan recursively
+		#[structopt(long = "scan-dir")]
+		scan_dir: PathBuf,
+	},
+	/// Read all block files and check that their content matches their hash
+	#[structopt(name = "check-block-hashes", version = garage_version())]
+	CheckBlockHashes {
+		/// Also decompress and hash compressed blocks, instead of only checking their checksum
+		#[structopt(long = "rehash-all")]
+		rehash_all: bool,
+		/// Number of blocks to check in parallel
+		#[structopt(long = "threads", default_value = "4")]
+		threads: usize,
+		/// Path of the JSON report, written on each node
+		/// (default: block-hashes-report.json in the data directory)
+		#[structopt(long = "report-file")]
+		report_file: Option<PathBuf>,
+	},
+	/// Encrypt all block files with the current encryption key, including block files
+	/// written before encryption was enabled and those encrypted with a previous key
+	#[structopt(name = "rekey-blocks", version = garage_version())]
+	RekeyBlocks,
+	/// Move block files out of read-only data directories, and with the consistent-hash
+	/// placement, to the data directory given by their hash
+	#[structopt(name = "rebalance", version = garage_version())]
+	Rebalance,
 }
 
 #[derive(Serialize, Deserialize, StructOpt, Debug, Eq, PartialEq, Clone)]
@@ -484,6 +890,58 @@ pub struct OfflineRepairOpt {
 	pub what: OfflineRepairWhat,
 }
 
+#[derive(Serialize, Deserialize, StructOpt, Debug, Eq, PartialEq, Clone)]
+pub enum DbOperation {
+	/// Copy the metadata database into a database using another engine,
+	/// and switch the configuration to the new engine. The Garage server must
+	/// be stopped; if the migration is interrupted, run the command again to resume it.
+	#[structopt(name = "migrate", version = garage_version())]
+	Migrate(DbMigrateOpt),
+
+	/// Write all the trees of the metadata database to a compressed file,
+	/// independently of the database engine. The Garage server must be stopped.
+	#[structopt(name = "dump-tables", version = garage_version())]
+	DumpTables(DbDumpTablesOpt),
+
+	/// Restore the metadata database from a file written by `garage db dump-tables`.
+	/// The database must not exist yet, and the Garage server must be stopped.
+	#[structopt(name = "import-tables", version = garage_version())]
+	ImportTables(DbImportTablesOpt),
+}
+
+#[derive(Serialize, Deserialize, StructOpt, Debug, Eq, PartialEq, Clone)]
+pub struct DbMigrateOpt {
+	/// Engine of the current database (must match db_engine in the configuration)
+	#[structopt(long = "from")]
+	pub from: String,
+
+	/// Engine of the new database (sled, lmdb or sqlite)
+	#[structopt(long = "to")]
+	pub to: String,
+
+	/// Confirm the migration
+	#[structopt(long = "yes")]
+	pub yes: bool,
+}
+
+#[derive(Serialize, Deserialize, StructOpt, Debug, Eq, PartialEq, Clone)]
+pub struct DbDumpTablesOpt {
+	/// File to write, as zstd-compressed JSON lines
+	#[structopt(long = "output")]
+	pub output: PathBuf,
+}
+
+#[derive(Serialize, Deserialize, StructOpt, Debug, Eq, PartialEq, Clone)]
+pub struct DbImportTablesOpt {
+	/// File written by `garage db dump-tables`
+	#[structopt(long = "input")]
+	pub input: PathBuf,
+
+	/// Confirm the import
+	#[structopt(long = "yes")]
+	pub yes: bool,
+}
+
 #[derive(Serialize, Deserialize, StructOpt, Debug, Eq, PartialEq, Clone)]
 pub enum OfflineRepairWhat {
 	/// Repair K2V item counters
@@ -495,6 +953,17 @@ pub enum OfflineRepairWhat {
 	ObjectCounters,
 }
 
+#[derive(Serialize, Deserialize, StructOpt, Debug, Clone)]
+pub enum ScrubOperation {
+	/// Show the progress of the current scrub pass
+	#[structopt(name = "status", version = garage_version())]
+	Status {
+		/// Show the scrub status of all nodes
+		#[structopt(short = "a", long = "all-nodes")]
+		all_nodes: bool,
+	},
+}
+
 #[derive(Serialize, Deserialize, StructOpt, Debug, Clone)]
 pub struct StatsOpt {
 	/// Gather statistics from all nodes
@@ -505,6 +974,11 @@ pub struct StatsOpt {
 	#[structopt(short = "d", long = "detailed")]
 	pub detailed: bool,
 
+	/// Output the summary of the cluster health in JSON format
+	#[structopt(long = "json")]
+	#[serde(default)]
+	pub json: bool,
+
 	/// Don't show global cluster stats (internal use in RPC)
 	#[structopt(skip)]
 	#[serde(default)]
@@ -585,3 +1059,174 @@ pub enum BlockOperation {
 		blocks: Vec<String>,
 	},
 }
+
+#[derive(Serialize, Deserialize, StructOpt, Debug, Eq, PartialEq, Clone)]
+pub enum DebugOperation {
+	/// Show which nodes store the metadata and data blocks of an object
+	#[structopt(name = "explain-ring", version = garage_version())]
+	ExplainRing {
+		/// Name or ID of the bucket
+		bucket: String,
+		/// Key of the object in the bucket
+		object: String,
+	},
+	/// Show how a partition key is mapped to nodes: its position on the ring,
+	/// its partition, and the state of each node that stores the partition
+	#[structopt(name = "show-partition-layout", version = garage_version())]
+	ShowPartitionLayout {
+		/// Partition key, either as a hex-encoded hash or as BUCKET/OBJECT
+		partition_key: String,
+	},
+	/// Compact the metadata database to reclaim space after mass deletions
+	/// (the Garage server must be stopped, unless --background is used)
+	#[structopt(name = "compact-all-tables", version = garage_version())]
+	CompactAllTables(CompactTablesOpt),
+	/// List storage nodes sorted by configured capacity, with their actual disk usage
+	#[structopt(name = "list-nodes-by-capacity", version = garage_version())]
+	ListNodesByCapacity {
+		/// Sort nodes by decreasing capacity
+		#[structopt(long = "descending")]
+		descending: bool,
+		/// Highlight nodes whose data disk utilization is above this percentage
+		#[structopt(long = "threshold")]
+		threshold: Option<u64>,
+	},
+	/// Report access keys that have not been used recently
+	#[structopt(name = "analyze-keys", version = garage_version())]
+	AnalyzeKeys(AnalyzeKeysOpt),
+	/// Check that all online nodes have the same cluster layout
+	#[structopt(name = "check-layout-consistency", version = garage_version())]
+	CheckLayoutConsistency,
+	/// Ask all online nodes which nodes they see, and check that the cluster
+	/// is not split into several groups of nodes that each see a majority of the cluster
+	#[structopt(name = "check-network-partition", version = garage_version())]
+	CheckNetworkPartition,
+	/// Send RPC calls to a node one after the other, and report the time spent
+	/// serializing, deserializing and waiting for the network
+	#[structopt(name = "trace-rpc", version = garage_version())]
+	TraceRpc {
+		/// ID of the node to send the calls to
+		#[structopt(long = "node")]
+		node: String,
+		/// RPC to send: TableGet (read an entry of the object table),
+		/// BlockHas (ask whether a block is stored) or NodeStatus
+		#[structopt(
+			long = "operation",
+			default_value = "TableGet",
+			possible_values = &["TableGet", "BlockHas", "NodeStatus"]
+		)]
+		operation: String,
+		/// Number of calls to send
+		#[structopt(long = "count", default_value = "100")]
+		count: usize,
+	},
+	/// Write a test object to a bucket, read its data back from each node
+	/// that should store it, and delete it
+	#[structopt(name = "test-replication", version = garage_version())]
+	TestReplication {
+		/// Bucket in which to write the test object
+		#[structopt(long = "bucket")]
+		bucket: String,
+		/// Size of the test object, in bytes
+		#[structopt(long = "size-bytes", alias = "size", default_value = "1048576")]
+		size_bytes: u64,
+	},
+	/// Check that every block of the versions stored on a node has a block ref,
+	/// and that the block is stored on at least one node (outputs JSON)
+	#[structopt(name = "check-version-block-ref-consistency", version = garage_version())]
+	CheckVersionBlockRefConsistency {
+		/// ID of the node whose versions to check (defaults to the node we are connected to)
+		#[structopt(long = "node")]
+		node: Option<String>,
+	},
+	/// Check that the block files stored on this node contain as much data as the blocks
+	/// referenced by object versions, to find files that were only partially written
+	/// (e.g. after a power failure)
+	#[structopt(name = "check-partial-writes", version = garage_version())]
+	CheckPartialWrites {
+		/// Delete the incomplete files, so that the blocks are fetched again from other nodes
+		#[structopt(long = "fix")]
+		fix: bool,
+	},
+	/// Show the number of entries stored on this node for each partition key of a table
+	#[structopt(name = "partition-stats", version = garage_version())]
+	PartitionStats {
+		/// Name of the table (bucket_v2, bucket_alias, key, object, version, block_ref)
+		#[structopt(long = "table")]
+		table: String,
+		/// Number of partition keys to show, largest first
+		#[structopt(long = "top", default_value = "20")]
+		top: usize,
+	},
+	/// Show the value of all background variables of this node,
+	/// with their default value and where their current value comes from
+	#[structopt(name = "dump-bg-vars", version = garage_version())]
+	DumpBgVars {
+		/// Output JSON instead of a table
+		#[structopt(long = "json")]
+		json: bool,
+	},
+	/// Read all entries of the Sled metadata database, which makes Sled verify
+	/// the checksums of the data it reads, and report the trees that are corrupted
+	/// (outputs JSON; the Garage server must be stopped)
+	#[structopt(name = "check-sled-integrity", version = garage_version())]
+	CheckSledIntegrity(CheckSledIntegrityOpt),
+	/// List all background workers of this node, with their state
+	/// and statistics about their iterations
+	#[structopt(name = "list-bg-workers", version = garage_version())]
+	ListBgWorkers,
+	/// Estimate how long a repair would take on this node, by timing the processing
+	/// of a random sample of the entries it goes through (outputs JSON)
+	#[structopt(name = "estimate-repair-time", version = garage_version())]
+	EstimateRepairTime {
+		/// Type of repair
+		#[structopt(
+			long = "type",
+			possible_values = &["full-sync", "versions", "block-refs", "scrub"]
+		)]
+		repair_type: String,
+	},
+}
+
+#[derive(Serialize, Deserialize, StructOpt, Debug, Eq, PartialEq, Clone)]
+pub struct AnalyzeKeysOpt {
+	/// Report keys that have not been used for this duration (e.g. `90d`)
+	#[structopt(long = "unused-since")]
+	pub unused_since: String,
+
+	/// Path of the JSON file in which the report is written
+	#[structopt(long = "report-file")]
+	pub report_file: PathBuf,
+
+	/// Disable the reported keys
+	#[structopt(long = "delete-unused")]
+	pub delete_unused: bool,
+
+	/// Confirm disabling the reported keys
+	#[structopt(long = "confirm")]
+	pub confirm: bool,
+}
+
+#[derive(Serialize, Deserialize, StructOpt, Debug, Eq, PartialEq, Clone)]
+pub struct CheckSledIntegrityOpt {
+	/// Only check the given tree (e.g. `object:table`)
+	#[structopt(long = "tree")]
+	pub tree: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, StructOpt, Debug, Eq, PartialEq, Clone)]
+pub struct CompactTablesOpt {
+	/// Only compact the given table (e.g. `object`). Only supported by
+	/// the Sqlite engine, other engines can only compact the whole database
+	#[structopt(long = "table")]
+	pub table: Option<String>,
+
+	/// Ask the running Garage server to compact its database
+	/// in a low-priority background worker
+	#[structopt(long = "background")]
+	pub background: bool,
+
+	/// Confirm the launch of the compaction
+	#[structopt(long = "yes")]
+	pub yes: bool,
+}
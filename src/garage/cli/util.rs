@@ -12,9 +12,11 @@ use garage_block::manager::BlockResyncErrorInfo;
 
 use garage_model::bucket_table::*;
 use garage_model::key_table::*;
+use garage_model::permission::BucketKeyPerm;
 use garage_model::s3::object_table::{BYTES, OBJECTS, UNFINISHED_UPLOADS};
 use garage_model::s3::version_table::Version;
 
+use crate::admin::KeyBucketCounts;
 use crate::cli::structs::WorkerListOpt;
 
 pub fn print_bucket_list(bl: Vec<Bucket>) {
@@ -58,6 +60,18 @@ pub fn print_key_list(kl: Vec<(String, String)>) {
 	format_table(table);
 }
 
+pub fn print_key_list_bucket_counts(kl: Vec<KeyBucketCounts>) {
+	println!("List of keys:");
+	let mut table = vec!["\tID\tName\tRead-write\tRead-only\tOwner\tTotal".to_string()];
+	for key in kl {
+		table.push(format!(
+			"\t{}\t{}\t{}\t{}\t{}\t{}",
+			key.key_id, key.name, key.read_write, key.read_only, key.owner, key.total
+		));
+	}
+	format_table(table);
+}
+
 pub fn print_key_info(key: &Key, relevant_buckets: &HashMap<Uuid, Bucket>) {
 	let bucket_global_aliases = |b: &Uuid| {
 		if let Some(bucket) = relevant_buckets.get(b) {
@@ -80,7 +94,8 @@ pub fn print_key_info(key: &Key, relevant_buckets: &HashMap<Uuid, Bucket>) {
 		Deletable::Present(p) => {
 			println!("Key name: {}", p.name.get());
 			println!("Key ID: {}", key.key_id);
-			println!("Secret key: {}", p.secret_key);
+			println!("Secret key: {}", p.secret_key.get());
+			println!("Enabled: {}", p.enabled.get());
 			println!("Can create buckets: {}", p.allow_create_bucket.get());
 			println!("\nKey-specific bucket aliases:");
 			let mut table = vec![];
@@ -105,6 +120,11 @@ pub fn print_key_info(key: &Key, relevant_buckets: &HashMap<Uuid, Bucket>) {
 				let rflag = if perm.allow_read { "R" } else { " " };
 				let wflag = if perm.allow_write { "W" } else { " " };
 				let oflag = if perm.allow_owner { "O" } else { " " };
+				let gflag = if perm.allow_bypass_governance {
+					"G"
+				} else {
+					" "
+				};
 				let local_aliases = p
 					.local_aliases
 					.items()
@@ -114,13 +134,15 @@ pub fn print_key_info(key: &Key, relevant_buckets: &HashMap<Uuid, Bucket>) {
 					.collect::<Vec<_>>()
 					.join(", ");
 				table.push(format!(
-					"\t{}{}{}\t{}\t{}\t{:?}",
+					"\t{}{}{}{}\t{}\t{}\t{:?}\t{}",
 					rflag,
 					wflag,
 					oflag,
+					gflag,
 					bucket_global_aliases(bucket_id),
 					local_aliases,
-					bucket_id
+					bucket_id,
+					format_single_ops(perm)
 				));
 			}
 			format_table(table);
@@ -168,6 +190,75 @@ pub fn print_bucket_info(
 
 			println!("\nWebsite access: {}", p.website_config.get().is_some());
 
+			match p.versioning_state.get() {
+				VersioningState::Disabled => (),
+				VersioningState::Enabled => println!("\nVersioning: enabled"),
+				VersioningState::Suspended => println!("\nVersioning: suspended"),
+			}
+
+			match p.object_lock_config.get() {
+				Some(ObjectLockConfig {
+					default_retention: Some(r),
+				}) => println!(
+					"\nObject lock: enabled (default retention: {:?}, {} days)",
+					r.mode, r.days
+				),
+				Some(_) => println!("\nObject lock: enabled"),
+				None => (),
+			}
+
+			match p.encryption_config.get() {
+				Some(EncryptionConfig {
+					sse_algorithm,
+					kms_master_key_id: Some(key_id),
+				}) => println!(
+					"\nServer-side encryption configuration: {} (key {})",
+					sse_algorithm, key_id
+				),
+				Some(c) => println!(
+					"\nServer-side encryption configuration: {}",
+					c.sse_algorithm
+				),
+				None => (),
+			}
+			if *p.encryption_required.get() {
+				println!("\nServer-side encryption required for all uploads");
+			}
+
+			if let Some(mos) = p.max_object_size.get() {
+				let mos = bytesize::ByteSize::b(*mos);
+				println!(
+					"\nMaximum object size (PutObject): {} ({})",
+					mos.to_string_as(true),
+					mos.to_string_as(false)
+				);
+			}
+
+			if let Some(level) = p.compression_level.get() {
+				println!("\nCompression level: {}", level);
+			}
+
+			if let Some(rules) = p.replication_config.get() {
+				println!("\nReplication rules:");
+				let mut table = vec!["\tID\tEnabled\tDestination\tPrefixes\tDeletes".to_string()];
+				for r in rules.iter() {
+					table.push(format!(
+						"\t{}\t{}\t{}/{}\t{}\t{}",
+						r.id,
+						r.enabled,
+						r.destination.endpoint.trim_end_matches('/'),
+						r.destination.bucket,
+						if r.prefixes.is_empty() {
+							"*".to_string()
+						} else {
+							r.prefixes.join(",")
+						},
+						r.replicate_deletes
+					));
+				}
+				format_table(table);
+			}
+
 			let quotas = p.quotas.get();
 			if quotas.max_size.is_some() || quotas.max_objects.is_some() {
 				println!("\nQuotas:");
@@ -209,13 +300,20 @@ pub fn print_bucket_info(
 				let rflag = if perm.allow_read { "R" } else { " " };
 				let wflag = if perm.allow_write { "W" } else { " " };
 				let oflag = if perm.allow_owner { "O" } else { " " };
+				let gflag = if perm.allow_bypass_governance {
+					"G"
+				} else {
+					" "
+				};
 				table.push(format!(
-					"\t{}{}{}\t{}\t{}",
+					"\t{}{}{}{}\t{}\t{}\t{}",
 					rflag,
 					wflag,
 					oflag,
+					gflag,
 					k,
-					key_name(k)
+					key_name(k),
+					format_single_ops(perm)
 				));
 			}
 			format_table(table);
@@ -223,6 +321,16 @@ pub fn print_bucket_info(
 	};
 }
 
+/// Operations given or removed individually, as `+Op` and `-Op`
+fn format_single_ops(perm: &BucketKeyPerm) -> String {
+	perm.extra_ops
+		.iter()
+		.map(|op| format!("+{}", op))
+		.chain(perm.denied_ops.iter().map(|op| format!("-{}", op)))
+		.collect::<Vec<_>>()
+		.join(",")
+}
+
 pub fn find_matching_node(
 	cand: impl std::iter::Iterator<Item = Uuid>,
 	pattern: &str,
@@ -0,0 +1,96 @@
+//! Runtime modification of the log filter of the Garage daemon
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tracing_subscriber::filter::EnvFilter;
+
+use garage_util::error::Error;
+
+type ReloadFn = Box<dyn Fn(EnvFilter) -> Result<(), String> + Send + Sync>;
+
+/// Handle on the log filter of the running daemon,
+/// used by `garage server set-log-level`
+pub struct LogFilter {
+	/// Filter the daemon was started with (RUST_LOG, or the default value)
+	from_env: String,
+	/// Filter restored after a temporary change: `log_filter` from the
+	/// configuration file if set, `from_env` otherwise
+	configured: Mutex<String>,
+	reload: ReloadFn,
+	/// Current filter, and the number of times it was changed,
+	/// used to cancel a pending revert when the filter changes again
+	current: Mutex<(String, u64)>,
+}
+
+impl LogFilter {
+	pub fn new(configured: String, reload: ReloadFn) -> Arc<Self> {
+		Arc::new(Self {
+			current: Mutex::new((configured.clone(), 0)),
+			configured: Mutex::new(configured.clone()),
+			from_env: configured,
+			reload,
+		})
+	}
+
+	/// Set the level of the log messages of Garage and Netapp,
+	/// and return the filter that was previously in use.
+	/// If `revert_after` is set, the filter the daemon was started with
+	/// is restored after that delay, unless the filter is changed again in the meantime.
+	pub fn set_level(
+		self: &Arc<Self>,
+		level: &str,
+		revert_after: Option<Duration>,
+	) -> Result<String, Error> {
+		let filter = format!("netapp={},garage={}", level, level);
+		let (old_filter, generation) = self
+			.set_filter(filter, None)?
+			.expect("filter is always changed when no generation is given");
+
+		if let Some(delay) = revert_after {
+			let log_filter = self.clone();
+			tokio::spawn(async move {
+				tokio::time::sleep(delay).await;
+				let configured = log_filter.configured.lock().unwrap().clone();
+				match log_filter.set_filter(configured.clone(), Some(generation)) {
+					Ok(Some(_)) => warn!("Log filter reverted to `{}`", configured),
+					Ok(None) => (),
+					Err(e) => error!("Could not revert log filter: {}", e),
+				}
+			});
+		}
+
+		Ok(old_filter)
+	}
+
+	/// Use the `log_filter` of the configuration file, or the filter from the
+	/// environment if it is not set, both now and when reverting a temporary change
+	pub fn set_configured(&self, filter: Option<String>) -> Result<(), Error> {
+		let filter = filter.unwrap_or_else(|| self.from_env.clone());
+		self.set_filter(filter.clone(), None)?;
+		*self.configured.lock().unwrap() = filter;
+		Ok(())
+	}
+
+	/// Replace the current filter, and return the previous filter and the new generation.
+	/// If `if_generation` is set, this is only done if no other change happened
+	/// since that generation (otherwise `None` is returned).
+	fn set_filter(
+		&self,
+		filter: String,
+		if_generation: Option<u64>,
+	) -> Result<Option<(String, u64)>, Error> {
+		let env_filter = EnvFilter::try_new(&filter)
+			.map_err(|e| Error::Message(format!("Invalid log filter {}: {}", filter, e)))?;
+
+		let mut current = self.current.lock().unwrap();
+		if if_generation.map(|g| g != current.1).unwrap_or(false) {
+			return Ok(None);
+		}
+		(self.reload)(env_filter).map_err(Error::Message)?;
+
+		let old_filter = std::mem::replace(&mut current.0, filter);
+		current.1 += 1;
+		Ok(Some((old_filter, current.1)))
+	}
+}
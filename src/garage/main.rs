@@ -6,6 +6,7 @@ extern crate tracing;
 
 mod admin;
 mod cli;
+mod log_filter;
 mod repair;
 mod server;
 #[cfg(feature = "telemetry-otlp")]
@@ -63,7 +64,7 @@ struct Opt {
 	cmd: Command,
 }
 
-#[derive(StructOpt, Debug)]
+#[derive(StructOpt, Debug, Clone)]
 pub struct Secrets {
 	/// RPC secret network key, used to replace rpc_secret in config.toml when running the
 	/// daemon or doing admin operations
@@ -79,6 +80,11 @@ pub struct Secrets {
 	/// running the Garage daemon
 	#[structopt(long = "metrics-token", env = "GARAGE_METRICS_TOKEN")]
 	pub metrics_token: Option<String>,
+
+	/// Block encryption key, replaces encryption_key in config.toml when
+	/// running the Garage daemon
+	#[structopt(long = "encryption-key", env = "GARAGE_ENCRYPTION_KEY")]
+	pub encryption_key: Option<String>,
 }
 
 #[tokio::main]
@@ -157,22 +163,47 @@ async fn main() {
 	// Initialize logging as well as other libraries used in Garage
 	if std::env::var("RUST_LOG").is_err() {
 		let default_log = match &opt.cmd {
-			Command::Server => "netapp=info,garage=info",
+			Command::Server(ServerOpt { cmd: None }) => "netapp=info,garage=info",
 			_ => "netapp=warn,garage=warn",
 		};
 		std::env::set_var("RUST_LOG", default_log)
 	}
-	tracing_subscriber::fmt()
+	let subscriber = tracing_subscriber::fmt()
 		.with_writer(std::io::stderr)
 		.with_env_filter(tracing_subscriber::filter::EnvFilter::from_default_env())
-		.init();
+		.with_filter_reloading();
+	let reload_handle = subscriber.reload_handle();
+	subscriber.init();
+	let log_filter = log_filter::LogFilter::new(
+		std::env::var("RUST_LOG").unwrap_or_default(),
+		Box::new(move |filter| reload_handle.reload(filter).map_err(|e| e.to_string())),
+	);
 	sodiumoxide::init().expect("Unable to init sodiumoxide");
 
 	let res = match opt.cmd {
-		Command::Server => server::run_server(opt.config_file, opt.secrets).await,
+		Command::Server(ServerOpt { cmd: None }) => {
+			server::run_server(opt.config_file, opt.secrets, log_filter).await
+		}
 		Command::OfflineRepair(repair_opt) => {
 			repair::offline::offline_repair(opt.config_file, opt.secrets, repair_opt).await
 		}
+		Command::Debug(DebugOperation::CompactAllTables(compact_opt))
+			if !compact_opt.background =>
+		{
+			repair::offline::offline_compact_tables(opt.config_file, opt.secrets, compact_opt).await
+		}
+		Command::Debug(DebugOperation::CheckSledIntegrity(check_opt)) => {
+			repair::offline::offline_check_sled_integrity(opt.config_file, opt.secrets, check_opt)
+		}
+		Command::Db(DbOperation::Migrate(migrate_opt)) => {
+			repair::offline::offline_migrate_db(opt.config_file, opt.secrets, migrate_opt)
+		}
+		Command::Db(DbOperation::DumpTables(dump_opt)) => {
+			repair::offline::offline_dump_tables(opt.config_file, opt.secrets, dump_opt)
+		}
+		Command::Db(DbOperation::ImportTables(import_opt)) => {
+			repair::offline::offline_import_tables(opt.config_file, opt.secrets, import_opt)
+		}
 		Command::Node(NodeOperation::NodeId(node_id_opt)) => {
 			node_id_command(opt.config_file, node_id_opt.quiet)
 		}
@@ -266,5 +297,8 @@ fn fill_secrets(mut config: Config, secrets: Secrets) -> Config {
 	if secrets.metrics_token.is_some() {
 		config.admin.metrics_token = secrets.metrics_token;
 	}
+	if secrets.encryption_key.is_some() {
+		config.encryption_key = secrets.encryption_key;
+	}
 	config
 }
@@ -0,0 +1,259 @@
+//! Recovery of the blocks missing on this node by downloading
+//! the corresponding objects from an S3 endpoint
+
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+use aws_sigv4::http_request::{
+	sign, PayloadChecksumKind, PercentEncodingMode, SignableRequest, SigningParams,
+	SigningSettings, UriPathNormalizationMode,
+};
+use bytes::Bytes;
+use hyper::client::HttpConnector;
+use hyper::header::RANGE;
+use hyper::{Body, Client, Request, StatusCode};
+use hyper_rustls::HttpsConnector;
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+use garage_api::encoding::uri_encode;
+use garage_model::garage::Garage;
+use garage_model::s3::block_ref_table::*;
+use garage_model::s3::version_table::*;
+use garage_table::*;
+use garage_util::background::*;
+use garage_util::data::*;
+use garage_util::error::{Error, OkOrMessage};
+use garage_util::migrate::Migrate;
+
+/// S3 endpoint from which the content of objects is read
+pub struct S3Gateway {
+	pub endpoint: String,
+	bucket: String,
+	access_key: String,
+	secret_key: String,
+	region: String,
+	client: Client<HttpsConnector<HttpConnector>>,
+}
+
+impl S3Gateway {
+	pub fn new(
+		endpoint: String,
+		bucket: String,
+		access_key: String,
+		secret_key: String,
+		region: String,
+	) -> Result<Self, Error> {
+		endpoint
+			.parse::<hyper::Uri>()
+			.map_err(|e| Error::Message(format!("Invalid gateway URL {}: {}", endpoint, e)))?;
+
+		let connector = hyper_rustls::HttpsConnectorBuilder::new()
+			.with_native_roots()
+			.https_or_http()
+			.enable_http1()
+			.build();
+
+		Ok(Self {
+			endpoint: endpoint.trim_end_matches('/').to_string(),
+			bucket,
+			access_key,
+			secret_key,
+			region,
+			client: Client::builder().build(connector),
+		})
+	}
+
+	/// Read `len` bytes of an object of the bucket, starting at `offset`
+	async fn get_range(&self, key: &str, offset: u64, len: u64) -> Result<Bytes, Error> {
+		let url = format!(
+			"{}/{}/{}",
+			self.endpoint,
+			uri_encode(&self.bucket, true),
+			uri_encode(key, false)
+		);
+		let mut req = Request::get(url)
+			.header(RANGE, format!("bytes={}-{}", offset, offset + len - 1))
+			.body(Bytes::new())?;
+
+		let mut settings = SigningSettings::default();
+		settings.percent_encoding_mode = PercentEncodingMode::Single;
+		settings.payload_checksum_kind = PayloadChecksumKind::XAmzSha256;
+		settings.uri_path_normalization_mode = UriPathNormalizationMode::Disabled;
+		let params = SigningParams::builder()
+			.access_key(&self.access_key)
+			.secret_key(&self.secret_key)
+			.region(&self.region)
+			.service_name("s3")
+			.time(SystemTime::now())
+			.settings(settings)
+			.build()
+			.map_err(|e| Error::Message(format!("Could not sign request: {}", e)))?;
+		let (signing_instructions, _signature) = sign(SignableRequest::from(&req), &params)
+			.map_err(|e| Error::Message(format!("Could not sign request: {}", e)))?
+			.into_parts();
+		signing_instructions.apply_to_request(&mut req);
+
+		let resp = self.client.request(req.map(Body::from)).await?;
+		let status = resp.status();
+		let body = hyper::body::to_bytes(resp.into_body()).await?;
+		match status {
+			StatusCode::PARTIAL_CONTENT => Ok(body),
+			// The gateway ignored the Range header and sent the whole object
+			StatusCode::OK if body.len() as u64 >= offset + len => {
+				Ok(body.slice(offset as usize..(offset + len) as usize))
+			}
+			s => Err(Error::Message(format!(
+				"Gateway returned {} when reading {}",
+				s, key
+			))),
+		}
+	}
+}
+
+/// Position and size of a block in the content of an object version
+fn block_range(version: &Version, hash: &Hash) -> Option<(u64, u64)> {
+	let mut offset = 0;
+	for (_, block) in version.blocks.items().iter() {
+		if block.hash == *hash {
+			return Some((offset, block.size));
+		}
+		offset += block.size;
+	}
+	None
+}
+
+// ----
+
+pub struct FetchFromGatewayWorker {
+	garage: Arc<Garage>,
+	gateway: S3Gateway,
+	bucket_id: Uuid,
+	request_interval: Duration,
+	last_request: Option<Instant>,
+	pos: Vec<u8>,
+	counter: usize,
+	missing: usize,
+	fetched: usize,
+	failed: usize,
+}
+
+impl FetchFromGatewayWorker {
+	pub fn new(
+		garage: Arc<Garage>,
+		gateway: S3Gateway,
+		bucket_id: Uuid,
+		max_requests_per_sec: u32,
+	) -> Self {
+		Self {
+			garage,
+			gateway,
+			bucket_id,
+			request_interval: Duration::from_secs(1) / std::cmp::max(max_requests_per_sec, 1),
+			last_request: None,
+			pos: vec![],
+			counter: 0,
+			missing: 0,
+			fetched: 0,
+			failed: 0,
+		}
+	}
+
+	async fn fetch_block(&mut self, hash: &Hash, version: &Version) -> Result<(), Error> {
+		let (offset, len) =
+			block_range(version, hash).ok_or_message("Block is not part of its version")?;
+
+		if let Some(last) = self.last_request {
+			tokio::time::sleep_until(last + self.request_interval).await;
+		}
+		self.last_request = Some(Instant::now());
+
+		let data = self.gateway.get_range(&version.key, offset, len).await?;
+		self.garage
+			.block_manager
+			.write_fetched_block(hash, data)
+			.await
+	}
+}
+
+#[async_trait]
+impl Worker for FetchFromGatewayWorker {
+	fn name(&self) -> String {
+		"Fetch blocks from gateway worker".into()
+	}
+
+	fn status(&self) -> WorkerStatus {
+		WorkerStatus {
+			progress: Some(self.counter.to_string()),
+			freeform: vec![
+				format!("Gateway: {}", self.gateway.endpoint),
+				format!("Missing blocks: {}", self.missing),
+				format!("Fetched: {}", self.fetched),
+				format!("Failed: {}", self.failed),
+			],
+			..Default::default()
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		let (item_bytes, next_pos) =
+			match self.garage.block_ref_table.data.store.get_gt(&self.pos)? {
+				Some((k, v)) => (v, k),
+				None => {
+					info!(
+						"fetch_from_gateway: finished, done {}, {} missing blocks, {} fetched, {} failed",
+						self.counter, self.missing, self.fetched, self.failed
+					);
+					return Ok(WorkerState::Done);
+				}
+			};
+		self.counter += 1;
+		self.pos = next_pos;
+
+		let block_ref = BlockRef::decode(&item_bytes).ok_or_message("Cannot decode BlockRef")?;
+		if block_ref.deleted.get()
+			|| !self
+				.garage
+				.block_manager
+				.need_block(&block_ref.block)
+				.await?
+		{
+			return Ok(WorkerState::Busy);
+		}
+
+		let version = match self
+			.garage
+			.version_table
+			.get(&block_ref.version, &EmptyKey)
+			.await?
+		{
+			Some(v) if !v.deleted.get() && v.bucket_id == self.bucket_id => v,
+			_ => return Ok(WorkerState::Busy),
+		};
+
+		self.missing += 1;
+		match self.fetch_block(&block_ref.block, &version).await {
+			Ok(()) => {
+				debug!(
+					"Fetched block {:?} from object {}",
+					block_ref.block, version.key
+				);
+				self.fetched += 1;
+			}
+			Err(e) => {
+				warn!(
+					"Could not fetch block {:?} from object {}: {}",
+					block_ref.block, version.key, e
+				);
+				self.failed += 1;
+			}
+		}
+
+		Ok(WorkerState::Busy)
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		unreachable!()
+	}
+}
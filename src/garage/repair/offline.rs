@@ -1,9 +1,31 @@
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use std::ops::Bound;
+
+use serde::{Deserialize, Serialize};
+
+use garage_db as db;
 
 use garage_util::config::*;
 use garage_util::error::*;
+use garage_util::migrate::Migrate;
+use garage_util::version::garage_version;
 
-use garage_model::garage::Garage;
+use garage_table::TableSchema;
+
+use garage_model::bucket_alias_table::BucketAliasTable;
+use garage_model::bucket_table::BucketTable;
+use garage_model::garage::{db_path, open_db, Garage};
+use garage_model::index_counter::CounterTable;
+#[cfg(feature = "k2v")]
+use garage_model::k2v::item_table::{K2VItem, K2VItemTable};
+use garage_model::key_table::KeyTable;
+use garage_model::s3::block_ref_table::BlockRefTable;
+use garage_model::s3::object_table::{Object, ObjectTable};
+use garage_model::s3::replication_state_table::ReplicationStateTable;
+use garage_model::s3::version_table::VersionTable;
 
 use crate::cli::structs::*;
 use crate::{fill_secrets, Secrets};
@@ -45,3 +67,745 @@ pub async fn offline_repair(
 
 	Ok(())
 }
+
+/// Rough throughput of a database compaction, used to estimate its duration
+const COMPACTION_BYTES_PER_SEC: u64 = 50 * 1024 * 1024;
+
+pub async fn offline_compact_tables(
+	config_file: PathBuf,
+	secrets: Secrets,
+	opt: CompactTablesOpt,
+) -> Result<(), Error> {
+	if !opt.yes {
+		return Err(Error::Message(
+			"Please add the --yes flag to launch the compaction. The Garage server must be stopped while it is running.".into(),
+		));
+	}
+
+	info!("Loading configuration...");
+	let config = fill_secrets(read_config(config_file)?, secrets);
+
+	let db_path = db_path(&config);
+	if !db_path.exists() {
+		return Err(Error::Message(format!(
+			"No metadata database found at {}",
+			db_path.display()
+		)));
+	}
+	let size_before = disk_usage(&db_path)?;
+	println!(
+		"Database size: {}",
+		bytesize::ByteSize::b(size_before).to_string_as(false)
+	);
+	println!(
+		"Estimated duration: {}s",
+		size_before / COMPACTION_BYTES_PER_SEC + 1
+	);
+
+	let db = open_db(&config, &db_path)?;
+	if let Some(table) = &opt.table {
+		for tree in table_trees(&db, table)? {
+			println!(
+				"Compacting {} ({} items)",
+				tree,
+				db.open_tree(&tree)?.len()?
+			);
+			compact_tree(&db, &tree)?;
+		}
+		drop(db);
+	} else {
+		match db.compact() {
+			Ok(()) => drop(db),
+			Err(e) => {
+				// No in-place compaction for this engine: rewrite the database
+				// into a fresh one, which contains no free space, and swap them
+				info!("{}, rewriting database instead", e);
+				let mut tmp_path = db_path.clone().into_os_string();
+				tmp_path.push(".compact-tmp");
+				let tmp_path = PathBuf::from(tmp_path);
+				let mut old_path = db_path.clone().into_os_string();
+				old_path.push(".compact-old");
+				let old_path = PathBuf::from(old_path);
+
+				remove_path(&tmp_path)?;
+				let tmp_db = open_db(&config, &tmp_path)?;
+				tmp_db.import(&db)?;
+				drop(tmp_db);
+				drop(db);
+
+				std::fs::rename(&db_path, &old_path)?;
+				std::fs::rename(&tmp_path, &db_path)?;
+				remove_path(&old_path)?;
+			}
+		}
+	}
+
+	let size_after = disk_usage(&db_path)?;
+	println!(
+		"Compaction finished. Database size: {} (before: {})",
+		bytesize::ByteSize::b(size_after).to_string_as(false),
+		bytesize::ByteSize::b(size_before).to_string_as(false)
+	);
+
+	Ok(())
+}
+
+/// Names of the database trees that store the given table
+pub fn table_trees(db: &db::Db, table: &str) -> Result<Vec<String>, Error> {
+	let prefix = format!("{}:", table);
+	let trees = db
+		.list_trees()?
+		.into_iter()
+		.filter(|t| t.starts_with(&prefix))
+		.collect::<Vec<_>>();
+	if trees.is_empty() {
+		return Err(Error::Message(format!("No such table: {}", table)));
+	}
+	Ok(trees)
+}
+
+/// Compact a single tree, for engines that can do it without
+/// touching the rest of the database
+pub fn compact_tree(db: &db::Db, tree: &str) -> Result<(), Error> {
+	db.open_tree(tree)?.compact().map_err(|e| {
+		Error::Message(format!(
+			"Unable to compact {}: {} (run the compaction without --table to compact the whole database)",
+			tree, e
+		))
+	})
+}
+
+#[cfg(feature = "sled")]
+#[derive(Serialize)]
+struct SledIntegrityReport {
+	db_path: PathBuf,
+	trees: Vec<SledTreeIntegrity>,
+	trees_failed: usize,
+}
+
+#[cfg(feature = "sled")]
+#[derive(Serialize)]
+struct SledTreeIntegrity {
+	name: String,
+	/// Number of entries that could be read and whose checksums are valid
+	entries_checked: u64,
+	error: Option<String>,
+	/// Keys (hex-encoded) that might be affected by the error: all keys after
+	/// the last entry that could be read (Sled does not tell us where the
+	/// corrupted data ends)
+	affected_keys_after: Option<String>,
+}
+
+/// Read all entries of the Sled database, which verifies their checksums,
+/// and output a JSON report
+#[cfg(feature = "sled")]
+pub fn offline_check_sled_integrity(
+	config_file: PathBuf,
+	secrets: Secrets,
+	opt: CheckSledIntegrityOpt,
+) -> Result<(), Error> {
+	use garage_db::sled_adapter::sled;
+
+	info!("Loading configuration...");
+	let config = fill_secrets(read_config(config_file)?, secrets);
+	if config.db_engine != "sled" {
+		return Err(Error::Message(format!(
+			"The metadata database uses the {} engine, not Sled",
+			config.db_engine
+		)));
+	}
+
+	let db_path = db_path(&config);
+	if !db_path.exists() {
+		return Err(Error::Message(format!(
+			"No metadata database found at {}",
+			db_path.display()
+		)));
+	}
+	let db = sled::Config::default()
+		.path(&db_path)
+		.cache_capacity(config.sled_cache_capacity)
+		.open()
+		.ok_or_message("Unable to open sled DB (is the Garage server stopped?)")?;
+
+	let mut tree_names = db
+		.tree_names()
+		.into_iter()
+		.map(|n| String::from_utf8_lossy(&n).into_owned())
+		.filter(|n| n != "__sled__default")
+		.collect::<Vec<_>>();
+	tree_names.sort();
+	if let Some(tree) = &opt.tree {
+		if !tree_names.contains(tree) {
+			return Err(Error::Message(format!("No such tree: {}", tree)));
+		}
+		tree_names = vec![tree.clone()];
+	}
+
+	let mut trees = vec![];
+	for name in tree_names {
+		info!("Checking tree {}...", name);
+		let mut result = SledTreeIntegrity {
+			name,
+			entries_checked: 0,
+			error: None,
+			affected_keys_after: None,
+		};
+		match db.open_tree(&result.name) {
+			Ok(tree) => {
+				let mut last_key = vec![];
+				for item in tree.iter() {
+					match item {
+						Ok((k, _v)) => {
+							result.entries_checked += 1;
+							last_key = k.to_vec();
+						}
+						Err(e) => {
+							result.error = Some(e.to_string());
+							result.affected_keys_after = Some(hex::encode(&last_key));
+							break;
+						}
+					}
+				}
+			}
+			Err(e) => {
+				result.error = Some(e.to_string());
+				result.affected_keys_after = Some(String::new());
+			}
+		}
+		trees.push(result);
+	}
+
+	let trees_failed = trees.iter().filter(|t| t.error.is_some()).count();
+	let report = SledIntegrityReport {
+		db_path,
+		trees,
+		trees_failed,
+	};
+	println!("{}", serde_json::to_string_pretty(&report)?);
+
+	if trees_failed > 0 {
+		eprintln!(
+			"{} tree(s) could not be read entirely. Consider restoring the metadata database from a backup or resyncing it from other nodes, and switching to another database engine (LMDB or Sqlite, see the `convert_db` utility).",
+			trees_failed
+		);
+	}
+	Ok(())
+}
+
+#[cfg(not(feature = "sled"))]
+pub fn offline_check_sled_integrity(
+	_config_file: PathBuf,
+	_secrets: Secrets,
+	_opt: CheckSledIntegrityOpt,
+) -> Result<(), Error> {
+	Err(Error::Message("sled db not available in this build".into()))
+}
+
+/// Number of entries copied in each transaction of a database migration
+const DB_MIGRATION_BATCH_SIZE: usize = 10_000;
+
+/// Progress of a database migration, saved in the metadata directory
+/// so that an interrupted migration can be resumed
+#[derive(Serialize, Deserialize, Default)]
+struct DbMigrationCheckpoint {
+	from: String,
+	to: String,
+	/// Trees that have been entirely copied
+	done_trees: Vec<String>,
+	/// Tree being copied, and the last key (hex-encoded) that was copied
+	current_tree: Option<(String, String)>,
+}
+
+/// Path of the file in which the progress of a database migration is saved.
+/// The server refuses to start while this file exists.
+pub fn db_migration_checkpoint_path(config: &Config) -> PathBuf {
+	config.metadata_dir.join("db-migration-checkpoint.json")
+}
+
+fn canonical_db_engine(engine: &str) -> Result<&'static str, Error> {
+	match engine {
+		"sled" => Ok("sled"),
+		"lmdb" | "heed" => Ok("lmdb"),
+		"sqlite" | "sqlite3" | "rusqlite" => Ok("sqlite"),
+		e => Err(Error::Message(format!("Invalid DB engine: {}", e))),
+	}
+}
+
+pub fn offline_migrate_db(
+	config_file: PathBuf,
+	secrets: Secrets,
+	opt: DbMigrateOpt,
+) -> Result<(), Error> {
+	if !opt.yes {
+		return Err(Error::Message(
+			"Please add the --yes flag to launch the migration. The Garage server must be stopped while it is running.".into(),
+		));
+	}
+
+	info!("Loading configuration...");
+	let config = fill_secrets(read_config(config_file.clone())?, secrets);
+
+	let from = canonical_db_engine(&opt.from)?;
+	let to = canonical_db_engine(&opt.to)?;
+	if canonical_db_engine(&config.db_engine)? != from {
+		return Err(Error::Message(format!(
+			"The configured database engine is {}, not {}",
+			config.db_engine, opt.from
+		)));
+	}
+	if from == to {
+		return Err(Error::Message(
+			"The source and destination engines are the same".into(),
+		));
+	}
+
+	let mut from_config = config.clone();
+	from_config.db_engine = from.to_string();
+	let mut to_config = config.clone();
+	to_config.db_engine = to.to_string();
+	let from_path = db_path(&from_config);
+	let to_path = db_path(&to_config);
+
+	let checkpoint_path = db_migration_checkpoint_path(&config);
+	let mut checkpoint = match std::fs::read(&checkpoint_path) {
+		Ok(bytes) => {
+			let checkpoint: DbMigrationCheckpoint = serde_json::from_slice(&bytes)?;
+			if checkpoint.from != from || checkpoint.to != to {
+				return Err(Error::Message(format!(
+					"A migration from {} to {} is already in progress",
+					checkpoint.from, checkpoint.to
+				)));
+			}
+			println!(
+				"Resuming migration, {} trees already copied",
+				checkpoint.done_trees.len()
+			);
+			checkpoint
+		}
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+			if to_path.exists() {
+				return Err(Error::Message(format!(
+					"A database already exists at {}, remove it first",
+					to_path.display()
+				)));
+			}
+			DbMigrationCheckpoint {
+				from: from.to_string(),
+				to: to.to_string(),
+				..Default::default()
+			}
+		}
+		Err(e) => return Err(e.into()),
+	};
+	save_db_migration_checkpoint(&checkpoint_path, &checkpoint)?;
+
+	let from_db = open_db(&from_config, &from_path)?;
+	let to_db = open_db(&to_config, &to_path)?;
+
+	let mut trees = from_db.list_trees()?;
+	trees.sort();
+	for name in trees.iter() {
+		if checkpoint.done_trees.contains(name) {
+			continue;
+		}
+		let from_tree = from_db.open_tree(name)?;
+		let to_tree = to_db.open_tree(name)?;
+
+		let mut last_key = match &checkpoint.current_tree {
+			Some((tree, key)) if tree == name => {
+				Some(hex::decode(key).ok_or_message("Invalid key in checkpoint")?)
+			}
+			_ => None,
+		};
+		let mut copied = 0;
+		loop {
+			let start = match &last_key {
+				Some(k) => Bound::Excluded(k.clone()),
+				None => Bound::Unbounded,
+			};
+			let batch = from_tree
+				.range::<Vec<u8>, _>((start, Bound::Unbounded))?
+				.take(DB_MIGRATION_BATCH_SIZE)
+				.collect::<Result<Vec<_>, _>>()?;
+			let (k, _) = match batch.last() {
+				Some(last) => last,
+				None => break,
+			};
+
+			to_db.transaction(|mut tx| {
+				for (k, v) in batch.iter() {
+					tx.insert(&to_tree, k, v)?;
+				}
+				tx.commit::<_, Error>(())
+			})?;
+			copied += batch.len();
+			println!("{}: copied {} items", name, copied);
+
+			checkpoint.current_tree = Some((name.clone(), hex::encode(k)));
+			save_db_migration_checkpoint(&checkpoint_path, &checkpoint)?;
+			last_key = Some(k.clone());
+		}
+
+		let (from_len, to_len) = (from_tree.len()?, to_tree.len()?);
+		if from_len != to_len {
+			return Err(Error::Message(format!(
+				"Tree {} has {} items in the {} database, but {} items were copied to the {} database",
+				name, from_len, from, to_len, to
+			)));
+		}
+		println!("{}: finished, {} items", name, to_len);
+
+		checkpoint.done_trees.push(name.clone());
+		checkpoint.current_tree = None;
+		save_db_migration_checkpoint(&checkpoint_path, &checkpoint)?;
+	}
+
+	// Check all trees again before switching, in case some were copied
+	// during a previous run and modified since then
+	for name in trees.iter() {
+		let (from_len, to_len) = (
+			from_db.open_tree(name)?.len()?,
+			to_db.open_tree(name)?.len()?,
+		);
+		if from_len != to_len {
+			return Err(Error::Message(format!(
+				"Tree {} has {} items in the {} database, but {} items in the {} database. Remove {} and {} to restart the migration from scratch.",
+				name, from_len, from, to_len, to, to_path.display(), checkpoint_path.display()
+			)));
+		}
+	}
+	drop(to_db);
+	drop(from_db);
+
+	let switched = set_config_db_engine(&config_file, to);
+	std::fs::remove_file(&checkpoint_path)?;
+	match switched {
+		Ok(()) => println!(
+			"Migration finished, {} is now configured to use the {} database at {}.",
+			config_file.display(),
+			to,
+			to_path.display()
+		),
+		Err(e) => println!(
+			"Migration finished, but the configuration could not be updated ({}). Set `db_engine = \"{}\"` in {} before starting the server.",
+			e,
+			to,
+			config_file.display()
+		),
+	}
+	println!(
+		"The {} database at {} can be removed once the server works with the new database.",
+		from,
+		from_path.display()
+	);
+
+	Ok(())
+}
+
+fn save_db_migration_checkpoint(
+	path: &Path,
+	checkpoint: &DbMigrationCheckpoint,
+) -> Result<(), Error> {
+	let mut tmp_path = path.to_path_buf().into_os_string();
+	tmp_path.push(".tmp");
+	std::fs::write(&tmp_path, serde_json::to_vec(checkpoint)?)?;
+	std::fs::rename(&tmp_path, path)?;
+	Ok(())
+}
+
+/// Set the value of db_engine in the configuration file,
+/// which is replaced atomically by a modified copy
+fn set_config_db_engine(config_file: &Path, engine: &str) -> Result<(), Error> {
+	let config = std::fs::read_to_string(config_file)?;
+	let line = format!("db_engine = \"{}\"", engine);
+
+	let mut found = false;
+	let mut lines = config
+		.lines()
+		.map(|l| {
+			let key = l.trim_start().split('=').next().unwrap_or_default().trim();
+			if key == "db_engine" && !found {
+				found = true;
+				line.clone()
+			} else {
+				l.to_string()
+			}
+		})
+		.collect::<Vec<_>>();
+	if !found {
+		// Top-level keys must be set before the first table of the file
+		lines.insert(0, line);
+	}
+
+	let mut tmp_path = config_file.to_path_buf().into_os_string();
+	tmp_path.push(".tmp");
+	std::fs::write(&tmp_path, lines.join("\n") + "\n")?;
+	if let Ok(meta) = std::fs::metadata(config_file) {
+		std::fs::set_permissions(&tmp_path, meta.permissions())?;
+	}
+	std::fs::rename(&tmp_path, config_file)?;
+	Ok(())
+}
+
+// ---- Logical dump of the metadata database ----
+
+/// Version of the format of the files written by `garage db dump-tables`.
+/// It must be increased when the encoding of the records changes, and
+/// `offline_import_tables` must then convert records of previous versions.
+const DUMP_FORMAT_VERSION: u64 = 1;
+
+/// First line of a dump file
+#[derive(Serialize, Deserialize)]
+struct DumpHeader {
+	format: String,
+	version: u64,
+	garage_version: String,
+	db_engine: String,
+}
+
+/// A line of a dump file, for one entry of a tree of the database
+#[derive(Serialize, Deserialize)]
+struct DumpRecord {
+	tree: String,
+	/// Table to which the tree belongs, if it stores the entries of a table
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	table: Option<String>,
+	/// Key and value of the entry, hex-encoded
+	key: String,
+	value: String,
+	/// Value of the entry decoded as a table entry, for inspection only:
+	/// imports only use the raw value
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	decoded: Option<serde_json::Value>,
+}
+
+type EntryDecoder = fn(&[u8]) -> Option<serde_json::Value>;
+
+fn decode_entry<F: TableSchema>(bytes: &[u8]) -> Option<serde_json::Value> {
+	let entry = F::E::decode(bytes)?;
+	serde_json::to_value(&entry).ok()
+}
+
+/// Name and entry decoder of each table, for the trees `<table>:table`
+/// in which the entries of the tables are stored
+fn table_decoders() -> Vec<(&'static str, EntryDecoder)> {
+	vec![
+		(BucketTable::TABLE_NAME, decode_entry::<BucketTable>),
+		(
+			BucketAliasTable::TABLE_NAME,
+			decode_entry::<BucketAliasTable>,
+		),
+		(KeyTable::TABLE_NAME, decode_entry::<KeyTable>),
+		(ObjectTable::TABLE_NAME, decode_entry::<ObjectTable>),
+		(VersionTable::TABLE_NAME, decode_entry::<VersionTable>),
+		(BlockRefTable::TABLE_NAME, decode_entry::<BlockRefTable>),
+		(
+			CounterTable::<Object>::TABLE_NAME,
+			decode_entry::<CounterTable<Object>>,
+		),
+		(
+			ReplicationStateTable::TABLE_NAME,
+			decode_entry::<ReplicationStateTable>,
+		),
+		#[cfg(feature = "k2v")]
+		(K2VItemTable::TABLE_NAME, decode_entry::<K2VItemTable>),
+		#[cfg(feature = "k2v")]
+		(
+			CounterTable::<K2VItem>::TABLE_NAME,
+			decode_entry::<CounterTable<K2VItem>>,
+		),
+	]
+}
+
+fn open_existing_db(config: &Config) -> Result<db::Db, Error> {
+	let db_path = db_path(config);
+	if !db_path.exists() {
+		return Err(Error::Message(format!(
+			"No metadata database found at {}",
+			db_path.display()
+		)));
+	}
+	open_db(config, &db_path)
+}
+
+pub fn offline_dump_tables(
+	config_file: PathBuf,
+	secrets: Secrets,
+	opt: DbDumpTablesOpt,
+) -> Result<(), Error> {
+	info!("Loading configuration...");
+	let config = fill_secrets(read_config(config_file)?, secrets);
+
+	let db = open_existing_db(&config)?;
+	let decoders = table_decoders();
+
+	let file = std::fs::File::create(&opt.output)?;
+	let mut out = zstd::stream::Encoder::new(BufWriter::new(file), 0)?;
+	let header = DumpHeader {
+		format: "garage-dump-tables".into(),
+		version: DUMP_FORMAT_VERSION,
+		garage_version: garage_version().to_string(),
+		db_engine: config.db_engine.clone(),
+	};
+	serde_json::to_writer(&mut out, &header)?;
+	out.write_all(b"\n")?;
+
+	let mut trees = db.list_trees()?;
+	trees.sort();
+	let mut total = 0;
+	for name in trees.iter() {
+		let decoder = name
+			.strip_suffix(":table")
+			.and_then(|t| decoders.iter().find(|(table, _)| *table == t));
+
+		let tree = db.open_tree(name)?;
+		let mut count = 0;
+		for item in tree.iter()? {
+			let (k, v) = item?;
+			let record = DumpRecord {
+				tree: name.clone(),
+				table: decoder.map(|(table, _)| table.to_string()),
+				key: hex::encode(&k),
+				value: hex::encode(&v),
+				decoded: decoder.and_then(|(_, decode)| decode(&v)),
+			};
+			serde_json::to_writer(&mut out, &record)?;
+			out.write_all(b"\n")?;
+			count += 1;
+		}
+		println!("{}: {} items", name, count);
+		total += count;
+	}
+
+	out.finish()?.flush()?;
+	println!(
+		"Dumped {} items from {} trees to {}",
+		total,
+		trees.len(),
+		opt.output.display()
+	);
+	Ok(())
+}
+
+pub fn offline_import_tables(
+	config_file: PathBuf,
+	secrets: Secrets,
+	opt: DbImportTablesOpt,
+) -> Result<(), Error> {
+	if !opt.yes {
+		return Err(Error::Message(
+			"Please add the --yes flag to launch the import. The Garage server must be stopped while it is running.".into(),
+		));
+	}
+
+	info!("Loading configuration...");
+	let config = fill_secrets(read_config(config_file)?, secrets);
+
+	let db_path = db_path(&config);
+	if db_path.exists() {
+		return Err(Error::Message(format!(
+			"A database already exists at {}, tables can only be imported into a new database",
+			db_path.display()
+		)));
+	}
+
+	let file = std::fs::File::open(&opt.input)?;
+	let mut lines = BufReader::new(zstd::stream::Decoder::new(file)?).lines();
+
+	let header: DumpHeader = match lines.next() {
+		Some(line) => serde_json::from_str(&line?)?,
+		None => return Err(Error::Message("The dump file is empty".into())),
+	};
+	if header.format != "garage-dump-tables" {
+		return Err(Error::Message(format!(
+			"{} was not written by garage db dump-tables",
+			opt.input.display()
+		)));
+	}
+	match header.version {
+		DUMP_FORMAT_VERSION => (),
+		v => {
+			return Err(Error::Message(format!(
+				"Unsupported dump format version {} (this version of Garage supports version {})",
+				v, DUMP_FORMAT_VERSION
+			)))
+		}
+	}
+	println!(
+		"Importing tables dumped by Garage {} from a {} database",
+		header.garage_version, header.db_engine
+	);
+
+	let db = open_db(&config, &db_path)?;
+	let mut trees = HashMap::new();
+	let mut batch = vec![];
+	let mut total = 0;
+	for line in lines {
+		let record: DumpRecord = serde_json::from_str(&line?)?;
+		if !trees.contains_key(&record.tree) {
+			let tree = db.open_tree(&record.tree)?;
+			trees.insert(record.tree.clone(), tree);
+		}
+		batch.push((
+			record.tree,
+			hex::decode(&record.key).ok_or_message("Invalid key in dump file")?,
+			hex::decode(&record.value).ok_or_message("Invalid value in dump file")?,
+		));
+		if batch.len() >= DB_MIGRATION_BATCH_SIZE {
+			total += import_batch(&db, &trees, &mut batch)?;
+			println!("Imported {} items", total);
+		}
+	}
+	total += import_batch(&db, &trees, &mut batch)?;
+
+	for (name, tree) in trees.iter() {
+		println!("{}: {} items", name, tree.len()?);
+	}
+	println!(
+		"Imported {} items into the {} database at {}",
+		total,
+		config.db_engine,
+		db_path.display()
+	);
+	Ok(())
+}
+
+fn import_batch(
+	db: &db::Db,
+	trees: &HashMap<String, db::Tree>,
+	batch: &mut Vec<(String, Vec<u8>, Vec<u8>)>,
+) -> Result<usize, Error> {
+	db.transaction(|mut tx| {
+		for (tree, k, v) in batch.iter() {
+			tx.insert(&trees[tree], k, v)?;
+		}
+		tx.commit::<_, Error>(())
+	})?;
+	let len = batch.len();
+	batch.clear();
+	Ok(len)
+}
+
+pub(crate) fn disk_usage(path: &Path) -> Result<u64, Error> {
+	let meta = std::fs::metadata(path)?;
+	if meta.is_dir() {
+		let mut total = 0;
+		for ent in std::fs::read_dir(path)? {
+			total += disk_usage(&ent?.path())?;
+		}
+		Ok(total)
+	} else {
+		Ok(meta.len())
+	}
+}
+
+fn remove_path(path: &Path) -> Result<(), Error> {
+	match std::fs::metadata(path) {
+		Ok(m) if m.is_dir() => std::fs::remove_dir_all(path)?,
+		Ok(_) => std::fs::remove_file(path)?,
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+		Err(e) => return Err(e.into()),
+	}
+	Ok(())
+}
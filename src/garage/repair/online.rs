@@ -1,19 +1,26 @@
+use std::collections::BTreeSet;
 use std::sync::Arc;
 use std::time::Duration;
 
 use async_trait::async_trait;
+use md5::{Digest, Md5};
 use tokio::sync::watch;
 
+use garage_api::s3::put::CHECKSUM_ALGORITHM_HEADER;
 use garage_block::repair::ScrubWorkerCommand;
 use garage_model::garage::Garage;
 use garage_model::s3::block_ref_table::*;
 use garage_model::s3::object_table::*;
 use garage_model::s3::version_table::*;
+use garage_table::replication::TableReplication;
 use garage_table::*;
 use garage_util::background::*;
+use garage_util::data::*;
 use garage_util::error::Error;
 use garage_util::migrate::Migrate;
+use garage_util::time::now_msec;
 
+use crate::repair::gateway::*;
 use crate::*;
 
 pub async fn launch_online_repair(
@@ -28,22 +35,182 @@ pub async fn launch_online_repair(
 			garage.object_table.syncer.add_full_sync()?;
 			garage.version_table.syncer.add_full_sync()?;
 			garage.block_ref_table.syncer.add_full_sync()?;
+			garage.replication_state_table.syncer.add_full_sync()?;
 			garage.key_table.syncer.add_full_sync()?;
 		}
-		RepairWhat::Versions => {
+		RepairWhat::Versions {
+			check_part_ordering: true,
+			abort_invalid,
+			..
+		} => {
+			info!("Checking the parts of multipart versions");
+			bg.spawn_worker(CheckPartOrderingWorker::new(garage.clone(), abort_invalid));
+		}
+		RepairWhat::Versions {
+			check_complete_flag: true,
+			min_age,
+			promote,
+			..
+		} => {
+			let min_age = match min_age {
+				Some(s) => parse_duration::parse::parse(&s).map_err(|_| {
+					Error::Message("Invalid duration passed for --min-age parameter".into())
+				})?,
+				None => Duration::from_secs(24 * 3600),
+			};
+			info!("Checking multipart versions stuck in uploading state");
+			bg.spawn_worker(CheckCompleteFlagWorker::new(
+				garage.clone(),
+				min_age,
+				promote,
+			));
+		}
+		RepairWhat::Versions { .. } => {
 			info!("Repairing the versions table");
 			bg.spawn_worker(RepairVersionsWorker::new(garage.clone()));
 		}
-		RepairWhat::BlockRefs => {
+		RepairWhat::BlockRefs {
+			remove_stale_tombstones: true,
+			older_than,
+			..
+		} => {
+			let older_than = match older_than {
+				Some(s) => parse_duration::parse::parse(&s).map_err(|_| {
+					Error::Message("Invalid duration passed for --older-than parameter".into())
+				})?,
+				None => Duration::from_secs(7 * 24 * 3600),
+			};
+			info!("Removing stale tombstones from the block refs table");
+			bg.spawn_worker(RemoveStaleTombstonesWorker::new(garage.clone(), older_than));
+		}
+		RepairWhat::BlockRefs {
+			fix_broken_links: false,
+			..
+		} => {
 			info!("Repairing the block refs table");
 			bg.spawn_worker(RepairBlockrefsWorker::new(garage.clone()));
 		}
-		RepairWhat::Blocks => {
+		RepairWhat::BlockRefs {
+			fix_broken_links: true,
+			..
+		} => {
+			info!("Fixing block refs with truncated block hashes");
+			bg.spawn_worker(FixBrokenBlockrefsWorker::new(garage.clone()));
+		}
+		RepairWhat::Objects {
+			check_version_refs: true,
+			fix,
+			..
+		} => {
+			info!("Checking that object versions exist in the version table");
+			bg.spawn_worker(CheckVersionRefsWorker::new(garage.clone(), fix));
+		}
+		RepairWhat::Objects {
+			reconcile_delete_markers: false,
+			..
+		} => {
+			return Err(Error::Message(
+				"No repair operation specified for objects, use --reconcile-delete-markers or --check-version-refs".into(),
+			));
+		}
+		RepairWhat::Objects {
+			reconcile_delete_markers: true,
+			..
+		} => {
+			if !garage.config.enable_delete_marker_gc {
+				return Err(Error::Message(
+					"Removing delete markers is disabled, set enable_delete_marker_gc = true in the configuration file".into(),
+				));
+			}
+			info!("Reconciling orphaned delete markers");
+			bg.spawn_worker(ReconcileDeleteMarkersWorker::new(garage.clone()));
+		}
+		RepairWhat::ObjectIntegrity { auto_repair } => {
+			info!("Checking objects against their versions, block refs and blocks");
+			bg.spawn_worker(ObjectIntegrityWorker::new(garage.clone(), auto_repair));
+		}
+		RepairWhat::SyncVersionSizes => {
+			info!("Checking the sizes of objects against their versions");
+			bg.spawn_worker(SyncVersionSizesWorker::new(garage.clone()));
+		}
+		RepairWhat::Blocks {
+			fetch_from_gateway: Some(endpoint),
+			bucket,
+			access_key,
+			secret_key,
+			region,
+			max_requests_per_sec,
+		} => {
+			let bucket_name = bucket.ok_or_message("--bucket is required")?;
+			let bucket_id = garage
+				.bucket_helper()
+				.resolve_global_bucket_name(&bucket_name)
+				.await
+				.map_err(|e| Error::Message(e.to_string()))?
+				.ok_or_message("Bucket not found")?;
+			let gateway = S3Gateway::new(
+				endpoint,
+				bucket_name,
+				access_key.ok_or_message("--access-key is required")?,
+				secret_key.ok_or_message("--secret-key is required")?,
+				region,
+			)?;
+			info!(
+				"Fetching missing blocks from S3 gateway {}",
+				gateway.endpoint
+			);
+			bg.spawn_worker(FetchFromGatewayWorker::new(
+				garage.clone(),
+				gateway,
+				bucket_id,
+				max_requests_per_sec,
+			));
+		}
+		RepairWhat::Blocks { .. } => {
 			info!("Repairing the stored blocks");
 			bg.spawn_worker(garage_block::repair::RepairWorker::new(
 				garage.block_manager.clone(),
 			));
 		}
+		RepairWhat::BlockDirectory { fix_permissions } => {
+			info!("Checking permissions of block files");
+			bg.spawn_worker(garage_block::repair::BlockPermissionsWorker::new(
+				garage.block_manager.clone(),
+				fix_permissions,
+			));
+		}
+		RepairWhat::RebuildBlockIndex { scan_dir } => {
+			info!("Rebuilding block index from {}", scan_dir.display());
+			bg.spawn_worker(garage_block::repair::RebuildBlockIndexWorker::new(
+				garage.block_manager.clone(),
+				scan_dir,
+			));
+		}
+		RepairWhat::CheckBlockHashes {
+			rehash_all,
+			threads,
+			report_file,
+		} => {
+			info!("Checking hashes of block files");
+			bg.spawn_worker(garage_block::repair::BlockHashCheckWorker::new(
+				garage.block_manager.clone(),
+				rehash_all,
+				threads,
+				report_file,
+			));
+		}
+		RepairWhat::RekeyBlocks => {
+			info!("Re-keying block files");
+			bg.spawn_worker(garage_block::repair::BlockRekeyWorker::new(
+				garage.block_manager.clone(),
+			)?);
+		}
+		RepairWhat::Rebalance => {
+			info!("Rebalancing block files between data directories");
+			bg.spawn_worker(garage_block::repair::BlockRebalanceWorker::new(
+				garage.block_manager.clone(),
+			));
+		}
 		RepairWhat::Scrub { cmd } => {
 			let cmd = match cmd {
 				ScrubCmd::Start => ScrubWorkerCommand::Start,
@@ -146,67 +313,446 @@ impl Worker for RepairVersionsWorker {
 
 // ----
 
-struct RepairBlockrefsWorker {
+struct CheckPartOrderingWorker {
 	garage: Arc<Garage>,
+	abort_invalid: bool,
 	pos: Vec<u8>,
 	counter: usize,
+	invalid: usize,
 }
 
-impl RepairBlockrefsWorker {
-	fn new(garage: Arc<Garage>) -> Self {
+impl CheckPartOrderingWorker {
+	fn new(garage: Arc<Garage>, abort_invalid: bool) -> Self {
 		Self {
 			garage,
+			abort_invalid,
 			pos: vec![],
 			counter: 0,
+			invalid: 0,
 		}
 	}
 }
 
 #[async_trait]
-impl Worker for RepairBlockrefsWorker {
+impl Worker for CheckPartOrderingWorker {
 	fn name(&self) -> String {
-		"Block refs repair worker".into()
+		"Multipart version check worker".into()
 	}
 
 	fn status(&self) -> WorkerStatus {
 		WorkerStatus {
 			progress: Some(self.counter.to_string()),
+			freeform: vec![format!("Invalid multipart versions: {}", self.invalid)],
 			..Default::default()
 		}
 	}
 
 	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
-		let (item_bytes, next_pos) =
-			match self.garage.block_ref_table.data.store.get_gt(&self.pos)? {
-				Some((k, v)) => (v, k),
-				None => {
-					info!("repair_block_ref: finished, done {}", self.counter);
-					return Ok(WorkerState::Done);
+		let (item_bytes, next_pos) = match self.garage.version_table.data.store.get_gt(&self.pos)? {
+			Some((k, v)) => (v, k),
+			None => {
+				info!(
+					"check_part_ordering: finished, done {}, found {} invalid multipart versions",
+					self.counter, self.invalid
+				);
+				return Ok(WorkerState::Done);
+			}
+		};
+
+		let version = Version::decode(&item_bytes).ok_or_message("Cannot decode Version")?;
+		if !version.deleted.get() && !version.parts_etags.is_empty() {
+			let object = self
+				.garage
+				.object_table
+				.get(&version.bucket_id, &version.key)
+				.await?;
+			let object_version = object.as_ref().and_then(|o| {
+				o.versions()
+					.iter()
+					.find(|x| x.uuid == version.uuid && x.is_complete())
+			});
+			if let Some(object_version) = object_version {
+				let violations = check_part_ordering(&version);
+				if !violations.is_empty() {
+					self.invalid += 1;
+					warn!(
+						"Invalid multipart version {:?} (bucket {:?}, key {}): {}",
+						version.uuid,
+						version.bucket_id,
+						version.key,
+						violations.join(", ")
+					);
+					if self.abort_invalid {
+						info!("Marking multipart version {:?} as aborted", version.uuid);
+						let mut aborted_version = object_version.clone();
+						aborted_version.state = ObjectVersionState::Aborted;
+						self.garage
+							.object_table
+							.insert(&Object::new(
+								version.bucket_id,
+								version.key.clone(),
+								vec![aborted_version],
+							))
+							.await?;
+					}
 				}
-			};
+			}
+		}
 
-		let block_ref = BlockRef::decode(&item_bytes).ok_or_message("Cannot decode BlockRef")?;
-		if !block_ref.deleted.get() {
-			let version = self
+		self.counter += 1;
+		self.pos = next_pos;
+
+		Ok(WorkerState::Busy)
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		unreachable!()
+	}
+}
+
+/// Check the parts of a completed multipart version against the rules enforced
+/// by CompleteMultipartUpload: part numbers must be consecutive starting at 1,
+/// and every block must belong to one of the parts. Parts and blocks are stored
+/// in sorted maps, so their order does not need to be checked, and parts smaller
+/// than the S3 minimum size have always been accepted by Garage.
+fn check_part_ordering(version: &Version) -> Vec<String> {
+	let mut violations = vec![];
+
+	let parts = version.parts_etags.items();
+	if parts
+		.iter()
+		.enumerate()
+		.any(|(i, (pn, _))| *pn != i as u64 + 1)
+	{
+		violations.push("part numbers are not consecutive starting at 1".to_string());
+	}
+
+	let block_parts = version
+		.blocks
+		.items()
+		.iter()
+		.map(|(bk, _)| bk.part_number)
+		.collect::<BTreeSet<_>>();
+	if !parts.iter().map(|(pn, _)| *pn).eq(block_parts) {
+		violations.push("part numbers of the blocks do not match the list of parts".to_string());
+	}
+
+	violations
+}
+
+// ----
+
+struct CheckCompleteFlagWorker {
+	garage: Arc<Garage>,
+	min_age: Duration,
+	promote: bool,
+	pos: Vec<u8>,
+	counter: usize,
+	complete: usize,
+	superseded: usize,
+	promoted: usize,
+	abandoned: usize,
+}
+
+impl CheckCompleteFlagWorker {
+	fn new(garage: Arc<Garage>, min_age: Duration, promote: bool) -> Self {
+		Self {
+			garage,
+			min_age,
+			promote,
+			pos: vec![],
+			counter: 0,
+			complete: 0,
+			superseded: 0,
+			promoted: 0,
+			abandoned: 0,
+		}
+	}
+
+	/// Check that the parts of a multipart version are numbered consecutively,
+	/// that every part has blocks, and that a block ref exists for every block.
+	/// Block refs are read with a quorum, so that a block ref that is missing
+	/// on this node only is not a reason to consider the version incomplete,
+	/// and a block ref that is present on this node only is not enough.
+	async fn all_parts_received(&self, version: &Version) -> Result<bool, Error> {
+		let parts = version.parts_etags.items();
+		if parts
+			.iter()
+			.enumerate()
+			.any(|(i, (pn, _))| *pn != i as u64 + 1)
+		{
+			return Ok(false);
+		}
+
+		let blocks = version.blocks.items();
+		let block_parts = blocks
+			.iter()
+			.map(|(bk, _)| bk.part_number)
+			.collect::<BTreeSet<_>>();
+		if !parts.iter().map(|(pn, _)| *pn).eq(block_parts) {
+			return Ok(false);
+		}
+
+		for (_, vb) in blocks.iter() {
+			let block_ref = self
 				.garage
-				.version_table
-				.get(&block_ref.version, &EmptyKey)
+				.block_ref_table
+				.get(&vb.hash, &version.uuid)
 				.await?;
-			// The version might not exist if it has been GC'ed
-			let ref_exists = version.map(|v| !v.deleted.get()).unwrap_or(false);
-			if !ref_exists {
+			if !block_ref.map(|br| !br.deleted.get()).unwrap_or(false) {
+				return Ok(false);
+			}
+		}
+		Ok(true)
+	}
+
+	/// Mark a multipart version whose parts have all been received as complete
+	async fn promote(
+		&self,
+		version: &Version,
+		object_version: &ObjectVersion,
+	) -> Result<(), Error> {
+		let mut headers = match &object_version.state {
+			ObjectVersionState::Uploading(headers) => headers.clone(),
+			_ => unreachable!(),
+		};
+		headers.other.remove(CHECKSUM_ALGORITHM_HEADER);
+
+		let mut etag_md5_hasher = Md5::new();
+		for (_, etag) in version.parts_etags.items().iter() {
+			etag_md5_hasher.update(etag.as_bytes());
+		}
+		let etag = format!(
+			"{}-{}",
+			hex::encode(etag_md5_hasher.finalize()),
+			version.parts_etags.items().len()
+		);
+		let total_size = version.blocks.items().iter().map(|x| x.1.size).sum();
+
+		info!(
+			"Marking multipart version {:?} (bucket {:?}, key {}) as complete",
+			version.uuid, version.bucket_id, version.key
+		);
+		let mut complete_version = object_version.clone();
+		complete_version.state = ObjectVersionState::Complete(ObjectVersionData::FirstBlock(
+			ObjectVersionMeta {
+				headers,
+				size: total_size,
+				etag,
+				checksum: None,
+			},
+			version.blocks.items()[0].1.hash,
+		));
+		self.garage
+			.object_table
+			.insert(&Object::new(
+				version.bucket_id,
+				version.key.clone(),
+				vec![complete_version],
+			))
+			.await?;
+		Ok(())
+	}
+}
+
+#[async_trait]
+impl Worker for CheckCompleteFlagWorker {
+	fn name(&self) -> String {
+		"Multipart complete flag check worker".into()
+	}
+
+	fn status(&self) -> WorkerStatus {
+		WorkerStatus {
+			progress: Some(self.counter.to_string()),
+			freeform: vec![
+				format!("Versions with all their parts: {}", self.complete),
+				format!(
+					"Versions superseded by a newer version: {}",
+					self.superseded
+				),
+				format!("Versions promoted to complete: {}", self.promoted),
+				format!("Versions abandoned: {}", self.abandoned),
+			],
+			..Default::default()
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		let (item_bytes, next_pos) = match self.garage.version_table.data.store.get_gt(&self.pos)? {
+			Some((k, v)) => (v, k),
+			None => {
 				info!(
-					"Repair block ref: marking block_ref as deleted: {:?}",
-					block_ref
+					"check_complete_flag: finished, done {}, {} versions with all their parts ({} superseded), {} versions promoted to complete, {} versions abandoned",
+					self.counter, self.complete, self.superseded, self.promoted, self.abandoned
 				);
-				self.garage
-					.block_ref_table
-					.insert(&BlockRef {
-						block: block_ref.block,
-						version: block_ref.version,
-						deleted: true.into(),
-					})
-					.await?;
+				return Ok(WorkerState::Done);
+			}
+		};
+
+		let version = Version::decode(&item_bytes).ok_or_message("Cannot decode Version")?;
+		if !version.deleted.get() && !version.parts_etags.is_empty() {
+			// This is a quorum read: if the CompleteMultipartUpload call reached
+			// some of the nodes, the version is already seen as complete here
+			// (and the other nodes are fixed by read repair)
+			let object = self
+				.garage
+				.object_table
+				.get(&version.bucket_id, &version.key)
+				.await?;
+			let min_timestamp = now_msec().saturating_sub(self.min_age.as_millis() as u64);
+			let versions = object.as_ref().map(|o| o.versions()).unwrap_or(&[]);
+			let position = versions.iter().position(|x| {
+				x.uuid == version.uuid && x.is_uploading() && x.timestamp <= min_timestamp
+			});
+
+			if let Some(i) = position {
+				let object_version = &versions[i];
+				// The local copy of the version might be stale, and it is only
+				// trusted if a quorum of nodes agrees on it
+				let quorum_version = self
+					.garage
+					.version_table
+					.get(&version.uuid, &EmptyKey)
+					.await?
+					.filter(|v| !v.deleted.get());
+				let received = match &quorum_version {
+					Some(v) => self.all_parts_received(v).await?,
+					None => false,
+				};
+
+				if !received {
+					info!(
+						"Multipart version {:?} (bucket {:?}, key {}) is missing parts, considering it abandoned",
+						version.uuid, version.bucket_id, version.key
+					);
+					self.abandoned += 1;
+				} else {
+					let version = quorum_version.unwrap();
+					self.complete += 1;
+					// A version that is completed now would take the place of the newer
+					// versions of the object, and might cause their removal
+					if versions[i + 1..].iter().any(|v| v.is_complete()) {
+						info!(
+							"Multipart version {:?} (bucket {:?}, key {}) has all its parts, but a newer version of the object exists: not marking it as complete",
+							version.uuid, version.bucket_id, version.key
+						);
+						self.superseded += 1;
+					} else if self.promote {
+						self.promote(&version, object_version).await?;
+						self.promoted += 1;
+					} else {
+						info!(
+							"Multipart version {:?} (bucket {:?}, key {}) has all its parts, run with --promote to mark it as complete",
+							version.uuid, version.bucket_id, version.key
+						);
+					}
+				}
+			}
+		}
+
+		self.counter += 1;
+		self.pos = next_pos;
+
+		Ok(WorkerState::Busy)
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		unreachable!()
+	}
+}
+
+// ----
+
+struct ReconcileDeleteMarkersWorker {
+	garage: Arc<Garage>,
+	pos: Vec<u8>,
+	counter: usize,
+	removed: usize,
+}
+
+impl ReconcileDeleteMarkersWorker {
+	fn new(garage: Arc<Garage>) -> Self {
+		Self {
+			garage,
+			pos: vec![],
+			counter: 0,
+			removed: 0,
+		}
+	}
+}
+
+/// An object whose versions are only delete markers and aborted uploads
+/// does not hide any data anymore (single delete markers are already
+/// tombstones and are collected by the table GC)
+fn is_orphaned_delete_marker(object: &Object) -> bool {
+	let versions = object.versions();
+	!object.is_tombstone()
+		&& versions
+			.iter()
+			.any(|v| v.state == ObjectVersionState::Complete(ObjectVersionData::DeleteMarker))
+		&& versions.iter().all(|v| {
+			matches!(
+				v.state,
+				ObjectVersionState::Aborted
+					| ObjectVersionState::Complete(ObjectVersionData::DeleteMarker)
+			)
+		})
+}
+
+#[async_trait]
+impl Worker for ReconcileDeleteMarkersWorker {
+	fn name(&self) -> String {
+		"Delete marker reconciliation worker".into()
+	}
+
+	fn status(&self) -> WorkerStatus {
+		WorkerStatus {
+			progress: Some(self.counter.to_string()),
+			freeform: vec![format!(
+				"Delete markers queued for removal: {}",
+				self.removed
+			)],
+			..Default::default()
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		let (item_bytes, next_pos) = match self.garage.object_table.data.store.get_gt(&self.pos)? {
+			Some((k, v)) => (v, k),
+			None => {
+				info!(
+					"reconcile_delete_markers: finished, done {}, {} delete markers queued for removal",
+					self.counter, self.removed
+				);
+				return Ok(WorkerState::Done);
+			}
+		};
+
+		let object = Object::decode(&item_bytes).ok_or_message("Cannot decode Object")?;
+		if is_orphaned_delete_marker(&object) {
+			// Confirm with a quorum read that no other version of the object
+			// exists, and only remove the marker if this node already holds
+			// the merged value. The table GC then checks that all replicas
+			// hold this same value before deleting it anywhere.
+			let merged = self
+				.garage
+				.object_table
+				.get(&object.bucket_id, &object.key)
+				.await?;
+			if let Some(merged) = merged {
+				if merged == object
+					&& self
+						.garage
+						.object_table
+						.data
+						.queue_gc(&next_pos, &item_bytes)?
+				{
+					info!(
+						"Removing orphaned delete marker for object {:?}/{}",
+						object.bucket_id, object.key
+					);
+					self.removed += 1;
+				}
 			}
 		}
 
@@ -220,3 +766,926 @@ impl Worker for RepairBlockrefsWorker {
 		unreachable!()
 	}
 }
+
+// ----
+
+struct CheckVersionRefsWorker {
+	garage: Arc<Garage>,
+	fix: bool,
+	pos: Vec<u8>,
+	counter: usize,
+	missing: usize,
+	deleted: usize,
+}
+
+impl CheckVersionRefsWorker {
+	fn new(garage: Arc<Garage>, fix: bool) -> Self {
+		Self {
+			garage,
+			fix,
+			pos: vec![],
+			counter: 0,
+			missing: 0,
+			deleted: 0,
+		}
+	}
+}
+
+#[async_trait]
+impl Worker for CheckVersionRefsWorker {
+	fn name(&self) -> String {
+		"Object version refs check worker".into()
+	}
+
+	fn status(&self) -> WorkerStatus {
+		let mut freeform = vec![format!("Objects with missing version: {}", self.missing)];
+		if self.fix {
+			freeform.push(format!("Versions aborted: {}", self.deleted));
+		}
+		WorkerStatus {
+			progress: Some(self.counter.to_string()),
+			freeform,
+			..Default::default()
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		let (item_bytes, next_pos) = match self.garage.object_table.data.store.get_gt(&self.pos)? {
+			Some((k, v)) => (v, k),
+			None => {
+				info!(
+					"check_version_refs: finished, done {}, {} objects with missing version, {} versions aborted",
+					self.counter, self.missing, self.deleted
+				);
+				return Ok(WorkerState::Done);
+			}
+		};
+		self.counter += 1;
+		self.pos = next_pos.clone();
+
+		// Only the first node storing the object checks it,
+		// so that it is reported only once
+		let pk_hash = Hash::try_from(&next_pos[..32]).unwrap();
+		let nodes = self
+			.garage
+			.object_table
+			.data
+			.replication
+			.write_nodes(&pk_hash);
+		if nodes.first() != Some(&self.garage.system.id) {
+			return Ok(WorkerState::Busy);
+		}
+
+		// The local copy of the object might be stale: it is only used to find
+		// the objects to check, which are then read again from a quorum of nodes
+		let object = Object::decode(&item_bytes).ok_or_message("Cannot decode Object")?;
+		if current_version_with_blocks(&object).is_none() {
+			return Ok(WorkerState::Busy);
+		}
+		let object = match self
+			.garage
+			.object_table
+			.get(&object.bucket_id, &object.key)
+			.await?
+		{
+			Some(o) => o,
+			None => return Ok(WorkerState::Busy),
+		};
+		let current = match current_version_with_blocks(&object) {
+			Some(v) => v,
+			None => return Ok(WorkerState::Busy),
+		};
+
+		// A version that is marked as deleted is not missing: the deletion
+		// of the object has not yet been seen by all the nodes
+		if self
+			.garage
+			.version_table
+			.get(&current.uuid, &EmptyKey)
+			.await?
+			.is_some()
+		{
+			return Ok(WorkerState::Busy);
+		}
+
+		// Confirm with all nodes that the version is missing before reporting it,
+		// as a quorum read could miss it if it was not written everywhere
+		if self
+			.garage
+			.version_table
+			.get_from_all_nodes(&current.uuid, &EmptyKey)
+			.await?
+			.is_some()
+		{
+			return Ok(WorkerState::Busy);
+		}
+
+		warn!(
+			"Object {:?}/{} references version {:?} which does not exist",
+			object.bucket_id, object.key, current.uuid
+		);
+		self.missing += 1;
+
+		if self.fix {
+			// Abort only the broken version: the previous versions of the object,
+			// if any, are kept, and the newer ones are not affected
+			let mut aborted = current.clone();
+			aborted.state = ObjectVersionState::Aborted;
+			self.garage
+				.object_table
+				.insert(&Object::new(
+					object.bucket_id,
+					object.key.clone(),
+					vec![aborted],
+				))
+				.await?;
+			info!(
+				"Aborted version {:?} of object {:?}/{}",
+				current.uuid, object.bucket_id, object.key
+			);
+			self.deleted += 1;
+		}
+
+		Ok(WorkerState::Busy)
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		unreachable!()
+	}
+}
+
+/// The current version of an object, if it has data stored in blocks:
+/// inline objects and delete markers have no entry in the version table
+fn current_version_with_blocks(object: &Object) -> Option<&ObjectVersion> {
+	object
+		.versions()
+		.iter()
+		.rev()
+		.find(|v| v.is_complete())
+		.filter(|v| {
+			matches!(
+				v.state,
+				ObjectVersionState::Complete(ObjectVersionData::FirstBlock(_, _))
+			)
+		})
+}
+
+// ----
+
+struct ObjectIntegrityWorker {
+	garage: Arc<Garage>,
+	auto_repair: bool,
+	pos: Vec<u8>,
+	counter: usize,
+	blocks: usize,
+	missing_versions: usize,
+	missing_block_refs: usize,
+	deleted_block_refs: usize,
+	missing_replicas: usize,
+	bad_replicas: usize,
+	lost_blocks: usize,
+	repaired_block_refs: usize,
+	repaired_blocks: usize,
+}
+
+impl ObjectIntegrityWorker {
+	fn new(garage: Arc<Garage>, auto_repair: bool) -> Self {
+		Self {
+			garage,
+			auto_repair,
+			pos: vec![],
+			counter: 0,
+			blocks: 0,
+			missing_versions: 0,
+			missing_block_refs: 0,
+			deleted_block_refs: 0,
+			missing_replicas: 0,
+			bad_replicas: 0,
+			lost_blocks: 0,
+			repaired_block_refs: 0,
+			repaired_blocks: 0,
+		}
+	}
+
+	async fn check_version(&mut self, object: &Object, uuid: Uuid) -> Result<(), Error> {
+		let version = match self.garage.version_table.get(&uuid, &EmptyKey).await? {
+			Some(v) => Some(v),
+			None => {
+				self.garage
+					.version_table
+					.get_from_all_nodes(&uuid, &EmptyKey)
+					.await?
+			}
+		};
+		let version = match version {
+			Some(v) if !v.deleted.get() => v,
+			_ => {
+				warn!(
+					"object-integrity: object {:?}/{} references version {:?} which does not exist",
+					object.bucket_id, object.key, uuid
+				);
+				self.missing_versions += 1;
+				return Ok(());
+			}
+		};
+
+		for (_, vb) in version.blocks.items().iter() {
+			self.blocks += 1;
+			self.check_block_ref(object, uuid, vb.hash).await?;
+			self.check_block(object, vb.hash).await?;
+		}
+		Ok(())
+	}
+
+	async fn check_block_ref(
+		&mut self,
+		object: &Object,
+		version: Uuid,
+		hash: Hash,
+	) -> Result<(), Error> {
+		let block_ref = match self.garage.block_ref_table.get(&hash, &version).await? {
+			Some(br) => Some(br),
+			None => {
+				self.garage
+					.block_ref_table
+					.get_from_all_nodes(&hash, &version)
+					.await?
+			}
+		};
+		match block_ref {
+			Some(br) if !br.deleted.get() => (),
+			Some(_) => {
+				// A deleted block ref cannot be revived, as deletion always wins
+				// when merging, so this can only be reported
+				warn!(
+					"object-integrity: object {:?}/{}, version {:?}: block ref for block {:?} is marked as deleted",
+					object.bucket_id, object.key, version, hash
+				);
+				self.deleted_block_refs += 1;
+			}
+			None => {
+				warn!(
+					"object-integrity: object {:?}/{}, version {:?}: block ref for block {:?} is missing",
+					object.bucket_id, object.key, version, hash
+				);
+				self.missing_block_refs += 1;
+				if self.auto_repair {
+					let block_ref = BlockRef {
+						block: hash,
+						version,
+						deleted: false.into(),
+					};
+					self.garage.block_ref_table.insert(&block_ref).await?;
+					self.repaired_block_refs += 1;
+				}
+			}
+		}
+		Ok(())
+	}
+
+	async fn check_block(&mut self, object: &Object, hash: Hash) -> Result<(), Error> {
+		let block_manager = &self.garage.block_manager;
+		let storage_nodes = block_manager.replication.write_nodes(&hash);
+		let locations = block_manager.get_block_locations(&hash).await?;
+
+		// Read the block from every node that has it, which checks its hash
+		let mut good_copy = None;
+		let mut bad = 0;
+		for node in locations.iter() {
+			match block_manager.rpc_get_block_from(&hash, *node).await {
+				Ok(data) => {
+					good_copy.get_or_insert(data);
+				}
+				Err(e) => {
+					warn!(
+						"object-integrity: object {:?}/{}: block {:?} could not be read from node {:?}: {}",
+						object.bucket_id, object.key, hash, node, e
+					);
+					bad += 1;
+				}
+			}
+		}
+		let missing = storage_nodes.len().saturating_sub(locations.len());
+		if missing > 0 {
+			warn!(
+				"object-integrity: object {:?}/{}: block {:?} is missing on {} of {} nodes",
+				object.bucket_id,
+				object.key,
+				hash,
+				missing,
+				storage_nodes.len()
+			);
+		}
+		self.missing_replicas += missing;
+		self.bad_replicas += bad;
+
+		match good_copy {
+			None => {
+				error!(
+					"object-integrity: object {:?}/{}: block {:?} has no valid copy on any node",
+					object.bucket_id, object.key, hash
+				);
+				self.lost_blocks += 1;
+			}
+			Some(data) if self.auto_repair && (missing > 0 || bad > 0) => {
+				// Nodes that had a corrupted copy have moved it aside when it was read,
+				// so sending the block again to all storage nodes restores every copy
+				block_manager.rpc_put_block(hash, data).await?;
+				self.repaired_blocks += 1;
+			}
+			Some(_) => (),
+		}
+		Ok(())
+	}
+}
+
+#[async_trait]
+impl Worker for ObjectIntegrityWorker {
+	fn name(&self) -> String {
+		"Object integrity check worker".into()
+	}
+
+	fn status(&self) -> WorkerStatus {
+		let mut freeform = vec![
+			format!("Blocks checked: {}", self.blocks),
+			format!("Missing versions: {}", self.missing_versions),
+			format!("Missing block refs: {}", self.missing_block_refs),
+			format!("Deleted block refs: {}", self.deleted_block_refs),
+			format!("Missing block replicas: {}", self.missing_replicas),
+			format!(
+				"Corrupted or unreadable block replicas: {}",
+				self.bad_replicas
+			),
+			format!("Blocks with no valid copy: {}", self.lost_blocks),
+		];
+		if self.auto_repair {
+			freeform.push(format!(
+				"Block refs recreated: {}",
+				self.repaired_block_refs
+			));
+			freeform.push(format!("Blocks re-replicated: {}", self.repaired_blocks));
+		}
+		WorkerStatus {
+			progress: Some(self.counter.to_string()),
+			freeform,
+			..Default::default()
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		let (item_bytes, next_pos) = match self.garage.object_table.data.store.get_gt(&self.pos)? {
+			Some((k, v)) => (v, k),
+			None => {
+				info!(
+					"object-integrity: finished, {} objects and {} blocks checked; {} missing versions, {} missing and {} deleted block refs, {} missing and {} bad block replicas, {} blocks with no valid copy",
+					self.counter,
+					self.blocks,
+					self.missing_versions,
+					self.missing_block_refs,
+					self.deleted_block_refs,
+					self.missing_replicas,
+					self.bad_replicas,
+					self.lost_blocks
+				);
+				return Ok(WorkerState::Done);
+			}
+		};
+		self.counter += 1;
+		self.pos = next_pos.clone();
+
+		// Only the first node storing the object checks it,
+		// so that it is reported only once
+		let pk_hash = Hash::try_from(&next_pos[..32]).unwrap();
+		let nodes = self
+			.garage
+			.object_table
+			.data
+			.replication
+			.write_nodes(&pk_hash);
+		if nodes.first() != Some(&self.garage.system.id) {
+			return Ok(WorkerState::Busy);
+		}
+
+		// Inline objects and delete markers have no blocks, and versions still
+		// being uploaded may legitimately not have all their blocks yet
+		let object = Object::decode(&item_bytes).ok_or_message("Cannot decode Object")?;
+		for ov in object.versions().iter() {
+			if matches!(
+				ov.state,
+				ObjectVersionState::Complete(ObjectVersionData::FirstBlock(_, _))
+			) {
+				self.check_version(&object, ov.uuid).await?;
+			}
+		}
+
+		Ok(WorkerState::Busy)
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		unreachable!()
+	}
+}
+
+// ----
+
+struct SyncVersionSizesWorker {
+	garage: Arc<Garage>,
+	pos: Vec<u8>,
+	counter: usize,
+	corrected: usize,
+}
+
+impl SyncVersionSizesWorker {
+	fn new(garage: Arc<Garage>) -> Self {
+		Self {
+			garage,
+			pos: vec![],
+			counter: 0,
+			corrected: 0,
+		}
+	}
+
+	/// Object metadata can't be modified in place (the CRDT merge would keep the
+	/// larger value), so the object is written again as a new version,
+	/// referencing the same blocks, with the correct size.
+	/// The timestamp is only increased by 1ms to keep the same modification date.
+	async fn rewrite_version(
+		&self,
+		object: &Object,
+		object_version: &ObjectVersion,
+		meta: &ObjectVersionMeta,
+		first_block: Hash,
+		version: &Version,
+	) -> Result<(), Error> {
+		let new_uuid = gen_uuid();
+
+		let mut new_version = Version::new(new_uuid, object.bucket_id, object.key.clone(), false);
+		for (bk, bv) in version.blocks.items().iter() {
+			new_version.blocks.put(*bk, *bv);
+		}
+		let block_refs = new_version
+			.blocks
+			.items()
+			.iter()
+			.map(|(_, b)| BlockRef {
+				block: b.hash,
+				version: new_uuid,
+				deleted: false.into(),
+			})
+			.collect::<Vec<_>>();
+		futures::try_join!(
+			self.garage.version_table.insert(&new_version),
+			self.garage.block_ref_table.insert_many(&block_refs[..]),
+		)?;
+
+		let new_meta = ObjectVersionMeta {
+			size: version.size(),
+			..meta.clone()
+		};
+		let new_object_version = ObjectVersion {
+			uuid: new_uuid,
+			timestamp: object_version.timestamp + 1,
+			state: ObjectVersionState::Complete(ObjectVersionData::FirstBlock(
+				new_meta,
+				first_block,
+			)),
+			versioned: object_version.versioned,
+			lock: object_version.lock.clone(),
+			tags: object_version.tags.clone(),
+			storage_class: Default::default(),
+			restore: Default::default(),
+		};
+		self.garage
+			.object_table
+			.insert(&Object::new(
+				object.bucket_id,
+				object.key.clone(),
+				vec![new_object_version],
+			))
+			.await?;
+		Ok(())
+	}
+}
+
+#[async_trait]
+impl Worker for SyncVersionSizesWorker {
+	fn name(&self) -> String {
+		"Object size repair worker".into()
+	}
+
+	fn status(&self) -> WorkerStatus {
+		WorkerStatus {
+			progress: Some(self.counter.to_string()),
+			freeform: vec![format!("Objects corrected: {}", self.corrected)],
+			..Default::default()
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		let (item_bytes, next_pos) = match self.garage.object_table.data.store.get_gt(&self.pos)? {
+			Some((k, v)) => (v, k),
+			None => {
+				info!(
+					"sync_version_sizes: finished, done {}, corrected {}",
+					self.counter, self.corrected
+				);
+				return Ok(WorkerState::Done);
+			}
+		};
+		self.counter += 1;
+		self.pos = next_pos.clone();
+
+		// Objects are stored on several nodes, only the first of them
+		// fixes them so that a single new version is written
+		let pk_hash = Hash::try_from(&next_pos[..32]).unwrap();
+		let nodes = self
+			.garage
+			.object_table
+			.data
+			.replication
+			.write_nodes(&pk_hash);
+		if nodes.first() != Some(&self.garage.system.id) {
+			return Ok(WorkerState::Busy);
+		}
+
+		let object = Object::decode(&item_bytes).ok_or_message("Cannot decode Object")?;
+		let current = object.versions().iter().rev().find(|v| v.is_complete());
+		let (object_version, meta, first_block) = match current.map(|v| (v, &v.state)) {
+			Some((v, ObjectVersionState::Complete(ObjectVersionData::FirstBlock(meta, h)))) => {
+				(v, meta, *h)
+			}
+			_ => return Ok(WorkerState::Busy),
+		};
+
+		let version = self
+			.garage
+			.version_table
+			.get(&object_version.uuid, &EmptyKey)
+			.await?;
+		if let Some(version) = version {
+			let size = version.size();
+			if !version.deleted.get() && !version.blocks.is_empty() && size != meta.size {
+				info!(
+					"Object {:?}/{} has size {} but its blocks sum up to {}, correcting it",
+					object.bucket_id, object.key, meta.size, size
+				);
+				self.rewrite_version(&object, object_version, meta, first_block, &version)
+					.await?;
+				self.corrected += 1;
+			}
+		}
+
+		Ok(WorkerState::Busy)
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		unreachable!()
+	}
+}
+
+// ----
+
+struct RepairBlockrefsWorker {
+	garage: Arc<Garage>,
+	pos: Vec<u8>,
+	counter: usize,
+}
+
+impl RepairBlockrefsWorker {
+	fn new(garage: Arc<Garage>) -> Self {
+		Self {
+			garage,
+			pos: vec![],
+			counter: 0,
+		}
+	}
+}
+
+#[async_trait]
+impl Worker for RepairBlockrefsWorker {
+	fn name(&self) -> String {
+		"Block refs repair worker".into()
+	}
+
+	fn status(&self) -> WorkerStatus {
+		WorkerStatus {
+			progress: Some(self.counter.to_string()),
+			..Default::default()
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		let (item_bytes, next_pos) =
+			match self.garage.block_ref_table.data.store.get_gt(&self.pos)? {
+				Some((k, v)) => (v, k),
+				None => {
+					info!("repair_block_ref: finished, done {}", self.counter);
+					return Ok(WorkerState::Done);
+				}
+			};
+
+		let block_ref = BlockRef::decode(&item_bytes).ok_or_message("Cannot decode BlockRef")?;
+		if !block_ref.deleted.get() {
+			let version = self
+				.garage
+				.version_table
+				.get(&block_ref.version, &EmptyKey)
+				.await?;
+			// The version might not exist if it has been GC'ed
+			let ref_exists = version.map(|v| !v.deleted.get()).unwrap_or(false);
+			if !ref_exists {
+				info!(
+					"Repair block ref: marking block_ref as deleted: {:?}",
+					block_ref
+				);
+				self.garage
+					.block_ref_table
+					.insert(&BlockRef {
+						block: block_ref.block,
+						version: block_ref.version,
+						deleted: true.into(),
+					})
+					.await?;
+			}
+		}
+
+		self.counter += 1;
+		self.pos = next_pos;
+
+		Ok(WorkerState::Busy)
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		unreachable!()
+	}
+}
+
+// ----
+
+struct RemoveStaleTombstonesWorker {
+	garage: Arc<Garage>,
+	older_than: Duration,
+	removed: usize,
+}
+
+impl RemoveStaleTombstonesWorker {
+	fn new(garage: Arc<Garage>, older_than: Duration) -> Self {
+		Self {
+			garage,
+			older_than,
+			removed: 0,
+		}
+	}
+}
+
+#[async_trait]
+impl Worker for RemoveStaleTombstonesWorker {
+	fn name(&self) -> String {
+		"Block ref stale tombstone removal worker".into()
+	}
+
+	fn status(&self) -> WorkerStatus {
+		WorkerStatus {
+			freeform: vec![format!("Tombstones removed: {}", self.removed)],
+			..Default::default()
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		// Tombstones are only queued for GC on the first node storing them,
+		// which is the only one that knows when they were created
+		let (removed, more) = self
+			.garage
+			.block_ref_table
+			.remove_stale_tombstones(self.older_than)
+			.await?;
+		self.removed += removed;
+
+		if more {
+			Ok(WorkerState::Busy)
+		} else {
+			info!(
+				"remove_stale_tombstones: finished, {} tombstones removed",
+				self.removed
+			);
+			Ok(WorkerState::Done)
+		}
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		unreachable!()
+	}
+}
+
+// ----
+
+struct FixBrokenBlockrefsWorker {
+	garage: Arc<Garage>,
+	pos: Vec<u8>,
+	counter: usize,
+	truncated: usize,
+	fixed: usize,
+	ambiguous: usize,
+	not_found: usize,
+}
+
+impl FixBrokenBlockrefsWorker {
+	fn new(garage: Arc<Garage>) -> Self {
+		Self {
+			garage,
+			pos: vec![],
+			counter: 0,
+			truncated: 0,
+			fixed: 0,
+			ambiguous: 0,
+			not_found: 0,
+		}
+	}
+}
+
+#[async_trait]
+impl Worker for FixBrokenBlockrefsWorker {
+	fn name(&self) -> String {
+		"Broken block refs repair worker".into()
+	}
+
+	fn status(&self) -> WorkerStatus {
+		WorkerStatus {
+			progress: Some(self.counter.to_string()),
+			freeform: vec![
+				format!("Truncated block hashes: {}", self.truncated),
+				format!("Fixed: {}", self.fixed),
+				format!("Ambiguous (several matching blocks): {}", self.ambiguous),
+				format!("No matching block: {}", self.not_found),
+			],
+			..Default::default()
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		let (item_bytes, next_pos) = match self
+			.garage
+			.block_ref_table
+			.data
+			.store
+			.get_gt(&self.pos)?
+		{
+			Some((k, v)) => (v, k),
+			None => {
+				info!(
+						"fix_broken_block_refs: finished, done {}, {} truncated hashes, {} fixed, {} ambiguous, {} without matching block",
+						self.counter, self.truncated, self.fixed, self.ambiguous, self.not_found
+					);
+				return Ok(WorkerState::Done);
+			}
+		};
+
+		let truncated = match TruncatedBlockRef::decode(&item_bytes) {
+			Some(br) if BlockRef::decode(&item_bytes).is_none() && br.block.len() < 32 => Some(br),
+			_ => None,
+		};
+		if let Some(broken) = truncated {
+			self.truncated += 1;
+			let matches = self
+				.garage
+				.block_manager
+				.find_blocks_by_hash_prefix(&broken.block)
+				.await?;
+			match matches.len() {
+				1 => {
+					info!(
+						"Fixing block ref of version {:?}: truncated hash {} is block {:?}",
+						broken.version,
+						hex::encode(&broken.block),
+						matches[0]
+					);
+					self.garage
+						.block_ref_table
+						.insert(&BlockRef {
+							block: matches[0],
+							version: broken.version,
+							deleted: broken.deleted,
+						})
+						.await?;
+					self.garage
+						.block_ref_table
+						.data
+						.delete_if_equal(&next_pos, &item_bytes)?;
+					self.fixed += 1;
+				}
+				0 => {
+					warn!(
+						"Block ref of version {:?} has truncated hash {}, no matching block found",
+						broken.version,
+						hex::encode(&broken.block)
+					);
+					self.not_found += 1;
+				}
+				_ => {
+					warn!(
+						"Block ref of version {:?} has truncated hash {}, which matches several blocks: {:?}",
+						broken.version,
+						hex::encode(&broken.block),
+						matches
+					);
+					self.ambiguous += 1;
+				}
+			}
+		}
+
+		self.counter += 1;
+		self.pos = next_pos;
+
+		Ok(WorkerState::Busy)
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		unreachable!()
+	}
+}
+
+// ----
+
+pub struct DbCompactionWorker {
+	garage: Arc<Garage>,
+	table: Option<String>,
+	result: Option<Result<(u64, u64), String>>,
+}
+
+impl DbCompactionWorker {
+	pub fn new(garage: Arc<Garage>, table: Option<String>) -> Self {
+		Self {
+			garage,
+			table,
+			result: None,
+		}
+	}
+}
+
+#[async_trait]
+impl Worker for DbCompactionWorker {
+	fn name(&self) -> String {
+		match &self.table {
+			Some(table) => format!("Metadata DB compaction worker ({})", table),
+			None => "Metadata DB compaction worker".into(),
+		}
+	}
+
+	fn status(&self) -> WorkerStatus {
+		let freeform = match &self.result {
+			None => vec!["Compaction in progress".into()],
+			Some(Ok((before, after))) => vec![format!(
+				"Compaction finished, size before: {}, after: {}",
+				bytesize::ByteSize::b(*before).to_string_as(false),
+				bytesize::ByteSize::b(*after).to_string_as(false)
+			)],
+			Some(Err(e)) => vec![format!("Compaction failed: {}", e)],
+		};
+		WorkerStatus {
+			freeform,
+			..Default::default()
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		let db_path = garage_model::garage::db_path(&self.garage.config);
+		let size_before = crate::repair::offline::disk_usage(&db_path)?;
+
+		let db = self.garage.db.clone();
+		let table = self.table.clone();
+		let res = tokio::task::spawn_blocking(move || match table {
+			Some(table) => {
+				for tree in crate::repair::offline::table_trees(&db, &table)? {
+					crate::repair::offline::compact_tree(&db, &tree)?;
+				}
+				Ok(())
+			}
+			None => db.compact().map_err(Error::from),
+		})
+		.await
+		.map_err(|e| Error::Message(format!("Compaction task panicked: {}", e)))?;
+
+		match res {
+			Ok(()) => {
+				let size_after = crate::repair::offline::disk_usage(&db_path)?;
+				info!(
+					"Metadata DB compaction finished, size before: {}, after: {}",
+					size_before, size_after
+				);
+				self.result = Some(Ok((size_before, size_after)));
+			}
+			Err(e) => {
+				error!("Metadata DB compaction failed: {}", e);
+				self.result = Some(Err(format!(
+					"{} (stop Garage and run compaction offline instead)",
+					e
+				)));
+			}
+		}
+		Ok(WorkerState::Done)
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		unreachable!()
+	}
+}
@@ -1,4 +1,5 @@
 use std::path::PathBuf;
+use std::sync::Arc;
 
 use tokio::sync::watch;
 
@@ -15,6 +16,8 @@ use garage_web::WebServer;
 use garage_api::k2v::api_server::K2VApiServer;
 
 use crate::admin::*;
+use crate::log_filter::LogFilter;
+use crate::repair::offline::db_migration_checkpoint_path;
 #[cfg(feature = "telemetry-otlp")]
 use crate::tracing_setup::*;
 use crate::{fill_secrets, Secrets};
@@ -27,9 +30,23 @@ async fn wait_from(mut chan: watch::Receiver<bool>) {
 	}
 }
 
-pub async fn run_server(config_file: PathBuf, secrets: Secrets) -> Result<(), Error> {
+pub async fn run_server(
+	config_file: PathBuf,
+	secrets: Secrets,
+	log_filter: Arc<LogFilter>,
+) -> Result<(), Error> {
 	info!("Loading configuration...");
-	let config = fill_secrets(read_config(config_file)?, secrets);
+	let config = fill_secrets(read_config(config_file.clone())?, secrets.clone());
+
+	if config.log_filter.is_some() {
+		log_filter.set_configured(config.log_filter.clone())?;
+	}
+
+	if db_migration_checkpoint_path(&config).exists() {
+		return Err(Error::Message(
+			"A migration of the metadata database is in progress. Run `garage db migrate` again to finish it before starting the server.".into(),
+		));
+	}
 
 	// ---- Initialize Garage internals ----
 
@@ -59,6 +76,7 @@ pub async fn run_server(config_file: PathBuf, secrets: Secrets) -> Result<(), Er
 	info!("Initialize Admin API server and metrics collector...");
 	let admin_server = AdminApiServer::new(
 		garage.clone(),
+		background.clone(),
 		#[cfg(feature = "metrics")]
 		metrics_exporter,
 	);
@@ -67,62 +85,75 @@ pub async fn run_server(config_file: PathBuf, secrets: Secrets) -> Result<(), Er
 	let run_system = tokio::spawn(garage.system.clone().run(watch_cancel.clone()));
 
 	info!("Create admin RPC handler...");
-	AdminRpcHandler::new(garage.clone(), background.clone());
+	AdminRpcHandler::new(garage.clone(), background.clone(), log_filter.clone());
+
+	#[cfg(unix)]
+	let reload_config = tokio::spawn(reload_config_on_sighup(
+		config_file,
+		secrets,
+		garage.clone(),
+		log_filter,
+		watch_cancel.clone(),
+	));
 
 	// ---- Launch public-facing API servers ----
 
 	let mut servers = vec![];
 
-	if let Some(s3_bind_addr) = &config.s3_api.api_bind_addr {
-		info!("Initializing S3 API server...");
-		servers.push((
-			"S3 API",
-			tokio::spawn(S3ApiServer::run(
-				garage.clone(),
-				*s3_bind_addr,
-				config.s3_api.s3_region.clone(),
-				wait_from(watch_cancel.clone()),
-			)),
-		));
-	}
-
-	if config.k2v_api.is_some() {
-		#[cfg(feature = "k2v")]
-		{
-			info!("Initializing K2V API server...");
+	if !config.node_role.serves_http() {
+		info!("Storage node: not launching any HTTP API server");
+	} else {
+		if let Some(s3_bind_addr) = &config.s3_api.api_bind_addr {
+			info!("Initializing S3 API server...");
 			servers.push((
-				"K2V API",
-				tokio::spawn(K2VApiServer::run(
+				"S3 API",
+				tokio::spawn(S3ApiServer::run(
 					garage.clone(),
-					config.k2v_api.as_ref().unwrap().api_bind_addr,
+					*s3_bind_addr,
 					config.s3_api.s3_region.clone(),
 					wait_from(watch_cancel.clone()),
 				)),
 			));
 		}
-		#[cfg(not(feature = "k2v"))]
-		error!("K2V is not enabled in this build, cannot start K2V API server");
-	}
 
-	if let Some(web_config) = &config.s3_web {
-		info!("Initializing web server...");
-		servers.push((
-			"Web",
-			tokio::spawn(WebServer::run(
-				garage.clone(),
-				web_config.bind_addr,
-				web_config.root_domain.clone(),
-				wait_from(watch_cancel.clone()),
-			)),
-		));
-	}
+		if config.k2v_api.is_some() {
+			#[cfg(feature = "k2v")]
+			{
+				info!("Initializing K2V API server...");
+				servers.push((
+					"K2V API",
+					tokio::spawn(K2VApiServer::run(
+						garage.clone(),
+						config.k2v_api.as_ref().unwrap().api_bind_addr,
+						config.s3_api.s3_region.clone(),
+						wait_from(watch_cancel.clone()),
+					)),
+				));
+			}
+			#[cfg(not(feature = "k2v"))]
+			error!("K2V is not enabled in this build, cannot start K2V API server");
+		}
 
-	if let Some(admin_bind_addr) = &config.admin.api_bind_addr {
-		info!("Launching Admin API server...");
-		servers.push((
-			"Admin",
-			tokio::spawn(admin_server.run(*admin_bind_addr, wait_from(watch_cancel.clone()))),
-		));
+		if let Some(web_config) = &config.s3_web {
+			info!("Initializing web server...");
+			servers.push((
+				"Web",
+				tokio::spawn(WebServer::run(
+					garage.clone(),
+					web_config.bind_addr,
+					web_config.root_domain.clone(),
+					wait_from(watch_cancel.clone()),
+				)),
+			));
+		}
+
+		if let Some(admin_bind_addr) = &config.admin.api_bind_addr {
+			info!("Launching Admin API server...");
+			servers.push((
+				"Admin",
+				tokio::spawn(admin_server.run(*admin_bind_addr, wait_from(watch_cancel.clone()))),
+			));
+		}
 	}
 
 	#[cfg(not(feature = "metrics"))]
@@ -143,6 +174,9 @@ pub async fn run_server(config_file: PathBuf, secrets: Secrets) -> Result<(), Er
 		}
 	}
 
+	#[cfg(unix)]
+	reload_config.await?;
+
 	// Remove RPC handlers for system to break reference cycles
 	garage.system.netapp.drop_all_handlers();
 	opentelemetry::global::shutdown_tracer_provider();
@@ -171,17 +205,62 @@ fn watch_shutdown_signal() -> watch::Receiver<bool> {
 		let mut sigint = signal(SignalKind::interrupt()).expect("Failed to install SIGINT handler");
 		let mut sigterm =
 			signal(SignalKind::terminate()).expect("Failed to install SIGTERM handler");
-		let mut sighup = signal(SignalKind::hangup()).expect("Failed to install SIGHUP handler");
 		tokio::select! {
 			_ = sigint.recv() => info!("Received SIGINT, shutting down."),
 			_ = sigterm.recv() => info!("Received SIGTERM, shutting down."),
-			_ = sighup.recv() => info!("Received SIGHUP, shutting down."),
 		}
 		send_cancel.send(true).unwrap();
 	});
 	watch_cancel
 }
 
+/// Read the configuration file again each time SIGHUP is received,
+/// and apply the fields that can be changed without restarting
+#[cfg(unix)]
+async fn reload_config_on_sighup(
+	config_file: PathBuf,
+	secrets: Secrets,
+	garage: Arc<Garage>,
+	log_filter: Arc<LogFilter>,
+	must_exit: watch::Receiver<bool>,
+) {
+	use tokio::signal::unix::*;
+
+	let mut sighup = signal(SignalKind::hangup()).expect("Failed to install SIGHUP handler");
+	let mut current_log_filter = garage.config.log_filter.clone();
+	loop {
+		tokio::select! {
+			_ = sighup.recv() => (),
+			_ = wait_from(must_exit.clone()) => return,
+		}
+
+		info!("Received SIGHUP, reloading configuration...");
+		let new_config = match read_config(config_file.clone()) {
+			Ok(c) => fill_secrets(c, secrets.clone()),
+			Err(e) => {
+				error!(
+					"Could not reload configuration, keeping the current one: {}",
+					e
+				);
+				continue;
+			}
+		};
+
+		if new_config.log_filter != current_log_filter {
+			match log_filter.set_configured(new_config.log_filter.clone()) {
+				Ok(()) => {
+					info!("Log filter changed to {:?}", new_config.log_filter);
+					current_log_filter = new_config.log_filter.clone();
+				}
+				Err(e) => error!("Could not change log filter: {}", e),
+			}
+		}
+
+		garage.apply_config_update(new_config);
+		info!("Configuration reloaded.");
+	}
+}
+
 #[cfg(windows)]
 fn watch_shutdown_signal() -> watch::Receiver<bool> {
 	use tokio::signal::windows::*;
@@ -51,3 +51,760 @@ async fn test_admin_bucket_perms() {
 
 	assert!(hb().await.is_err());
 }
+
+#[tokio::test]
+async fn test_admin_key_disable_rotate() {
+	let ctx = common::context();
+
+	let lb = |client: &aws_sdk_s3::Client| client.list_buckets().send();
+
+	assert!(lb(&ctx.client).await.is_ok());
+
+	ctx.garage
+		.command()
+		.args(["key", "disable", &ctx.key.id])
+		.quiet()
+		.expect_success_status("Could not disable key");
+
+	assert!(lb(&ctx.client).await.is_err());
+
+	ctx.garage
+		.command()
+		.args(["key", "enable", &ctx.key.id])
+		.quiet()
+		.expect_success_status("Could not enable key");
+
+	assert!(lb(&ctx.client).await.is_ok());
+
+	let output = ctx
+		.garage
+		.command()
+		.args(["key", "rotate-secret", &ctx.key.id])
+		.expect_success_output("Could not rotate key secret");
+	let stdout = String::from_utf8(output.stdout).unwrap();
+	let new_secret = stdout
+		.lines()
+		.find_map(|l| l.strip_prefix("Secret key: "))
+		.expect("No secret key in output");
+	assert_ne!(new_secret, ctx.key.secret);
+
+	assert!(lb(&ctx.client).await.is_err());
+
+	let new_key = common::garage::Key {
+		secret: new_secret.to_string(),
+		..ctx.key.clone()
+	};
+	let new_client = common::client::build_client(&new_key);
+	assert!(lb(&new_client).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_admin_set_log_level() {
+	let ctx = common::context();
+
+	let output = ctx
+		.garage
+		.command()
+		.args(["server", "set-log-level", "--level", "info"])
+		.args(["--revert-after", "1s"])
+		.expect_success_output("Could not set log level");
+	let stdout = String::from_utf8(output.stdout).unwrap();
+	assert!(stdout.contains("set to info"));
+
+	let status = ctx
+		.garage
+		.command()
+		.args(["server", "set-log-level", "--level", "info"])
+		.args(["--revert-after", "not a duration"])
+		.quiet()
+		.status()
+		.expect("Unable to run command");
+	assert!(!status.success());
+}
+
+#[tokio::test]
+async fn test_admin_dump_bg_vars() {
+	let ctx = common::context();
+
+	let output = ctx
+		.garage
+		.command()
+		.args(["debug", "dump-bg-vars", "--json"])
+		.expect_success_output("Could not dump background variables");
+	let vars: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+	let find = |name: &str| {
+		vars.as_array()
+			.unwrap()
+			.iter()
+			.find(|v| v["name"] == name)
+			.cloned()
+			.unwrap_or_else(|| panic!("Variable {} not found", name))
+	};
+
+	let tranquility = find("scrub-tranquility");
+	assert_eq!(tranquility["default"], "4");
+	assert!(tranquility["source"] == "default" || tranquility["source"] == "runtime");
+	assert_eq!(find("scrub-next-run")["source"], "read-only");
+
+	let output = ctx
+		.garage
+		.command()
+		.args(["debug", "dump-bg-vars"])
+		.expect_success_output("Could not dump background variables");
+	let stdout = String::from_utf8(output.stdout).unwrap();
+	assert!(stdout.contains("scrub-tranquility"));
+}
+
+#[tokio::test]
+async fn test_admin_cluster_stats() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("cluster-stats");
+
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("obj")
+		.body(b"hello".to_vec().into())
+		.send()
+		.await
+		.unwrap();
+
+	// Object counters are updated asynchronously
+	let mut stats = serde_json::Value::Null;
+	for _ in 0..50 {
+		let output = ctx
+			.garage
+			.command()
+			.args(["stats", "--json"])
+			.expect_success_output("Could not get cluster stats");
+		stats = serde_json::from_slice(&output.stdout).unwrap();
+		let counted = stats["buckets"]
+			.as_array()
+			.unwrap()
+			.iter()
+			.any(|b| b["globalAliases"][0] == bucket.as_str() && b["objects"] == 1);
+		if counted {
+			break;
+		}
+		tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+	}
+
+	let b = stats["buckets"]
+		.as_array()
+		.unwrap()
+		.iter()
+		.find(|b| b["globalAliases"][0] == bucket.as_str())
+		.expect("Bucket not found in stats");
+	assert_eq!(b["objects"], 1);
+	assert_eq!(b["bytes"], 5);
+
+	assert_eq!(stats["replicationMode"], "1");
+	let nodes = stats["nodes"].as_array().unwrap();
+	assert_eq!(nodes.len(), 1);
+	assert!(nodes[0]["garageVersion"].is_string());
+	assert!(nodes[0]["blockResyncQueueLen"].is_number());
+	assert!(nodes[0]["tableSyncsInFlight"].is_number());
+	assert!(nodes[0]["error"].is_null());
+
+	let output = ctx
+		.garage
+		.command()
+		.arg("stats")
+		.expect_success_output("Could not get cluster stats");
+	let stdout = String::from_utf8(output.stdout).unwrap();
+	assert!(stdout.contains("Replication mode: 1"));
+	assert!(stdout.contains("ResyncQueue"));
+	assert!(stdout.contains(&bucket));
+}
+
+#[tokio::test]
+async fn test_admin_list_bg_workers() {
+	let ctx = common::context();
+
+	let output = ctx
+		.garage
+		.command()
+		.args(["debug", "list-bg-workers"])
+		.expect_success_output("Could not list background workers");
+	let stdout = String::from_utf8(output.stdout).unwrap();
+	assert!(stdout.contains("Avg. duration"));
+	assert!(stdout.contains("Block scrub worker"));
+}
+
+#[tokio::test]
+async fn test_admin_check_network_partition() {
+	let ctx = common::context();
+
+	let output = ctx
+		.garage
+		.command()
+		.args(["debug", "check-network-partition"])
+		.expect_success_output("Could not check network partition");
+	let stdout = String::from_utf8(output.stdout).unwrap();
+	assert!(stdout.contains("No network partition detected"));
+	assert!(stdout.contains("1/1"));
+}
+
+#[tokio::test]
+async fn test_admin_repair_sync_version_sizes() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("sync-version-sizes");
+	let body = vec![0x42u8; 16 * 1024];
+
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("obj")
+		.body(body.clone().into())
+		.send()
+		.await
+		.unwrap();
+
+	ctx.garage
+		.command()
+		.args(["repair", "--yes", "sync-version-sizes"])
+		.quiet()
+		.expect_success_status("Could not launch repair");
+	tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+
+	// An object whose size is correct is left untouched
+	let head = ctx
+		.client
+		.head_object()
+		.bucket(&bucket)
+		.key("obj")
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(head.content_length, body.len() as i64);
+
+	let output = ctx
+		.garage
+		.command()
+		.args(["worker", "list"])
+		.expect_success_output("Could not list workers");
+	let stdout = String::from_utf8(output.stdout).unwrap();
+	assert!(stdout.contains("Object size repair worker"));
+}
+
+#[tokio::test]
+async fn test_admin_repair_rekey_blocks() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("rekey-blocks");
+	let body = vec![0x42u8; 16 * 1024];
+
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("obj")
+		.body(body.clone().into())
+		.send()
+		.await
+		.unwrap();
+
+	ctx.garage
+		.command()
+		.args(["repair", "--yes", "rekey-blocks"])
+		.quiet()
+		.expect_success_status("Could not launch repair");
+	tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+
+	// Block files are still readable once re-keyed
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key("obj")
+		.send()
+		.await
+		.unwrap();
+	assert_bytes_eq!(o.body, &body);
+
+	let output = ctx
+		.garage
+		.command()
+		.args(["worker", "list"])
+		.expect_success_output("Could not list workers");
+	let stdout = String::from_utf8(output.stdout).unwrap();
+	assert!(stdout.contains("Block re-keying worker"));
+}
+
+#[tokio::test]
+async fn test_admin_test_replication() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("test-replication");
+
+	let output = ctx
+		.garage
+		.command()
+		.args(["debug", "test-replication", "--bucket"])
+		.arg(&bucket)
+		.args(["--size-bytes", "2000000"])
+		.expect_success_output("Could not test replication");
+	let stdout = String::from_utf8(output.stdout).unwrap();
+	assert!(stdout.contains("All 1 nodes returned the correct data."));
+	assert!(stdout.contains("deleted"));
+
+	let list = ctx
+		.client
+		.list_objects_v2()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap();
+	assert!(list.contents.is_none());
+}
+
+#[tokio::test]
+async fn test_admin_repair_check_version_refs() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("check-version-refs");
+
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("obj")
+		.body(vec![0x42u8; 16 * 1024].into())
+		.send()
+		.await
+		.unwrap();
+
+	ctx.garage
+		.command()
+		.args([
+			"repair",
+			"--yes",
+			"object-table",
+			"--check-version-refs",
+			"--fix",
+		])
+		.quiet()
+		.expect_success_status("Could not launch repair");
+	tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+
+	// The version of the object exists, it must not be deleted
+	ctx.client
+		.head_object()
+		.bucket(&bucket)
+		.key("obj")
+		.send()
+		.await
+		.unwrap();
+
+	let output = ctx
+		.garage
+		.command()
+		.args(["worker", "list"])
+		.expect_success_output("Could not list workers");
+	let stdout = String::from_utf8(output.stdout).unwrap();
+	assert!(stdout.contains("Object version refs check worker"));
+}
+
+#[tokio::test]
+async fn test_admin_repair_object_integrity() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("object-integrity");
+	let body = vec![0x42u8; 2 * 1024 * 1024];
+
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("obj")
+		.body(body.clone().into())
+		.send()
+		.await
+		.unwrap();
+
+	ctx.garage
+		.command()
+		.args(["repair", "--yes", "object-integrity", "--auto-repair"])
+		.quiet()
+		.expect_success_status("Could not launch repair");
+	tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+
+	// A consistent object is left untouched
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key("obj")
+		.send()
+		.await
+		.unwrap();
+	assert_bytes_eq!(o.body, &body);
+
+	let output = ctx
+		.garage
+		.command()
+		.args(["worker", "list"])
+		.expect_success_output("Could not list workers");
+	let stdout = String::from_utf8(output.stdout).unwrap();
+	assert!(stdout.contains("Object integrity check worker"));
+}
+
+#[tokio::test]
+async fn test_admin_scrub_status() {
+	let ctx = common::context();
+
+	let output = ctx
+		.garage
+		.command()
+		.args(["scrub", "status", "--all-nodes"])
+		.expect_success_output("Could not get scrub status");
+	let stdout = String::from_utf8(output.stdout).unwrap();
+	assert!(stdout.contains("Progress"));
+	assert!(stdout.contains("ETA"));
+
+	ctx.garage
+		.command()
+		.args(["repair", "--yes", "scrub", "start"])
+		.quiet()
+		.expect_success_status("Could not start scrub");
+	tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+
+	let output = ctx
+		.garage
+		.command()
+		.args(["scrub", "status"])
+		.expect_success_output("Could not get scrub status");
+	let stdout = String::from_utf8(output.stdout).unwrap();
+	assert!(stdout.contains("running") || stdout.contains("finished"));
+}
+
+#[tokio::test]
+async fn test_admin_trace_rpc() {
+	let ctx = common::context();
+	let node_id = ctx.garage.node_id();
+
+	for operation in ["TableGet", "BlockHas", "NodeStatus"] {
+		let output = ctx
+			.garage
+			.command()
+			.args(["debug", "trace-rpc", "--node", &node_id[..16]])
+			.args(["--operation", operation, "--count", "10"])
+			.expect_success_output("Could not trace RPC");
+		let stdout = String::from_utf8(output.stdout).unwrap();
+		assert!(stdout.contains("Calls: 10, successful: 10, failed: 0"));
+		assert!(stdout.contains("Network"));
+	}
+}
+
+#[tokio::test]
+async fn test_admin_repair_remove_stale_tombstones() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("remove-stale-tombstones");
+
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("obj")
+		.body(vec![0x42u8; 16 * 1024].into())
+		.send()
+		.await
+		.unwrap();
+	ctx.client
+		.delete_object()
+		.bucket(&bucket)
+		.key("obj")
+		.send()
+		.await
+		.unwrap();
+
+	ctx.garage
+		.command()
+		.args([
+			"repair",
+			"--yes",
+			"block-ref-table",
+			"--remove-stale-tombstones",
+		])
+		.args(["--older-than", "0s"])
+		.quiet()
+		.expect_success_status("Could not launch repair");
+	tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+
+	let output = ctx
+		.garage
+		.command()
+		.args(["worker", "list"])
+		.expect_success_output("Could not list workers");
+	let stdout = String::from_utf8(output.stdout).unwrap();
+	assert!(stdout.contains("Block ref stale tombstone removal worker"));
+}
+
+#[tokio::test]
+async fn test_admin_show_partition_layout() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("show-partition-layout");
+
+	let output = ctx
+		.garage
+		.command()
+		.args(["debug", "show-partition-layout"])
+		.arg(format!("{}/obj", bucket))
+		.expect_success_output("Could not show partition layout");
+	let stdout = String::from_utf8(output.stdout).unwrap();
+	assert!(stdout.contains("Ring position: 0x"));
+	assert!(stdout.contains("Replicas (1):"));
+	assert!(stdout.contains(&ctx.garage.node_id()[..16]));
+	assert!(stdout.contains("block_ref: "));
+
+	let output = ctx
+		.garage
+		.command()
+		.args(["debug", "show-partition-layout"])
+		.arg("00".repeat(32))
+		.expect_success_output("Could not show partition layout");
+	let stdout = String::from_utf8(output.stdout).unwrap();
+	assert!(stdout.contains("Partition: 0 "));
+
+	let status = ctx
+		.garage
+		.command()
+		.args(["debug", "show-partition-layout", "not-a-hash"])
+		.quiet()
+		.status()
+		.expect("Unable to run command");
+	assert!(!status.success());
+}
+
+/// Find the hash and the file of the first data block of an object
+fn object_block_file(
+	ctx: &common::Context,
+	bucket: &str,
+	key: &str,
+) -> (String, std::path::PathBuf) {
+	let output = ctx
+		.garage
+		.command()
+		.args(["debug", "explain-ring", bucket, key])
+		.expect_success_output("Could not explain ring");
+	let stdout = String::from_utf8(output.stdout).unwrap();
+	let hash = stdout
+		.split("Data blocks")
+		.nth(1)
+		.unwrap()
+		.split_whitespace()
+		.find(|w| w.len() == 64)
+		.unwrap()
+		.to_string();
+
+	let block_dir = ctx
+		.garage
+		.path
+		.join("data")
+		.join(&hash[0..2])
+		.join(&hash[2..4]);
+	let block_file = [hash.clone(), format!("{}.zst", hash)]
+		.iter()
+		.map(|name| block_dir.join(name))
+		.find(|p| p.exists())
+		.unwrap();
+	(hash, block_file)
+}
+
+#[tokio::test]
+async fn test_admin_check_partial_writes() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("check-partial-writes");
+
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("obj")
+		.body(vec![0x17u8; 16 * 1024].into())
+		.send()
+		.await
+		.unwrap();
+
+	// Blocks encrypted with SSE-C are longer than the data of the object
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("encrypted")
+		.body(vec![0x18u8; 16 * 1024].into())
+		.sse_customer_algorithm("AES256")
+		.sse_customer_key("BwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwc=")
+		.sse_customer_key_md5("y4HAEFCYWuvAXWFTtA1Qpg==")
+		.send()
+		.await
+		.unwrap();
+
+	let (hash, block_file) = object_block_file(&ctx, &bucket, "obj");
+	let (encrypted_hash, encrypted_block_file) = object_block_file(&ctx, &bucket, "encrypted");
+
+	let data = std::fs::read(&block_file).unwrap();
+	std::fs::write(&block_file, &data[..data.len() / 2]).unwrap();
+
+	let output = ctx
+		.garage
+		.command()
+		.args(["debug", "check-partial-writes"])
+		.expect_success_output("Could not check partial writes");
+	let stdout = String::from_utf8(output.stdout).unwrap();
+	assert!(stdout.contains("2 block files checked, 1 partially written."));
+	assert!(stdout.contains(&hash));
+	assert!(!stdout.contains(&encrypted_hash));
+	assert!(block_file.exists());
+
+	let output = ctx
+		.garage
+		.command()
+		.args(["debug", "check-partial-writes", "--fix"])
+		.expect_success_output("Could not fix partial writes");
+	let stdout = String::from_utf8(output.stdout).unwrap();
+	assert!(stdout.contains(&hash));
+	assert!(!block_file.exists());
+	assert!(encrypted_block_file.exists());
+}
+
+#[tokio::test]
+async fn test_admin_db_migrate_checks_engines() {
+	let ctx = common::context();
+
+	// These are rejected before the database of the running server is opened
+	for args in [
+		vec!["db", "migrate", "--from", "sled", "--to", "lmdb"],
+		vec!["db", "migrate", "--yes", "--from", "lmdb", "--to", "sqlite"],
+		vec!["db", "migrate", "--yes", "--from", "sled", "--to", "sled"],
+		vec![
+			"db", "migrate", "--yes", "--from", "sled", "--to", "rocksdb",
+		],
+	] {
+		let status = ctx
+			.garage
+			.command()
+			.args(&args)
+			.quiet()
+			.status()
+			.expect("Unable to run command");
+		assert!(!status.success(), "{:?} was accepted", args);
+	}
+}
+
+#[tokio::test]
+async fn test_admin_db_import_tables_checks_database() {
+	let ctx = common::context();
+
+	let dump = ctx.garage.path.join("missing-dump.jsonl.zst");
+	let dump = dump.to_str().unwrap();
+
+	// These are rejected before the dump file is read: the import needs
+	// confirmation, and the database of the running server already exists
+	for args in [
+		vec!["db", "import-tables", "--input", dump],
+		vec!["db", "import-tables", "--yes", "--input", dump],
+	] {
+		let status = ctx
+			.garage
+			.command()
+			.args(&args)
+			.quiet()
+			.status()
+			.expect("Unable to run command");
+		assert!(!status.success(), "{:?} was accepted", args);
+	}
+}
+
+#[tokio::test]
+async fn test_admin_metrics() {
+	use hyper::{body::to_bytes, Body, Client, Request, StatusCode};
+
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("metrics");
+
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("obj")
+		.body(b"hello".to_vec().into())
+		.send()
+		.await
+		.unwrap();
+	ctx.client
+		.list_objects_v2()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap();
+
+	let client = Client::new();
+	let req = Request::builder()
+		.method("GET")
+		.uri(format!(
+			"http://127.0.0.1:{}/metrics",
+			ctx.garage.admin_port
+		))
+		.body(Body::empty())
+		.unwrap();
+	let resp = client.request(req).await.unwrap();
+	assert_eq!(resp.status(), StatusCode::OK);
+
+	let body = to_bytes(resp.into_body()).await.unwrap();
+	let body = String::from_utf8(body.to_vec()).unwrap();
+	assert!(body.contains("table_get_range_request_duration"));
+	assert!(body.contains("background_worker_count{state=\"idle\"}"));
+	assert!(body.contains("db_transaction_duration"));
+}
+
+#[tokio::test]
+async fn test_admin_layout_history_rollback() {
+	let ctx = common::context();
+
+	let layout_history = || {
+		let output = ctx
+			.garage
+			.command()
+			.args(["layout", "history"])
+			.expect_success_output("Could not show layout history");
+		String::from_utf8(output.stdout).unwrap()
+	};
+	fn history_line<'a>(history: &'a str, version: &str) -> Option<Vec<&'a str>> {
+		history
+			.lines()
+			.find(|l| l.starts_with(version))
+			.map(|l| l.split_whitespace().collect())
+	}
+
+	let history = layout_history();
+	assert!(history_line(&history, "1 (current)").is_some());
+
+	let node_id = ctx.garage.node_id();
+	ctx.garage
+		.command()
+		.args(["layout", "assign"])
+		.arg(&node_id[..16])
+		.args(["-c", "2"])
+		.quiet()
+		.expect_success_status("Could not assign garage node layout");
+	ctx.garage
+		.command()
+		.args(["layout", "apply", "--version", "2"])
+		.quiet()
+		.expect_success_status("Could not apply garage node layout");
+
+	let history = layout_history();
+	let current = history_line(&history, "2 (current)").unwrap();
+	assert_eq!(current.last(), Some(&"2"));
+	let previous = history_line(&history, "1 ").unwrap();
+	assert_eq!(previous.last(), Some(&"1"));
+
+	ctx.garage
+		.command()
+		.args(["layout", "rollback", "--version", "1", "--force"])
+		.quiet()
+		.expect_success_status("Could not roll back layout");
+
+	let history = layout_history();
+	let current = history_line(&history, "3 (current)").unwrap();
+	assert_eq!(current.last(), Some(&"1"));
+
+	let status = ctx
+		.garage
+		.command()
+		.args(["layout", "rollback", "--version", "42", "--force"])
+		.quiet()
+		.status()
+		.expect("Unable to run command");
+	assert!(!status.success());
+}
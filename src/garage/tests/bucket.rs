@@ -1,6 +1,12 @@
 use crate::common;
 use crate::common::ext::CommandExt;
+use aws_sdk_s3::error::ProvideErrorMetadata;
 use aws_sdk_s3::operation::delete_bucket::DeleteBucketOutput;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{
+	CompletedMultipartUpload, CompletedPart, ServerSideEncryption, ServerSideEncryptionByDefault,
+	ServerSideEncryptionConfiguration, ServerSideEncryptionRule,
+};
 
 #[tokio::test]
 async fn test_bucket_all() {
@@ -101,3 +107,386 @@ async fn test_bucket_all() {
 			.any(|x| x.name.as_ref().unwrap() == "hello"));
 	}
 }
+
+#[tokio::test]
+async fn test_bucket_storage_usage() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("storage-usage");
+
+	for key in ["a", "b"] {
+		ctx.client
+			.put_object()
+			.bucket(&bucket)
+			.key(key)
+			.body(ByteStream::from(vec![0u8; 1000]))
+			.send()
+			.await
+			.unwrap();
+	}
+	ctx.client
+		.delete_object()
+		.bucket(&bucket)
+		.key("b")
+		.send()
+		.await
+		.unwrap();
+	ctx.client
+		.create_multipart_upload()
+		.bucket(&bucket)
+		.key("c")
+		.send()
+		.await
+		.unwrap();
+
+	let output = ctx
+		.garage
+		.command()
+		.args(["bucket", "get-storage-usage", "--bucket", &bucket])
+		.args(["--detail", "all"])
+		.expect_success_output("Could not get bucket storage usage");
+	let stdout = String::from_utf8(output.stdout).unwrap();
+	let value = |name: &str| {
+		stdout
+			.lines()
+			.find_map(|l| l.strip_prefix(name))
+			.map(str::trim)
+			.unwrap_or_else(|| panic!("No {} in output", name))
+			.to_string()
+	};
+	assert_eq!(value("Current objects:"), "1");
+	assert_eq!(value("Current object size:"), "1.0 KB");
+	assert_eq!(value("Delete markers:"), "1");
+	assert_eq!(value("Incomplete uploads:"), "1");
+	assert_eq!(value("Data blocks:"), "0");
+}
+
+#[tokio::test]
+async fn test_bucket_list_objects() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("list-objects");
+
+	for key in ["a", "dir/b", "dir/c", "other/d"] {
+		ctx.client
+			.put_object()
+			.bucket(&bucket)
+			.key(key)
+			.body(ByteStream::from(vec![0u8; 10]))
+			.send()
+			.await
+			.unwrap();
+	}
+	ctx.client
+		.delete_object()
+		.bucket(&bucket)
+		.key("dir/c")
+		.send()
+		.await
+		.unwrap();
+
+	let list = |args: &[&str]| {
+		let output = ctx
+			.garage
+			.command()
+			.args(["bucket", "list-objects", &bucket, "--json"])
+			.args(args)
+			.expect_success_output("Could not list objects");
+		String::from_utf8(output.stdout)
+			.unwrap()
+			.lines()
+			.map(|l| serde_json::from_str::<serde_json::Value>(l).unwrap())
+			.collect::<Vec<_>>()
+	};
+
+	let objects = list(&[]);
+	let keys = objects.iter().map(|o| &o["key"]).collect::<Vec<_>>();
+	assert_eq!(keys, ["a", "dir/b", "other/d"]);
+	assert_eq!(objects[0]["size"], 10);
+	assert_eq!(objects[0]["storage_class"], "STANDARD");
+
+	let objects = list(&["--prefix", "dir/"]);
+	assert_eq!(objects.len(), 1);
+	assert_eq!(objects[0]["key"], "dir/b");
+
+	let objects = list(&["--delimiter", "/"]);
+	assert_eq!(objects.len(), 3);
+	assert_eq!(objects[0]["key"], "a");
+	assert_eq!(objects[1]["prefix"], "dir/");
+	assert_eq!(objects[2]["prefix"], "other/");
+}
+
+#[tokio::test]
+async fn test_bucket_max_object_size() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("max-object-size");
+
+	ctx.garage
+		.command()
+		.args(["bucket", "set-max-object-size", "--bucket", &bucket])
+		.args(["--max-bytes", "1000"])
+		.quiet()
+		.expect_success_status("Could not set maximum object size");
+
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("small")
+		.body(ByteStream::from(vec![0u8; 1000]))
+		.send()
+		.await
+		.unwrap();
+	assert!(ctx
+		.client
+		.put_object()
+		.bucket(&bucket)
+		.key("large")
+		.body(ByteStream::from(vec![0u8; 2000]))
+		.send()
+		.await
+		.is_err());
+
+	// Multipart uploads are not subject to the limit
+	let upload = ctx
+		.client
+		.create_multipart_upload()
+		.bucket(&bucket)
+		.key("multipart")
+		.send()
+		.await
+		.unwrap();
+	let upload_id = upload.upload_id.unwrap();
+	let part = ctx
+		.client
+		.upload_part()
+		.bucket(&bucket)
+		.key("multipart")
+		.upload_id(&upload_id)
+		.part_number(1)
+		.body(ByteStream::from(vec![0u8; 2000]))
+		.send()
+		.await
+		.unwrap();
+	ctx.client
+		.complete_multipart_upload()
+		.bucket(&bucket)
+		.key("multipart")
+		.upload_id(&upload_id)
+		.multipart_upload(
+			CompletedMultipartUpload::builder()
+				.parts(
+					CompletedPart::builder()
+						.part_number(1)
+						.e_tag(part.e_tag.unwrap())
+						.build(),
+				)
+				.build(),
+		)
+		.send()
+		.await
+		.unwrap();
+
+	// But copying them in a single request is
+	assert!(ctx
+		.client
+		.copy_object()
+		.bucket(&bucket)
+		.key("copy")
+		.copy_source(format!("{}/multipart", bucket))
+		.send()
+		.await
+		.is_err());
+
+	ctx.garage
+		.command()
+		.args(["bucket", "set-max-object-size", "--bucket", &bucket])
+		.args(["--max-bytes", "none"])
+		.quiet()
+		.expect_success_status("Could not remove maximum object size");
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("large")
+		.body(ByteStream::from(vec![0u8; 2000]))
+		.send()
+		.await
+		.unwrap();
+}
+
+#[tokio::test]
+async fn test_bucket_encryption_required() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("encryption-required");
+
+	// Multipart upload created before encryption is required
+	let upload = ctx
+		.client
+		.create_multipart_upload()
+		.bucket(&bucket)
+		.key("multipart")
+		.send()
+		.await
+		.unwrap();
+
+	ctx.garage
+		.command()
+		.args(["bucket", "set-encryption-required", &bucket])
+		.quiet()
+		.expect_success_status("Could not require server-side encryption");
+
+	let put = |key: &str, sse: Option<ServerSideEncryption>| {
+		ctx.client
+			.put_object()
+			.bucket(&bucket)
+			.key(key)
+			.set_server_side_encryption(sse)
+			.body(ByteStream::from(vec![0u8; 100]))
+			.send()
+	};
+	put("encrypted", Some(ServerSideEncryption::Aes256))
+		.await
+		.unwrap();
+	let err = put("plain", None).await.unwrap_err();
+	assert_eq!(
+		err.code(),
+		Some("ServerSideEncryptionConfigurationNotFoundError")
+	);
+
+	// Copies must request server-side encryption as well
+	assert!(ctx
+		.client
+		.copy_object()
+		.bucket(&bucket)
+		.key("copy")
+		.copy_source(format!("{}/encrypted", bucket))
+		.send()
+		.await
+		.is_err());
+	ctx.client
+		.copy_object()
+		.bucket(&bucket)
+		.key("copy")
+		.copy_source(format!("{}/encrypted", bucket))
+		.server_side_encryption(ServerSideEncryption::Aes256)
+		.send()
+		.await
+		.unwrap();
+
+	// Parts cannot be added to the upload created without encryption
+	assert!(ctx
+		.client
+		.upload_part()
+		.bucket(&bucket)
+		.key("multipart")
+		.upload_id(upload.upload_id.unwrap())
+		.part_number(1)
+		.body(ByteStream::from(vec![0u8; 100]))
+		.send()
+		.await
+		.is_err());
+
+	// The encryption configuration of the bucket does not require encryption
+	let other_bucket = ctx.create_bucket("encryption-configuration");
+	ctx.client
+		.put_bucket_encryption()
+		.bucket(&other_bucket)
+		.server_side_encryption_configuration(
+			ServerSideEncryptionConfiguration::builder()
+				.rules(
+					ServerSideEncryptionRule::builder()
+						.apply_server_side_encryption_by_default(
+							ServerSideEncryptionByDefault::builder()
+								.sse_algorithm(ServerSideEncryption::Aes256)
+								.build(),
+						)
+						.build(),
+				)
+				.build(),
+		)
+		.send()
+		.await
+		.unwrap();
+	ctx.client
+		.put_object()
+		.bucket(&other_bucket)
+		.key("plain")
+		.body(ByteStream::from(vec![0u8; 100]))
+		.send()
+		.await
+		.unwrap();
+
+	ctx.garage
+		.command()
+		.args(["bucket", "set-encryption-required", &bucket, "--disable"])
+		.quiet()
+		.expect_success_status("Could not stop requiring server-side encryption");
+	put("plain", None).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_bucket_quotas() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("quotas");
+
+	ctx.garage
+		.command()
+		.args(["bucket", "set-quotas", &bucket, "--max-objects", "1"])
+		.quiet()
+		.expect_success_status("Could not set bucket quotas");
+
+	let put = |key: String| {
+		ctx.client
+			.put_object()
+			.bucket(&bucket)
+			.key(key)
+			.body(ByteStream::from(vec![0u8; 100]))
+			.send()
+	};
+
+	// Quotas are checked against object counters that are updated
+	// asynchronously: two concurrent writes can both pass the check
+	// and together exceed the quota.
+	let (res_a, res_b) = futures::join!(put("a".into()), put("b".into()));
+	let written = [("a", res_a.is_ok()), ("b", res_b.is_ok())]
+		.iter()
+		.filter(|(_, ok)| *ok)
+		.map(|(k, _)| *k)
+		.collect::<Vec<_>>();
+	assert!(!written.is_empty());
+
+	// Once the counters have caught up, new objects are rejected
+	let mut rejected = false;
+	for i in 0..50 {
+		match put(format!("c{}", i)).await {
+			Ok(_) => tokio::time::sleep(std::time::Duration::from_millis(200)).await,
+			Err(e) => {
+				let e = e.into_service_error();
+				assert_eq!(e.code(), Some("QuotaExceeded"));
+				rejected = true;
+				break;
+			}
+		}
+	}
+	assert!(rejected);
+
+	// Overwriting an existing object does not add an object
+	put(written[0].into()).await.unwrap();
+
+	// Copies are subject to the quota too
+	let err = ctx
+		.client
+		.copy_object()
+		.bucket(&bucket)
+		.key("copy")
+		.copy_source(format!("{}/{}", bucket, written[0]))
+		.send()
+		.await
+		.unwrap_err()
+		.into_service_error();
+	assert_eq!(err.code(), Some("QuotaExceeded"));
+
+	ctx.garage
+		.command()
+		.args(["bucket", "set-quotas", &bucket, "--max-objects", "none"])
+		.quiet()
+		.expect_success_status("Could not remove bucket quotas");
+	put("d".into()).await.unwrap();
+}
@@ -196,6 +196,23 @@ impl<'a> RequestBuilder<'a> {
 
 				"STREAMING-AWS4-HMAC-SHA256-PAYLOAD".to_owned()
 			}
+			BodySignature::StreamingUnsignedTrailer(size) => {
+				all_headers.insert("content-encoding".to_owned(), "aws-chunked".to_owned());
+				all_headers.insert(
+					"x-amz-decoded-content-length".to_owned(),
+					self.body.len().to_string(),
+				);
+				all_headers.insert(
+					"x-amz-trailer".to_owned(),
+					"x-amz-checksum-sha256".to_owned(),
+				);
+				all_headers.insert(
+					"content-length".to_owned(),
+					to_unsigned_trailer_body(&self.body, size).len().to_string(),
+				);
+
+				"STREAMING-UNSIGNED-PAYLOAD-TRAILER".to_owned()
+			}
 		};
 		all_headers.insert("x-amz-content-sha256".to_owned(), body_sha.clone());
 
@@ -232,10 +249,14 @@ impl<'a> RequestBuilder<'a> {
 			request = request.header(k, v);
 		}
 
-		let body = if let BodySignature::Streaming(size) = self.body_signature {
-			to_streaming_body(&self.body, size, signature, streaming_signer, now, &scope)
-		} else {
-			self.body.clone()
+		let body = match self.body_signature {
+			BodySignature::Streaming(size) => {
+				to_streaming_body(&self.body, size, signature, streaming_signer, now, &scope)
+			}
+			BodySignature::StreamingUnsignedTrailer(size) => {
+				to_unsigned_trailer_body(&self.body, size)
+			}
+			_ => self.body.clone(),
 		};
 		let request = request
 			.uri(uri)
@@ -250,6 +271,8 @@ pub enum BodySignature {
 	Unsigned,
 	Classic,
 	Streaming(usize),
+	/// Chunks are not signed, and followed by a trailer with the SHA256 of the body
+	StreamingUnsignedTrailer(usize),
 }
 
 fn query_param_to_string(params: &HashMap<String, Option<String>>) -> String {
@@ -304,3 +327,20 @@ fn to_streaming_body(
 
 	res
 }
+
+fn to_unsigned_trailer_body(body: &[u8], chunk_size: usize) -> Vec<u8> {
+	use base64::prelude::*;
+
+	let mut res = Vec::with_capacity(body.len());
+	for chunk in body.chunks(chunk_size).chain(std::iter::once(&[][..])) {
+		res.extend_from_slice(format!("{:x}\r\n", chunk.len()).as_bytes());
+		if !chunk.is_empty() {
+			res.extend_from_slice(chunk);
+			res.extend_from_slice(b"\r\n");
+		}
+	}
+	let checksum = BASE64_STANDARD.encode(garage_util::data::sha256sum(body));
+	res.extend_from_slice(format!("x-amz-checksum-sha256:{}\r\n\r\n", checksum).as_bytes());
+
+	res
+}
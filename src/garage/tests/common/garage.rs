@@ -10,6 +10,8 @@ pub const DEFAULT_PORT: u16 = 49995;
 
 static GARAGE_TEST_SECRET: &str =
 	"c3ea8cb80333d04e208d136698b1a01ae370d463f0d435ab2177510b3478bf44";
+static GARAGE_TEST_ENCRYPTION_KEY: &str =
+	"5f8a0b3c9d2e7f1a4b6c8d0e2f4a6b8c0d2e4f6a8b0c2d4e6f8a0b2c4d6e8f0a";
 
 #[derive(Debug, Default, Clone)]
 pub struct Key {
@@ -24,24 +26,28 @@ pub struct Instance {
 	pub default_key: Key,
 	pub s3_port: u16,
 	pub k2v_port: u16,
+	pub rpc_port: u16,
 	pub web_port: u16,
 	pub admin_port: u16,
 }
 
 impl Instance {
 	fn new() -> Instance {
-		use std::{env, fs};
+		use std::env;
 
-		let port = env::var("GARAGE_TEST_INTEGRATION_PORT")
-			.map(|value| value.parse().expect("Invalid port provided"))
-			.ok()
-			.unwrap_or(DEFAULT_PORT);
+		let port = test_port();
 
 		let path = env::var("GARAGE_TEST_INTEGRATION_PATH")
 			.map(PathBuf::from)
 			.ok()
 			.unwrap_or_else(|| env::temp_dir().join(format!("garage-integ-test-{}", port)));
 
+		Instance::start(path, port, "1")
+	}
+
+	fn start(path: PathBuf, port: u16, replication_mode: &str) -> Instance {
+		use std::fs;
+
 		// Clean test runtime directory
 		if path.exists() {
 			fs::remove_dir_all(&path).expect("Could not clean test runtime directory");
@@ -53,16 +59,21 @@ impl Instance {
 metadata_dir = "{path}/meta"
 data_dir = "{path}/data"
 
-replication_mode = "1"
+replication_mode = "{replication_mode}"
 
 rpc_bind_addr = "127.0.0.1:{rpc_port}"
 rpc_public_addr = "127.0.0.1:{rpc_port}"
 rpc_secret = "{secret}"
 
+encryption_key = "{encryption_key}"
+
 [s3_api]
 s3_region = "{region}"
 api_bind_addr = "127.0.0.1:{s3_port}"
 root_domain = ".s3.garage"
+access_log_flush_interval_secs = 1
+json_access_log = true
+access_log_path = "{path}/access.log"
 
 [k2v_api]
 api_bind_addr = "127.0.0.1:{k2v_port}"
@@ -76,7 +87,9 @@ index = "index.html"
 api_bind_addr = "127.0.0.1:{admin_port}"
 "#,
 			path = path.display(),
+			replication_mode = replication_mode,
 			secret = GARAGE_TEST_SECRET,
+			encryption_key = GARAGE_TEST_ENCRYPTION_KEY,
 			region = super::REGION,
 			s3_port = port,
 			k2v_port = port + 1,
@@ -105,6 +118,7 @@ api_bind_addr = "127.0.0.1:{admin_port}"
 			default_key: Key::default(),
 			s3_port: port,
 			k2v_port: port + 1,
+			rpc_port: port + 2,
 			web_port: port + 3,
 			admin_port: port + 4,
 		}
@@ -151,6 +165,10 @@ api_bind_addr = "127.0.0.1:{admin_port}"
 			.expect_success_status("Could not apply garage node layout");
 	}
 
+	pub fn rpc_addr(&self) -> String {
+		format!("127.0.0.1:{}", self.rpc_port)
+	}
+
 	fn terminate(&mut self) {
 		// TODO: Terminate "gracefully" the process with SIGTERM instead of directly SIGKILL it.
 		self.process
@@ -215,6 +233,89 @@ api_bind_addr = "127.0.0.1:{admin_port}"
 	}
 }
 
+/// A cluster of several Garage nodes storing three copies of the data,
+/// started for a test and stopped when it is dropped
+pub struct Cluster {
+	pub nodes: Vec<Instance>,
+}
+
+impl Cluster {
+	/// Start a cluster of `size` nodes, using ports after those of the
+	/// main instance. `index` must be different for each cluster started
+	/// by the tests, so that their ports do not overlap.
+	pub fn new(index: u16, size: usize) -> Cluster {
+		use std::{env, thread, time::Duration};
+
+		let base_port = test_port() + 100 * (index + 1);
+		let mut nodes = (0..size as u16)
+			.map(|i| {
+				let port = base_port + 10 * i;
+				let path = env::temp_dir().join(format!("garage-integ-test-{}", port));
+				Instance::start(path, port, "3")
+			})
+			.collect::<Vec<_>>();
+		for node in nodes.iter_mut() {
+			node.wait_for_boot();
+		}
+
+		let first = &nodes[0];
+		for (i, node) in nodes.iter().enumerate() {
+			let node_id = node.node_id();
+			let node_short_id = &node_id[..64];
+			if i > 0 {
+				first
+					.command()
+					.args(["node", "connect"])
+					.arg(format!("{}@{}", node_short_id, node.rpc_addr()))
+					.quiet()
+					.expect_success_status("Could not connect garage nodes");
+			}
+			first
+				.command()
+				.args(["layout", "assign"])
+				.arg(node_short_id)
+				.args(["-c", "1", "-z"])
+				.arg(format!("zone{}", i))
+				.quiet()
+				.expect_success_status("Could not assign garage node layout");
+		}
+		first
+			.command()
+			.args(["layout", "apply"])
+			.args(["--version", "1"])
+			.quiet()
+			.expect_success_status("Could not apply garage node layout");
+
+		// Wait for all the nodes to use the new layout
+		for node in nodes.iter() {
+			for _ in 0..60 {
+				let output = node
+					.command()
+					.args(["layout", "show"])
+					.expect_success_output("Could not show garage node layout");
+				if String::from_utf8_lossy(&output.stdout)
+					.contains("Current cluster layout version: 1")
+				{
+					break;
+				}
+				thread::sleep(Duration::from_secs(1));
+			}
+		}
+
+		let key = nodes[0].key(Some("garage_test"));
+		nodes[0].default_key = key;
+		Cluster { nodes }
+	}
+}
+
+impl Drop for Cluster {
+	fn drop(&mut self) {
+		for node in self.nodes.iter_mut() {
+			node.terminate();
+		}
+	}
+}
+
 static mut INSTANCE: MaybeUninit<Instance> = MaybeUninit::uninit();
 static INSTANCE_INIT: Once = Once::new();
 
@@ -242,6 +343,13 @@ pub fn instance() -> &'static Instance {
 	unsafe { INSTANCE.assume_init_ref() }
 }
 
+fn test_port() -> u16 {
+	std::env::var("GARAGE_TEST_INTEGRATION_PORT")
+		.map(|value| value.parse().expect("Invalid port provided"))
+		.ok()
+		.unwrap_or(DEFAULT_PORT)
+}
+
 pub fn command(config_path: &Path) -> process::Command {
 	use std::env;
 
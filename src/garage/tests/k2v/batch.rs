@@ -611,3 +611,77 @@ async fn test_batch() {
 		])
 	);
 }
+
+#[tokio::test]
+async fn test_batch_multiple_partitions() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("test-k2v-batch-partitions");
+
+	// Partition keys are hashed to different positions of the ring, so that
+	// a single batch is split in several RPC calls
+	let pks = (0..16)
+		.map(|i| format!("partition-{}", i))
+		.collect::<Vec<_>>();
+
+	let inserts = pks
+		.iter()
+		.enumerate()
+		.map(|(i, pk)| {
+			json!({
+				"pk": pk,
+				"sk": "item",
+				"ct": null,
+				"v": BASE64_STANDARD.encode(format!("value {}", i)),
+			})
+		})
+		.collect::<Vec<_>>();
+	let res = ctx
+		.k2v
+		.request
+		.builder(bucket.clone())
+		.body(serde_json::to_vec(&inserts).unwrap())
+		.method(Method::POST)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::NO_CONTENT);
+
+	// A search that finds nothing does not make the whole batch fail,
+	// its result is returned with an empty list of items
+	let mut searches = pks
+		.iter()
+		.map(|pk| json!({"partitionKey": pk, "start": "item", "singleItem": true}))
+		.collect::<Vec<_>>();
+	searches.push(json!({"partitionKey": "partition-0", "start": "missing", "singleItem": true}));
+	searches.push(json!({"partitionKey": "no-such-partition"}));
+
+	let res = ctx
+		.k2v
+		.request
+		.builder(bucket.clone())
+		.query_param("search", Option::<&str>::None)
+		.body(serde_json::to_vec(&searches).unwrap())
+		.method(Method::POST)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::OK);
+	let json_res = json_body(res).await;
+	let results = json_res.as_array().unwrap();
+	assert_eq!(results.len(), pks.len() + 2);
+
+	for (i, (pk, result)) in pks.iter().zip(results.iter()).enumerate() {
+		assert_eq!(result["partitionKey"], json!(pk));
+		let items = result["items"].as_array().unwrap();
+		assert_eq!(items.len(), 1);
+		assert_eq!(items[0]["sk"], json!("item"));
+		assert_eq!(
+			items[0]["v"],
+			json!([BASE64_STANDARD.encode(format!("value {}", i))])
+		);
+	}
+	for result in &results[pks.len()..] {
+		assert_eq!(result["items"], json!([]));
+		assert_eq!(result["more"], json!(false));
+	}
+}
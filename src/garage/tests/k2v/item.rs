@@ -1,13 +1,14 @@
 use std::time::Duration;
 
 use crate::common;
+use crate::common::ext::*;
 
 use assert_json_diff::assert_json_eq;
 use base64::prelude::*;
 use serde_json::json;
 
 use crate::json_body;
-use hyper::{Method, StatusCode};
+use hyper::{Body, Method, Response, StatusCode};
 
 #[tokio::test]
 async fn test_items_and_indices() {
@@ -736,3 +737,382 @@ async fn test_item_return_format() {
 	let res_body = json_body(res).await;
 	assert_json_eq!(res_body, json!([null]));
 }
+
+#[tokio::test]
+async fn test_item_compare_and_swap() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("test-k2v-item-cas");
+
+	// Create the item, using the causality token of an empty item
+	let res = ctx
+		.k2v
+		.request
+		.builder(bucket.clone())
+		.path("root")
+		.query_param("sort_key", Some("cas"))
+		.query_param("cas", Some("AAAAAAAAAAA"))
+		.body(b"first value".to_vec())
+		.method(Method::PUT)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::NO_CONTENT);
+
+	let res = ctx
+		.k2v
+		.request
+		.builder(bucket.clone())
+		.path("root")
+		.query_param("sort_key", Some("cas"))
+		.signed_header("accept", "application/octet-stream")
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::OK);
+	let ct = res
+		.headers()
+		.get("x-garage-causality-token")
+		.unwrap()
+		.to_str()
+		.unwrap()
+		.to_string();
+
+	// Creating it again fails, and returns the current causality token
+	let res = ctx
+		.k2v
+		.request
+		.builder(bucket.clone())
+		.path("root")
+		.query_param("sort_key", Some("cas"))
+		.query_param("cas", Some("AAAAAAAAAAA"))
+		.body(b"concurrent value".to_vec())
+		.method(Method::PUT)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::CONFLICT);
+	assert_eq!(
+		res.headers()
+			.get("x-garage-causality-token")
+			.unwrap()
+			.to_str()
+			.unwrap(),
+		ct
+	);
+
+	// Updating with the current causality token succeeds
+	let res = ctx
+		.k2v
+		.request
+		.builder(bucket.clone())
+		.path("root")
+		.query_param("sort_key", Some("cas"))
+		.query_param("cas", Some(ct.clone()))
+		.body(b"second value".to_vec())
+		.method(Method::PUT)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::NO_CONTENT);
+
+	// The old causality token can no longer be used
+	let res = ctx
+		.k2v
+		.request
+		.builder(bucket.clone())
+		.path("root")
+		.query_param("sort_key", Some("cas"))
+		.query_param("cas", Some(ct))
+		.body(b"third value".to_vec())
+		.method(Method::PUT)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::CONFLICT);
+
+	let res = ctx
+		.k2v
+		.request
+		.builder(bucket.clone())
+		.path("root")
+		.query_param("sort_key", Some("cas"))
+		.signed_header("accept", "application/octet-stream")
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::OK);
+	let res_body = hyper::body::to_bytes(res.into_body())
+		.await
+		.unwrap()
+		.to_vec();
+	assert_eq!(res_body, b"second value".to_vec());
+}
+
+#[tokio::test]
+async fn test_item_cas_concurrent() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("test-k2v-item-cas-concurrent");
+
+	let res = ctx
+		.k2v
+		.request
+		.builder(bucket.clone())
+		.path("root")
+		.query_param("sort_key", Some("lock"))
+		.body(b"free".to_vec())
+		.method(Method::PUT)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::NO_CONTENT);
+
+	let res = read_item(&ctx, &bucket, "lock").await.unwrap();
+	assert_eq!(res.status(), StatusCode::OK);
+	let ct = res
+		.headers()
+		.get("x-garage-causality-token")
+		.unwrap()
+		.to_str()
+		.unwrap()
+		.to_string();
+
+	// Several clients try to take the lock at the same time,
+	// using the same causality token: only one of them succeeds
+	let mut req1 = ctx.k2v.request.builder(bucket.clone());
+	req1.path("root")
+		.query_param("sort_key", Some("lock"))
+		.query_param("causaltokenIs", Some(ct.clone()))
+		.body(b"owner 1".to_vec())
+		.method(Method::POST);
+	let mut req2 = ctx.k2v.request.builder(bucket.clone());
+	req2.path("root")
+		.query_param("sort_key", Some("lock"))
+		.query_param("causaltokenIs", Some(ct.clone()))
+		.body(b"owner 2".to_vec())
+		.method(Method::POST);
+	let (res1, res2) = tokio::join!(req1.send(), req2.send());
+	let (res1, res2) = (res1.unwrap(), res2.unwrap());
+
+	let mut statuses = vec![res1.status(), res2.status()];
+	statuses.sort();
+	assert_eq!(statuses, vec![StatusCode::NO_CONTENT, StatusCode::CONFLICT]);
+	let (winner, loser) = if res1.status() == StatusCode::NO_CONTENT {
+		(b"owner 1".to_vec(), res2)
+	} else {
+		(b"owner 2".to_vec(), res1)
+	};
+
+	// The failed attempt returns the causality token of the current value
+	let res = read_item(&ctx, &bucket, "lock").await.unwrap();
+	assert_eq!(res.status(), StatusCode::OK);
+	assert_eq!(
+		res.headers().get("x-garage-causality-token"),
+		loser.headers().get("x-garage-causality-token")
+	);
+	let res_body = hyper::body::to_bytes(res.into_body())
+		.await
+		.unwrap()
+		.to_vec();
+	assert_eq!(res_body, winner);
+}
+
+#[tokio::test]
+async fn test_item_ttl() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("test-k2v-item-ttl");
+
+	for (sk, ttl) in [("a", Some("1")), ("b", None), ("c", Some("3600"))] {
+		let mut req = ctx.k2v.request.builder(bucket.clone());
+		req.path("root")
+			.query_param("sort_key", Some(sk))
+			.body(format!("{}: value", sk).into_bytes())
+			.method(Method::PUT);
+		if let Some(ttl) = ttl {
+			req.signed_header("x-garage-ttl", ttl);
+		}
+		let res = req.send().await.unwrap();
+		assert_eq!(res.status(), StatusCode::NO_CONTENT);
+	}
+
+	// Invalid TTL values are rejected
+	let res = ctx
+		.k2v
+		.request
+		.builder(bucket.clone())
+		.path("root")
+		.query_param("sort_key", Some("d"))
+		.signed_header("x-garage-ttl", "soon")
+		.body(b"d: value".to_vec())
+		.method(Method::PUT)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+	let res = read_item(&ctx, &bucket, "a").await.unwrap();
+	assert_eq!(res.status(), StatusCode::OK);
+
+	tokio::time::sleep(Duration::from_millis(1500)).await;
+
+	// The expired item is no longer returned, even before its tombstone is written
+	let res = read_item(&ctx, &bucket, "a").await.unwrap();
+	assert_eq!(res.status(), StatusCode::NOT_FOUND);
+	let res = read_item(&ctx, &bucket, "b").await.unwrap();
+	assert_eq!(res.status(), StatusCode::OK);
+	let res = read_item(&ctx, &bucket, "c").await.unwrap();
+	assert_eq!(res.status(), StatusCode::OK);
+
+	let res = ctx
+		.k2v
+		.request
+		.builder(bucket.clone())
+		.query_param("search", Option::<&str>::None)
+		.body(
+			br#"[
+	{"partitionKey": "root"},
+	{"partitionKey": "root", "start": "a", "singleItem": true}
+		]"#
+			.to_vec(),
+		)
+		.method(Method::POST)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::OK);
+	let json_res = json_body(res).await;
+	let sort_keys = |i: usize| {
+		json_res[i]["items"]
+			.as_array()
+			.unwrap()
+			.iter()
+			.map(|x| x["sk"].as_str().unwrap().to_string())
+			.collect::<Vec<_>>()
+	};
+	assert_eq!(sort_keys(0), vec!["b", "c"]);
+	assert!(sort_keys(1).is_empty());
+
+	// Writing a value without a TTL makes the item persistent again
+	let res = ctx
+		.k2v
+		.request
+		.builder(bucket.clone())
+		.path("root")
+		.query_param("sort_key", Some("a"))
+		.body(b"a: persistent value".to_vec())
+		.method(Method::PUT)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::NO_CONTENT);
+
+	tokio::time::sleep(Duration::from_millis(100)).await;
+	let res = read_item(&ctx, &bucket, "a").await.unwrap();
+	assert_eq!(res.status(), StatusCode::OK);
+	let res_body = hyper::body::to_bytes(res.into_body())
+		.await
+		.unwrap()
+		.to_vec();
+	assert_eq!(res_body, b"a: persistent value".to_vec());
+}
+
+async fn read_item(ctx: &common::Context, bucket: &str, sk: &str) -> hyper::Result<Response<Body>> {
+	ctx.k2v
+		.request
+		.builder(bucket.to_string())
+		.path("root")
+		.query_param("sort_key", Some(sk))
+		.signed_header("accept", "application/octet-stream")
+		.send()
+		.await
+}
+
+#[tokio::test]
+async fn test_item_cas_concurrent_cluster() {
+	let cluster = common::garage::Cluster::new(0, 3);
+	let first = &cluster.nodes[0];
+	let key = &first.default_key;
+	let bucket = "test-k2v-item-cas-cluster".to_string();
+
+	first
+		.command()
+		.args(["bucket", "create", &bucket])
+		.quiet()
+		.expect_success_status("Could not create bucket");
+	first
+		.command()
+		.args(["bucket", "allow"])
+		.args(["--read", "--write"])
+		.arg(&bucket)
+		.args(["--key", &key.id])
+		.quiet()
+		.expect_success_status("Could not allow key for bucket");
+
+	let requesters = cluster
+		.nodes
+		.iter()
+		.map(|node| common::custom_requester::CustomRequester::new_k2v(node, key))
+		.collect::<Vec<_>>();
+
+	for round in 0..10 {
+		let sk = format!("lock{}", round);
+
+		let res = requesters[0]
+			.builder(bucket.clone())
+			.path("root")
+			.query_param("sort_key", Some(&sk))
+			.body(b"free".to_vec())
+			.method(Method::PUT)
+			.send()
+			.await
+			.unwrap();
+		assert_eq!(res.status(), StatusCode::NO_CONTENT);
+
+		let res = requesters[0]
+			.builder(bucket.clone())
+			.path("root")
+			.query_param("sort_key", Some(&sk))
+			.signed_header("accept", "application/octet-stream")
+			.send()
+			.await
+			.unwrap();
+		assert_eq!(res.status(), StatusCode::OK);
+		let ct = res
+			.headers()
+			.get("x-garage-causality-token")
+			.unwrap()
+			.to_str()
+			.unwrap()
+			.to_string();
+
+		// Clients connected to different nodes try to take the lock
+		// at the same time: only one of them succeeds
+		let mut reqs = requesters
+			.iter()
+			.enumerate()
+			.map(|(i, requester)| {
+				let mut req = requester.builder(bucket.clone());
+				req.path("root")
+					.query_param("sort_key", Some(&sk))
+					.query_param("causaltokenIs", Some(&ct))
+					.body(format!("owner {}", i).into_bytes())
+					.method(Method::POST);
+				req
+			})
+			.collect::<Vec<_>>();
+		let results = futures::future::join_all(reqs.iter_mut().map(|req| req.send())).await;
+
+		let mut statuses = results
+			.into_iter()
+			.map(|res| res.unwrap().status())
+			.collect::<Vec<_>>();
+		statuses.sort();
+		assert_eq!(
+			statuses,
+			vec![
+				StatusCode::NO_CONTENT,
+				StatusCode::CONFLICT,
+				StatusCode::CONFLICT
+			]
+		);
+	}
+}
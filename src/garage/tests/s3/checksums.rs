@@ -0,0 +1,374 @@
+use crate::common;
+use aws_sdk_s3::types::{ChecksumAlgorithm, CompletedMultipartUpload, CompletedPart};
+use base64::prelude::*;
+use garage_util::data::sha256sum;
+use hyper::body::to_bytes;
+use hyper::{Method, StatusCode};
+
+const BODY: &[u8] = b"hello world";
+/// Base64 of the MD5 of BODY
+const BODY_MD5: &str = "XrY7u+Ae7tCTyyK7j1rNww==";
+/// Base64 of the CRC32 of BODY
+const BODY_CRC32: &str = "DUoRhQ==";
+/// Checksum of an object made of two parts whose content is BODY,
+/// with the CRC32 of the concatenation of the CRC32 of its parts
+const TWO_PARTS_CRC32: &str = "7ryKJw==-2";
+
+async fn put_with_header(
+	ctx: &common::Context,
+	bucket: &str,
+	path: &str,
+	headers: &[(&str, &str)],
+) -> (StatusCode, String) {
+	let mut builder = ctx.custom_request.builder(bucket.to_string());
+	builder.method(Method::PUT).path(path).body(BODY.to_vec());
+	for (name, value) in headers {
+		builder.unsigned_header(name, value);
+	}
+	let res = builder.send().await.unwrap();
+	let status = res.status();
+	let body = to_bytes(res.into_body()).await.unwrap();
+	(status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+#[tokio::test]
+async fn test_put_object_checksums() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("put-object-checksums");
+	let sha256 = BASE64_STANDARD.encode(sha256sum(BODY));
+	let bad_sha256 = BASE64_STANDARD.encode(sha256sum(b"something else"));
+
+	for headers in [
+		&[("content-md5", BODY_MD5)][..],
+		&[("x-amz-checksum-sha256", sha256.as_str())][..],
+		&[("x-amz-checksum-crc32", BODY_CRC32)][..],
+		&[
+			("x-amz-sdk-checksum-algorithm", "CRC32"),
+			("x-amz-checksum-crc32", BODY_CRC32),
+		][..],
+	] {
+		let (status, _) = put_with_header(&ctx, &bucket, "good", headers).await;
+		assert_eq!(status, StatusCode::OK, "{:?}", headers);
+	}
+
+	let (status, body) = put_with_header(
+		&ctx,
+		&bucket,
+		"bad-md5",
+		&[("content-md5", "AAAAAAAAAAAAAAAAAAAAAA==")],
+	)
+	.await;
+	assert_eq!(status, StatusCode::BAD_REQUEST);
+	assert!(body.contains("<Code>BadDigest</Code>"));
+
+	let (status, body) = put_with_header(
+		&ctx,
+		&bucket,
+		"bad-sha256",
+		&[("x-amz-checksum-sha256", bad_sha256.as_str())],
+	)
+	.await;
+	assert_eq!(status, StatusCode::BAD_REQUEST);
+	assert!(body.contains("<Code>InvalidChecksum</Code>"));
+
+	let (status, body) = put_with_header(
+		&ctx,
+		&bucket,
+		"bad-crc32",
+		&[
+			("x-amz-sdk-checksum-algorithm", "CRC32"),
+			("x-amz-checksum-crc32", "AAAAAA=="),
+		],
+	)
+	.await;
+	assert_eq!(status, StatusCode::BAD_REQUEST);
+	assert!(body.contains("<Code>InvalidChecksum</Code>"));
+
+	// Objects whose checksum did not match were not stored
+	for key in ["bad-md5", "bad-sha256", "bad-crc32"] {
+		assert!(ctx
+			.client
+			.head_object()
+			.bucket(&bucket)
+			.key(key)
+			.send()
+			.await
+			.is_err());
+	}
+}
+
+#[tokio::test]
+async fn test_upload_part_checksums() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("upload-part-checksums");
+	let bad_sha256 = BASE64_STANDARD.encode(sha256sum(b"something else"));
+
+	let upload = ctx
+		.client
+		.create_multipart_upload()
+		.bucket(&bucket)
+		.key("obj")
+		.send()
+		.await
+		.unwrap();
+	let upload_id = upload.upload_id.unwrap();
+
+	let put_part = |part_number: &'static str, header: (&'static str, String)| {
+		let ctx = &ctx;
+		let bucket = bucket.clone();
+		let upload_id = upload_id.clone();
+		async move {
+			ctx.custom_request
+				.builder(bucket)
+				.method(Method::PUT)
+				.path("obj")
+				.query_param("partNumber", Some(part_number))
+				.query_param("uploadId", Some(upload_id))
+				.unsigned_header(header.0, header.1)
+				.body(BODY.to_vec())
+				.send()
+				.await
+				.unwrap()
+				.status()
+		}
+	};
+
+	assert_eq!(
+		put_part("1", ("content-md5", BODY_MD5.into())).await,
+		StatusCode::OK
+	);
+	assert_eq!(
+		put_part("2", ("content-md5", "AAAAAAAAAAAAAAAAAAAAAA==".into())).await,
+		StatusCode::BAD_REQUEST
+	);
+	assert_eq!(
+		put_part("3", ("x-amz-checksum-sha256", bad_sha256)).await,
+		StatusCode::BAD_REQUEST
+	);
+
+	let parts = ctx
+		.client
+		.list_parts()
+		.bucket(&bucket)
+		.key("obj")
+		.upload_id(&upload_id)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(parts.parts.unwrap_or_default().len(), 1);
+}
+
+#[tokio::test]
+async fn test_stored_object_checksum() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("stored-object-checksum");
+
+	let res = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::PUT)
+		.path("obj")
+		.unsigned_header("x-amz-checksum-crc32", BODY_CRC32)
+		.body(BODY.to_vec())
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::OK);
+	assert_eq!(res.headers()["x-amz-checksum-crc32"], BODY_CRC32);
+
+	// The checksum is only returned when it is asked for
+	for (mode, expected) in [(None, None), (Some("ENABLED"), Some(BODY_CRC32))] {
+		let mut builder = ctx.custom_request.builder(bucket.clone());
+		builder.method(Method::HEAD).path("obj");
+		if let Some(mode) = mode {
+			builder.unsigned_header("x-amz-checksum-mode", mode);
+		}
+		let res = builder.send().await.unwrap();
+		assert_eq!(res.status(), StatusCode::OK);
+		assert_eq!(
+			res.headers()
+				.get("x-amz-checksum-crc32")
+				.map(|v| v.to_str().unwrap()),
+			expected
+		);
+	}
+
+	// The checksum computed with the algorithm asked by the client is stored too
+	let res = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::PUT)
+		.path("computed")
+		.unsigned_header("x-amz-sdk-checksum-algorithm", "CRC32")
+		.body(BODY.to_vec())
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::OK);
+	assert_eq!(res.headers()["x-amz-checksum-crc32"], BODY_CRC32);
+
+	let res = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::GET)
+		.path("computed")
+		.query_param("attributes", None::<String>)
+		.unsigned_header("x-amz-object-attributes", "Checksum,ObjectSize")
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::OK);
+	let body = String::from_utf8(to_bytes(res.into_body()).await.unwrap().to_vec()).unwrap();
+	assert!(body.contains(&format!(
+		"<Checksum><ChecksumCRC32>{}</ChecksumCRC32></Checksum>",
+		BODY_CRC32
+	)));
+	assert!(body.contains(&format!("<ObjectSize>{}</ObjectSize>", BODY.len())));
+	assert!(!body.contains("<ETag>"));
+}
+
+#[tokio::test]
+async fn test_multipart_upload_checksum() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("multipart-upload-checksum");
+
+	let upload = ctx
+		.client
+		.create_multipart_upload()
+		.bucket(&bucket)
+		.key("obj")
+		.checksum_algorithm(ChecksumAlgorithm::Crc32)
+		.send()
+		.await
+		.unwrap();
+	let upload_id = upload.upload_id.unwrap();
+
+	// The parts are checksummed with the algorithm of the upload,
+	// even when the client gives no checksum
+	let mut etags = vec![];
+	for part_number in ["1", "2"] {
+		let res = ctx
+			.custom_request
+			.builder(bucket.clone())
+			.method(Method::PUT)
+			.path("obj")
+			.query_param("partNumber", Some(part_number))
+			.query_param("uploadId", Some(&upload_id))
+			.body(BODY.to_vec())
+			.send()
+			.await
+			.unwrap();
+		assert_eq!(res.status(), StatusCode::OK);
+		assert_eq!(res.headers()["x-amz-checksum-crc32"], BODY_CRC32);
+		etags.push(res.headers()["etag"].to_str().unwrap().to_string());
+	}
+
+	let completed = |checksum: &str| {
+		let mut parts = CompletedMultipartUpload::builder();
+		for (i, etag) in etags.iter().enumerate() {
+			parts = parts.parts(
+				CompletedPart::builder()
+					.part_number(i as i32 + 1)
+					.e_tag(etag)
+					.checksum_crc32(checksum)
+					.build(),
+			);
+		}
+		parts.build()
+	};
+
+	// The checksums given for the parts must be those of the uploaded parts
+	assert!(ctx
+		.client
+		.complete_multipart_upload()
+		.bucket(&bucket)
+		.key("obj")
+		.upload_id(&upload_id)
+		.multipart_upload(completed("AAAAAA=="))
+		.send()
+		.await
+		.is_err());
+
+	let res = ctx
+		.client
+		.complete_multipart_upload()
+		.bucket(&bucket)
+		.key("obj")
+		.upload_id(&upload_id)
+		.multipart_upload(completed(BODY_CRC32))
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.checksum_crc32.as_deref(), Some(TWO_PARTS_CRC32));
+
+	let res = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::HEAD)
+		.path("obj")
+		.unsigned_header("x-amz-checksum-mode", "ENABLED")
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::OK);
+	assert_eq!(res.headers()["x-amz-checksum-crc32"], TWO_PARTS_CRC32);
+	// The algorithm of the upload is not kept in the headers of the object
+	assert!(res.headers().get("x-amz-checksum-algorithm").is_none());
+
+	let res = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::GET)
+		.path("obj")
+		.query_param("attributes", None::<String>)
+		.unsigned_header("x-amz-object-attributes", "ObjectParts")
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::OK);
+	let body = String::from_utf8(to_bytes(res.into_body()).await.unwrap().to_vec()).unwrap();
+	assert!(body.contains("<TotalPartsCount>2</TotalPartsCount>"));
+	for part_number in [1, 2] {
+		assert!(body.contains(&format!(
+			"<Part><PartNumber>{}</PartNumber><Size>{}</Size><ChecksumCRC32>{}</ChecksumCRC32></Part>",
+			part_number,
+			BODY.len(),
+			BODY_CRC32
+		)));
+	}
+
+	// The parts can be listed page by page
+	let res = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::GET)
+		.path("obj")
+		.query_param("attributes", None::<String>)
+		.unsigned_header("x-amz-object-attributes", "ObjectParts")
+		.unsigned_header("x-amz-max-parts", "1")
+		.send()
+		.await
+		.unwrap();
+	let body = String::from_utf8(to_bytes(res.into_body()).await.unwrap().to_vec()).unwrap();
+	assert!(body.contains("<NextPartNumberMarker>1</NextPartNumberMarker>"));
+	assert!(body.contains("<IsTruncated>true</IsTruncated>"));
+	assert!(body.contains("<PartNumber>1</PartNumber>"));
+	assert!(!body.contains("<PartNumber>2</PartNumber>"));
+
+	let res = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::GET)
+		.path("obj")
+		.query_param("attributes", None::<String>)
+		.unsigned_header("x-amz-object-attributes", "ObjectParts")
+		.unsigned_header("x-amz-max-parts", "1")
+		.unsigned_header("x-amz-part-number-marker", "1")
+		.send()
+		.await
+		.unwrap();
+	let body = String::from_utf8(to_bytes(res.into_body()).await.unwrap().to_vec()).unwrap();
+	assert!(body.contains("<IsTruncated>false</IsTruncated>"));
+	assert!(!body.contains("<PartNumber>1</PartNumber>"));
+	assert!(body.contains("<PartNumber>2</PartNumber>"));
+}
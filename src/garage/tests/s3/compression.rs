@@ -0,0 +1,98 @@
+use std::path::PathBuf;
+
+use crate::common;
+use crate::common::ext::CommandExt;
+
+use aws_sdk_s3::primitives::ByteStream;
+use garage_util::data::blake2sum;
+use hyper::Method;
+
+/// Path of the file of a block in the data directory of the test instance,
+/// without the extension of compressed blocks
+fn block_path(ctx: &common::Context, data: &[u8]) -> PathBuf {
+	let hash = blake2sum(data);
+	let mut path = ctx.garage.path.join("data");
+	path.push(hex::encode(&hash.as_slice()[0..1]));
+	path.push(hex::encode(&hash.as_slice()[1..2]));
+	path.push(hex::encode(hash.as_slice()));
+	path
+}
+
+#[tokio::test]
+async fn test_compression_level() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("compression-levels");
+
+	// Large enough not to be stored inline, small enough to fit in a single block
+	let compressed_data = b"compressed ".repeat(10_000);
+	let plain_data = b"uncompressed ".repeat(10_000);
+
+	ctx.garage
+		.command()
+		.args(["bucket", "set-compression-level"])
+		.args(["--bucket", &bucket])
+		.args(["--level", "19"])
+		.quiet()
+		.expect_success_status("Could not set bucket compression level");
+
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("compressed")
+		.body(ByteStream::from(compressed_data.clone()))
+		.send()
+		.await
+		.unwrap();
+	assert!(block_path(&ctx, &compressed_data)
+		.with_extension("zst")
+		.exists());
+
+	// Compression can be disabled for a single upload
+	let resp = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::PUT)
+		.path("plain")
+		.unsigned_header("x-garage-compression", "none")
+		.body(plain_data.clone())
+		.send()
+		.await
+		.unwrap();
+	assert!(resp.status().is_success());
+	assert!(block_path(&ctx, &plain_data).exists());
+
+	let resp = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::PUT)
+		.path("invalid")
+		.unsigned_header("x-garage-compression", "lz4")
+		.body(plain_data.clone())
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(resp.status(), 400);
+
+	// Blocks are read according to how they were stored,
+	// whatever the current compression level of the bucket
+	ctx.garage
+		.command()
+		.args(["bucket", "set-compression-level"])
+		.args(["--bucket", &bucket])
+		.args(["--level", "default"])
+		.quiet()
+		.expect_success_status("Could not reset bucket compression level");
+
+	for (key, data) in [("compressed", &compressed_data), ("plain", &plain_data)] {
+		let o = ctx
+			.client
+			.get_object()
+			.bucket(&bucket)
+			.key(key)
+			.send()
+			.await
+			.unwrap();
+		let body = o.body.collect().await.unwrap().into_bytes();
+		assert_eq!(&body[..], &data[..]);
+	}
+}
@@ -0,0 +1,105 @@
+use crate::common;
+use aws_sdk_s3::primitives::ByteStream;
+use hyper::{body::to_bytes, Method, StatusCode};
+
+const BODY: &[u8; 62] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+#[tokio::test]
+async fn test_strong_consistency_read() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("strong-consistency");
+
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("obj")
+		.body(ByteStream::from_static(BODY))
+		.send()
+		.await
+		.unwrap();
+
+	for consistency in ["strong", "eventual"] {
+		let resp = ctx
+			.custom_request
+			.builder(bucket.clone())
+			.method(Method::GET)
+			.path("obj".to_owned())
+			.unsigned_header("x-garage-consistency", consistency)
+			.send()
+			.await
+			.unwrap();
+		assert_eq!(resp.status(), StatusCode::OK);
+		let body = to_bytes(resp.into_body()).await.unwrap();
+		assert_eq!(&body[..], &BODY[..]);
+
+		let resp = ctx
+			.custom_request
+			.builder(bucket.clone())
+			.method(Method::HEAD)
+			.path("obj".to_owned())
+			.unsigned_header("x-garage-consistency", consistency)
+			.send()
+			.await
+			.unwrap();
+		assert_eq!(resp.status(), StatusCode::OK);
+
+		let resp = ctx
+			.custom_request
+			.builder(bucket.clone())
+			.method(Method::GET)
+			.path("missing".to_owned())
+			.unsigned_header("x-garage-consistency", consistency)
+			.send()
+			.await
+			.unwrap();
+		assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+	}
+
+	// Objects stored in several blocks also read their version with a quorum
+	let big = vec![42u8; 5 * 1024 * 1024 / 2];
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("big")
+		.body(ByteStream::from(big.clone()))
+		.send()
+		.await
+		.unwrap();
+	let resp = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::GET)
+		.path("big".to_owned())
+		.unsigned_header("x-garage-consistency", "strong")
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body = to_bytes(resp.into_body()).await.unwrap();
+	assert_eq!(&body[..], &big[..]);
+
+	let resp = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::GET)
+		.path("big".to_owned())
+		.unsigned_header("x-garage-consistency", "strong")
+		.unsigned_header("range", "bytes=1048570-1048580")
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+	let body = to_bytes(resp.into_body()).await.unwrap();
+	assert_eq!(&body[..], &big[1048570..=1048580]);
+
+	let resp = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::GET)
+		.path("obj".to_owned())
+		.unsigned_header("x-garage-consistency", "linearizable")
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
@@ -0,0 +1,211 @@
+use crate::common;
+use crate::common::ext::CommandExt;
+
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
+use garage_util::data::blake2sum;
+use hyper::{body::to_bytes, Body, Client, Request};
+
+/// Number of blocks that were not sent to storage nodes as they already had them
+async fn deduplicated_blocks(ctx: &common::Context) -> u64 {
+	let req = Request::builder()
+		.method("GET")
+		.uri(format!(
+			"http://127.0.0.1:{}/metrics",
+			ctx.garage.admin_port
+		))
+		.body(Body::empty())
+		.unwrap();
+	let resp = Client::new().request(req).await.unwrap();
+	let body = to_bytes(resp.into_body()).await.unwrap();
+	String::from_utf8(body.to_vec())
+		.unwrap()
+		.lines()
+		.find(|l| l.starts_with("block_deduplicated_blocks"))
+		.and_then(|l| l.split_whitespace().last())
+		.map(|v| v.parse().unwrap())
+		.unwrap_or(0)
+}
+
+fn block_refcount(ctx: &common::Context, data: &[u8]) -> u64 {
+	let output = ctx
+		.garage
+		.command()
+		.args(["block", "info", &hex::encode(blake2sum(data))])
+		.expect_success_output("Could not get block info");
+	String::from_utf8(output.stdout)
+		.unwrap()
+		.lines()
+		.find_map(|l| l.strip_prefix("Refcount: "))
+		.map(|v| v.parse().unwrap())
+		.unwrap()
+}
+
+#[tokio::test]
+async fn test_block_deduplication() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("deduplication");
+
+	// Large enough not to be stored inline, small enough to fit in a single block
+	let data = b"deduplicated ".repeat(10_000);
+
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("original")
+		.body(ByteStream::from(data.clone()))
+		.send()
+		.await
+		.unwrap();
+	let deduplicated_before = deduplicated_blocks(&ctx).await;
+
+	// The same content is uploaded with PutObject and UploadPart:
+	// the block is referenced by each of the three objects, but not sent again
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("copy")
+		.body(ByteStream::from(data.clone()))
+		.send()
+		.await
+		.unwrap();
+
+	let up = ctx
+		.client
+		.create_multipart_upload()
+		.bucket(&bucket)
+		.key("multipart")
+		.send()
+		.await
+		.unwrap();
+	let uid = up.upload_id.as_ref().unwrap();
+	let part = ctx
+		.client
+		.upload_part()
+		.bucket(&bucket)
+		.key("multipart")
+		.upload_id(uid)
+		.part_number(1)
+		.body(ByteStream::from(data.clone()))
+		.send()
+		.await
+		.unwrap();
+	ctx.client
+		.complete_multipart_upload()
+		.bucket(&bucket)
+		.key("multipart")
+		.upload_id(uid)
+		.multipart_upload(
+			CompletedMultipartUpload::builder()
+				.parts(
+					CompletedPart::builder()
+						.part_number(1)
+						.e_tag(part.e_tag.unwrap())
+						.build(),
+				)
+				.build(),
+		)
+		.send()
+		.await
+		.unwrap();
+
+	assert!(deduplicated_blocks(&ctx).await >= deduplicated_before + 2);
+	assert_eq!(block_refcount(&ctx, &data), 3);
+
+	// Deleting one of the objects does not affect the others
+	ctx.client
+		.delete_object()
+		.bucket(&bucket)
+		.key("original")
+		.send()
+		.await
+		.unwrap();
+
+	for key in ["copy", "multipart"] {
+		let o = ctx
+			.client
+			.get_object()
+			.bucket(&bucket)
+			.key(key)
+			.send()
+			.await
+			.unwrap();
+		let body = o.body.collect().await.unwrap().into_bytes();
+		assert_eq!(&body[..], &data[..]);
+	}
+}
+
+/// Wait for the reference counter of a block to reach the expected value,
+/// as block references are updated in the background after an object is deleted
+async fn wait_block_refcount(ctx: &common::Context, data: &[u8], expected: u64) {
+	for _ in 0..50 {
+		if block_refcount(ctx, data) == expected {
+			return;
+		}
+		tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+	}
+	assert_eq!(block_refcount(ctx, data), expected);
+}
+
+fn block_stored(ctx: &common::Context, data: &[u8]) -> bool {
+	let hash = blake2sum(data);
+	let mut path = ctx.garage.path.join("data");
+	path.push(hex::encode(&hash.as_slice()[0..1]));
+	path.push(hex::encode(&hash.as_slice()[1..2]));
+	path.push(hex::encode(hash.as_slice()));
+	path.exists() || path.with_extension("zst").exists()
+}
+
+#[tokio::test]
+async fn test_block_deletion_is_deferred() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("deferred-block-deletion");
+
+	let data = b"shared block ".repeat(10_000);
+
+	for key in ["first", "second"] {
+		ctx.client
+			.put_object()
+			.bucket(&bucket)
+			.key(key)
+			.body(ByteStream::from(data.clone()))
+			.send()
+			.await
+			.unwrap();
+	}
+	assert_eq!(block_refcount(&ctx, &data), 2);
+
+	// The block is still referenced by the second object and must be kept
+	ctx.client
+		.delete_object()
+		.bucket(&bucket)
+		.key("first")
+		.send()
+		.await
+		.unwrap();
+	wait_block_refcount(&ctx, &data, 1).await;
+	assert!(block_stored(&ctx, &data));
+
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key("second")
+		.send()
+		.await
+		.unwrap();
+	let body = o.body.collect().await.unwrap().into_bytes();
+	assert_eq!(&body[..], &data[..]);
+
+	// Once no object references the block anymore, it is only removed
+	// from disk by the resync worker after the block GC delay
+	ctx.client
+		.delete_object()
+		.bucket(&bucket)
+		.key("second")
+		.send()
+		.await
+		.unwrap();
+	wait_block_refcount(&ctx, &data, 0).await;
+	assert!(block_stored(&ctx, &data));
+}
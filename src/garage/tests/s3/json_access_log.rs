@@ -0,0 +1,54 @@
+use crate::common;
+use aws_sdk_s3::primitives::ByteStream;
+
+fn find_log_line(ctx: &common::Context, key: &str) -> Option<serde_json::Value> {
+	let logs = std::fs::read_to_string(ctx.garage.path.join("access.log")).unwrap();
+	logs.lines()
+		.map(|l| serde_json::from_str::<serde_json::Value>(l).unwrap())
+		.find(|l| l["key"] == key)
+}
+
+#[tokio::test]
+async fn test_json_access_log() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("json-access-log");
+
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("json-logged-object")
+		.body(ByteStream::from_static(b"hello world"))
+		.send()
+		.await
+		.unwrap();
+	ctx.client
+		.get_object()
+		.bucket(&bucket)
+		.key("json-logged-missing")
+		.send()
+		.await
+		.unwrap_err();
+
+	// Log lines are written in the background
+	tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+
+	let put = find_log_line(&ctx, "json-logged-object").expect("Missing access log");
+	assert_eq!(put["log_version"], 1);
+	assert_eq!(put["method"], "PUT");
+	assert_eq!(put["operation"], "PutObject");
+	assert_eq!(put["bucket"], bucket.as_str());
+	assert_eq!(put["access_key_id"], ctx.key.id.as_str());
+	assert_eq!(put["status_code"], 200);
+	assert_eq!(put["error_code"], serde_json::Value::Null);
+	assert_eq!(put["bytes_in"], 11);
+	assert_eq!(put["remote_ip"], "127.0.0.1");
+	assert_eq!(put["request_id"].as_str().unwrap().len(), 32);
+	assert!(put["elapsed_ms"].is_u64());
+	assert!(put["user_agent"].is_string());
+
+	let get = find_log_line(&ctx, "json-logged-missing").expect("Missing access log");
+	assert_eq!(get["operation"], "GetObject");
+	assert_eq!(get["status_code"], 404);
+	assert_eq!(get["error_code"], "NoSuchKey");
+	assert_ne!(get["request_id"], put["request_id"]);
+}
@@ -0,0 +1,165 @@
+use crate::common;
+
+use aws_sdk_s3::types::{
+	AbortIncompleteMultipartUpload, BucketLifecycleConfiguration, ExpirationStatus,
+	LifecycleExpiration, LifecycleRule, LifecycleRuleAndOperator, LifecycleRuleFilter, Transition,
+	TransitionStorageClass,
+};
+
+#[tokio::test]
+async fn test_lifecycle() {
+	const BCKT_NAME: &str = "my-lifecycle";
+	let ctx = common::context();
+	let bucket = ctx.create_bucket(BCKT_NAME);
+
+	// No lifecycle configuration at first
+	ctx.client
+		.get_bucket_lifecycle_configuration()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap_err();
+
+	let lifecycle = BucketLifecycleConfiguration::builder()
+		.rules(
+			LifecycleRule::builder()
+				.id("expire-logs")
+				.status(ExpirationStatus::Enabled)
+				.filter(LifecycleRuleFilter::Prefix("logs/".into()))
+				.expiration(LifecycleExpiration::builder().days(30).build())
+				.build(),
+		)
+		.rules(
+			LifecycleRule::builder()
+				.id("abort-mpu")
+				.status(ExpirationStatus::Disabled)
+				.filter(LifecycleRuleFilter::And(
+					LifecycleRuleAndOperator::builder()
+						.prefix("big/")
+						.object_size_greater_than(1000)
+						.build(),
+				))
+				.expiration(LifecycleExpiration::builder().days(7).build())
+				.abort_incomplete_multipart_upload(
+					AbortIncompleteMultipartUpload::builder()
+						.days_after_initiation(2)
+						.build(),
+				)
+				.build(),
+		)
+		.build();
+
+	// Size filters cannot be used to abort multipart uploads
+	ctx.client
+		.put_bucket_lifecycle_configuration()
+		.bucket(&bucket)
+		.lifecycle_configuration(lifecycle.clone())
+		.send()
+		.await
+		.unwrap_err();
+
+	let lifecycle = BucketLifecycleConfiguration::builder()
+		.rules(lifecycle.rules().unwrap()[0].clone())
+		.rules(
+			LifecycleRule::builder()
+				.id("abort-mpu")
+				.status(ExpirationStatus::Disabled)
+				.filter(LifecycleRuleFilter::Prefix("big/".into()))
+				.abort_incomplete_multipart_upload(
+					AbortIncompleteMultipartUpload::builder()
+						.days_after_initiation(2)
+						.build(),
+				)
+				.build(),
+		)
+		.build();
+
+	ctx.client
+		.put_bucket_lifecycle_configuration()
+		.bucket(&bucket)
+		.lifecycle_configuration(lifecycle)
+		.send()
+		.await
+		.unwrap();
+
+	{
+		let res = ctx
+			.client
+			.get_bucket_lifecycle_configuration()
+			.bucket(&bucket)
+			.send()
+			.await
+			.unwrap();
+		let rules = res.rules().unwrap();
+		assert_eq!(rules.len(), 2);
+
+		assert_eq!(rules[0].id().unwrap(), "expire-logs");
+		assert_eq!(rules[0].status().unwrap(), &ExpirationStatus::Enabled);
+		assert_eq!(
+			rules[0].filter().unwrap(),
+			&LifecycleRuleFilter::Prefix("logs/".into())
+		);
+		assert_eq!(rules[0].expiration().unwrap().days(), 30);
+		assert!(rules[0].abort_incomplete_multipart_upload().is_none());
+
+		assert_eq!(rules[1].id().unwrap(), "abort-mpu");
+		assert_eq!(rules[1].status().unwrap(), &ExpirationStatus::Disabled);
+		assert!(rules[1].expiration().is_none());
+		assert_eq!(
+			rules[1]
+				.abort_incomplete_multipart_upload()
+				.unwrap()
+				.days_after_initiation(),
+			2
+		);
+	}
+
+	// Transitions are not supported
+	let lifecycle = BucketLifecycleConfiguration::builder()
+		.rules(
+			LifecycleRule::builder()
+				.status(ExpirationStatus::Enabled)
+				.filter(LifecycleRuleFilter::Prefix("old/".into()))
+				.transitions(
+					Transition::builder()
+						.days(30)
+						.storage_class(TransitionStorageClass::Glacier)
+						.build(),
+				)
+				.build(),
+		)
+		.build();
+	ctx.client
+		.put_bucket_lifecycle_configuration()
+		.bucket(&bucket)
+		.lifecycle_configuration(lifecycle)
+		.send()
+		.await
+		.unwrap_err();
+
+	// The previous configuration is kept
+	{
+		let res = ctx
+			.client
+			.get_bucket_lifecycle_configuration()
+			.bucket(&bucket)
+			.send()
+			.await
+			.unwrap();
+		assert_eq!(res.rules().unwrap().len(), 2);
+	}
+
+	ctx.client
+		.delete_bucket_lifecycle()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap();
+
+	ctx.client
+		.get_bucket_lifecycle_configuration()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap_err();
+}
@@ -0,0 +1,140 @@
+use std::time::Duration;
+
+use crate::common;
+
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{BucketLoggingStatus, LoggingEnabled};
+
+#[tokio::test]
+async fn test_bucket_logging() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("my-logged-bucket");
+	let target = ctx.create_bucket("my-access-logs");
+
+	// Logging is disabled at first
+	let r = ctx
+		.client
+		.get_bucket_logging()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap();
+	assert!(r.logging_enabled().is_none());
+
+	// The target bucket must exist
+	ctx.client
+		.put_bucket_logging()
+		.bucket(&bucket)
+		.bucket_logging_status(
+			BucketLoggingStatus::builder()
+				.logging_enabled(
+					LoggingEnabled::builder()
+						.target_bucket("my-missing-bucket")
+						.target_prefix("logs/")
+						.build(),
+				)
+				.build(),
+		)
+		.send()
+		.await
+		.unwrap_err();
+
+	ctx.client
+		.put_bucket_logging()
+		.bucket(&bucket)
+		.bucket_logging_status(
+			BucketLoggingStatus::builder()
+				.logging_enabled(
+					LoggingEnabled::builder()
+						.target_bucket(&target)
+						.target_prefix("logs/")
+						.build(),
+				)
+				.build(),
+		)
+		.send()
+		.await
+		.unwrap();
+
+	let r = ctx
+		.client
+		.get_bucket_logging()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap();
+	let enabled = r.logging_enabled().unwrap();
+	assert_eq!(enabled.target_bucket(), Some(target.as_str()));
+	assert_eq!(enabled.target_prefix(), Some("logs/"));
+
+	// Requests to the bucket are logged in the target bucket
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("hello")
+		.body(ByteStream::from_static(b"hello world"))
+		.send()
+		.await
+		.unwrap();
+	ctx.client
+		.get_object()
+		.bucket(&bucket)
+		.key("hello")
+		.send()
+		.await
+		.unwrap();
+
+	let mut log_keys = vec![];
+	for _ in 0..20 {
+		tokio::time::sleep(Duration::from_millis(500)).await;
+		let r = ctx
+			.client
+			.list_objects_v2()
+			.bucket(&target)
+			.prefix("logs/")
+			.send()
+			.await
+			.unwrap();
+		log_keys = r
+			.contents()
+			.unwrap_or_default()
+			.iter()
+			.map(|o| o.key().unwrap().to_string())
+			.collect::<Vec<_>>();
+		if !log_keys.is_empty() {
+			break;
+		}
+	}
+	assert!(!log_keys.is_empty());
+	assert!(log_keys[0].ends_with(".gz"));
+
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&target)
+		.key(&log_keys[0])
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(o.content_type(), Some("application/gzip"));
+	let data = o.body.collect().await.unwrap().into_bytes();
+	assert_eq!(&data[..2], &[0x1f, 0x8b]);
+
+	// An empty BucketLoggingStatus disables logging
+	ctx.client
+		.put_bucket_logging()
+		.bucket(&bucket)
+		.bucket_logging_status(BucketLoggingStatus::builder().build())
+		.send()
+		.await
+		.unwrap();
+
+	let r = ctx
+		.client
+		.get_bucket_logging()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap();
+	assert!(r.logging_enabled().is_none());
+}
@@ -1,6 +1,19 @@
+mod checksums;
+mod compression;
+mod consistency;
+mod deduplication;
+mod json_access_log;
+mod lifecycle;
 mod list;
+mod logging;
 mod multipart;
+mod notification;
+mod object_lock;
 mod objects;
+mod permissions;
+mod replication;
+mod select;
 mod simple;
 mod streaming_signature;
+mod versioning;
 mod website;
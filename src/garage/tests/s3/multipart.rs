@@ -204,6 +204,116 @@ async fn test_uploadlistpart() {
 	}
 }
 
+#[tokio::test]
+async fn test_multipart_sse_c() {
+	// A SSE-C key made of 32 bytes 0x07, and its MD5, both encoded in base64
+	const SSEC_KEY: &str = "BwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwc=";
+	const SSEC_KEY_MD5: &str = "y4HAEFCYWuvAXWFTtA1Qpg==";
+
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("multipart-sse-c");
+
+	let u1 = vec![0x11; SZ_5MB];
+	let u2 = vec![0x22; SZ_5MB];
+
+	let up = ctx
+		.client
+		.create_multipart_upload()
+		.bucket(&bucket)
+		.key("a")
+		.sse_customer_algorithm("AES256")
+		.sse_customer_key(SSEC_KEY)
+		.sse_customer_key_md5(SSEC_KEY_MD5)
+		.send()
+		.await
+		.unwrap();
+	let uid = up.upload_id.as_ref().unwrap();
+	assert_eq!(up.sse_customer_key_md5.as_deref(), Some(SSEC_KEY_MD5));
+
+	// The parts must be encrypted with the key of the upload
+	assert!(ctx
+		.client
+		.upload_part()
+		.bucket(&bucket)
+		.key("a")
+		.upload_id(uid)
+		.part_number(1)
+		.body(ByteStream::from(u1.clone()))
+		.send()
+		.await
+		.is_err());
+
+	let mut etags = vec![];
+	for (i, data) in vec![u1, u2].into_iter().enumerate() {
+		let p = ctx
+			.client
+			.upload_part()
+			.bucket(&bucket)
+			.key("a")
+			.upload_id(uid)
+			.part_number(i as i32 + 1)
+			.sse_customer_algorithm("AES256")
+			.sse_customer_key(SSEC_KEY)
+			.sse_customer_key_md5(SSEC_KEY_MD5)
+			.body(ByteStream::from(data))
+			.send()
+			.await
+			.unwrap();
+		etags.push(p.e_tag.unwrap());
+	}
+
+	let cmp = CompletedMultipartUpload::builder()
+		.parts(
+			CompletedPart::builder()
+				.part_number(1)
+				.e_tag(&etags[0])
+				.build(),
+		)
+		.parts(
+			CompletedPart::builder()
+				.part_number(2)
+				.e_tag(&etags[1])
+				.build(),
+		)
+		.build();
+
+	ctx.client
+		.complete_multipart_upload()
+		.bucket(&bucket)
+		.key("a")
+		.upload_id(uid)
+		.multipart_upload(cmp)
+		.send()
+		.await
+		.unwrap();
+
+	// Read a range that spans both parts
+	let r = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key("a")
+		.range(format!("bytes={}-{}", SZ_5MB - 10, SZ_5MB + 9))
+		.sse_customer_algorithm("AES256")
+		.sse_customer_key(SSEC_KEY)
+		.sse_customer_key_md5(SSEC_KEY_MD5)
+		.send()
+		.await
+		.unwrap();
+	let mut expected = vec![0x11; 10];
+	expected.extend_from_slice(&[0x22; 10]);
+	assert_bytes_eq!(r.body, &expected[..]);
+
+	assert!(ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key("a")
+		.send()
+		.await
+		.is_err());
+}
+
 #[tokio::test]
 async fn test_uploadpartcopy() {
 	let ctx = common::context();
@@ -413,3 +523,116 @@ async fn test_uploadpartcopy() {
 	assert_eq!(real_obj.len(), exp_obj.len());
 	assert_eq!(real_obj, exp_obj);
 }
+
+#[tokio::test]
+async fn test_uploadpartcopy_whole_object() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("uploadpartcopy-whole");
+	let dest_bucket = ctx.create_bucket("uploadpartcopy-whole-dest");
+
+	let u1 = (0..SZ_5MB + 1234)
+		.map(|i| (i % 251) as u8)
+		.collect::<Vec<_>>();
+	let u2 = vec![0x22; SZ_5MB];
+
+	let source = ctx
+		.client
+		.put_object()
+		.bucket(&bucket)
+		.key("source")
+		.body(ByteStream::from(u1.clone()))
+		.send()
+		.await
+		.unwrap();
+
+	let up = ctx
+		.client
+		.create_multipart_upload()
+		.bucket(&dest_bucket)
+		.key("target")
+		.send()
+		.await
+		.unwrap();
+	let uid = up.upload_id.as_ref().unwrap();
+
+	// Without a range, the whole source object is copied across buckets,
+	// and the ETag of the part is that of the source object
+	let p1 = ctx
+		.client
+		.upload_part_copy()
+		.bucket(&dest_bucket)
+		.key("target")
+		.upload_id(uid)
+		.part_number(1)
+		.copy_source(format!("{}/source", bucket))
+		.send()
+		.await
+		.unwrap();
+	let p1_etag = p1.copy_part_result.unwrap().e_tag.unwrap();
+	assert_eq!(Some(&p1_etag), source.e_tag.as_ref());
+
+	let p2 = ctx
+		.client
+		.upload_part()
+		.bucket(&dest_bucket)
+		.key("target")
+		.upload_id(uid)
+		.part_number(2)
+		.body(ByteStream::from(u2.clone()))
+		.send()
+		.await
+		.unwrap();
+
+	let cmp = CompletedMultipartUpload::builder()
+		.parts(
+			CompletedPart::builder()
+				.part_number(1)
+				.e_tag(p1_etag)
+				.build(),
+		)
+		.parts(
+			CompletedPart::builder()
+				.part_number(2)
+				.e_tag(p2.e_tag.unwrap())
+				.build(),
+		)
+		.build();
+	ctx.client
+		.complete_multipart_upload()
+		.bucket(&dest_bucket)
+		.key("target")
+		.upload_id(uid)
+		.multipart_upload(cmp)
+		.send()
+		.await
+		.unwrap();
+
+	// The source can be deleted, the copy still references its blocks
+	ctx.client
+		.delete_object()
+		.bucket(&bucket)
+		.key("source")
+		.send()
+		.await
+		.unwrap();
+
+	let obj = ctx
+		.client
+		.get_object()
+		.bucket(&dest_bucket)
+		.key("target")
+		.send()
+		.await
+		.unwrap();
+	let real_obj = obj
+		.body
+		.collect()
+		.await
+		.expect("Error reading data")
+		.into_bytes();
+
+	let mut exp_obj = u1;
+	exp_obj.extend(&u2);
+	assert_eq!(real_obj.len(), exp_obj.len());
+	assert_eq!(real_obj, exp_obj);
+}
@@ -0,0 +1,186 @@
+use std::time::Duration;
+
+use crate::common;
+
+use aws_sdk_s3::types::{
+	Event, FilterRule, FilterRuleName, NotificationConfiguration, NotificationConfigurationFilter,
+	S3KeyFilter, TopicConfiguration,
+};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+
+/// Start a minimal HTTP webhook on localhost, that forwards
+/// the bodies of the requests it receives on a channel
+async fn start_webhook() -> (String, mpsc::UnboundedReceiver<serde_json::Value>) {
+	let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+	let url = format!("http://{}/hook", listener.local_addr().unwrap());
+	let (tx, rx) = mpsc::unbounded_channel();
+
+	tokio::spawn(async move {
+		loop {
+			let (socket, _) = listener.accept().await.unwrap();
+			let tx = tx.clone();
+			tokio::spawn(async move {
+				let mut reader = BufReader::new(socket);
+				loop {
+					let mut content_length = 0;
+					let mut line = String::new();
+					loop {
+						line.clear();
+						if reader.read_line(&mut line).await.unwrap_or(0) == 0 {
+							return;
+						}
+						let line = line.trim_end();
+						if line.is_empty() {
+							break;
+						}
+						if let Some((name, value)) = line.split_once(':') {
+							if name.eq_ignore_ascii_case("content-length") {
+								content_length = value.trim().parse().unwrap();
+							}
+						}
+					}
+					let mut body = vec![0u8; content_length];
+					reader.read_exact(&mut body).await.unwrap();
+					tx.send(serde_json::from_slice(&body).unwrap()).unwrap();
+					reader
+						.get_mut()
+						.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+						.await
+						.unwrap();
+				}
+			});
+		}
+	});
+
+	(url, rx)
+}
+
+async fn next_notification(
+	rx: &mut mpsc::UnboundedReceiver<serde_json::Value>,
+) -> serde_json::Value {
+	tokio::time::timeout(Duration::from_secs(10), rx.recv())
+		.await
+		.expect("no notification received")
+		.unwrap()
+}
+
+#[tokio::test]
+async fn test_notification() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("notification");
+	let (url, mut rx) = start_webhook().await;
+
+	ctx.client
+		.put_bucket_notification_configuration()
+		.bucket(&bucket)
+		.notification_configuration(
+			NotificationConfiguration::builder()
+				.topic_configurations(
+					TopicConfiguration::builder()
+						.id("docs")
+						.topic_arn(&url)
+						.events(Event::S3ObjectCreated)
+						.events(Event::S3ObjectRemoved)
+						.filter(
+							NotificationConfigurationFilter::builder()
+								.key(
+									S3KeyFilter::builder()
+										.filter_rules(
+											FilterRule::builder()
+												.name(FilterRuleName::Prefix)
+												.value("docs/")
+												.build(),
+										)
+										.build(),
+								)
+								.build(),
+						)
+						.build(),
+				)
+				.build(),
+		)
+		.send()
+		.await
+		.unwrap();
+
+	let conf = ctx
+		.client
+		.get_bucket_notification_configuration()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap();
+	let topics = conf.topic_configurations().unwrap();
+	assert_eq!(topics.len(), 1);
+	assert_eq!(topics[0].id(), Some("docs"));
+	assert_eq!(topics[0].topic_arn(), Some(url.as_str()));
+	assert_eq!(
+		topics[0].events(),
+		Some(&[Event::S3ObjectCreated, Event::S3ObjectRemoved][..])
+	);
+
+	// Objects outside of the prefix do not trigger notifications
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("tmp/b.txt")
+		.body(b"ignored".to_vec().into())
+		.send()
+		.await
+		.unwrap();
+
+	let put = ctx
+		.client
+		.put_object()
+		.bucket(&bucket)
+		.key("docs/a.txt")
+		.body(b"hello".to_vec().into())
+		.send()
+		.await
+		.unwrap();
+
+	let notif = next_notification(&mut rx).await;
+	let record = &notif["Records"][0];
+	assert_eq!(record["eventName"], "ObjectCreated:Put");
+	assert_eq!(record["s3"]["configurationId"], "docs");
+	assert_eq!(record["s3"]["bucket"]["name"], "notification");
+	assert_eq!(record["s3"]["object"]["key"], "docs/a.txt");
+	assert_eq!(record["s3"]["object"]["size"], 5);
+	assert_eq!(
+		record["s3"]["object"]["eTag"],
+		put.e_tag().unwrap().trim_matches('"')
+	);
+
+	ctx.client
+		.delete_object()
+		.bucket(&bucket)
+		.key("docs/a.txt")
+		.send()
+		.await
+		.unwrap();
+
+	let notif = next_notification(&mut rx).await;
+	let record = &notif["Records"][0];
+	assert_eq!(record["eventName"], "ObjectRemoved:Delete");
+	assert_eq!(record["s3"]["object"]["key"], "docs/a.txt");
+
+	// Disabling notifications
+	ctx.client
+		.put_bucket_notification_configuration()
+		.bucket(&bucket)
+		.notification_configuration(NotificationConfiguration::builder().build())
+		.send()
+		.await
+		.unwrap();
+	let conf = ctx
+		.client
+		.get_bucket_notification_configuration()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap();
+	assert!(conf.topic_configurations().unwrap_or_default().is_empty());
+	assert!(rx.try_recv().is_err());
+}
@@ -0,0 +1,418 @@
+use crate::common;
+use crate::common::ext::CommandExt;
+use aws_sdk_s3::error::ProvideErrorMetadata;
+use aws_sdk_s3::primitives::{ByteStream, DateTime};
+use aws_sdk_s3::types::{
+	BucketVersioningStatus, DefaultRetention, Delete, ObjectIdentifier, ObjectLockConfiguration,
+	ObjectLockEnabled, ObjectLockLegalHold, ObjectLockLegalHoldStatus, ObjectLockMode,
+	ObjectLockRetention, ObjectLockRetentionMode, ObjectLockRule, VersioningConfiguration,
+};
+
+const KEY: &str = "locked";
+
+#[tokio::test]
+async fn test_object_lock() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("object-lock");
+
+	// Object lock requires versioning
+	let err = ctx
+		.client
+		.put_object_lock_configuration()
+		.bucket(&bucket)
+		.object_lock_configuration(
+			ObjectLockConfiguration::builder()
+				.object_lock_enabled(ObjectLockEnabled::Enabled)
+				.build(),
+		)
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("InvalidBucketState"));
+
+	ctx.client
+		.put_bucket_versioning()
+		.bucket(&bucket)
+		.versioning_configuration(
+			VersioningConfiguration::builder()
+				.status(BucketVersioningStatus::Enabled)
+				.build(),
+		)
+		.send()
+		.await
+		.unwrap();
+
+	ctx.client
+		.put_object_lock_configuration()
+		.bucket(&bucket)
+		.object_lock_configuration(
+			ObjectLockConfiguration::builder()
+				.object_lock_enabled(ObjectLockEnabled::Enabled)
+				.rule(
+					ObjectLockRule::builder()
+						.default_retention(
+							DefaultRetention::builder()
+								.mode(ObjectLockRetentionMode::Governance)
+								.days(1)
+								.build(),
+						)
+						.build(),
+				)
+				.build(),
+		)
+		.send()
+		.await
+		.unwrap();
+
+	let r = ctx
+		.client
+		.get_object_lock_configuration()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap();
+	let conf = r.object_lock_configuration.unwrap();
+	let retention = conf.rule.unwrap().default_retention.unwrap();
+	assert_eq!(retention.mode, Some(ObjectLockRetentionMode::Governance));
+	assert_eq!(retention.days, 1);
+
+	// Versioning can no longer be suspended
+	let err = ctx
+		.client
+		.put_bucket_versioning()
+		.bucket(&bucket)
+		.versioning_configuration(
+			VersioningConfiguration::builder()
+				.status(BucketVersioningStatus::Suspended)
+				.build(),
+		)
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("InvalidBucketState"));
+
+	// New versions get the default retention of the bucket
+	let v1 = ctx
+		.client
+		.put_object()
+		.bucket(&bucket)
+		.key(KEY)
+		.body(ByteStream::from_static(b"v1"))
+		.send()
+		.await
+		.unwrap()
+		.version_id
+		.unwrap();
+
+	let o = ctx
+		.client
+		.head_object()
+		.bucket(&bucket)
+		.key(KEY)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(o.object_lock_mode, Some(ObjectLockMode::Governance));
+	assert!(o.object_lock_retain_until_date.is_some());
+
+	// A locked version cannot be deleted, but a delete marker can be added
+	let err = ctx
+		.client
+		.delete_object()
+		.bucket(&bucket)
+		.key(KEY)
+		.version_id(&v1)
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("AccessDenied"));
+
+	let r = ctx
+		.client
+		.delete_object()
+		.bucket(&bucket)
+		.key(KEY)
+		.send()
+		.await
+		.unwrap();
+	assert!(r.delete_marker);
+
+	// Governance-mode retention can only be bypassed by keys allowed to do so
+	let err = ctx
+		.client
+		.delete_object()
+		.bucket(&bucket)
+		.key(KEY)
+		.version_id(&v1)
+		.bypass_governance_retention(true)
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("AccessDenied"));
+
+	ctx.garage
+		.command()
+		.args(["bucket", "allow", "--bypass-governance"])
+		.arg(&bucket)
+		.args(["--key", &ctx.key.id])
+		.quiet()
+		.expect_success_status("Could not allow key to bypass governance");
+
+	// A legal hold protects the version even when governance is bypassed
+	ctx.client
+		.put_object_legal_hold()
+		.bucket(&bucket)
+		.key(KEY)
+		.version_id(&v1)
+		.legal_hold(
+			ObjectLockLegalHold::builder()
+				.status(ObjectLockLegalHoldStatus::On)
+				.build(),
+		)
+		.send()
+		.await
+		.unwrap();
+
+	let r = ctx
+		.client
+		.get_object_legal_hold()
+		.bucket(&bucket)
+		.key(KEY)
+		.version_id(&v1)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(
+		r.legal_hold.unwrap().status,
+		Some(ObjectLockLegalHoldStatus::On)
+	);
+
+	let err = ctx
+		.client
+		.delete_object()
+		.bucket(&bucket)
+		.key(KEY)
+		.version_id(&v1)
+		.bypass_governance_retention(true)
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("AccessDenied"));
+
+	ctx.client
+		.put_object_legal_hold()
+		.bucket(&bucket)
+		.key(KEY)
+		.version_id(&v1)
+		.legal_hold(
+			ObjectLockLegalHold::builder()
+				.status(ObjectLockLegalHoldStatus::Off)
+				.build(),
+		)
+		.send()
+		.await
+		.unwrap();
+
+	ctx.client
+		.delete_object()
+		.bucket(&bucket)
+		.key(KEY)
+		.version_id(&v1)
+		.bypass_governance_retention(true)
+		.send()
+		.await
+		.unwrap();
+}
+
+#[tokio::test]
+async fn test_object_lock_compliance() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("object-lock-compliance");
+
+	ctx.client
+		.put_bucket_versioning()
+		.bucket(&bucket)
+		.versioning_configuration(
+			VersioningConfiguration::builder()
+				.status(BucketVersioningStatus::Enabled)
+				.build(),
+		)
+		.send()
+		.await
+		.unwrap();
+
+	// Retention cannot be requested before object lock is enabled
+	let until = DateTime::from_secs(chrono::Utc::now().timestamp() + 3600);
+	let err = ctx
+		.client
+		.put_object()
+		.bucket(&bucket)
+		.key(KEY)
+		.object_lock_mode(ObjectLockMode::Compliance)
+		.object_lock_retain_until_date(until)
+		.body(ByteStream::from_static(b"v1"))
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("InvalidRequest"));
+
+	let err = ctx
+		.client
+		.get_object_lock_configuration()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(
+		err.into_service_error().code(),
+		Some("ObjectLockConfigurationNotFoundError")
+	);
+
+	ctx.client
+		.put_object_lock_configuration()
+		.bucket(&bucket)
+		.object_lock_configuration(
+			ObjectLockConfiguration::builder()
+				.object_lock_enabled(ObjectLockEnabled::Enabled)
+				.build(),
+		)
+		.send()
+		.await
+		.unwrap();
+
+	ctx.garage
+		.command()
+		.args(["bucket", "allow", "--bypass-governance"])
+		.arg(&bucket)
+		.args(["--key", &ctx.key.id])
+		.quiet()
+		.expect_success_status("Could not allow key to bypass governance");
+
+	// Without a default retention, new versions are not locked
+	let v0 = ctx
+		.client
+		.put_object()
+		.bucket(&bucket)
+		.key(KEY)
+		.body(ByteStream::from_static(b"v0"))
+		.send()
+		.await
+		.unwrap()
+		.version_id
+		.unwrap();
+	let err = ctx
+		.client
+		.get_object_retention()
+		.bucket(&bucket)
+		.key(KEY)
+		.version_id(&v0)
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(
+		err.into_service_error().code(),
+		Some("NoSuchObjectLockConfiguration")
+	);
+
+	let v1 = ctx
+		.client
+		.put_object()
+		.bucket(&bucket)
+		.key(KEY)
+		.object_lock_mode(ObjectLockMode::Compliance)
+		.object_lock_retain_until_date(until)
+		.body(ByteStream::from_static(b"v1"))
+		.send()
+		.await
+		.unwrap()
+		.version_id
+		.unwrap();
+
+	let r = ctx
+		.client
+		.get_object_retention()
+		.bucket(&bucket)
+		.key(KEY)
+		.version_id(&v1)
+		.send()
+		.await
+		.unwrap();
+	let retention = r.retention.unwrap();
+	assert_eq!(retention.mode, Some(ObjectLockRetentionMode::Compliance));
+	assert_eq!(retention.retain_until_date.unwrap().secs(), until.secs());
+
+	// Compliance-mode retention can be extended, but never shortened
+	let err = ctx
+		.client
+		.put_object_retention()
+		.bucket(&bucket)
+		.key(KEY)
+		.version_id(&v1)
+		.bypass_governance_retention(true)
+		.retention(
+			ObjectLockRetention::builder()
+				.mode(ObjectLockRetentionMode::Compliance)
+				.retain_until_date(DateTime::from_secs(until.secs() - 1800))
+				.build(),
+		)
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("AccessDenied"));
+
+	ctx.client
+		.put_object_retention()
+		.bucket(&bucket)
+		.key(KEY)
+		.version_id(&v1)
+		.retention(
+			ObjectLockRetention::builder()
+				.mode(ObjectLockRetentionMode::Compliance)
+				.retain_until_date(DateTime::from_secs(until.secs() + 1800))
+				.build(),
+		)
+		.send()
+		.await
+		.unwrap();
+
+	let err = ctx
+		.client
+		.delete_object()
+		.bucket(&bucket)
+		.key(KEY)
+		.version_id(&v1)
+		.bypass_governance_retention(true)
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("AccessDenied"));
+
+	// Locked versions are not deleted by DeleteObjects either
+	let r = ctx
+		.client
+		.delete_objects()
+		.bucket(&bucket)
+		.bypass_governance_retention(true)
+		.delete(
+			Delete::builder()
+				.objects(ObjectIdentifier::builder().key(KEY).version_id(&v1).build())
+				.build(),
+		)
+		.send()
+		.await
+		.unwrap();
+	assert!(r.deleted.unwrap_or_default().is_empty());
+	let errors = r.errors.unwrap();
+	assert_eq!(errors.len(), 1);
+	assert_eq!(errors[0].code.as_deref(), Some("AccessDenied"));
+
+	// Unlocked versions can still be deleted
+	ctx.client
+		.delete_object()
+		.bucket(&bucket)
+		.key(KEY)
+		.version_id(&v0)
+		.send()
+		.await
+		.unwrap();
+}
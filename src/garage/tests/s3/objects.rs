@@ -1,6 +1,11 @@
 use crate::common;
+use aws_sdk_s3::error::ProvideErrorMetadata;
 use aws_sdk_s3::primitives::ByteStream;
-use aws_sdk_s3::types::{Delete, ObjectIdentifier};
+use aws_sdk_s3::types::{
+	CompletedMultipartUpload, CompletedPart, Delete, MetadataDirective, ObjectIdentifier,
+	ObjectStorageClass, RestoreRequest, ServerSideEncryption, StorageClass, Tag, Tagging,
+	TaggingDirective,
+};
 
 const STD_KEY: &str = "hello world";
 const CTRL_KEY: &str = "\x00\x01\x02\x00";
@@ -187,6 +192,94 @@ async fn test_getobject() {
 	}
 }
 
+#[tokio::test]
+async fn test_getobject_multiple_ranges() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("getobject-multiple-ranges");
+
+	// An object stored inline in the metadata, and one stored in several blocks
+	let big = (0..2_500_000u32)
+		.map(|i| (i % 251) as u8)
+		.collect::<Vec<u8>>();
+	for (key, data) in [("small", BODY.to_vec()), ("big", big.clone())] {
+		ctx.client
+			.put_object()
+			.bucket(&bucket)
+			.key(key)
+			.content_type("text/plain")
+			.body(ByteStream::from(data))
+			.send()
+			.await
+			.unwrap();
+	}
+
+	let get_ranges = |key: &'static str, range: &'static str| {
+		let bucket = bucket.clone();
+		let ctx = &ctx;
+		async move {
+			ctx.custom_request
+				.builder(bucket)
+				.path(key)
+				.signed_header("range", range)
+				.send()
+				.await
+				.unwrap()
+		}
+	};
+
+	for (key, data, range, bounds) in [
+		(
+			"small",
+			&BODY[..],
+			"bytes=1-9, 20-29,-5",
+			vec![(1, 10), (20, 30), (57, 62)],
+		),
+		(
+			"big",
+			&big[..],
+			"bytes=10-19,1048570-1048589, 2000000-",
+			vec![(10, 20), (1048570, 1048590), (2000000, 2500000)],
+		),
+	] {
+		let resp = get_ranges(key, range).await;
+		assert_eq!(resp.status(), 206);
+		let content_type = resp.headers()["content-type"].to_str().unwrap().to_string();
+		let boundary = content_type
+			.strip_prefix("multipart/byteranges; boundary=")
+			.unwrap();
+		let content_length: usize = resp.headers()["content-length"]
+			.to_str()
+			.unwrap()
+			.parse()
+			.unwrap();
+
+		let mut expected = vec![];
+		for (begin, end) in bounds {
+			expected.extend(
+				format!(
+					"\r\n--{}\r\nContent-Type: text/plain\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+					boundary,
+					begin,
+					end - 1,
+					data.len()
+				)
+				.into_bytes(),
+			);
+			expected.extend_from_slice(&data[begin..end]);
+		}
+		expected.extend(format!("\r\n--{}--\r\n", boundary).into_bytes());
+
+		let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+		assert_eq!(body.len(), content_length);
+		assert!(body[..] == expected[..], "wrong multipart body for {}", key);
+	}
+
+	// If one of the ranges starts after the end of the object, the request fails
+	let resp = get_ranges("small", "bytes=0-1,100-200").await;
+	assert_eq!(resp.status(), 416);
+	assert_eq!(resp.headers()["content-range"], "bytes */62");
+}
+
 #[tokio::test]
 async fn test_deleteobject() {
 	let ctx = common::context();
@@ -273,3 +366,679 @@ async fn test_deleteobject() {
 		.await
 		.unwrap();
 }
+
+#[tokio::test]
+async fn test_putobject_server_side_encryption() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("putobject-sse");
+	let body = vec![0x42u8; 16 * 1024];
+
+	let r = ctx
+		.client
+		.put_object()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.server_side_encryption(ServerSideEncryption::Aes256)
+		.body(ByteStream::from(body.clone()))
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(r.server_side_encryption, Some(ServerSideEncryption::Aes256));
+
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(o.server_side_encryption, Some(ServerSideEncryption::Aes256));
+	assert_bytes_eq!(o.body, &body);
+
+	// Only SSE-S3 is supported
+	assert!(ctx
+		.client
+		.put_object()
+		.bucket(&bucket)
+		.key("kms")
+		.server_side_encryption(ServerSideEncryption::AwsKms)
+		.body(ByteStream::from_static(BODY))
+		.send()
+		.await
+		.is_err());
+}
+
+/// A SSE-C key made of 32 bytes 0x07, and its MD5, both encoded in base64
+const SSEC_KEY: &str = "BwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwc=";
+const SSEC_KEY_MD5: &str = "y4HAEFCYWuvAXWFTtA1Qpg==";
+/// Another SSE-C key, made of 32 bytes 0x08
+const SSEC_OTHER_KEY: &str = "CAgICAgICAgICAgICAgICAgICAgICAgICAgICAgICAg=";
+const SSEC_OTHER_KEY_MD5: &str = "ry9sFyn8WYF9Mz2zWfMQhQ==";
+
+#[tokio::test]
+async fn test_putobject_sse_c() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("putobject-sse-c");
+
+	// An object stored inline in the object table, and an object stored in blocks
+	for (key, body) in [
+		("inline", BODY.to_vec()),
+		("blocks", vec![0x42u8; 16 * 1024]),
+	] {
+		let r = ctx
+			.client
+			.put_object()
+			.bucket(&bucket)
+			.key(key)
+			.sse_customer_algorithm("AES256")
+			.sse_customer_key(SSEC_KEY)
+			.sse_customer_key_md5(SSEC_KEY_MD5)
+			.body(ByteStream::from(body.clone()))
+			.send()
+			.await
+			.unwrap();
+		assert_eq!(r.sse_customer_algorithm.as_deref(), Some("AES256"));
+		assert_eq!(r.sse_customer_key_md5.as_deref(), Some(SSEC_KEY_MD5));
+
+		let o = ctx
+			.client
+			.get_object()
+			.bucket(&bucket)
+			.key(key)
+			.sse_customer_algorithm("AES256")
+			.sse_customer_key(SSEC_KEY)
+			.sse_customer_key_md5(SSEC_KEY_MD5)
+			.send()
+			.await
+			.unwrap();
+		assert_eq!(o.sse_customer_key_md5.as_deref(), Some(SSEC_KEY_MD5));
+		assert_eq!(o.content_length, body.len() as i64);
+		assert_bytes_eq!(o.body, &body);
+
+		let o = ctx
+			.client
+			.get_object()
+			.bucket(&bucket)
+			.key(key)
+			.range("bytes=10-19")
+			.sse_customer_algorithm("AES256")
+			.sse_customer_key(SSEC_KEY)
+			.sse_customer_key_md5(SSEC_KEY_MD5)
+			.send()
+			.await
+			.unwrap();
+		assert_bytes_eq!(o.body, &body[10..20]);
+
+		let h = ctx
+			.client
+			.head_object()
+			.bucket(&bucket)
+			.key(key)
+			.sse_customer_algorithm("AES256")
+			.sse_customer_key(SSEC_KEY)
+			.sse_customer_key_md5(SSEC_KEY_MD5)
+			.send()
+			.await
+			.unwrap();
+		assert_eq!(h.content_length, body.len() as i64);
+
+		// The key is required to read the object, and must be the right one
+		assert!(ctx
+			.client
+			.get_object()
+			.bucket(&bucket)
+			.key(key)
+			.send()
+			.await
+			.is_err());
+		assert!(ctx
+			.client
+			.head_object()
+			.bucket(&bucket)
+			.key(key)
+			.send()
+			.await
+			.is_err());
+		assert!(ctx
+			.client
+			.get_object()
+			.bucket(&bucket)
+			.key(key)
+			.sse_customer_algorithm("AES256")
+			.sse_customer_key(SSEC_OTHER_KEY)
+			.sse_customer_key_md5(SSEC_OTHER_KEY_MD5)
+			.send()
+			.await
+			.is_err());
+	}
+
+	// The MD5 given in the request must be that of the key
+	assert!(ctx
+		.client
+		.put_object()
+		.bucket(&bucket)
+		.key("bad-md5")
+		.sse_customer_algorithm("AES256")
+		.sse_customer_key(SSEC_KEY)
+		.sse_customer_key_md5(SSEC_OTHER_KEY_MD5)
+		.body(ByteStream::from_static(BODY))
+		.send()
+		.await
+		.is_err());
+}
+
+#[tokio::test]
+async fn test_object_user_metadata() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("object-user-metadata");
+
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.metadata("color", "blue")
+		.metadata("shape", "round")
+		.body(ByteStream::from_static(BODY))
+		.send()
+		.await
+		.unwrap();
+
+	let o = ctx
+		.client
+		.head_object()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.send()
+		.await
+		.unwrap();
+	let meta = o.metadata.unwrap();
+	assert_eq!(meta.len(), 2);
+	assert_eq!(meta["color"], "blue");
+	assert_eq!(meta["shape"], "round");
+
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(o.metadata.unwrap()["color"], "blue");
+
+	// By default, CopyObject copies the metadata of the source object
+	ctx.client
+		.copy_object()
+		.bucket(&bucket)
+		.key("copy")
+		.copy_source(format!("{}/{}", bucket, STD_KEY))
+		.metadata("ignored", "value")
+		.send()
+		.await
+		.unwrap();
+	let o = ctx
+		.client
+		.head_object()
+		.bucket(&bucket)
+		.key("copy")
+		.send()
+		.await
+		.unwrap();
+	let meta = o.metadata.unwrap();
+	assert_eq!(meta.len(), 2);
+	assert_eq!(meta["color"], "blue");
+
+	// With the REPLACE directive, it uses the metadata of the request
+	ctx.client
+		.copy_object()
+		.bucket(&bucket)
+		.key("replace")
+		.copy_source(format!("{}/{}", bucket, STD_KEY))
+		.metadata_directive(MetadataDirective::Replace)
+		.metadata("color", "red")
+		.send()
+		.await
+		.unwrap();
+	let o = ctx
+		.client
+		.head_object()
+		.bucket(&bucket)
+		.key("replace")
+		.send()
+		.await
+		.unwrap();
+	let meta = o.metadata.unwrap();
+	assert_eq!(meta.len(), 1);
+	assert_eq!(meta["color"], "red");
+
+	// Multipart uploads get their metadata when they are created
+	let upload = ctx
+		.client
+		.create_multipart_upload()
+		.bucket(&bucket)
+		.key("multipart")
+		.metadata("color", "green")
+		.send()
+		.await
+		.unwrap();
+	let part = ctx
+		.client
+		.upload_part()
+		.bucket(&bucket)
+		.key("multipart")
+		.upload_id(upload.upload_id.as_ref().unwrap())
+		.part_number(1)
+		.body(ByteStream::from_static(BODY))
+		.send()
+		.await
+		.unwrap();
+	ctx.client
+		.complete_multipart_upload()
+		.bucket(&bucket)
+		.key("multipart")
+		.upload_id(upload.upload_id.unwrap())
+		.multipart_upload(
+			CompletedMultipartUpload::builder()
+				.parts(
+					CompletedPart::builder()
+						.part_number(1)
+						.e_tag(part.e_tag.unwrap())
+						.build(),
+				)
+				.build(),
+		)
+		.send()
+		.await
+		.unwrap();
+	let o = ctx
+		.client
+		.head_object()
+		.bucket(&bucket)
+		.key("multipart")
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(o.metadata.unwrap()["color"], "green");
+
+	// User metadata is limited to 2 KB
+	let err = ctx
+		.client
+		.put_object()
+		.bucket(&bucket)
+		.key("too-large")
+		.metadata("large", "x".repeat(2048))
+		.body(ByteStream::from_static(BODY))
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("MetadataTooLarge"));
+
+	let err = ctx
+		.client
+		.copy_object()
+		.bucket(&bucket)
+		.key("too-large")
+		.copy_source(format!("{}/{}", bucket, STD_KEY))
+		.metadata_directive(MetadataDirective::Replace)
+		.metadata("large", "x".repeat(2048))
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("MetadataTooLarge"));
+}
+
+#[tokio::test]
+async fn test_object_tagging() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("object-tagging");
+
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.tagging("project=garage&team=storage")
+		.body(ByteStream::from_static(BODY))
+		.send()
+		.await
+		.unwrap();
+
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(o.tag_count, 2);
+
+	let r = ctx
+		.client
+		.get_object_tagging()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.send()
+		.await
+		.unwrap();
+	let tags = r.tag_set.unwrap();
+	assert_eq!(tags.len(), 2);
+	assert_eq!(tags[0].key.as_deref(), Some("project"));
+	assert_eq!(tags[0].value.as_deref(), Some("garage"));
+	assert_eq!(tags[1].key.as_deref(), Some("team"));
+	assert_eq!(tags[1].value.as_deref(), Some("storage"));
+
+	// Tags are replaced as a whole
+	ctx.client
+		.put_object_tagging()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.tagging(
+			Tagging::builder()
+				.tag_set(Tag::builder().key("color").value("blue").build())
+				.build(),
+		)
+		.send()
+		.await
+		.unwrap();
+	let r = ctx
+		.client
+		.get_object_tagging()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.send()
+		.await
+		.unwrap();
+	let tags = r.tag_set.unwrap();
+	assert_eq!(tags.len(), 1);
+	assert_eq!(tags[0].key.as_deref(), Some("color"));
+
+	// By default, CopyObject copies the tags of the source object
+	ctx.client
+		.copy_object()
+		.bucket(&bucket)
+		.key("copy")
+		.copy_source(format!("{}/{}", bucket, STD_KEY))
+		.tagging("ignored=value")
+		.send()
+		.await
+		.unwrap();
+	let r = ctx
+		.client
+		.get_object_tagging()
+		.bucket(&bucket)
+		.key("copy")
+		.send()
+		.await
+		.unwrap();
+	let tags = r.tag_set.unwrap();
+	assert_eq!(tags.len(), 1);
+	assert_eq!(tags[0].value.as_deref(), Some("blue"));
+
+	// With the REPLACE directive, it uses the tags of the request
+	ctx.client
+		.copy_object()
+		.bucket(&bucket)
+		.key("replace")
+		.copy_source(format!("{}/{}", bucket, STD_KEY))
+		.tagging_directive(TaggingDirective::Replace)
+		.tagging("color=red")
+		.send()
+		.await
+		.unwrap();
+	let r = ctx
+		.client
+		.get_object_tagging()
+		.bucket(&bucket)
+		.key("replace")
+		.send()
+		.await
+		.unwrap();
+	let tags = r.tag_set.unwrap();
+	assert_eq!(tags.len(), 1);
+	assert_eq!(tags[0].value.as_deref(), Some("red"));
+
+	ctx.client
+		.delete_object_tagging()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.send()
+		.await
+		.unwrap();
+	let r = ctx
+		.client
+		.get_object_tagging()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.send()
+		.await
+		.unwrap();
+	assert!(r.tag_set.unwrap_or_default().is_empty());
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(o.tag_count, 0);
+
+	// Tags given when a multipart upload is created are set on the completed object
+	let upload = ctx
+		.client
+		.create_multipart_upload()
+		.bucket(&bucket)
+		.key("multipart")
+		.tagging("project=garage")
+		.send()
+		.await
+		.unwrap();
+	let part = ctx
+		.client
+		.upload_part()
+		.bucket(&bucket)
+		.key("multipart")
+		.upload_id(upload.upload_id.as_ref().unwrap())
+		.part_number(1)
+		.body(ByteStream::from_static(BODY))
+		.send()
+		.await
+		.unwrap();
+	ctx.client
+		.complete_multipart_upload()
+		.bucket(&bucket)
+		.key("multipart")
+		.upload_id(upload.upload_id.unwrap())
+		.multipart_upload(
+			CompletedMultipartUpload::builder()
+				.parts(
+					CompletedPart::builder()
+						.part_number(1)
+						.e_tag(part.e_tag.unwrap())
+						.build(),
+				)
+				.build(),
+		)
+		.send()
+		.await
+		.unwrap();
+	let r = ctx
+		.client
+		.get_object_tagging()
+		.bucket(&bucket)
+		.key("multipart")
+		.send()
+		.await
+		.unwrap();
+	let tags = r.tag_set.unwrap();
+	assert_eq!(tags.len(), 1);
+	assert_eq!(tags[0].key.as_deref(), Some("project"));
+	assert_eq!(tags[0].value.as_deref(), Some("garage"));
+
+	// Invalid tags are rejected
+	let err = ctx
+		.client
+		.put_object()
+		.bucket(&bucket)
+		.key("invalid")
+		.tagging("a=1&a=2")
+		.body(ByteStream::from_static(BODY))
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("InvalidTag"));
+
+	let err = ctx
+		.client
+		.put_object_tagging()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.tagging(
+			Tagging::builder()
+				.tag_set(Tag::builder().key("k").value("v".repeat(257)).build())
+				.build(),
+		)
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("InvalidTag"));
+}
+
+#[tokio::test]
+async fn test_glacier_restore() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("glacier-restore");
+
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.storage_class(StorageClass::Glacier)
+		.body(ByteStream::from_static(BODY))
+		.send()
+		.await
+		.unwrap();
+
+	let err = ctx
+		.client
+		.put_object()
+		.bucket(&bucket)
+		.key("invalid")
+		.storage_class(StorageClass::from("COLD_AS_ICE"))
+		.body(ByteStream::from_static(BODY))
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("InvalidStorageClass"));
+
+	let h = ctx
+		.client
+		.head_object()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(h.storage_class, Some(StorageClass::Glacier));
+	assert_eq!(h.restore, None);
+
+	let l = ctx
+		.client
+		.list_objects_v2()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(
+		l.contents.unwrap()[0].storage_class,
+		Some(ObjectStorageClass::Glacier)
+	);
+
+	// Archived objects can neither be read nor copied until they are restored
+	let err = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("InvalidObjectState"));
+	let err = ctx
+		.client
+		.copy_object()
+		.bucket(&bucket)
+		.key("copy")
+		.copy_source(format!("{}/{}", bucket, STD_KEY))
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("InvalidObjectState"));
+
+	ctx.client
+		.restore_object()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.restore_request(RestoreRequest::builder().days(1).build())
+		.send()
+		.await
+		.unwrap();
+
+	// The restore is completed by a background worker
+	let mut restore = None;
+	for _ in 0..50 {
+		let h = ctx
+			.client
+			.head_object()
+			.bucket(&bucket)
+			.key(STD_KEY)
+			.send()
+			.await
+			.unwrap();
+		restore = h.restore;
+		if restore
+			.as_deref()
+			.map(|r| r.starts_with("ongoing-request=\"false\""))
+			.unwrap_or(false)
+		{
+			break;
+		}
+		tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+	}
+	assert!(
+		restore.as_deref().unwrap().contains("expiry-date="),
+		"{:?}",
+		restore
+	);
+
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.send()
+		.await
+		.unwrap();
+	assert_bytes_eq!(o.body, BODY);
+
+	// Objects of the STANDARD storage class cannot be restored
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("standard")
+		.body(ByteStream::from_static(BODY))
+		.send()
+		.await
+		.unwrap();
+	let err = ctx
+		.client
+		.restore_object()
+		.bucket(&bucket)
+		.key("standard")
+		.restore_request(RestoreRequest::builder().days(1).build())
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("InvalidObjectState"));
+}
@@ -0,0 +1,336 @@
+use crate::common;
+use crate::common::ext::CommandExt;
+use aws_sdk_s3::error::ProvideErrorMetadata;
+use aws_sdk_s3::types::{Delete, ObjectIdentifier};
+use hyper::{Method, StatusCode};
+
+const KEY: &str = "obj";
+
+#[tokio::test]
+async fn test_single_operation_permissions() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("single-op-permissions");
+
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key(KEY)
+		.body(b"hello".to_vec().into())
+		.send()
+		.await
+		.unwrap();
+
+	// Keep the read and write permissions, but remove two operations
+	let output = ctx
+		.garage
+		.command()
+		.args([
+			"bucket",
+			"deny",
+			"--op",
+			"DeleteObject",
+			"--op",
+			"ListObjectsV2",
+		])
+		.arg(&bucket)
+		.args(["--key", &ctx.key.id])
+		.expect_success_output("Could not deny operations");
+	let stdout = String::from_utf8(output.stdout).unwrap();
+	assert!(stdout.contains("Denied operations: ListObjectsV2, DeleteObject"));
+
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key(KEY)
+		.body(b"hello again".to_vec().into())
+		.send()
+		.await
+		.unwrap();
+	ctx.client
+		.get_object()
+		.bucket(&bucket)
+		.key(KEY)
+		.send()
+		.await
+		.unwrap();
+
+	let err = ctx
+		.client
+		.delete_object()
+		.bucket(&bucket)
+		.key(KEY)
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("AccessDenied"));
+
+	let err = ctx
+		.client
+		.list_objects_v2()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("AccessDenied"));
+
+	// Unknown operation names are refused
+	let output = ctx
+		.garage
+		.command()
+		.args(["bucket", "deny", "--op", "DestroyEverything"])
+		.arg(&bucket)
+		.args(["--key", &ctx.key.id])
+		.output()
+		.unwrap();
+	assert!(!output.status.success());
+
+	// Giving the write permission again gives back all write operations
+	ctx.garage
+		.command()
+		.args(["bucket", "allow", "--write"])
+		.arg(&bucket)
+		.args(["--key", &ctx.key.id])
+		.quiet()
+		.expect_success_status("Could not allow write");
+	ctx.client
+		.delete_object()
+		.bucket(&bucket)
+		.key(KEY)
+		.send()
+		.await
+		.unwrap();
+
+	// A single operation can also be given without the permission of its group
+	ctx.garage
+		.command()
+		.args(["bucket", "deny", "--read", "--write"])
+		.arg(&bucket)
+		.args(["--key", &ctx.key.id])
+		.quiet()
+		.expect_success_status("Could not deny read and write");
+	ctx.garage
+		.command()
+		.args(["bucket", "allow", "--op", "PutObject"])
+		.arg(&bucket)
+		.args(["--key", &ctx.key.id])
+		.quiet()
+		.expect_success_status("Could not allow PutObject");
+
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key(KEY)
+		.body(b"write only".to_vec().into())
+		.send()
+		.await
+		.unwrap();
+	let err = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key(KEY)
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("AccessDenied"));
+}
+
+#[tokio::test]
+async fn test_head_and_list_buckets_without_ownership() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("head-bucket-permissions");
+
+	let head_bucket = |bucket: String| {
+		let ctx = &ctx;
+		async move {
+			ctx.custom_request
+				.builder(bucket)
+				.method(Method::HEAD)
+				.send()
+				.await
+				.unwrap()
+				.status()
+		}
+	};
+	let listed = || async {
+		ctx.client
+			.list_buckets()
+			.send()
+			.await
+			.unwrap()
+			.buckets
+			.unwrap_or_default()
+			.into_iter()
+			.any(|b| b.name.as_deref() == Some(bucket.as_str()))
+	};
+
+	// Bucket that does not exist
+	assert_eq!(
+		head_bucket("head-bucket-nonexistent".into()).await,
+		StatusCode::NOT_FOUND
+	);
+
+	// Bucket that exists, on which the key has no permission
+	ctx.garage
+		.command()
+		.args(["bucket", "deny", "--read", "--write", "--owner"])
+		.arg(&bucket)
+		.args(["--key", &ctx.key.id])
+		.quiet()
+		.expect_success_status("Could not deny key for bucket");
+	assert_eq!(head_bucket(bucket.clone()).await, StatusCode::FORBIDDEN);
+	assert!(!listed().await);
+
+	// Bucket on which the key can only write
+	ctx.garage
+		.command()
+		.args(["bucket", "allow", "--write"])
+		.arg(&bucket)
+		.args(["--key", &ctx.key.id])
+		.quiet()
+		.expect_success_status("Could not allow write");
+	assert_eq!(head_bucket(bucket.clone()).await, StatusCode::OK);
+	assert!(listed().await);
+
+	// Bucket on which HeadBucket was specifically denied
+	ctx.garage
+		.command()
+		.args(["bucket", "allow", "--read"])
+		.arg(&bucket)
+		.args(["--key", &ctx.key.id])
+		.quiet()
+		.expect_success_status("Could not allow read");
+	ctx.garage
+		.command()
+		.args(["bucket", "deny", "--op", "HeadBucket"])
+		.arg(&bucket)
+		.args(["--key", &ctx.key.id])
+		.quiet()
+		.expect_success_status("Could not deny HeadBucket");
+	assert_eq!(head_bucket(bucket.clone()).await, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn test_bucket_policy() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("bucket-policy");
+
+	for key in ["public/a", "private/b"] {
+		ctx.client
+			.put_object()
+			.bucket(&bucket)
+			.key(key)
+			.body(b"hello".to_vec().into())
+			.send()
+			.await
+			.unwrap();
+	}
+
+	let err = ctx
+		.client
+		.get_bucket_policy()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("NoSuchBucketPolicy"));
+
+	// Unsupported policies are refused
+	let err = ctx
+		.client
+		.put_bucket_policy()
+		.bucket(&bucket)
+		.policy(r#"{"Statement": [{"Effect": "Allow", "NotPrincipal": "*"}]}"#)
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("MalformedPolicy"));
+
+	let policy = format!(
+		r#"{{
+			"Version": "2012-10-17",
+			"Statement": [{{
+				"Effect": "Deny",
+				"Principal": "*",
+				"Action": ["s3:GetObject", "s3:DeleteObject"],
+				"Resource": "arn:aws:s3:::{}/private/*"
+			}}]
+		}}"#,
+		bucket
+	);
+	ctx.client
+		.put_bucket_policy()
+		.bucket(&bucket)
+		.policy(&policy)
+		.send()
+		.await
+		.unwrap();
+
+	let r = ctx
+		.client
+		.get_bucket_policy()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(r.policy(), Some(policy.as_str()));
+
+	let r = ctx
+		.client
+		.get_bucket_policy_status()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap();
+	assert!(!r.policy_status().unwrap().is_public());
+
+	// The policy denies actions that the permissions of the key allow
+	ctx.client
+		.get_object()
+		.bucket(&bucket)
+		.key("public/a")
+		.send()
+		.await
+		.unwrap();
+	let err = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key("private/b")
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("AccessDenied"));
+
+	// Objects of DeleteObjects are checked one by one
+	let r = ctx
+		.client
+		.delete_objects()
+		.bucket(&bucket)
+		.delete(
+			Delete::builder()
+				.objects(ObjectIdentifier::builder().key("public/a").build())
+				.objects(ObjectIdentifier::builder().key("private/b").build())
+				.build(),
+		)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(r.deleted().unwrap().len(), 1);
+	assert_eq!(r.errors().unwrap()[0].key(), Some("private/b"));
+	assert_eq!(r.errors().unwrap()[0].code(), Some("AccessDenied"));
+
+	ctx.client
+		.delete_bucket_policy()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap();
+	ctx.client
+		.get_object()
+		.bucket(&bucket)
+		.key("private/b")
+		.send()
+		.await
+		.unwrap();
+}
@@ -0,0 +1,110 @@
+use std::time::Duration;
+
+use crate::common;
+use crate::common::ext::CommandExt;
+
+use aws_sdk_s3::primitives::ByteStream;
+
+/// Wait until the keys of the bucket are the expected ones, and return them
+async fn wait_for_keys(ctx: &common::Context, bucket: &str, expected: &[&str]) -> Vec<String> {
+	let mut keys = vec![];
+	for _ in 0..40 {
+		tokio::time::sleep(Duration::from_millis(500)).await;
+		let r = ctx
+			.client
+			.list_objects_v2()
+			.bucket(bucket)
+			.send()
+			.await
+			.unwrap();
+		keys = r
+			.contents()
+			.unwrap_or_default()
+			.iter()
+			.map(|o| o.key().unwrap().to_string())
+			.collect::<Vec<_>>();
+		if keys == expected {
+			break;
+		}
+	}
+	keys
+}
+
+#[tokio::test]
+async fn test_bucket_replication() {
+	let ctx = common::context();
+	let source = ctx.create_bucket("replication-source");
+	let destination = ctx.create_bucket("replication-destination");
+
+	ctx.garage
+		.command()
+		.args(["worker", "set", "replication-interval-secs", "1"])
+		.quiet()
+		.expect_success_status("Could not set replication interval");
+
+	// The destination is the S3 API of the test cluster itself
+	ctx.garage
+		.command()
+		.args(["bucket", "add-replication-rule", &source])
+		.args(["--id", "backup"])
+		.args(["--endpoint", &ctx.garage.s3_uri().to_string()])
+		.args(["--region", "garage-integ-test"])
+		.args(["--target-bucket", &destination])
+		.args(["--access-key-id", &ctx.key.id])
+		.args(["--secret-access-key", &ctx.key.secret])
+		.args(["--prefix", "docs/"])
+		.arg("--replicate-deletes")
+		.quiet()
+		.expect_success_status("Could not add replication rule");
+
+	for key in ["docs/a.txt", "docs/b.txt", "tmp/c.txt"] {
+		ctx.client
+			.put_object()
+			.bucket(&source)
+			.key(key)
+			.content_type("text/plain")
+			.metadata("owner", "alice")
+			.body(ByteStream::from(format!("content of {}", key).into_bytes()))
+			.send()
+			.await
+			.unwrap();
+	}
+
+	// Only the objects whose key starts with the prefix of the rule are replicated
+	let keys = wait_for_keys(&ctx, &destination, &["docs/a.txt", "docs/b.txt"]).await;
+	assert_eq!(keys, ["docs/a.txt", "docs/b.txt"]);
+
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&destination)
+		.key("docs/a.txt")
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(o.content_type(), Some("text/plain"));
+	let meta = o.metadata().unwrap();
+	assert_eq!(meta.get("owner").map(String::as_str), Some("alice"));
+	assert_eq!(meta.get("garage-replica").map(String::as_str), Some("true"));
+	let data = o.body.collect().await.unwrap().into_bytes();
+	assert_eq!(&data[..], b"content of docs/a.txt");
+
+	// Deletions are replicated
+	ctx.client
+		.delete_object()
+		.bucket(&source)
+		.key("docs/a.txt")
+		.send()
+		.await
+		.unwrap();
+
+	let keys = wait_for_keys(&ctx, &destination, &["docs/b.txt"]).await;
+	assert_eq!(keys, ["docs/b.txt"]);
+
+	ctx.garage
+		.command()
+		.args(["bucket", "remove-replication-rule", &source])
+		.args(["--id", "backup"])
+		.quiet()
+		.expect_success_status("Could not remove replication rule");
+}
@@ -0,0 +1,136 @@
+use crate::common;
+
+use aws_sdk_s3::types::{
+	CsvInput, CsvOutput, ExpressionType, FileHeaderInfo, InputSerialization, JsonInput, JsonOutput,
+	JsonType, OutputSerialization, SelectObjectContentEventStream,
+};
+
+const CSV: &[u8] = b"name,city,age\nAlice,Paris,31\nBob,Lyon,25\nCarol,Paris,47\n";
+
+const JSON: &[u8] = br#"{"name": "Alice", "pets": [{"kind": "cat"}]}
+{"name": "Bob", "pets": []}
+"#;
+
+/// Run a query and return the records and whether the
+/// Stats and End events were received
+async fn select(
+	ctx: &common::Context,
+	bucket: &str,
+	key: &str,
+	expression: &str,
+	input: InputSerialization,
+	output: OutputSerialization,
+) -> (String, bool, bool) {
+	let mut resp = ctx
+		.client
+		.select_object_content()
+		.bucket(bucket)
+		.key(key)
+		.expression(expression)
+		.expression_type(ExpressionType::Sql)
+		.input_serialization(input)
+		.output_serialization(output)
+		.send()
+		.await
+		.unwrap();
+
+	let mut records = vec![];
+	let (mut stats, mut end) = (false, false);
+	while let Some(event) = resp.payload.recv().await.unwrap() {
+		match event {
+			SelectObjectContentEventStream::Records(r) => {
+				records.extend_from_slice(r.payload().unwrap().as_ref())
+			}
+			SelectObjectContentEventStream::Stats(_) => stats = true,
+			SelectObjectContentEventStream::End(_) => end = true,
+			_ => (),
+		}
+	}
+	(String::from_utf8(records).unwrap(), stats, end)
+}
+
+fn csv_input() -> InputSerialization {
+	InputSerialization::builder()
+		.csv(
+			CsvInput::builder()
+				.file_header_info(FileHeaderInfo::Use)
+				.build(),
+		)
+		.build()
+}
+
+#[tokio::test]
+async fn test_select_object_content() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("select");
+
+	for (key, data) in [("people.csv", CSV), ("people.json", JSON)] {
+		ctx.client
+			.put_object()
+			.bucket(&bucket)
+			.key(key)
+			.body(data.to_vec().into())
+			.send()
+			.await
+			.unwrap();
+	}
+
+	let csv_output = || {
+		OutputSerialization::builder()
+			.csv(CsvOutput::builder().build())
+			.build()
+	};
+
+	let (records, stats, end) = select(
+		&ctx,
+		&bucket,
+		"people.csv",
+		"SELECT s.name, s.age FROM S3Object s WHERE s.city = 'Paris'",
+		csv_input(),
+		csv_output(),
+	)
+	.await;
+	assert_eq!(records, "Alice,31\nCarol,47\n");
+	assert!(stats);
+	assert!(end);
+
+	let (records, _, _) = select(
+		&ctx,
+		&bucket,
+		"people.csv",
+		"SELECT COUNT(*), AVG(age) FROM S3Object WHERE age > 30",
+		csv_input(),
+		OutputSerialization::builder()
+			.json(JsonOutput::builder().build())
+			.build(),
+	)
+	.await;
+	assert_eq!(records, "{\"_1\":2,\"_2\":39.0}\n");
+
+	let (records, _, _) = select(
+		&ctx,
+		&bucket,
+		"people.json",
+		"SELECT s.name FROM S3Object s WHERE s.pets[0].kind = 'cat'",
+		InputSerialization::builder()
+			.json(JsonInput::builder().r#type(JsonType::Lines).build())
+			.build(),
+		csv_output(),
+	)
+	.await;
+	assert_eq!(records, "Alice\n");
+
+	// Invalid queries are rejected before any data is sent
+	let err = ctx
+		.client
+		.select_object_content()
+		.bucket(&bucket)
+		.key("people.csv")
+		.expression("SELECT name FROM other_table")
+		.expression_type(ExpressionType::Sql)
+		.input_serialization(csv_input())
+		.output_serialization(csv_output())
+		.send()
+		.await;
+	assert!(err.is_err());
+}
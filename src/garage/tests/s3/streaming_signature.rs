@@ -101,6 +101,39 @@ async fn test_putobject_streaming() {
 	}
 }
 
+#[tokio::test]
+async fn test_putobject_streaming_unsigned_trailer() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("putobject-streaming-trailer");
+
+	let resp = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::PUT)
+		.path("abc".to_owned())
+		.body(BODY.to_vec())
+		.body_signature(BodySignature::StreamingUnsignedTrailer(16))
+		.send()
+		.await
+		.unwrap();
+	assert!(resp.status().is_success());
+
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key("abc")
+		.send()
+		.await
+		.unwrap();
+
+	// The aws-chunked encoding of the request is not stored with the object
+	assert_eq!(o.content_encoding, None);
+	assert_eq!(o.e_tag.unwrap(), "\"46cf18a9b447991b450cad3facf5937e\"");
+	assert_eq!(o.content_length, 62);
+	assert_bytes_eq!(o.body, BODY);
+}
+
 #[tokio::test]
 async fn test_create_bucket_streaming() {
 	let ctx = common::context();
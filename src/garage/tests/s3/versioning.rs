@@ -0,0 +1,335 @@
+use crate::common;
+use aws_sdk_s3::error::ProvideErrorMetadata;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{BucketVersioningStatus, VersioningConfiguration};
+
+const KEY: &str = "versioned";
+
+#[tokio::test]
+async fn test_versioning() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("versioning");
+
+	// Versioning is not enabled at first
+	let r = ctx
+		.client
+		.get_bucket_versioning()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap();
+	assert!(r.status.is_none());
+
+	// Object written before versioning is enabled: the "null" version
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key(KEY)
+		.body(ByteStream::from_static(b"v0"))
+		.send()
+		.await
+		.unwrap();
+
+	ctx.client
+		.put_bucket_versioning()
+		.bucket(&bucket)
+		.versioning_configuration(
+			VersioningConfiguration::builder()
+				.status(BucketVersioningStatus::Enabled)
+				.build(),
+		)
+		.send()
+		.await
+		.unwrap();
+
+	let r = ctx
+		.client
+		.get_bucket_versioning()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(r.status, Some(BucketVersioningStatus::Enabled));
+
+	let mut version_ids = vec![];
+	for body in [&b"v1"[..], &b"v2"[..]].iter() {
+		let r = ctx
+			.client
+			.put_object()
+			.bucket(&bucket)
+			.key(KEY)
+			.body(ByteStream::from(body.to_vec()))
+			.send()
+			.await
+			.unwrap();
+		version_ids.push(r.version_id.unwrap());
+	}
+	assert_ne!(version_ids[0], "null");
+	assert_ne!(version_ids[0], version_ids[1]);
+
+	// The latest version is returned by default, older ones on demand
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key(KEY)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(o.version_id.as_deref(), Some(version_ids[1].as_str()));
+	assert_bytes_eq!(o.body, b"v2");
+
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key(KEY)
+		.version_id(&version_ids[0])
+		.send()
+		.await
+		.unwrap();
+	assert_bytes_eq!(o.body, b"v1");
+
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key(KEY)
+		.version_id("null")
+		.send()
+		.await
+		.unwrap();
+	assert_bytes_eq!(o.body, b"v0");
+
+	let err = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key(KEY)
+		.version_id("00000000000000000000000000000000")
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("NoSuchVersion"));
+
+	// Deleting the object adds a delete marker and keeps all versions
+	let r = ctx
+		.client
+		.delete_object()
+		.bucket(&bucket)
+		.key(KEY)
+		.send()
+		.await
+		.unwrap();
+	assert!(r.delete_marker);
+	let delete_marker_id = r.version_id.unwrap();
+
+	let err = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key(KEY)
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("NoSuchKey"));
+	let err = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key(KEY)
+		.version_id(&delete_marker_id)
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("MethodNotAllowed"));
+
+	let l = ctx
+		.client
+		.list_objects_v2()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap();
+	assert!(l.contents.is_none());
+
+	let l = ctx
+		.client
+		.list_object_versions()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap();
+	let versions = l.versions.unwrap();
+	assert_eq!(versions.len(), 3);
+	assert_eq!(
+		versions[0].version_id.as_deref(),
+		Some(version_ids[1].as_str())
+	);
+	assert_eq!(
+		versions[1].version_id.as_deref(),
+		Some(version_ids[0].as_str())
+	);
+	assert_eq!(versions[2].version_id.as_deref(), Some("null"));
+	assert!(versions.iter().all(|v| !v.is_latest));
+	let delete_markers = l.delete_markers.unwrap();
+	assert_eq!(delete_markers.len(), 1);
+	assert_eq!(
+		delete_markers[0].version_id.as_deref(),
+		Some(delete_marker_id.as_str())
+	);
+	assert!(delete_markers[0].is_latest);
+
+	// A delete marker cannot be copied, but older versions can
+	let copy_bucket = ctx.create_bucket("versioning-copy");
+	let err = ctx
+		.client
+		.copy_object()
+		.bucket(&copy_bucket)
+		.key(KEY)
+		.copy_source(format!("{}/{}", bucket, KEY))
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("NoSuchKey"));
+	let err = ctx
+		.client
+		.copy_object()
+		.bucket(&copy_bucket)
+		.key(KEY)
+		.copy_source(format!("{}/{}?versionId={}", bucket, KEY, delete_marker_id))
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("InvalidRequest"));
+	ctx.client
+		.copy_object()
+		.bucket(&copy_bucket)
+		.key(KEY)
+		.copy_source(format!("{}/{}?versionId={}", bucket, KEY, version_ids[0]))
+		.send()
+		.await
+		.unwrap();
+	let r = ctx
+		.client
+		.get_object()
+		.bucket(&copy_bucket)
+		.key(KEY)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(r.body.collect().await.unwrap().into_bytes().as_ref(), b"v1");
+
+	// Pagination goes through all versions
+	let l = ctx
+		.client
+		.list_object_versions()
+		.bucket(&bucket)
+		.max_keys(2)
+		.send()
+		.await
+		.unwrap();
+	assert!(l.is_truncated);
+	let l = ctx
+		.client
+		.list_object_versions()
+		.bucket(&bucket)
+		.max_keys(2)
+		.key_marker(l.next_key_marker.unwrap())
+		.version_id_marker(l.next_version_id_marker.unwrap())
+		.send()
+		.await
+		.unwrap();
+	assert!(!l.is_truncated);
+	assert_eq!(l.versions.unwrap().len(), 2);
+	assert!(l.delete_markers.is_none());
+
+	// Removing the delete marker restores the latest version
+	ctx.client
+		.delete_object()
+		.bucket(&bucket)
+		.key(KEY)
+		.version_id(&delete_marker_id)
+		.send()
+		.await
+		.unwrap();
+
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key(KEY)
+		.send()
+		.await
+		.unwrap();
+	assert_bytes_eq!(o.body, b"v2");
+
+	// Versions can be deleted permanently
+	ctx.client
+		.delete_object()
+		.bucket(&bucket)
+		.key(KEY)
+		.version_id(&version_ids[1])
+		.send()
+		.await
+		.unwrap();
+
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key(KEY)
+		.send()
+		.await
+		.unwrap();
+	assert_bytes_eq!(o.body, b"v1");
+
+	let l = ctx
+		.client
+		.list_object_versions()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(l.versions.unwrap().len(), 2);
+
+	// Once versioning is suspended, new writes replace the "null" version
+	ctx.client
+		.put_bucket_versioning()
+		.bucket(&bucket)
+		.versioning_configuration(
+			VersioningConfiguration::builder()
+				.status(BucketVersioningStatus::Suspended)
+				.build(),
+		)
+		.send()
+		.await
+		.unwrap();
+
+	let r = ctx
+		.client
+		.put_object()
+		.bucket(&bucket)
+		.key(KEY)
+		.body(ByteStream::from_static(b"v3"))
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(r.version_id.as_deref(), Some("null"));
+
+	let l = ctx
+		.client
+		.list_object_versions()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap();
+	let versions = l.versions.unwrap();
+	assert_eq!(versions.len(), 2);
+	assert_eq!(versions[0].version_id.as_deref(), Some("null"));
+	assert!(versions[0].is_latest);
+	assert_eq!(
+		versions[1].version_id.as_deref(),
+		Some(version_ids[0].as_str())
+	);
+}
@@ -5,7 +5,10 @@ use crate::json_body;
 use assert_json_diff::assert_json_eq;
 use aws_sdk_s3::{
 	primitives::ByteStream,
-	types::{CorsConfiguration, CorsRule, ErrorDocument, IndexDocument, WebsiteConfiguration},
+	types::{
+		Condition, CorsConfiguration, CorsRule, ErrorDocument, IndexDocument, Protocol, Redirect,
+		RedirectAllRequestsTo, RoutingRule, WebsiteConfiguration,
+	},
 };
 use http::{Request, StatusCode};
 use hyper::{
@@ -329,7 +332,29 @@ async fn test_website_s3_api() {
 		);
 	}
 
-	//@TODO test CORS on the S3 endpoint. We need to handle auth manually to check it.
+	// Test CORS preflight requests on the S3 endpoint, which are not authenticated
+	for (method, status) in [("PUT", StatusCode::OK), ("DELETE", StatusCode::FORBIDDEN)] {
+		let req = Request::builder()
+			.method("OPTIONS")
+			.uri(format!(
+				"http://127.0.0.1:{}/{}/site/",
+				ctx.garage.s3_port, BCKT_NAME
+			))
+			.header("Origin", "https://example.com")
+			.header("Access-Control-Request-Method", method)
+			.body(Body::empty())
+			.unwrap();
+
+		let resp = client.request(req).await.unwrap();
+
+		assert_eq!(resp.status(), status);
+		assert_eq!(
+			resp.headers().contains_key("access-control-allow-origin"),
+			status == StatusCode::OK
+		);
+	}
+
+	//@TODO test CORS on actual requests to the S3 endpoint. We need to handle auth manually to check it.
 
 	// Delete cors
 	ctx.client
@@ -401,6 +426,115 @@ async fn test_website_s3_api() {
 	}
 }
 
+#[tokio::test]
+async fn test_website_redirects() {
+	const BCKT_NAME: &str = "my-redirects";
+	let ctx = common::context();
+	let bucket = ctx.create_bucket(BCKT_NAME);
+
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("index.html")
+		.body(ByteStream::from_static(BODY))
+		.send()
+		.await
+		.unwrap();
+
+	let conf = WebsiteConfiguration::builder()
+		.index_document(IndexDocument::builder().suffix("index.html").build())
+		.routing_rules(
+			RoutingRule::builder()
+				.condition(Condition::builder().key_prefix_equals("docs/").build())
+				.redirect(
+					Redirect::builder()
+						.replace_key_prefix_with("documents/")
+						.http_redirect_code("302")
+						.build(),
+				)
+				.build(),
+		)
+		.routing_rules(
+			RoutingRule::builder()
+				.condition(
+					Condition::builder()
+						.http_error_code_returned_equals("404")
+						.build(),
+				)
+				.redirect(
+					Redirect::builder()
+						.host_name("fallback.example.com")
+						.protocol(Protocol::Https)
+						.replace_key_with("not-found.html")
+						.build(),
+				)
+				.build(),
+		)
+		.build();
+
+	ctx.client
+		.put_bucket_website()
+		.bucket(&bucket)
+		.website_configuration(conf)
+		.send()
+		.await
+		.unwrap();
+
+	let client = Client::new();
+	let get = |path: &str| {
+		Request::builder()
+			.method("GET")
+			.uri(format!("http://127.0.0.1:{}{}", ctx.garage.web_port, path))
+			.header("Host", format!("{}.web.garage", BCKT_NAME))
+			.body(Body::empty())
+			.unwrap()
+	};
+
+	// Existing objects are served normally
+	let resp = client.request(get("/")).await.unwrap();
+	assert_eq!(resp.status(), StatusCode::OK);
+
+	// Prefix rules apply before looking up the object
+	let resp = client.request(get("/docs/a/b.html")).await.unwrap();
+	assert_eq!(resp.status(), StatusCode::FOUND);
+	assert_eq!(
+		resp.headers().get(hyper::header::LOCATION).unwrap(),
+		"/documents/a/b.html"
+	);
+
+	// Error code rules apply to missing objects
+	let resp = client.request(get("/missing.html")).await.unwrap();
+	assert_eq!(resp.status(), StatusCode::MOVED_PERMANENTLY);
+	assert_eq!(
+		resp.headers().get(hyper::header::LOCATION).unwrap(),
+		"https://fallback.example.com/not-found.html"
+	);
+
+	// Redirect all requests to another host
+	let conf = WebsiteConfiguration::builder()
+		.redirect_all_requests_to(
+			RedirectAllRequestsTo::builder()
+				.host_name("www.example.com")
+				.build(),
+		)
+		.build();
+
+	ctx.client
+		.put_bucket_website()
+		.bucket(&bucket)
+		.website_configuration(conf)
+		.send()
+		.await
+		.unwrap();
+
+	let resp = client.request(get("/index.html?a=b")).await.unwrap();
+	assert_eq!(resp.status(), StatusCode::MOVED_PERMANENTLY);
+	assert_eq!(
+		resp.headers().get(hyper::header::LOCATION).unwrap(),
+		"//www.example.com/index.html?a=b"
+	);
+}
+
 #[tokio::test]
 async fn test_website_check_domain() {
 	let ctx = common::context();
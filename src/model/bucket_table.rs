@@ -4,9 +4,11 @@ use garage_util::data::*;
 use garage_util::time::*;
 
 use crate::permission::BucketKeyPerm;
+use crate::s3::object_table::ObjectTags;
 
 mod v08 {
 	use crate::permission::BucketKeyPerm;
+	use crate::s3::object_table::ObjectLockMode;
 	use garage_util::crdt;
 	use garage_util::data::Uuid;
 	use serde::{Deserialize, Serialize};
@@ -51,12 +53,111 @@ mod v08 {
 		/// Bucket quotas
 		#[serde(default)]
 		pub quotas: crdt::Lww<BucketQuotas>,
+		/// Whether uploads to this bucket must request server-side encryption
+		#[serde(default)]
+		pub encryption_required: crdt::Lww<bool>,
+		/// Server-side encryption configuration, as set by PutBucketEncryption
+		#[serde(default)]
+		pub encryption_config: crdt::Lww<Option<EncryptionConfig>>,
+		/// Maximum size of objects uploaded in a single PutObject request
+		/// (multipart uploads are not subject to this limit)
+		#[serde(default)]
+		pub max_object_size: crdt::Lww<Option<u64>>,
+		/// Zstd compression level of the data blocks of objects uploaded to this bucket,
+		/// overriding the `compression_level` of the node that receives the upload
+		#[serde(default)]
+		pub compression_level: crdt::Lww<Option<i32>>,
+		/// Lifecycle configuration, as set by PutBucketLifecycleConfiguration
+		#[serde(default)]
+		pub lifecycle_config: crdt::Lww<Option<Vec<LifecycleRule>>>,
+		/// Versioning state, as set by PutBucketVersioning
+		#[serde(default)]
+		pub versioning_state: crdt::Lww<VersioningState>,
+		/// Object lock configuration, as set by PutObjectLockConfiguration
+		/// (once object lock is enabled on a bucket, it cannot be disabled)
+		#[serde(default)]
+		pub object_lock_config: crdt::Lww<Option<ObjectLockConfig>>,
+		/// Access logging configuration, as set by PutBucketLogging
+		#[serde(default)]
+		pub logging_config: crdt::Lww<Option<LoggingConfig>>,
+		/// Rules to replicate objects to other Garage clusters
+		#[serde(default)]
+		pub replication_config: crdt::Lww<Option<Vec<ReplicationRule>>>,
+		/// Notifications sent when objects are created or removed,
+		/// as set by PutBucketNotificationConfiguration
+		#[serde(default)]
+		pub notification_config: crdt::Lww<Option<Vec<NotificationConfig>>>,
+		/// Bucket policy, as the JSON document given to PutBucketPolicy
+		#[serde(default)]
+		pub policy: crdt::Lww<Option<String>>,
+	}
+
+	/// Versioning state of a bucket
+	#[derive(
+		Default, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize,
+	)]
+	pub enum VersioningState {
+		/// Versioning has never been enabled on the bucket
+		#[default]
+		Disabled,
+		/// New object versions are kept when they are overwritten or deleted
+		Enabled,
+		/// New object versions replace the previous unversioned version,
+		/// but versions created while versioning was enabled are kept
+		Suspended,
 	}
 
 	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
 	pub struct WebsiteConfig {
 		pub index_document: String,
 		pub error_document: Option<String>,
+		/// Redirect all requests to another host, as set by RedirectAllRequestsTo
+		#[serde(default)]
+		pub redirect_all: Option<RedirectAll>,
+		/// Rules to redirect some requests, evaluated in order
+		#[serde(default)]
+		pub routing_rules: Vec<RoutingRule>,
+	}
+
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct RedirectAll {
+		pub hostname: String,
+		/// Protocol of redirections (http or https), by default the protocol of the request
+		pub protocol: Option<String>,
+	}
+
+	/// Website routing rule: requests that match the condition are redirected
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct RoutingRule {
+		pub condition: Option<RedirectCondition>,
+		pub redirect: Redirect,
+	}
+
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct RedirectCondition {
+		/// Only redirect requests that would have returned this error code
+		pub http_error_code: Option<u16>,
+		/// Only redirect requests for keys that start with this prefix
+		pub prefix: Option<String>,
+	}
+
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct Redirect {
+		/// Host name of redirections, by default the host of the request
+		pub hostname: Option<String>,
+		/// Protocol of redirections (http or https), by default the protocol of the request
+		pub protocol: Option<String>,
+		pub http_redirect_code: u16,
+		/// Replacement of the key in redirections, by default the key is kept as is
+		pub replace_key: Option<RedirectReplaceKey>,
+	}
+
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub enum RedirectReplaceKey {
+		/// Replace the prefix of the condition with this one
+		Prefix(String),
+		/// Replace the whole key with this one
+		Full(String),
 	}
 
 	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
@@ -69,6 +170,137 @@ mod v08 {
 		pub expose_headers: Vec<String>,
 	}
 
+	/// Lifecycle configuration rule
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct LifecycleRule {
+		/// The ID of the rule
+		pub id: Option<String>,
+		/// Whether the rule is active
+		pub enabled: bool,
+		/// The filter to check whether rule applies to a given object
+		pub filter: LifecycleFilter,
+		/// Number of days after which incomplete multipart uploads are aborted
+		pub abort_incomplete_mpu_days: Option<usize>,
+		/// Expiration policy for stored objects
+		pub expiration: Option<LifecycleExpiration>,
+		/// Number of days after which versions that are no longer the current
+		/// version of their object are deleted permanently
+		#[serde(default)]
+		pub noncurrent_expiration_days: Option<usize>,
+	}
+
+	/// A lifecycle filter is a set of conditions that must all be true.
+	/// For each condition, if it is None, it is not verified (always true),
+	/// and if it is Some(x), then it is verified for value x
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize, Default)]
+	pub struct LifecycleFilter {
+		/// If Some(x), object key has to start with prefix x
+		pub prefix: Option<String>,
+		/// If Some(x), object size has to be more than x
+		pub size_gt: Option<u64>,
+		/// If Some(x), object size has to be less than x
+		pub size_lt: Option<u64>,
+		/// Tags that the object has to have, with the same values
+		#[serde(default)]
+		pub tags: Vec<(String, String)>,
+	}
+
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub enum LifecycleExpiration {
+		/// Objects expire x days after they were created
+		AfterDays(usize),
+		/// Objects expire at date x (must be in yyyy-mm-dd format)
+		AtDate(String),
+	}
+
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct EncryptionConfig {
+		/// Server-side encryption algorithm (AES256 or aws:kms)
+		pub sse_algorithm: String,
+		/// KMS key to be used with the aws:kms algorithm
+		pub kms_master_key_id: Option<String>,
+	}
+
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct ObjectLockConfig {
+		/// Retention applied to new object versions that do not specify one
+		pub default_retention: Option<DefaultRetention>,
+	}
+
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct DefaultRetention {
+		/// Retention mode
+		pub mode: ObjectLockMode,
+		/// Number of days during which new object versions are protected
+		pub days: u64,
+	}
+
+	/// Rule to replicate objects to a bucket of another Garage cluster,
+	/// through its S3 API
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct ReplicationRule {
+		/// The ID of the rule, unique in the bucket
+		pub id: String,
+		/// Whether the rule is active
+		pub enabled: bool,
+		/// Only objects whose key starts with one of these prefixes are replicated;
+		/// all objects are replicated if this is empty
+		pub prefixes: Vec<String>,
+		/// Whether objects are deleted from the destination
+		/// when they are deleted from this bucket
+		pub replicate_deletes: bool,
+		/// Bucket to which objects are replicated
+		pub destination: ReplicationDestination,
+	}
+
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct ReplicationDestination {
+		/// URL of the S3 API endpoint of the destination cluster
+		pub endpoint: String,
+		/// S3 region of the destination cluster
+		pub region: String,
+		/// Name of the bucket in the destination cluster
+		pub bucket: String,
+		/// Access key used to write to the destination bucket
+		pub access_key_id: String,
+		pub secret_access_key: String,
+	}
+
+	/// Notification of the changes of the objects of a bucket,
+	/// sent by an HTTP POST request to a webhook or published to a NATS subject
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct NotificationConfig {
+		/// The ID of the configuration, unique in the bucket
+		pub id: String,
+		/// Events for which a notification is sent
+		pub events: Vec<NotificationEvent>,
+		/// URL of the webhook or `nats://` URL of the NATS subject
+		/// to which notifications are sent
+		pub endpoint_url: String,
+		/// Only changes of objects whose key starts with this prefix are notified
+		pub filter_prefix: Option<String>,
+	}
+
+	/// Type of the events that can be notified. The `...All` variants are
+	/// only used in configurations, to match all the events of their kind.
+	#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize)]
+	pub enum NotificationEvent {
+		ObjectCreatedAll,
+		ObjectCreatedPut,
+		ObjectCreatedCompleteMultipartUpload,
+		ObjectRemovedAll,
+		ObjectRemovedDelete,
+		ObjectRemovedDeleteMarkerCreated,
+	}
+
+	#[derive(PartialEq, Eq, Hash, Clone, Debug, Serialize, Deserialize)]
+	pub struct LoggingConfig {
+		/// Global name of the bucket in which access logs are written
+		pub target_bucket: String,
+		/// Prefix of the keys of the access log objects
+		pub target_prefix: String,
+	}
+
 	#[derive(Default, PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
 	pub struct BucketQuotas {
 		/// Maximum size in bytes (bucket size = sum of sizes of objects in the bucket)
@@ -82,6 +314,146 @@ mod v08 {
 
 pub use v08::*;
 
+impl LifecycleRule {
+	/// Returns true if this rule is enabled and its filter matches
+	/// an object with the given key, size and tags
+	pub fn applies_to(&self, key: &str, size: u64, tags: &ObjectTags) -> bool {
+		self.enabled && self.filter.applies_to(key, size, tags)
+	}
+}
+
+impl ReplicationRule {
+	/// Returns true if this rule is enabled and replicates objects with the given key
+	pub fn applies_to(&self, key: &str) -> bool {
+		self.enabled
+			&& (self.prefixes.is_empty() || self.prefixes.iter().any(|p| key.starts_with(p)))
+	}
+}
+
+impl NotificationConfig {
+	/// Returns true if this configuration sends a notification
+	/// for an event of the given type on the object with the given key
+	pub fn applies_to(&self, key: &str, event: NotificationEvent) -> bool {
+		self.events.iter().any(|e| e.matches(event))
+			&& self
+				.filter_prefix
+				.as_ref()
+				.map(|p| key.starts_with(p.as_str()))
+				.unwrap_or(true)
+	}
+}
+
+impl NotificationEvent {
+	const ALL: &'static [NotificationEvent] = &[
+		NotificationEvent::ObjectCreatedAll,
+		NotificationEvent::ObjectCreatedPut,
+		NotificationEvent::ObjectCreatedCompleteMultipartUpload,
+		NotificationEvent::ObjectRemovedAll,
+		NotificationEvent::ObjectRemovedDelete,
+		NotificationEvent::ObjectRemovedDeleteMarkerCreated,
+	];
+
+	/// Name of the event type in the S3 API, without the `s3:` prefix
+	/// that is used in notification configurations
+	pub fn name(self) -> &'static str {
+		match self {
+			NotificationEvent::ObjectCreatedAll => "ObjectCreated:*",
+			NotificationEvent::ObjectCreatedPut => "ObjectCreated:Put",
+			NotificationEvent::ObjectCreatedCompleteMultipartUpload => {
+				"ObjectCreated:CompleteMultipartUpload"
+			}
+			NotificationEvent::ObjectRemovedAll => "ObjectRemoved:*",
+			NotificationEvent::ObjectRemovedDelete => "ObjectRemoved:Delete",
+			NotificationEvent::ObjectRemovedDeleteMarkerCreated => {
+				"ObjectRemoved:DeleteMarkerCreated"
+			}
+		}
+	}
+
+	/// Parse the name of an event type, as given in notification configurations
+	pub fn parse(name: &str) -> Option<Self> {
+		let name = name.strip_prefix("s3:")?;
+		Self::ALL.iter().find(|e| e.name() == name).copied()
+	}
+
+	/// Returns true if this event type of a configuration matches `event`
+	pub fn matches(self, event: NotificationEvent) -> bool {
+		match self {
+			NotificationEvent::ObjectCreatedAll => event.name().starts_with("ObjectCreated:"),
+			NotificationEvent::ObjectRemovedAll => event.name().starts_with("ObjectRemoved:"),
+			_ => self == event,
+		}
+	}
+}
+
+impl LifecycleFilter {
+	/// Returns true if all of the conditions of the filter hold
+	/// for an object with the given key, size and tags
+	pub fn applies_to(&self, key: &str, size: u64, tags: &ObjectTags) -> bool {
+		if let Some(prefix) = &self.prefix {
+			if !key.starts_with(prefix) {
+				return false;
+			}
+		}
+		if let Some(size_gt) = self.size_gt {
+			if size <= size_gt {
+				return false;
+			}
+		}
+		if let Some(size_lt) = self.size_lt {
+			if size >= size_lt {
+				return false;
+			}
+		}
+		tags.contains_all(&self.tags)
+	}
+}
+
+impl RoutingRule {
+	/// Check whether this rule applies to a request for `key`: before the object
+	/// is looked up if `error_code` is `None`, or after the lookup failed with
+	/// HTTP status code `error_code`
+	pub fn applies_to(&self, key: &str, error_code: Option<u16>) -> bool {
+		match &self.condition {
+			None => error_code.is_none(),
+			Some(cond) => {
+				cond.http_error_code == error_code
+					&& cond
+						.prefix
+						.as_ref()
+						.map(|p| key.starts_with(p.as_str()))
+						.unwrap_or(true)
+			}
+		}
+	}
+
+	/// Get the key to which a request for `key` is redirected by this rule
+	pub fn redirect_key(&self, key: &str) -> String {
+		match &self.redirect.replace_key {
+			None => key.to_string(),
+			Some(RedirectReplaceKey::Full(k)) => k.clone(),
+			Some(RedirectReplaceKey::Prefix(p)) => {
+				let prefix_len = self
+					.condition
+					.as_ref()
+					.and_then(|c| c.prefix.as_ref())
+					.map(|p| p.len())
+					.unwrap_or(0);
+				format!("{}{}", p, &key[prefix_len..])
+			}
+		}
+	}
+}
+
+/// Parse a date given in a lifecycle rule, in yyyy-mm-dd format
+pub fn parse_lifecycle_date(date: &str) -> Option<chrono::NaiveDate> {
+	chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()
+}
+
+impl AutoCrdt for VersioningState {
+	const WARN_IF_DIFFERENT: bool = true;
+}
+
 impl AutoCrdt for BucketQuotas {
 	const WARN_IF_DIFFERENT: bool = true;
 }
@@ -97,6 +469,17 @@ impl BucketParams {
 			website_config: crdt::Lww::new(None),
 			cors_config: crdt::Lww::new(None),
 			quotas: crdt::Lww::new(BucketQuotas::default()),
+			encryption_required: crdt::Lww::new(false),
+			encryption_config: crdt::Lww::new(None),
+			max_object_size: crdt::Lww::new(None),
+			compression_level: crdt::Lww::new(None),
+			lifecycle_config: crdt::Lww::new(None),
+			versioning_state: crdt::Lww::new(VersioningState::Disabled),
+			object_lock_config: crdt::Lww::new(None),
+			logging_config: crdt::Lww::new(None),
+			replication_config: crdt::Lww::new(None),
+			notification_config: crdt::Lww::new(None),
+			policy: crdt::Lww::new(None),
 		}
 	}
 }
@@ -112,6 +495,17 @@ impl Crdt for BucketParams {
 		self.website_config.merge(&o.website_config);
 		self.cors_config.merge(&o.cors_config);
 		self.quotas.merge(&o.quotas);
+		self.encryption_required.merge(&o.encryption_required);
+		self.encryption_config.merge(&o.encryption_config);
+		self.max_object_size.merge(&o.max_object_size);
+		self.compression_level.merge(&o.compression_level);
+		self.lifecycle_config.merge(&o.lifecycle_config);
+		self.versioning_state.merge(&o.versioning_state);
+		self.object_lock_config.merge(&o.object_lock_config);
+		self.logging_config.merge(&o.logging_config);
+		self.replication_config.merge(&o.replication_config);
+		self.notification_config.merge(&o.notification_config);
+		self.policy.merge(&o.policy);
 	}
 }
 
@@ -163,6 +557,22 @@ impl Bucket {
 		self.params().map(|s| s.aliases.items()).unwrap_or(&[])
 	}
 
+	/// Returns true if new object versions written to this bucket
+	/// must be kept when they are overwritten or deleted
+	pub fn versioning_enabled(&self) -> bool {
+		self.params()
+			.map(|p| *p.versioning_state.get() == VersioningState::Enabled)
+			.unwrap_or(false)
+	}
+
+	/// Returns true if object lock is enabled on this bucket, in which case
+	/// object versions can be protected from deletion
+	pub fn object_lock_enabled(&self) -> bool {
+		self.params()
+			.map(|p| p.object_lock_config.get().is_some())
+			.unwrap_or(false)
+	}
+
 	pub fn local_aliases(&self) -> &[((String, String), u64, bool)] {
 		self.params()
 			.map(|s| s.local_aliases.items())
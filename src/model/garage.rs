@@ -1,3 +1,4 @@
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use netapp::NetworkKey;
@@ -7,17 +8,27 @@ use garage_db as db;
 use garage_util::background::*;
 use garage_util::config::*;
 use garage_util::error::*;
+use garage_util::persister::PersisterShared;
+use garage_util::time::msec_to_rfc3339;
 
 use garage_rpc::replication_mode::ReplicationMode;
 use garage_rpc::system::System;
 
+use garage_block::encryption::BlockEncryption;
+use garage_block::layout::DataLayout;
 use garage_block::manager::*;
 use garage_table::replication::TableFullReplication;
 use garage_table::replication::TableShardedReplication;
 use garage_table::*;
 
 use crate::s3::block_ref_table::*;
+use crate::s3::lifecycle_worker::{LifecycleWorker, LifecycleWorkerPersisted};
+use crate::s3::mpu_cleanup_worker::{MpuCleanupWorker, MpuCleanupWorkerPersisted};
+use crate::s3::notification::{NotificationQueue, NotificationWorker};
 use crate::s3::object_table::*;
+use crate::s3::replication_state_table::*;
+use crate::s3::replication_worker::{ReplicationWorker, ReplicationWorkerPersisted};
+use crate::s3::restore_worker::{RestoreQueue, RestoreWorker};
 use crate::s3::version_table::*;
 
 use crate::bucket_alias_table::*;
@@ -27,7 +38,10 @@ use crate::index_counter::*;
 use crate::key_table::*;
 
 #[cfg(feature = "k2v")]
-use crate::k2v::{item_table::*, rpc::*, sub::*};
+use garage_util::data::Uuid;
+
+#[cfg(feature = "k2v")]
+use crate::k2v::{causality::*, item_table::*, rpc::*, sub::*, ttl_worker::*};
 
 /// An entire Garage full of data
 pub struct Garage {
@@ -61,6 +75,19 @@ pub struct Garage {
 	pub version_table: Arc<Table<VersionTable, TableShardedReplication>>,
 	/// Table containing S3 block references (not blocks themselves)
 	pub block_ref_table: Arc<Table<BlockRefTable, TableShardedReplication>>,
+	/// Table containing the state of the replication of S3 objects to other clusters
+	pub replication_state_table: Arc<Table<ReplicationStateTable, TableShardedReplication>>,
+
+	/// Persisted state of the lifecycle worker
+	pub lifecycle_persister: PersisterShared<LifecycleWorkerPersisted>,
+	/// Persisted state of the multipart upload cleanup worker
+	pub mpu_cleanup_persister: PersisterShared<MpuCleanupWorkerPersisted>,
+	/// Persisted state of the bucket replication worker
+	pub replication_persister: PersisterShared<ReplicationWorkerPersisted>,
+	/// Restores of GLACIER objects requested on this node and not completed yet
+	pub restore_queue: RestoreQueue,
+	/// Changes of objects to be sent to the notification webhooks of their bucket
+	pub notification_queue: Arc<NotificationQueue>,
 
 	#[cfg(feature = "k2v")]
 	pub k2v: GarageK2V,
@@ -82,91 +109,11 @@ impl Garage {
 		// Create meta dir and data dir if they don't exist already
 		std::fs::create_dir_all(&config.metadata_dir)
 			.ok_or_message("Unable to create Garage metadata directory")?;
-		std::fs::create_dir_all(&config.data_dir)
-			.ok_or_message("Unable to create Garage data directory")?;
+		let data_layout = DataLayout::new(&config.data_dir, config.data_dir_placement)?;
+		data_layout.create_dirs()?;
 
 		info!("Opening database...");
-		let mut db_path = config.metadata_dir.clone();
-		let db = match config.db_engine.as_str() {
-			// ---- Sled DB ----
-			#[cfg(feature = "sled")]
-			"sled" => {
-				db_path.push("db");
-				info!("Opening Sled database at: {}", db_path.display());
-				let db = db::sled_adapter::sled::Config::default()
-					.path(&db_path)
-					.cache_capacity(config.sled_cache_capacity)
-					.flush_every_ms(Some(config.sled_flush_every_ms))
-					.open()
-					.ok_or_message("Unable to open sled DB")?;
-				db::sled_adapter::SledDb::init(db)
-			}
-			#[cfg(not(feature = "sled"))]
-			"sled" => return Err(Error::Message("sled db not available in this build".into())),
-			// ---- Sqlite DB ----
-			#[cfg(feature = "sqlite")]
-			"sqlite" | "sqlite3" | "rusqlite" => {
-				db_path.push("db.sqlite");
-				info!("Opening Sqlite database at: {}", db_path.display());
-				let db = db::sqlite_adapter::rusqlite::Connection::open(db_path)
-					.ok_or_message("Unable to open sqlite DB")?;
-				db::sqlite_adapter::SqliteDb::init(db)
-			}
-			#[cfg(not(feature = "sqlite"))]
-			"sqlite" | "sqlite3" | "rusqlite" => {
-				return Err(Error::Message(
-					"sqlite db not available in this build".into(),
-				))
-			}
-			// ---- LMDB DB ----
-			#[cfg(feature = "lmdb")]
-			"lmdb" | "heed" => {
-				db_path.push("db.lmdb");
-				info!("Opening LMDB database at: {}", db_path.display());
-				std::fs::create_dir_all(&db_path)
-					.ok_or_message("Unable to create LMDB data directory")?;
-				let map_size = garage_db::lmdb_adapter::recommended_map_size();
-
-				use db::lmdb_adapter::heed;
-				let mut env_builder = heed::EnvOpenOptions::new();
-				env_builder.max_dbs(100);
-				env_builder.max_readers(500);
-				env_builder.map_size(map_size);
-				unsafe {
-					env_builder.flag(heed::flags::Flags::MdbNoSync);
-					env_builder.flag(heed::flags::Flags::MdbNoMetaSync);
-				}
-				let db = match env_builder.open(&db_path) {
-					Err(heed::Error::Io(e)) if e.kind() == std::io::ErrorKind::OutOfMemory => {
-						return Err(Error::Message(
-							"OutOfMemory error while trying to open LMDB database. This can happen \
-							if your operating system is not allowing you to use sufficient virtual \
-							memory address space. Please check that no limit is set (ulimit -v). \
-							On 32-bit machines, you should probably switch to another database engine.".into()))
-					}
-					x => x.ok_or_message("Unable to open LMDB DB")?,
-				};
-				db::lmdb_adapter::LmdbDb::init(db)
-			}
-			#[cfg(not(feature = "lmdb"))]
-			"lmdb" | "heed" => return Err(Error::Message("lmdb db not available in this build".into())),
-			// ---- Unavailable DB engine ----
-			e => {
-				return Err(Error::Message(format!(
-					"Unsupported DB engine: {} (options: {})",
-					e,
-					vec![
-						#[cfg(feature = "sled")]
-						"sled",
-						#[cfg(feature = "sqlite")]
-						"sqlite",
-						#[cfg(feature = "lmdb")]
-						"lmdb",
-					]
-					.join(", ")
-				)));
-			}
-		};
+		let db = open_db(&config, &db_path(&config))?;
 
 		let network_key = hex::decode(config.rpc_secret.as_ref().ok_or_message(
 			"rpc_secret value is missing, not present in config file or in environment",
@@ -184,15 +131,22 @@ impl Garage {
 		let data_rep_param = TableShardedReplication {
 			system: system.clone(),
 			replication_factor: replication_mode.replication_factor(),
-			write_quorum: replication_mode.write_quorum(),
+			write_quorum: replication_mode.data_write_quorum(),
 			read_quorum: 1,
 		};
 
+		let meta_read_quorum = match &config.data_read_quorum {
+			Some(v) => replication_mode
+				.parse_read_quorum(v)
+				.ok_or_message("Invalid data_read_quorum in config file.")?,
+			None => replication_mode.read_quorum(),
+		};
+
 		let meta_rep_param = TableShardedReplication {
 			system: system.clone(),
 			replication_factor: replication_mode.replication_factor(),
 			write_quorum: replication_mode.write_quorum(),
-			read_quorum: replication_mode.read_quorum(),
+			read_quorum: meta_read_quorum,
 		};
 
 		let control_rep_param = TableFullReplication {
@@ -201,10 +155,35 @@ impl Garage {
 		};
 
 		info!("Initialize block manager...");
+		if let Some(v) = config.block_format_version {
+			if v > garage_block::manager::MAX_BLOCK_FORMAT_VERSION {
+				return Err(Error::Message(format!(
+					"Unsupported block_format_version: {} (maximum: {})",
+					v,
+					garage_block::manager::MAX_BLOCK_FORMAT_VERSION
+				)));
+			}
+		}
+		let encryption = match &config.encryption_key {
+			Some(key) => Some(
+				BlockEncryption::new(key, &config.previous_encryption_keys)
+					.err_context("Invalid encryption_key or previous_encryption_keys")?,
+			),
+			None if !config.previous_encryption_keys.is_empty() => {
+				return Err(Error::Message(
+					"previous_encryption_keys is set, but encryption_key is not".into(),
+				));
+			}
+			None => None,
+		};
 		let block_manager = BlockManager::new(
 			&db,
-			config.data_dir.clone(),
+			data_layout,
 			config.compression_level,
+			config.block_file_mode,
+			config.block_format_version,
+			encryption,
+			replication_mode.erasure_coding(),
 			data_rep_param,
 			system.clone(),
 		);
@@ -247,25 +226,91 @@ impl Garage {
 		info!("Initialize object counter table...");
 		let object_counter_table = IndexCounter::new(system.clone(), meta_rep_param.clone(), &db);
 
+		let notification_queue = Arc::new(NotificationQueue::new(
+			meta_rep_param.clone(),
+			config.notification_queue_length,
+		));
+
 		info!("Initialize object_table...");
 		#[allow(clippy::redundant_clone)]
 		let object_table = Table::new(
 			ObjectTable {
 				version_table: version_table.clone(),
 				object_counter_table: object_counter_table.clone(),
+				notification_queue: notification_queue.clone(),
 			},
 			meta_rep_param.clone(),
 			system.clone(),
 			&db,
 		);
 
+		info!("Initialize replication_state_table...");
+		let replication_state_table = Table::new(
+			ReplicationStateTable,
+			meta_rep_param.clone(),
+			system.clone(),
+			&db,
+		);
+
 		// ---- K2V ----
 		#[cfg(feature = "k2v")]
 		let k2v = GarageK2V::new(system.clone(), &db, meta_rep_param);
 
+		// ---- Lifecycle worker state ----
+		let lifecycle_persister: PersisterShared<LifecycleWorkerPersisted> =
+			PersisterShared::new(&system.metadata_dir, "lifecycle_worker");
+		let mpu_cleanup_persister: PersisterShared<MpuCleanupWorkerPersisted> =
+			PersisterShared::new(&system.metadata_dir, "mpu_cleanup_worker");
+		let replication_persister: PersisterShared<ReplicationWorkerPersisted> =
+			PersisterShared::new(&system.metadata_dir, "replication_worker");
+
 		// Initialize bg vars
 		let mut bg_vars = vars::BgVars::new();
 		block_manager.register_bg_vars(&mut bg_vars);
+		bg_vars.register_rw(
+			&lifecycle_persister,
+			"lifecycle-tranquility",
+			|p| p.get_with(|x| x.tranquility),
+			|p, tranquility| p.set_with(|x| x.tranquility = tranquility),
+		);
+		bg_vars.register_ro(&lifecycle_persister, "lifecycle-last-completed", |p| {
+			p.get_with(|x| x.last_completed.clone().unwrap_or_else(|| "never".into()))
+		});
+		bg_vars.register_rw(
+			&mpu_cleanup_persister,
+			"mpu-cleanup-tranquility",
+			|p| p.get_with(|x| x.tranquility),
+			|p, tranquility| p.set_with(|x| x.tranquility = tranquility),
+		);
+		bg_vars.register_ro(&mpu_cleanup_persister, "mpu-cleanup-last-completed", |p| {
+			p.get_with(|x| {
+				x.last_completed
+					.map(msec_to_rfc3339)
+					.unwrap_or_else(|| "never".into())
+			})
+		});
+		bg_vars.register_rw(
+			&replication_persister,
+			"replication-tranquility",
+			|p| p.get_with(|x| x.tranquility),
+			|p, tranquility| p.set_with(|x| x.tranquility = tranquility),
+		);
+		bg_vars.register_rw(
+			&replication_persister,
+			"replication-interval-secs",
+			|p| p.get_with(|x| x.interval_secs),
+			|p, interval_secs| p.set_with(|x| x.interval_secs = interval_secs),
+		);
+		bg_vars.register_ro(&replication_persister, "replication-last-completed", |p| {
+			p.get_with(|x| {
+				x.last_completed
+					.map(msec_to_rfc3339)
+					.unwrap_or_else(|| "never".into())
+			})
+		});
+		bg_vars.set_expiry_tree(db.open_tree("bg_var_expiry")?);
+
+		let restore_queue = RestoreQueue::new(&db)?;
 
 		// -- done --
 		Ok(Arc::new(Self {
@@ -282,13 +327,26 @@ impl Garage {
 			object_counter_table,
 			version_table,
 			block_ref_table,
+			replication_state_table,
+			lifecycle_persister,
+			mpu_cleanup_persister,
+			replication_persister,
+			restore_queue,
+			notification_queue,
 			#[cfg(feature = "k2v")]
 			k2v,
 		}))
 	}
 
-	pub fn spawn_workers(&self, bg: &BackgroundRunner) {
-		self.block_manager.spawn_workers(bg);
+	pub fn spawn_workers(self: &Arc<Self>, bg: &BackgroundRunner) {
+		// Gateway nodes hold no data blocks: the block manager is only used
+		// to read and write blocks on other nodes, and needs no workers
+		if self.config.node_role.stores_data() {
+			self.block_manager.spawn_workers(bg);
+		} else {
+			info!("Gateway node: block manager workers are not started");
+		}
+		self.bg_vars.spawn_workers(bg);
 
 		self.bucket_table.spawn_workers(bg);
 		self.bucket_alias_table.spawn_workers(bg);
@@ -298,11 +356,57 @@ impl Garage {
 		self.object_counter_table.spawn_workers(bg);
 		self.version_table.spawn_workers(bg);
 		self.block_ref_table.spawn_workers(bg);
+		self.replication_state_table.spawn_workers(bg);
+
+		bg.spawn_worker(LifecycleWorker::new(
+			self.clone(),
+			self.lifecycle_persister.clone(),
+		));
+		bg.spawn_worker(MpuCleanupWorker::new(
+			self.clone(),
+			self.mpu_cleanup_persister.clone(),
+		));
+		bg.spawn_worker(ReplicationWorker::new(
+			self.clone(),
+			self.replication_persister.clone(),
+		));
+		bg.spawn_worker(RestoreWorker::new(self.clone()));
+		bg.spawn_worker(NotificationWorker::new(self.clone()));
 
 		#[cfg(feature = "k2v")]
 		self.k2v.spawn_workers(bg);
 	}
 
+	/// Apply the fields of a configuration read again from the configuration file
+	/// that can be changed at runtime (see `HOT_RELOADABLE_FIELDS`), and return
+	/// the fields that differ from the running configuration but are only taken
+	/// into account on restart. The log filter is applied by the caller.
+	pub fn apply_config_update(&self, new_config: Config) -> Vec<&'static str> {
+		let changed = self.config.changed_fields(&new_config);
+
+		let compression_level = self.block_manager.compression_level();
+		if new_config.compression_level != compression_level {
+			info!(
+				"Changing compression level from {:?} to {:?}",
+				compression_level, new_config.compression_level
+			);
+			self.block_manager
+				.set_compression_level(new_config.compression_level);
+		}
+
+		let ignored = changed
+			.into_iter()
+			.filter(|f| !HOT_RELOADABLE_FIELDS.contains(f))
+			.collect::<Vec<_>>();
+		for field in ignored.iter() {
+			warn!(
+				"Configuration field `{}` was changed, but it is only taken into account when Garage is restarted",
+				field
+			);
+		}
+		ignored
+	}
+
 	pub fn bucket_helper(&self) -> helper::bucket::BucketHelper {
 		helper::bucket::BucketHelper(self)
 	}
@@ -345,5 +449,140 @@ impl GarageK2V {
 	pub fn spawn_workers(&self, bg: &BackgroundRunner) {
 		self.item_table.spawn_workers(bg);
 		self.counter_table.spawn_workers(bg);
+		bg.spawn_worker(K2VTtlWorker::new(self.item_table.clone(), self.rpc.clone()));
 	}
+
+	/// Read an item and replace its values by the one computed by `f` from
+	/// the current state of the item, provided that no write happened on the
+	/// item since `causality_token` was obtained (`f` is not called otherwise).
+	pub async fn atomic_update(
+		&self,
+		bucket_id: Uuid,
+		partition_key: String,
+		sort_key: String,
+		causality_token: CausalContext,
+		f: impl FnOnce(Option<&K2VItem>) -> DvvsValue,
+	) -> Result<CasResult, Error> {
+		let current = self
+			.item_table
+			.get(
+				&K2VItemPartition {
+					bucket_id,
+					partition_key: partition_key.clone(),
+				},
+				&sort_key,
+			)
+			.await?;
+
+		let current_causality = current
+			.as_ref()
+			.map(K2VItem::causal_context)
+			.unwrap_or_default();
+		if current_causality != causality_token {
+			return Ok(CasResult::CasFailed { current_causality });
+		}
+
+		let value = f(current.as_ref());
+		self.rpc
+			.compare_and_swap(bucket_id, partition_key, sort_key, causality_token, value)
+			.await
+	}
+}
+
+/// Get the path of the metadata database for the DB engine set in the configuration
+pub fn db_path(config: &Config) -> PathBuf {
+	let mut db_path = config.metadata_dir.clone();
+	match config.db_engine.as_str() {
+		"sqlite" | "sqlite3" | "rusqlite" => db_path.push("db.sqlite"),
+		"lmdb" | "heed" => db_path.push("db.lmdb"),
+		_ => db_path.push("db"),
+	}
+	db_path
+}
+
+#[cfg(feature = "lmdb")]
+const LMDB_OUT_OF_MEMORY: &str =
+	"OutOfMemory error while trying to open LMDB database. This can happen \
+	if your operating system is not allowing you to use sufficient virtual \
+	memory address space. Please check that no limit is set (ulimit -v). \
+	On 32-bit machines, you should probably switch to another database engine.";
+
+/// Open the metadata database stored at the given path,
+/// using the DB engine set in the configuration
+pub fn open_db(config: &Config, db_path: &Path) -> Result<db::Db, Error> {
+	let db = match config.db_engine.as_str() {
+		// ---- Sled DB ----
+		#[cfg(feature = "sled")]
+		"sled" => {
+			info!("Opening Sled database at: {}", db_path.display());
+			let db = db::sled_adapter::sled::Config::default()
+				.path(db_path)
+				.cache_capacity(config.sled_cache_capacity)
+				.flush_every_ms(Some(config.sled_flush_every_ms))
+				.open()
+				.ok_or_message("Unable to open sled DB")?;
+			db::sled_adapter::SledDb::init(db)
+		}
+		#[cfg(not(feature = "sled"))]
+		"sled" => return Err(Error::Message("sled db not available in this build".into())),
+		// ---- Sqlite DB ----
+		#[cfg(feature = "sqlite")]
+		"sqlite" | "sqlite3" | "rusqlite" => {
+			info!("Opening Sqlite database at: {}", db_path.display());
+			let db = db::sqlite_adapter::rusqlite::Connection::open(db_path)
+				.ok_or_message("Unable to open sqlite DB")?;
+			db::sqlite_adapter::SqliteDb::init(db)
+		}
+		#[cfg(not(feature = "sqlite"))]
+		"sqlite" | "sqlite3" | "rusqlite" => {
+			return Err(Error::Message(
+				"sqlite db not available in this build".into(),
+			))
+		}
+		// ---- LMDB DB ----
+		#[cfg(feature = "lmdb")]
+		"lmdb" | "heed" => {
+			info!("Opening LMDB database at: {}", db_path.display());
+			std::fs::create_dir_all(db_path)
+				.ok_or_message("Unable to create LMDB data directory")?;
+			let map_size = garage_db::lmdb_adapter::recommended_map_size();
+
+			use db::lmdb_adapter::heed;
+			let mut env_builder = heed::EnvOpenOptions::new();
+			env_builder.max_dbs(100);
+			env_builder.max_readers(500);
+			unsafe {
+				env_builder.flag(heed::flags::Flags::MdbNoSync);
+				env_builder.flag(heed::flags::Flags::MdbNoMetaSync);
+			}
+			// The map is made larger when it is full, starting from this size
+			let db = db::lmdb_adapter::ResizableLmdbEnv::open(env_builder, db_path, map_size);
+			let db = match db {
+				Err(heed::Error::Io(e)) if e.kind() == std::io::ErrorKind::OutOfMemory => {
+					return Err(Error::Message(LMDB_OUT_OF_MEMORY.into()))
+				}
+				x => x.ok_or_message("Unable to open LMDB DB")?,
+			};
+			db::lmdb_adapter::LmdbDb::init(db)
+		}
+		#[cfg(not(feature = "lmdb"))]
+		"lmdb" | "heed" => return Err(Error::Message("lmdb db not available in this build".into())),
+		// ---- Unavailable DB engine ----
+		e => {
+			return Err(Error::Message(format!(
+				"Unsupported DB engine: {} (options: {})",
+				e,
+				vec![
+					#[cfg(feature = "sled")]
+					"sled",
+					#[cfg(feature = "sqlite")]
+					"sqlite",
+					#[cfg(feature = "lmdb")]
+					"lmdb",
+				]
+				.join(", ")
+			)));
+		}
+	};
+	Ok(db)
 }
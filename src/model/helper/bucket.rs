@@ -1,5 +1,8 @@
+use std::collections::{HashSet, VecDeque};
 use std::time::Duration;
 
+use serde::{Deserialize, Serialize};
+
 use garage_util::crdt::*;
 use garage_util::data::*;
 use garage_util::error::{Error as GarageError, OkOrMessage};
@@ -15,9 +18,58 @@ use crate::helper::key::KeyHelper;
 use crate::key_table::*;
 use crate::permission::BucketKeyPerm;
 use crate::s3::object_table::*;
+use crate::s3::replication_state_table::*;
+use crate::s3::replication_worker::is_replica;
+use crate::s3::version_table::*;
 
 pub struct BucketHelper<'a>(pub(crate) &'a Garage);
 
+/// Breakdown of the storage space used by a bucket
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BucketStorageUsage {
+	/// Number of objects whose current version holds data
+	pub objects: u64,
+	/// Total size of the current version of these objects
+	pub object_bytes: u64,
+	/// Number of objects whose current version is a delete marker
+	pub delete_markers: u64,
+	/// Versions that are stored but that are not the current version of their object
+	pub versions: Option<VersionStorageUsage>,
+	/// Data blocks referenced by the current version of objects
+	pub blocks: Option<BlockStorageUsage>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VersionStorageUsage {
+	/// Number of multipart uploads in progress
+	pub incomplete_uploads: u64,
+	/// Total size of the parts already uploaded for these multipart uploads
+	pub incomplete_upload_bytes: u64,
+	/// Number of complete versions that are not the current version of their object
+	pub noncurrent_versions: u64,
+	/// Total size of these versions
+	pub noncurrent_version_bytes: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BlockStorageUsage {
+	/// Number of distinct data blocks
+	pub blocks: u64,
+	/// Total size of these blocks (before compression)
+	pub block_bytes: u64,
+}
+
+/// Progress of the replication of the objects of a bucket by one of its replication rules
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReplicationRuleStatus {
+	/// Number of objects whose current version has been replicated
+	pub replicated_objects: u64,
+	/// Number of objects whose current version is still to be replicated
+	pub pending_objects: u64,
+	/// Timestamp of the most recent object version that was replicated
+	pub last_replicated: Option<u64>,
+}
+
 #[allow(clippy::ptr_arg)]
 impl<'a> BucketHelper<'a> {
 	pub async fn resolve_global_bucket_name(
@@ -335,7 +387,8 @@ impl<'a> BucketHelper<'a> {
 			.local_aliases
 			.get(alias_name)
 			.cloned()
-			.flatten() != Some(bucket_id)
+			.flatten()
+			!= Some(bucket_id)
 		{
 			return Err(GarageError::Message(format!(
 				"Bucket {:?} does not have alias {} in namespace of key {}",
@@ -513,6 +566,11 @@ impl<'a> BucketHelper<'a> {
 							state: ObjectVersionState::Aborted,
 							uuid: v.uuid,
 							timestamp: v.timestamp,
+							versioned: v.versioned,
+							lock: ObjectVersionLock::default(),
+							tags: Default::default(),
+							storage_class: Default::default(),
+							restore: Default::default(),
 						})
 						.collect::<Vec<_>>();
 					if !aborted_versions.is_empty() {
@@ -539,4 +597,200 @@ impl<'a> BucketHelper<'a> {
 
 		Ok(ret)
 	}
+
+	/// Compute the storage space used by a bucket, by scanning all of its objects.
+	/// Versions are looked up in the version table to compute the size of
+	/// incomplete uploads if `versions` is set, and the blocks of the current
+	/// versions if `blocks` is set.
+	pub async fn get_storage_usage(
+		&self,
+		bucket_id: &Uuid,
+		versions: bool,
+		blocks: bool,
+	) -> Result<BucketStorageUsage, Error> {
+		let mut ret = BucketStorageUsage {
+			versions: versions.then(VersionStorageUsage::default),
+			blocks: blocks.then(BlockStorageUsage::default),
+			..Default::default()
+		};
+		let mut seen_blocks = HashSet::new();
+		let mut start: Option<String> = None;
+
+		loop {
+			let objects = self
+				.0
+				.object_table
+				.get_range(
+					bucket_id,
+					start.clone(),
+					None,
+					1000,
+					EnumerationOrder::Forward,
+				)
+				.await?;
+			let n_objects = objects.len();
+
+			for object in objects {
+				// The first object of a page is the last one of the previous page
+				if Some(&object.key) == start.as_ref() {
+					continue;
+				}
+
+				let current = object.versions().iter().rposition(|v| v.is_complete());
+				for (i, v) in object.versions().iter().enumerate() {
+					match &v.state {
+						ObjectVersionState::Complete(ObjectVersionData::DeleteMarker)
+							if Some(i) == current =>
+						{
+							ret.delete_markers += 1;
+						}
+						ObjectVersionState::Complete(ObjectVersionData::Inline(meta, _))
+						| ObjectVersionState::Complete(ObjectVersionData::FirstBlock(meta, _))
+							if Some(i) == current =>
+						{
+							ret.objects += 1;
+							ret.object_bytes += meta.size;
+							if let Some(block_usage) = ret.blocks.as_mut() {
+								if let Some(version) = self.get_version(v).await? {
+									for (_, vb) in version.blocks.items().iter() {
+										if seen_blocks.insert(vb.hash) {
+											block_usage.blocks += 1;
+											block_usage.block_bytes += vb.size;
+										}
+									}
+								}
+							}
+						}
+						ObjectVersionState::Complete(ObjectVersionData::Inline(meta, _))
+						| ObjectVersionState::Complete(ObjectVersionData::FirstBlock(meta, _)) => {
+							if let Some(version_usage) = ret.versions.as_mut() {
+								version_usage.noncurrent_versions += 1;
+								version_usage.noncurrent_version_bytes += meta.size;
+							}
+						}
+						ObjectVersionState::Uploading(_) => {
+							if let Some(version_usage) = ret.versions.as_mut() {
+								version_usage.incomplete_uploads += 1;
+								if let Some(version) = self.get_version(v).await? {
+									version_usage.incomplete_upload_bytes += version
+										.blocks
+										.items()
+										.iter()
+										.map(|(_, vb)| vb.size)
+										.sum::<u64>();
+								}
+							}
+						}
+						_ => (),
+					}
+				}
+
+				start = Some(object.key);
+			}
+
+			if n_objects < 1000 {
+				break;
+			}
+		}
+
+		Ok(ret)
+	}
+
+	/// Compute the progress of the replication of a bucket for each of the given rules,
+	/// by scanning all of its objects alongside their replication states
+	pub async fn get_replication_status(
+		&self,
+		bucket_id: &Uuid,
+		rules: &[ReplicationRule],
+	) -> Result<Vec<ReplicationRuleStatus>, Error> {
+		let mut ret = vec![ReplicationRuleStatus::default(); rules.len()];
+		let mut start: Option<String> = None;
+
+		// Replication states are read in pages alongside the objects,
+		// both being sorted by key
+		let mut states = VecDeque::<ReplicationState>::new();
+		let mut states_start: Option<String> = None;
+		let mut states_done = false;
+
+		loop {
+			let objects = self
+				.0
+				.object_table
+				.get_range(
+					bucket_id,
+					start.clone(),
+					None,
+					1000,
+					EnumerationOrder::Forward,
+				)
+				.await?;
+			let n_objects = objects.len();
+
+			for object in objects {
+				// The first object of a page is the last one of the previous page
+				if Some(&object.key) == start.as_ref() {
+					continue;
+				}
+
+				while !states_done && states.back().map(|s| s.key < object.key).unwrap_or(true) {
+					let mut page = self
+						.0
+						.replication_state_table
+						.get_range(
+							bucket_id,
+							states_start.clone(),
+							Some(DeletedFilter::NotDeleted),
+							1000,
+							EnumerationOrder::Forward,
+						)
+						.await?;
+					states_done = page.len() < 1000;
+					if let Some(last) = page.last().map(|s| s.key.clone()) {
+						// The first state of a page is the last one of the previous page
+						page.retain(|s| Some(&s.key) != states_start.as_ref());
+						states_start = Some(last);
+					}
+					states.extend(page);
+				}
+				while states.front().map(|s| s.key < object.key).unwrap_or(false) {
+					states.pop_front();
+				}
+				let state = states.front().filter(|s| s.key == object.key);
+
+				// Replicas written by other clusters are never replicated
+				if let Some(version) = object.current_version().filter(|v| !is_replica(v)) {
+					for (rule, status) in rules.iter().zip(ret.iter_mut()) {
+						if !rule.applies_to(&object.key) {
+							continue;
+						}
+						match state.and_then(|s| s.rules.get(&rule.id)) {
+							Some(r) if r.uuid == version.uuid => {
+								status.replicated_objects += 1;
+								status.last_replicated =
+									std::cmp::max(status.last_replicated, Some(r.timestamp));
+							}
+							_ => status.pending_objects += 1,
+						}
+					}
+				}
+
+				start = Some(object.key);
+			}
+
+			if n_objects < 1000 {
+				break;
+			}
+		}
+
+		Ok(ret)
+	}
+
+	async fn get_version(&self, v: &ObjectVersion) -> Result<Option<Version>, Error> {
+		Ok(self
+			.0
+			.version_table
+			.get(&v.uuid, &EmptyKey)
+			.await?
+			.filter(|version| !version.deleted.get()))
+	}
 }
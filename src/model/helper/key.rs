@@ -1,11 +1,12 @@
 use garage_table::util::*;
 use garage_util::crdt::*;
 use garage_util::error::OkOrMessage;
+use garage_util::time::now_msec;
 
 use crate::garage::Garage;
 use crate::helper::bucket::BucketHelper;
 use crate::helper::error::*;
-use crate::key_table::{Key, KeyFilter};
+use crate::key_table::{Key, KeyFilter, KEY_LAST_USED_PRECISION};
 use crate::permission::BucketKeyPerm;
 
 pub struct KeyHelper<'a>(pub(crate) &'a Garage);
@@ -37,6 +38,25 @@ impl<'a> KeyHelper<'a> {
 			.ok_or_else(|| Error::NoSuchAccessKey(key_id.to_string()))
 	}
 
+	/// Record in the background that a key has been used to authenticate
+	/// a request, if its last use was recorded more than
+	/// `KEY_LAST_USED_PRECISION` ago.
+	pub fn record_key_use(&self, key: &Key) {
+		let now = now_msec();
+		let mut key = key.clone();
+		match key.params_mut() {
+			Some(p) if p.last_used + KEY_LAST_USED_PRECISION <= now => p.last_used = now,
+			_ => return,
+		}
+
+		let key_table = self.0.key_table.clone();
+		tokio::spawn(async move {
+			if let Err(e) = key_table.insert(&key).await {
+				warn!("Could not record last use of key {}: {}", key.key_id, e);
+			}
+		});
+	}
+
 	/// Returns a Key if it is present in key table,
 	/// looking it up by key ID or by a match on its name,
 	/// only if it is in non-deleted state.
@@ -3,6 +3,7 @@ use std::collections::{BTreeMap, HashMap};
 use std::marker::PhantomData;
 use std::sync::Arc;
 
+use opentelemetry::{global, metrics::ValueObserver, KeyValue};
 use serde::{Deserialize, Serialize};
 
 use garage_db as db;
@@ -160,12 +161,62 @@ impl<T: CountedItem> TableSchema for CounterTable<T> {
 	}
 }
 
+/// Export the values of the counters stored on this node, summed by partition key
+/// (the bucket id for all counter tables), e.g. the number of objects and bytes
+/// of each bucket
+fn values_observer<T: CountedItem>(
+	store: db::Tree,
+	ring: tokio::sync::watch::Receiver<Arc<Ring>>,
+) -> ValueObserver<i64> {
+	global::meter(T::COUNTER_TABLE_NAME)
+		.i64_value_observer("index_counter.value", move |observer| {
+			let ring = ring.borrow().clone();
+			let mut sums = HashMap::<(Hash, String), i64>::new();
+
+			let iter = match store.iter() {
+				Ok(iter) => iter,
+				Err(e) => {
+					warn!(
+						"Unable to read {} for metrics: {}",
+						T::COUNTER_TABLE_NAME,
+						e
+					);
+					return;
+				}
+			};
+			for item in iter {
+				let entry = match item.ok().and_then(|(_, v)| CounterEntry::<T>::decode(&v)) {
+					Some(entry) => entry,
+					None => continue,
+				};
+				for (name, v) in entry.filtered_values(&ring) {
+					*sums.entry((entry.pk.hash(), name)).or_insert(0) += v;
+				}
+			}
+
+			for ((bucket_id, name), v) in sums {
+				observer.observe(
+					v,
+					&[
+						KeyValue::new("counter_table", T::COUNTER_TABLE_NAME),
+						KeyValue::new("bucket_id", hex::encode(bucket_id)),
+						KeyValue::new("counter", name),
+					],
+				);
+			}
+		})
+		.with_description("Value of the index counters stored on this node, per bucket")
+		.init()
+}
+
 // ----
 
 pub struct IndexCounter<T: CountedItem> {
 	this_node: Uuid,
 	local_counter: db::Tree,
 	pub table: Arc<Table<CounterTable<T>, TableShardedReplication>>,
+
+	_values_observer: ValueObserver<i64>,
 }
 
 impl<T: CountedItem> IndexCounter<T> {
@@ -174,19 +225,24 @@ impl<T: CountedItem> IndexCounter<T> {
 		replication: TableShardedReplication,
 		db: &db::Db,
 	) -> Arc<Self> {
+		let table = Table::new(
+			CounterTable {
+				_phantom_t: Default::default(),
+			},
+			replication,
+			system.clone(),
+			db,
+		);
+
+		let values_observer = values_observer::<T>(table.data.store.clone(), system.ring.clone());
+
 		Arc::new(Self {
 			this_node: system.id,
 			local_counter: db
 				.open_tree(format!("local_counter_v2:{}", T::COUNTER_TABLE_NAME))
 				.expect("Unable to open local counter tree"),
-			table: Table::new(
-				CounterTable {
-					_phantom_t: Default::default(),
-				},
-				replication,
-				system,
-				db,
-			),
+			table,
+			_values_observer: values_observer,
 		})
 	}
 
@@ -5,6 +5,7 @@ use serde::{Deserialize, Serialize};
 
 use garage_db as db;
 use garage_util::data::*;
+use garage_util::time::now_msec;
 
 use garage_table::crdt::*;
 use garage_table::*;
@@ -20,6 +21,7 @@ pub const BYTES: &str = "bytes";
 
 mod v08 {
 	use crate::k2v::causality::K2VNodeId;
+	use garage_util::crdt::Lww;
 	use garage_util::data::Uuid;
 	use serde::{Deserialize, Serialize};
 	use std::collections::BTreeMap;
@@ -30,6 +32,11 @@ mod v08 {
 		pub sort_key: String,
 
 		pub(super) items: BTreeMap<K2VNodeId, DvvsEntry>,
+
+		/// Time (msec since Unix epoch) after which the item is
+		/// considered deleted, if any. Set by the last write of a value.
+		#[serde(default)]
+		pub(super) ttl: Lww<Option<u64>>,
 	}
 
 	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize, Hash)]
@@ -65,16 +72,31 @@ impl K2VItem {
 			},
 			sort_key,
 			items: BTreeMap::new(),
+			ttl: Lww::default(),
 		}
 	}
-	/// Updates a K2VItem with a new value or a deletion event
+	/// Updates a K2VItem with a new value or a deletion event.
+	/// Writing a value also replaces the expiration time of the item by `ttl`.
 	pub fn update(
 		&mut self,
 		this_node: Uuid,
 		context: &Option<CausalContext>,
 		new_value: DvvsValue,
+		ttl: Option<u64>,
 		node_ts: u64,
 	) -> u64 {
+		// Values of an expired item are logically deleted: they must not
+		// conflict with the new value, even if their tombstone has not been written yet
+		if self.is_expired(now_msec()) {
+			for (_, e) in self.items.iter_mut() {
+				e.t_discard = e.max_time();
+			}
+		}
+
+		if let DvvsValue::Value(_) = new_value {
+			self.ttl.update(ttl);
+		}
+
 		if let Some(context) = context {
 			for (node, t_discard) in context.vector_clock.iter() {
 				if let Some(e) = self.items.get_mut(node) {
@@ -126,6 +148,17 @@ impl K2VItem {
 		ret
 	}
 
+	/// Time (msec since Unix epoch) after which the item expires, if any
+	pub fn ttl(&self) -> Option<u64> {
+		*self.ttl.get()
+	}
+
+	/// Whether the item has expired and must no longer be returned,
+	/// even if its tombstone has not yet been written
+	pub fn is_expired(&self, now: u64) -> bool {
+		self.ttl().map(|ttl| ttl <= now).unwrap_or(false)
+	}
+
 	fn discard(&mut self) {
 		for (_, ent) in self.items.iter_mut() {
 			ent.discard();
@@ -157,6 +190,7 @@ impl Crdt for K2VItem {
 				self.items.insert(*node, e2.clone());
 			}
 		}
+		self.ttl.merge(&other.ttl);
 	}
 }
 
@@ -250,7 +284,8 @@ impl TableSchema for K2VItemTable {
 	fn matches_filter(entry: &Self::E, filter: &Self::Filter) -> bool {
 		let v = entry.values();
 		!(filter.conflicts_only && v.len() < 2)
-			&& !(filter.exclude_only_tombstones && entry.is_tombstone())
+			&& !(filter.exclude_only_tombstones
+				&& (entry.is_tombstone() || entry.is_expired(now_msec())))
 	}
 }
 
@@ -317,4 +352,49 @@ mod tests {
 		e3.merge(&e2);
 		assert_eq!(e2, e3);
 	}
+
+	#[test]
+	fn test_item_ttl() {
+		let node = gen_uuid();
+		let mut item = K2VItem::new(gen_uuid(), "pk".into(), "sk".into());
+		let now = now_msec();
+
+		item.update(
+			node,
+			&None,
+			DvvsValue::Value(vec![1]),
+			Some(now + 3_600_000),
+			0,
+		);
+		assert_eq!(item.ttl(), Some(now + 3_600_000));
+		assert!(!item.is_expired(now));
+		assert!(item.is_expired(now + 3_600_000));
+
+		// Deletions do not change the TTL of the item
+		let mut deleted = item.clone();
+		deleted.update(node, &None, DvvsValue::Deleted, None, 0);
+		assert_eq!(deleted.ttl(), Some(now + 3_600_000));
+
+		// The TTL of the last written value wins when merging
+		let mut persistent = item.clone();
+		persistent.update(node, &None, DvvsValue::Value(vec![2]), None, 0);
+		assert_eq!(persistent.ttl(), None);
+		item.merge(&persistent);
+		assert_eq!(item.ttl(), None);
+		assert_eq!(item.values().len(), 2);
+	}
+
+	#[test]
+	fn test_item_ttl_expired_values_discarded() {
+		let node = gen_uuid();
+		let mut item = K2VItem::new(gen_uuid(), "pk".into(), "sk".into());
+		item.update(node, &None, DvvsValue::Value(vec![1]), Some(1), 0);
+		assert!(item.is_expired(now_msec()));
+		assert!(!item.is_tombstone());
+
+		// A new value does not conflict with the values of the expired item
+		item.update(node, &None, DvvsValue::Value(vec![2]), None, 0);
+		assert!(!item.is_expired(now_msec()));
+		assert_eq!(item.values(), vec![&DvvsValue::Value(vec![2])]);
+	}
 }
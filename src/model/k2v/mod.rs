@@ -6,3 +6,5 @@ pub mod item_table;
 pub mod rpc;
 
 pub mod sub;
+
+pub mod ttl_worker;
@@ -5,6 +5,7 @@
 //! node does not process the entry directly, as this would
 //! mean the vector clock gets much larger than needed).
 
+use std::cell::Cell;
 use std::collections::{BTreeMap, HashMap};
 use std::convert::TryInto;
 use std::sync::{Arc, Mutex, MutexGuard};
@@ -57,6 +58,8 @@ enum K2VRpc {
 	},
 	PollItemResponse(Option<K2VItem>),
 	PollRangeResponse(Uuid, Vec<K2VItem>),
+	CompareAndSwap(InsertedItem),
+	CasFailed(CausalContext),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -65,15 +68,28 @@ struct InsertedItem {
 	sort_key: String,
 	causal_context: Option<CausalContext>,
 	value: DvvsValue,
+	/// Expiration time of the item (msec since Unix epoch)
+	#[serde(default)]
+	ttl: Option<u64>,
 }
 
 impl Rpc for K2VRpc {
 	type Response = Result<K2VRpc, Error>;
 }
 
+/// Result of a compare-and-swap operation on a K2V item
+#[derive(Debug)]
+pub enum CasResult {
+	/// The causality token matched and the new value was written
+	Ok,
+	/// The item was modified since the causality token was obtained,
+	/// nothing was written
+	CasFailed { current_causality: CausalContext },
+}
+
 /// The block manager, handling block exchange between nodes, and block storage on local node
 pub struct K2VRpcHandler {
-	system: Arc<System>,
+	pub(crate) system: Arc<System>,
 	item_table: Arc<Table<K2VItemTable, TableShardedReplication>>,
 
 	// Using a mutex on the local_timestamp_tree is not strictly necessary,
@@ -118,6 +134,7 @@ impl K2VRpcHandler {
 		sort_key: String,
 		causal_context: Option<CausalContext>,
 		value: DvvsValue,
+		ttl: Option<u64>,
 	) -> Result<(), Error> {
 		let partition = K2VItemPartition {
 			bucket_id,
@@ -140,6 +157,7 @@ impl K2VRpcHandler {
 					sort_key,
 					causal_context,
 					value,
+					ttl,
 				}),
 				RequestStrategy::with_priority(PRIO_NORMAL)
 					.with_quorum(1)
@@ -176,6 +194,7 @@ impl K2VRpcHandler {
 				sort_key,
 				causal_context,
 				value,
+				ttl: None,
 			});
 		}
 
@@ -208,6 +227,61 @@ impl K2VRpcHandler {
 		Ok(())
 	}
 
+	/// Write a value for an item only if its current causal context is exactly
+	/// `expected`, i.e. if no write happened since that causality token was
+	/// obtained. The check and the write are done in a single DB transaction
+	/// by the first node of the partition, which is the only node that processes
+	/// the compare-and-swap operations on its items. If this node cannot be
+	/// reached, the operation fails instead of being sent to another node,
+	/// as two nodes could otherwise accept conflicting writes. When the call
+	/// fails, the write might still have been done: the item must be read
+	/// again to know it.
+	pub async fn compare_and_swap(
+		&self,
+		bucket_id: Uuid,
+		partition_key: String,
+		sort_key: String,
+		expected: CausalContext,
+		value: DvvsValue,
+	) -> Result<CasResult, Error> {
+		let partition = K2VItemPartition {
+			bucket_id,
+			partition_key,
+		};
+		let owner = *self
+			.item_table
+			.data
+			.replication
+			.write_nodes(&partition.hash())
+			.first()
+			.ok_or_message("No node stores the partition of the item")?;
+
+		let resp = self
+			.system
+			.rpc
+			.call(
+				&self.endpoint,
+				owner,
+				K2VRpc::CompareAndSwap(InsertedItem {
+					partition,
+					sort_key,
+					causal_context: Some(expected),
+					value,
+					ttl: None,
+				}),
+				RequestStrategy::with_priority(PRIO_NORMAL),
+			)
+			.await;
+		match resp {
+			Ok(K2VRpc::Ok) => Ok(CasResult::Ok),
+			Ok(K2VRpc::CasFailed(current_causality)) => {
+				Ok(CasResult::CasFailed { current_causality })
+			}
+			Ok(m) => Err(Error::unexpected_rpc_message(m)),
+			Err(e) => Err(Error::Quorum(1, 0, 1, vec![format!("{}", e)])),
+		}
+	}
+
 	pub async fn poll_item(
 		&self,
 		bucket_id: Uuid,
@@ -407,6 +481,97 @@ impl K2VRpcHandler {
 		Ok(K2VRpc::Ok)
 	}
 
+	async fn handle_compare_and_swap(&self, item: &InsertedItem) -> Result<K2VRpc, Error> {
+		let expected = item
+			.causal_context
+			.as_ref()
+			.ok_or_message("Missing causal context for compare-and-swap")?;
+		let current_causality = Cell::new(None);
+
+		// Only the first node of the partition processes compare-and-swap
+		// operations: refuse them if the node that sent this one uses a
+		// different cluster layout and thinks that we are in charge
+		let owner = self
+			.item_table
+			.data
+			.replication
+			.write_nodes(&item.partition.hash())
+			.first()
+			.copied();
+		if owner != Some(self.system.id) {
+			return Err(Error::Message(
+				"This node is not the first node of the partition of the item".into(),
+			));
+		}
+
+		// This node might not have received yet a write that a client has
+		// already read from other nodes: merge the item read from a quorum
+		// before comparing, so as not to refuse a valid causality token.
+		// This does not make concurrent operations safe, only the fact that
+		// they are all processed by this node does.
+		let quorum_item = self.item_table.get(&item.partition, &item.sort_key).await?;
+
+		let new = {
+			let local_timestamp_tree = self.local_timestamp_tree.lock().unwrap();
+			let now = now_msec();
+
+			self.item_table.data.update_entry_with_opt(
+				&item.partition,
+				&item.sort_key,
+				|tx, ent| {
+					let mut ent = ent.unwrap_or_else(|| {
+						K2VItem::new(
+							item.partition.bucket_id,
+							item.partition.partition_key.clone(),
+							item.sort_key.clone(),
+						)
+					});
+					if let Some(quorum_item) = &quorum_item {
+						ent.merge(quorum_item);
+					}
+
+					let causality = ent.causal_context();
+					if causality != *expected {
+						current_causality.set(Some(causality));
+						return Ok(None);
+					}
+					current_causality.set(None);
+
+					let old_local_timestamp = tx
+						.get(&local_timestamp_tree, TIMESTAMP_KEY)?
+						.and_then(|x| x.try_into().ok())
+						.map(u64::from_be_bytes)
+						.unwrap_or_default();
+					let new_local_timestamp = ent.update(
+						self.system.id,
+						&item.causal_context,
+						item.value.clone(),
+						item.ttl,
+						std::cmp::max(old_local_timestamp, now),
+					);
+					tx.insert(
+						&local_timestamp_tree,
+						TIMESTAMP_KEY,
+						u64::to_be_bytes(new_local_timestamp),
+					)?;
+
+					Ok(Some(ent))
+				},
+			)?
+		};
+
+		if let Some(current_causality) = current_causality.take() {
+			return Ok(K2VRpc::CasFailed(current_causality));
+		}
+
+		// Propagate to rest of network
+		if let Some(updated) = new {
+			self.item_table.insert(&updated).await?;
+		}
+
+		Ok(K2VRpc::Ok)
+	}
+
 	fn local_insert(
 		&self,
 		local_timestamp_tree: &MutexGuard<'_, db::Tree>,
@@ -434,6 +599,7 @@ impl K2VRpcHandler {
 					self.system.id,
 					&item.causal_context,
 					item.value.clone(),
+					item.ttl,
 					std::cmp::max(old_local_timestamp, now),
 				);
 
@@ -543,6 +709,7 @@ impl EndpointHandler<K2VRpc> for K2VRpcHandler {
 		match message {
 			K2VRpc::InsertItem(item) => self.handle_insert(item).await,
 			K2VRpc::InsertManyItems(items) => self.handle_insert_many(&items[..]).await,
+			K2VRpc::CompareAndSwap(item) => self.handle_compare_and_swap(item).await,
 			K2VRpc::PollItem {
 				key,
 				causal_context,
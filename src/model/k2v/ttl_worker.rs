@@ -0,0 +1,164 @@
+//! Background worker that writes tombstones for K2V items whose TTL
+//! has expired, so that they are removed from the index counters
+//! and eventually garbage collected
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::sync::watch;
+
+use garage_util::background::*;
+use garage_util::error::Error;
+use garage_util::time::*;
+use garage_util::tranquilizer::Tranquilizer;
+
+use garage_table::replication::{TableReplication, TableShardedReplication};
+use garage_table::*;
+
+use crate::k2v::item_table::*;
+use crate::k2v::rpc::K2VRpcHandler;
+
+/// Interval between the start of two passes of the worker
+const K2V_TTL_INTERVAL: Duration = Duration::from_secs(600);
+
+const K2V_TTL_TRANQUILITY: u32 = 2;
+
+enum State {
+	Completed(Option<u64>),
+	Running {
+		pos: Vec<u8>,
+		counter: usize,
+		items_expired: usize,
+	},
+}
+
+pub struct K2VTtlWorker {
+	item_table: Arc<Table<K2VItemTable, TableShardedReplication>>,
+	rpc: Arc<K2VRpcHandler>,
+
+	state: State,
+	tranquilizer: Tranquilizer,
+}
+
+impl K2VTtlWorker {
+	pub fn new(
+		item_table: Arc<Table<K2VItemTable, TableShardedReplication>>,
+		rpc: Arc<K2VRpcHandler>,
+	) -> Self {
+		Self {
+			item_table,
+			rpc,
+			state: State::Completed(None),
+			tranquilizer: Tranquilizer::new(30),
+		}
+	}
+}
+
+#[async_trait]
+impl Worker for K2VTtlWorker {
+	fn name(&self) -> String {
+		"K2V item expiration".into()
+	}
+
+	fn status(&self) -> WorkerStatus {
+		match &self.state {
+			State::Completed(None) => WorkerStatus {
+				freeform: vec!["No pass completed yet".into()],
+				..Default::default()
+			},
+			State::Completed(Some(ts)) => WorkerStatus {
+				freeform: vec![format!("Last pass completed at {}", msec_to_rfc3339(*ts))],
+				..Default::default()
+			},
+			State::Running {
+				counter,
+				items_expired,
+				..
+			} => WorkerStatus {
+				progress: Some(format!("{}", counter)),
+				freeform: vec![format!("Items expired: {}", items_expired)],
+				..Default::default()
+			},
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		match &mut self.state {
+			State::Completed(_) => Ok(WorkerState::Idle),
+			State::Running {
+				pos,
+				counter,
+				items_expired,
+			} => {
+				let (item_bytes, next_pos) = match self.item_table.data.store.get_gt(&pos)? {
+					None => {
+						debug!(
+							"K2V item expiration pass finished, items expired: {}",
+							items_expired
+						);
+						self.state = State::Completed(Some(now_msec()));
+						return Ok(WorkerState::Idle);
+					}
+					Some((k, v)) => (v, k),
+				};
+				*pos = next_pos;
+				*counter += 1;
+
+				let item = self.item_table.data.decode_entry(&item_bytes)?;
+				if item.is_tombstone() || !item.is_expired(now_msec()) {
+					return Ok(WorkerState::Busy);
+				}
+
+				// Only the first node storing the partition writes the tombstone
+				let write_nodes = self
+					.item_table
+					.data
+					.replication
+					.write_nodes(&item.partition.hash());
+				if write_nodes.first() != Some(&self.rpc.system.id) {
+					return Ok(WorkerState::Busy);
+				}
+
+				self.tranquilizer.reset();
+
+				// The tombstone only discards the values we have seen: values written
+				// concurrently, with a new TTL, are kept
+				let cc = item.causal_context();
+				self.rpc
+					.insert(
+						item.partition.bucket_id,
+						item.partition.partition_key,
+						item.sort_key,
+						Some(cc),
+						DvvsValue::Deleted,
+						None,
+					)
+					.await?;
+				*items_expired += 1;
+
+				Ok(self.tranquilizer.tranquilize_worker(K2V_TTL_TRANQUILITY))
+			}
+		}
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		match &self.state {
+			State::Completed(last_completed) => {
+				let interval = K2V_TTL_INTERVAL.as_millis() as u64;
+				let next_start = last_completed.map(|t| t + interval).unwrap_or(0);
+				let now = now_msec();
+				if next_start > now {
+					tokio::time::sleep(Duration::from_millis(next_start - now)).await;
+				}
+				self.state = State::Running {
+					pos: vec![],
+					counter: 0,
+					items_expired: 0,
+				};
+				WorkerState::Busy
+			}
+			State::Running { .. } => WorkerState::Busy,
+		}
+	}
+}
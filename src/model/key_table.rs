@@ -2,10 +2,11 @@ use serde::{Deserialize, Serialize};
 
 use garage_util::crdt::{self, Crdt};
 use garage_util::data::*;
+use garage_util::time::now_msec;
 
 use garage_table::{DeletedFilter, EmptyKey, Entry, TableSchema};
 
-use crate::permission::BucketKeyPerm;
+use crate::permission::{BucketKeyPerm, S3Op};
 
 pub(crate) mod v05 {
 	use garage_util::crdt;
@@ -114,26 +115,119 @@ mod v08 {
 	}
 }
 
-pub use v08::*;
+mod v09 {
+	use super::v08;
+	use crate::permission::BucketKeyPerm;
+	use garage_util::crdt;
+	use garage_util::data::Uuid;
+	use serde::{Deserialize, Serialize};
+
+	/// An api key
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct Key {
+		/// The id of the key (immutable), used as partition key
+		pub key_id: String,
+
+		/// Internal state of the key
+		pub state: crdt::Deletable<KeyParams>,
+	}
+
+	/// Configuration for a key
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct KeyParams {
+		/// The secret_key associated (can be rotated)
+		pub secret_key: crdt::Lww<String>,
+
+		/// Name for the key
+		pub name: crdt::Lww<String>,
+
+		/// Flag indicating whether the key can be used to authenticate requests
+		pub enabled: crdt::Lww<bool>,
+
+		/// Flag to allow users having this key to create buckets
+		pub allow_create_bucket: crdt::Lww<bool>,
+
+		/// If the key is present: it gives some permissions,
+		/// a map of bucket IDs (uuids) to permissions.
+		/// Otherwise no permissions are granted to key
+		pub authorized_buckets: crdt::Map<Uuid, BucketKeyPerm>,
+
+		/// A key can have a local view of buckets names it is
+		/// the only one to see, this is the namespace for these aliases
+		pub local_aliases: crdt::LwwMap<String, Option<Uuid>>,
+
+		/// Creation date of the key, in msec since the epoch
+		/// (zero for keys created before it was recorded)
+		#[serde(default)]
+		pub created: u64,
+
+		/// Last time the key was used to authenticate a request, in msec
+		/// since the epoch, with a precision of `KEY_LAST_USED_PRECISION`
+		/// (zero if the key was never used since this is recorded)
+		#[serde(default)]
+		pub last_used: u64,
+	}
+
+	impl garage_util::migrate::Migrate for Key {
+		const VERSION_MARKER: &'static [u8] = b"G09s3key";
+
+		type Previous = v08::Key;
+
+		fn migrate(old_k: v08::Key) -> Key {
+			// The secret key and enabled flag get a zero timestamp,
+			// so that any later update wins over the migrated values
+			let state = match old_k.state {
+				crdt::Deletable::Present(p) => crdt::Deletable::Present(KeyParams {
+					secret_key: crdt::Lww::raw(0, p.secret_key),
+					name: p.name,
+					enabled: crdt::Lww::raw(0, true),
+					allow_create_bucket: p.allow_create_bucket,
+					authorized_buckets: p.authorized_buckets,
+					local_aliases: p.local_aliases,
+					created: 0,
+					last_used: 0,
+				}),
+				crdt::Deletable::Deleted => crdt::Deletable::Deleted,
+			};
+			Key {
+				key_id: old_k.key_id,
+				state,
+			}
+		}
+	}
+}
+
+pub use v09::*;
+
+/// Precision with which the last use of a key is recorded, in msec,
+/// to avoid writing to the key table on each request
+pub const KEY_LAST_USED_PRECISION: u64 = 3600 * 1000;
 
 impl KeyParams {
 	fn new(secret_key: &str, name: &str) -> Self {
 		KeyParams {
-			secret_key: secret_key.to_string(),
+			secret_key: crdt::Lww::new(secret_key.to_string()),
 			name: crdt::Lww::new(name.to_string()),
+			enabled: crdt::Lww::new(true),
 			allow_create_bucket: crdt::Lww::new(false),
 			authorized_buckets: crdt::Map::new(),
 			local_aliases: crdt::LwwMap::new(),
+			created: now_msec(),
+			last_used: 0,
 		}
 	}
 }
 
 impl Crdt for KeyParams {
 	fn merge(&mut self, o: &Self) {
+		self.secret_key.merge(&o.secret_key);
 		self.name.merge(&o.name);
+		self.enabled.merge(&o.enabled);
 		self.allow_create_bucket.merge(&o.allow_create_bucket);
 		self.authorized_buckets.merge(&o.authorized_buckets);
 		self.local_aliases.merge(&o.local_aliases);
+		self.created = std::cmp::max(self.created, o.created);
+		self.last_used = std::cmp::max(self.last_used, o.last_used);
 	}
 }
 
@@ -141,7 +235,7 @@ impl Key {
 	/// Initialize a new Key, generating a random identifier and associated secret key
 	pub fn new(name: &str) -> Self {
 		let key_id = format!("GK{}", hex::encode(&rand::random::<[u8; 12]>()[..]));
-		let secret_key = hex::encode(&rand::random::<[u8; 32]>()[..]);
+		let secret_key = Self::generate_secret_key();
 		Self {
 			key_id,
 			state: crdt::Deletable::present(KeyParams::new(&secret_key, name)),
@@ -164,11 +258,21 @@ impl Key {
 		}
 	}
 
+	/// Generate a new random secret key
+	pub fn generate_secret_key() -> String {
+		hex::encode(&rand::random::<[u8; 32]>()[..])
+	}
+
 	/// Returns true if this represents a deleted bucket
 	pub fn is_deleted(&self) -> bool {
 		self.state.is_deleted()
 	}
 
+	/// Returns true if the key is not deleted and has not been disabled
+	pub fn is_enabled(&self) -> bool {
+		self.params().map(|p| *p.enabled.get()).unwrap_or(false)
+	}
+
 	/// Returns an option representing the params (None if in deleted state)
 	pub fn params(&self) -> Option<&KeyParams> {
 		self.state.as_option()
@@ -201,6 +305,16 @@ impl Key {
 	pub fn allow_owner(&self, bucket: &Uuid) -> bool {
 		self.bucket_permissions(bucket).allow_owner
 	}
+
+	/// Check if `Key` is allowed to do an S3 operation in bucket
+	pub fn allow_op(&self, bucket: &Uuid, op: S3Op) -> bool {
+		self.bucket_permissions(bucket).allowed_ops().contains(op)
+	}
+
+	/// Check if `Key` is allowed to bypass governance-mode object lock retention in bucket
+	pub fn allow_bypass_governance(&self, bucket: &Uuid) -> bool {
+		self.bucket_permissions(bucket).allow_bypass_governance
+	}
 }
 
 impl Entry<EmptyKey, String> for Key {
@@ -62,6 +62,8 @@ impl Migrate {
 			Some(WebsiteConfig {
 				index_document: "index.html".into(),
 				error_document: None,
+				redirect_all: None,
+				routing_rules: vec![],
 			})
 		} else {
 			None
@@ -79,6 +81,17 @@ impl Migrate {
 					website_config: Lww::new(website),
 					cors_config: Lww::new(None),
 					quotas: Lww::new(Default::default()),
+					encryption_required: Lww::new(false),
+					encryption_config: Lww::new(None),
+					max_object_size: Lww::new(None),
+					compression_level: Lww::new(None),
+					lifecycle_config: Lww::new(None),
+					versioning_state: Lww::new(VersioningState::Disabled),
+					object_lock_config: Lww::new(None),
+					logging_config: Lww::new(None),
+					replication_config: Lww::new(None),
+					notification_config: Lww::new(None),
+					policy: Lww::new(None),
 				}),
 			})
 			.await?;
@@ -99,6 +112,9 @@ impl Migrate {
 						allow_read: perm.allow_read,
 						allow_write: perm.allow_write,
 						allow_owner: false,
+						allow_bypass_governance: false,
+						extra_ops: S3OpSet::EMPTY,
+						denied_ops: S3OpSet::EMPTY,
 					},
 				)
 				.await?;
@@ -1,4 +1,7 @@
 use std::cmp::Ordering;
+use std::fmt;
+use std::iter::FromIterator;
+use std::str::FromStr;
 
 use serde::{Deserialize, Serialize};
 
@@ -18,6 +21,17 @@ pub struct BucketKeyPerm {
 	/// - enable / disable website access
 	/// - delete bucket
 	pub allow_owner: bool,
+	/// The key can be used to shorten or remove the governance-mode object lock
+	/// retention of object versions, or delete them while it applies
+	#[serde(default)]
+	pub allow_bypass_governance: bool,
+	/// S3 operations given individually, that the flags of the key do not give.
+	/// The K2V API only looks at the flags.
+	#[serde(default)]
+	pub extra_ops: S3OpSet,
+	/// S3 operations removed individually, that the flags of the key give
+	#[serde(default)]
+	pub denied_ops: S3OpSet,
 }
 
 impl BucketKeyPerm {
@@ -26,17 +40,78 @@ impl BucketKeyPerm {
 		allow_read: false,
 		allow_write: false,
 		allow_owner: false,
+		allow_bypass_governance: false,
+		extra_ops: S3OpSet::EMPTY,
+		denied_ops: S3OpSet::EMPTY,
 	};
 
+	/// Permissions of the key that creates a bucket. Bypassing governance-mode
+	/// retention is not one of them, it always has to be given explicitly.
 	pub const ALL_PERMISSIONS: Self = Self {
 		timestamp: 0,
 		allow_read: true,
 		allow_write: true,
 		allow_owner: true,
+		allow_bypass_governance: false,
+		extra_ops: S3OpSet::EMPTY,
+		denied_ops: S3OpSet::EMPTY,
 	};
 
 	pub fn is_any(&self) -> bool {
-		self.allow_read || self.allow_write || self.allow_owner
+		self.allow_read
+			|| self.allow_write
+			|| self.allow_owner
+			|| self.allow_bypass_governance
+			|| !self.extra_ops.is_empty()
+	}
+
+	/// S3 operations the key can do: those of its flags, with the ones given or
+	/// removed individually. It is computed each time, so that the operations
+	/// supported by later versions of Garage are given to the keys that have
+	/// the flag of their group.
+	pub fn allowed_ops(&self) -> S3OpSet {
+		self.flag_ops()
+			.union(self.extra_ops)
+			.difference(self.denied_ops)
+	}
+
+	fn flag_ops(&self) -> S3OpSet {
+		S3OpSet::for_flags(self.allow_read, self.allow_write, self.allow_owner)
+	}
+
+	/// Give or remove the read flag, and all the read operations with it
+	pub fn set_read(&mut self, allow: bool) {
+		self.allow_read = allow;
+		self.clear_group_ops(S3OpGroup::Read);
+	}
+
+	/// Give or remove the write flag, and all the write operations with it
+	pub fn set_write(&mut self, allow: bool) {
+		self.allow_write = allow;
+		self.clear_group_ops(S3OpGroup::Write);
+	}
+
+	/// Give or remove the owner flag, and all the owner operations with it
+	pub fn set_owner(&mut self, allow: bool) {
+		self.allow_owner = allow;
+		self.clear_group_ops(S3OpGroup::Owner);
+	}
+
+	fn clear_group_ops(&mut self, group: S3OpGroup) {
+		let ops = S3OpSet::group(group);
+		self.extra_ops = self.extra_ops.difference(ops);
+		self.denied_ops = self.denied_ops.difference(ops);
+	}
+
+	/// Give or remove single operations, independently of the flags
+	pub fn set_ops(&mut self, ops: S3OpSet, allow: bool) {
+		if allow {
+			self.denied_ops = self.denied_ops.difference(ops);
+			self.extra_ops = self.extra_ops.union(ops.difference(self.flag_ops()));
+		} else {
+			self.extra_ops = self.extra_ops.difference(ops);
+			self.denied_ops = self.denied_ops.union(ops.intersection(self.flag_ops()));
+		}
 	}
 }
 
@@ -57,8 +132,327 @@ impl Crdt for BucketKeyPerm {
 				if !other.allow_owner {
 					self.allow_owner = false;
 				}
+				if !other.allow_bypass_governance {
+					self.allow_bypass_governance = false;
+				}
+				self.extra_ops = self.extra_ops.intersection(other.extra_ops);
+				self.denied_ops = self.denied_ops.union(other.denied_ops);
 			}
 			_ => (),
 		}
 	}
 }
+
+// ---- S3 operations ----
+
+/// Group of operations given by one of the coarse permission flags
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum S3OpGroup {
+	Read,
+	Write,
+	Owner,
+}
+
+macro_rules! s3_ops {
+	($($group:ident => [$($op:ident = $bit:literal),* $(,)?]),* $(,)?) => {
+		/// An operation of the S3 API on a bucket, that can be allowed
+		/// or denied to a key. Variants are named like the endpoints of the S3 API.
+		#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+		pub enum S3Op {
+			$($($op = $bit,)*)*
+		}
+
+		impl S3Op {
+			/// All operations
+			pub const ALL: &'static [S3Op] = &[$($(S3Op::$op,)*)*];
+
+			pub fn name(self) -> &'static str {
+				match self {
+					$($(S3Op::$op => stringify!($op),)*)*
+				}
+			}
+
+			/// Group of the permission flag that gives this operation
+			pub fn group(self) -> S3OpGroup {
+				match self {
+					$($(S3Op::$op => S3OpGroup::$group,)*)*
+				}
+			}
+		}
+	};
+}
+
+// The number of each operation is its bit in the stored `S3OpSet`s: it must never
+// change, and operations added later take the next free number.
+s3_ops! {
+	Read => [
+		GetBucketEncryption = 0,
+		GetBucketLifecycleConfiguration = 1,
+		GetBucketLocation = 2,
+		GetBucketVersioning = 3,
+		GetObject = 4,
+		GetObjectAttributes = 5,
+		GetObjectLegalHold = 6,
+		GetObjectLockConfiguration = 7,
+		GetObjectRetention = 8,
+		GetObjectTagging = 9,
+		HeadBucket = 10,
+		HeadObject = 11,
+		ListMultipartUploads = 12,
+		ListObjects = 13,
+		ListObjectsV2 = 14,
+		ListObjectVersions = 15,
+		ListParts = 16,
+		SelectObjectContent = 17,
+	],
+	Write => [
+		AbortMultipartUpload = 18,
+		CompleteMultipartUpload = 19,
+		CopyObject = 20,
+		CreateMultipartUpload = 21,
+		DeleteBucketEncryption = 22,
+		DeleteBucketLifecycle = 23,
+		DeleteObject = 24,
+		DeleteObjectTagging = 25,
+		DeleteObjects = 26,
+		PostObject = 27,
+		PutBucketEncryption = 28,
+		PutBucketLifecycleConfiguration = 29,
+		PutBucketVersioning = 30,
+		PutObject = 31,
+		PutObjectLegalHold = 32,
+		PutObjectLockConfiguration = 33,
+		PutObjectRetention = 34,
+		PutObjectTagging = 35,
+		RestoreObject = 36,
+		UploadPart = 37,
+		UploadPartCopy = 38,
+	],
+	Owner => [
+		DeleteBucket = 39,
+		DeleteBucketCors = 40,
+		DeleteBucketLogging = 41,
+		DeleteBucketPolicy = 42,
+		DeleteBucketWebsite = 43,
+		GetBucketCors = 44,
+		GetBucketLogging = 45,
+		GetBucketNotificationConfiguration = 46,
+		GetBucketPolicy = 47,
+		GetBucketPolicyStatus = 48,
+		GetBucketWebsite = 49,
+		PutBucketCors = 50,
+		PutBucketLogging = 51,
+		PutBucketNotificationConfiguration = 52,
+		PutBucketPolicy = 53,
+		PutBucketWebsite = 54,
+	],
+}
+
+impl fmt::Display for S3Op {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(self.name())
+	}
+}
+
+impl FromStr for S3Op {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		S3Op::ALL
+			.iter()
+			.find(|op| op.name() == s)
+			.copied()
+			.ok_or_else(|| format!("Unknown S3 operation: {}", s))
+	}
+}
+
+/// A set of S3 operations, stored as a bitmap in which each operation has a
+/// fixed bit. The bits of operations that are unknown to this version of Garage,
+/// which were set by a later version, are kept as they are.
+#[derive(
+	Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize,
+)]
+pub struct S3OpSet(u64);
+
+impl S3OpSet {
+	pub const EMPTY: Self = Self(0);
+	pub const ALL: Self = Self::of(S3Op::ALL);
+
+	const fn of(ops: &[S3Op]) -> Self {
+		let mut bits = 0;
+		let mut i = 0;
+		while i < ops.len() {
+			bits |= 1 << ops[i] as u64;
+			i += 1;
+		}
+		Self(bits)
+	}
+
+	/// Operations given by the read, write and owner permission flags
+	pub fn for_flags(read: bool, write: bool, owner: bool) -> Self {
+		let mut ret = Self::EMPTY;
+		if read {
+			ret = ret.union(Self::group(S3OpGroup::Read));
+		}
+		if write {
+			ret = ret.union(Self::group(S3OpGroup::Write));
+		}
+		if owner {
+			ret = ret.union(Self::group(S3OpGroup::Owner));
+		}
+		ret
+	}
+
+	/// All the operations of a group
+	pub fn group(group: S3OpGroup) -> Self {
+		S3Op::ALL
+			.iter()
+			.filter(|op| op.group() == group)
+			.copied()
+			.collect()
+	}
+
+	pub fn contains(&self, op: S3Op) -> bool {
+		self.0 & (1 << op as u64) != 0
+	}
+
+	pub fn insert(&mut self, op: S3Op) {
+		self.0 |= 1 << op as u64;
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.0 == 0
+	}
+
+	pub fn union(self, other: Self) -> Self {
+		Self(self.0 | other.0)
+	}
+
+	pub fn intersection(self, other: Self) -> Self {
+		Self(self.0 & other.0)
+	}
+
+	pub fn difference(self, other: Self) -> Self {
+		Self(self.0 & !other.0)
+	}
+
+	pub fn iter(self) -> impl Iterator<Item = S3Op> {
+		S3Op::ALL
+			.iter()
+			.copied()
+			.filter(move |op| self.contains(*op))
+	}
+}
+
+impl FromIterator<S3Op> for S3OpSet {
+	fn from_iter<I: IntoIterator<Item = S3Op>>(iter: I) -> Self {
+		let mut ret = Self::EMPTY;
+		for op in iter {
+			ret.insert(op);
+		}
+		ret
+	}
+}
+
+impl fmt::Display for S3OpSet {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let names = self.iter().map(S3Op::name).collect::<Vec<_>>();
+		f.write_str(&names.join(", "))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use garage_util::encode::{nonversioned_decode, nonversioned_encode};
+
+	#[test]
+	fn test_s3_op_set() {
+		assert_eq!(S3OpSet::ALL.iter().count(), S3Op::ALL.len());
+		assert_eq!(S3OpSet::for_flags(true, true, true), S3OpSet::ALL,);
+		let read = S3OpSet::group(S3OpGroup::Read);
+		assert!(read.contains(S3Op::GetObject));
+		assert!(!read.contains(S3Op::PutObject));
+		assert_eq!("ListObjectsV2".parse::<S3Op>(), Ok(S3Op::ListObjectsV2));
+		assert!("Foo".parse::<S3Op>().is_err());
+	}
+
+	#[test]
+	fn test_bucket_key_perm_migration() {
+		#[derive(Serialize)]
+		struct OldBucketKeyPerm {
+			timestamp: u64,
+			allow_read: bool,
+			allow_write: bool,
+			allow_owner: bool,
+		}
+		let old = OldBucketKeyPerm {
+			timestamp: 12,
+			allow_read: true,
+			allow_write: false,
+			allow_owner: false,
+		};
+		let bytes = nonversioned_encode(&old).unwrap();
+		let perm = nonversioned_decode::<BucketKeyPerm>(&bytes).unwrap();
+		assert!(perm.allow_read && !perm.allow_write);
+		assert_eq!(perm.allowed_ops(), S3OpSet::group(S3OpGroup::Read));
+		assert!(perm.denied_ops.is_empty() && perm.extra_ops.is_empty());
+
+		let mut perm = perm;
+		perm.set_ops(std::iter::once(S3Op::ListObjects).collect(), false);
+		perm.set_ops(std::iter::once(S3Op::PutObject).collect(), true);
+		let bytes = nonversioned_encode(&perm).unwrap();
+		let decoded = nonversioned_decode::<BucketKeyPerm>(&bytes).unwrap();
+		assert_eq!(decoded, perm);
+		assert_eq!(
+			decoded.denied_ops.iter().collect::<Vec<_>>(),
+			vec![S3Op::ListObjects]
+		);
+		assert_eq!(
+			decoded.extra_ops.iter().collect::<Vec<_>>(),
+			vec![S3Op::PutObject]
+		);
+		assert!(decoded.allowed_ops().contains(S3Op::GetObject));
+		assert!(!decoded.allowed_ops().contains(S3Op::ListObjects));
+
+		// Giving the flag again removes the single operations of its group
+		perm.set_read(true);
+		assert!(perm.denied_ops.is_empty());
+		assert_eq!(perm.extra_ops, decoded.extra_ops);
+	}
+
+	#[test]
+	fn test_unknown_ops_are_kept() {
+		// An operation that a later version of Garage denied individually
+		let unknown = S3OpSet(1 << 63);
+		let mut perm = BucketKeyPerm {
+			timestamp: 1,
+			denied_ops: unknown,
+			..BucketKeyPerm::ALL_PERMISSIONS
+		};
+		assert_eq!(perm.allowed_ops(), S3OpSet::ALL);
+
+		perm.set_ops(std::iter::once(S3Op::DeleteObject).collect(), false);
+		let bytes = nonversioned_encode(&perm).unwrap();
+		let decoded = nonversioned_decode::<BucketKeyPerm>(&bytes).unwrap();
+		assert_eq!(decoded, perm);
+		assert_eq!(
+			decoded.denied_ops,
+			unknown.union(std::iter::once(S3Op::DeleteObject).collect())
+		);
+	}
+
+	#[test]
+	fn test_bucket_key_perm_merge() {
+		let mut a = BucketKeyPerm {
+			timestamp: 1,
+			..BucketKeyPerm::ALL_PERMISSIONS
+		};
+		assert!(!a.allow_bypass_governance);
+		let mut b = a;
+		b.set_ops(std::iter::once(S3Op::DeleteObject).collect(), false);
+		a.merge(&b);
+		assert!(!a.allowed_ops().contains(S3Op::DeleteObject));
+		assert!(a.allowed_ops().contains(S3Op::PutObject));
+	}
+}
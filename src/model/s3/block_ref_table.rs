@@ -28,6 +28,22 @@ mod v08 {
 	}
 
 	impl garage_util::migrate::InitialFormat for BlockRef {}
+
+	/// A block ref whose block hash was stored truncated, as could happen
+	/// because of a bug in earlier versions of Garage. Such entries cannot
+	/// be decoded as a `BlockRef` and are only read by the repair procedure.
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct TruncatedBlockRef {
+		/// Truncated hash of the block
+		#[serde(with = "serde_bytes")]
+		pub block: Vec<u8>,
+		/// Id of the Version for the object containing this block
+		pub version: Uuid,
+		/// Is the Version that contains this block deleted
+		pub deleted: crdt::Bool,
+	}
+
+	impl garage_util::migrate::InitialFormat for TruncatedBlockRef {}
 }
 
 pub use v08::*;
@@ -0,0 +1,479 @@
+//! Background worker that applies the lifecycle rules of buckets:
+//! expiration of objects, permanent deletion of old noncurrent versions
+//! and abortion of old incomplete multipart uploads
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::prelude::*;
+use tokio::sync::watch;
+
+use garage_util::background::*;
+use garage_util::data::*;
+use garage_util::error::{Error, OkOrMessage};
+use garage_util::migrate::Migrate;
+use garage_util::persister::PersisterShared;
+use garage_util::time::*;
+use garage_util::tranquilizer::Tranquilizer;
+
+use garage_table::replication::TableReplication;
+use garage_table::*;
+
+use crate::bucket_table::*;
+use crate::garage::Garage;
+use crate::s3::object_table::*;
+
+mod v090 {
+	use serde::{Deserialize, Serialize};
+
+	#[derive(Serialize, Deserialize, Clone)]
+	pub struct LifecycleWorkerPersisted {
+		/// Date (yyyy-mm-dd, UTC) of the last completed pass
+		pub last_completed: Option<String>,
+		pub tranquility: u32,
+	}
+
+	impl garage_util::migrate::InitialFormat for LifecycleWorkerPersisted {
+		const VERSION_MARKER: &'static [u8] = b"G09lwp";
+	}
+}
+
+pub use v090::*;
+
+const INITIAL_LIFECYCLE_TRANQUILITY: u32 = 2;
+
+impl Default for LifecycleWorkerPersisted {
+	fn default() -> Self {
+		LifecycleWorkerPersisted {
+			last_completed: None,
+			tranquility: INITIAL_LIFECYCLE_TRANQUILITY,
+		}
+	}
+}
+
+enum State {
+	Completed(Option<NaiveDate>),
+	Running {
+		date: NaiveDate,
+		pos: Vec<u8>,
+		counter: usize,
+		objects_expired: usize,
+		versions_deleted: usize,
+		mpu_aborted: usize,
+	},
+}
+
+enum Skip {
+	None,
+	Bucket,
+}
+
+pub struct LifecycleWorker {
+	garage: Arc<Garage>,
+
+	state: State,
+	last_bucket: Option<Bucket>,
+	tranquilizer: Tranquilizer,
+
+	persister: PersisterShared<LifecycleWorkerPersisted>,
+}
+
+impl LifecycleWorker {
+	pub fn new(garage: Arc<Garage>, persister: PersisterShared<LifecycleWorkerPersisted>) -> Self {
+		let last_completed =
+			persister.get_with(|p| p.last_completed.as_deref().and_then(parse_lifecycle_date));
+		Self {
+			garage,
+			state: State::Completed(last_completed),
+			last_bucket: None,
+			tranquilizer: Tranquilizer::new(30),
+			persister,
+		}
+	}
+
+	fn start_pass(&mut self, date: NaiveDate) {
+		info!("Starting lifecycle worker pass for {}", date);
+		self.state = State::Running {
+			date,
+			pos: vec![],
+			counter: 0,
+			objects_expired: 0,
+			versions_deleted: 0,
+			mpu_aborted: 0,
+		};
+		self.last_bucket = None;
+	}
+}
+
+#[async_trait]
+impl Worker for LifecycleWorker {
+	fn name(&self) -> String {
+		"Object lifecycle worker".into()
+	}
+
+	fn status(&self) -> WorkerStatus {
+		let tranquility = self.persister.get_with(|p| p.tranquility);
+		match &self.state {
+			State::Completed(None) => WorkerStatus {
+				tranquility: Some(tranquility),
+				freeform: vec!["No pass completed yet".into()],
+				..Default::default()
+			},
+			State::Completed(Some(date)) => WorkerStatus {
+				tranquility: Some(tranquility),
+				freeform: vec![format!("Last pass completed for {}", date)],
+				..Default::default()
+			},
+			State::Running {
+				date,
+				counter,
+				objects_expired,
+				versions_deleted,
+				mpu_aborted,
+				..
+			} => WorkerStatus {
+				tranquility: Some(tranquility),
+				progress: Some(format!("{}", counter)),
+				freeform: vec![
+					format!("Running pass for {}", date),
+					format!("Objects expired: {}", objects_expired),
+					format!("Noncurrent versions deleted: {}", versions_deleted),
+					format!("Multipart uploads aborted: {}", mpu_aborted),
+				],
+				..Default::default()
+			},
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		match &mut self.state {
+			State::Completed(_) => Ok(WorkerState::Idle),
+			State::Running {
+				date,
+				pos,
+				counter,
+				objects_expired,
+				versions_deleted,
+				mpu_aborted,
+			} => {
+				let (object_bytes, next_pos) = match self
+					.garage
+					.object_table
+					.data
+					.store
+					.get_gt(&pos)?
+				{
+					None => {
+						info!(
+							"Lifecycle worker finished for {}, objects expired: {}, noncurrent versions deleted: {}, multipart uploads aborted: {}",
+							date, objects_expired, versions_deleted, mpu_aborted
+						);
+						let date = *date;
+						self.persister
+							.set_with(|p| p.last_completed = Some(date.to_string()))?;
+						self.state = State::Completed(Some(date));
+						return Ok(WorkerState::Idle);
+					}
+					Some((k, v)) => (v, k),
+				};
+				*pos = next_pos;
+				*counter += 1;
+
+				let object = Object::decode(&object_bytes).ok_or_message("Cannot decode Object")?;
+
+				self.tranquilizer.reset();
+				let (skip, wrote) = process_object(
+					&self.garage,
+					*date,
+					&object,
+					objects_expired,
+					versions_deleted,
+					mpu_aborted,
+					&mut self.last_bucket,
+				)
+				.await?;
+				if let Skip::Bucket = skip {
+					// Sort keys are UTF-8 strings, which never contain the byte 0xFF:
+					// this position comes after all objects of the bucket.
+					let mut next = object.bucket_id.to_vec();
+					next.push(0xFF);
+					*pos = next;
+				}
+
+				if wrote {
+					let tranquility = self.persister.get_with(|p| p.tranquility);
+					Ok(self.tranquilizer.tranquilize_worker(tranquility))
+				} else {
+					Ok(WorkerState::Busy)
+				}
+			}
+		}
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		match &self.state {
+			State::Completed(last_completed) => {
+				let today = today();
+				if last_completed.map(|d| d < today).unwrap_or(true) {
+					self.start_pass(today);
+					return WorkerState::Busy;
+				}
+				// Wait until the next day starts, plus a bit of margin
+				let next_start = midnight_ts(today.succ_opt().expect("no tomorrow"));
+				let delay = next_start.saturating_sub(now_msec()) + 10_000;
+				tokio::time::sleep(Duration::from_millis(delay)).await;
+				WorkerState::Busy
+			}
+			State::Running { .. } => WorkerState::Busy,
+		}
+	}
+}
+
+/// Apply the lifecycle rules of its bucket to an object.
+/// Returns whether the rest of the bucket can be skipped,
+/// and whether anything was written.
+async fn process_object(
+	garage: &Arc<Garage>,
+	now_date: NaiveDate,
+	object: &Object,
+	objects_expired: &mut usize,
+	versions_deleted: &mut usize,
+	mpu_aborted: &mut usize,
+	last_bucket: &mut Option<Bucket>,
+) -> Result<(Skip, bool), Error> {
+	if !object
+		.versions()
+		.iter()
+		.any(|v| v.is_data() || v.is_uploading())
+	{
+		return Ok((Skip::None, false));
+	}
+
+	let bucket = match last_bucket.take() {
+		Some(b) if b.id == object.bucket_id => b,
+		_ => {
+			match garage
+				.bucket_table
+				.get(&EmptyKey, &object.bucket_id)
+				.await?
+			{
+				Some(b) => b,
+				None => {
+					warn!(
+						"Lifecycle worker: object in non-existent bucket {:?}",
+						object.bucket_id
+					);
+					return Ok((Skip::Bucket, false));
+				}
+			}
+		}
+	};
+
+	let lifecycle_policy = match bucket
+		.state
+		.as_option()
+		.and_then(|x| x.lifecycle_config.get().as_ref())
+	{
+		Some(lc) if !lc.is_empty() => lc.clone(),
+		_ => {
+			*last_bucket = Some(bucket);
+			return Ok((Skip::Bucket, false));
+		}
+	};
+	let versioned = bucket.versioning_enabled();
+	*last_bucket = Some(bucket);
+
+	// All objects of a bucket are stored on the same nodes:
+	// only the first of them applies the lifecycle rules of the bucket
+	let write_nodes = garage
+		.object_table
+		.data
+		.replication
+		.write_nodes(&object.bucket_id);
+	if write_nodes.first() != Some(&garage.system.id) {
+		return Ok((Skip::Bucket, false));
+	}
+
+	let mut wrote = false;
+
+	if let Some(v) = object.current_version() {
+		let expired = version_size(v)
+			.map(|size| {
+				lifecycle_policy.iter().any(|rule| {
+					rule.applies_to(&object.key, size, v.tags.get())
+						&& check_expiration(rule, v.timestamp, now_date)
+				})
+			})
+			.unwrap_or(false);
+
+		if expired {
+			info!(
+				"Lifecycle: expiring object {:?} in bucket {:?}",
+				object.key, object.bucket_id
+			);
+			let timestamp = object
+				.versions()
+				.iter()
+				.map(|v| v.timestamp + 1)
+				.fold(now_msec(), std::cmp::max);
+			let deleted_object = Object::new(
+				object.bucket_id,
+				object.key.clone(),
+				vec![ObjectVersion {
+					uuid: gen_uuid(),
+					timestamp,
+					state: ObjectVersionState::Complete(ObjectVersionData::DeleteMarker),
+					versioned,
+					lock: ObjectVersionLock::default(),
+					tags: Default::default(),
+					storage_class: Default::default(),
+					restore: Default::default(),
+				}],
+			);
+			garage.object_table.insert(&deleted_object).await?;
+			*objects_expired += 1;
+			wrote = true;
+		}
+	}
+
+	// A version becomes noncurrent when the next complete version is written,
+	// so the delay before its deletion is counted from that moment
+	let now = now_msec();
+	let complete_versions = object
+		.versions()
+		.iter()
+		.filter(|v| v.is_complete())
+		.collect::<Vec<_>>();
+	let deleted_versions = complete_versions
+		.windows(2)
+		.filter(|w| {
+			let (v, next) = (w[0], w[1]);
+			let size = match version_size(v) {
+				Some(size) => size,
+				None => return false,
+			};
+			!v.lock.is_locked(now, false)
+				&& lifecycle_policy.iter().any(|rule| {
+					rule.applies_to(&object.key, size, v.tags.get())
+						&& rule
+							.noncurrent_expiration_days
+							.map(|days| date_after_days(next.timestamp, days) <= now_date)
+							.unwrap_or(false)
+				})
+		})
+		.map(|w| aborted_version(w[0]))
+		.collect::<Vec<_>>();
+	if !deleted_versions.is_empty() {
+		info!(
+			"Lifecycle: deleting {} noncurrent version(s) of object {:?} in bucket {:?}",
+			deleted_versions.len(),
+			object.key,
+			object.bucket_id
+		);
+		*versions_deleted += deleted_versions.len();
+		let deleted_object = Object::new(object.bucket_id, object.key.clone(), deleted_versions);
+		garage.object_table.insert(&deleted_object).await?;
+		wrote = true;
+	}
+
+	let aborted_versions = object
+		.versions()
+		.iter()
+		.filter(|v| v.is_uploading())
+		.filter(|v| {
+			lifecycle_policy.iter().any(|rule| {
+				rule.enabled
+					&& rule
+						.filter
+						.prefix
+						.as_ref()
+						.map(|p| object.key.starts_with(p))
+						.unwrap_or(true)
+					&& rule
+						.abort_incomplete_mpu_days
+						.map(|days| date_after_days(v.timestamp, days) <= now_date)
+						.unwrap_or(false)
+			})
+		})
+		.map(aborted_version)
+		.collect::<Vec<_>>();
+	if !aborted_versions.is_empty() {
+		info!(
+			"Lifecycle: aborting {} incomplete upload(s) of object {:?} in bucket {:?}",
+			aborted_versions.len(),
+			object.key,
+			object.bucket_id
+		);
+		*mpu_aborted += aborted_versions.len();
+		let aborted_object = Object::new(object.bucket_id, object.key.clone(), aborted_versions);
+		garage.object_table.insert(&aborted_object).await?;
+		wrote = true;
+	}
+
+	Ok((Skip::None, wrote))
+}
+
+/// Size of an object version, if it holds data
+fn version_size(v: &ObjectVersion) -> Option<u64> {
+	match &v.state {
+		ObjectVersionState::Complete(ObjectVersionData::Inline(meta, _))
+		| ObjectVersionState::Complete(ObjectVersionData::FirstBlock(meta, _)) => Some(meta.size),
+		_ => None,
+	}
+}
+
+/// Tombstone that permanently removes a version (or an incomplete upload)
+/// when merged into its object
+fn aborted_version(v: &ObjectVersion) -> ObjectVersion {
+	ObjectVersion {
+		state: ObjectVersionState::Aborted,
+		uuid: v.uuid,
+		timestamp: v.timestamp,
+		versioned: v.versioned,
+		lock: ObjectVersionLock::default(),
+		tags: Default::default(),
+		storage_class: Default::default(),
+		restore: Default::default(),
+	}
+}
+
+fn check_expiration(rule: &LifecycleRule, timestamp: u64, now_date: NaiveDate) -> bool {
+	match &rule.expiration {
+		None => false,
+		Some(LifecycleExpiration::AfterDays(days)) => date_after_days(timestamp, *days) <= now_date,
+		Some(LifecycleExpiration::AtDate(exp_date)) => match parse_lifecycle_date(exp_date) {
+			Some(exp_date) => now_date >= exp_date,
+			None => {
+				warn!(
+					"Invalid expiration date stored in bucket lifecycle config: {}",
+					exp_date
+				);
+				false
+			}
+		},
+	}
+}
+
+/// Date at which an object created at `timestamp` becomes `days` days old.
+/// As on AWS, the result is rounded up to the next midnight UTC.
+fn date_after_days(timestamp: u64, days: usize) -> NaiveDate {
+	let creation_date = Utc
+		.timestamp_millis_opt(timestamp as i64)
+		.single()
+		.map(|dt| dt.date_naive())
+		.unwrap_or(NaiveDate::MIN);
+	creation_date
+		.checked_add_days(chrono::Days::new(days as u64 + 1))
+		.unwrap_or(NaiveDate::MAX)
+}
+
+fn today() -> NaiveDate {
+	Utc::now().date_naive()
+}
+
+fn midnight_ts(date: NaiveDate) -> u64 {
+	date.and_hms_opt(0, 0, 0)
+		.expect("midnight does not exist")
+		.timestamp_millis() as u64
+}
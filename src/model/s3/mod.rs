@@ -1,3 +1,10 @@
 pub mod block_ref_table;
+pub mod lifecycle_worker;
+pub mod mpu_cleanup_worker;
+pub mod notification;
 pub mod object_table;
+pub mod replication_client;
+pub mod replication_state_table;
+pub mod replication_worker;
+pub mod restore_worker;
 pub mod version_table;
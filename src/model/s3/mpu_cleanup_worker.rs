@@ -0,0 +1,330 @@
+//! Background worker that aborts multipart uploads that have been left
+//! incomplete for longer than `multipart_upload_timeout_days`, so that the
+//! blocks of their parts can be garbage collected
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use opentelemetry::{global, metrics::Counter};
+use tokio::sync::watch;
+
+use garage_util::background::*;
+use garage_util::error::{Error, OkOrMessage};
+use garage_util::migrate::Migrate;
+use garage_util::persister::PersisterShared;
+use garage_util::time::*;
+use garage_util::tranquilizer::Tranquilizer;
+
+use garage_table::replication::TableReplication;
+
+use crate::garage::Garage;
+use crate::s3::object_table::*;
+
+mod v090 {
+	use serde::{Deserialize, Serialize};
+
+	#[derive(Serialize, Deserialize, Clone)]
+	pub struct MpuCleanupWorkerPersisted {
+		/// Timestamp (msec) at which the last pass was completed
+		pub last_completed: Option<u64>,
+		pub tranquility: u32,
+	}
+
+	impl garage_util::migrate::InitialFormat for MpuCleanupWorkerPersisted {
+		const VERSION_MARKER: &'static [u8] = b"G09mcwp";
+	}
+}
+
+pub use v090::*;
+
+const INITIAL_MPU_CLEANUP_TRANQUILITY: u32 = 2;
+
+/// Interval between the start of two passes of the worker
+const MPU_CLEANUP_INTERVAL: Duration = Duration::from_secs(24 * 3600);
+
+impl Default for MpuCleanupWorkerPersisted {
+	fn default() -> Self {
+		MpuCleanupWorkerPersisted {
+			last_completed: None,
+			tranquility: INITIAL_MPU_CLEANUP_TRANQUILITY,
+		}
+	}
+}
+
+enum State {
+	Completed(Option<u64>),
+	Running {
+		cutoff: u64,
+		pos: Vec<u8>,
+		counter: usize,
+		mpu_aborted: usize,
+	},
+}
+
+pub struct MpuCleanupWorker {
+	garage: Arc<Garage>,
+
+	state: State,
+	tranquilizer: Tranquilizer,
+	aborted_counter: Counter<u64>,
+
+	persister: PersisterShared<MpuCleanupWorkerPersisted>,
+}
+
+impl MpuCleanupWorker {
+	pub fn new(garage: Arc<Garage>, persister: PersisterShared<MpuCleanupWorkerPersisted>) -> Self {
+		let last_completed = persister.get_with(|p| p.last_completed);
+		let aborted_counter = global::meter("garage_model/s3")
+			.u64_counter("mpu_cleanup.aborted_uploads")
+			.with_description("Number of incomplete multipart uploads aborted after a timeout")
+			.init();
+		Self {
+			garage,
+			state: State::Completed(last_completed),
+			tranquilizer: Tranquilizer::new(30),
+			aborted_counter,
+			persister,
+		}
+	}
+
+	fn timeout_msec(&self) -> Option<u64> {
+		match self.garage.config.multipart_upload_timeout_days {
+			0 => None,
+			days => Some(days * 24 * 3600 * 1000),
+		}
+	}
+}
+
+#[async_trait]
+impl Worker for MpuCleanupWorker {
+	fn name(&self) -> String {
+		"Multipart upload cleanup worker".into()
+	}
+
+	fn status(&self) -> WorkerStatus {
+		let tranquility = self.persister.get_with(|p| p.tranquility);
+		match &self.state {
+			State::Completed(None) => WorkerStatus {
+				tranquility: Some(tranquility),
+				freeform: vec!["No pass completed yet".into()],
+				..Default::default()
+			},
+			State::Completed(Some(ts)) => WorkerStatus {
+				tranquility: Some(tranquility),
+				freeform: vec![format!("Last pass completed at {}", msec_to_rfc3339(*ts))],
+				..Default::default()
+			},
+			State::Running {
+				counter,
+				mpu_aborted,
+				..
+			} => WorkerStatus {
+				tranquility: Some(tranquility),
+				progress: Some(format!("{}", counter)),
+				freeform: vec![format!("Multipart uploads aborted: {}", mpu_aborted)],
+				..Default::default()
+			},
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		match &mut self.state {
+			State::Completed(_) => Ok(WorkerState::Idle),
+			State::Running {
+				cutoff,
+				pos,
+				counter,
+				mpu_aborted,
+			} => {
+				let (object_bytes, next_pos) =
+					match self.garage.object_table.data.store.get_gt(&pos)? {
+						None => {
+							info!(
+								"Multipart upload cleanup finished, uploads aborted: {}",
+								mpu_aborted
+							);
+							let now = now_msec();
+							self.persister.set_with(|p| p.last_completed = Some(now))?;
+							self.state = State::Completed(Some(now));
+							return Ok(WorkerState::Idle);
+						}
+						Some((k, v)) => (v, k),
+					};
+				*pos = next_pos;
+				*counter += 1;
+
+				let object = Object::decode(&object_bytes).ok_or_message("Cannot decode Object")?;
+				if stale_uploads(&object, *cutoff).is_empty() {
+					return Ok(WorkerState::Busy);
+				}
+
+				// All objects of a bucket are stored on the same nodes:
+				// only the first of them aborts the stale uploads
+				let write_nodes = self
+					.garage
+					.object_table
+					.data
+					.replication
+					.write_nodes(&object.bucket_id);
+				if write_nodes.first() != Some(&self.garage.system.id) {
+					return Ok(WorkerState::Busy);
+				}
+
+				self.tranquilizer.reset();
+
+				// Read the object again from the cluster, as one of its uploads
+				// might have been completed or aborted since our local copy was written
+				let object = match self
+					.garage
+					.object_table
+					.get(&object.bucket_id, &object.key)
+					.await?
+				{
+					Some(o) => o,
+					None => return Ok(WorkerState::Busy),
+				};
+				let aborted_versions = stale_uploads(&object, *cutoff);
+				if aborted_versions.is_empty() {
+					return Ok(WorkerState::Busy);
+				}
+
+				for v in aborted_versions.iter() {
+					info!(
+						"Aborting multipart upload {:?} of object {:?} in bucket {:?}, started at {}",
+						v.uuid,
+						object.key,
+						object.bucket_id,
+						msec_to_rfc3339(v.timestamp)
+					);
+				}
+				*mpu_aborted += aborted_versions.len();
+				self.aborted_counter.add(aborted_versions.len() as u64, &[]);
+				let aborted_object =
+					Object::new(object.bucket_id, object.key.clone(), aborted_versions);
+				self.garage.object_table.insert(&aborted_object).await?;
+
+				let tranquility = self.persister.get_with(|p| p.tranquility);
+				Ok(self.tranquilizer.tranquilize_worker(tranquility))
+			}
+		}
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		match &self.state {
+			State::Completed(last_completed) => {
+				let timeout = match self.timeout_msec() {
+					Some(t) => t,
+					None => {
+						// Cleanup is disabled in the configuration
+						tokio::time::sleep(MPU_CLEANUP_INTERVAL).await;
+						return WorkerState::Idle;
+					}
+				};
+				let interval = MPU_CLEANUP_INTERVAL.as_millis() as u64;
+				let next_start = last_completed.map(|t| t + interval).unwrap_or(0);
+				let now = now_msec();
+				if next_start <= now {
+					info!("Starting multipart upload cleanup pass");
+					self.state = State::Running {
+						cutoff: now.saturating_sub(timeout),
+						pos: vec![],
+						counter: 0,
+						mpu_aborted: 0,
+					};
+					return WorkerState::Busy;
+				}
+				tokio::time::sleep(Duration::from_millis(next_start - now)).await;
+				WorkerState::Busy
+			}
+			State::Running { .. } => WorkerState::Busy,
+		}
+	}
+}
+
+/// Returns the versions of an object that are multipart uploads started
+/// before `cutoff`, in their aborted state. Versions that are not
+/// in the uploading state, e.g. because they have been completed, are never returned.
+fn stale_uploads(object: &Object, cutoff: u64) -> Vec<ObjectVersion> {
+	object
+		.versions()
+		.iter()
+		.filter(|v| v.is_uploading() && v.timestamp < cutoff)
+		.map(|v| ObjectVersion {
+			state: ObjectVersionState::Aborted,
+			..v.clone()
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use garage_util::crdt::Crdt;
+	use garage_util::data::*;
+
+	fn version(timestamp: u64, state: ObjectVersionState) -> ObjectVersion {
+		ObjectVersion {
+			uuid: gen_uuid(),
+			timestamp,
+			state,
+			versioned: false,
+			lock: ObjectVersionLock::default(),
+			tags: Default::default(),
+			storage_class: Default::default(),
+			restore: Default::default(),
+		}
+	}
+
+	fn uploading() -> ObjectVersionState {
+		ObjectVersionState::Uploading(ObjectVersionHeaders {
+			content_type: "text/plain".into(),
+			other: Default::default(),
+		})
+	}
+
+	#[test]
+	fn test_stale_uploads() {
+		let old_upload = version(1000, uploading());
+		let recent_upload = version(5000, uploading());
+		let completed = version(
+			500,
+			ObjectVersionState::Complete(ObjectVersionData::DeleteMarker),
+		);
+		let object = Object::new(
+			gen_uuid(),
+			"key".into(),
+			vec![
+				completed,
+				old_upload.clone(),
+				recent_upload,
+				version(800, ObjectVersionState::Aborted),
+			],
+		);
+
+		let stale = stale_uploads(&object, 2000);
+		assert_eq!(stale.len(), 1);
+		assert_eq!(stale[0].uuid, old_upload.uuid);
+		assert_eq!(stale[0].timestamp, old_upload.timestamp);
+		assert_eq!(stale[0].state, ObjectVersionState::Aborted);
+	}
+
+	#[test]
+	fn test_stale_uploads_completed_concurrently() {
+		let upload = version(1000, uploading());
+		let mut object = Object::new(gen_uuid(), "key".into(), vec![upload.clone()]);
+		assert_eq!(stale_uploads(&object, 2000).len(), 1);
+
+		// The upload is completed before the worker re-reads the object
+		let completed = Object::new(
+			object.bucket_id,
+			object.key.clone(),
+			vec![ObjectVersion {
+				state: ObjectVersionState::Complete(ObjectVersionData::DeleteMarker),
+				..upload
+			}],
+		);
+		object.merge(&completed);
+		assert!(stale_uploads(&object, 2000).is_empty());
+	}
+}
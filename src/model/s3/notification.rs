@@ -0,0 +1,700 @@
+//! Notifications of the changes of the objects of a bucket, sent to the
+//! webhooks or NATS subjects of its notification configuration. Events are
+//! detected by the object table on the first node storing the bucket's
+//! partition, and kept in memory until they are delivered by the
+//! `NotificationWorker`.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use hyper::client::{connect::HttpConnector, Client};
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, Method, Request, Uri};
+use hyper_rustls::HttpsConnector;
+use percent_encoding::{percent_decode_str, utf8_percent_encode};
+use serde::Serialize;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::{watch, Notify};
+
+use garage_table::replication::{TableReplication, TableShardedReplication};
+use garage_table::*;
+use garage_util::background::*;
+use garage_util::data::*;
+use garage_util::encode::json_encode;
+use garage_util::error::Error;
+use garage_util::time::*;
+
+use crate::bucket_table::{NotificationConfig, NotificationEvent};
+use crate::garage::Garage;
+use crate::s3::object_table::*;
+use crate::s3::replication_client::KEY_ENCODE_SET;
+
+/// Number of times the delivery of a notification is attempted
+const MAX_DELIVERY_ATTEMPTS: u32 = 3;
+/// Delay before the first retry of a failed delivery, doubled at each retry
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(1);
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+/// Port of NATS servers, when it is not given in the URL
+const NATS_DEFAULT_PORT: u16 = 4222;
+
+/// A change of an object, that may be notified
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectEvent {
+	pub bucket_id: Uuid,
+	pub key: String,
+	pub event: NotificationEvent,
+	pub etag: String,
+	pub size: u64,
+	pub version_id: String,
+	/// Timestamp (msec) of the version that was created or removed
+	pub timestamp: u64,
+}
+
+/// Events waiting to be delivered. The queue is bounded: when it is full,
+/// the oldest events are dropped.
+pub struct NotificationQueue {
+	replication: TableShardedReplication,
+	capacity: usize,
+	events: Mutex<VecDeque<ObjectEvent>>,
+	dropped: AtomicU64,
+	notify: Notify,
+}
+
+impl NotificationQueue {
+	pub fn new(replication: TableShardedReplication, capacity: usize) -> Self {
+		Self {
+			replication,
+			capacity,
+			events: Mutex::new(VecDeque::new()),
+			dropped: AtomicU64::new(0),
+			notify: Notify::new(),
+		}
+	}
+
+	/// Number of events waiting to be delivered
+	pub fn len(&self) -> usize {
+		self.events.lock().unwrap().len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	/// Number of events that were dropped because the queue was full
+	pub fn dropped(&self) -> u64 {
+		self.dropped.load(Ordering::Relaxed)
+	}
+
+	/// Queue the events corresponding to an update of the object table.
+	/// All the nodes storing the object see the update, only the first of them
+	/// queues the events so that they are delivered once.
+	pub(crate) fn object_updated(&self, old: Option<&Object>, new: Option<&Object>) {
+		let new = match new {
+			Some(new) => new,
+			None => return,
+		};
+		let write_nodes = self.replication.write_nodes(&new.partition_key().hash());
+		if write_nodes.first() != Some(&self.replication.system.id) {
+			return;
+		}
+		for event in object_events(old, new) {
+			self.push(event);
+		}
+	}
+
+	fn push(&self, event: ObjectEvent) {
+		if self.capacity == 0 {
+			return;
+		}
+		let mut events = self.events.lock().unwrap();
+		while events.len() >= self.capacity {
+			events.pop_front();
+			self.dropped.fetch_add(1, Ordering::Relaxed);
+		}
+		events.push_back(event);
+		drop(events);
+		self.notify.notify_one();
+	}
+
+	fn pop(&self) -> Option<ObjectEvent> {
+		self.events.lock().unwrap().pop_front()
+	}
+}
+
+/// Events that correspond to the change of an object from `old` to `new`:
+/// the versions that were completed, and if there are none, the versions
+/// that were removed (versions are also removed when they are overwritten,
+/// which is only notified as the creation of the new version)
+fn object_events(old: Option<&Object>, new: &Object) -> Vec<ObjectEvent> {
+	let old_versions = old.map(|o| o.versions()).unwrap_or_default();
+	let event = |event, v: &ObjectVersion, meta: Option<&ObjectVersionMeta>| ObjectEvent {
+		bucket_id: new.bucket_id,
+		key: new.key.clone(),
+		event,
+		etag: meta.map(|m| m.etag.clone()).unwrap_or_default(),
+		size: meta.map(|m| m.size).unwrap_or(0),
+		version_id: v.version_id(),
+		timestamp: v.timestamp,
+	};
+
+	let mut events = vec![];
+	for v in new.versions() {
+		let data = match &v.state {
+			ObjectVersionState::Complete(data) => data,
+			_ => continue,
+		};
+		let was_complete = old_versions
+			.iter()
+			.any(|ov| ov.uuid == v.uuid && matches!(ov.state, ObjectVersionState::Complete(_)));
+		if was_complete {
+			continue;
+		}
+		events.push(match data {
+			ObjectVersionData::DeleteMarker if v.versioned => {
+				event(NotificationEvent::ObjectRemovedDeleteMarkerCreated, v, None)
+			}
+			ObjectVersionData::DeleteMarker => {
+				event(NotificationEvent::ObjectRemovedDelete, v, None)
+			}
+			ObjectVersionData::Inline(meta, _) | ObjectVersionData::FirstBlock(meta, _) => {
+				// The ETag of objects uploaded in several parts ends with the number of parts
+				let e = match meta.etag.contains('-') {
+					true => NotificationEvent::ObjectCreatedCompleteMultipartUpload,
+					false => NotificationEvent::ObjectCreatedPut,
+				};
+				event(e, v, Some(meta))
+			}
+		});
+	}
+
+	if events.is_empty() {
+		for ov in old_versions.iter() {
+			let meta = match &ov.state {
+				ObjectVersionState::Complete(ObjectVersionData::Inline(meta, _))
+				| ObjectVersionState::Complete(ObjectVersionData::FirstBlock(meta, _)) => meta,
+				_ => continue,
+			};
+			let still_data = new
+				.versions()
+				.iter()
+				.any(|v| v.uuid == ov.uuid && v.is_data());
+			if !still_data {
+				events.push(event(
+					NotificationEvent::ObjectRemovedDelete,
+					ov,
+					Some(meta),
+				));
+			}
+		}
+	}
+	events
+}
+
+pub struct NotificationWorker {
+	garage: Arc<Garage>,
+	client: Client<HttpsConnector<HttpConnector>>,
+	delivered: u64,
+	failed: u64,
+}
+
+impl NotificationWorker {
+	pub fn new(garage: Arc<Garage>) -> Self {
+		let connector = hyper_rustls::HttpsConnectorBuilder::new()
+			.with_native_roots()
+			.https_or_http()
+			.enable_http1()
+			.enable_http2()
+			.build();
+		Self {
+			garage,
+			client: Client::builder().build(connector),
+			delivered: 0,
+			failed: 0,
+		}
+	}
+
+	/// Send the notifications of an event to the webhooks and NATS subjects of the bucket
+	async fn notify(&mut self, event: &ObjectEvent) -> Result<(), Error> {
+		let bucket = match self
+			.garage
+			.bucket_table
+			.get(&EmptyKey, &event.bucket_id)
+			.await?
+		{
+			Some(b) => b,
+			None => return Ok(()),
+		};
+		let params = match bucket.params() {
+			Some(p) => p,
+			None => return Ok(()),
+		};
+		let configs = match params.notification_config.get() {
+			Some(c) => c,
+			None => return Ok(()),
+		};
+
+		// Events refer to the bucket by one of its global names, if it has one
+		let bucket_name = params
+			.aliases
+			.items()
+			.iter()
+			.find(|(_, _, active)| *active)
+			.map(|(name, _, _)| name.clone())
+			.unwrap_or_else(|| hex::encode(event.bucket_id));
+
+		for config in configs.iter() {
+			if !config.applies_to(&event.key, event.event) {
+				continue;
+			}
+			let message = EventMessage::new(
+				&self.garage.config.s3_api.s3_region,
+				&bucket_name,
+				config,
+				event,
+			);
+			let body = json_encode(&message)?;
+			match self.deliver(&config.endpoint_url, &body).await {
+				Ok(()) => self.delivered += 1,
+				Err(e) => {
+					self.failed += 1;
+					error!(
+						"Could not deliver notification to {}: {}. Payload: {}",
+						config.endpoint_url,
+						e,
+						String::from_utf8_lossy(&body)
+					);
+				}
+			}
+		}
+		Ok(())
+	}
+
+	/// POST a notification to a webhook or publish it to a NATS subject,
+	/// retrying with exponential backoff
+	async fn deliver(&self, url: &str, body: &[u8]) -> Result<(), Error> {
+		let nats = NatsTarget::parse(url);
+		let mut attempt = 0;
+		loop {
+			let res = match &nats {
+				Some(target) => target.publish(body).await,
+				None => self.post(url, body).await,
+			};
+			attempt += 1;
+			match res {
+				Ok(()) => return Ok(()),
+				Err(e) if attempt >= MAX_DELIVERY_ATTEMPTS => return Err(e),
+				Err(e) => {
+					debug!("Notification delivery to {} failed ({}), retrying", url, e);
+					tokio::time::sleep(RETRY_BASE_DELAY * 2u32.pow(attempt - 1)).await;
+				}
+			}
+		}
+	}
+
+	async fn post(&self, url: &str, body: &[u8]) -> Result<(), Error> {
+		let req = Request::builder()
+			.method(Method::POST)
+			.uri(url)
+			.header(CONTENT_TYPE, "application/json")
+			.body(Body::from(body.to_vec()))?;
+		let resp = tokio::time::timeout(DELIVERY_TIMEOUT, self.client.request(req))
+			.await
+			.map_err(|_| Error::Timeout)??;
+		if resp.status().is_success() {
+			Ok(())
+		} else {
+			Err(Error::Message(format!(
+				"webhook returned HTTP status {}",
+				resp.status()
+			)))
+		}
+	}
+}
+
+/// A NATS subject to which notifications are published, configured by
+/// a `nats://[user:password@|token@]host[:port]/subject` URL
+#[derive(Debug, PartialEq, Eq)]
+pub struct NatsTarget {
+	pub host: String,
+	pub port: u16,
+	pub user: Option<String>,
+	pub password: Option<String>,
+	pub token: Option<String>,
+	pub subject: String,
+}
+
+impl NatsTarget {
+	/// Returns `None` if the URL is not a valid `nats://` URL
+	pub fn parse(url: &str) -> Option<Self> {
+		let uri = url.parse::<Uri>().ok()?;
+		if uri.scheme_str() != Some("nats") || uri.query().is_some() {
+			return None;
+		}
+		let authority = uri.authority()?;
+		let host = authority
+			.host()
+			.trim_start_matches('[')
+			.trim_end_matches(']')
+			.to_string();
+		if host.is_empty() {
+			return None;
+		}
+		let port = authority.port_u16().unwrap_or(NATS_DEFAULT_PORT);
+
+		let decode = |s: &str| {
+			percent_decode_str(s)
+				.decode_utf8()
+				.ok()
+				.map(|s| s.into_owned())
+		};
+		let (mut user, mut password, mut token) = (None, None, None);
+		if let Some((userinfo, _)) = authority.as_str().rsplit_once('@') {
+			match userinfo.split_once(':') {
+				Some((u, p)) => {
+					user = Some(decode(u)?);
+					password = Some(decode(p)?);
+				}
+				None => token = Some(decode(userinfo)?),
+			}
+		}
+
+		// Messages can only be published to subjects without wildcards
+		let subject = decode(uri.path().trim_start_matches('/'))?;
+		if subject.is_empty()
+			|| subject.contains(char::is_whitespace)
+			|| subject
+				.split('.')
+				.any(|t| t.is_empty() || t == "*" || t == ">")
+		{
+			return None;
+		}
+
+		Some(Self {
+			host,
+			port,
+			user,
+			password,
+			token,
+			subject,
+		})
+	}
+
+	async fn publish(&self, body: &[u8]) -> Result<(), Error> {
+		tokio::time::timeout(DELIVERY_TIMEOUT, self.publish_inner(body))
+			.await
+			.map_err(|_| Error::Timeout)?
+	}
+
+	/// Publish a message with the client protocol of NATS. The server is
+	/// sent a PING after the message, so that its PONG acknowledges that
+	/// the message was accepted. Servers requiring TLS are not supported.
+	async fn publish_inner(&self, body: &[u8]) -> Result<(), Error> {
+		let stream = TcpStream::connect((self.host.as_str(), self.port)).await?;
+		let (read, mut write) = stream.into_split();
+		let mut read = BufReader::new(read);
+
+		let mut line = String::new();
+		read.read_line(&mut line).await?;
+		if !line.starts_with("INFO ") {
+			return Err(Error::Message(format!(
+				"unexpected greeting from NATS server: {}",
+				line.trim_end()
+			)));
+		}
+		if line.contains("\"tls_required\":true") {
+			return Err(Error::Message(
+				"NATS servers that require TLS are not supported".into(),
+			));
+		}
+
+		let connect = NatsConnect {
+			verbose: false,
+			pedantic: false,
+			tls_required: false,
+			name: "garage",
+			lang: "rust",
+			version: env!("CARGO_PKG_VERSION"),
+			user: self.user.as_deref(),
+			pass: self.password.as_deref(),
+			auth_token: self.token.as_deref(),
+		};
+		let mut msg = b"CONNECT ".to_vec();
+		msg.extend(json_encode(&connect)?);
+		msg.extend(format!("\r\nPUB {} {}\r\n", self.subject, body.len()).as_bytes());
+		msg.extend(body);
+		msg.extend(b"\r\nPING\r\n");
+		write.write_all(&msg).await?;
+
+		loop {
+			line.clear();
+			if read.read_line(&mut line).await? == 0 {
+				return Err(Error::Message(
+					"connection closed by the NATS server".into(),
+				));
+			}
+			match line.trim_end() {
+				"PONG" => return Ok(()),
+				"PING" => write.write_all(b"PONG\r\n").await?,
+				l if l.starts_with("-ERR") => {
+					return Err(Error::Message(format!(
+						"NATS server returned an error: {}",
+						l[4..].trim()
+					)))
+				}
+				_ => (),
+			}
+		}
+	}
+}
+
+#[derive(Serialize)]
+struct NatsConnect<'a> {
+	verbose: bool,
+	pedantic: bool,
+	tls_required: bool,
+	name: &'a str,
+	lang: &'a str,
+	version: &'a str,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	user: Option<&'a str>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pass: Option<&'a str>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	auth_token: Option<&'a str>,
+}
+
+#[async_trait]
+impl Worker for NotificationWorker {
+	fn name(&self) -> String {
+		"Bucket notifications".into()
+	}
+
+	fn status(&self) -> WorkerStatus {
+		let queue = &self.garage.notification_queue;
+		WorkerStatus {
+			queue_length: Some(queue.len() as u64),
+			freeform: vec![
+				format!("Notifications delivered: {}", self.delivered),
+				format!("Notifications failed: {}", self.failed),
+				format!("Events dropped: {}", queue.dropped()),
+			],
+			..Default::default()
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		match self.garage.notification_queue.pop() {
+			Some(event) => {
+				self.notify(&event).await?;
+				Ok(WorkerState::Busy)
+			}
+			None => Ok(WorkerState::Idle),
+		}
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		self.garage.notification_queue.notify.notified().await;
+		WorkerState::Busy
+	}
+}
+
+// ---- JSON format of the notifications, as sent by Amazon S3 ----
+
+#[derive(Serialize)]
+struct EventMessage {
+	#[serde(rename = "Records")]
+	records: Vec<EventRecord>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct EventRecord {
+	event_version: &'static str,
+	event_source: &'static str,
+	aws_region: String,
+	event_time: String,
+	event_name: &'static str,
+	s3: EventS3,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct EventS3 {
+	s3_schema_version: &'static str,
+	configuration_id: String,
+	bucket: EventBucket,
+	object: EventObject,
+}
+
+#[derive(Serialize)]
+struct EventBucket {
+	name: String,
+	arn: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct EventObject {
+	key: String,
+	size: u64,
+	e_tag: String,
+	version_id: String,
+	sequencer: String,
+}
+
+impl EventMessage {
+	fn new(
+		region: &str,
+		bucket_name: &str,
+		config: &NotificationConfig,
+		event: &ObjectEvent,
+	) -> Self {
+		EventMessage {
+			records: vec![EventRecord {
+				event_version: "2.1",
+				event_source: "garage:s3",
+				aws_region: region.to_string(),
+				event_time: msec_to_rfc3339(now_msec()),
+				event_name: event.event.name(),
+				s3: EventS3 {
+					s3_schema_version: "1.0",
+					configuration_id: config.id.clone(),
+					bucket: EventBucket {
+						name: bucket_name.to_string(),
+						arn: format!("arn:aws:s3:::{}", bucket_name),
+					},
+					object: EventObject {
+						key: utf8_percent_encode(&event.key, KEY_ENCODE_SET).to_string(),
+						size: event.size,
+						e_tag: event.etag.clone(),
+						version_id: event.version_id.clone(),
+						sequencer: format!("{:016X}", event.timestamp),
+					},
+				},
+			}],
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn version(uuid: u8, timestamp: u64, data: Option<&str>, versioned: bool) -> ObjectVersion {
+		let data = match data {
+			Some(etag) => ObjectVersionData::Inline(
+				ObjectVersionMeta {
+					headers: ObjectVersionHeaders {
+						content_type: "text/plain".into(),
+						other: Default::default(),
+					},
+					size: 3,
+					etag: etag.into(),
+					checksum: None,
+				},
+				b"abc".to_vec(),
+			),
+			None => ObjectVersionData::DeleteMarker,
+		};
+		ObjectVersion {
+			uuid: Uuid::from([uuid; 32]),
+			timestamp,
+			state: ObjectVersionState::Complete(data),
+			versioned,
+			lock: Default::default(),
+			tags: Default::default(),
+			storage_class: Default::default(),
+			restore: Default::default(),
+		}
+	}
+
+	fn object(versions: Vec<ObjectVersion>) -> Object {
+		Object::new(Uuid::from([0u8; 32]), "key".into(), versions)
+	}
+
+	fn event_types(old: Option<&Object>, new: &Object) -> Vec<NotificationEvent> {
+		object_events(old, new)
+			.into_iter()
+			.map(|e| e.event)
+			.collect()
+	}
+
+	#[test]
+	fn test_object_events() {
+		let v1 = object(vec![version(1, 1, Some("a"), false)]);
+		assert_eq!(
+			event_types(None, &v1),
+			vec![NotificationEvent::ObjectCreatedPut]
+		);
+		// Merging the same object again is not an event
+		assert_eq!(event_types(Some(&v1), &v1), vec![]);
+
+		// Overwriting an object only notifies the creation of the new version
+		let v2 = object(vec![version(2, 2, Some("b-2"), false)]);
+		assert_eq!(
+			event_types(Some(&v1), &v2),
+			vec![NotificationEvent::ObjectCreatedCompleteMultipartUpload]
+		);
+
+		let deleted = object(vec![version(3, 3, None, false)]);
+		assert_eq!(
+			event_types(Some(&v2), &deleted),
+			vec![NotificationEvent::ObjectRemovedDelete]
+		);
+
+		let marker = object(vec![
+			version(1, 1, Some("a"), true),
+			version(3, 3, None, true),
+		]);
+		assert_eq!(
+			event_types(Some(&object(vec![version(1, 1, Some("a"), true)])), &marker),
+			vec![NotificationEvent::ObjectRemovedDeleteMarkerCreated]
+		);
+
+		// Removal of a specific version
+		let removed = object(vec![version(3, 3, None, true)]);
+		let events = object_events(Some(&marker), &removed);
+		assert_eq!(events.len(), 1);
+		assert_eq!(events[0].event, NotificationEvent::ObjectRemovedDelete);
+		assert_eq!(events[0].version_id, hex::encode([1u8; 32]));
+	}
+
+	#[test]
+	fn test_nats_target() {
+		assert_eq!(
+			NatsTarget::parse("nats://localhost/garage.events"),
+			Some(NatsTarget {
+				host: "localhost".into(),
+				port: NATS_DEFAULT_PORT,
+				user: None,
+				password: None,
+				token: None,
+				subject: "garage.events".into(),
+			})
+		);
+		assert_eq!(
+			NatsTarget::parse("nats://garage:p%40ss@[::1]:4223/events"),
+			Some(NatsTarget {
+				host: "::1".into(),
+				port: 4223,
+				user: Some("garage".into()),
+				password: Some("p@ss".into()),
+				token: None,
+				subject: "events".into(),
+			})
+		);
+		let target = NatsTarget::parse("nats://s3cr3t@nats.local/events").unwrap();
+		assert_eq!(target.token.as_deref(), Some("s3cr3t"));
+		assert_eq!(target.user, None);
+
+		assert_eq!(NatsTarget::parse("https://localhost/events"), None);
+		assert_eq!(NatsTarget::parse("nats://localhost/"), None);
+		assert_eq!(NatsTarget::parse("nats://localhost/events.*"), None);
+		assert_eq!(NatsTarget::parse("nats://localhost/events..created"), None);
+		assert_eq!(NatsTarget::parse("nats://localhost/events?x=1"), None);
+	}
+}
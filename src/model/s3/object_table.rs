@@ -10,6 +10,7 @@ use garage_table::replication::TableShardedReplication;
 use garage_table::*;
 
 use crate::index_counter::*;
+use crate::s3::notification::NotificationQueue;
 use crate::s3::version_table::*;
 
 pub const OBJECTS: &str = "objects";
@@ -17,6 +18,7 @@ pub const UNFINISHED_UPLOADS: &str = "unfinished_uploads";
 pub const BYTES: &str = "bytes";
 
 mod v05 {
+	use garage_util::crdt;
 	use garage_util::data::{Hash, Uuid};
 	use serde::{Deserialize, Serialize};
 	use std::collections::BTreeMap;
@@ -43,6 +45,84 @@ mod v05 {
 		pub timestamp: u64,
 		/// State of the version
 		pub state: ObjectVersionState,
+		/// Whether the version was created while versioning was enabled on
+		/// the bucket, in which case it is kept when it is overwritten or deleted.
+		/// Other versions are the "null" version of S3 versioning.
+		#[serde(default)]
+		pub versioned: bool,
+		/// Object lock protection of the version
+		#[serde(default)]
+		pub lock: ObjectVersionLock,
+		/// Tags of the version, which are always replaced as a whole
+		#[serde(default)]
+		pub tags: crdt::Lww<ObjectTags>,
+		/// Storage class of the version, set when it is created
+		#[serde(default)]
+		pub storage_class: StorageClass,
+		/// Whether a version in the GLACIER storage class has been restored
+		/// and can be read
+		#[serde(default)]
+		pub restore: crdt::Lww<ObjectRestoreStatus>,
+	}
+
+	/// Storage class of an object version
+	#[derive(
+		Default, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize,
+	)]
+	pub enum StorageClass {
+		/// The version can always be read
+		#[default]
+		Standard,
+		/// The version is archived, and can only be read for a limited time
+		/// after it has been restored with a RestoreObject call
+		Glacier,
+	}
+
+	/// Restore status of an object version in the GLACIER storage class
+	#[derive(
+		Default, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize,
+	)]
+	pub enum ObjectRestoreStatus {
+		/// No restore was requested
+		#[default]
+		None,
+		/// A restore was requested for the given number of days,
+		/// and is being processed
+		Pending { days: u64 },
+		/// The version can be read until the given timestamp (in msec)
+		Available { until: u64 },
+	}
+
+	/// Tags of an object version, as (key, value) pairs
+	#[derive(Default, PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
+	pub struct ObjectTags(pub Vec<(String, String)>);
+
+	/// Object lock protection of an object version, that prevents it from being deleted
+	#[derive(Default, PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct ObjectVersionLock {
+		/// Retention mode, and date until which the version is protected
+		pub retention: crdt::Lww<Option<ObjectRetention>>,
+		/// Whether a legal hold protects the version, independently of its retention
+		pub legal_hold: crdt::Lww<bool>,
+	}
+
+	/// Object lock retention of an object version
+	#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize)]
+	pub struct ObjectRetention {
+		/// Retention mode
+		pub mode: ObjectLockMode,
+		/// Timestamp (in msec) until which the version is protected
+		pub retain_until: u64,
+	}
+
+	/// Object lock retention mode
+	#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize)]
+	pub enum ObjectLockMode {
+		/// The retention can be shortened or removed by keys that are allowed
+		/// to bypass governance retention
+		Governance,
+		/// The retention can only be extended, by anyone
+		Compliance,
 	}
 
 	/// State of an object version
@@ -77,6 +157,28 @@ mod v05 {
 		pub size: u64,
 		/// etag of the object
 		pub etag: String,
+		/// Checksum of the object, if one was given or requested when it was uploaded
+		#[serde(default)]
+		pub checksum: Option<ObjectChecksum>,
+	}
+
+	/// Algorithm of a checksum of the x-amz-checksum-* family
+	#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize)]
+	pub enum ChecksumAlgorithm {
+		Crc32,
+		Crc32c,
+		Sha1,
+		Sha256,
+	}
+
+	/// Checksum of the content of an object version, or of a part of a multipart upload
+	#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
+	pub struct ObjectChecksum {
+		/// Algorithm of the checksum
+		pub algorithm: ChecksumAlgorithm,
+		/// Checksum encoded in base64. For objects uploaded with multipart uploads,
+		/// checksum of the checksums of the parts, followed by `-<number of parts>`
+		pub value: String,
 	}
 
 	/// Additional headers for an object
@@ -98,8 +200,9 @@ mod v08 {
 	use super::v05;
 
 	pub use v05::{
-		ObjectVersion, ObjectVersionData, ObjectVersionHeaders, ObjectVersionMeta,
-		ObjectVersionState,
+		ChecksumAlgorithm, ObjectChecksum, ObjectLockMode, ObjectRestoreStatus, ObjectRetention,
+		ObjectTags, ObjectVersion, ObjectVersionData, ObjectVersionHeaders, ObjectVersionLock,
+		ObjectVersionMeta, ObjectVersionState, StorageClass,
 	};
 
 	/// An object
@@ -166,6 +269,25 @@ impl Object {
 	pub fn versions(&self) -> &[ObjectVersion] {
 		&self.versions[..]
 	}
+
+	/// Get the current version of the object, i.e. its last complete version,
+	/// which can be a delete marker
+	pub fn current_version(&self) -> Option<&ObjectVersion> {
+		self.versions.iter().rev().find(|v| v.is_complete())
+	}
+
+	/// Get the current version of the object if it is not a delete marker
+	pub fn current_data_version(&self) -> Option<&ObjectVersion> {
+		self.current_version().filter(|v| v.is_data())
+	}
+
+	/// Find a complete version of the object by its S3 version id
+	pub fn find_version(&self, version_id: &str) -> Option<&ObjectVersion> {
+		self.versions
+			.iter()
+			.rev()
+			.find(|v| v.is_complete() && v.version_id() == version_id)
+	}
 }
 
 impl Crdt for ObjectVersionState {
@@ -193,6 +315,132 @@ impl AutoCrdt for ObjectVersionData {
 	const WARN_IF_DIFFERENT: bool = true;
 }
 
+impl Crdt for ObjectVersionLock {
+	fn merge(&mut self, other: &Self) {
+		self.retention.merge(&other.retention);
+		self.legal_hold.merge(&other.legal_hold);
+	}
+}
+
+impl ObjectVersionLock {
+	/// Create the object lock protection of a new version
+	pub fn new(retention: Option<ObjectRetention>, legal_hold: bool) -> Self {
+		Self {
+			retention: Lww::new(retention),
+			legal_hold: Lww::new(legal_hold),
+		}
+	}
+
+	/// Get the retention of the version, if it has not expired at time `now`
+	pub fn active_retention(&self, now: u64) -> Option<ObjectRetention> {
+		self.retention.get().filter(|r| r.retain_until > now)
+	}
+
+	/// Check whether the version cannot be deleted at time `now`.
+	/// Governance retention is ignored if `bypass_governance` is set.
+	pub fn is_locked(&self, now: u64, bypass_governance: bool) -> bool {
+		*self.legal_hold.get()
+			|| match self.active_retention(now) {
+				Some(r) => r.mode == ObjectLockMode::Compliance || !bypass_governance,
+				None => false,
+			}
+	}
+}
+
+impl AutoCrdt for ObjectTags {
+	const WARN_IF_DIFFERENT: bool = false;
+}
+
+impl AutoCrdt for ObjectRestoreStatus {
+	const WARN_IF_DIFFERENT: bool = false;
+}
+
+impl StorageClass {
+	/// Parse the value of the `x-amz-storage-class` header. The storage classes
+	/// of AWS that make objects unavailable until they are restored map to GLACIER,
+	/// the other ones to STANDARD.
+	pub fn parse(name: &str) -> Option<Self> {
+		match name {
+			"STANDARD"
+			| "REDUCED_REDUNDANCY"
+			| "STANDARD_IA"
+			| "ONEZONE_IA"
+			| "INTELLIGENT_TIERING"
+			| "GLACIER_IR"
+			| "OUTPOSTS"
+			| "SNOW"
+			| "EXPRESS_ONEZONE" => Some(StorageClass::Standard),
+			"GLACIER" | "DEEP_ARCHIVE" => Some(StorageClass::Glacier),
+			_ => None,
+		}
+	}
+
+	/// Name of the storage class in the S3 API
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			StorageClass::Standard => "STANDARD",
+			StorageClass::Glacier => "GLACIER",
+		}
+	}
+}
+
+impl ChecksumAlgorithm {
+	pub const ALL: &'static [ChecksumAlgorithm] = &[
+		ChecksumAlgorithm::Crc32,
+		ChecksumAlgorithm::Crc32c,
+		ChecksumAlgorithm::Sha1,
+		ChecksumAlgorithm::Sha256,
+	];
+
+	/// Parse the name of an algorithm, as given in the
+	/// `x-amz-checksum-algorithm` and `x-amz-sdk-checksum-algorithm` headers
+	pub fn parse(name: &str) -> Option<Self> {
+		Self::ALL
+			.iter()
+			.find(|a| a.as_str().eq_ignore_ascii_case(name))
+			.copied()
+	}
+
+	/// Name of the algorithm in the S3 API
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			ChecksumAlgorithm::Crc32 => "CRC32",
+			ChecksumAlgorithm::Crc32c => "CRC32C",
+			ChecksumAlgorithm::Sha1 => "SHA1",
+			ChecksumAlgorithm::Sha256 => "SHA256",
+		}
+	}
+
+	/// Name of the header that gives a checksum computed with this algorithm
+	pub fn header_name(&self) -> &'static str {
+		match self {
+			ChecksumAlgorithm::Crc32 => "x-amz-checksum-crc32",
+			ChecksumAlgorithm::Crc32c => "x-amz-checksum-crc32c",
+			ChecksumAlgorithm::Sha1 => "x-amz-checksum-sha1",
+			ChecksumAlgorithm::Sha256 => "x-amz-checksum-sha256",
+		}
+	}
+}
+
+impl AutoCrdt for ObjectChecksum {
+	const WARN_IF_DIFFERENT: bool = true;
+}
+
+impl ObjectTags {
+	/// Get the value of a tag
+	pub fn get(&self, key: &str) -> Option<&str> {
+		self.0
+			.iter()
+			.find(|(k, _)| k == key)
+			.map(|(_, v)| v.as_str())
+	}
+
+	/// Check whether all the given tags are set with the same values
+	pub fn contains_all(&self, tags: &[(String, String)]) -> bool {
+		tags.iter().all(|(k, v)| self.get(k) == Some(v.as_str()))
+	}
+}
+
 impl ObjectVersion {
 	fn cmp_key(&self) -> (u64, Uuid) {
 		(self.timestamp, self.uuid)
@@ -216,6 +464,31 @@ impl ObjectVersion {
 			_ => false,
 		}
 	}
+
+	/// Is the object version a delete marker
+	pub fn is_delete_marker(&self) -> bool {
+		self.state == ObjectVersionState::Complete(ObjectVersionData::DeleteMarker)
+	}
+
+	/// Can the data of the version be read at time `now`: versions in the GLACIER
+	/// storage class can only be read while they are restored
+	pub fn is_readable(&self, now: u64) -> bool {
+		match self.storage_class {
+			StorageClass::Standard => true,
+			StorageClass::Glacier => {
+				matches!(self.restore.get(), ObjectRestoreStatus::Available { until } if *until > now)
+			}
+		}
+	}
+
+	/// S3 version id of this version: "null" for unversioned versions
+	pub fn version_id(&self) -> String {
+		if self.versioned {
+			hex::encode(self.uuid)
+		} else {
+			"null".into()
+		}
+	}
 }
 
 impl Entry<Uuid, String> for Object {
@@ -226,9 +499,15 @@ impl Entry<Uuid, String> for Object {
 		&self.key
 	}
 	fn is_tombstone(&self) -> bool {
-		self.versions.len() == 1
+		// An object whose versions have all been aborted or deleted by version id
+		// no longer holds anything, and can be removed like a delete marker
+		(self.versions.len() == 1
 			&& self.versions[0].state
-				== ObjectVersionState::Complete(ObjectVersionData::DeleteMarker)
+				== ObjectVersionState::Complete(ObjectVersionData::DeleteMarker))
+			|| self
+				.versions
+				.iter()
+				.all(|v| v.state == ObjectVersionState::Aborted)
 	}
 }
 
@@ -242,6 +521,11 @@ impl Crdt for Object {
 			{
 				Ok(i) => {
 					self.versions[i].state.merge(&other_v.state);
+					self.versions[i].lock.merge(&other_v.lock);
+					self.versions[i].tags.merge(&other_v.tags);
+					self.versions[i].storage_class =
+						std::cmp::max(self.versions[i].storage_class, other_v.storage_class);
+					self.versions[i].restore.merge(&other_v.restore);
 				}
 				Err(i) => {
 					self.versions.insert(i, other_v.clone());
@@ -250,17 +534,28 @@ impl Crdt for Object {
 		}
 
 		// Remove versions which are obsolete, i.e. those that come
-		// before the last version which .is_complete().
+		// before the last unversioned version which .is_complete(),
+		// except versions that were created with versioning enabled and that
+		// are either complete or aborted. An aborted version is the only record
+		// of its deletion (e.g. by version id): a replica that missed the deletion
+		// would otherwise bring it back. An object whose versions are all aborted
+		// is a tombstone, and is removed by the table GC once all replicas have it.
 		let last_complete = self
 			.versions
 			.iter()
 			.enumerate()
 			.rev()
-			.find(|(_, v)| v.is_complete())
+			.find(|(_, v)| v.is_complete() && !v.versioned)
 			.map(|(vi, _)| vi);
 
 		if let Some(last_vi) = last_complete {
-			self.versions = self.versions.drain(last_vi..).collect::<Vec<_>>();
+			let mut vi = 0;
+			self.versions.retain(|v| {
+				let keep = vi >= last_vi
+					|| (v.versioned && (v.is_complete() || v.state == ObjectVersionState::Aborted));
+				vi += 1;
+				keep
+			});
 		}
 	}
 }
@@ -268,6 +563,7 @@ impl Crdt for Object {
 pub struct ObjectTable {
 	pub version_table: Arc<Table<VersionTable, TableShardedReplication>>,
 	pub object_counter_table: Arc<IndexCounter<Object>>,
+	pub notification_queue: Arc<NotificationQueue>,
 }
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
@@ -307,7 +603,7 @@ impl TableSchema for ObjectTable {
 					.versions
 					.binary_search_by(|nv| nv.cmp_key().cmp(&v.cmp_key()))
 				{
-					Err(_) => true,
+					Err(_) => v.state != ObjectVersionState::Aborted,
 					Ok(i) => {
 						new_v.versions[i].state == ObjectVersionState::Aborted
 							&& v.state != ObjectVersionState::Aborted
@@ -330,9 +626,15 @@ impl TableSchema for ObjectTable {
 		Ok(())
 	}
 
+	fn after_commit(&self, old: Option<&Self::E>, new: Option<&Self::E>) {
+		// Queue the notifications of the changes of the object, only once they
+		// are stored: the transaction in which `updated` runs can be retried or aborted
+		self.notification_queue.object_updated(old, new);
+	}
+
 	fn matches_filter(entry: &Self::E, filter: &Self::Filter) -> bool {
 		match filter {
-			ObjectFilter::IsData => entry.versions.iter().any(|v| v.is_data()),
+			ObjectFilter::IsData => entry.current_data_version().is_some(),
 			ObjectFilter::IsUploading => entry.versions.iter().any(|v| v.is_uploading()),
 		}
 	}
@@ -355,7 +657,7 @@ impl CountedItem for Object {
 
 	fn counts(&self) -> Vec<(&'static str, i64)> {
 		let versions = self.versions();
-		let n_objects = if versions.iter().any(|v| v.is_data()) {
+		let n_objects = if self.current_data_version().is_some() {
 			1
 		} else {
 			0
@@ -380,3 +682,120 @@ impl CountedItem for Object {
 		]
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn version(timestamp: u64, versioned: bool) -> ObjectVersion {
+		ObjectVersion {
+			uuid: gen_uuid(),
+			timestamp,
+			state: ObjectVersionState::Complete(ObjectVersionData::Inline(
+				ObjectVersionMeta {
+					headers: ObjectVersionHeaders {
+						content_type: "text/plain".into(),
+						other: Default::default(),
+					},
+					size: 4,
+					etag: "etag".into(),
+					checksum: None,
+				},
+				b"data".to_vec(),
+			)),
+			versioned,
+			lock: ObjectVersionLock::default(),
+			tags: Default::default(),
+			storage_class: Default::default(),
+			restore: Default::default(),
+		}
+	}
+
+	fn aborted(v: &ObjectVersion) -> ObjectVersion {
+		ObjectVersion {
+			state: ObjectVersionState::Aborted,
+			..v.clone()
+		}
+	}
+
+	#[test]
+	fn test_merge_keeps_deleted_versions_deleted() {
+		let bucket_id = gen_uuid();
+		let v1 = version(1000, true);
+		let v2 = version(2000, true);
+		let initial = Object::new(bucket_id, "key".into(), vec![v1.clone(), v2.clone()]);
+		let delete_v1 = Object::new(bucket_id, "key".into(), vec![aborted(&v1)]);
+
+		// v1 is deleted by its version id: the write reaches a quorum of
+		// replicas A and B, and replica C misses it
+		let mut a = initial.clone();
+		let mut b = initial.clone();
+		let mut c = initial;
+		a.merge(&delete_v1);
+		b.merge(&delete_v1);
+		assert_eq!(a, b);
+
+		// Anti-entropy, in both directions and in any order
+		c.merge(&a);
+		a.merge(&c);
+		b.merge(&c);
+		for replica in [&a, &b, &c] {
+			assert_eq!(replica, &a);
+			let states = replica
+				.versions()
+				.iter()
+				.map(|v| (v.uuid, v.state == ObjectVersionState::Aborted))
+				.collect::<Vec<_>>();
+			assert_eq!(states, vec![(v1.uuid, true), (v2.uuid, false)]);
+			assert!(!replica.is_tombstone());
+		}
+
+		// Once all versions are deleted, the object can be collected
+		let delete_v2 = Object::new(bucket_id, "key".into(), vec![aborted(&v2)]);
+		a.merge(&delete_v2);
+		c.merge(&a);
+		assert_eq!(a, c);
+		assert!(c.is_tombstone());
+	}
+
+	#[test]
+	fn test_merge_prunes_dominated_versions() {
+		let bucket_id = gen_uuid();
+		let v1 = version(1000, false);
+		let v2 = version(2000, true);
+		let v3 = version(3000, false);
+		let v4 = version(4000, false);
+		let mut object = Object::new(bucket_id, "key".into(), vec![v1.clone(), v2.clone()]);
+		object.merge(&Object::new(bucket_id, "key".into(), vec![aborted(&v2)]));
+		object.merge(&Object::new(bucket_id, "key".into(), vec![v3.clone()]));
+
+		// The unversioned version v1 is replaced by v3, the deletion of the
+		// versioned version v2 is kept
+		let uuids = |o: &Object| o.versions().iter().map(|v| v.uuid).collect::<Vec<_>>();
+		assert_eq!(uuids(&object), vec![v2.uuid, v3.uuid]);
+		assert_eq!(object.versions()[0].state, ObjectVersionState::Aborted);
+
+		// Merging a stale copy does not bring them back
+		object.merge(&Object::new(bucket_id, "key".into(), vec![v1, v2.clone()]));
+		assert_eq!(uuids(&object), vec![v2.uuid, v3.uuid]);
+		assert_eq!(object.versions()[0].state, ObjectVersionState::Aborted);
+
+		// An unversioned version that is aborted is removed once it is replaced
+		object.merge(&Object::new(bucket_id, "key".into(), vec![aborted(&v3)]));
+		object.merge(&Object::new(bucket_id, "key".into(), vec![v4.clone()]));
+		assert_eq!(uuids(&object), vec![v2.uuid, v4.uuid]);
+	}
+
+	#[test]
+	fn test_aborted_object_is_tombstone() {
+		let bucket_id = gen_uuid();
+		let v1 = version(1000, true);
+		let v2 = version(2000, false);
+
+		let object = Object::new(bucket_id, "key".into(), vec![aborted(&v1), aborted(&v2)]);
+		assert!(object.is_tombstone());
+
+		let object = Object::new(bucket_id, "key".into(), vec![aborted(&v1), v2]);
+		assert!(!object.is_tombstone());
+	}
+}
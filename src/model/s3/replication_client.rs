@@ -0,0 +1,226 @@
+//! Minimal S3 client used to replicate objects to the buckets
+//! of other Garage clusters
+
+use std::time::{Duration, SystemTime};
+
+use aws_sigv4::http_request::{
+	sign, PayloadChecksumKind, PercentEncodingMode, SignableBody, SignableRequest, SigningParams,
+	SigningSettings, UriPathNormalizationMode,
+};
+use hyper::client::{connect::HttpConnector, Client};
+use hyper::header::{HeaderName, HeaderValue, CONTENT_LENGTH, CONTENT_TYPE};
+use hyper::{Body, Method, Request, StatusCode, Uri};
+use hyper_rustls::HttpsConnector;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+use garage_util::error::Error;
+
+use crate::bucket_table::ReplicationDestination;
+use crate::s3::object_table::ObjectVersionHeaders;
+
+/// Characters that are percent-encoded in object keys: all except
+/// the unreserved characters of RFC 3986 and the path separator
+pub(crate) const KEY_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+	.remove(b'-')
+	.remove(b'.')
+	.remove(b'_')
+	.remove(b'~')
+	.remove(b'/');
+
+/// Characters that are percent-encoded in the keys and values of tags
+pub(crate) const TAG_ENCODE_SET: &AsciiSet = &KEY_ENCODE_SET.add(b'/');
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(300);
+
+pub struct ReplicationClient {
+	client: Client<HttpsConnector<HttpConnector>>,
+}
+
+impl ReplicationClient {
+	pub fn new() -> Self {
+		let connector = hyper_rustls::HttpsConnectorBuilder::new()
+			.with_native_roots()
+			.https_or_http()
+			.enable_http1()
+			.enable_http2()
+			.build();
+		Self {
+			client: Client::builder().build(connector),
+		}
+	}
+
+	/// Write an object to the destination bucket
+	pub async fn put_object(
+		&self,
+		dest: &ReplicationDestination,
+		key: &str,
+		headers: &ObjectVersionHeaders,
+		extra_headers: &[(&str, String)],
+		size: u64,
+		body: Body,
+	) -> Result<(), Error> {
+		let mut req = Request::builder()
+			.method(Method::PUT)
+			.uri(object_uri(dest, key)?)
+			.header(CONTENT_TYPE, &headers.content_type)
+			.header(CONTENT_LENGTH, size);
+		let other_headers = headers.other.iter().map(|(k, v)| (k.as_str(), v.as_str()));
+		let extra_headers = extra_headers.iter().map(|(k, v)| (*k, v.as_str()));
+		for (name, value) in other_headers.chain(extra_headers) {
+			req = req.header(
+				HeaderName::from_bytes(name.as_bytes())
+					.map_err(|_| Error::Message(format!("Invalid header name: {}", name)))?,
+				HeaderValue::from_str(value)
+					.map_err(|_| Error::Message(format!("Invalid value for header {}", name)))?,
+			);
+		}
+		self.send(dest, req.body(body)?).await?;
+		Ok(())
+	}
+
+	/// Returns true if the object exists in the destination bucket
+	pub async fn object_exists(
+		&self,
+		dest: &ReplicationDestination,
+		key: &str,
+	) -> Result<bool, Error> {
+		let req = Request::builder()
+			.method(Method::HEAD)
+			.uri(object_uri(dest, key)?)
+			.body(Body::empty())?;
+		match self.send(dest, req).await {
+			Ok(()) => Ok(true),
+			Err(StatusOrError::Status(StatusCode::NOT_FOUND)) => Ok(false),
+			Err(e) => Err(e.into()),
+		}
+	}
+
+	/// Delete an object from the destination bucket
+	pub async fn delete_object(
+		&self,
+		dest: &ReplicationDestination,
+		key: &str,
+	) -> Result<(), Error> {
+		let req = Request::builder()
+			.method(Method::DELETE)
+			.uri(object_uri(dest, key)?)
+			.body(Body::empty())?;
+		self.send(dest, req).await?;
+		Ok(())
+	}
+
+	async fn send(
+		&self,
+		dest: &ReplicationDestination,
+		mut req: Request<Body>,
+	) -> Result<(), StatusOrError> {
+		// The body is streamed from the data blocks of the object:
+		// its hash cannot be computed before the request is sent
+		let mut settings = SigningSettings::default();
+		settings.percent_encoding_mode = PercentEncodingMode::Single;
+		settings.payload_checksum_kind = PayloadChecksumKind::XAmzSha256;
+		settings.uri_path_normalization_mode = UriPathNormalizationMode::Disabled;
+		let params = SigningParams::builder()
+			.access_key(&dest.access_key_id)
+			.secret_key(&dest.secret_access_key)
+			.region(&dest.region)
+			.service_name("s3")
+			.time(SystemTime::now())
+			.settings(settings)
+			.build()
+			.map_err(|e| Error::Message(format!("Cannot sign request: {}", e)))?;
+		let signable = SignableRequest::new(
+			req.method(),
+			req.uri(),
+			req.headers(),
+			SignableBody::UnsignedPayload,
+		);
+		let (instructions, _signature) = sign(signable, &params)
+			.map_err(|e| Error::Message(format!("Cannot sign request: {}", e)))?
+			.into_parts();
+		instructions.apply_to_request(&mut req);
+
+		let resp = tokio::time::timeout(REQUEST_TIMEOUT, self.client.request(req))
+			.await
+			.map_err(|_| Error::Message("Request to destination timed out".into()))?
+			.map_err(Error::from)?;
+
+		let status = resp.status();
+		if status.is_success() {
+			return Ok(());
+		}
+		let body = hyper::body::to_bytes(resp.into_body())
+			.await
+			.unwrap_or_default();
+		debug!(
+			"Replication destination returned error {}: {}",
+			status,
+			String::from_utf8_lossy(&body)
+		);
+		Err(StatusOrError::Status(status))
+	}
+}
+
+impl Default for ReplicationClient {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+enum StatusOrError {
+	Status(StatusCode),
+	Error(Error),
+}
+
+impl From<Error> for StatusOrError {
+	fn from(e: Error) -> Self {
+		StatusOrError::Error(e)
+	}
+}
+
+impl From<StatusOrError> for Error {
+	fn from(e: StatusOrError) -> Self {
+		match e {
+			StatusOrError::Status(s) => {
+				Error::Message(format!("Replication destination returned error {}", s))
+			}
+			StatusOrError::Error(e) => e,
+		}
+	}
+}
+
+/// Path-style URI of an object in the destination bucket
+fn object_uri(dest: &ReplicationDestination, key: &str) -> Result<Uri, Error> {
+	let uri = format!(
+		"{}/{}/{}",
+		dest.endpoint.trim_end_matches('/'),
+		dest.bucket,
+		utf8_percent_encode(key, KEY_ENCODE_SET)
+	);
+	uri.parse()
+		.map_err(|_| Error::Message(format!("Invalid replication destination URI: {}", uri)))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_object_uri() {
+		let dest = ReplicationDestination {
+			endpoint: "https://s3.example.com/".into(),
+			region: "garage".into(),
+			bucket: "backup".into(),
+			access_key_id: "GK1234".into(),
+			secret_access_key: "secret".into(),
+		};
+		assert_eq!(
+			object_uri(&dest, "photos/my cat+dog.jpg").unwrap(),
+			"https://s3.example.com/backup/photos/my%20cat%2Bdog.jpg"
+		);
+		assert_eq!(
+			object_uri(&dest, "été/~a_b-c.d").unwrap(),
+			"https://s3.example.com/backup/%C3%A9t%C3%A9/~a_b-c.d"
+		);
+	}
+}
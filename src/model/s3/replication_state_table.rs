@@ -0,0 +1,93 @@
+use garage_util::data::*;
+
+use garage_table::crdt::*;
+use garage_table::*;
+
+mod v090 {
+	use garage_util::crdt;
+	use garage_util::data::Uuid;
+	use serde::{Deserialize, Serialize};
+
+	/// The replication state table stores, for each object of a bucket that has
+	/// replication rules, the last version of the object that was replicated
+	/// to the destination of each of these rules
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct ReplicationState {
+		/// The bucket in which the object is stored, used as partition key
+		pub bucket_id: Uuid,
+		/// The key of the object, used as sort key
+		pub key: String,
+		/// Last version replicated by each rule, indexed by rule ID
+		pub rules: crdt::LwwMap<String, ReplicatedVersion>,
+	}
+
+	#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
+	pub struct ReplicatedVersion {
+		/// UUID of the object version
+		pub uuid: Uuid,
+		/// Timestamp of the object version
+		pub timestamp: u64,
+		/// Whether the version is a delete marker
+		pub delete_marker: bool,
+	}
+
+	impl garage_util::migrate::InitialFormat for ReplicationState {
+		const VERSION_MARKER: &'static [u8] = b"G09rplst";
+	}
+}
+
+pub use v090::*;
+
+impl ReplicationState {
+	pub fn new(bucket_id: Uuid, key: String) -> Self {
+		Self {
+			bucket_id,
+			key,
+			rules: crdt::LwwMap::new(),
+		}
+	}
+
+	/// Returns true if the given version of the object has already
+	/// been replicated by the rule with ID `rule_id`
+	pub fn is_replicated(&self, rule_id: &String, version_uuid: Uuid) -> bool {
+		self.rules
+			.get(rule_id)
+			.map(|v| v.uuid == version_uuid)
+			.unwrap_or(false)
+	}
+}
+
+impl AutoCrdt for ReplicatedVersion {
+	const WARN_IF_DIFFERENT: bool = false;
+}
+
+impl Crdt for ReplicationState {
+	fn merge(&mut self, other: &Self) {
+		self.rules.merge(&other.rules);
+	}
+}
+
+impl Entry<Uuid, String> for ReplicationState {
+	fn partition_key(&self) -> &Uuid {
+		&self.bucket_id
+	}
+	fn sort_key(&self) -> &String {
+		&self.key
+	}
+}
+
+pub struct ReplicationStateTable;
+
+impl TableSchema for ReplicationStateTable {
+	const TABLE_NAME: &'static str = "replication_state";
+
+	type P = Uuid;
+	type S = String;
+	type E = ReplicationState;
+	type Filter = DeletedFilter;
+
+	fn matches_filter(_entry: &Self::E, filter: &Self::Filter) -> bool {
+		// Replication states are never deleted
+		filter.apply(false)
+	}
+}
@@ -0,0 +1,554 @@
+//! Background worker that replicates the objects of buckets that have
+//! replication rules to the buckets of other Garage clusters, through their S3 API
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::stream::StreamExt;
+use hyper::Body;
+use opentelemetry::{global, metrics::Counter};
+use percent_encoding::utf8_percent_encode;
+use tokio::sync::watch;
+
+use garage_util::background::*;
+use garage_util::data::*;
+use garage_util::error::{Error, OkOrMessage};
+use garage_util::migrate::Migrate;
+use garage_util::persister::PersisterShared;
+use garage_util::time::*;
+use garage_util::tranquilizer::Tranquilizer;
+
+use garage_table::replication::TableReplication;
+use garage_table::EmptyKey;
+
+use crate::bucket_table::*;
+use crate::garage::Garage;
+use crate::s3::object_table::*;
+use crate::s3::replication_client::*;
+use crate::s3::replication_state_table::*;
+
+mod v090 {
+	use serde::{Deserialize, Serialize};
+
+	#[derive(Serialize, Deserialize, Clone)]
+	pub struct ReplicationWorkerPersisted {
+		/// Timestamp (msec) at which the last pass was completed
+		pub last_completed: Option<u64>,
+		/// Minimum delay in seconds between the start of two passes
+		pub interval_secs: u64,
+		pub tranquility: u32,
+	}
+
+	impl garage_util::migrate::InitialFormat for ReplicationWorkerPersisted {
+		const VERSION_MARKER: &'static [u8] = b"G09rplwp";
+	}
+}
+
+pub use v090::*;
+
+const INITIAL_REPLICATION_TRANQUILITY: u32 = 2;
+const INITIAL_REPLICATION_INTERVAL_SECS: u64 = 60;
+/// Header stored with the objects that are encrypted with a key provided by the client
+const SSEC_KEY_MD5_HEADER: &str = "x-amz-server-side-encryption-customer-key-md5";
+
+/// User metadata added to the objects written by replication.
+/// Objects that have it are not replicated again, so that two clusters
+/// can replicate a bucket to each other.
+pub const REPLICA_META_HEADER: &str = "x-amz-meta-garage-replica";
+
+impl Default for ReplicationWorkerPersisted {
+	fn default() -> Self {
+		ReplicationWorkerPersisted {
+			last_completed: None,
+			interval_secs: INITIAL_REPLICATION_INTERVAL_SECS,
+			tranquility: INITIAL_REPLICATION_TRANQUILITY,
+		}
+	}
+}
+
+enum State {
+	Completed(Option<u64>),
+	Running {
+		pos: Vec<u8>,
+		counter: usize,
+		/// Replication rules of the buckets seen during this pass,
+		/// or None for buckets that are not replicated by this node
+		bucket_rules: HashMap<Uuid, Option<Vec<ReplicationRule>>>,
+		replicated: usize,
+		errors: usize,
+	},
+}
+
+#[derive(Default)]
+struct ReplicationResult {
+	replicated: usize,
+	errors: usize,
+}
+
+pub struct ReplicationWorker {
+	garage: Arc<Garage>,
+	client: ReplicationClient,
+
+	state: State,
+	tranquilizer: Tranquilizer,
+	replicated_counter: Counter<u64>,
+	error_counter: Counter<u64>,
+
+	persister: PersisterShared<ReplicationWorkerPersisted>,
+}
+
+impl ReplicationWorker {
+	pub fn new(
+		garage: Arc<Garage>,
+		persister: PersisterShared<ReplicationWorkerPersisted>,
+	) -> Self {
+		let last_completed = persister.get_with(|p| p.last_completed);
+		let meter = global::meter("garage_model/s3");
+		let replicated_counter = meter
+			.u64_counter("replication.replicated_versions")
+			.with_description(
+				"Number of object versions and deletions replicated to other Garage clusters",
+			)
+			.init();
+		let error_counter = meter
+			.u64_counter("replication.errors")
+			.with_description("Number of object versions that could not be replicated")
+			.init();
+		Self {
+			garage,
+			client: ReplicationClient::new(),
+			state: State::Completed(last_completed),
+			tranquilizer: Tranquilizer::new(30),
+			replicated_counter,
+			error_counter,
+			persister,
+		}
+	}
+
+	/// Get the replication rules of a bucket, if the bucket has some
+	/// and this node is the one that replicates its objects
+	async fn get_bucket_rules(
+		&self,
+		bucket_id: Uuid,
+	) -> Result<Option<Vec<ReplicationRule>>, Error> {
+		// All objects of a bucket are stored on the same nodes:
+		// only the first of them replicates them
+		let write_nodes = self
+			.garage
+			.object_table
+			.data
+			.replication
+			.write_nodes(&bucket_id);
+		if write_nodes.first() != Some(&self.garage.system.id) {
+			return Ok(None);
+		}
+
+		let bucket = self.garage.bucket_table.get(&EmptyKey, &bucket_id).await?;
+		let rules = bucket
+			.as_ref()
+			.and_then(|b| b.params())
+			.and_then(|p| p.replication_config.get().clone())
+			.filter(|rules| rules.iter().any(|r| r.enabled));
+		Ok(rules)
+	}
+
+	/// Replicate the current version of an object with all the rules that apply to it
+	async fn replicate_object(
+		&self,
+		object: &Object,
+		rules: &[ReplicationRule],
+	) -> Result<ReplicationResult, Error> {
+		let mut result = ReplicationResult::default();
+
+		let version = match object.current_version() {
+			Some(v) if !is_replica(v) => v,
+			_ => return Ok(result),
+		};
+		let rules = rules
+			.iter()
+			.filter(|r| r.applies_to(&object.key))
+			.collect::<Vec<_>>();
+		if rules.is_empty() {
+			return Ok(result);
+		}
+
+		let state = self
+			.garage
+			.replication_state_table
+			.get(&object.bucket_id, &object.key)
+			.await?;
+		let pending_rules = rules
+			.into_iter()
+			.filter(|r| {
+				!state
+					.as_ref()
+					.map(|s| s.is_replicated(&r.id, version.uuid))
+					.unwrap_or(false)
+			})
+			.collect::<Vec<_>>();
+		if pending_rules.is_empty() {
+			return Ok(result);
+		}
+
+		let mut new_state = ReplicationState::new(object.bucket_id, object.key.clone());
+		for rule in pending_rules {
+			let res = match &version.state {
+				ObjectVersionState::Complete(ObjectVersionData::DeleteMarker) => {
+					self.replicate_delete(rule, &object.key).await
+				}
+				ObjectVersionState::Complete(data) => {
+					self.replicate_version(rule, &object.key, version, data)
+						.await
+				}
+				_ => unreachable!(),
+			};
+			match res {
+				Ok(()) => {
+					new_state.rules.update_in_place(
+						rule.id.clone(),
+						ReplicatedVersion {
+							uuid: version.uuid,
+							timestamp: version.timestamp,
+							delete_marker: !version.is_data(),
+						},
+					);
+					result.replicated += 1;
+				}
+				Err(e) => {
+					warn!(
+						"Could not replicate object {:?} of bucket {:?} to {}/{} (rule {}): {}",
+						object.key,
+						object.bucket_id,
+						rule.destination.endpoint,
+						rule.destination.bucket,
+						rule.id,
+						e
+					);
+					result.errors += 1;
+				}
+			}
+		}
+
+		if !new_state.rules.is_empty() {
+			self.garage
+				.replication_state_table
+				.insert(&new_state)
+				.await?;
+		}
+		Ok(result)
+	}
+
+	async fn replicate_version(
+		&self,
+		rule: &ReplicationRule,
+		key: &str,
+		version: &ObjectVersion,
+		data: &ObjectVersionData,
+	) -> Result<(), Error> {
+		// The data of objects encrypted with a key provided by the client (SSE-C)
+		// can only be read with that key: as on AWS, they are not replicated
+		if let ObjectVersionData::Inline(meta, _) | ObjectVersionData::FirstBlock(meta, _) = data {
+			if meta.headers.other.contains_key(SSEC_KEY_MD5_HEADER) {
+				debug!("Not replicating {}, which is encrypted with SSE-C", key);
+				return Ok(());
+			}
+		}
+
+		let (meta, body) = match data {
+			ObjectVersionData::Inline(meta, bytes) => (meta, Body::from(bytes.clone())),
+			ObjectVersionData::FirstBlock(meta, _) => {
+				let version = self
+					.garage
+					.version_table
+					.get(&version.uuid, &EmptyKey)
+					.await?
+					.ok_or_message("Version not found")?;
+				let blocks = version
+					.blocks
+					.items()
+					.iter()
+					.map(|(_, vb)| vb.hash)
+					.collect::<Vec<_>>();
+				let garage = self.garage.clone();
+				let stream = futures::stream::iter(blocks).then(move |hash| {
+					let garage = garage.clone();
+					async move { garage.block_manager.rpc_get_block(&hash, None).await }
+				});
+				(meta, Body::wrap_stream(stream))
+			}
+			ObjectVersionData::DeleteMarker => unreachable!(),
+		};
+
+		let mut extra_headers = vec![(REPLICA_META_HEADER, "true".to_string())];
+		let tags = version.tags.get();
+		if !tags.0.is_empty() {
+			extra_headers.push(("x-amz-tagging", encode_tags(tags)));
+		}
+
+		self.client
+			.put_object(
+				&rule.destination,
+				key,
+				&meta.headers,
+				&extra_headers,
+				meta.size,
+				body,
+			)
+			.await
+	}
+
+	async fn replicate_delete(&self, rule: &ReplicationRule, key: &str) -> Result<(), Error> {
+		if !rule.replicate_deletes {
+			return Ok(());
+		}
+		// Deleting an object that does not exist in a versioned bucket would create
+		// a delete marker, that would be replicated back if the destination
+		// also replicates its deletions to this bucket
+		if self.client.object_exists(&rule.destination, key).await? {
+			self.client.delete_object(&rule.destination, key).await?;
+		}
+		Ok(())
+	}
+}
+
+#[async_trait]
+impl Worker for ReplicationWorker {
+	fn name(&self) -> String {
+		"Bucket replication worker".into()
+	}
+
+	fn status(&self) -> WorkerStatus {
+		let tranquility = self.persister.get_with(|p| p.tranquility);
+		match &self.state {
+			State::Completed(None) => WorkerStatus {
+				tranquility: Some(tranquility),
+				freeform: vec!["No pass completed yet".into()],
+				..Default::default()
+			},
+			State::Completed(Some(ts)) => WorkerStatus {
+				tranquility: Some(tranquility),
+				freeform: vec![format!("Last pass completed at {}", msec_to_rfc3339(*ts))],
+				..Default::default()
+			},
+			State::Running {
+				counter,
+				replicated,
+				errors,
+				..
+			} => WorkerStatus {
+				tranquility: Some(tranquility),
+				progress: Some(format!("{}", counter)),
+				freeform: vec![
+					format!("Versions replicated: {}", replicated),
+					format!("Replication errors: {}", errors),
+				],
+				..Default::default()
+			},
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		let (object_bytes, next_pos) = match &self.state {
+			State::Completed(_) => return Ok(WorkerState::Idle),
+			State::Running { pos, .. } => match self.garage.object_table.data.store.get_gt(pos)? {
+				None => {
+					if let State::Running {
+						replicated, errors, ..
+					} = &self.state
+					{
+						info!(
+							"Bucket replication pass finished, versions replicated: {}, errors: {}",
+							replicated, errors
+						);
+					}
+					let now = now_msec();
+					self.persister.set_with(|p| p.last_completed = Some(now))?;
+					self.state = State::Completed(Some(now));
+					return Ok(WorkerState::Idle);
+				}
+				Some((k, v)) => (v, k),
+			},
+		};
+
+		let object = Object::decode(&object_bytes).ok_or_message("Cannot decode Object")?;
+		let bucket_id = object.bucket_id;
+
+		let cached_rules = match &self.state {
+			State::Running { bucket_rules, .. } => bucket_rules.get(&bucket_id).cloned(),
+			State::Completed(_) => unreachable!(),
+		};
+		let rules = match cached_rules {
+			Some(r) => r,
+			None => self.get_bucket_rules(bucket_id).await?,
+		};
+
+		let (pos, counter, bucket_rules) = match &mut self.state {
+			State::Running {
+				pos,
+				counter,
+				bucket_rules,
+				..
+			} => (pos, counter, bucket_rules),
+			State::Completed(_) => unreachable!(),
+		};
+		*counter += 1;
+		bucket_rules.insert(bucket_id, rules.clone());
+
+		let rules = match rules {
+			Some(r) => r,
+			None => {
+				// Skip all the other objects of the bucket: keys of the object table
+				// are the bucket ID followed by the object key, which is valid UTF-8
+				// and thus never starts with byte 0xFF
+				*pos = [bucket_id.as_slice(), &[0xFF]].concat();
+				return Ok(WorkerState::Busy);
+			}
+		};
+		*pos = next_pos;
+
+		self.tranquilizer.reset();
+		let result = self.replicate_object(&object, &rules).await?;
+		if result.replicated > 0 {
+			self.replicated_counter.add(result.replicated as u64, &[]);
+		}
+		if result.errors > 0 {
+			self.error_counter.add(result.errors as u64, &[]);
+		}
+		if let State::Running {
+			replicated, errors, ..
+		} = &mut self.state
+		{
+			*replicated += result.replicated;
+			*errors += result.errors;
+		}
+
+		let tranquility = self.persister.get_with(|p| p.tranquility);
+		Ok(self.tranquilizer.tranquilize_worker(tranquility))
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		match &self.state {
+			State::Completed(last_completed) => {
+				let interval = self.persister.get_with(|p| p.interval_secs) * 1000;
+				let next_start = last_completed.map(|t| t + interval).unwrap_or(0);
+				let now = now_msec();
+				if next_start <= now {
+					self.state = State::Running {
+						pos: vec![],
+						counter: 0,
+						bucket_rules: HashMap::new(),
+						replicated: 0,
+						errors: 0,
+					};
+					return WorkerState::Busy;
+				}
+				// Wake up regularly, as the interval can be changed at runtime
+				let delay = std::cmp::min(next_start - now, 1000);
+				tokio::time::sleep(Duration::from_millis(delay)).await;
+				WorkerState::Busy
+			}
+			State::Running { .. } => WorkerState::Busy,
+		}
+	}
+}
+
+/// Returns true if the version was written by the replication of another cluster
+pub fn is_replica(version: &ObjectVersion) -> bool {
+	match &version.state {
+		ObjectVersionState::Complete(ObjectVersionData::Inline(meta, _))
+		| ObjectVersionState::Complete(ObjectVersionData::FirstBlock(meta, _)) => {
+			meta.headers.other.contains_key(REPLICA_META_HEADER)
+		}
+		_ => false,
+	}
+}
+
+/// Encode tags for the x-amz-tagging header
+fn encode_tags(tags: &ObjectTags) -> String {
+	tags.0
+		.iter()
+		.map(|(k, v)| {
+			format!(
+				"{}={}",
+				utf8_percent_encode(k, TAG_ENCODE_SET),
+				utf8_percent_encode(v, TAG_ENCODE_SET)
+			)
+		})
+		.collect::<Vec<_>>()
+		.join("&")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::collections::BTreeMap;
+
+	fn rule(prefixes: &[&str], enabled: bool) -> ReplicationRule {
+		ReplicationRule {
+			id: "rule".into(),
+			enabled,
+			prefixes: prefixes.iter().map(|p| p.to_string()).collect(),
+			replicate_deletes: false,
+			destination: ReplicationDestination {
+				endpoint: "http://127.0.0.1:3900".into(),
+				region: "garage".into(),
+				bucket: "backup".into(),
+				access_key_id: "GK1234".into(),
+				secret_access_key: "secret".into(),
+			},
+		}
+	}
+
+	fn version(other: BTreeMap<String, String>) -> ObjectVersion {
+		let meta = ObjectVersionMeta {
+			headers: ObjectVersionHeaders {
+				content_type: "text/plain".into(),
+				other,
+			},
+			size: 5,
+			etag: "etag".into(),
+			checksum: None,
+		};
+		ObjectVersion {
+			uuid: gen_uuid(),
+			timestamp: now_msec(),
+			state: ObjectVersionState::Complete(ObjectVersionData::Inline(meta, b"hello".to_vec())),
+			versioned: false,
+			lock: ObjectVersionLock::default(),
+			tags: Default::default(),
+			storage_class: Default::default(),
+			restore: Default::default(),
+		}
+	}
+
+	#[test]
+	fn test_rule_applies_to() {
+		assert!(rule(&[], true).applies_to("any/key"));
+		assert!(!rule(&[], false).applies_to("any/key"));
+
+		let r = rule(&["docs/", "img/"], true);
+		assert!(r.applies_to("docs/a.txt"));
+		assert!(r.applies_to("img/b.png"));
+		assert!(!r.applies_to("other/docs/a.txt"));
+	}
+
+	#[test]
+	fn test_is_replica() {
+		assert!(!is_replica(&version(BTreeMap::new())));
+
+		let mut other = BTreeMap::new();
+		other.insert(REPLICA_META_HEADER.to_string(), "true".to_string());
+		assert!(is_replica(&version(other)));
+	}
+
+	#[test]
+	fn test_encode_tags() {
+		let tags = ObjectTags(vec![
+			("project".into(), "garage".into()),
+			("path".into(), "a/b c&d".into()),
+		]);
+		assert_eq!(encode_tags(&tags), "project=garage&path=a%2Fb%20c%26d");
+	}
+}
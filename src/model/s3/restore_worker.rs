@@ -0,0 +1,180 @@
+//! Background worker that completes the restores of GLACIER object versions
+//! requested with RestoreObject, once the configured restore delay has elapsed
+
+use std::convert::TryInto;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::sync::{watch, Notify};
+
+use garage_db as db;
+
+use garage_util::background::*;
+use garage_util::data::*;
+use garage_util::error::Error;
+use garage_util::time::*;
+
+use crate::garage::Garage;
+use crate::s3::object_table::*;
+
+/// Duration of a day of restore, in msec
+const DAY_MSEC: u64 = 24 * 3600 * 1000;
+
+/// Longest time the worker sleeps without checking the queue
+const MAX_IDLE_DURATION: Duration = Duration::from_secs(60);
+
+/// Restores requested on this node that have not been completed yet.
+/// Entries are keyed by the time at which they are due, followed by the bucket
+/// id and the uuid of the version, and contain the key of the object.
+pub struct RestoreQueue {
+	tree: db::Tree,
+	notify: Notify,
+}
+
+impl RestoreQueue {
+	pub(crate) fn new(db: &db::Db) -> Result<Self, Error> {
+		Ok(Self {
+			tree: db.open_tree("restore_queue")?,
+			notify: Notify::new(),
+		})
+	}
+
+	/// Queue the restore of a version, to be completed after `delay_secs`
+	pub fn push(
+		&self,
+		bucket_id: Uuid,
+		key: &str,
+		version_uuid: Uuid,
+		delay_secs: u64,
+	) -> Result<(), Error> {
+		let due = now_msec() + delay_secs * 1000;
+		let mut queue_key = due.to_be_bytes().to_vec();
+		queue_key.extend_from_slice(bucket_id.as_slice());
+		queue_key.extend_from_slice(version_uuid.as_slice());
+		self.tree.insert(queue_key, key.as_bytes())?;
+		self.notify.notify_one();
+		Ok(())
+	}
+
+	/// Number of restores waiting to be completed
+	pub fn len(&self) -> Result<usize, Error> {
+		Ok(self.tree.len()?)
+	}
+
+	pub fn is_empty(&self) -> Result<bool, Error> {
+		Ok(self.len()? == 0)
+	}
+}
+
+struct QueuedRestore {
+	due: u64,
+	bucket_id: Uuid,
+	version_uuid: Uuid,
+	key: String,
+}
+
+impl QueuedRestore {
+	fn decode(queue_key: &[u8], value: &[u8]) -> Option<Self> {
+		if queue_key.len() != 8 + 32 + 32 {
+			return None;
+		}
+		Some(Self {
+			due: u64::from_be_bytes(queue_key[..8].try_into().unwrap()),
+			bucket_id: Uuid::try_from(&queue_key[8..40])?,
+			version_uuid: Uuid::try_from(&queue_key[40..72])?,
+			key: String::from_utf8(value.to_vec()).ok()?,
+		})
+	}
+}
+
+pub struct RestoreWorker {
+	garage: Arc<Garage>,
+	restores_completed: usize,
+}
+
+impl RestoreWorker {
+	pub fn new(garage: Arc<Garage>) -> Self {
+		Self {
+			garage,
+			restores_completed: 0,
+		}
+	}
+
+	/// Make a version whose restore is pending readable
+	/// for the number of days asked in the RestoreObject request
+	async fn complete_restore(&mut self, restore: &QueuedRestore) -> Result<(), Error> {
+		let object = self
+			.garage
+			.object_table
+			.get(&restore.bucket_id, &restore.key)
+			.await?;
+		let version = object
+			.as_ref()
+			.and_then(|o| o.versions().iter().find(|v| v.uuid == restore.version_uuid));
+		let mut version = match version {
+			Some(v) => v.clone(),
+			// The version was deleted in the meantime
+			None => return Ok(()),
+		};
+
+		if let ObjectRestoreStatus::Pending { days } = *version.restore.get() {
+			version.restore.update(ObjectRestoreStatus::Available {
+				until: now_msec() + days * DAY_MSEC,
+			});
+			let object = Object::new(restore.bucket_id, restore.key.clone(), vec![version]);
+			self.garage.object_table.insert(&object).await?;
+			self.restores_completed += 1;
+		}
+		Ok(())
+	}
+}
+
+#[async_trait]
+impl Worker for RestoreWorker {
+	fn name(&self) -> String {
+		"Object restore".into()
+	}
+
+	fn status(&self) -> WorkerStatus {
+		WorkerStatus {
+			queue_length: self.garage.restore_queue.len().ok().map(|l| l as u64),
+			freeform: vec![format!("Restores completed: {}", self.restores_completed)],
+			..Default::default()
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		let garage = self.garage.clone();
+		let queue = &garage.restore_queue.tree;
+		let (queue_key, value) = match queue.first()? {
+			Some(entry) => entry,
+			None => return Ok(WorkerState::Idle),
+		};
+
+		match QueuedRestore::decode(&queue_key, &value) {
+			Some(restore) if restore.due > now_msec() => return Ok(WorkerState::Idle),
+			Some(restore) => self.complete_restore(&restore).await?,
+			None => warn!("Invalid entry in restore queue: {:?}", queue_key),
+		}
+		queue.remove(&queue_key)?;
+		Ok(WorkerState::Busy)
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		let restore_queue = &self.garage.restore_queue;
+		let next_due = match restore_queue.tree.first() {
+			Ok(Some((k, v))) => QueuedRestore::decode(&k, &v).map(|r| r.due),
+			_ => None,
+		};
+		let sleep = match next_due {
+			Some(due) => Duration::from_millis(due.saturating_sub(now_msec())),
+			None => MAX_IDLE_DURATION,
+		};
+		tokio::select! {
+			_ = tokio::time::sleep(sleep.min(MAX_IDLE_DURATION)) => (),
+			_ = restore_queue.notify.notified() => (),
+		}
+		WorkerState::Busy
+	}
+}
@@ -65,6 +65,7 @@ mod v08 {
 	use serde::{Deserialize, Serialize};
 
 	use super::v05;
+	use crate::s3::object_table::ObjectChecksum;
 
 	/// A version of an object
 	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
@@ -81,6 +82,9 @@ mod v08 {
 		pub blocks: crdt::Map<VersionBlockKey, VersionBlock>,
 		/// Etag of each part in case of a multipart upload, empty otherwise
 		pub parts_etags: crdt::Map<u64, String>,
+		/// Checksum of the parts of a multipart upload that were uploaded with one
+		#[serde(default)]
+		pub parts_checksums: crdt::Map<u64, ObjectChecksum>,
 
 		// Back link to bucket+key so that we can figure if
 		// this was deleted later on
@@ -103,6 +107,7 @@ mod v08 {
 				deleted: old.deleted,
 				blocks: old.blocks,
 				parts_etags: old.parts_etags,
+				parts_checksums: crdt::Map::new(),
 				bucket_id: blake2sum(old.bucket.as_bytes()),
 				key: old.key,
 			}
@@ -119,11 +124,17 @@ impl Version {
 			deleted: deleted.into(),
 			blocks: crdt::Map::new(),
 			parts_etags: crdt::Map::new(),
+			parts_checksums: crdt::Map::new(),
 			bucket_id,
 			key,
 		}
 	}
 
+	/// Total size of the blocks of this version
+	pub fn size(&self) -> u64 {
+		self.blocks.items().iter().map(|(_, b)| b.size).sum()
+	}
+
 	pub fn has_part_number(&self, part_number: u64) -> bool {
 		let case1 = self
 			.parts_etags
@@ -176,9 +187,11 @@ impl Crdt for Version {
 		if self.deleted.get() {
 			self.blocks.clear();
 			self.parts_etags.clear();
+			self.parts_checksums.clear();
 		} else {
 			self.blocks.merge(&other.blocks);
 			self.parts_etags.merge(&other.parts_etags);
+			self.parts_checksums.merge(&other.parts_checksums);
 		}
 	}
 }
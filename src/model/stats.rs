@@ -0,0 +1,174 @@
+//! Summary of the health of the whole cluster, shown by `garage stats`
+//! and returned by the GetClusterStatistics endpoint of the admin API
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use garage_util::data::*;
+use garage_util::error::Error;
+
+use garage_table::*;
+
+use crate::garage::Garage;
+use crate::s3::object_table::{BYTES, OBJECTS, UNFINISHED_UPLOADS};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClusterStats {
+	/// Replication mode of the cluster (`replication_mode` in the configuration file)
+	pub replication_mode: String,
+	/// Nodes that have a role in the current cluster layout
+	pub nodes: Vec<ClusterNodeStats>,
+	pub buckets: Vec<BucketStats>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClusterNodeStats {
+	pub id: String,
+	pub hostname: Option<String>,
+	pub is_up: bool,
+	pub zone: String,
+	/// Relative capacity of the node in the layout, `None` for gateway nodes
+	pub capacity: Option<u32>,
+	/// Number of partitions stored by the node
+	pub partitions: usize,
+	pub data_disk: Option<DiskStats>,
+	pub meta_disk: Option<DiskStats>,
+	/// Version of Garage running on the node, and the following fields,
+	/// are unknown if the node could not be reached
+	pub garage_version: Option<String>,
+	/// Number of blocks waiting to be resynchronized
+	pub block_resync_queue_len: Option<usize>,
+	/// Number of blocks whose last resynchronization failed
+	pub block_resync_errors: Option<usize>,
+	/// Number of table synchronizations with other nodes in progress
+	pub table_syncs_in_flight: Option<usize>,
+	/// Why the statistics of the node could not be gathered
+	pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiskStats {
+	pub available: u64,
+	pub total: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BucketStats {
+	pub id: String,
+	pub global_aliases: Vec<String>,
+	pub objects: i64,
+	pub bytes: i64,
+	pub unfinished_uploads: i64,
+}
+
+/// Gather statistics from all the nodes of the cluster layout, and the object
+/// counters of all buckets
+pub async fn gather_cluster_stats(garage: &Garage) -> Result<ClusterStats, Error> {
+	let layout = garage.system.get_cluster_layout();
+
+	let mut partitions = HashMap::<Uuid, usize>::new();
+	for short_id in layout.ring_assignation_data.iter() {
+		*partitions
+			.entry(layout.node_id_vec[*short_id as usize])
+			.or_default() += 1;
+	}
+	let known_nodes = garage
+		.system
+		.get_known_nodes()
+		.into_iter()
+		.map(|n| (n.id, n))
+		.collect::<HashMap<_, _>>();
+
+	let roles = layout
+		.roles
+		.items()
+		.iter()
+		.filter_map(|(id, _, role)| Some((*id, role.0.clone()?)))
+		.collect::<Vec<_>>();
+	let node_stats = futures::future::join_all(
+		roles
+			.iter()
+			.map(|(id, _)| garage.system.get_node_stats(*id)),
+	)
+	.await;
+
+	let mut nodes = vec![];
+	for ((id, role), stats) in roles.into_iter().zip(node_stats) {
+		let known = known_nodes.get(&id);
+		let disk = |avail: Option<(u64, u64)>| {
+			avail.map(|(available, total)| DiskStats { available, total })
+		};
+		let (stats, error) = match stats {
+			Ok(s) => (Some(s), None),
+			Err(e) => (None, Some(e.to_string())),
+		};
+		nodes.push(ClusterNodeStats {
+			id: hex::encode(id),
+			hostname: known.map(|n| n.status.hostname.clone()),
+			is_up: known.map(|n| n.is_up).unwrap_or(id == garage.system.id),
+			zone: role.zone,
+			capacity: role.capacity,
+			partitions: partitions.get(&id).cloned().unwrap_or(0),
+			data_disk: disk(known.and_then(|n| n.status.data_disk_avail)),
+			meta_disk: disk(known.and_then(|n| n.status.meta_disk_avail)),
+			garage_version: stats.as_ref().map(|s| s.garage_version.clone()),
+			block_resync_queue_len: stats.as_ref().and_then(|s| s.block_resync_queue_len),
+			block_resync_errors: stats.as_ref().and_then(|s| s.block_resync_errors),
+			table_syncs_in_flight: stats.as_ref().map(|s| s.table_syncs_in_flight),
+			error,
+		});
+	}
+
+	let bucket_list = garage
+		.bucket_table
+		.get_range(
+			&EmptyKey,
+			None,
+			Some(DeletedFilter::NotDeleted),
+			10000,
+			EnumerationOrder::Forward,
+		)
+		.await?;
+	let counters = futures::future::join_all(
+		bucket_list
+			.iter()
+			.map(|b| garage.object_counter_table.table.get(&b.id, &EmptyKey)),
+	)
+	.await;
+
+	let ring = garage.system.ring.borrow().clone();
+	let mut buckets = vec![];
+	for (bucket, counters) in bucket_list.into_iter().zip(counters) {
+		let counters = counters?
+			.map(|x| x.filtered_values(&ring))
+			.unwrap_or_default();
+		let counter = |name| counters.get(name).cloned().unwrap_or_default();
+		buckets.push(BucketStats {
+			id: hex::encode(bucket.id),
+			global_aliases: bucket
+				.state
+				.as_option()
+				.unwrap()
+				.aliases
+				.items()
+				.iter()
+				.filter(|(_, _, a)| *a)
+				.map(|(n, _, _)| n.to_string())
+				.collect(),
+			objects: counter(OBJECTS),
+			bytes: counter(BYTES),
+			unfinished_uploads: counter(UNFINISHED_UPLOADS),
+		});
+	}
+
+	Ok(ClusterStats {
+		replication_mode: garage.config.replication_mode.clone(),
+		nodes,
+		buckets,
+	})
+}
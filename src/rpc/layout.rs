@@ -7,6 +7,7 @@ use garage_util::crdt::{AutoCrdt, Crdt, LwwMap};
 use garage_util::data::*;
 use garage_util::encode::nonversioned_encode;
 use garage_util::error::*;
+use garage_util::time::now_msec;
 
 use crate::ring::*;
 
@@ -38,6 +39,54 @@ pub struct ClusterLayout {
 
 impl garage_util::migrate::InitialFormat for ClusterLayout {}
 
+/// The last versions of the cluster layout received by a node, which can be
+/// applied again with `garage layout rollback`
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct LayoutHistory {
+	/// Versions of the layout, from the oldest to the most recent one
+	pub entries: Vec<LayoutHistoryEntry>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LayoutHistoryEntry {
+	/// Time at which the node received this version of the layout (msec since Unix epoch)
+	pub received_at: u64,
+	pub layout: ClusterLayout,
+}
+
+impl garage_util::migrate::InitialFormat for LayoutHistory {}
+
+impl LayoutHistory {
+	/// Add a new version of the layout to the history, and forget the oldest
+	/// versions to keep at most `max_len` of them. Returns false if the layout
+	/// is not more recent than the last version recorded.
+	pub fn push(&mut self, layout: &ClusterLayout, max_len: usize) -> bool {
+		if layout.version == 0
+			|| self
+				.entries
+				.last()
+				.map(|e| e.layout.version >= layout.version)
+				.unwrap_or(false)
+		{
+			return false;
+		}
+		self.entries.push(LayoutHistoryEntry {
+			received_at: now_msec(),
+			layout: layout.clone(),
+		});
+		let excess = self.entries.len().saturating_sub(std::cmp::max(max_len, 1));
+		self.entries.drain(..excess);
+		true
+	}
+
+	pub fn get(&self, version: u64) -> Option<&ClusterLayout> {
+		self.entries
+			.iter()
+			.map(|e| &e.layout)
+			.find(|l| l.version == version)
+	}
+}
+
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
 pub struct NodeRoleV(pub Option<NodeRole>);
 
@@ -48,8 +97,8 @@ impl AutoCrdt for NodeRoleV {
 /// The user-assigned roles of cluster nodes
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
 pub struct NodeRole {
-	/// Datacenter at which this entry belong. This information might be used to perform a better
-	/// geodistribution
+	/// Datacenter at which this entry belong. The copies of each partition are placed
+	/// in as many distinct zones as possible
 	pub zone: String,
 	/// The (relative) capacity of the node
 	/// If this is set to None, the node does not participate in storing data for the system
@@ -159,6 +208,59 @@ To know the correct value of the new layout version, invoke `garage layout show`
 		Ok(self)
 	}
 
+	/// Make a new version of the layout in which nodes have the roles they had in
+	/// `previous`, an older version of the layout, and partitions are assigned to
+	/// nodes as they were in that version. Staged role changes are dropped.
+	pub fn rollback_to(mut self, previous: &ClusterLayout) -> Result<Self, Error> {
+		if previous.version >= self.version {
+			return Err(Error::Message(format!(
+				"Layout version {} is not older than the current layout version {}",
+				previous.version, self.version
+			)));
+		}
+		if previous.replication_factor != self.replication_factor || !previous.check() {
+			return Err(Error::Message(format!(
+				"Layout version {} is not a valid layout for this cluster",
+				previous.version
+			)));
+		}
+
+		// Roles are written with new timestamps, so that they replace the current ones
+		let current_nodes = self
+			.roles
+			.items()
+			.iter()
+			.map(|(id, _, _)| *id)
+			.collect::<Vec<_>>();
+		for id in current_nodes {
+			if previous.roles.get(&id).is_none() {
+				self.roles.update_in_place(id, NodeRoleV(None));
+			}
+		}
+		for (id, _, role) in previous.roles.items().iter() {
+			if self.roles.get(id) != Some(role) {
+				self.roles.update_in_place(*id, role.clone());
+			}
+		}
+		self.roles.retain(|(_, _, v)| v.0.is_some());
+
+		self.node_id_vec = previous.node_id_vec.clone();
+		self.ring_assignation_data = previous.ring_assignation_data.clone();
+
+		self.staging.clear();
+		self.staging_hash = blake2sum(&nonversioned_encode(&self.staging).unwrap()[..]);
+
+		self.version += 1;
+
+		if !self.check() {
+			return Err(Error::Message(format!(
+				"Could not make a valid layout from layout version {}",
+				previous.version
+			)));
+		}
+		Ok(self)
+	}
+
 	/// Returns a list of IDs of nodes that currently have
 	/// a role in the cluster
 	pub fn node_ids(&self) -> &[Uuid] {
@@ -667,3 +769,130 @@ impl<'a> PartitionAss<'a> {
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn layout_with_zones(replication_factor: usize, zones: &[(&str, usize)]) -> ClusterLayout {
+		let mut layout = ClusterLayout::new(replication_factor);
+		for (i, (zone, n_nodes)) in zones.iter().enumerate() {
+			for j in 0..*n_nodes {
+				let mut id = [0u8; 32];
+				id[0] = i as u8;
+				id[1] = j as u8;
+				let role = NodeRole {
+					zone: zone.to_string(),
+					capacity: Some(100),
+					tags: vec![],
+				};
+				layout.staging.merge(
+					&layout
+						.staging
+						.update_mutator(Uuid::from(id), NodeRoleV(Some(role))),
+				);
+			}
+		}
+		let version = layout.version + 1;
+		layout.apply_staged_changes(Some(version)).unwrap()
+	}
+
+	fn partition_zones(layout: &ClusterLayout) -> Vec<HashSet<String>> {
+		let ring = Ring::new(layout.clone(), layout.replication_factor);
+		ring.partitions()
+			.iter()
+			.map(|(_, hash)| {
+				ring.get_nodes(hash, layout.replication_factor)
+					.iter()
+					.map(|n| layout.node_role(n).unwrap().zone.clone())
+					.collect()
+			})
+			.collect()
+	}
+
+	#[test]
+	fn test_replicas_in_distinct_zones() {
+		let layout = layout_with_zones(3, &[("dc1", 2), ("dc2", 2), ("dc3", 2)]);
+		let zones = partition_zones(&layout);
+		assert_eq!(zones.len(), 1 << PARTITION_BITS);
+		assert!(zones.iter().all(|z| z.len() == 3));
+
+		// Adding a node to one of the zones keeps all copies in distinct zones
+		let mut layout = layout;
+		let role = NodeRole {
+			zone: "dc1".into(),
+			capacity: Some(200),
+			tags: vec![],
+		};
+		layout.staging.merge(
+			&layout
+				.staging
+				.update_mutator(Uuid::from([0xFFu8; 32]), NodeRoleV(Some(role))),
+		);
+		let version = layout.version + 1;
+		let layout = layout.apply_staged_changes(Some(version)).unwrap();
+		assert!(partition_zones(&layout).iter().all(|z| z.len() == 3));
+	}
+
+	#[test]
+	fn test_rollback() {
+		let v1 = layout_with_zones(3, &[("dc1", 1), ("dc2", 1), ("dc3", 1)]);
+		let mut history = LayoutHistory::default();
+		assert!(history.push(&v1, 2));
+
+		// Remove a node and add two others
+		let mut layout = v1.clone();
+		let role = |zone: &str| {
+			NodeRoleV(Some(NodeRole {
+				zone: zone.into(),
+				capacity: Some(100),
+				tags: vec![],
+			}))
+		};
+		for (id, v) in [
+			([0xF0u8; 32], role("dc1")),
+			([0xF1u8; 32], role("dc3")),
+			([0u8; 32], NodeRoleV(None)),
+		] {
+			layout
+				.staging
+				.merge(&layout.staging.update_mutator(Uuid::from(id), v));
+		}
+		let v2 = layout.apply_staged_changes(Some(2)).unwrap();
+		assert!(history.push(&v2, 2));
+		assert!(!history.push(&v1, 2));
+
+		let v3 = v2.clone().rollback_to(history.get(1).unwrap()).unwrap();
+		assert_eq!(v3.version, 3);
+		assert!(v3.check());
+		assert_eq!(v3.ring_assignation_data, v1.ring_assignation_data);
+		let roles = |l: &ClusterLayout| {
+			let mut r = l
+				.roles
+				.items()
+				.iter()
+				.map(|(id, _, r)| (*id, r.clone()))
+				.collect::<Vec<_>>();
+			r.sort();
+			r
+		};
+		assert_eq!(roles(&v3), roles(&v1));
+
+		// The rollback is propagated like any new version of the layout
+		let mut other = v2.clone();
+		assert!(other.merge(&v3));
+		assert_eq!(roles(&other), roles(&v1));
+
+		// Only the last versions are kept, and a layout cannot be rolled back to itself
+		assert!(history.push(&v3, 2));
+		assert!(history.get(1).is_none());
+		assert!(v3.clone().rollback_to(&v3).is_err());
+	}
+
+	#[test]
+	fn test_replicas_in_fewer_zones() {
+		// With less zones than copies, all zones are still used
+		let layout = layout_with_zones(3, &[("dc1", 2), ("dc2", 2)]);
+		assert!(partition_zones(&layout).iter().all(|z| z.len() == 2));
+	}
+}
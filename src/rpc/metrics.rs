@@ -1,4 +1,11 @@
-use opentelemetry::{global, metrics::*};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use opentelemetry::{global, metrics::*, KeyValue};
+
+use garage_util::data::*;
+
+use crate::rpc_helper::PeerState;
 
 /// TableMetrics reference all counter used for metrics
 pub struct RpcMetrics {
@@ -6,11 +13,13 @@ pub struct RpcMetrics {
 	pub(crate) rpc_timeout_counter: Counter<u64>,
 	pub(crate) rpc_netapp_error_counter: Counter<u64>,
 	pub(crate) rpc_garage_error_counter: Counter<u64>,
+	pub(crate) rpc_overloaded_counter: Counter<u64>,
+	pub(crate) _rpc_in_flight: ValueObserver<u64>,
 
 	pub(crate) rpc_duration: ValueRecorder<f64>,
 }
 impl RpcMetrics {
-	pub fn new() -> Self {
+	pub fn new(peers: Arc<Mutex<HashMap<Uuid, PeerState>>>) -> Self {
 		let meter = global::meter("garage_rpc");
 		RpcMetrics {
 			rpc_counter: meter
@@ -29,6 +38,21 @@ impl RpcMetrics {
 				.u64_counter("rpc.garage_error_counter")
 				.with_description("Number of RPC errors (errors happening when handling the RPC)")
 				.init(),
+			rpc_overloaded_counter: meter
+				.u64_counter("rpc.overloaded_counter")
+				.with_description("Number of RPC requests that failed because too many requests were in flight to their destination")
+				.init(),
+			_rpc_in_flight: meter
+				.u64_value_observer("rpc.in_flight", move |observer| {
+					for (id, peer) in peers.lock().unwrap().iter() {
+						observer.observe(
+							peer.in_flight() as u64,
+							&[KeyValue::new("to", format!("{:?}", id))],
+						);
+					}
+				})
+				.with_description("Number of RPC requests in flight to each node")
+				.init(),
 			rpc_duration: meter
 				.f64_value_recorder("rpc.duration")
 				.with_description("Duration of RPCs")
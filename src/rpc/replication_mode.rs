@@ -1,3 +1,5 @@
+use crate::ring::MAX_REPLICATION;
+
 #[derive(Clone, Copy)]
 pub enum ReplicationMode {
 	None,
@@ -6,6 +8,13 @@ pub enum ReplicationMode {
 	ThreeWay,
 	ThreeWayDegraded,
 	ThreeWayDangerous,
+	/// Blocks are split in `data_shards` parts, to which `parity_shards` parts
+	/// are added with an erasure code, and each part is stored on a different node.
+	/// Metadata is replicated on all of these nodes.
+	ErasureCoding {
+		data_shards: usize,
+		parity_shards: usize,
+	},
 }
 
 impl ReplicationMode {
@@ -17,7 +26,21 @@ impl ReplicationMode {
 			"3" => Some(Self::ThreeWay),
 			"3-degraded" => Some(Self::ThreeWayDegraded),
 			"3-dangerous" => Some(Self::ThreeWayDangerous),
-			_ => None,
+			v => {
+				let (data_shards, parity_shards) = v.strip_prefix("ec:")?.split_once('+')?;
+				let data_shards = data_shards.parse::<usize>().ok()?;
+				let parity_shards = parity_shards.parse::<usize>().ok()?;
+				if data_shards == 0
+					|| parity_shards == 0
+					|| data_shards + parity_shards > MAX_REPLICATION
+				{
+					return None;
+				}
+				Some(Self::ErasureCoding {
+					data_shards,
+					parity_shards,
+				})
+			}
 		}
 	}
 
@@ -33,6 +56,10 @@ impl ReplicationMode {
 			Self::None => 1,
 			Self::TwoWay | Self::TwoWayDangerous => 2,
 			Self::ThreeWay | Self::ThreeWayDegraded | Self::ThreeWayDangerous => 3,
+			Self::ErasureCoding {
+				data_shards,
+				parity_shards,
+			} => data_shards + parity_shards,
 		}
 	}
 
@@ -42,6 +69,7 @@ impl ReplicationMode {
 			Self::TwoWay | Self::TwoWayDangerous => 1,
 			Self::ThreeWay => 2,
 			Self::ThreeWayDegraded | Self::ThreeWayDangerous => 1,
+			Self::ErasureCoding { .. } => self.replication_factor() / 2 + 1,
 		}
 	}
 
@@ -52,6 +80,90 @@ impl ReplicationMode {
 			Self::TwoWayDangerous => 1,
 			Self::ThreeWay | Self::ThreeWayDegraded => 2,
 			Self::ThreeWayDangerous => 1,
+			Self::ErasureCoding { .. } => self.replication_factor() / 2 + 1,
+		}
+	}
+
+	/// Number of data and parity shards of each block, if blocks are erasure-coded
+	pub fn erasure_coding(&self) -> Option<(usize, usize)> {
+		match self {
+			Self::ErasureCoding {
+				data_shards,
+				parity_shards,
+			} => Some((*data_shards, *parity_shards)),
+			_ => None,
+		}
+	}
+
+	/// Number of nodes that must store a block (or one of its shards)
+	/// for the write of this block to succeed: with erasure coding,
+	/// one more shard than what is needed to rebuild the block
+	pub fn data_write_quorum(&self) -> usize {
+		match self {
+			Self::ErasureCoding { data_shards, .. } => data_shards + 1,
+			_ => self.write_quorum(),
+		}
+	}
+
+	/// Read quorum of strongly consistent reads: a majority of the nodes,
+	/// and enough of them to always include one of the nodes that acknowledged
+	/// the last successful write
+	pub fn strong_read_quorum(&self) -> usize {
+		let majority = self.replication_factor() / 2 + 1;
+		let overlap = self.replication_factor() - self.write_quorum() + 1;
+		std::cmp::max(majority, overlap)
+	}
+
+	/// Parse the read quorum of the tables that store the metadata of objects,
+	/// which is either a number of nodes or `majority`
+	pub fn parse_read_quorum(&self, v: &str) -> Option<usize> {
+		match v {
+			"majority" => Some(self.replication_factor() / 2 + 1),
+			n => n
+				.parse::<usize>()
+				.ok()
+				.filter(|n| (1..=self.replication_factor()).contains(n)),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_read_quorum() {
+		let three_way = ReplicationMode::ThreeWay;
+		assert_eq!(three_way.parse_read_quorum("majority"), Some(2));
+		assert_eq!(three_way.parse_read_quorum("1"), Some(1));
+		assert_eq!(three_way.parse_read_quorum("3"), Some(3));
+		assert_eq!(three_way.parse_read_quorum("4"), None);
+		assert_eq!(three_way.parse_read_quorum("0"), None);
+		assert_eq!(three_way.parse_read_quorum("all"), None);
+
+		assert_eq!(ReplicationMode::None.strong_read_quorum(), 1);
+		assert_eq!(ReplicationMode::TwoWay.strong_read_quorum(), 2);
+		assert_eq!(ReplicationMode::TwoWayDangerous.strong_read_quorum(), 2);
+		assert_eq!(ReplicationMode::ThreeWay.strong_read_quorum(), 2);
+		assert_eq!(ReplicationMode::ThreeWayDegraded.strong_read_quorum(), 2);
+		assert_eq!(ReplicationMode::ThreeWayDangerous.strong_read_quorum(), 3);
+	}
+
+	#[test]
+	fn test_erasure_coding() {
+		let ec = ReplicationMode::parse("ec:4+2").unwrap();
+		assert_eq!(ec.erasure_coding(), Some((4, 2)));
+		assert_eq!(ec.replication_factor(), 6);
+		assert_eq!(ec.read_quorum(), 4);
+		assert_eq!(ec.write_quorum(), 4);
+		assert_eq!(ec.data_write_quorum(), 5);
+		assert_eq!(ec.strong_read_quorum(), 4);
+
+		assert_eq!(ReplicationMode::ThreeWay.erasure_coding(), None);
+		assert_eq!(ReplicationMode::ThreeWay.data_write_quorum(), 2);
+
+		for invalid in ["ec:4", "ec:0+2", "ec:4+0", "ec:5+2", "ec:a+b", "4+2"] {
+			assert!(ReplicationMode::parse(invalid).is_none());
 		}
 	}
 }
@@ -42,10 +42,11 @@ pub struct Ring {
 pub type CompactNodeType = u8;
 
 // The maximum number of times an object might get replicated
-// This must be at least 3 because Garage supports 3-way replication
+// This must be at least 3 because Garage supports 3-way replication,
+// and it limits the number of shards of erasure-coded blocks
 // Here we use 6 so that the size of a ring entry is 8 bytes
 // (2 bytes partition id, 6 bytes node numbers as u8s)
-const MAX_REPLICATION: usize = 6;
+pub(crate) const MAX_REPLICATION: usize = 6;
 
 /// An entry in the ring
 #[derive(Clone, Debug)]
@@ -1,12 +1,13 @@
 //! Contain structs related to making RPCs
-use std::sync::Arc;
-use std::time::Duration;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use futures::future::join_all;
 use futures::stream::futures_unordered::FuturesUnordered;
 use futures::stream::StreamExt;
 use tokio::select;
-use tokio::sync::watch;
+use tokio::sync::{watch, OwnedSemaphorePermit, Semaphore};
 
 use opentelemetry::KeyValue;
 use opentelemetry::{
@@ -20,9 +21,11 @@ pub use netapp::message::{
 	PRIO_NORMAL, PRIO_SECONDARY,
 };
 use netapp::peering::fullmesh::FullMeshPeeringStrategy;
+use netapp::stream::ByteStream;
 pub use netapp::{self, NetApp, NodeID};
 
 use garage_util::data::*;
+use garage_util::encode::{nonversioned_decode, nonversioned_encode};
 use garage_util::error::Error;
 use garage_util::metrics::RecordDuration;
 
@@ -31,6 +34,8 @@ use crate::ring::Ring;
 
 // Default RPC timeout = 5 minutes
 const DEFAULT_TIMEOUT: Duration = Duration::from_secs(300);
+// Default time to wait for a call slot to a node that has too many calls in flight
+const DEFAULT_IN_FLIGHT_WAIT: Duration = Duration::from_secs(10);
 
 /// Strategy to apply when making RPC
 #[derive(Copy, Clone)]
@@ -94,6 +99,45 @@ struct RpcHelperInner {
 	ring: watch::Receiver<Arc<Ring>>,
 	metrics: RpcMetrics,
 	rpc_timeout: Duration,
+	peers: Arc<Mutex<HashMap<Uuid, PeerState>>>,
+	max_in_flight_per_peer: usize,
+	in_flight_wait: Duration,
+}
+
+/// Calls in flight to another node, limited separately for each priority so that
+/// a burst of calls of low priority does not delay calls of higher priority.
+/// A call holds one of the permits of the semaphore of its priority until it
+/// returns, or until the stream of data of its response is dropped: as Netapp
+/// fails all pending calls to a node when the connection to it is closed, and as
+/// calls are bounded by the RPC timeout, permits are given back when the node
+/// disconnects or reconnects.
+pub(crate) struct PeerState {
+	in_flight: HashMap<RequestPriority, Arc<Semaphore>>,
+	max_in_flight: usize,
+}
+
+impl PeerState {
+	fn new(max_in_flight: usize) -> Self {
+		Self {
+			in_flight: HashMap::new(),
+			max_in_flight,
+		}
+	}
+
+	fn semaphore(&mut self, prio: RequestPriority) -> Arc<Semaphore> {
+		let max_in_flight = self.max_in_flight;
+		self.in_flight
+			.entry(prio)
+			.or_insert_with(|| Arc::new(Semaphore::new(max_in_flight)))
+			.clone()
+	}
+
+	pub(crate) fn in_flight(&self) -> usize {
+		self.in_flight
+			.values()
+			.map(|sem| self.max_in_flight - sem.available_permits())
+			.sum()
+	}
 }
 
 impl RpcHelper {
@@ -102,8 +146,11 @@ impl RpcHelper {
 		fullmesh: Arc<FullMeshPeeringStrategy>,
 		ring: watch::Receiver<Arc<Ring>>,
 		rpc_timeout: Option<Duration>,
+		max_in_flight_per_peer: usize,
+		in_flight_wait: Option<Duration>,
 	) -> Self {
-		let metrics = RpcMetrics::new();
+		let peers = Arc::new(Mutex::new(HashMap::new()));
+		let metrics = RpcMetrics::new(peers.clone());
 
 		Self(Arc::new(RpcHelperInner {
 			our_node_id,
@@ -111,6 +158,9 @@ impl RpcHelper {
 			ring,
 			metrics,
 			rpc_timeout: rpc_timeout.unwrap_or(DEFAULT_TIMEOUT),
+			peers,
+			max_in_flight_per_peer: std::cmp::max(max_in_flight_per_peer, 1),
+			in_flight_wait: in_flight_wait.unwrap_or(DEFAULT_IN_FLIGHT_WAIT),
 		}))
 	}
 
@@ -118,6 +168,51 @@ impl RpcHelper {
 		self.0.rpc_timeout
 	}
 
+	/// Number of RPC calls currently in flight to a node
+	pub fn in_flight(&self, to: Uuid) -> usize {
+		self.0
+			.peers
+			.lock()
+			.unwrap()
+			.get(&to)
+			.map(PeerState::in_flight)
+			.unwrap_or(0)
+	}
+
+	/// Wait until less than `rpc_max_in_flight_per_peer` calls of the same
+	/// priority are in flight to a node, and reserve a slot for a new call.
+	/// Calls to the local node are not limited as they are not queued in Netapp.
+	async fn reserve_call_slot(
+		&self,
+		to: Uuid,
+		prio: RequestPriority,
+	) -> Result<Option<OwnedSemaphorePermit>, Error> {
+		if to == self.0.our_node_id {
+			return Ok(None);
+		}
+		let in_flight = self
+			.0
+			.peers
+			.lock()
+			.unwrap()
+			.entry(to)
+			.or_insert_with(|| PeerState::new(self.0.max_in_flight_per_peer))
+			.semaphore(prio);
+		match tokio::time::timeout(self.0.in_flight_wait, in_flight.acquire_owned()).await {
+			Ok(permit) => Ok(Some(permit?)),
+			Err(_) => {
+				self.0.metrics.rpc_overloaded_counter.add(
+					1,
+					&[
+						KeyValue::new("from", format!("{:?}", self.0.our_node_id)),
+						KeyValue::new("to", format!("{:?}", to)),
+					],
+				);
+				Err(Error::Overloaded(format!("{:?}", to)))
+			}
+		}
+	}
+
 	pub async fn call<M, N, H, S>(
 		&self,
 		endpoint: &Endpoint<M, H>,
@@ -136,6 +231,7 @@ impl RpcHelper {
 			KeyValue::new("to", format!("{:?}", to)),
 		];
 
+		let _slot = self.reserve_call_slot(to, strat.rs_priority).await?;
 		self.0.metrics.rpc_counter.add(1, &metric_tags);
 
 		let node_id = to.into();
@@ -171,6 +267,36 @@ impl RpcHelper {
 		}
 	}
 
+	/// Make a call whose response comes with a stream of data, such as a block,
+	/// and return the response and its stream. The call has no timeout. Its slot
+	/// in the calls in flight to the node is held until the stream is dropped,
+	/// so that data transfers are limited by `rpc_max_in_flight_per_peer` too.
+	pub async fn call_streaming<M, N, H>(
+		&self,
+		endpoint: &Endpoint<M, H>,
+		to: Uuid,
+		msg: N,
+		prio: RequestPriority,
+	) -> Result<(M::Response, Option<ByteStream>), Error>
+	where
+		M: Rpc,
+		N: IntoReq<M> + Send,
+		H: StreamingEndpointHandler<M>,
+	{
+		let slot = self.reserve_call_slot(to, prio).await?;
+		let (resp, stream) = endpoint
+			.call_streaming(&to.into(), msg, prio)
+			.await?
+			.into_parts();
+		let stream = stream.map(|stream| -> ByteStream {
+			Box::pin(stream.map(move |packet| {
+				let _ = &slot;
+				packet
+			}))
+		});
+		Ok((resp, stream))
+	}
+
 	pub async fn call_many<M, N, H, S>(
 		&self,
 		endpoint: &Endpoint<M, H>,
@@ -383,6 +509,17 @@ impl RpcHelper {
 
 		if successes.len() >= quorum {
 			Ok(successes)
+		} else if errors.iter().any(|e| matches!(e, Error::Overloaded(_))) {
+			// Signal backpressure to the caller rather than a generic quorum error,
+			// so that clients know they should slow down
+			let nodes = errors
+				.iter()
+				.filter_map(|e| match e {
+					Error::Overloaded(n) => Some(n.as_str()),
+					_ => None,
+				})
+				.collect::<Vec<_>>();
+			Err(Error::Overloaded(nodes.join(", ")))
 		} else {
 			let errors = errors.iter().map(|e| format!("{}", e)).collect::<Vec<_>>();
 			Err(Error::Quorum(quorum, successes.len(), to.len(), errors))
@@ -434,3 +571,113 @@ impl RpcHelper {
 			.collect::<Vec<_>>()
 	}
 }
+
+// ------- Traced RPC calls -------
+
+/// Time spent in the different steps of an RPC call made with `TracedRpc`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RpcTiming {
+	/// Time taken to serialize the request
+	pub serialization: Duration,
+	/// Time between sending the request and getting the deserialized response
+	pub round_trip: Duration,
+	/// Time taken to deserialize the response
+	pub deserialization: Duration,
+}
+
+impl RpcTiming {
+	/// Time spent on the network and on the remote node
+	pub fn network(&self) -> Duration {
+		self.round_trip.saturating_sub(self.deserialization)
+	}
+}
+
+/// Sends RPC calls to a single node and records how long each step takes.
+/// As Netapp deserializes responses as soon as they are received, the
+/// deserialization time is measured by decoding the response a second time.
+/// Netapp does not serialize calls to the local node, so their round trip
+/// time is only the time spent in the handler.
+pub struct TracedRpc<'a, M: Rpc, H: StreamingEndpointHandler<M>> {
+	helper: &'a RpcHelper,
+	endpoint: &'a Endpoint<M, H>,
+}
+
+impl<'a, M, H> TracedRpc<'a, M, H>
+where
+	M: Rpc,
+	H: StreamingEndpointHandler<M>,
+{
+	pub fn new(helper: &'a RpcHelper, endpoint: &'a Endpoint<M, H>) -> Self {
+		Self { helper, endpoint }
+	}
+
+	pub async fn call(
+		&self,
+		to: Uuid,
+		msg: M,
+		prio: RequestPriority,
+	) -> Result<(M::Response, RpcTiming), Error> {
+		let start = Instant::now();
+		let req = Req::new(msg)?;
+		let serialization = start.elapsed();
+
+		let start = Instant::now();
+		let resp = tokio::time::timeout(
+			self.helper.rpc_timeout(),
+			self.endpoint.call_streaming(&to.into(), req, prio),
+		)
+		.await
+		.map_err(|_| Error::Timeout)??
+		.into_msg();
+		let round_trip = start.elapsed();
+
+		let resp_bytes = nonversioned_encode(&resp)?;
+		let start = Instant::now();
+		let resp = nonversioned_decode::<M::Response>(&resp_bytes)?;
+		let deserialization = start.elapsed();
+
+		Ok((
+			resp,
+			RpcTiming {
+				serialization,
+				round_trip,
+				deserialization,
+			},
+		))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[tokio::test]
+	async fn test_peer_state_in_flight() {
+		let mut peer = PeerState::new(2);
+		assert_eq!(peer.in_flight(), 0);
+
+		let a = peer.semaphore(PRIO_NORMAL).acquire_owned().await.unwrap();
+		let b = peer.semaphore(PRIO_NORMAL).acquire_owned().await.unwrap();
+		assert_eq!(peer.in_flight(), 2);
+
+		// A third call has to wait for one of the first two to finish
+		let wait = tokio::time::timeout(
+			Duration::from_millis(50),
+			peer.semaphore(PRIO_NORMAL).acquire_owned(),
+		)
+		.await;
+		assert!(wait.is_err());
+
+		// Calls of another priority are not delayed
+		let high = peer.semaphore(PRIO_HIGH).acquire_owned().await.unwrap();
+		assert_eq!(peer.in_flight(), 3);
+		drop(high);
+
+		drop(a);
+		assert_eq!(peer.in_flight(), 1);
+		let _c = peer.semaphore(PRIO_NORMAL).acquire_owned().await.unwrap();
+		assert_eq!(peer.in_flight(), 2);
+		drop(b);
+		assert_eq!(peer.in_flight(), 1);
+	}
+}
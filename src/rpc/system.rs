@@ -4,7 +4,7 @@ use std::io::{Read, Write};
 use std::net::{IpAddr, SocketAddr};
 use std::path::{Path, PathBuf};
 use std::sync::atomic::Ordering;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, RwLock, Weak};
 use std::time::{Duration, Instant};
 
 use arc_swap::ArcSwap;
@@ -22,9 +22,9 @@ use netapp::peering::fullmesh::FullMeshPeeringStrategy;
 use netapp::util::parse_and_resolve_peer_addr_async;
 use netapp::{NetApp, NetworkKey, NodeID, NodeKey};
 
-use garage_util::config::Config;
 #[cfg(feature = "kubernetes-discovery")]
 use garage_util::config::KubernetesDiscoveryConfig;
+use garage_util::config::{Config, ConfigNodeRole, DataDirEnum};
 use garage_util::data::*;
 use garage_util::error::*;
 use garage_util::persister::Persister;
@@ -70,6 +70,20 @@ pub enum SystemRpc {
 	GetKnownNodes,
 	/// Return known nodes
 	ReturnKnownNodes(Vec<KnownNodeInfo>),
+	/// Get statistics of the node. Answered with ReturnNodeStats
+	GetNodeStats,
+	/// Return statistics of the node
+	ReturnNodeStats(NodeStats),
+	/// Get the previous versions of the cluster layout kept by the node.
+	/// Answered with ReturnClusterLayoutHistory
+	GetClusterLayoutHistory,
+	/// Return the previous versions of the cluster layout
+	ReturnClusterLayoutHistory(LayoutHistory),
+	/// Check whether data is still being moved after the last change of the
+	/// cluster layout. Answered with ReturnLayoutMigrationStatus
+	GetLayoutMigrationStatus,
+	/// Return the reasons to think that data is still being moved, if any
+	ReturnLayoutMigrationStatus(Vec<String>),
 }
 
 impl Rpc for SystemRpc {
@@ -86,8 +100,12 @@ pub struct System {
 	pub id: Uuid,
 
 	persist_cluster_layout: Persister<ClusterLayout>,
+	persist_layout_history: Persister<LayoutHistory>,
 	persist_peer_list: Persister<PeerList>,
 
+	layout_history: RwLock<LayoutHistory>,
+	layout_history_length: usize,
+
 	local_status: ArcSwap<NodeStatus>,
 	node_status: RwLock<HashMap<Uuid, (u64, NodeStatus)>>,
 
@@ -96,6 +114,7 @@ pub struct System {
 	pub rpc: RpcHelper,
 
 	system_endpoint: Arc<Endpoint<SystemRpc, System>>,
+	node_stats_sources: RwLock<Vec<Weak<dyn NodeStatsSource>>>,
 
 	rpc_listen_addr: SocketAddr,
 	#[cfg(any(feature = "consul-discovery", feature = "kubernetes-discovery"))]
@@ -119,7 +138,7 @@ pub struct System {
 	/// Path to metadata directory
 	pub metadata_dir: PathBuf,
 	/// Path to data directory
-	pub data_dir: PathBuf,
+	pub data_dir: DataDirEnum,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -140,6 +159,29 @@ pub struct NodeStatus {
 	/// Disk usage on partition containing data directory (tuple: `(avail, total)`)
 	#[serde(default)]
 	pub data_disk_avail: Option<(u64, u64)>,
+
+	/// Role the node is configured for (`node_role` in its configuration file)
+	#[serde(default)]
+	pub node_role: ConfigNodeRole,
+}
+
+/// Statistics of a node that are not part of the status it advertises,
+/// returned by `SystemRpc::GetNodeStats`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NodeStats {
+	/// Version of Garage running on the node
+	pub garage_version: String,
+	/// Number of blocks waiting to be resynchronized
+	pub block_resync_queue_len: Option<usize>,
+	/// Number of blocks whose last resynchronization failed
+	pub block_resync_errors: Option<usize>,
+	/// Number of table synchronizations with other nodes in progress
+	pub table_syncs_in_flight: usize,
+}
+
+/// Component of a node that contributes to the statistics returned by `SystemRpc::GetNodeStats`
+pub trait NodeStatsSource: Send + Sync {
+	fn fill_node_stats(&self, stats: &mut NodeStats);
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -281,11 +323,27 @@ impl System {
 			}
 		};
 
+		let persist_layout_history: Persister<LayoutHistory> =
+			Persister::new(&config.metadata_dir, "cluster_layout_history");
+		let mut layout_history = persist_layout_history.load().unwrap_or_default();
+		if layout_history.push(&cluster_layout, config.layout_history_length) {
+			if let Err(e) = persist_layout_history.save(&layout_history) {
+				warn!("Could not save cluster layout history: {}", e);
+			}
+		}
+
 		let metrics = SystemMetrics::new(replication_factor);
 
-		let mut local_status = NodeStatus::initial(replication_factor, &cluster_layout);
+		let mut local_status =
+			NodeStatus::initial(replication_factor, config.node_role, &cluster_layout);
 		local_status.update_disk_usage(&config.metadata_dir, &config.data_dir, &metrics);
 
+		if let Some(role) = cluster_layout.node_role(&node_key.public_key().into()) {
+			if let Err(e) = local_status.check_layout_role(role) {
+				warn!("{}", e);
+			}
+		}
+
 		let ring = Ring::new(cluster_layout, replication_factor);
 		let (update_ring, ring) = watch::channel(Arc::new(ring));
 
@@ -354,7 +412,10 @@ impl System {
 		let sys = Arc::new(System {
 			id: netapp.id.into(),
 			persist_cluster_layout,
+			persist_layout_history,
 			persist_peer_list,
+			layout_history: RwLock::new(layout_history),
+			layout_history_length: config.layout_history_length,
 			local_status: ArcSwap::new(Arc::new(local_status)),
 			node_status: RwLock::new(HashMap::new()),
 			netapp: netapp.clone(),
@@ -364,8 +425,11 @@ impl System {
 				fullmesh,
 				ring.clone(),
 				config.rpc_timeout_msec.map(Duration::from_millis),
+				config.rpc_max_in_flight_per_peer,
+				config.rpc_in_flight_wait_msec.map(Duration::from_millis),
 			),
 			system_endpoint,
+			node_stats_sources: RwLock::new(vec![]),
 			replication_mode,
 			replication_factor,
 			rpc_listen_addr: config.rpc_bind_addr,
@@ -425,6 +489,45 @@ impl System {
 		known_nodes
 	}
 
+	pub fn local_status(&self) -> NodeStatus {
+		self.local_status.load().as_ref().clone()
+	}
+
+	/// Add a component to those that fill the statistics of this node
+	pub fn register_node_stats_source(&self, source: Weak<dyn NodeStatsSource>) {
+		self.node_stats_sources.write().unwrap().push(source);
+	}
+
+	pub fn local_node_stats(&self) -> NodeStats {
+		let mut stats = NodeStats {
+			garage_version: garage_util::version::garage_version().to_string(),
+			..Default::default()
+		};
+		for source in self.node_stats_sources.read().unwrap().iter() {
+			if let Some(source) = source.upgrade() {
+				source.fill_node_stats(&mut stats);
+			}
+		}
+		stats
+	}
+
+	/// Get the statistics of a node, which can be this node
+	pub async fn get_node_stats(&self, node: Uuid) -> Result<NodeStats, Error> {
+		let resp = self
+			.rpc
+			.call(
+				&self.system_endpoint,
+				node,
+				SystemRpc::GetNodeStats,
+				RequestStrategy::with_priority(PRIO_NORMAL),
+			)
+			.await?;
+		match resp {
+			SystemRpc::ReturnNodeStats(stats) => Ok(stats),
+			m => Err(Error::unexpected_rpc_message(m)),
+		}
+	}
+
 	pub fn get_cluster_layout(&self) -> ClusterLayout {
 		self.ring.borrow().layout.clone()
 	}
@@ -467,7 +570,10 @@ impl System {
 
 	pub fn health(&self) -> ClusterHealth {
 		let ring: Arc<_> = self.ring.borrow().clone();
-		let quorum = self.replication_mode.write_quorum();
+		let quorum = std::cmp::max(
+			self.replication_mode.write_quorum(),
+			self.replication_mode.data_write_quorum(),
+		);
 		let replication_factor = self.replication_factor;
 
 		let nodes = self
@@ -589,9 +695,73 @@ impl System {
 			.save_async(&ring.layout)
 			.await
 			.expect("Cannot save current cluster layout");
+
+		let history = {
+			let mut history = self.layout_history.write().unwrap();
+			if !history.push(&ring.layout, self.layout_history_length) {
+				return Ok(());
+			}
+			history.clone()
+		};
+		self.persist_layout_history.save_async(&history).await?;
 		Ok(())
 	}
 
+	pub fn get_layout_history(&self) -> LayoutHistory {
+		self.layout_history.read().unwrap().clone()
+	}
+
+	/// Reasons to think that data is still being moved between nodes
+	/// after the last change of the cluster layout, if any
+	pub async fn get_layout_migration_status(&self) -> Vec<String> {
+		let layout = self.get_cluster_layout();
+		let known_nodes = self
+			.get_known_nodes()
+			.into_iter()
+			.map(|n| (n.id, n))
+			.collect::<HashMap<_, _>>();
+
+		let mut pending = vec![];
+		for id in layout.node_ids().iter() {
+			if *id == self.id {
+				continue;
+			}
+			match known_nodes.get(id) {
+				Some(n) if n.is_up => {
+					if n.status.cluster_layout_version < layout.version {
+						pending.push(format!(
+							"node {:?} has not received layout version {} yet",
+							id, layout.version
+						));
+					}
+				}
+				_ => pending.push(format!("node {:?} is not reachable", id)),
+			}
+		}
+
+		let stats = join_all(layout.node_ids().iter().map(|id| self.get_node_stats(*id))).await;
+		for (id, stats) in layout.node_ids().iter().zip(stats) {
+			match stats {
+				Ok(s) => {
+					if s.table_syncs_in_flight > 0 {
+						pending.push(format!(
+							"node {:?} is synchronizing {} table partitions",
+							id, s.table_syncs_in_flight
+						));
+					}
+					if let Some(n) = s.block_resync_queue_len.filter(|n| *n > 0) {
+						pending.push(format!(
+							"node {:?} has {} blocks waiting to be resynchronized",
+							id, n
+						));
+					}
+				}
+				Err(e) => pending.push(format!("could not get statistics of node {:?}: {}", id, e)),
+			}
+		}
+		pending
+	}
+
 	fn update_local_status(&self) {
 		let mut new_si: NodeStatus = self.local_status.load().as_ref().clone();
 
@@ -860,13 +1030,24 @@ impl EndpointHandler<SystemRpc> for System {
 				self.clone().handle_advertise_cluster_layout(adv).await
 			}
 			SystemRpc::GetKnownNodes => Ok(self.handle_get_known_nodes()),
+			SystemRpc::GetNodeStats => Ok(SystemRpc::ReturnNodeStats(self.local_node_stats())),
+			SystemRpc::GetClusterLayoutHistory => Ok(SystemRpc::ReturnClusterLayoutHistory(
+				self.get_layout_history(),
+			)),
+			SystemRpc::GetLayoutMigrationStatus => Ok(SystemRpc::ReturnLayoutMigrationStatus(
+				self.get_layout_migration_status().await,
+			)),
 			m => Err(Error::unexpected_rpc_message(m)),
 		}
 	}
 }
 
 impl NodeStatus {
-	fn initial(replication_factor: usize, layout: &ClusterLayout) -> Self {
+	fn initial(
+		replication_factor: usize,
+		node_role: ConfigNodeRole,
+		layout: &ClusterLayout,
+	) -> Self {
 		NodeStatus {
 			hostname: gethostname::gethostname()
 				.into_string()
@@ -876,6 +1057,7 @@ impl NodeStatus {
 			cluster_layout_staging_hash: layout.staging_hash,
 			meta_disk_avail: None,
 			data_disk_avail: None,
+			node_role,
 		}
 	}
 
@@ -887,23 +1069,64 @@ impl NodeStatus {
 			cluster_layout_staging_hash: Hash::from([0u8; 32]),
 			meta_disk_avail: None,
 			data_disk_avail: None,
+			node_role: ConfigNodeRole::default(),
+		}
+	}
+
+	/// Check that a role in the cluster layout is compatible with the role
+	/// this node is configured for
+	pub fn check_layout_role(&self, role: &NodeRole) -> Result<(), Error> {
+		match (self.node_role, role.capacity) {
+			(ConfigNodeRole::Gateway, Some(_)) => Err(Error::Message(format!(
+				"Node {} is configured with node_role = \"gateway\" and cannot be given a storage capacity in the cluster layout",
+				self.hostname
+			))),
+			(ConfigNodeRole::Storage, None) => Err(Error::Message(format!(
+				"Node {} is configured with node_role = \"storage\" and cannot be a gateway node in the cluster layout",
+				self.hostname
+			))),
+			_ => Ok(()),
 		}
 	}
 
-	fn update_disk_usage(&mut self, meta_dir: &Path, data_dir: &Path, metrics: &SystemMetrics) {
+	fn update_disk_usage(
+		&mut self,
+		meta_dir: &Path,
+		data_dir: &DataDirEnum,
+		metrics: &SystemMetrics,
+	) {
 		use systemstat::{Platform, System};
 		let mounts = System::new().mounts().unwrap_or_default();
 
-		let mount_avail = |path: &Path| {
+		let mount = |path: &Path| {
 			mounts
 				.iter()
 				.filter(|x| path.starts_with(&x.fs_mounted_on))
 				.max_by_key(|x| x.fs_mounted_on.len())
-				.map(|x| (x.avail.as_u64(), x.total.as_u64()))
 		};
 
-		self.meta_disk_avail = mount_avail(meta_dir);
-		self.data_disk_avail = mount_avail(data_dir);
+		self.meta_disk_avail = mount(meta_dir).map(|x| (x.avail.as_u64(), x.total.as_u64()));
+
+		// With several data directories, the space of each of the filesystems
+		// in which new blocks can be written is counted once
+		let mut data_mounts = vec![];
+		for dir in data_dir.dirs().iter().filter(|d| !d.read_only) {
+			if let Some(m) = mount(&dir.path) {
+				if !data_mounts
+					.iter()
+					.any(|x: &&systemstat::Filesystem| x.fs_mounted_on == m.fs_mounted_on)
+				{
+					data_mounts.push(m);
+				}
+			}
+		}
+		self.data_disk_avail = if data_mounts.is_empty() {
+			None
+		} else {
+			Some(data_mounts.iter().fold((0, 0), |(avail, total), x| {
+				(avail + x.avail.as_u64(), total + x.total.as_u64())
+			}))
+		};
 
 		if let Some((avail, total)) = self.meta_disk_avail {
 			metrics
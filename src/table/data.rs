@@ -1,4 +1,5 @@
 use core::borrow::Borrow;
+use std::cell::Cell;
 use std::convert::TryInto;
 use std::sync::Arc;
 
@@ -165,7 +166,8 @@ impl<F: TableSchema, R: TableReplication> TableData<F, R> {
 	// Mutation functions
 	// When changing this code, take care of propagating modifications correctly:
 	// - When an entry is modified or deleted, call the updated() function
-	//   on the table instance
+	//   on the table instance, and its after_commit() function once the
+	//   transaction is committed
 	// - When an entry is modified or deleted, add it to the merkle updater's todo list.
 	//   This has to be done atomically with the modification for the merkle updater
 	//   to maintain consistency. The merkle updater must then be notified with todo_notify.
@@ -200,6 +202,40 @@ impl<F: TableSchema, R: TableReplication> TableData<F, R> {
 		partition_key: &F::P,
 		sort_key: &F::S,
 		update_fn: impl Fn(&mut db::Transaction, Option<F::E>) -> db::TxOpResult<F::E>,
+	) -> Result<Option<F::E>, Error> {
+		self.update_entry_with_opt(partition_key, sort_key, |tx, ent| {
+			update_fn(tx, ent).map(Some)
+		})
+	}
+
+	/// Replace the locally stored entry by `new`, only if the value
+	/// currently stored is equal to `expected` (`None` meaning that no entry
+	/// is stored). The comparison and the write are done in the same
+	/// transaction. Returns false if the stored value differed, in which
+	/// case nothing is written.
+	pub(crate) fn compare_and_swap(
+		&self,
+		partition_key: &F::P,
+		sort_key: &F::S,
+		expected: Option<&F::E>,
+		new: &F::E,
+	) -> Result<bool, Error> {
+		let swapped = Cell::new(false);
+		self.update_entry_with_opt(partition_key, sort_key, |_tx, ent| {
+			let unchanged = ent.as_ref() == expected;
+			swapped.set(unchanged);
+			Ok(if unchanged { Some(new.clone()) } else { None })
+		})?;
+		Ok(swapped.get())
+	}
+
+	/// Same as `update_entry_with`, but `update_fn` may return `None`
+	/// to leave the stored entry untouched.
+	pub fn update_entry_with_opt(
+		&self,
+		partition_key: &F::P,
+		sort_key: &F::S,
+		update_fn: impl Fn(&mut db::Transaction, Option<F::E>) -> db::TxOpResult<Option<F::E>>,
 	) -> Result<Option<F::E>, Error> {
 		let tree_key = self.tree_key(partition_key, sort_key);
 
@@ -212,6 +248,10 @@ impl<F: TableSchema, R: TableReplication> TableData<F, R> {
 				}
 				None => (None, None, update_fn(&mut tx, None)?),
 			};
+			let new_entry = match new_entry {
+				Some(e) => e,
+				None => return Ok(None),
+			};
 
 			// Changed can be true in two scenarios
 			// Scenario 1: the actual represented value changed,
@@ -235,14 +275,16 @@ impl<F: TableSchema, R: TableReplication> TableData<F, R> {
 				self.instance
 					.updated(&mut tx, old_entry.as_ref(), Some(&new_entry))?;
 
-				Ok(Some((new_entry, new_bytes_hash)))
+				Ok(Some((old_entry, new_entry, new_bytes_hash)))
 			} else {
 				Ok(None)
 			}
 		})?;
 
-		if let Some((new_entry, new_bytes_hash)) = changed {
+		if let Some((old_entry, new_entry, new_bytes_hash)) = changed {
 			self.metrics.internal_update_counter.add(1);
+			self.instance
+				.after_commit(old_entry.as_ref(), Some(&new_entry));
 
 			let is_tombstone = new_entry.is_tombstone();
 			self.merkle_todo_notify.notify_one();
@@ -266,7 +308,7 @@ impl<F: TableSchema, R: TableReplication> TableData<F, R> {
 		}
 	}
 
-	pub(crate) fn delete_if_equal(self: &Arc<Self>, k: &[u8], v: &[u8]) -> Result<bool, Error> {
+	pub fn delete_if_equal(self: &Arc<Self>, k: &[u8], v: &[u8]) -> Result<bool, Error> {
 		let removed = self
 			.store
 			.db()
@@ -278,16 +320,32 @@ impl<F: TableSchema, R: TableReplication> TableData<F, R> {
 					tx.insert(&self.merkle_todo, k, vec![])?;
 
 					self.instance.updated(&mut tx, Some(&old_entry), None)?;
-					Ok(true)
+					Ok(Some(old_entry))
 				}
-				_ => Ok(false),
+				_ => Ok(None),
 			})?;
 
-		if removed {
+		if let Some(old_entry) = &removed {
 			self.metrics.internal_delete_counter.add(1);
 			self.merkle_todo_notify.notify_one();
+			self.instance.after_commit(Some(old_entry), None);
+		}
+		Ok(removed.is_some())
+	}
+
+	/// Schedule an entry for deletion by the table GC, even if it is not a
+	/// tombstone. As for tombstones, the entry is only deleted once all nodes
+	/// storing it hold the exact same value, and only the partition leader
+	/// queues it. Returns whether the entry was queued on this node.
+	pub fn queue_gc(&self, tree_key: &[u8], value: &[u8]) -> Result<bool, Error> {
+		let pk_hash = Hash::try_from(&tree_key[..32]).unwrap();
+		let nodes = self.replication.write_nodes(&pk_hash);
+		if nodes.first() == Some(&self.system.id) {
+			GcTodoEntry::new(tree_key.to_vec(), blake2sum(value)).save(&self.gc_todo)?;
+			Ok(true)
+		} else {
+			Ok(false)
 		}
-		Ok(removed)
 	}
 
 	pub(crate) fn delete_if_equal_hash(
@@ -306,16 +364,17 @@ impl<F: TableSchema, R: TableReplication> TableData<F, R> {
 					tx.insert(&self.merkle_todo, k, vec![])?;
 
 					self.instance.updated(&mut tx, Some(&old_entry), None)?;
-					Ok(true)
+					Ok(Some(old_entry))
 				}
-				_ => Ok(false),
+				_ => Ok(None),
 			})?;
 
-		if removed {
+		if let Some(old_entry) = &removed {
 			self.metrics.internal_delete_counter.add(1);
 			self.merkle_todo_notify.notify_one();
+			self.instance.after_commit(Some(old_entry), None);
 		}
-		Ok(removed)
+		Ok(removed.is_some())
 	}
 
 	// ---- Insert queue functions ----
@@ -356,6 +415,7 @@ impl<F: TableSchema, R: TableReplication> TableData<F, R> {
 		match F::E::decode(bytes) {
 			Some(x) => Ok(x),
 			None => {
+				self.metrics.decode_error_counter.add(1);
 				error!("Unable to decode entry of {}", F::TABLE_NAME);
 				for line in hexdump::hexdump_iter(bytes) {
 					debug!("{}", line);
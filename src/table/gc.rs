@@ -270,6 +270,97 @@ impl<F: TableSchema, R: TableReplication> TableGc<F, R> {
 
 		Ok(())
 	}
+
+	/// Delete a batch of the tombstones that have been in the GC todo list for
+	/// longer than `older_than`, because some of the nodes storing them could not
+	/// be reached. Unlike the normal GC, the tombstones are deleted from this node
+	/// and from the nodes that answer, without waiting for the other nodes.
+	/// Returns the number of tombstones deleted and whether the batch was full.
+	pub(crate) async fn remove_stale_tombstones(
+		&self,
+		older_than: Duration,
+	) -> Result<(usize, bool), Error> {
+		let limit = now_msec().saturating_sub(older_than.as_millis() as u64);
+
+		let mut candidates = vec![];
+		let mut excluded = vec![];
+		for entry_kv in self.data.gc_todo.iter()? {
+			let (k, vhash) = entry_kv?;
+			let mut todo_entry = GcTodoEntry::parse(&k, &vhash);
+			if todo_entry.tombstone_timestamp > limit {
+				break;
+			}
+
+			// Entries can be queued for GC without being tombstones (see queue_gc),
+			// those must always go through the normal GC
+			let value = self
+				.data
+				.store
+				.get(&todo_entry.key[..])?
+				.filter(|v| blake2sum(&v[..]) == todo_entry.value_hash)
+				.map(|v| v.to_vec());
+			match value {
+				Some(v) if self.data.decode_entry(&v)?.is_tombstone() => {
+					todo_entry.value = Some(v);
+					candidates.push(todo_entry);
+				}
+				Some(_) => (),
+				None => excluded.push(todo_entry),
+			}
+			if candidates.len() + excluded.len() >= TABLE_GC_BATCH_SIZE {
+				break;
+			}
+		}
+		let full_batch = candidates.len() + excluded.len() >= TABLE_GC_BATCH_SIZE;
+
+		for entry in excluded {
+			entry.remove_if_equal(&self.data.gc_todo)?;
+		}
+
+		let n_items = candidates.len();
+		for item in candidates {
+			let pkh = Hash::try_from(&item.key[..32]).unwrap();
+			let mut nodes = self.data.replication.write_nodes(&pkh);
+			nodes.retain(|x| *x != self.system.id);
+
+			// As in the normal GC, make sure that the nodes have the tombstone
+			// before asking them to delete it, but ignore the nodes that don't answer
+			let value = ByteBuf::from(item.value.clone().unwrap());
+			let resps = self
+				.system
+				.rpc
+				.call_many(
+					&self.endpoint,
+					&nodes[..],
+					GcRpc::Update(vec![value]),
+					RequestStrategy::with_priority(PRIO_BACKGROUND),
+				)
+				.await?;
+			let nodes = resps
+				.into_iter()
+				.filter(|(_, r)| r.is_ok())
+				.map(|(n, _)| n)
+				.collect::<Vec<_>>();
+			self.system
+				.rpc
+				.call_many(
+					&self.endpoint,
+					&nodes[..],
+					GcRpc::DeleteIfEqualHash(vec![(
+						ByteBuf::from(item.key.clone()),
+						item.value_hash,
+					)]),
+					RequestStrategy::with_priority(PRIO_BACKGROUND),
+				)
+				.await?;
+
+			self.data
+				.delete_if_equal_hash(&item.key[..], item.value_hash)?;
+			item.remove_if_equal(&self.data.gc_todo)?;
+		}
+
+		Ok((n_items, full_batch))
+	}
 }
 
 #[async_trait]
@@ -12,11 +12,14 @@ pub struct TableMetrics {
 
 	pub(crate) get_request_counter: BoundCounter<u64>,
 	pub(crate) get_request_duration: BoundValueRecorder<f64>,
+	pub(crate) get_range_request_counter: BoundCounter<u64>,
+	pub(crate) get_range_request_duration: BoundValueRecorder<f64>,
 	pub(crate) put_request_counter: BoundCounter<u64>,
 	pub(crate) put_request_duration: BoundValueRecorder<f64>,
 
 	pub(crate) internal_update_counter: BoundCounter<u64>,
 	pub(crate) internal_delete_counter: BoundCounter<u64>,
+	pub(crate) decode_error_counter: BoundCounter<u64>,
 
 	pub(crate) sync_items_sent: Counter<u64>,
 	pub(crate) sync_items_received: Counter<u64>,
@@ -88,12 +91,22 @@ impl TableMetrics {
 
 			get_request_counter: meter
 				.u64_counter("table.get_request_counter")
-				.with_description("Number of get/get_range requests internally made on this table")
+				.with_description("Number of get requests internally made on this table")
 				.init()
 				.bind(&[KeyValue::new("table_name", table_name)]),
 			get_request_duration: meter
 				.f64_value_recorder("table.get_request_duration")
-				.with_description("Duration of get/get_range requests internally made on this table, in seconds")
+				.with_description("Duration of get requests internally made on this table, in seconds")
+				.init()
+				.bind(&[KeyValue::new("table_name", table_name)]),
+			get_range_request_counter: meter
+				.u64_counter("table.get_range_request_counter")
+				.with_description("Number of get_range (scan) requests internally made on this table")
+				.init()
+				.bind(&[KeyValue::new("table_name", table_name)]),
+			get_range_request_duration: meter
+				.f64_value_recorder("table.get_range_request_duration")
+				.with_description("Duration of get_range (scan) requests internally made on this table, in seconds")
 				.init()
 				.bind(&[KeyValue::new("table_name", table_name)]),
 			put_request_counter: meter
@@ -117,6 +130,11 @@ impl TableMetrics {
 				.with_description("Number of value deletions in the tree (due to GC or repartitioning)")
 				.init()
 				.bind(&[KeyValue::new("table_name", table_name)]),
+			decode_error_counter: meter
+				.u64_counter("table.decode_error_counter")
+				.with_description("Number of stored entries that could not be decoded (e.g. block refs with a truncated block hash)")
+				.init()
+				.bind(&[KeyValue::new("table_name", table_name)]),
 
 			sync_items_sent: meter
 				.u64_counter("table.sync_items_sent")
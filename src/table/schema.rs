@@ -93,5 +93,11 @@ pub trait TableSchema: Send + Sync + 'static {
 		Ok(())
 	}
 
+	/// Actions triggered by data changing in a table that must only be done
+	/// once the change is committed to the local database, as they cannot be
+	/// undone if the transaction is aborted or retried (e.g. notifying other
+	/// systems). Called after `updated`, with the same entries.
+	fn after_commit(&self, _old: Option<&Self::E>, _new: Option<&Self::E>) {}
+
 	fn matches_filter(entry: &Self::E, filter: &Self::Filter) -> bool;
 }
@@ -1,4 +1,5 @@
 use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
@@ -18,7 +19,7 @@ use garage_util::encode::{debug_serialize, nonversioned_encode};
 use garage_util::error::{Error, OkOrMessage};
 
 use garage_rpc::ring::*;
-use garage_rpc::system::System;
+use garage_rpc::system::{NodeStats, NodeStatsSource, System};
 use garage_rpc::*;
 
 use crate::data::*;
@@ -36,6 +37,9 @@ pub struct TableSyncer<F: TableSchema, R: TableReplication> {
 
 	add_full_sync_tx: ArcSwapOption<mpsc::UnboundedSender<()>>,
 	endpoint: Arc<Endpoint<SyncRpc, Self>>,
+
+	/// Number of synchronizations with other nodes in progress
+	syncs_in_flight: AtomicUsize,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -78,9 +82,15 @@ impl<F: TableSchema, R: TableReplication> TableSyncer<F, R> {
 			merkle,
 			add_full_sync_tx: ArcSwapOption::new(None),
 			endpoint,
+			syncs_in_flight: AtomicUsize::new(0),
 		});
 		syncer.endpoint.set_handler(syncer.clone());
 
+		let stats_source: Arc<dyn NodeStatsSource> = syncer.clone();
+		syncer
+			.system
+			.register_node_stats_source(Arc::downgrade(&stats_source));
+
 		syncer
 	}
 
@@ -108,6 +118,37 @@ impl<F: TableSchema, R: TableReplication> TableSyncer<F, R> {
 		Ok(())
 	}
 
+	/// Get the hash of the root of the Merkle tree of a partition on a node,
+	/// or `None` if the partition is empty on that node
+	pub async fn get_root_hash_from(
+		&self,
+		who: Uuid,
+		partition: Partition,
+	) -> Result<Option<Hash>, Error> {
+		let key = MerkleNodeKey {
+			partition,
+			prefix: vec![],
+		};
+		let resp = self
+			.system
+			.rpc
+			.call(
+				&self.endpoint,
+				who,
+				SyncRpc::GetNode(key),
+				RequestStrategy::with_priority(PRIO_NORMAL),
+			)
+			.await?;
+		match resp {
+			SyncRpc::Node(_, node) if node.is_empty() => Ok(None),
+			SyncRpc::Node(_, node) => Ok(Some(hash_of_merkle_node(&node)?)),
+			x => Err(Error::Message(format!(
+				"Invalid response to GetNode RPC: {}",
+				debug_serialize(x)
+			))),
+		}
+	}
+
 	// ----
 
 	async fn sync_partition(
@@ -289,6 +330,8 @@ impl<F: TableSchema, R: TableReplication> TableSyncer<F, R> {
 		who: Uuid,
 		must_exit: watch::Receiver<bool>,
 	) -> Result<(), Error> {
+		let _in_flight = SyncInFlight::new(&self.syncs_in_flight);
+
 		let (root_ck_key, root_ck) = self.get_root_ck(partition.partition)?;
 		if root_ck.is_empty() {
 			debug!(
@@ -488,6 +531,28 @@ impl<F: TableSchema, R: TableReplication> EndpointHandler<SyncRpc> for TableSync
 	}
 }
 
+impl<F: TableSchema, R: TableReplication> NodeStatsSource for TableSyncer<F, R> {
+	fn fill_node_stats(&self, stats: &mut NodeStats) {
+		stats.table_syncs_in_flight += self.syncs_in_flight.load(Ordering::Relaxed);
+	}
+}
+
+/// Counts a synchronization with another node as in progress until it is dropped
+struct SyncInFlight<'a>(&'a AtomicUsize);
+
+impl<'a> SyncInFlight<'a> {
+	fn new(counter: &'a AtomicUsize) -> Self {
+		counter.fetch_add(1, Ordering::Relaxed);
+		Self(counter)
+	}
+}
+
+impl<'a> Drop for SyncInFlight<'a> {
+	fn drop(&mut self) {
+		self.0.fetch_sub(1, Ordering::Relaxed);
+	}
+}
+
 // -------- Sync Worker ---------
 
 struct SyncWorker<F: TableSchema, R: TableReplication> {
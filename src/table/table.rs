@@ -1,6 +1,7 @@
 use std::borrow::Borrow;
 use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::sync::Arc;
+use std::time::Duration;
 
 use async_trait::async_trait;
 use futures::stream::*;
@@ -16,7 +17,7 @@ use garage_db as db;
 
 use garage_util::background::BackgroundRunner;
 use garage_util::data::*;
-use garage_util::error::Error;
+use garage_util::error::{Error, OkOrMessage};
 use garage_util::metrics::RecordDuration;
 use garage_util::migrate::Migrate;
 
@@ -42,6 +43,16 @@ pub struct Table<F: TableSchema, R: TableReplication> {
 	endpoint: Arc<Endpoint<TableRpc<F>, Self>>,
 }
 
+/// Number of nodes that must answer a read of an entry
+enum ReadQuorum {
+	/// The read quorum of the table
+	Default,
+	/// At least this number of nodes, and at least the read quorum of the table
+	AtLeast(usize),
+	/// All of the nodes that store the entry
+	AllNodes,
+}
+
 #[derive(Serialize, Deserialize)]
 pub(crate) enum TableRpc<F: TableSchema> {
 	Ok,
@@ -65,6 +76,12 @@ impl<F: TableSchema> Rpc for TableRpc<F> {
 	type Response = Result<TableRpc<F>, Error>;
 }
 
+const COUNT_BY_PARTITION_BATCH_SIZE: usize = 10000;
+
+/// Number of times `atomic_update` retries when the entry is modified
+/// concurrently before giving up
+const ATOMIC_UPDATE_MAX_ATTEMPTS: usize = 10;
+
 impl<F: TableSchema, R: TableReplication> Table<F, R> {
 	// =============== PUBLIC INTERFACE FUNCTIONS (new, insert, get, etc) ===============
 
@@ -215,7 +232,30 @@ impl<F: TableSchema, R: TableReplication> Table<F, R> {
 		let span = tracer.start(format!("{} get", F::TABLE_NAME));
 
 		let res = self
-			.get_internal(partition_key, sort_key)
+			.get_internal(partition_key, sort_key, ReadQuorum::Default)
+			.bound_record_duration(&self.data.metrics.get_request_duration)
+			.with_context(Context::current_with_span(span))
+			.await?;
+
+		self.data.metrics.get_request_counter.add(1);
+
+		Ok(res)
+	}
+
+	/// Read an entry from at least `quorum` of the nodes that store it,
+	/// or from the read quorum of the table if it is higher. This is used
+	/// for reads that must return the result of the last successful write.
+	pub async fn get_with_quorum(
+		self: &Arc<Self>,
+		partition_key: &F::P,
+		sort_key: &F::S,
+		quorum: usize,
+	) -> Result<Option<F::E>, Error> {
+		let tracer = opentelemetry::global::tracer("garage_table");
+		let span = tracer.start(format!("{} get", F::TABLE_NAME));
+
+		let res = self
+			.get_internal(partition_key, sort_key, ReadQuorum::AtLeast(quorum))
 			.bound_record_duration(&self.data.metrics.get_request_duration)
 			.with_context(Context::current_with_span(span))
 			.await?;
@@ -225,13 +265,37 @@ impl<F: TableSchema, R: TableReplication> Table<F, R> {
 		Ok(res)
 	}
 
+	/// Read an entry from all the nodes that store it instead of a quorum,
+	/// failing if any of them can't be reached. This is used to confirm
+	/// that an entry is absent before acting on it.
+	pub async fn get_from_all_nodes(
+		self: &Arc<Self>,
+		partition_key: &F::P,
+		sort_key: &F::S,
+	) -> Result<Option<F::E>, Error> {
+		self.get_internal(partition_key, sort_key, ReadQuorum::AllNodes)
+			.await
+	}
+
 	async fn get_internal(
 		self: &Arc<Self>,
 		partition_key: &F::P,
 		sort_key: &F::S,
+		read_quorum: ReadQuorum,
 	) -> Result<Option<F::E>, Error> {
 		let hash = partition_key.hash();
-		let who = self.data.replication.read_nodes(&hash);
+		let who = match read_quorum {
+			ReadQuorum::AllNodes => self.data.replication.write_nodes(&hash),
+			_ => self.data.replication.read_nodes(&hash),
+		};
+		let quorum = match read_quorum {
+			ReadQuorum::Default => self.data.replication.read_quorum(),
+			ReadQuorum::AtLeast(q) => std::cmp::min(
+				std::cmp::max(q, self.data.replication.read_quorum()),
+				who.len(),
+			),
+			ReadQuorum::AllNodes => who.len(),
+		};
 
 		let rpc = TableRpc::<F>::ReadEntry(partition_key.clone(), sort_key.clone());
 		let resps = self
@@ -242,7 +306,7 @@ impl<F: TableSchema, R: TableReplication> Table<F, R> {
 				&who[..],
 				rpc,
 				RequestStrategy::with_priority(PRIO_NORMAL)
-					.with_quorum(self.data.replication.read_quorum())
+					.with_quorum(quorum)
 					.interrupt_after_quorum(true),
 			)
 			.await?;
@@ -283,6 +347,35 @@ impl<F: TableSchema, R: TableReplication> Table<F, R> {
 		Ok(ret)
 	}
 
+	/// Delete a batch of tombstones that the table GC has not been able to
+	/// collect for longer than `older_than` (see `garage repair block_refs
+	/// --remove-stale-tombstones`). Returns the number of tombstones deleted
+	/// and whether there might be more of them.
+	pub async fn remove_stale_tombstones(
+		&self,
+		older_than: Duration,
+	) -> Result<(usize, bool), Error> {
+		self.gc.remove_stale_tombstones(older_than).await
+	}
+
+	/// Read an entry from a single node, recording the time spent in each
+	/// step of the RPC call (used by `garage debug trace-rpc`)
+	pub async fn traced_get(
+		&self,
+		node: Uuid,
+		partition_key: &F::P,
+		sort_key: &F::S,
+	) -> Result<RpcTiming, Error> {
+		let rpc = TableRpc::<F>::ReadEntry(partition_key.clone(), sort_key.clone());
+		let (resp, timing) = TracedRpc::new(&self.system.rpc, &*self.endpoint)
+			.call(node, rpc, PRIO_NORMAL)
+			.await?;
+		match resp? {
+			TableRpc::ReadEntryResponse(_) => Ok(timing),
+			m => Err(Error::unexpected_rpc_message(m)),
+		}
+	}
+
 	pub async fn get_range(
 		self: &Arc<Self>,
 		partition_key: &F::P,
@@ -302,11 +395,11 @@ impl<F: TableSchema, R: TableReplication> Table<F, R> {
 				limit,
 				enumeration_order,
 			)
-			.bound_record_duration(&self.data.metrics.get_request_duration)
+			.bound_record_duration(&self.data.metrics.get_range_request_duration)
 			.with_context(Context::current_with_span(span))
 			.await?;
 
-		self.data.metrics.get_request_counter.add(1);
+		self.data.metrics.get_range_request_counter.add(1);
 
 		Ok(res)
 	}
@@ -406,6 +499,46 @@ impl<F: TableSchema, R: TableReplication> Table<F, R> {
 		Ok(ret_vec)
 	}
 
+	/// Count the entries stored on this node for each partition key. Entries are
+	/// indexed by the hash of their partition key, which is the partition key
+	/// itself for tables partitioned by UUID or by hash (e.g. the object table,
+	/// partitioned by bucket ID). Only the keys of the local store are scanned,
+	/// values are never decoded.
+	pub fn count_by_partition(&self) -> Result<BTreeMap<Hash, u64>, Error> {
+		let mut ret = BTreeMap::<Hash, u64>::new();
+
+		// The store is scanned by batches so that we don't keep
+		// a single iterator open on the database for too long
+		let mut pos: Option<Vec<u8>> = None;
+		loop {
+			let iter = match &pos {
+				None => self.data.store.iter()?,
+				Some(p) => self.data.store.range::<&[u8], _>((
+					std::ops::Bound::Excluded(p.as_slice()),
+					std::ops::Bound::Unbounded,
+				))?,
+			};
+			let mut count = 0;
+			let mut last = None;
+			for item in iter.take(COUNT_BY_PARTITION_BATCH_SIZE) {
+				let (key, _) = item?;
+				let hash = key
+					.get(..32)
+					.and_then(Hash::try_from)
+					.ok_or_message("Invalid key in table store")?;
+				*ret.entry(hash).or_default() += 1;
+				count += 1;
+				last = Some(key);
+			}
+			if count < COUNT_BY_PARTITION_BATCH_SIZE {
+				break;
+			}
+			pos = last;
+		}
+
+		Ok(ret)
+	}
+
 	// =============== UTILITY FUNCTION FOR CLIENT OPERATIONS ===============
 
 	async fn repair_on_read(&self, who: &[Uuid], what: F::E) -> Result<(), Error> {
@@ -423,6 +556,61 @@ impl<F: TableSchema, R: TableReplication> Table<F, R> {
 	}
 }
 
+impl<F: TableSchema> Table<F, TableFullReplication> {
+	/// Read-modify-write an entry with compare-and-swap semantics.
+	///
+	/// As this table is fully replicated, the local node holds a complete copy
+	/// of it: the current value is read locally and passed to `f`, and the
+	/// value returned by `f` is written locally only if the stored value did not
+	/// change in the meantime. It is then propagated to the other nodes like in
+	/// a normal insert. If the stored value was modified concurrently, it is
+	/// read again and `f` is called again on the new value, so `f` may be
+	/// called several times.
+	///
+	/// If `f` returns `None`, nothing is written and the entry is left as is.
+	/// This does not delete the entry: in CRDT tables, deletions are done by
+	/// returning an entry in its deleted state (a tombstone).
+	pub async fn atomic_update<U>(
+		&self,
+		partition_key: &F::P,
+		sort_key: &F::S,
+		mut f: U,
+	) -> Result<(), Error>
+	where
+		U: FnMut(Option<F::E>) -> Option<F::E>,
+	{
+		for _ in 0..ATOMIC_UPDATE_MAX_ATTEMPTS {
+			let old = self
+				.data
+				.read_entry(partition_key, sort_key)?
+				.map(|bytes| self.data.decode_entry(&bytes))
+				.transpose()?;
+
+			let new = match f(old.clone()) {
+				Some(new) => new,
+				None => return Ok(()),
+			};
+
+			if self
+				.data
+				.compare_and_swap(partition_key, sort_key, old.as_ref(), &new)?
+			{
+				return self.insert(&new).await;
+			}
+			debug!(
+				"({}) atomic_update: entry modified concurrently, retrying",
+				F::TABLE_NAME
+			);
+		}
+
+		Err(Error::Message(format!(
+			"Entry of {} was modified concurrently {} times, giving up",
+			F::TABLE_NAME,
+			ATOMIC_UPDATE_MAX_ATTEMPTS
+		)))
+	}
+}
+
 #[async_trait]
 impl<F: TableSchema, R: TableReplication> EndpointHandler<TableRpc<F>> for Table<F, R> {
 	async fn handle(
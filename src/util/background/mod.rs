@@ -5,7 +5,9 @@ pub mod worker;
 
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 
+use opentelemetry::{global, metrics::ValueObserver, KeyValue};
 use serde::{Deserialize, Serialize};
 use tokio::sync::{mpsc, watch};
 
@@ -16,6 +18,7 @@ pub use worker::{Worker, WorkerState};
 pub struct BackgroundRunner {
 	send_worker: mpsc::UnboundedSender<Box<dyn Worker>>,
 	worker_info: Arc<std::sync::Mutex<HashMap<usize, WorkerInfo>>>,
+	_worker_state_observer: ValueObserver<u64>,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -26,6 +29,15 @@ pub struct WorkerInfo {
 	pub errors: usize,
 	pub consecutive_errors: usize,
 	pub last_error: Option<(String, u64)>,
+	/// Number of times the worker's work function was called
+	pub iterations: u64,
+	/// Total time spent in the worker's work function
+	pub work_time: Duration,
+	/// Time (in msec since UNIX epoch) at which the work function last returned
+	pub last_run: Option<u64>,
+	/// Time spent in the last call to the worker's work function
+	#[serde(default)]
+	pub last_run_duration: Option<Duration>,
 }
 
 /// WorkerStatus is a struct returned by the worker with a bunch of canonical
@@ -34,6 +46,9 @@ pub struct WorkerInfo {
 pub struct WorkerStatus {
 	pub tranquility: Option<u32>,
 	pub progress: Option<String>,
+	/// Completion percentage, for workers that know how much work remains
+	#[serde(default)]
+	pub progress_percent: Option<f32>,
 	pub queue_length: Option<u64>,
 	pub persistent_errors: Option<u64>,
 	pub freeform: Vec<String>,
@@ -52,9 +67,33 @@ impl BackgroundRunner {
 			worker_processor.run().await;
 		});
 
+		let worker_info_2 = worker_info.clone();
+		let worker_state_observer = global::meter("garage_util/background")
+			.u64_value_observer("background.worker_count", move |observer| {
+				let mut counts = HashMap::<&'static str, u64>::new();
+				for info in worker_info_2.lock().unwrap().values() {
+					let state = match info.state {
+						WorkerState::Busy => "busy",
+						WorkerState::Throttled(_) => "throttled",
+						WorkerState::Idle => "idle",
+						WorkerState::Done => "done",
+					};
+					*counts.entry(state).or_insert(0) += 1;
+				}
+				for state in ["busy", "throttled", "idle", "done"] {
+					observer.observe(
+						counts.get(state).copied().unwrap_or(0),
+						&[KeyValue::new("state", state)],
+					);
+				}
+			})
+			.with_description("Number of background workers in each state")
+			.init();
+
 		let bgrunner = Arc::new(Self {
 			send_worker,
 			worker_info,
+			_worker_state_observer: worker_state_observer,
 		});
 		(bgrunner, await_all_done)
 	}
@@ -1,21 +1,84 @@
 use std::collections::HashMap;
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+use garage_db as db;
+
+use crate::background::*;
 use crate::error::{Error, OkOrMessage};
-use crate::migrate::Migrate;
+use crate::migrate::{InitialFormat, Migrate};
 use crate::persister::PersisterShared;
+use crate::time::*;
 
 pub struct BgVars {
-	vars: HashMap<&'static str, Box<dyn BgVarTrait>>,
+	vars: HashMap<&'static str, Arc<dyn BgVarTrait>>,
+	expiry: Option<db::Tree>,
+}
+
+/// An entry of the `bg_var_expiry` tree, indicating that a variable
+/// must be reverted to its default value at a given time
+#[derive(Serialize, Deserialize)]
+struct BgVarExpiry {
+	/// Time at which the variable is reverted, in msec since the epoch
+	expires_at: u64,
+}
+
+impl InitialFormat for BgVarExpiry {}
+
+/// Current state of a variable, as returned by `BgVars::dump`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BgVarInfo {
+	pub name: String,
+	pub value: String,
+	pub default: String,
+	pub source: BgVarSource,
+	/// Time (in msec since UNIX epoch) at which the variable will be reverted
+	/// to its default value, if any
+	pub expires_at: Option<u64>,
+}
+
+/// Where the current value of a variable comes from
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum BgVarSource {
+	/// The variable has its default value
+	Default,
+	/// The variable was changed at runtime (e.g. with `garage worker set`
+	/// or `garage server set-var`)
+	Runtime,
+	/// The variable cannot be changed, it reports the state of a worker
+	ReadOnly,
+}
+
+impl std::fmt::Display for BgVarSource {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::Default => write!(f, "default"),
+			Self::Runtime => write!(f, "runtime"),
+			Self::ReadOnly => write!(f, "read-only"),
+		}
+	}
 }
 
 impl BgVars {
 	pub fn new() -> Self {
 		Self {
 			vars: HashMap::new(),
+			expiry: None,
 		}
 	}
 
+	/// Enable changing variables with an expiry date, which is stored
+	/// in the given tree so that it is kept across restarts
+	pub fn set_expiry_tree(&mut self, tree: db::Tree) {
+		self.expiry = Some(tree);
+	}
+
 	pub fn register_rw<V, T, GF, SF>(
 		&mut self,
 		p: &PersisterShared<V>,
@@ -28,13 +91,22 @@ impl BgVars {
 		GF: Fn(&PersisterShared<V>) -> T + Send + Sync + 'static,
 		SF: Fn(&PersisterShared<V>, T) -> Result<(), Error> + Send + Sync + 'static,
 	{
+		let default = Some(get_fn(&PersisterShared::detached(V::default())).to_string());
+
 		let p1 = p.clone();
 		let get_fn = move || get_fn(&p1);
 
 		let p2 = p.clone();
 		let set_fn = move |v| set_fn(&p2, v);
 
-		self.vars.insert(name, Box::new(BgVar { get_fn, set_fn }));
+		self.vars.insert(
+			name,
+			Arc::new(BgVar {
+				get_fn,
+				set_fn,
+				default,
+			}),
+		);
 	}
 
 	pub fn register_ro<V, T, GF>(&mut self, p: &PersisterShared<V>, name: &'static str, get_fn: GF)
@@ -48,29 +120,174 @@ impl BgVars {
 
 		let set_fn = move |_| Err(Error::Message(format!("Cannot set value of {}", name)));
 
-		self.vars.insert(name, Box::new(BgVar { get_fn, set_fn }));
+		self.vars.insert(
+			name,
+			Arc::new(BgVar {
+				get_fn,
+				set_fn,
+				default: None,
+			}),
+		);
 	}
 
+	/// Get the value of a variable. If it has expired, its default value is
+	/// returned, even if the expiry worker has not reverted it yet.
 	pub fn get(&self, var: &str) -> Result<String, Error> {
-		Ok(self
+		let bgvar = self
 			.vars
 			.get(var)
-			.ok_or_message("variable does not exist")?
-			.get())
+			.ok_or_message("variable does not exist")?;
+		self.get_unexpired(var, bgvar.as_ref(), now_msec())
 	}
 
 	pub fn get_all(&self) -> Vec<(&'static str, String)> {
-		self.vars.iter().map(|(k, v)| (*k, v.get())).collect()
+		let now = now_msec();
+		self.vars
+			.iter()
+			.map(|(k, v)| {
+				let value = self.get_unexpired(k, v.as_ref(), now).unwrap_or_else(|e| {
+					error!("Unable to read expiry of variable {}: {}", k, e);
+					v.get()
+				});
+				(*k, value)
+			})
+			.collect()
+	}
+
+	/// Get the current value of all variables, with their default value,
+	/// where their value comes from and when they expire
+	pub fn dump(&self) -> Result<Vec<BgVarInfo>, Error> {
+		let now = now_msec();
+
+		let mut ret = vec![];
+		for (name, bgvar) in self.vars.iter() {
+			let value = self.get_unexpired(name, bgvar.as_ref(), now)?;
+			let expires_at = self.expires_at(name)?.filter(|t| *t > now);
+			let (default, source) = match bgvar.default() {
+				None => (value.clone(), BgVarSource::ReadOnly),
+				Some(d) if d == value && expires_at.is_none() => (d, BgVarSource::Default),
+				Some(d) => (d, BgVarSource::Runtime),
+			};
+			ret.push(BgVarInfo {
+				name: name.to_string(),
+				value,
+				default,
+				source,
+				expires_at,
+			});
+		}
+		ret.sort_by(|a, b| a.name.cmp(&b.name));
+		Ok(ret)
 	}
 
 	pub fn set(&self, var: &str, val: &str) -> Result<(), Error> {
 		self.vars
 			.get(var)
 			.ok_or_message("variable does not exist")?
-			.set(val)
+			.set(val)?;
+		// An explicit change cancels any pending revert of this variable
+		if let Some(tree) = &self.expiry {
+			tree.remove(var)?;
+		}
+		Ok(())
+	}
+
+	/// Set a variable until a given time, after which it is reverted
+	/// to its default value
+	pub fn set_with_expiry(
+		&self,
+		var: &str,
+		val: String,
+		expires_at: SystemTime,
+	) -> Result<(), Error> {
+		let tree = self
+			.expiry
+			.as_ref()
+			.ok_or_message("variable expiry is not available")?;
+		let bgvar = self
+			.vars
+			.get(var)
+			.ok_or_message("variable does not exist")?;
+		let expires_at = expires_at
+			.duration_since(UNIX_EPOCH)
+			.ok_or_message("invalid expiry time")?
+			.as_millis() as u64;
+
+		bgvar.set(&val)?;
+		tree.insert(var, BgVarExpiry { expires_at }.encode()?)?;
+		Ok(())
+	}
+
+	/// Time at which a variable expires, if it was set with an expiry date
+	fn expires_at(&self, var: &str) -> Result<Option<u64>, Error> {
+		match &self.expiry {
+			Some(tree) => Ok(tree
+				.get(var)?
+				.and_then(|v| BgVarExpiry::decode(&v))
+				.map(|exp| exp.expires_at)),
+			None => Ok(None),
+		}
+	}
+
+	/// Value of a variable, or its default value if it has expired
+	fn get_unexpired(&self, var: &str, bgvar: &dyn BgVarTrait, now: u64) -> Result<String, Error> {
+		match (self.expires_at(var)?, bgvar.default()) {
+			(Some(t), Some(default)) if t <= now => Ok(default),
+			_ => Ok(bgvar.get()),
+		}
+	}
+
+	pub fn spawn_workers(&self, bg: &BackgroundRunner) {
+		if let Some(tree) = &self.expiry {
+			bg.spawn_worker(BgVarsExpiryWorker {
+				vars: self.vars.clone(),
+				tree: tree.clone(),
+			});
+		}
 	}
 }
 
+fn revert_if_expired(
+	tree: &db::Tree,
+	var: &str,
+	bgvar: &dyn BgVarTrait,
+	now: u64,
+) -> Result<(), Error> {
+	let exp = match tree.get(var)? {
+		Some(v) => match BgVarExpiry::decode(&v) {
+			Some(exp) => exp,
+			None => {
+				warn!("Invalid expiry entry for variable {}, removing it", var);
+				tree.remove(var)?;
+				return Ok(());
+			}
+		},
+		None => return Ok(()),
+	};
+	if exp.expires_at <= now {
+		if let Some(default) = bgvar.default() {
+			info!(
+				"Variable {} has expired, reverting it to its default value {}",
+				var, default
+			);
+			bgvar.set(&default)?;
+		}
+		tree.remove(var)?;
+	}
+	Ok(())
+}
+
+fn revert_all_expired(
+	tree: &db::Tree,
+	vars: &HashMap<&'static str, Arc<dyn BgVarTrait>>,
+) -> Result<(), Error> {
+	let now = now_msec();
+	for (var, bgvar) in vars.iter() {
+		revert_if_expired(tree, var, bgvar.as_ref(), now)?;
+	}
+	Ok(())
+}
+
 impl Default for BgVars {
 	fn default() -> Self {
 		Self::new()
@@ -79,9 +296,42 @@ impl Default for BgVars {
 
 // ----
 
+struct BgVarsExpiryWorker {
+	vars: HashMap<&'static str, Arc<dyn BgVarTrait>>,
+	tree: db::Tree,
+}
+
+#[async_trait]
+impl Worker for BgVarsExpiryWorker {
+	fn name(&self) -> String {
+		"Variable expiry".into()
+	}
+
+	fn status(&self) -> WorkerStatus {
+		WorkerStatus {
+			queue_length: Some(self.tree.len().unwrap_or(0) as u64),
+			..Default::default()
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		revert_all_expired(&self.tree, &self.vars)?;
+		Ok(WorkerState::Idle)
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		tokio::time::sleep(Duration::from_secs(10)).await;
+		WorkerState::Busy
+	}
+}
+
+// ----
+
 trait BgVarTrait: Send + Sync + 'static {
 	fn get(&self) -> String;
 	fn set(&self, v: &str) -> Result<(), Error>;
+	/// Default value of the variable, `None` for read-only variables
+	fn default(&self) -> Option<String>;
 }
 
 struct BgVar<T, GF, SF>
@@ -92,6 +342,7 @@ where
 {
 	get_fn: GF,
 	set_fn: SF,
+	default: Option<String>,
 }
 
 impl<T, GF, SF> BgVarTrait for BgVar<T, GF, SF>
@@ -110,4 +361,8 @@ where
 			.map_err(|_| Error::Message(format!("invalid value: {}", vstr)))?;
 		(self.set_fn)(value)
 	}
+
+	fn default(&self) -> Option<String> {
+		self.default.clone()
+	}
 }
@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use async_trait::async_trait;
 use futures::future::*;
@@ -103,6 +103,10 @@ impl WorkerProcessor {
 								errors: 0,
 								consecutive_errors: 0,
 								last_error: None,
+								iterations: 0,
+								work_time: Duration::ZERO,
+								last_run: None,
+								last_run_duration: None,
 							};
 						workers.push(async move {
 							worker.step().await;
@@ -125,6 +129,10 @@ impl WorkerProcessor {
 								if worker.last_error.is_some() {
 									i.last_error = worker.last_error.take();
 								}
+								i.iterations = worker.iterations;
+								i.work_time = worker.work_time;
+								i.last_run = worker.last_run;
+								i.last_run_duration = worker.last_run_duration;
 							}
 							None => {
 								wi.insert(worker.task_id, WorkerInfo {
@@ -134,6 +142,10 @@ impl WorkerProcessor {
 									errors: worker.errors,
 									consecutive_errors: worker.consecutive_errors,
 									last_error: worker.last_error.take(),
+									iterations: worker.iterations,
+									work_time: worker.work_time,
+									last_run: worker.last_run,
+									last_run_duration: worker.last_run_duration,
 								});
 							}
 						}
@@ -183,12 +195,16 @@ struct WorkerHandler {
 	errors: usize,
 	consecutive_errors: usize,
 	last_error: Option<(String, u64)>,
+	iterations: u64,
+	work_time: Duration,
+	last_run: Option<u64>,
+	last_run_duration: Option<Duration>,
 }
 
 impl WorkerHandler {
 	async fn step(&mut self) {
 		match self.state {
-			WorkerState::Busy => match self.worker.work(&mut self.stop_signal).await {
+			WorkerState::Busy => match self.timed_work().await {
 				Ok(s) => {
 					self.state = s;
 					self.consecutive_errors = 0;
@@ -230,4 +246,15 @@ impl WorkerHandler {
 			WorkerState::Done => unreachable!(),
 		}
 	}
+
+	async fn timed_work(&mut self) -> Result<WorkerState, Error> {
+		let start = Instant::now();
+		let res = self.worker.work(&mut self.stop_signal).await;
+		let duration = start.elapsed();
+		self.iterations += 1;
+		self.work_time += duration;
+		self.last_run = Some(now_msec());
+		self.last_run_duration = Some(duration);
+		res
+	}
 }
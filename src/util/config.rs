@@ -3,21 +3,40 @@ use std::io::Read;
 use std::net::SocketAddr;
 use std::path::PathBuf;
 
-use serde::{de, Deserialize};
+use serde::{de, Deserialize, Serialize};
 
 use crate::error::Error;
 
 /// Represent the whole configuration
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Debug, Clone, PartialEq)]
 pub struct Config {
 	/// Path where to store metadata. Should be fast, but low volume
 	pub metadata_dir: PathBuf,
-	/// Path where to store data. Can be slower, but need higher volume
-	pub data_dir: PathBuf,
+	/// Path where to store data. Can be slower, but need higher volume.
+	/// Either a single directory, or a list of directories on different disks
+	pub data_dir: DataDirEnum,
+	/// How new blocks are spread between data directories when several are
+	/// configured (options: consistent-hash, round-robin, least-full)
+	#[serde(default)]
+	pub data_dir_placement: DataDirPlacement,
 
 	/// Size of data blocks to save to disk
 	#[serde(default = "default_block_size")]
 	pub block_size: usize,
+	/// Unix permissions of block files (default: 0o600)
+	#[serde(default)]
+	pub block_file_mode: Option<u32>,
+	/// Format version of newly written block files (default: 1)
+	#[serde(default)]
+	pub block_format_version: Option<u8>,
+	/// Key used to encrypt block files at rest: 32 bytes hex encoded
+	pub encryption_key: Option<String>,
+	/// Optional file where the block encryption key is read from
+	pub encryption_key_file: Option<String>,
+	/// Keys previously used to encrypt block files, that are still needed
+	/// to read block files that have not been re-keyed yet
+	#[serde(default)]
+	pub previous_encryption_keys: Vec<String>,
 
 	/// Replication mode. Supported values:
 	/// - none, 1 -> no replication
@@ -26,6 +45,17 @@ pub struct Config {
 	// (we can add more aliases for this later)
 	pub replication_mode: String,
 
+	/// Number of nodes that must answer reads of the tables storing the metadata
+	/// of objects: a number of nodes, or `majority` (default: depends on the
+	/// replication mode)
+	#[serde(default)]
+	pub data_read_quorum: Option<String>,
+
+	/// Role of this node in the cluster (options: full, gateway, storage).
+	/// Gateway nodes hold no data, storage nodes serve no HTTP API (default: full)
+	#[serde(default)]
+	pub node_role: ConfigNodeRole,
+
 	/// Zstd compression level used on data blocks
 	#[serde(
 		deserialize_with = "deserialize_compression",
@@ -33,6 +63,26 @@ pub struct Config {
 	)]
 	pub compression_level: Option<i32>,
 
+	/// Allow `garage repair objects --reconcile-delete-markers` to remove
+	/// delete markers that no longer hide any object version (default: false)
+	#[serde(default)]
+	pub enable_delete_marker_gc: bool,
+
+	/// Number of days after which incomplete multipart uploads are
+	/// automatically aborted, 0 to disable (default: 7)
+	#[serde(default = "default_multipart_upload_timeout_days")]
+	pub multipart_upload_timeout_days: u64,
+
+	/// Number of seconds between a RestoreObject request on an object
+	/// of the GLACIER storage class and the moment it can be read (default: 0)
+	#[serde(default)]
+	pub glacier_restore_delay_secs: u64,
+
+	/// Maximum number of bucket notifications waiting to be sent,
+	/// the oldest ones are dropped when it is reached (default: 10000)
+	#[serde(default = "default_notification_queue_length")]
+	pub notification_queue_length: usize,
+
 	/// RPC secret key: 32 bytes hex encoded
 	pub rpc_secret: Option<String>,
 	/// Optional file where RPC secret key is read from
@@ -47,6 +97,16 @@ pub struct Config {
 	pub rpc_ping_timeout_msec: Option<u64>,
 	/// Timeout for Netapp RPC calls
 	pub rpc_timeout_msec: Option<u64>,
+	/// Number of versions of the cluster layout kept by each node, which can
+	/// be applied again with `garage layout rollback` (default: 5)
+	#[serde(default = "default_layout_history_length")]
+	pub layout_history_length: usize,
+	/// Maximum number of RPC calls of each priority in flight to a single node (default: 128)
+	#[serde(default = "default_rpc_max_in_flight_per_peer")]
+	pub rpc_max_in_flight_per_peer: usize,
+	/// Time an RPC call waits for a call to the same node to finish when
+	/// `rpc_max_in_flight_per_peer` is reached, before failing
+	pub rpc_in_flight_wait_msec: Option<u64>,
 
 	// -- Bootstraping and discovery
 	/// Bootstrap peers RPC address
@@ -72,6 +132,12 @@ pub struct Config {
 	#[serde(default = "default_sled_flush_every_ms")]
 	pub sled_flush_every_ms: u64,
 
+	// -- Logs
+	/// Filter for log messages, with the syntax of the `RUST_LOG` environment
+	/// variable, which it replaces when set (e.g. `garage=debug`)
+	#[serde(default)]
+	pub log_filter: Option<String>,
+
 	// -- APIs
 	/// Configuration for S3 api
 	pub s3_api: S3ApiConfig,
@@ -87,8 +153,74 @@ pub struct Config {
 	pub admin: AdminConfig,
 }
 
+/// Fields of the configuration that are applied without restarting Garage
+/// when the configuration file is reloaded (on SIGHUP)
+pub const HOT_RELOADABLE_FIELDS: &[&str] = &["compression_level", "log_filter"];
+
+macro_rules! changed_fields {
+	($old:expr, $new:expr, $($field:ident),* $(,)?) => {{
+		// Destructure without `..`, so that adding a field to `Config`
+		// without adding it here fails to compile
+		let Config { $($field: _),* } = $old;
+		let mut changed = vec![];
+		$(
+			if $old.$field != $new.$field {
+				changed.push(stringify!($field));
+			}
+		)*
+		changed
+	}};
+}
+
+impl Config {
+	/// Names of the top-level fields whose value differs in `new`
+	pub fn changed_fields(&self, new: &Config) -> Vec<&'static str> {
+		changed_fields!(
+			self,
+			new,
+			metadata_dir,
+			data_dir,
+			data_dir_placement,
+			block_size,
+			block_file_mode,
+			block_format_version,
+			encryption_key,
+			encryption_key_file,
+			previous_encryption_keys,
+			replication_mode,
+			data_read_quorum,
+			node_role,
+			compression_level,
+			enable_delete_marker_gc,
+			multipart_upload_timeout_days,
+			glacier_restore_delay_secs,
+			notification_queue_length,
+			rpc_secret,
+			rpc_secret_file,
+			rpc_bind_addr,
+			rpc_public_addr,
+			rpc_ping_timeout_msec,
+			rpc_timeout_msec,
+			layout_history_length,
+			rpc_max_in_flight_per_peer,
+			rpc_in_flight_wait_msec,
+			bootstrap_peers,
+			consul_discovery,
+			kubernetes_discovery,
+			db_engine,
+			sled_cache_capacity,
+			sled_flush_every_ms,
+			log_filter,
+			s3_api,
+			k2v_api,
+			s3_web,
+			admin,
+		)
+	}
+}
+
 /// Configuration for S3 api
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Debug, Clone, PartialEq)]
 pub struct S3ApiConfig {
 	/// Address and port to bind for api serving
 	pub api_bind_addr: Option<SocketAddr>,
@@ -97,17 +229,34 @@ pub struct S3ApiConfig {
 	/// Suffix to remove from domain name to find bucket. If None,
 	/// vhost-style S3 request are disabled
 	pub root_domain: Option<String>,
+	/// Size in bytes above which the access logs buffered for a target bucket
+	/// are written to it
+	#[serde(default = "default_access_log_buffer_size")]
+	pub access_log_buffer_size: usize,
+	/// Maximum delay in seconds before buffered access logs are written
+	/// to their target bucket
+	#[serde(default = "default_access_log_flush_interval_secs")]
+	pub access_log_flush_interval_secs: u64,
+	/// Write a line of JSON for each request to the S3 API (default: false)
+	#[serde(default)]
+	pub json_access_log: bool,
+	/// File where JSON access logs are appended (default: standard output)
+	pub access_log_path: Option<PathBuf>,
+	/// Addresses or CIDR ranges of the reverse proxies whose X-Forwarded-For
+	/// and X-Forwarded-Proto headers are trusted by bucket policies
+	#[serde(default)]
+	pub trusted_proxies: Vec<String>,
 }
 
 /// Configuration for K2V api
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Debug, Clone, PartialEq)]
 pub struct K2VApiConfig {
 	/// Address and port to bind for api serving
 	pub api_bind_addr: SocketAddr,
 }
 
 /// Configuration for serving files as normal web server
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Debug, Clone, PartialEq)]
 pub struct WebConfig {
 	/// Address and port to bind for web serving
 	pub bind_addr: SocketAddr,
@@ -116,7 +265,7 @@ pub struct WebConfig {
 }
 
 /// Configuration for the admin and monitoring HTTP API
-#[derive(Deserialize, Debug, Clone, Default)]
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
 pub struct AdminConfig {
 	/// Address and port to bind for admin API serving
 	pub api_bind_addr: Option<SocketAddr>,
@@ -132,10 +281,90 @@ pub struct AdminConfig {
 	pub admin_token_file: Option<String>,
 
 	/// OTLP server to where to export traces
+	#[serde(alias = "opentelemetry_endpoint")]
 	pub trace_sink: Option<String>,
 }
 
-#[derive(Deserialize, Debug, Clone, Default)]
+/// Data directories of a node, as set by the `data_dir` configuration key
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum DataDirEnum {
+	/// All blocks are stored in a single directory
+	Single(PathBuf),
+	/// Blocks are spread between several directories
+	Multiple(Vec<DataDir>),
+}
+
+/// A directory in which blocks are stored, when several are configured
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct DataDir {
+	/// Path of the directory
+	pub path: PathBuf,
+	/// Maximum space that may be used on the filesystem of this directory,
+	/// in bytes or with a unit (e.g. `500G`, `2T`). Also used as the weight
+	/// of the directory with the consistent-hash placement.
+	#[serde(default, deserialize_with = "deserialize_capacity")]
+	pub capacity: Option<u64>,
+	/// Do not write new blocks in this directory, existing blocks are still read
+	/// and can be moved to other directories with `garage repair rebalance`
+	#[serde(default)]
+	pub read_only: bool,
+}
+
+impl DataDirEnum {
+	/// The list of configured data directories
+	pub fn dirs(&self) -> Vec<DataDir> {
+		match self {
+			DataDirEnum::Single(path) => vec![DataDir {
+				path: path.clone(),
+				capacity: None,
+				read_only: false,
+			}],
+			DataDirEnum::Multiple(dirs) => dirs.clone(),
+		}
+	}
+}
+
+/// Policy used to choose the data directory in which a new block is written
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum DataDirPlacement {
+	/// Each block goes to a directory chosen from its hash, proportionally to the
+	/// capacity of the directories
+	#[default]
+	ConsistentHash,
+	/// New blocks are written to each directory in turn
+	RoundRobin,
+	/// New blocks are written to the directory whose filesystem is the least full
+	LeastFull,
+}
+
+/// Role of a node, as set by the `node_role` configuration key
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ConfigNodeRole {
+	/// Serves the HTTP APIs and stores data
+	#[default]
+	Full,
+	/// Serves the HTTP APIs but does not store any data
+	Gateway,
+	/// Stores data but does not serve any HTTP API
+	Storage,
+}
+
+impl ConfigNodeRole {
+	/// Whether this node can be assigned a storage capacity in the cluster layout
+	pub fn stores_data(&self) -> bool {
+		*self != ConfigNodeRole::Gateway
+	}
+
+	/// Whether this node binds the HTTP API servers
+	pub fn serves_http(&self) -> bool {
+		*self != ConfigNodeRole::Storage
+	}
+}
+
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum ConsulDiscoveryAPI {
 	#[default]
@@ -143,7 +372,7 @@ pub enum ConsulDiscoveryAPI {
 	Agent,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Debug, Clone, PartialEq)]
 pub struct ConsulDiscoveryConfig {
 	/// The consul api to use when registering: either `catalog` (the default) or `agent`
 	#[serde(default)]
@@ -171,7 +400,7 @@ pub struct ConsulDiscoveryConfig {
 	pub meta: Option<std::collections::HashMap<String, String>>,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Debug, Clone, PartialEq)]
 pub struct KubernetesDiscoveryConfig {
 	/// Kubernetes namespace the service discovery resources are be created in
 	pub namespace: String,
@@ -195,6 +424,24 @@ fn default_sled_flush_every_ms() -> u64 {
 fn default_block_size() -> usize {
 	1048576
 }
+fn default_multipart_upload_timeout_days() -> u64 {
+	7
+}
+fn default_notification_queue_length() -> usize {
+	10000
+}
+fn default_layout_history_length() -> usize {
+	5
+}
+fn default_rpc_max_in_flight_per_peer() -> usize {
+	128
+}
+fn default_access_log_buffer_size() -> usize {
+	1048576
+}
+fn default_access_log_flush_interval_secs() -> u64 {
+	300
+}
 
 /// Read and parse configuration
 pub fn read_config(config_file: PathBuf) -> Result<Config, Error> {
@@ -212,6 +459,11 @@ pub fn read_config(config_file: PathBuf) -> Result<Config, Error> {
 		&parsed_config.rpc_secret_file,
 		"rpc_secret",
 	)?;
+	secret_from_file(
+		&mut parsed_config.encryption_key,
+		&parsed_config.encryption_key_file,
+		"encryption_key",
+	)?;
 	secret_from_file(
 		&mut parsed_config.admin.metrics_token,
 		&parsed_config.admin.metrics_token_file,
@@ -312,6 +564,77 @@ where
 	deserializer.deserialize_any(OptionVisitor)
 }
 
+fn deserialize_capacity<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+	D: de::Deserializer<'de>,
+{
+	use std::convert::TryFrom;
+
+	struct CapacityVisitor;
+
+	impl<'de> serde::de::Visitor<'de> for CapacityVisitor {
+		type Value = Option<u64>;
+		fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+			formatter.write_str("a number of bytes, or a size with a unit such as '500G'")
+		}
+
+		fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+		where
+			E: de::Error,
+		{
+			parse_capacity(value).map(Some).ok_or_else(|| {
+				E::custom(format!(
+					"Invalid capacity: '{}', should be a number of bytes or a size such as '500G'",
+					value
+				))
+			})
+		}
+
+		fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E>
+		where
+			E: de::Error,
+		{
+			u64::try_from(v)
+				.map(Some)
+				.map_err(|_| E::custom("Capacity cannot be negative".to_owned()))
+		}
+
+		fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E>
+		where
+			E: de::Error,
+		{
+			Ok(Some(v))
+		}
+	}
+
+	deserializer.deserialize_any(CapacityVisitor)
+}
+
+/// Parse a size such as `1000000`, `500G`, `2TB` or `1.5TiB` into a number of bytes
+fn parse_capacity(value: &str) -> Option<u64> {
+	let value = value.trim();
+	let split = value
+		.find(|c: char| !(c.is_ascii_digit() || c == '.'))
+		.unwrap_or(value.len());
+	let (number, unit) = value.split_at(split);
+	let number = number.parse::<f64>().ok()?;
+	let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+		"" | "b" => 1,
+		"k" | "kb" => 1_000,
+		"m" | "mb" => 1_000_000,
+		"g" | "gb" => 1_000_000_000,
+		"t" | "tb" => 1_000_000_000_000,
+		"p" | "pb" => 1_000_000_000_000_000,
+		"ki" | "kib" => 1 << 10,
+		"mi" | "mib" => 1 << 20,
+		"gi" | "gib" => 1 << 30,
+		"ti" | "tib" => 1 << 40,
+		"pi" | "pib" => 1 << 50,
+		_ => return None,
+	};
+	Some((number * multiplier as f64) as u64)
+}
+
 #[cfg(test)]
 mod tests {
 	use crate::error::Error;
@@ -345,6 +668,148 @@ mod tests {
 		Ok(())
 	}
 
+	#[test]
+	fn test_node_role() {
+		use super::ConfigNodeRole;
+
+		let base = r#"
+			metadata_dir = "/tmp/garage/meta"
+			data_dir = "/tmp/garage/data"
+			replication_mode = "3"
+			rpc_bind_addr = "[::]:3901"
+
+			[s3_api]
+			s3_region = "garage"
+			"#;
+
+		let config: super::Config = toml::from_str(base).unwrap();
+		assert_eq!(config.node_role, ConfigNodeRole::Full);
+
+		let config: super::Config =
+			toml::from_str(&format!("node_role = \"gateway\"\n{}", base)).unwrap();
+		assert_eq!(config.node_role, ConfigNodeRole::Gateway);
+		assert!(!config.node_role.stores_data());
+		assert!(config.node_role.serves_http());
+
+		let config: super::Config =
+			toml::from_str(&format!("node_role = \"storage\"\n{}", base)).unwrap();
+		assert!(config.node_role.stores_data());
+		assert!(!config.node_role.serves_http());
+
+		assert!(toml::from_str::<super::Config>(&format!("node_role = \"s3\"\n{}", base)).is_err());
+	}
+
+	#[test]
+	fn test_data_dir() {
+		use super::{DataDir, DataDirEnum, DataDirPlacement};
+		use std::path::PathBuf;
+
+		let base = r#"
+			metadata_dir = "/tmp/garage/meta"
+			replication_mode = "3"
+			rpc_bind_addr = "[::]:3901"
+
+			[s3_api]
+			s3_region = "garage"
+			"#;
+
+		let config: super::Config =
+			toml::from_str(&format!("data_dir = \"/tmp/garage/data\"\n{}", base)).unwrap();
+		assert_eq!(
+			config.data_dir,
+			DataDirEnum::Single(PathBuf::from("/tmp/garage/data"))
+		);
+		assert_eq!(config.data_dir_placement, DataDirPlacement::ConsistentHash);
+		assert_eq!(config.data_dir.dirs().len(), 1);
+
+		let config: super::Config = toml::from_str(&format!(
+			r#"
+			data_dir = [
+				{{ path = "/mnt/hdd1", capacity = "2T" }},
+				{{ path = "/mnt/hdd2", capacity = 500000000000 }},
+				{{ path = "/mnt/old", read_only = true }},
+			]
+			data_dir_placement = "least-full"
+			{}"#,
+			base
+		))
+		.unwrap();
+		assert_eq!(
+			config.data_dir.dirs(),
+			vec![
+				DataDir {
+					path: "/mnt/hdd1".into(),
+					capacity: Some(2_000_000_000_000),
+					read_only: false,
+				},
+				DataDir {
+					path: "/mnt/hdd2".into(),
+					capacity: Some(500_000_000_000),
+					read_only: false,
+				},
+				DataDir {
+					path: "/mnt/old".into(),
+					capacity: None,
+					read_only: true,
+				},
+			]
+		);
+		assert_eq!(config.data_dir_placement, DataDirPlacement::LeastFull);
+
+		assert!(toml::from_str::<super::Config>(&format!(
+			"data_dir = [{{ path = \"/mnt/hdd1\", capacity = \"2 parsecs\" }}]\n{}",
+			base
+		))
+		.is_err());
+	}
+
+	#[test]
+	fn test_parse_capacity() {
+		use super::parse_capacity;
+
+		assert_eq!(parse_capacity("1000"), Some(1000));
+		assert_eq!(parse_capacity("500G"), Some(500_000_000_000));
+		assert_eq!(parse_capacity("2 TB"), Some(2_000_000_000_000));
+		assert_eq!(parse_capacity("1.5KiB"), Some(1536));
+		assert_eq!(parse_capacity("10Mi"), Some(10 << 20));
+		assert_eq!(parse_capacity("G"), None);
+		assert_eq!(parse_capacity("12X"), None);
+	}
+
+	#[test]
+	fn test_changed_fields() {
+		let base = r#"
+			metadata_dir = "/tmp/garage/meta"
+			data_dir = "/tmp/garage/data"
+			replication_mode = "3"
+			rpc_bind_addr = "[::]:3901"
+
+			[s3_api]
+			s3_region = "garage"
+			"#;
+
+		let config: super::Config = toml::from_str(base).unwrap();
+		assert!(config.changed_fields(&config.clone()).is_empty());
+
+		let new_config: super::Config = toml::from_str(&format!(
+			"compression_level = 10\nlog_filter = \"garage=debug\"\n{}",
+			base.replace("3901", "3911")
+		))
+		.unwrap();
+		let changed = config.changed_fields(&new_config);
+		assert_eq!(
+			changed,
+			vec!["compression_level", "rpc_bind_addr", "log_filter"]
+		);
+		assert_eq!(
+			changed
+				.iter()
+				.filter(|f| !super::HOT_RELOADABLE_FIELDS.contains(f))
+				.collect::<Vec<_>>(),
+			vec![&"rpc_bind_addr"]
+		);
+	}
+
 	#[test]
 	fn test_rpc_secret_file_works() -> Result<(), Error> {
 		let path_secret = mktemp::Temp::new_file()?;
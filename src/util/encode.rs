@@ -40,3 +40,8 @@ pub fn debug_serialize<T: Serialize>(x: T) -> String {
 		Err(e) => format!("<JSON serialization error: {}>", e),
 	}
 }
+
+/// Serialize to JSON
+pub fn json_encode<T: Serialize + ?Sized>(val: &T) -> Result<Vec<u8>, serde_json::Error> {
+	serde_json::to_vec(val)
+}
@@ -54,6 +54,9 @@ pub enum Error {
 	#[error(display = "Timeout")]
 	Timeout,
 
+	#[error(display = "Too many RPC calls in flight to node(s) {}", _0)]
+	Overloaded(String),
+
 	#[error(
 		display = "Could not reach quorum of {}. {} of {} request succeeded, others returned errors: {:?}",
 		_0,
@@ -101,6 +101,16 @@ impl<V: Migrate + Default> PersisterShared<V> {
 		Self(Arc::new((persister, RwLock::new(value))))
 	}
 
+	/// Create a value that is not backed by a file, for instance to evaluate
+	/// getters on the default value. Saving it always fails.
+	pub fn detached(value: V) -> Self {
+		let persister = Persister {
+			path: PathBuf::new(),
+			_marker: Default::default(),
+		};
+		Self(Arc::new((persister, RwLock::new(value))))
+	}
+
 	pub fn get_with<F, R>(&self, f: F) -> R
 	where
 		F: FnOnce(&V) -> R,
@@ -25,6 +25,7 @@ use garage_api::s3::error::{
 };
 use garage_api::s3::get::{handle_get, handle_head};
 
+use garage_model::bucket_table::RoutingRule;
 use garage_model::garage::Garage;
 
 use garage_table::*;
@@ -175,7 +176,7 @@ impl WebServer {
 			.object_table
 			.get(&bucket_id, &key.to_string())
 			.await?
-			.map(|object| object.versions().iter().any(|v| v.is_data()))
+			.map(|object| object.current_data_version().is_some())
 			.unwrap_or(false);
 		Ok(exists)
 	}
@@ -216,6 +217,21 @@ impl WebServer {
 			.as_ref()
 			.ok_or(Error::NotFound)?;
 
+		if let Some(redirect_all) = &website_config.redirect_all {
+			let path = req
+				.uri()
+				.path_and_query()
+				.map(|p| p.as_str())
+				.unwrap_or("/");
+			let location = redirect_location(
+				redirect_all.protocol.as_deref(),
+				Some(&redirect_all.hostname),
+				authority,
+				path,
+			);
+			return redirect_response(StatusCode::MOVED_PERMANENTLY, &location);
+		}
+
 		// Get path
 		let path = req.uri().path().to_string();
 		let index = &website_config.index_document;
@@ -226,17 +242,36 @@ impl WebServer {
 			bucket_name, bucket_id, key, may_redirect
 		);
 
+		// Routing rules are matched against the requested path, without the index document
+		let rule_key = percent_encoding::percent_decode_str(&path).decode_utf8()?;
+		let rule_key = rule_key.trim_start_matches('/');
+		let find_routing_rule = |error_code: Option<StatusCode>| {
+			website_config
+				.routing_rules
+				.iter()
+				.find(|rule| rule.applies_to(rule_key, error_code.map(|c| c.as_u16())))
+		};
+
+		if matches!(*req.method(), Method::GET | Method::HEAD) {
+			if let Some(rule) = find_routing_rule(None) {
+				return routing_rule_redirect(rule, authority, rule_key);
+			}
+		}
+
 		let ret_doc = match *req.method() {
 			Method::OPTIONS => handle_options_for_bucket(req, &bucket),
-			Method::HEAD => handle_head(self.garage.clone(), req, bucket_id, &key, None).await,
-			Method::GET => handle_get(self.garage.clone(), req, bucket_id, &key, None).await,
+			Method::HEAD => {
+				handle_head(self.garage.clone(), req, bucket_id, &key, None, None).await
+			}
+			Method::GET => handle_get(self.garage.clone(), req, bucket_id, &key, None, None).await,
 			_ => Err(ApiError::bad_request("HTTP method not supported")),
 		};
 
 		// Try implicit redirect on error
 		let ret_doc_with_redir = match (&ret_doc, may_redirect) {
-			(Err(ApiError::NoSuchKey), ImplicitRedirect::To { key, url })
-				if self.check_key_exists(bucket_id, key.as_str()).await? =>
+			(Err(e), ImplicitRedirect::To { key, url })
+				if matches!(e, ApiError::NoSuchKey | ApiError::DeletedKey(_))
+					&& self.check_key_exists(bucket_id, key.as_str()).await? =>
 			{
 				Ok(Response::builder()
 					.status(StatusCode::FOUND)
@@ -249,6 +284,13 @@ impl WebServer {
 
 		match ret_doc_with_redir.map_err(Error::from) {
 			Err(error) => {
+				// Routing rules can redirect requests that resulted in a given error code
+				if *req.method() != Method::OPTIONS {
+					if let Some(rule) = find_routing_rule(Some(error.http_status_code())) {
+						return routing_rule_redirect(rule, authority, rule_key);
+					}
+				}
+
 				// For a HEAD or OPTIONS method, and for non-4xx errors,
 				// we don't return the error document as content,
 				// we return above and just return the error message
@@ -273,7 +315,15 @@ impl WebServer {
 					.body(Body::empty())
 					.unwrap();
 
-				match handle_get(self.garage.clone(), &req2, bucket_id, &error_document, None).await
+				match handle_get(
+					self.garage.clone(),
+					&req2,
+					bucket_id,
+					&error_document,
+					None,
+					None,
+				)
+				.await
 				{
 					Ok(mut error_doc) => {
 						// The error won't be logged back in handle_request,
@@ -308,8 +358,8 @@ impl WebServer {
 			}
 			Ok(mut resp) => {
 				// Maybe add CORS headers
-				if let Some(rule) = find_matching_cors_rule(&bucket, req)? {
-					add_cors_headers(&mut resp, rule)
+				if let Some((rule, origin)) = find_matching_cors_rule(&bucket, req)? {
+					add_cors_headers(&mut resp, rule, &origin)
 						.ok_or_internal_error("Invalid bucket CORS configuration")?;
 				}
 				Ok(resp)
@@ -333,6 +383,70 @@ fn error_to_res(e: Error) -> Response<Body> {
 	http_error
 }
 
+/// Characters that must be percent-encoded in the path of redirection URLs
+const PATH_ENCODE_SET: &percent_encoding::AsciiSet = &percent_encoding::CONTROLS
+	.add(b' ')
+	.add(b'"')
+	.add(b'#')
+	.add(b'%')
+	.add(b'<')
+	.add(b'>')
+	.add(b'?')
+	.add(b'`')
+	.add(b'{')
+	.add(b'}');
+
+/// Redirect a request for `key` as specified by a routing rule of the website
+fn routing_rule_redirect(
+	rule: &RoutingRule,
+	request_host: &str,
+	key: &str,
+) -> Result<Response<Body>, Error> {
+	let path = format!(
+		"/{}",
+		percent_encoding::utf8_percent_encode(&rule.redirect_key(key), PATH_ENCODE_SET)
+	);
+	let location = redirect_location(
+		rule.redirect.protocol.as_deref(),
+		rule.redirect.hostname.as_deref(),
+		request_host,
+		&path,
+	);
+	let status = StatusCode::from_u16(rule.redirect.http_redirect_code)
+		.unwrap_or(StatusCode::MOVED_PERMANENTLY);
+	redirect_response(status, &location)
+}
+
+/// Build the URL of a redirection to `path`: when the protocol or the host name
+/// are not specified, those of the request are kept
+fn redirect_location(
+	protocol: Option<&str>,
+	hostname: Option<&str>,
+	request_host: &str,
+	path: &str,
+) -> String {
+	match (protocol, hostname) {
+		(None, None) => path.to_string(),
+		(None, Some(hostname)) => format!("//{}{}", hostname, path),
+		(Some(protocol), hostname) => {
+			format!(
+				"{}://{}{}",
+				protocol,
+				hostname.unwrap_or(request_host),
+				path
+			)
+		}
+	}
+}
+
+fn redirect_response(status: StatusCode, location: &str) -> Result<Response<Body>, Error> {
+	Ok(Response::builder()
+		.status(status)
+		.header("Location", location)
+		.body(Body::empty())
+		.ok_or_internal_error("Invalid redirection")?)
+}
+
 #[derive(Debug, PartialEq)]
 enum ImplicitRedirect {
 	No,
@@ -413,4 +527,24 @@ mod tests {
 		assert!(path_to_keys("i/am/relative", "index.html").is_err());
 		Ok(())
 	}
+
+	#[test]
+	fn redirect_location_test() {
+		assert_eq!(
+			redirect_location(None, None, "site.garage", "/a%20b"),
+			"/a%20b"
+		);
+		assert_eq!(
+			redirect_location(None, Some("example.com"), "site.garage", "/a"),
+			"//example.com/a"
+		);
+		assert_eq!(
+			redirect_location(Some("https"), None, "site.garage", "/a"),
+			"https://site.garage/a"
+		);
+		assert_eq!(
+			redirect_location(Some("https"), Some("example.com"), "site.garage", "/a"),
+			"https://example.com/a"
+		);
+	}
 }